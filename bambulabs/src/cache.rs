@@ -0,0 +1,157 @@
+//! A bounded cache of the messages received from the printer. Printers
+//! report plenty of messages nobody asks for (each with its own sequence
+//! id), so without bounds the cache grows for as long as the client runs.
+//! Messages expire after a time to live, and the oldest are evicted once
+//! there are too many.
+
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::{message::Message, sequence_id::SequenceId};
+
+/// How long a message is kept, unless configured otherwise. This is well
+/// beyond how long [crate::client::Client::publish] waits for a response.
+pub(crate) const DEFAULT_RESPONSE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How many messages are kept, unless configured otherwise.
+pub(crate) const DEFAULT_RESPONSE_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+struct Entry {
+    /// When the message was received, or last seen again.
+    seen_at: Instant,
+    message: Message,
+}
+
+/// Messages from the printer, keyed by sequence id.
+#[derive(Debug)]
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: DashMap<SequenceId, Entry>,
+}
+
+impl ResponseCache {
+    /// Creates a new `ResponseCache`, keeping at most `capacity` messages,
+    /// each for at most `ttl`.
+    pub(crate) fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Insert a message, evicting expired messages (and then the oldest)
+    /// if there are too many.
+    pub(crate) fn insert(&self, sequence_id: SequenceId, message: Message, now: Instant) {
+        self.entries.insert(sequence_id, Entry { seen_at: now, message });
+        if self.entries.len() <= self.capacity {
+            return;
+        }
+
+        self.evict_expired(now);
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|entry| entry.seen_at)
+                .map(|entry| entry.key().clone())
+            else {
+                break;
+            };
+            tracing::debug!("evicting message {} from a full cache", oldest);
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Mark a message as seen again, so it doesn't expire.
+    pub(crate) fn touch(&self, sequence_id: &SequenceId, now: Instant) {
+        if let Some(mut entry) = self.entries.get_mut(sequence_id) {
+            entry.seen_at = now;
+        }
+    }
+
+    /// Get a message, unless it's expired.
+    pub(crate) fn get(&self, sequence_id: &SequenceId, now: Instant) -> Option<Message> {
+        let entry = self.entries.get(sequence_id)?;
+        if self.is_expired(&entry, now) {
+            drop(entry);
+            self.entries.remove(sequence_id);
+            return None;
+        }
+        Some(entry.message.clone())
+    }
+
+    /// Remove a message, returning it unless it's expired.
+    pub(crate) fn take(&self, sequence_id: &SequenceId, now: Instant) -> Option<Message> {
+        let (_, entry) = self.entries.remove(sequence_id)?;
+        (!self.is_expired(&entry, now)).then_some(entry.message)
+    }
+
+    /// Remove every expired message.
+    pub(crate) fn evict_expired(&self, now: Instant) {
+        self.entries.retain(|_, entry| !self.is_expired(entry, now));
+    }
+
+    fn is_expired(&self, entry: &Entry, now: Instant) -> bool {
+        now.saturating_duration_since(entry.seen_at) > self.ttl
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Message {
+        Message::Unknown(Some("{}".to_string()))
+    }
+
+    #[test]
+    fn test_ttl() {
+        let start = Instant::now();
+        let cache = ResponseCache::new(Duration::from_secs(60), 10);
+
+        cache.insert(SequenceId::Integer(1), message(), start);
+        cache.insert(SequenceId::status(), message(), start);
+        assert!(cache
+            .get(&SequenceId::Integer(1), start + Duration::from_secs(30))
+            .is_some());
+
+        // Touching a message keeps it alive.
+        cache.touch(&SequenceId::status(), start + Duration::from_secs(50));
+
+        let later = start + Duration::from_secs(90);
+        assert!(cache.get(&SequenceId::Integer(1), later).is_none());
+        assert!(cache.get(&SequenceId::status(), later).is_some());
+        assert_eq!(cache.len(), 1);
+
+        cache.evict_expired(start + Duration::from_secs(200));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let start = Instant::now();
+        let cache = ResponseCache::new(Duration::from_secs(60), 3);
+
+        for i in 0..5 {
+            cache.insert(SequenceId::Integer(i), message(), start + Duration::from_secs(i as u64));
+        }
+        assert_eq!(cache.len(), 3);
+
+        // The oldest are evicted first.
+        let now = start + Duration::from_secs(5);
+        assert!(cache.get(&SequenceId::Integer(0), now).is_none());
+        assert!(cache.get(&SequenceId::Integer(1), now).is_none());
+        assert!(cache.take(&SequenceId::Integer(4), now).is_some());
+        assert!(cache.take(&SequenceId::Integer(4), now).is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -3,18 +3,51 @@
 use std::{sync::Arc, time::Duration};
 
 use anyhow::{Context, Result};
-use dashmap::DashMap;
 use tokio::sync::Mutex;
 
 use crate::{
-    command::Command,
-    message::{Message, Print, PushStatus},
-    parser::parse_message,
+    cache::{ResponseCache, DEFAULT_RESPONSE_CAPACITY, DEFAULT_RESPONSE_TTL},
+    coalesce::{is_push_status, StatusCoalescer, DEFAULT_STATUS_INTERVAL},
+    command::{Command, OperationProtocol},
+    debug::{MqttDebug, MqttDebugSnapshot},
+    message::{GetVersion, Info, Init, LiveView, Message, Print, PushStatus},
+    parser::parse_payload,
+    pinned::{format_fingerprint, parse_fingerprint, PinnedCert},
     sequence_id::SequenceId,
 };
 
 const MQTT_PORT: u16 = 8883;
 const MAX_PACKET_SIZE: usize = 1024 * 1024;
+/// Number of times an FTPS upload is attempted (resuming after the first)
+/// before giving up.
+const UPLOAD_ATTEMPTS: usize = 3;
+
+/// `curl` exit codes for failures reaching the printer, rather than the
+/// printer refusing the request: couldn't resolve host (6), couldn't
+/// connect (7), timed out (28), empty reply (52), and failures sending (55)
+/// and receiving (56) data.
+const CURL_TRANSIENT_EXIT_CODES: [i32; 6] = [6, 7, 28, 52, 55, 56];
+
+/// Error returned when a request to the printer failed in a way which may
+/// well not happen if it's tried again, such as timing out, or the printer
+/// not being reachable.
+#[derive(Debug)]
+pub struct TransientError(String);
+
+impl std::fmt::Display for TransientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+fn verifier(pinned: Option<Arc<PinnedCert>>) -> Arc<dyn rustls::client::danger::ServerCertVerifier> {
+    match pinned {
+        Some(pinned) => pinned,
+        None => Arc::new(crate::no_auth::NoAuth::new()),
+    }
+}
 
 /// The Bambu MQTT client.
 #[derive(Clone)]
@@ -26,13 +59,26 @@ pub struct Client {
     /// The serial number.
     pub serial: String,
 
+    /// The id the printer goes by, recorded on the spans of calls to it.
+    machine_id: Option<String>,
+
+    /// Checks the printer's certificate, if it's pinned.
+    pub(crate) pinned: Option<Arc<PinnedCert>>,
+
     topic_device_request: String,
     topic_device_report: String,
 
     client: Arc<rumqttc::AsyncClient>,
     event_loop: Arc<Mutex<rumqttc::EventLoop>>,
 
-    responses: Arc<DashMap<SequenceId, Message>>,
+    /// Messages from the printer, waiting to be read.
+    responses: Arc<ResponseCache>,
+
+    /// Decides which status pushes are parsed.
+    status: Arc<StatusCoalescer>,
+
+    /// Records the MQTT traffic from the printer, if asked to.
+    debug: Option<Arc<MqttDebug>>,
 }
 
 impl Client {
@@ -42,27 +88,111 @@ impl Client {
         let ip = ip.into();
         let serial = serial.into();
 
-        let opts = Self::get_config(&ip, &access_code)?;
+        let opts = Self::get_config(&ip, &access_code, None)?;
         let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
 
         Ok(Self {
             ip,
             access_code,
+            pinned: None,
             topic_device_request: format!("device/{}/request", &serial),
             topic_device_report: format!("device/{}/report", &serial),
             serial,
+            machine_id: None,
             client: Arc::new(client),
             event_loop: Arc::new(Mutex::new(event_loop)),
-            responses: Arc::new(DashMap::new()),
+            responses: Arc::new(ResponseCache::new(DEFAULT_RESPONSE_TTL, DEFAULT_RESPONSE_CAPACITY)),
+            status: Arc::new(StatusCoalescer::new(DEFAULT_STATUS_INTERVAL)),
+            debug: None,
         })
     }
 
-    fn get_config(ip: &str, access_code: &str) -> Result<rumqttc::MqttOptions> {
+    /// Record `machine_id` (the id the printer goes by) on the spans of
+    /// calls made with this Client, so they can be found in a trace.
+    pub fn with_machine_id(mut self, machine_id: &str) -> Self {
+        self.machine_id = Some(machine_id.to_owned());
+        self
+    }
+
+    /// Parse status pushes at most once every `interval` (rather than every
+    /// second), skipping those which don't change anything. Reading the
+    /// status always parses the latest push, however recent.
+    pub fn with_status_interval(mut self, interval: Duration) -> Self {
+        self.status = Arc::new(StatusCoalescer::new(interval));
+        self
+    }
+
+    /// Keep at most `capacity` messages from the printer (rather than 256),
+    /// each for at most `ttl` (rather than five minutes). The status expires
+    /// too, if the printer stops pushing it for longer than `ttl`.
+    pub fn with_response_cache(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.responses = Arc::new(ResponseCache::new(ttl, capacity));
+        self
+    }
+
+    /// Record the MQTT traffic from the printer (message counts by topic,
+    /// the last `capacity` payloads, and payloads which couldn't be
+    /// parsed), for [Client::mqtt_debug] to return.
+    pub fn with_mqtt_debug(mut self, capacity: usize) -> Self {
+        self.debug = Some(Arc::new(MqttDebug::new(capacity)));
+        self
+    }
+
+    /// What's been recorded of the MQTT traffic from the printer, if
+    /// recording was turned on with [Client::with_mqtt_debug].
+    pub fn mqtt_debug(&self) -> Option<MqttDebugSnapshot> {
+        self.debug.as_ref().map(|debug| debug.snapshot())
+    }
+
+    /// Only trust the printer if its certificate has the given SHA-256
+    /// fingerprint (as hex, optionally `:` separated), rather than trusting
+    /// whatever certificate it presents. This applies to both MQTT and FTPS
+    /// uploads.
+    pub fn with_certificate_fingerprint(self, fingerprint: &str) -> Result<Self> {
+        let fingerprint = parse_fingerprint(fingerprint)?;
+        self.with_pinned(PinnedCert::new(Some(fingerprint), None))
+    }
+
+    /// Trust the first certificate the printer presents, and only that
+    /// certificate from then on. `on_first_use` is called with its
+    /// fingerprint, so it can be passed to
+    /// [Client::with_certificate_fingerprint] next time.
+    pub fn with_trust_on_first_use<F: Fn(String) + Send + Sync + 'static>(self, on_first_use: F) -> Result<Self> {
+        self.with_pinned(PinnedCert::new(None, Some(Arc::new(on_first_use))))
+    }
+
+    fn with_pinned(mut self, pinned: PinnedCert) -> Result<Self> {
+        let pinned = Arc::new(pinned);
+        let opts = Self::get_config(&self.ip, &self.access_code, Some(pinned.clone()))?;
+        let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
+
+        self.pinned = Some(pinned);
+        self.client = Arc::new(client);
+        self.event_loop = Arc::new(Mutex::new(event_loop));
+        Ok(self)
+    }
+
+    /// The SHA-256 fingerprint of the printer's pinned certificate, once
+    /// it's known.
+    pub fn certificate_fingerprint(&self) -> Option<String> {
+        self.pinned
+            .as_ref()?
+            .fingerprint()
+            .map(|fingerprint| format_fingerprint(&fingerprint))
+    }
+
+    /// Checks the printer's certificate when connecting to it over TLS: the
+    /// pinned certificate, if there is one, or anything at all if not.
+    pub(crate) fn certificate_verifier(&self) -> Arc<dyn rustls::client::danger::ServerCertVerifier> {
+        verifier(self.pinned.clone())
+    }
+
+    fn get_config(ip: &str, access_code: &str, pinned: Option<Arc<PinnedCert>>) -> Result<rumqttc::MqttOptions> {
         let client_id = format!("bambu-api-{}", nanoid::nanoid!(8));
 
         let ssl_config = rustls::ClientConfig::builder()
             .dangerous()
-            .with_custom_certificate_verifier(Arc::new(crate::no_auth::NoAuth::new()))
+            .with_custom_certificate_verifier(verifier(pinned))
             .with_no_client_auth();
 
         let mut opts = rumqttc::MqttOptions::new(client_id, ip, MQTT_PORT);
@@ -95,7 +225,7 @@ impl Client {
                     tracing::error!("Error polling for message: {:?}", err);
                     tracing::warn!("Reconnecting...");
                     // We are in a bad state and should reconnect.
-                    let opts = Self::get_config(&self.ip, &self.access_code)?;
+                    let opts = Self::get_config(&self.ip, &self.access_code, self.pinned.clone())?;
                     let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
                     drop(ep);
                     self.client = Arc::new(client);
@@ -109,35 +239,69 @@ impl Client {
             }
         };
 
-        let message = parse_message(&msg_opt);
+        let rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) = &msg_opt else {
+            return Ok(());
+        };
+        if let Some(debug) = &self.debug {
+            debug.record(&publish.topic, &publish.payload, chrono::Utc::now());
+        }
 
+        // Status pushes are coalesced, rather than all parsed as they come.
+        if is_push_status(&publish.payload) {
+            let now = std::time::Instant::now();
+            match self.status.offer(&publish.payload, now) {
+                Some(payload) => self.store(self.parse(&payload)),
+                // The status is still current, even if it's not parsed.
+                None => self.responses.touch(&SequenceId::status(), now),
+            }
+            return Ok(());
+        }
+
+        self.store(self.parse(&publish.payload));
+        Ok(())
+    }
+
+    /// Parse a payload from the printer, recording it if it couldn't be
+    /// parsed and we're recording the traffic.
+    fn parse(&self, payload: &[u8]) -> Message {
+        let (message, error) = parse_payload(payload);
+        if let (Some(debug), Some(error)) = (&self.debug, error) {
+            debug.record_parse_failure(payload, &error, chrono::Utc::now());
+        }
+        message
+    }
+
+    /// Store a message from the printer, for [Client::get_status] or
+    /// [Client::publish] to find.
+    fn store(&self, message: Message) {
+        let now = std::time::Instant::now();
         if let Some(sequence_id) = message.sequence_id() {
             // If the message is a push status, make the sequence id "status".
             if let Message::Print(Print::PushStatus(_)) = &message {
-                self.responses.insert(SequenceId::status(), message);
-                return Ok(());
+                self.responses.insert(SequenceId::status(), message, now);
+                return;
             }
 
-            self.responses.insert(sequence_id, message);
-            return Ok(());
+            self.responses.insert(sequence_id, message, now);
+            return;
         }
 
         if let Message::Unknown(None) = message {
-            return Ok(());
+            return;
         }
 
         tracing::error!("Received message AND COULD NOT INSERT: {:?}", message);
-
-        Ok(())
     }
 
     /// Get the latest status of the printer.
     pub fn get_status(&self) -> Result<Option<PushStatus>> {
-        let response = self.responses.get(&SequenceId::status());
-        if let Some(response) = response {
-            if let Message::Print(Print::PushStatus(status)) = response.value() {
-                return Ok(Some(status.clone()));
-            }
+        if let Some(payload) = self.status.take_pending(std::time::Instant::now()) {
+            self.store(self.parse(&payload));
+        }
+
+        let response = self.responses.get(&SequenceId::status(), std::time::Instant::now());
+        if let Some(Message::Print(Print::PushStatus(status))) = response {
+            return Ok(Some(status));
         }
 
         Ok(None)
@@ -171,6 +335,10 @@ impl Client {
     /// # Errors
     ///
     /// Returns an error if there was a problem publishing the command.
+    #[tracing::instrument(
+        skip_all,
+        fields(machine_id = self.machine_id.as_deref(), serial = %self.serial, sequence_id = %command.sequence_id()),
+    )]
     pub async fn publish(&self, command: Command) -> Result<Message> {
         let sequence_id = command.sequence_id();
         let payload = serde_json::to_string(&command)?;
@@ -187,55 +355,263 @@ impl Client {
         // Wait for the response.
         let current_time = std::time::Instant::now();
         while current_time.elapsed().as_secs() < 60 {
-            if let Some(response) = self.responses.get(sequence_id) {
-                return Ok(response.value().clone());
+            if let Some(response) = self.responses.take(sequence_id, std::time::Instant::now()) {
+                return Ok(response);
             }
             // This sleep is important since it frees up the thread.
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
 
-        anyhow::bail!("Timeout waiting for response to command: {:?}", command)
+        Err(TransientError(format!("Timeout waiting for response to command: {:?}", command)).into())
+    }
+
+    /// Negotiate the camera stream with the printer, offering
+    /// `op_protocols` for `peer_host` to connect with. Returns the printer's
+    /// reply, including the protocol it selected.
+    pub async fn live_view_init(&self, peer_host: &str, op_protocols: Vec<OperationProtocol>) -> Result<Init> {
+        let response = self.publish(Command::live_view_init(peer_host, op_protocols)).await?;
+        let Message::LiveView(LiveView::Init(init)) = response else {
+            anyhow::bail!("Unexpected response to live view init: {:?}", response);
+        };
+        if init.result != crate::message::Result::Success {
+            anyhow::bail!("Live view init failed: {:?}", init.reason);
+        }
+        Ok(init)
     }
 
-    /// Upload a file.
+    /// Ask the printer for the versions of its firmware and hardware
+    /// modules.
+    pub async fn get_version(&self) -> Result<GetVersion> {
+        let response = self.publish(Command::get_version()).await?;
+        let Message::Info(Info::GetVersion(version)) = response else {
+            anyhow::bail!("Unexpected response to get version: {:?}", response);
+        };
+        Ok(version)
+    }
+
+    /// Upload a file, keeping its local filename on the printer.
     pub async fn upload_file(&self, path: &std::path::Path) -> Result<()> {
+        let remote_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file path"))?;
+        self.upload_file_as(path, remote_name).await
+    }
+
+    /// Upload a file, storing it on the printer as `remote_name`. The
+    /// caller is responsible for making sure `remote_name` is safe to use
+    /// as an FTP path.
+    ///
+    /// Interrupted uploads are resumed (appending to the partial file on
+    /// the printer) a couple of times, and the upload is only
+    /// considered done once the size of the file on the printer matches the
+    /// local file. If the upload can't be completed, the partial file is
+    /// removed from the printer.
+    #[tracing::instrument(skip(self, path), fields(machine_id = self.machine_id.as_deref(), serial = %self.serial))]
+    pub async fn upload_file_as(&self, path: &std::path::Path, remote_name: &str) -> Result<()> {
+        let local_path = path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid file path"))?
+            .to_string();
+        let local_size = std::fs::metadata(path)?.len();
+
+        let mut last_error = None;
+        for attempt in 1..=UPLOAD_ATTEMPTS {
+            let mut args = vec!["--upload-file".to_string(), local_path.clone()];
+            if attempt > 1 {
+                // Ask the server how much of the file it has (SIZE), and
+                // append the rest (APPE).
+                args.extend(["--continue-at".to_string(), "-".to_string()]);
+            }
+
+            if let Err(e) = self.curl(remote_name, &args).await {
+                tracing::warn!(
+                    attempt = attempt,
+                    remote_name = remote_name,
+                    error = format!("{:?}", e),
+                    "upload interrupted"
+                );
+                last_error = Some(e);
+                continue;
+            }
+
+            match self.remote_file_size(remote_name).await {
+                Ok(Some(remote_size)) if remote_size == local_size => return Ok(()),
+                Ok(remote_size) => {
+                    tracing::warn!(
+                        attempt = attempt,
+                        remote_name = remote_name,
+                        local_size = local_size,
+                        remote_size = remote_size,
+                        "uploaded file size mismatch"
+                    );
+                    if remote_size.is_some_and(|remote_size| remote_size > local_size) {
+                        // Nothing to resume from; start over.
+                        self.delete_file(remote_name).await?;
+                    }
+                    last_error = Some(anyhow::anyhow!(
+                        "Uploaded file size mismatch: expected {} bytes, found {:?}",
+                        local_size,
+                        remote_size
+                    ));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        // Don't leave a partial file on the SD card.
+        if let Err(e) = self.delete_file(remote_name).await {
+            tracing::warn!(
+                remote_name = remote_name,
+                error = format!("{:?}", e),
+                "failed to clean up partial upload"
+            );
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow::anyhow!("Failed to upload file"))
+            .context(format!(
+                "Failed to upload {} after {} attempts",
+                remote_name, UPLOAD_ATTEMPTS
+            )))
+    }
+
+    /// Get the size of a file on the printer, in bytes, or `None` if the
+    /// server didn't report one.
+    #[tracing::instrument(skip(self), fields(machine_id = self.machine_id.as_deref(), serial = %self.serial))]
+    pub async fn remote_file_size(&self, remote_name: &str) -> Result<Option<u64>> {
+        let output = self.curl(remote_name, &["--head".to_string()]).await?;
+        Ok(parse_content_length(std::str::from_utf8(&output.stdout)?))
+    }
+
+    /// Get how much room is left on the printer's SD card, in bytes, or
+    /// `None` if the printer won't say.
+    #[tracing::instrument(skip(self), fields(machine_id = self.machine_id.as_deref(), serial = %self.serial))]
+    pub async fn free_space(&self) -> Result<Option<u64>> {
+        // AVBL isn't a standard FTP command, so carry on (`*`) if the
+        // server doesn't know it. Its reply is only in curl's log.
+        let output = self
+            .curl(
+                "",
+                &["--verbose".to_string(), "--quote".to_string(), "*AVBL".to_string()],
+            )
+            .await?;
+        Ok(parse_available(std::str::from_utf8(&output.stderr)?))
+    }
+
+    /// Delete a file from the printer.
+    #[tracing::instrument(skip(self), fields(machine_id = self.machine_id.as_deref(), serial = %self.serial))]
+    pub async fn delete_file(&self, remote_name: &str) -> Result<()> {
+        self.curl("", &["--quote".to_string(), format!("DELE {}", remote_name)])
+            .await?;
+        Ok(())
+    }
+
+    /// Run `curl` against `remote_name` on the printer's FTPS server.
+    async fn curl(&self, remote_name: &str, extra_args: &[String]) -> Result<std::process::Output> {
         let host_url = url::Url::parse(&format!("mqtts://{}:{}", self.ip, MQTT_PORT))?;
         let host = host_url
             .host_str()
             .ok_or(anyhow::anyhow!("not a valid hostname"))?
             .to_string();
-        let access_code = self.access_code.clone();
-        let path = path.to_path_buf();
-        let args: Vec<String> = vec![
+        let mut args: Vec<String> = vec![
             "--silent".to_string(),
-            "--upload-file".to_string(),
-            path.to_str()
-                .ok_or_else(|| anyhow::anyhow!("Invalid file path"))?
-                .to_string(),
+            "--show-error".to_string(),
             "--ftp-pasv".to_string(),
             "--insecure".to_string(),
-            format!("ftps://{}/", host).to_string(),
+            format!("ftps://{}/{}", host, remote_name).to_string(),
             "--user".to_string(),
-            format!("bblp:{}", access_code).to_string(),
+            format!("bblp:{}", self.access_code).to_string(),
         ];
+        if let Some(pinned) = &self.pinned {
+            // The certificate doesn't name the printer, so curl still needs
+            // `--insecure`, but it checks pinned keys regardless. It pins the
+            // public key rather than the whole certificate, so we can only
+            // pin it once we've seen (and checked) the certificate over MQTT.
+            let Some(pin) = pinned.public_key_pin() else {
+                anyhow::bail!("Printer's certificate hasn't been checked yet, refusing to connect over FTPS");
+            };
+            args.extend(["--pinnedpubkey".to_string(), pin]);
+        }
+        args.extend_from_slice(extra_args);
         let output = tokio::process::Command::new("curl")
             .args(&args)
             .output()
             .await
-            .context("Failed to upload file")?;
+            .context("Failed to run curl")?;
+        tracing::debug!(status = %output.status, "curl exited");
 
         // Make sure the command was successful.
         if !output.status.success() {
             let stdout = std::str::from_utf8(&output.stdout)?;
             let stderr = std::str::from_utf8(&output.stderr)?;
-            anyhow::bail!(
-                "Failed to upload file: {:?}\nstdout:\n{}stderr:{}",
-                output,
-                stdout,
-                stderr
+            let message = format!(
+                "FTP request failed: {:?}\nstdout:\n{}stderr:{}",
+                output.status, stdout, stderr
             );
+            if output
+                .status
+                .code()
+                .is_some_and(|code| CURL_TRANSIENT_EXIT_CODES.contains(&code))
+            {
+                return Err(TransientError(message).into());
+            }
+            anyhow::bail!(message);
         }
 
-        Ok(())
+        Ok(output)
+    }
+}
+
+/// Pull the file size out of the headers `curl --head` prints for an FTP
+/// file.
+fn parse_content_length(headers: &str) -> Option<u64> {
+    headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Find the reply to `AVBL` (the space available, in bytes) in curl's
+/// verbose log of an FTP session.
+fn parse_available(log: &str) -> Option<u64> {
+    let mut lines = log.lines().skip_while(|line| !line.starts_with("> AVBL"));
+    lines.next()?;
+    let reply = lines.find(|line| line.starts_with("< "))?;
+    reply.strip_prefix("< 213 ")?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_length() {
+        assert_eq!(
+            parse_content_length(
+                "Last-Modified: Tue, 01 Oct 2024 10:00:00 GMT\r\nContent-Length: 1234\r\nAccept-ranges: bytes\r\n"
+            ),
+            Some(1234)
+        );
+        assert_eq!(parse_content_length("content-length:42"), Some(42));
+        assert_eq!(parse_content_length("Accept-ranges: bytes\r\n"), None);
+        assert_eq!(parse_content_length(""), None);
+    }
+
+    #[test]
+    fn test_parse_available() {
+        assert_eq!(
+            parse_available("< 230 Login successful.\n> PWD\n< 257 \"/\"\n> AVBL\n< 213 1048576\n> PASV\n"),
+            Some(1048576)
+        );
+        assert_eq!(
+            parse_available("> SIZE cube.3mf\n< 213 1234\n> AVBL\n< 500 Unknown command.\n"),
+            None
+        );
+        assert_eq!(parse_available("< 230 Login successful.\n"), None);
     }
 }
@@ -0,0 +1,162 @@
+//! Coalescing of the printer's status pushes. Printers (the X1 series
+//! especially) push their status several times a second, and parsing every
+//! push adds up on a server managing dozens of printers. Pushes which don't
+//! change anything are dropped, and the rest are parsed at most once per
+//! interval, or when the status is read, if that's sooner.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How often status pushes are parsed, unless configured otherwise.
+pub(crate) const DEFAULT_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Return true if `payload` is a status push, without parsing it.
+pub(crate) fn is_push_status(payload: &[u8]) -> bool {
+    find(payload, br#""push_status""#).is_some()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Split `payload` around the value of its `sequence_id`, which changes
+/// with every push, so two pushes can be compared without it.
+fn split_sequence_id(payload: &[u8]) -> (&[u8], &[u8]) {
+    const KEY: &[u8] = br#""sequence_id":"#;
+    let Some(start) = find(payload, KEY).map(|start| start + KEY.len()) else {
+        return (payload, &[]);
+    };
+    let end = payload[start..]
+        .iter()
+        .position(|byte| *byte == b',' || *byte == b'}')
+        .map_or(payload.len(), |end| start + end);
+    (&payload[..start], &payload[end..])
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The last payload pushed, parsed or not.
+    last: Option<Vec<u8>>,
+
+    /// A payload which hasn't been parsed yet.
+    pending: Option<Vec<u8>>,
+
+    /// When a payload was last parsed.
+    parsed_at: Option<Instant>,
+}
+
+/// Decides which status pushes are worth parsing.
+#[derive(Debug)]
+pub(crate) struct StatusCoalescer {
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl StatusCoalescer {
+    /// Creates a new `StatusCoalescer`, parsing at most one push every
+    /// `interval`.
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Offer a status push, returning it if it should be parsed now. If
+    /// it's changed, but it's too soon to parse it, it's kept until the
+    /// next push (or read) which is due.
+    pub(crate) fn offer(&self, payload: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let Ok(mut state) = self.state.lock() else {
+            return Some(payload.to_vec());
+        };
+
+        if state
+            .last
+            .as_deref()
+            .is_some_and(|last| split_sequence_id(last) == split_sequence_id(payload))
+        {
+            tracing::trace!("dropping unchanged status push");
+            if !self.is_due(&state, now) {
+                return None;
+            }
+            let pending = state.pending.take()?;
+            state.parsed_at = Some(now);
+            return Some(pending);
+        }
+        state.last = Some(payload.to_vec());
+
+        if !self.is_due(&state, now) {
+            state.pending = Some(payload.to_vec());
+            return None;
+        }
+        state.pending = None;
+        state.parsed_at = Some(now);
+        Some(payload.to_vec())
+    }
+
+    /// Take the push waiting to be parsed, if there is one, so the status
+    /// is up to date when it's read.
+    pub(crate) fn take_pending(&self, now: Instant) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().ok()?;
+        let pending = state.pending.take()?;
+        state.parsed_at = Some(now);
+        Some(pending)
+    }
+
+    fn is_due(&self, state: &State, now: Instant) -> bool {
+        state
+            .parsed_at
+            .is_none_or(|parsed_at| now.duration_since(parsed_at) >= self.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(sequence_id: u32, temperature: u32) -> Vec<u8> {
+        format!(
+            r#"{{"print":{{"command":"push_status","sequence_id":"{}","nozzle_temper":{}}}}}"#,
+            sequence_id, temperature
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_is_push_status() {
+        assert!(is_push_status(&push(1, 200)));
+        assert!(!is_push_status(
+            br#"{"print":{"command":"project_file","sequence_id":"2"}}"#
+        ));
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let start = Instant::now();
+        let coalescer = StatusCoalescer::new(Duration::from_secs(1));
+
+        // The first push is parsed right away.
+        assert_eq!(coalescer.offer(&push(1, 200), start), Some(push(1, 200)));
+
+        // Pushes which only differ by sequence id are dropped.
+        assert_eq!(coalescer.offer(&push(2, 200), start + Duration::from_secs(2)), None);
+
+        // Changes within the interval wait, and only the latest is kept.
+        let now = start + Duration::from_secs(3);
+        assert_eq!(coalescer.offer(&push(3, 210), now), Some(push(3, 210)));
+        assert_eq!(coalescer.offer(&push(4, 215), now), None);
+        assert_eq!(coalescer.offer(&push(5, 220), now), None);
+        assert_eq!(coalescer.take_pending(now), Some(push(5, 220)));
+        assert_eq!(coalescer.take_pending(now), None);
+
+        // A change left waiting is parsed with the next push that's due,
+        // even if that push doesn't change anything.
+        assert_eq!(coalescer.offer(&push(6, 225), now), None);
+        assert_eq!(
+            coalescer.offer(&push(7, 225), now + Duration::from_secs(1)),
+            Some(push(6, 225))
+        );
+    }
+}
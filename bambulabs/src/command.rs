@@ -4,7 +4,7 @@ use parse_display::{Display, FromStr};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{sequence_id::SequenceId, speedprofile::SpeedProfile};
+use crate::{fan::Fan, sequence_id::SequenceId, speedprofile::SpeedProfile};
 
 /// The commands that can be sent to the printer.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -20,6 +20,8 @@ pub enum Command {
     Pushing(Pushing),
     /// A camera command.
     Camera(Camera),
+    /// A liveview command.
+    LiveView(LiveView),
 }
 
 impl Command {
@@ -31,6 +33,7 @@ impl Command {
             Command::System(system) => system.sequence_id(),
             Command::Pushing(pushing) => pushing.sequence_id(),
             Command::Camera(camera) => camera.sequence_id(),
+            Command::LiveView(live_view) => live_view.sequence_id(),
         }
     }
 
@@ -85,6 +88,12 @@ impl Command {
         }))
     }
 
+    /// Return a command to set a fan's speed, as a percentage. Speeds over
+    /// 100% are taken as 100%.
+    pub fn set_fan_speed(fan: Fan, percent: u8) -> Self {
+        Self::send_gcode_line(&fan.set_speed_gcode(percent))
+    }
+
     /// Return a command to set the chamber light.
     pub fn set_chamber_light(led_mode: LedMode) -> Self {
         Command::System(System::Ledctrl(Ledctrl {
@@ -106,19 +115,31 @@ impl Command {
         }))
     }
 
-    /// Return a command to print a file on the ftp server.
-    pub fn print_file(job_name: &str, filename: &str, use_ams: bool) -> Self {
+    /// Return a command to negotiate the camera stream. `peer_host` is the
+    /// host which will connect to the stream, and `op_protocols` are the
+    /// protocols it can speak, in order of preference.
+    pub fn live_view_init(peer_host: &str, op_protocols: Vec<OperationProtocol>) -> Self {
+        Command::LiveView(LiveView::Init(Init {
+            sequence_id: SequenceId::new(),
+            peer_host: peer_host.to_string(),
+            op_protocols,
+        }))
+    }
+
+    /// Return a command to print a file on the ftp server, with the
+    /// printer's checks and extras set by `options`.
+    pub fn print_file(job_name: &str, filename: &str, use_ams: bool, options: PrintOptions) -> Self {
         Command::Print(Print::ProjectFile(ProjectFile {
             sequence_id: SequenceId::new(),
             param: format!("Metadata/plate_{}.gcode", 1),
             subtask_name: job_name.to_string(),
             url: format!("ftp://{}", filename),
             bed_type: BedType::Auto,
-            timelapsed: false,
-            bed_leveling: true,
-            flow_calibration: true,
-            vibration_calibration: true,
-            layer_inspect: false,
+            timelapsed: options.timelapse,
+            bed_leveling: options.bed_leveling,
+            flow_calibration: options.flow_calibration,
+            vibration_calibration: options.vibration_calibration,
+            layer_inspect: options.layer_inspect,
             use_ams,
             // I have no idea if we should set the below but in the python lib, they just made
             // them all zeroes.
@@ -205,6 +226,53 @@ impl Camera {
     }
 }
 
+/// A liveview command.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "command")]
+pub enum LiveView {
+    /// Initialize the live view.
+    Init(Init),
+}
+
+impl LiveView {
+    /// Get the sequence ID.
+    pub fn sequence_id(&self) -> &SequenceId {
+        match self {
+            LiveView::Init(Init { sequence_id, .. }) => sequence_id,
+        }
+    }
+}
+
+/// The payload for initializing the live view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Init {
+    /// The sequence ID.
+    pub sequence_id: SequenceId,
+    /// The host which will connect to the stream.
+    pub peer_host: String,
+    /// The protocols the peer can speak.
+    pub op_protocols: Vec<OperationProtocol>,
+}
+
+/// A protocol offered when initializing the live view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OperationProtocol {
+    /// The protocol, such as `rtsps`.
+    pub protocol: String,
+    /// The protocol version.
+    pub version: String,
+}
+
+impl OperationProtocol {
+    /// Create a new operation protocol.
+    pub fn new(protocol: &str, version: &str) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            version: version.to_string(),
+        }
+    }
+}
+
 /// The payload for recording.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Record {
@@ -326,6 +394,33 @@ pub struct Stop {
     pub sequence_id: SequenceId,
 }
 
+/// Checks and extras the printer runs along with a print.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+pub struct PrintOptions {
+    /// Level the bed before printing.
+    pub bed_leveling: bool,
+    /// Calibrate the extrusion flow before printing.
+    pub flow_calibration: bool,
+    /// Calibrate for vibration (resonance compensation) before printing.
+    pub vibration_calibration: bool,
+    /// Record a timelapse of the print.
+    pub timelapse: bool,
+    /// Inspect the first layer with the lidar, on printers which have one.
+    pub layer_inspect: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            bed_leveling: true,
+            flow_calibration: true,
+            vibration_calibration: true,
+            timelapse: false,
+            layer_inspect: false,
+        }
+    }
+}
+
 /// The payload for starting a print with a file on the ftp server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ProjectFile {
@@ -809,6 +904,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_fan_speed() {
+        let command = Command::set_fan_speed(Fan::Chamber, 60);
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"print":{"command":"gcode_line","sequence_id":1,"param":"M106 P3 S153"}}"#
+        );
+    }
+
     #[test]
     fn test_send_gcode_line() {
         let command = Command::send_gcode_line("G28");
@@ -841,11 +946,35 @@ mod tests {
 
     #[test]
     fn test_print_file() {
-        let command = Command::print_file("myjob", "thing.3mf", true);
+        let command = Command::print_file("myjob", "thing.3mf", true, PrintOptions::default());
         let payload = serde_json::to_string(&command).unwrap();
         assert_eq!(
             payload,
             r#"{"print":{"command":"project_file","sequence_id":1,"param":"Metadata/plate_1.gcode","subtask_name":"myjob","url":"ftp://thing.3mf","bed_type":"auto","timelapsed":false,"bed_leveling":true,"flow_calibration":true,"vibration_calibration":true,"layer_inspect":false,"use_ams":true,"profile_id":"0","project_id":"0","subtask_id":"0","task_id":"0"}}"#
         );
+
+        let options = PrintOptions {
+            bed_leveling: false,
+            timelapse: true,
+            ..Default::default()
+        };
+        let Command::Print(Print::ProjectFile(project_file)) =
+            Command::print_file("myjob", "thing.3mf", false, options)
+        else {
+            panic!("not a project_file command");
+        };
+        assert!(!project_file.bed_leveling);
+        assert!(project_file.timelapsed);
+        assert!(project_file.flow_calibration);
+    }
+
+    #[test]
+    fn test_live_view_init() {
+        let command = Command::live_view_init("192.168.1.50", vec![OperationProtocol::new("rtsps", "1")]);
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"live_view":{"command":"init","sequence_id":1,"peer_host":"192.168.1.50","op_protocols":[{"protocol":"rtsps","version":"1"}]}}"#
+        );
     }
 }
@@ -0,0 +1,243 @@
+//! Recording of the MQTT traffic from the printer, for working out what's
+//! changed when new firmware sends payloads we don't understand. This is
+//! off unless asked for, since it keeps copies of recent payloads.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// How many payloads which failed to parse are kept.
+const PARSE_FAILURE_CAPACITY: usize = 16;
+
+/// Longest payload kept, in bytes. Longer ones are truncated.
+const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+
+/// Messages received on one topic, with one command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MqttTopicStats {
+    /// The MQTT topic.
+    pub topic: String,
+
+    /// The message's type and command (such as `print.push_status`), if
+    /// it's JSON which says.
+    pub command: Option<String>,
+
+    /// How many messages have been received.
+    pub count: u64,
+
+    /// When the last message was received.
+    pub last_received_at: DateTime<Utc>,
+}
+
+/// A payload received from the printer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MqttPayload {
+    /// When the payload was received.
+    pub received_at: DateTime<Utc>,
+
+    /// The MQTT topic it was received on.
+    pub topic: String,
+
+    /// The payload, as text.
+    pub payload: String,
+
+    /// Whether the payload was too long to keep whole.
+    pub truncated: bool,
+}
+
+/// A payload from the printer which couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MqttParseFailure {
+    /// When the payload was parsed.
+    pub parsed_at: DateTime<Utc>,
+
+    /// Why it couldn't be parsed.
+    pub error: String,
+
+    /// The payload, as text.
+    pub payload: String,
+
+    /// Whether the payload was too long to keep whole.
+    pub truncated: bool,
+}
+
+/// What's been recorded of the MQTT traffic from the printer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MqttDebugSnapshot {
+    /// Messages received, by topic and command.
+    pub topics: Vec<MqttTopicStats>,
+
+    /// The most recent payloads received, oldest first.
+    pub recent_payloads: Vec<MqttPayload>,
+
+    /// How many payloads have failed to parse.
+    pub parse_failure_count: u64,
+
+    /// The most recent payloads which failed to parse, oldest first.
+    pub parse_failures: Vec<MqttParseFailure>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    topics: BTreeMap<(String, Option<String>), MqttTopicStats>,
+    recent_payloads: VecDeque<MqttPayload>,
+    parse_failure_count: u64,
+    parse_failures: VecDeque<MqttParseFailure>,
+}
+
+/// Records the MQTT traffic from the printer.
+#[derive(Debug)]
+pub(crate) struct MqttDebug {
+    /// How many recent payloads are kept.
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl MqttDebug {
+    /// Creates a new `MqttDebug`, keeping the last `capacity` payloads.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Record a payload received on `topic`.
+    pub(crate) fn record(&self, topic: &str, payload: &[u8], now: DateTime<Utc>) {
+        let command = command(payload);
+        let (text, truncated) = truncate(payload);
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .topics
+            .entry((topic.to_owned(), command.clone()))
+            .and_modify(|stats| {
+                stats.count += 1;
+                stats.last_received_at = now;
+            })
+            .or_insert_with(|| MqttTopicStats {
+                topic: topic.to_owned(),
+                command,
+                count: 1,
+                last_received_at: now,
+            });
+
+        if state.recent_payloads.len() >= self.capacity {
+            state.recent_payloads.pop_front();
+        }
+        state.recent_payloads.push_back(MqttPayload {
+            received_at: now,
+            topic: topic.to_owned(),
+            payload: text,
+            truncated,
+        });
+    }
+
+    /// Record a payload which couldn't be parsed, and why.
+    pub(crate) fn record_parse_failure(&self, payload: &[u8], error: &str, now: DateTime<Utc>) {
+        let (text, truncated) = truncate(payload);
+
+        let mut state = self.state.lock().unwrap();
+        state.parse_failure_count += 1;
+        if state.parse_failures.len() >= PARSE_FAILURE_CAPACITY {
+            state.parse_failures.pop_front();
+        }
+        state.parse_failures.push_back(MqttParseFailure {
+            parsed_at: now,
+            error: error.to_owned(),
+            payload: text,
+            truncated,
+        });
+    }
+
+    /// Return what's been recorded so far.
+    pub(crate) fn snapshot(&self) -> MqttDebugSnapshot {
+        let state = self.state.lock().unwrap();
+        MqttDebugSnapshot {
+            topics: state.topics.values().cloned().collect(),
+            recent_payloads: state.recent_payloads.iter().cloned().collect(),
+            parse_failure_count: state.parse_failure_count,
+            parse_failures: state.parse_failures.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Return the type and command of a JSON payload, such as
+/// `print.push_status`, or just the type if it has no command.
+fn command(payload: &[u8]) -> Option<String> {
+    let serde_json::Value::Object(message) = serde_json::from_slice(payload).ok()? else {
+        return None;
+    };
+    let (kind, body) = message.iter().next()?;
+    match body.get("command").and_then(|command| command.as_str()) {
+        Some(command) => Some(format!("{}.{}", kind, command)),
+        None => Some(kind.to_owned()),
+    }
+}
+
+/// Return `payload` as text, cut down to [MAX_PAYLOAD_LEN] bytes, and
+/// whether it had to be.
+fn truncate(payload: &[u8]) -> (String, bool) {
+    let truncated = payload.len() > MAX_PAYLOAD_LEN;
+    let payload = &payload[..payload.len().min(MAX_PAYLOAD_LEN)];
+    (String::from_utf8_lossy(payload).to_string(), truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            command(br#"{"print": {"command": "push_status", "sequence_id": "1"}}"#),
+            Some("print.push_status".to_owned())
+        );
+        assert_eq!(command(br#"{"info": {"sequence_id": "1"}}"#), Some("info".to_owned()));
+        assert_eq!(command(br#"[1, 2]"#), None);
+        assert_eq!(command(b"not json"), None);
+    }
+
+    #[test]
+    fn test_record() {
+        let debug = MqttDebug::new(2);
+        let now = Utc::now();
+        let topic = "device/123/report";
+
+        debug.record(topic, br#"{"print": {"command": "push_status"}}"#, now);
+        debug.record(topic, br#"{"print": {"command": "push_status"}}"#, now);
+        debug.record(topic, br#"{"info": {"command": "get_version"}}"#, now);
+        debug.record_parse_failure(b"{", "EOF while parsing an object", now);
+
+        let snapshot = debug.snapshot();
+        assert_eq!(
+            snapshot
+                .topics
+                .iter()
+                .map(|stats| (stats.command.as_deref(), stats.count))
+                .collect::<Vec<_>>(),
+            vec![(Some("info.get_version"), 1), (Some("print.push_status"), 2)]
+        );
+        assert_eq!(snapshot.recent_payloads.len(), 2);
+        assert_eq!(
+            snapshot.recent_payloads[1].payload,
+            r#"{"info": {"command": "get_version"}}"#
+        );
+        assert_eq!(snapshot.parse_failure_count, 1);
+        assert_eq!(snapshot.parse_failures[0].payload, "{");
+    }
+
+    #[test]
+    fn test_truncate() {
+        let payload = vec![b'a'; MAX_PAYLOAD_LEN + 1];
+        let (text, truncated) = truncate(&payload);
+        assert_eq!(text.len(), MAX_PAYLOAD_LEN);
+        assert!(truncated);
+        assert_eq!(truncate(b"{}"), ("{}".to_owned(), false));
+    }
+}
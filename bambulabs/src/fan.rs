@@ -3,6 +3,12 @@
 use parse_display::{Display, FromStr};
 use serde::{Deserialize, Serialize};
 
+/// Highest speed a fan can be set to with `M106`.
+const MAX_PWM: u8 = 255;
+
+/// Highest speed level a fan reports, such as in `cooling_fan_speed`.
+const MAX_LEVEL: u8 = 15;
+
 /// Enum for the fans in the printer.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, FromStr)]
 #[serde(rename_all = "snake_case")]
@@ -15,3 +21,54 @@ pub enum Fan {
     /// The chamber fan.
     Chamber = 3,
 }
+
+impl Fan {
+    /// Return the gcode to set the fan's speed, as a percentage. Speeds
+    /// over 100% are taken as 100%.
+    pub fn set_speed_gcode(self, percent: u8) -> String {
+        format!("M106 P{} S{}", self as u8, percent_to_pwm(percent))
+    }
+}
+
+/// Convert a fan speed, as a percentage, to the PWM duty `M106` takes (out
+/// of 255). Speeds over 100% are taken as 100%.
+pub fn percent_to_pwm(percent: u8) -> u8 {
+    ((percent.min(100) as u32 * MAX_PWM as u32 + 50) / 100) as u8
+}
+
+/// Convert a fan speed level, as the printer reports it (out of 15, such
+/// as in `cooling_fan_speed` or `big_fan1_speed`), to a percentage. The
+/// printer's own screen shows these rounded to the nearest 10%.
+pub fn level_to_percent(level: u8) -> u8 {
+    let tens = (level.min(MAX_LEVEL) as u32 * 10 + MAX_LEVEL as u32 / 2) / MAX_LEVEL as u32;
+    (tens * 10) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_to_pwm() {
+        assert_eq!(percent_to_pwm(0), 0);
+        assert_eq!(percent_to_pwm(50), 128);
+        assert_eq!(percent_to_pwm(100), 255);
+        assert_eq!(percent_to_pwm(250), 255);
+    }
+
+    #[test]
+    fn test_level_to_percent() {
+        assert_eq!(level_to_percent(0), 0);
+        assert_eq!(level_to_percent(1), 10);
+        assert_eq!(level_to_percent(7), 50);
+        assert_eq!(level_to_percent(15), 100);
+        assert_eq!(level_to_percent(200), 100);
+    }
+
+    #[test]
+    fn test_set_speed_gcode() {
+        assert_eq!(Fan::PartCooling.set_speed_gcode(100), "M106 P1 S255");
+        assert_eq!(Fan::Auxiliary.set_speed_gcode(40), "M106 P2 S102");
+        assert_eq!(Fan::Chamber.set_speed_gcode(0), "M106 P3 S0");
+    }
+}
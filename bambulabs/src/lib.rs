@@ -2,13 +2,20 @@
 
 #![deny(missing_docs)]
 
+mod cache;
+mod camera;
 pub mod client;
+mod coalesce;
 pub mod command;
+pub mod debug;
 pub mod fan;
 pub mod features;
 pub mod message;
 mod no_auth;
 pub mod parser;
+mod pinned;
 pub mod sequence_id;
 pub mod speedprofile;
 pub mod templates;
+
+pub use camera::JpegCamera;
@@ -154,6 +154,14 @@ pub struct Init {
     other: BTreeMap<String, Value>,
 }
 
+impl Init {
+    /// Returns the protocol the printer selected for the stream, which is
+    /// the first one it replied with.
+    pub fn selected_protocol(&self) -> Option<&OperationProtocol> {
+        self.op_protocols.first()
+    }
+}
+
 /// An operation protocol.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct OperationProtocol {
@@ -161,6 +169,10 @@ pub struct OperationProtocol {
     pub protocol: String,
     /// The version.
     pub version: String,
+    /// The URL to connect to the stream at, when the printer selected this
+    /// protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
     #[serde(flatten)]
     other: BTreeMap<String, Value>,
 }
@@ -711,8 +723,10 @@ pub struct PrintAms {
 pub struct PrintAmsData {
     /// The id.
     pub id: String,
-    /// The humidity.
+    /// The humidity level, from 1 to 5.
     pub humidity: String,
+    /// The relative humidity, as a percentage. Only sent by newer firmware.
+    pub humidity_raw: Option<String>,
     /// The temperature.
     pub temp: String,
     /// The tray.
@@ -855,6 +869,17 @@ pub struct GetVersion {
     other: BTreeMap<String, Value>,
 }
 
+impl GetVersion {
+    /// The version of the printer's firmware, which is that of its `ota`
+    /// module.
+    pub fn firmware_version(&self) -> Option<&str> {
+        self.module
+            .iter()
+            .find(|module| module.name == "ota")
+            .map(|module| module.sw_ver.as_str())
+    }
+}
+
 /// An info module.
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct InfoModule {
@@ -1008,6 +1033,30 @@ mod tests {
         assert!(matches!(result.unwrap(), Message::Print(_)));
     }
 
+    #[test]
+    fn test_deserialize_message_live_view_init() {
+        let message = r#"{
+            "live_view": {
+                "command": "init",
+                "sequence_id": 2,
+                "peer_host": "192.168.1.50",
+                "op_protocols": [{"protocol": "rtsps", "version": "1", "url": "rtsps://192.168.1.103/streaming/live/1"}],
+                "reason": "SUCCESS",
+                "result": "SUCCESS"
+            }
+        }"#;
+
+        let Message::LiveView(LiveView::Init(init)) = serde_json::from_str::<Message>(message).unwrap() else {
+            panic!("Invalid message deserialized");
+        };
+        assert_eq!(init.result, Result::Success);
+        assert_eq!(init.selected_protocol().unwrap().protocol, "rtsps");
+        assert_eq!(
+            init.selected_protocol().unwrap().url.as_deref(),
+            Some("rtsps://192.168.1.103/streaming/live/1")
+        );
+    }
+
     #[test]
     fn test_deserialize_message_info() {
         let message = format!(
@@ -1037,6 +1086,46 @@ mod tests {
         assert!(matches!(result.unwrap(), Message::Info(_)));
     }
 
+    #[test]
+    fn test_get_version_firmware_version() {
+        let message = r#"{
+            "info":{
+                "command":"get_version",
+                "sequence_id":2,
+                "module":[
+                    {
+                        "name":"mc",
+                        "project_name":"C11",
+                        "sw_ver":"00.00.19.15",
+                        "hw_ver":"MC07",
+                        "sn":"01S00C123400001"
+                    },
+                    {
+                        "name":"ota",
+                        "project_name":"C11",
+                        "sw_ver":"01.04.02.00",
+                        "hw_ver":"OTA",
+                        "sn":"01S00C123400001"
+                    }
+                ],
+                "result":"SUCCESS",
+                "reason":""
+            }
+        }"#;
+
+        let Message::Info(Info::GetVersion(version)) = serde_json::from_str::<Message>(message).unwrap() else {
+            panic!("Invalid message deserialized");
+        };
+        assert_eq!(version.firmware_version(), Some("01.04.02.00"));
+
+        let Message::Info(Info::GetVersion(version)) =
+            serde_json::from_str::<Message>(&message.replace(r#""name":"ota""#, r#""name":"ahb""#)).unwrap()
+        else {
+            panic!("Invalid message deserialized");
+        };
+        assert_eq!(version.firmware_version(), None);
+    }
+
     #[test]
     fn test_deserialize_message_system() {
         let message = format!(
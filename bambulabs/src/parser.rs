@@ -2,29 +2,28 @@
 
 use crate::message::Message;
 
-pub(crate) fn parse_message(message: &rumqttc::Event) -> Message {
-    match message {
-        rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish)) => {
-            let payload = publish.payload.clone();
+/// Parse the payload of a message published by the printer, along with why
+/// it couldn't be parsed as a known [Message], if it couldn't.
+pub(crate) fn parse_payload(payload: &[u8]) -> (Message, Option<String>) {
+    let Ok(payload) = std::str::from_utf8(payload) else {
+        return (
+            Message::Unknown(Some(String::from_utf8_lossy(payload).to_string())),
+            Some("payload isn't valid UTF-8".to_owned()),
+        );
+    };
 
-            if let Ok(payload) = std::str::from_utf8(&payload) {
-                match serde_json::from_str::<Message>(payload)
-                    .map_err(|err| format_serde_error::SerdeError::new(payload.to_string(), err))
-                {
-                    Ok(message) => {
-                        return message;
-                    }
-                    Err(err) => {
-                        tracing::error!("Error parsing message: {:?}", err);
-                        if let Ok(message) = serde_json::from_str::<serde_json::Value>(payload) {
-                            return Message::Json(message);
-                        }
-                    }
-                }
+    match serde_json::from_str::<Message>(payload) {
+        Ok(message) => (message, None),
+        Err(err) => {
+            let error = err.to_string();
+            tracing::error!(
+                "Error parsing message: {:?}",
+                format_serde_error::SerdeError::new(payload.to_string(), err)
+            );
+            match serde_json::from_str::<serde_json::Value>(payload) {
+                Ok(message) => (Message::Json(message), Some(error)),
+                Err(_) => (Message::Unknown(Some(payload.to_string())), Some(error)),
             }
-
-            Message::Unknown(Some(std::str::from_utf8(&payload).unwrap().to_string()))
         }
-        _ => Message::Unknown(None),
     }
 }
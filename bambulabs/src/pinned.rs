@@ -0,0 +1,271 @@
+//! A `ServerCertVerifier` that trusts a single certificate, identified by
+//! its SHA-256 fingerprint.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use rustls::crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+
+/// Parse a SHA-256 fingerprint, as hex with or without `:` separators
+/// (such as `openssl x509 -fingerprint -sha256` prints).
+pub(crate) fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        anyhow::bail!("Invalid SHA-256 fingerprint: {:?}", fingerprint);
+    }
+
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow::anyhow!("Invalid SHA-256 fingerprint: {:?}", fingerprint))?;
+    }
+    Ok(bytes)
+}
+
+/// Format a SHA-256 fingerprint as `:` separated hex, the way
+/// `openssl x509 -fingerprint -sha256` prints it.
+pub(crate) fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    fingerprint
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Read one DER element from the front of `der`, returning its tag, its
+/// contents, the whole element (header included), and whatever follows it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8], &[u8])> {
+    let tag = *der.first()?;
+    let first = *der.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let len = der
+            .get(2..2 + count)?
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+    let end = header.checked_add(len)?;
+    Some((tag, der.get(header..end)?, der.get(..end)?, der.get(end..)?))
+}
+
+/// Pull the `SubjectPublicKeyInfo` out of a DER encoded X.509 certificate.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate, ... }
+    let (_, certificate, _, _) = der_element(certificate)?;
+    let (_, tbs, _, _) = der_element(certificate)?;
+
+    // TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber,
+    // signature, issuer, validity, subject, subjectPublicKeyInfo, ... }
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.3;
+    }
+    for _ in 0..5 {
+        rest = der_element(rest)?.3;
+    }
+    let (tag, _, spki, _) = der_element(rest)?;
+    (tag == 0x30).then_some(spki)
+}
+
+/// What we know about the printer's certificate.
+#[derive(Debug, Default)]
+struct PinState {
+    /// SHA-256 fingerprint the certificate must have, once we know it.
+    fingerprint: Option<[u8; 32]>,
+
+    /// The certificate the printer last presented, once it's been checked.
+    certificate: Option<Vec<u8>>,
+}
+
+/// Called with a certificate's fingerprint when it's trusted on first use,
+/// so it can be remembered for next time.
+pub(crate) type OnFirstUse = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Accepts the printer's certificate only if it matches a known
+/// fingerprint. Bambu printers present self-signed certificates that don't
+/// name their IP address, so pinning is the only useful check.
+///
+/// If there's no fingerprint yet and trust on first use is allowed, the
+/// first certificate seen is trusted, and pinned from then on.
+pub(crate) struct PinnedCert {
+    state: Mutex<PinState>,
+    on_first_use: Option<OnFirstUse>,
+    provider: CryptoProvider,
+}
+
+impl std::fmt::Debug for PinnedCert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PinnedCert").field("state", &self.state).finish()
+    }
+}
+
+impl PinnedCert {
+    /// Creates a new `PinnedCert` trusting the certificate with the given
+    /// SHA-256 fingerprint, or, if there isn't one, the first certificate
+    /// seen (calling `on_first_use` with its fingerprint).
+    pub(crate) fn new(fingerprint: Option<[u8; 32]>, on_first_use: Option<OnFirstUse>) -> Self {
+        Self {
+            state: Mutex::new(PinState {
+                fingerprint,
+                certificate: None,
+            }),
+            on_first_use,
+            provider: default_provider(),
+        }
+    }
+
+    /// The fingerprint of the pinned certificate, once it's known.
+    pub(crate) fn fingerprint(&self) -> Option<[u8; 32]> {
+        self.state.lock().ok()?.fingerprint
+    }
+
+    /// The `sha256//` pin of the public key of the printer's (checked)
+    /// certificate, in the form `curl --pinnedpubkey` takes.
+    pub(crate) fn public_key_pin(&self) -> Option<String> {
+        let state = self.state.lock().ok()?;
+        let spki = subject_public_key_info(state.certificate.as_deref()?)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, spki);
+        Some(format!("sha256//{}", BASE64_STANDARD.encode(digest.as_ref())))
+    }
+
+    /// Check `certificate` against the pin, pinning it if this is its
+    /// first use.
+    fn check(&self, certificate: &[u8]) -> bool {
+        let digest = ring::digest::digest(&ring::digest::SHA256, certificate);
+        let Ok(digest) = <[u8; 32]>::try_from(digest.as_ref()) else {
+            return false;
+        };
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+
+        match state.fingerprint {
+            Some(fingerprint) if fingerprint != digest => {
+                tracing::error!(
+                    expected = format_fingerprint(&fingerprint),
+                    presented = format_fingerprint(&digest),
+                    "printer presented an unexpected certificate"
+                );
+                return false;
+            }
+            Some(_) => {}
+            None => {
+                let Some(on_first_use) = &self.on_first_use else {
+                    return false;
+                };
+                tracing::info!(
+                    fingerprint = format_fingerprint(&digest),
+                    "trusting printer certificate on first use"
+                );
+                state.fingerprint = Some(digest);
+                on_first_use(format_fingerprint(&digest));
+            }
+        }
+
+        state.certificate = Some(certificate.to_vec());
+        true
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if !self.check(end_entity.as_ref()) {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let plain = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let colons = "00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF:00:11:22:33:44:55:66:77:88:99:AA:BB:CC:DD:EE:FF";
+        assert_eq!(parse_fingerprint(plain).unwrap(), parse_fingerprint(colons).unwrap());
+        assert_eq!(parse_fingerprint(plain).unwrap()[15], 0xff);
+
+        assert!(parse_fingerprint("0011").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+
+    /// A self-signed P-256 certificate, like the ones printers present.
+    const CERTIFICATE: &str = "MIIBijCCATGgAwIBAgIUFj2ZUfh0S+4M5BQIjOEuQ18bpmIwCgYIKoZIzj0EAwIwGjEYMBYGA1UEAwwPMDBNMDBBMDAwMDAwMDAwMCAXDTI2MTAxNjAxMTExNVoYDzIxMjYwOTIyMDExMTE1WjAaMRgwFgYDVQQDDA8wME0wMEEwMDAwMDAwMDAwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAROfqhve+cgT4w8fgOQwsxm0g6J2DHQB/54/kovmL0Jvt/iFNpxNlN3QfTnqGspMraQh7pCpDo6ACpMDzmQ1Idio1MwUTAdBgNVHQ4EFgQUYeQXz/FVAh2E6RJHm7h7tA99SucwHwYDVR0jBBgwFoAUYeQXz/FVAh2E6RJHm7h7tA99SucwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNHADBEAiBXM5XMAEh5f5RRuHvNT1zhBeMThc7EAHGJ23qzN4kRcgIgM5mQsaiNBxh2z8wVbSJjSWHm7Nr1tOkoZtm/zfrg72o=";
+    const FINGERPRINT: &str =
+        "CC:2B:E0:F0:86:C2:6D:8E:75:5E:3F:2C:81:A0:FA:8D:2D:D0:7F:FC:4D:2C:4E:91:EC:8A:B6:27:65:58:66:B3";
+
+    #[test]
+    fn test_pinned() {
+        let certificate = BASE64_STANDARD.decode(CERTIFICATE).unwrap();
+
+        let pinned = PinnedCert::new(Some(parse_fingerprint(FINGERPRINT).unwrap()), None);
+        assert_eq!(pinned.public_key_pin(), None);
+        assert!(pinned.check(&certificate));
+        assert_eq!(
+            pinned.public_key_pin().as_deref(),
+            Some("sha256//O40tAlyjNiImdiwsuRNhKxSb1lTL5EujRQ+WKaz3V4w=")
+        );
+
+        let pinned = PinnedCert::new(Some([0; 32]), None);
+        assert!(!pinned.check(&certificate));
+
+        // Without a fingerprint, only trust on first use lets it through.
+        assert!(!PinnedCert::new(None, None).check(&certificate));
+        let trusted = Arc::new(Mutex::new(None));
+        let on_first_use: OnFirstUse = {
+            let trusted = trusted.clone();
+            Arc::new(move |fingerprint| *trusted.lock().unwrap() = Some(fingerprint))
+        };
+        let pinned = PinnedCert::new(None, Some(on_first_use));
+        assert!(pinned.check(&certificate));
+        assert_eq!(trusted.lock().unwrap().as_deref(), Some(FINGERPRINT));
+        assert_eq!(
+            pinned.fingerprint().map(|fp| format_fingerprint(&fp)).as_deref(),
+            Some(FINGERPRINT)
+        );
+        assert!(!pinned.check(b"someone else"));
+    }
+}
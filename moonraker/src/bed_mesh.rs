@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, Result};
+
+/// The bed mesh Klipper is compensating for, from its `bed_mesh` object.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BedMesh {
+    /// Name of the loaded mesh profile, or empty if no mesh is loaded.
+    pub profile_name: String,
+
+    /// Corner of the mesh with the smallest X and Y, as `[x, y]`, in
+    /// millimeters.
+    pub mesh_min: [f64; 2],
+
+    /// Corner of the mesh with the largest X and Y, as `[x, y]`, in
+    /// millimeters.
+    pub mesh_max: [f64; 2],
+
+    /// Heights probed, in millimeters, as one row for each Y, from the
+    /// smallest, each with one height for each X, from the smallest.
+    pub probed_matrix: Vec<Vec<f64>>,
+
+    /// The probed heights, interpolated to the mesh Klipper compensates
+    /// with, in the same order as `probed_matrix`.
+    pub mesh_matrix: Vec<Vec<f64>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct BedMeshStatus {
+    bed_mesh: Option<BedMesh>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct BedMeshQueryResponse {
+    status: BedMeshStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct BedMeshQueryResponseWrapper {
+    result: BedMeshQueryResponse,
+}
+
+impl Client {
+    /// Return Klipper's bed mesh, if it's configured with `[bed_mesh]`. The
+    /// mesh's matrices are empty if no mesh is loaded.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn bed_mesh(&self) -> Result<Option<BedMesh>> {
+        tracing::debug!(base = self.url_base, "requesting bed mesh");
+        let client = &self.http;
+
+        let resp = client
+            .get(format!(
+                "{}/printer/objects/query?bed_mesh=profile_name,mesh_min,mesh_max,probed_matrix,mesh_matrix",
+                self.url_base
+            ))
+            .send()
+            .await?;
+        let resp: BedMeshQueryResponseWrapper = check_response(resp).await?.json().await?;
+
+        Ok(resp.result.status.bed_mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bed_mesh() {
+        let resp: BedMeshQueryResponseWrapper = serde_json::from_value(serde_json::json!({
+            "result": {
+                "eventtime": 1234.5,
+                "status": {
+                    "bed_mesh": {
+                        "profile_name": "default",
+                        "mesh_min": [10.0, 10.0],
+                        "mesh_max": [220.0, 220.0],
+                        "probed_matrix": [[0.01, -0.02, 0.0], [0.03, 0.02, -0.01]],
+                        "mesh_matrix": [[0.01, -0.005, -0.02], [0.03, 0.025, 0.02]]
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let bed_mesh = resp.result.status.bed_mesh.unwrap();
+        assert_eq!(bed_mesh.profile_name, "default");
+        assert_eq!(bed_mesh.mesh_max, [220.0, 220.0]);
+        assert_eq!(bed_mesh.probed_matrix.len(), 2);
+
+        // Klipper leaves out objects it isn't configured with.
+        let resp: BedMeshQueryResponseWrapper =
+            serde_json::from_value(serde_json::json!({"result": {"eventtime": 1234.5, "status": {}}})).unwrap();
+        assert_eq!(resp.result.status.bed_mesh, None);
+    }
+}
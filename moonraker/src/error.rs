@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+/// Errors returned while talking to a Moonraker instance.
+///
+/// These are split up so that callers can tell the difference between a
+/// Moonraker host that can't be reached at all, one that is up but refused
+/// the request, and one where Klipper itself is unhappy.
+#[derive(Debug, thiserror::Error)]
+pub enum MoonrakerError {
+    /// The request never made it to Moonraker, or the response could not
+    /// be read (connection refused, timeout, malformed body, ...).
+    #[error("moonraker transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// Moonraker answered, but with a non-success HTTP status code.
+    #[error("moonraker returned HTTP {status}: {message}")]
+    Http {
+        /// HTTP status code returned by Moonraker.
+        status: u16,
+
+        /// Error message returned by Moonraker, if one could be parsed
+        /// out of the response body.
+        message: String,
+    },
+
+    /// Klipper is in the `shutdown` state, and needs a firmware restart
+    /// before it will accept commands again.
+    #[error("klipper is shutdown: {0}")]
+    KlipperShutdown(String),
+
+    /// Klipper is in the `error` state.
+    #[error("klipper error: {0}")]
+    Klipper(String),
+
+    /// Local i/o failure, such as reading a file to upload.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// A path handed to the client was not valid.
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+}
+
+/// Result type returned by the Moonraker [crate::Client].
+pub type Result<T, E = MoonrakerError> = std::result::Result<T, E>;
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct ErrorResponseItem {
+    code: u16,
+    message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct ErrorResponseWrapper {
+    error: ErrorResponseItem,
+}
+
+/// Turn a non-success response into a [MoonrakerError::Http], pulling
+/// the error message out of the Moonraker error envelope if possible.
+pub(crate) async fn check_response(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    let body = resp.text().await?;
+    let message = match serde_json::from_str::<ErrorResponseWrapper>(&body) {
+        Ok(wrapper) => wrapper.error.message,
+        Err(_) => body,
+    };
+
+    tracing::warn!(status = status.as_u16(), message = message, "moonraker request failed");
+
+    Err(MoonrakerError::Http {
+        status: status.as_u16(),
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_error_response() {
+        let body = r#"{"error": {"code": 400, "message": "File does not exist", "traceback": ""}}"#;
+        let wrapper: ErrorResponseWrapper = serde_json::from_str(body).unwrap();
+        assert_eq!(wrapper.error.code, 400);
+        assert_eq!(wrapper.error.message, "File does not exist");
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, MoonrakerError, Result};
+
+/// Thumbnail image embedded in an uploaded gcode file.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Thumbnail {
+    /// Width of the thumbnail, in pixels.
+    pub width: u32,
+
+    /// Height of the thumbnail, in pixels.
+    pub height: u32,
+
+    /// Size of the thumbnail, in bytes.
+    pub size: u64,
+
+    /// Path of the thumbnail, relative to the gcode file.
+    pub relative_path: String,
+}
+
+/// Metadata Moonraker extracted from an uploaded gcode file. Most of this
+/// comes from the slicer comments, so almost everything is optional.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FileMetadata {
+    /// Path of the file relative to the `gcodes` root.
+    pub filename: String,
+
+    /// Size of the file, in bytes.
+    pub size: u64,
+
+    /// Last modified time, as a unix timestamp.
+    pub modified: f64,
+
+    /// Unix timestamp of the last time this file was printed.
+    #[serde(default)]
+    pub print_start_time: Option<f64>,
+
+    /// History ID of the last job that printed this file.
+    #[serde(default)]
+    pub job_id: Option<String>,
+
+    /// Name of the slicer that produced this file.
+    #[serde(default)]
+    pub slicer: Option<String>,
+
+    /// Version of the slicer that produced this file.
+    #[serde(default)]
+    pub slicer_version: Option<String>,
+
+    /// Layer height, in mm.
+    #[serde(default)]
+    pub layer_height: Option<f64>,
+
+    /// First layer height, in mm.
+    #[serde(default)]
+    pub first_layer_height: Option<f64>,
+
+    /// Height of the tallest object, in mm.
+    #[serde(default)]
+    pub object_height: Option<f64>,
+
+    /// Total filament used, in mm.
+    #[serde(default)]
+    pub filament_total: Option<f64>,
+
+    /// Slicer estimated print time, in seconds.
+    #[serde(default)]
+    pub estimated_time: Option<f64>,
+
+    /// Thumbnails embedded in the file.
+    #[serde(default)]
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct FileMetadataWrapper {
+    result: FileMetadata,
+}
+
+impl Client {
+    /// Get the metadata Moonraker has extracted from an uploaded gcode file.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn metadata(&self, file_name: &Path) -> Result<FileMetadata> {
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
+        tracing::debug!(base = self.url_base, file_name = file_name, "requesting file metadata");
+        let client = &self.http;
+        let resp = client
+            .get(format!("{}/server/files/metadata", self.url_base))
+            .query(&[("filename", file_name)])
+            .send()
+            .await?;
+        let resp: FileMetadataWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+}
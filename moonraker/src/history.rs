@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, Result};
+
+/// A single print job as recorded by Moonraker's history component.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HistoryJob {
+    /// Moonraker's unique ID for this job.
+    pub job_id: String,
+
+    /// Path of the printed file, relative to the `gcodes` root.
+    pub filename: String,
+
+    /// Final (or current) status of the job, such as `completed`,
+    /// `cancelled`, `error` or `in_progress`.
+    pub status: String,
+
+    /// `true` if the printed file still exists on disk.
+    pub exists: bool,
+
+    /// Unix timestamp of when the job started.
+    pub start_time: f64,
+
+    /// Unix timestamp of when the job ended, if it has.
+    pub end_time: Option<f64>,
+
+    /// Time spent actually printing, in seconds.
+    pub print_duration: f64,
+
+    /// Total time the job took, including pauses, in seconds.
+    pub total_duration: f64,
+
+    /// Filament used, in mm.
+    pub filament_used: f64,
+}
+
+/// Page of print history returned by Moonraker.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct HistoryList {
+    /// Total number of jobs in the history, not just in this page.
+    pub count: u64,
+
+    /// Jobs in this page, newest first.
+    pub jobs: Vec<HistoryJob>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct HistoryListWrapper {
+    result: HistoryList,
+}
+
+impl Client {
+    /// List past print jobs, newest first. `limit` caps the number of
+    /// jobs returned, and `start` is the offset of the first job.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn history(&self, limit: u64, start: u64) -> Result<HistoryList> {
+        tracing::debug!(base = self.url_base, limit = limit, start = start, "requesting history");
+        let client = &self.http;
+        let resp = client
+            .get(format!("{}/server/history/list", self.url_base))
+            .query(&[("limit", limit), ("start", start)])
+            .send()
+            .await?;
+        let resp: HistoryListWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, MoonrakerError, Result};
+
+/// A job waiting in Moonraker's job queue.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct QueuedJob {
+    /// Path of the queued file, relative to the `gcodes` root.
+    pub filename: String,
+
+    /// Moonraker's unique ID for this queued job.
+    pub job_id: String,
+
+    /// Unix timestamp of when the job was queued.
+    pub time_added: f64,
+
+    /// Time the job has spent in the queue, in seconds.
+    pub time_in_queue: f64,
+}
+
+/// Current state of Moonraker's job queue.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct JobQueueStatus {
+    /// Jobs waiting to be printed, in the order they will be printed.
+    pub queued_jobs: Vec<QueuedJob>,
+
+    /// State of the queue, such as `ready`, `paused`, `loading` or
+    /// `starting`.
+    pub queue_state: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct JobQueueStatusWrapper {
+    result: JobQueueStatus,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct EnqueueRequest<'a> {
+    filenames: Vec<&'a str>,
+    reset: bool,
+}
+
+impl Client {
+    /// Get the current state of Moonraker's job queue.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn job_queue(&self) -> Result<JobQueueStatus> {
+        tracing::debug!(base = self.url_base, "requesting job queue status");
+        let client = &self.http;
+        let resp = client
+            .get(format!("{}/server/job_queue/status", self.url_base))
+            .send()
+            .await?;
+        let resp: JobQueueStatusWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+
+    /// Add already uploaded files to the end of Moonraker's job queue.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
+    pub async fn enqueue(&self, file_names: &[&Path]) -> Result<JobQueueStatus> {
+        let filenames = file_names
+            .iter()
+            .map(|file_name| {
+                file_name
+                    .to_str()
+                    .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tracing::debug!(base = self.url_base, "requesting enqueue");
+        let client = &self.http;
+        let resp = client
+            .post(format!("{}/server/job_queue/job", self.url_base))
+            .json(&EnqueueRequest {
+                filenames,
+                reset: false,
+            })
+            .send()
+            .await?;
+        let resp: JobQueueStatusWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+
+    /// Remove jobs from Moonraker's job queue by their queue job ID.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
+    pub async fn dequeue(&self, job_ids: &[&str]) -> Result<JobQueueStatus> {
+        tracing::debug!(base = self.url_base, "requesting dequeue");
+        let client = &self.http;
+        let resp = client
+            .delete(format!("{}/server/job_queue/job", self.url_base))
+            .query(&[("job_ids", job_ids.join(","))])
+            .send()
+            .await?;
+        let resp: JobQueueStatusWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+
+    /// Pause Moonraker's job queue. The current print is not affected,
+    /// but no new jobs will be started.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
+    pub async fn pause_job_queue(&self) -> Result<JobQueueStatus> {
+        tracing::debug!(base = self.url_base, "requesting job queue pause");
+        let client = &self.http;
+        let resp = client
+            .post(format!("{}/server/job_queue/pause", self.url_base))
+            .send()
+            .await?;
+        let resp: JobQueueStatusWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+
+    /// Start (or resume) Moonraker's job queue.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
+    pub async fn start_job_queue(&self) -> Result<JobQueueStatus> {
+        tracing::debug!(base = self.url_base, "requesting job queue start");
+        let client = &self.http;
+        let resp = client
+            .post(format!("{}/server/job_queue/start", self.url_base))
+            .send()
+            .await?;
+        let resp: JobQueueStatusWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+}
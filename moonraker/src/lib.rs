@@ -10,30 +10,92 @@
 //! This crate implements support for interfacing with the moonraker 3d printer
 //! api, proxying calls to klipper.
 
+mod bed_mesh;
+mod error;
+mod files;
+mod history;
+mod job_queue;
 mod metrics;
+mod power;
 mod print;
+mod server;
 mod status;
+mod transport;
 mod upload;
+mod webcams;
 
-use anyhow::Result;
-pub use metrics::{ControlledTemperatureReadings, TemperatureReadings};
+pub use bed_mesh::BedMesh;
+pub use error::{MoonrakerError, Result};
+pub use files::{FileMetadata, Thumbnail};
+pub use history::{HistoryJob, HistoryList};
+pub use job_queue::{JobQueueStatus, QueuedJob};
+pub use metrics::{ControlledTemperatureReadings, SensorTemperatureReadings, TemperatureReadings};
+pub use power::PowerDevice;
 pub use print::InfoResponse;
+pub use server::ServerInfo;
+pub use status::FilamentSensor;
+use transport::Endpoint;
 pub use upload::{DeleteResponse, DeleteResponseItem, UploadResponse, UploadResponseItem};
+pub use webcams::Webcam;
 
 /// Client is a moonraker instance which can accept gcode for printing.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Client {
     pub(crate) url_base: String,
+    endpoint: Endpoint,
+    pub(crate) http: reqwest::Client,
+
+    /// The id the machine goes by, recorded on the spans of calls to it.
+    pub(crate) machine_id: Option<String>,
 }
 
 impl Client {
     /// Create a new Client handle to control the printer via the
-    /// moonraker interface.
-    pub fn new(url_base: &str) -> Result<Self> {
-        tracing::debug!(base = url_base, "new");
+    /// moonraker interface. The endpoint is either an HTTP(S) URL, or
+    /// `unix://` followed by the path to a unix socket serving Moonraker's
+    /// HTTP API.
+    pub fn new(endpoint: &str) -> Result<Self> {
+        tracing::debug!(base = endpoint, "new");
+
+        let endpoint = Endpoint::parse(endpoint)?;
+        Ok(Self {
+            url_base: endpoint.url_base().to_owned(),
+            http: endpoint.http_client()?,
+            endpoint,
+            machine_id: None,
+        })
+    }
+
+    /// Create a new Client as [Client::new] does, but sending requests over
+    /// HTTP(S) with `http`, such as a client shared with everything else
+    /// making requests, configured to go through a proxy. Requests to a
+    /// unix socket are sent with a client of their own.
+    pub fn with_http_client(endpoint: &str, http: reqwest::Client) -> Result<Self> {
+        tracing::debug!(base = endpoint, "new");
 
+        let endpoint = Endpoint::parse(endpoint)?;
+        let http = match endpoint {
+            Endpoint::Http(_) => http,
+            Endpoint::Unix(_) => endpoint.http_client()?,
+        };
         Ok(Self {
-            url_base: url_base.to_owned(),
+            url_base: endpoint.url_base().to_owned(),
+            http,
+            endpoint,
+            machine_id: None,
         })
     }
+
+    /// Record `machine_id` (the id the machine goes by) on the spans of
+    /// calls made with this Client, so they can be found in a trace.
+    pub fn with_machine_id(mut self, machine_id: &str) -> Self {
+        self.machine_id = Some(machine_id.to_owned());
+        self
+    }
+}
+
+impl PartialEq for Client {
+    fn eq(&self, other: &Self) -> bool {
+        self.endpoint == other.endpoint
+    }
 }
@@ -1,7 +1,9 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use super::Client;
+use crate::{error::check_response, Result};
 
 /// Temperature readings from a heated element controlled by klipper.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -16,6 +18,19 @@ pub struct ControlledTemperatureReadings {
     pub powers: Vec<f64>,
 }
 
+/// Temperature readings from any other sensor klipper reports, such as a
+/// `temperature_sensor`, `temperature_fan` or `heater_generic`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SensorTemperatureReadings {
+    /// Observed temperatures, from oldest (0th) to latest (last)
+    pub temperatures: Vec<f64>,
+
+    /// Target temperatures, from oldest (0th) to latest (last), if the
+    /// sensor controls a heater or fan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub targets: Option<Vec<f64>>,
+}
+
 /// TemperatureReadings as reported by klipper.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct TemperatureReadings {
@@ -24,6 +39,11 @@ pub struct TemperatureReadings {
 
     /// Information about a heated bed, if present
     pub heater_bed: Option<ControlledTemperatureReadings>,
+
+    /// Every other sensor, by its klipper config section (such as
+    /// `temperature_sensor mcu_temp`, or `extruder1`).
+    #[serde(flatten)]
+    pub sensors: BTreeMap<String, SensorTemperatureReadings>,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -32,18 +52,53 @@ struct TemperatureReadingsWrapper {
 }
 
 impl Client {
-    /// Print an uploaded file.
+    /// Return the recent readings of every temperature sensor klipper
+    /// reports.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
     pub async fn temperatures(&self) -> Result<TemperatureReadings> {
         tracing::debug!(base = self.url_base, "requesting temperatures");
-        let client = reqwest::Client::new();
+        let client = &self.http;
 
-        let resp: TemperatureReadingsWrapper = client
+        let resp = client
             .get(format!("{}/server/temperature_store", self.url_base))
             .send()
-            .await?
-            .json()
             .await?;
+        let resp: TemperatureReadingsWrapper = check_response(resp).await?.json().await?;
 
         Ok(resp.result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sensors() {
+        let readings: TemperatureReadings = serde_json::from_value(serde_json::json!({
+            "extruder": {"temperatures": [210.0, 215.0], "targets": [215.0, 215.0], "powers": [0.5, 0.4]},
+            "heater_bed": {"temperatures": [60.0], "targets": [60.0], "powers": [0.2]},
+            "temperature_sensor mcu_temp": {"temperatures": [40.1, 40.3]},
+            "heater_generic chamber": {"temperatures": [35.0], "targets": [45.0], "powers": [1.0]}
+        }))
+        .unwrap();
+
+        assert_eq!(readings.extruder.temperatures.last(), Some(&215.0));
+        assert_eq!(
+            readings.sensors.keys().collect::<Vec<_>>(),
+            vec!["heater_generic chamber", "temperature_sensor mcu_temp"]
+        );
+        assert_eq!(
+            readings.sensors["temperature_sensor mcu_temp"],
+            SensorTemperatureReadings {
+                temperatures: vec![40.1, 40.3],
+                targets: None,
+            }
+        );
+        assert_eq!(readings.sensors["heater_generic chamber"].targets, Some(vec![45.0]));
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, Result};
+
+/// A power device (such as a relay, smart plug or GPIO) configured in
+/// Moonraker's `[power]` sections.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PowerDevice {
+    /// Name of the device.
+    pub device: String,
+
+    /// State of the device, such as `on`, `off`, `init` or `error`.
+    pub status: String,
+
+    /// Whether the device refuses to change state while printing.
+    #[serde(default)]
+    pub locked_while_printing: bool,
+
+    /// Type of the device, such as `gpio`, `klipper_device` or `tplink_smartplug`.
+    #[serde(rename = "type")]
+    pub device_type: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct PowerDevices {
+    devices: Vec<PowerDevice>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct PowerDevicesWrapper {
+    result: PowerDevices,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct PowerStateWrapper {
+    result: HashMap<String, String>,
+}
+
+impl Client {
+    /// List the power devices Moonraker is configured with.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn power_devices(&self) -> Result<Vec<PowerDevice>> {
+        tracing::debug!(base = self.url_base, "requesting power devices");
+        let client = &self.http;
+        let resp = client
+            .get(format!("{}/machine/device_power/devices", self.url_base))
+            .send()
+            .await?;
+        let resp: PowerDevicesWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result.devices)
+    }
+
+    /// Turn a power device on or off, returning its new state.
+    #[tracing::instrument(
+        skip_all,
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base, device = device),
+    )]
+    pub async fn set_power_device(&self, device: &str, on: bool) -> Result<String> {
+        tracing::debug!(base = self.url_base, "requesting power device change");
+        let client = &self.http;
+        let resp = client
+            .post(format!("{}/machine/device_power/device", self.url_base))
+            .query(&[("device", device), ("action", if on { "on" } else { "off" })])
+            .send()
+            .await?;
+        let resp: PowerStateWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result.get(device).cloned().unwrap_or_default())
+    }
+}
@@ -1,9 +1,9 @@
 use std::path::Path;
 
-use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use super::Client;
+use crate::{error::check_response, MoonrakerError, Result};
 
 /// Information about the underlying Klipper runtime and host computer.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -30,18 +30,34 @@ struct InfoResponseWrapper {
     pub result: InfoResponse,
 }
 
+impl InfoResponse {
+    /// Return a [MoonrakerError] if Klipper reports that it is shut down
+    /// or in an error state, using the `state_message` as the reason.
+    pub fn klipper_error(&self) -> Option<MoonrakerError> {
+        match self.state.as_str() {
+            "shutdown" => Some(MoonrakerError::KlipperShutdown(self.state_message.clone())),
+            "error" => Some(MoonrakerError::Klipper(self.state_message.clone())),
+            _ => None,
+        }
+    }
+}
+
 impl Client {
     /// Print an uploaded file.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn print(&self, file_name: &Path) -> Result<()> {
         tracing::debug!(base = self.url_base, "requesting print");
 
-        let file_name = file_name.to_str().unwrap();
-        let client = reqwest::Client::new();
-        client
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
+        let client = &self.http;
+        let resp = client
             .post(format!("{}/printer/print/start", self.url_base))
             .form(&[("filename", file_name)])
             .send()
             .await?;
+        check_response(resp).await?;
         Ok(())
     }
 
@@ -49,67 +65,93 @@ impl Client {
     /// "shutdown" state. It should be used to implement an "emergency stop"
     /// button and also used if a user enters M112(emergency stop) via a
     /// console.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn emergency_stop(&self) -> Result<()> {
         tracing::warn!(base = self.url_base, "requesting emergency stop");
-        let client = reqwest::Client::new();
-        client
+        let client = &self.http;
+        let resp = client
             .post(format!("{}/printer/emergency_stop", self.url_base))
             .send()
             .await?;
+        check_response(resp).await?;
         Ok(())
     }
 
     /// Get information regarding the processor and its state.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
     pub async fn info(&self) -> Result<InfoResponse> {
         tracing::debug!(base = self.url_base, "requesting info");
-        let client = reqwest::Client::new();
-        let resp: InfoResponseWrapper = client
-            .post(format!("{}/printer/info", self.url_base))
+        let client = &self.http;
+        let resp = client.post(format!("{}/printer/info", self.url_base)).send().await?;
+        let resp: InfoResponseWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+
+    /// Run a gcode script, such as a macro, on the printer. This returns
+    /// once Klipper has finished running it.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
+    pub async fn run_gcode(&self, script: &str) -> Result<()> {
+        tracing::debug!(base = self.url_base, script = script, "requesting gcode script");
+        let client = &self.http;
+        let resp = client
+            .post(format!("{}/printer/gcode/script", self.url_base))
+            .form(&[("script", script)])
             .send()
-            .await?
-            .json()
             .await?;
-        Ok(resp.result)
+        check_response(resp).await?;
+        Ok(())
     }
 
     /// Restart the printer (shut down and reboot).
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn restart(&self) -> Result<()> {
         tracing::debug!(base = self.url_base, "requesting restart");
-        let client = reqwest::Client::new();
-        client.post(format!("{}/printer/restart", self.url_base)).send().await?;
+        let client = &self.http;
+        let resp = client.post(format!("{}/printer/restart", self.url_base)).send().await?;
+        check_response(resp).await?;
         Ok(())
     }
 
     /// Cancel a print job.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn cancel_print(&self) -> Result<()> {
         tracing::debug!(base = self.url_base, "requesting cancel");
-        let client = reqwest::Client::new();
-        client
+        let client = &self.http;
+        let resp = client
             .post(format!("{}/printer/print/cancel", self.url_base))
             .send()
             .await?;
+        check_response(resp).await?;
         Ok(())
     }
 
     /// Pause a print job.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn pause_print(&self) -> Result<()> {
         tracing::debug!(base = self.url_base, "requesting pause");
-        let client = reqwest::Client::new();
-        client
+        let client = &self.http;
+        let resp = client
             .post(format!("{}/printer/print/pause", self.url_base))
             .send()
             .await?;
+        check_response(resp).await?;
         Ok(())
     }
 
     /// Resume a print job.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn resume_print(&self) -> Result<()> {
         tracing::debug!(base = self.url_base, "requesting resume");
-        let client = reqwest::Client::new();
-        client
+        let client = &self.http;
+        let resp = client
             .post(format!("{}/printer/print/resume", self.url_base))
             .send()
             .await?;
+        check_response(resp).await?;
         Ok(())
     }
 }
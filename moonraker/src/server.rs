@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, Result};
+
+/// Information about Moonraker itself, and its connection to Klipper.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ServerInfo {
+    /// Whether Moonraker is connected to Klipper.
+    pub klippy_connected: bool,
+
+    /// State of Klipper, such as `ready` or `startup`.
+    pub klippy_state: String,
+
+    /// Version of Moonraker.
+    pub moonraker_version: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct ServerInfoWrapper {
+    result: ServerInfo,
+}
+
+impl Client {
+    /// Get information about Moonraker, such as whether it's connected to
+    /// Klipper. This answers even when Klipper isn't running, so it's
+    /// useful to tell whether something is a Moonraker instance at all.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn server_info(&self) -> Result<ServerInfo> {
+        tracing::debug!(base = self.url_base, "requesting server info");
+        let client = &self.http;
+        let resp = client.get(format!("{}/server/info", self.url_base)).send().await?;
+        let resp: ServerInfoWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_server_info() {
+        let resp: ServerInfoWrapper = serde_json::from_str(
+            r#"{"result": {"klippy_connected": true, "klippy_state": "ready", "components": ["database"],
+                "failed_components": [], "registered_directories": ["config", "gcodes"], "warnings": [],
+                "websocket_count": 2, "moonraker_version": "v0.9.3-1-g6a3a6e5", "api_version": [1, 5, 0],
+                "api_version_string": "1.5.0"}}"#,
+        )
+        .unwrap();
+        assert!(resp.result.klippy_connected);
+        assert_eq!(resp.result.klippy_state, "ready");
+        assert_eq!(resp.result.moonraker_version, "v0.9.3-1-g6a3a6e5");
+    }
+}
@@ -1,7 +1,9 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use super::Client;
+use crate::{error::check_response, Result};
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct VirtualSdcard {
@@ -26,6 +28,21 @@ pub struct PrintStats {
     pub filename: String,
     pub state: String,
     pub message: String,
+    #[serde(default)]
+    pub info: PrintStatsInfo,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct PrintStatsInfo {
+    pub total_layer: Option<u32>,
+    pub current_layer: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct GcodeMove {
+    /// Position of the toolhead, as `[x, y, z, e]`, in the coordinates
+    /// used by the gcode.
+    pub gcode_position: [f64; 4],
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -33,6 +50,8 @@ pub struct Status {
     pub virtual_sdcard: VirtualSdcard,
     pub webhooks: Webhooks,
     pub print_stats: PrintStats,
+    #[serde(default)]
+    pub gcode_move: Option<GcodeMove>,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -46,22 +65,172 @@ struct QueryResponseWrapper {
     result: QueryResponse,
 }
 
+/// A filament sensor (a `filament_switch_sensor` or
+/// `filament_motion_sensor`) configured in Klipper.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FilamentSensor {
+    /// Name of the sensor's config section, such as
+    /// `filament_switch_sensor runout`.
+    #[serde(default)]
+    pub name: String,
+
+    /// Whether the sensor is enabled.
+    pub enabled: bool,
+
+    /// Whether the sensor can see filament.
+    pub filament_detected: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct ObjectList {
+    objects: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct ObjectListWrapper {
+    result: ObjectList,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct SensorQueryResponse {
+    status: BTreeMap<String, FilamentSensor>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct SensorQueryResponseWrapper {
+    result: SensorQueryResponse,
+}
+
+/// Return whether the Klipper object `name` is a filament sensor.
+fn is_filament_sensor(name: &str) -> bool {
+    name.starts_with("filament_switch_sensor ") || name.starts_with("filament_motion_sensor ")
+}
+
 impl Client {
     /// Print an uploaded file.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
     pub async fn status(&self) -> Result<Status> {
         tracing::debug!(base = self.url_base, "requesting status");
-        let client = reqwest::Client::new();
+        let client = &self.http;
 
-        let resp: QueryResponseWrapper = client
+        let resp = client
             .get(format!(
-                "{}/printer/objects/query?webhooks&virtual_sdcard&print_stats",
+                "{}/printer/objects/query?webhooks&virtual_sdcard&print_stats&gcode_move=gcode_position",
                 self.url_base
             ))
             .send()
-            .await?
-            .json()
             .await?;
+        let resp: QueryResponseWrapper = check_response(resp).await?.json().await?;
 
         Ok(resp.result.status)
     }
+
+    /// Return the state of each filament sensor Klipper is configured
+    /// with.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn filament_sensors(&self) -> Result<Vec<FilamentSensor>> {
+        tracing::debug!(base = self.url_base, "requesting filament sensors");
+        let client = &self.http;
+
+        let resp = client
+            .get(format!("{}/printer/objects/list", self.url_base))
+            .send()
+            .await?;
+        let resp: ObjectListWrapper = check_response(resp).await?.json().await?;
+        let names: Vec<String> = resp
+            .result
+            .objects
+            .into_iter()
+            .filter(|name| is_filament_sensor(name))
+            .collect();
+        if names.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let query = names
+            .iter()
+            .map(|name| name.replace(' ', "%20"))
+            .collect::<Vec<_>>()
+            .join("&");
+        let resp = client
+            .get(format!("{}/printer/objects/query?{}", self.url_base, query))
+            .send()
+            .await?;
+        let resp: SensorQueryResponseWrapper = check_response(resp).await?.json().await?;
+
+        Ok(resp
+            .result
+            .status
+            .into_iter()
+            .map(|(name, sensor)| FilamentSensor { name, ..sensor })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_layers() {
+        let status: Status = serde_json::from_value(serde_json::json!({
+            "virtual_sdcard": {
+                "progress": 0.25,
+                "file_position": 1024.0,
+                "is_active": true,
+                "file_path": "/home/pi/printer_data/gcodes/part.gcode",
+                "file_size": 4096.0
+            },
+            "webhooks": {"state": "ready", "state_message": "Printer is ready"},
+            "print_stats": {
+                "print_duration": 60.0,
+                "total_duration": 65.0,
+                "filament_used": 100.0,
+                "filename": "part.gcode",
+                "state": "printing",
+                "message": "",
+                "info": {"total_layer": 214, "current_layer": 57}
+            },
+            "gcode_move": {"gcode_position": [10.0, 20.0, 11.4, 300.0]}
+        }))
+        .unwrap();
+
+        assert_eq!(status.print_stats.info.current_layer, Some(57));
+        assert_eq!(status.print_stats.info.total_layer, Some(214));
+        assert_eq!(status.gcode_move.unwrap().gcode_position[2], 11.4);
+    }
+
+    #[test]
+    fn test_parse_filament_sensors() {
+        assert!(is_filament_sensor("filament_switch_sensor runout"));
+        assert!(is_filament_sensor("filament_motion_sensor encoder"));
+        assert!(!is_filament_sensor("filament_switch_sensor"));
+        assert!(!is_filament_sensor("print_stats"));
+
+        let resp: SensorQueryResponseWrapper = serde_json::from_value(serde_json::json!({
+            "result": {
+                "eventtime": 1234.5,
+                "status": {
+                    "filament_switch_sensor runout": {"filament_detected": false, "enabled": true}
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            resp.result.status["filament_switch_sensor runout"],
+            FilamentSensor {
+                name: String::new(),
+                enabled: true,
+                filament_detected: false,
+            }
+        );
+    }
 }
@@ -0,0 +1,76 @@
+//! How requests get to Moonraker: over HTTP(S), or over a unix socket, for
+//! hosts running several Moonraker instances behind sockets rather than
+//! ports.
+
+use crate::{MoonrakerError, Result};
+
+/// Scheme of endpoints which are a path to a unix socket.
+const UNIX_SCHEME: &str = "unix://";
+
+/// Base URL for requests sent over a unix socket. The host is ignored by
+/// the socket, but needed to build the request.
+const UNIX_URL_BASE: &str = "http://localhost";
+
+/// Where a Moonraker instance is listening.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    /// An HTTP(S) URL, such as `http://printer.local:7125`.
+    Http(String),
+
+    /// A unix socket, serving the HTTP API, such as
+    /// `unix:///run/moonraker/printer-2.sock`.
+    Unix(String),
+}
+
+impl Endpoint {
+    /// Parse an endpoint, which is either an HTTP(S) URL, or `unix://`
+    /// followed by the path to a socket.
+    pub(crate) fn parse(endpoint: &str) -> Result<Self> {
+        if let Some(path) = endpoint.strip_prefix(UNIX_SCHEME) {
+            if path.is_empty() {
+                return Err(MoonrakerError::InvalidPath(endpoint.to_owned()));
+            }
+            return Ok(Self::Unix(path.to_owned()));
+        }
+        Ok(Self::Http(endpoint.trim_end_matches('/').to_owned()))
+    }
+
+    /// The base URL requests are made against.
+    pub(crate) fn url_base(&self) -> &str {
+        match self {
+            Self::Http(url) => url,
+            Self::Unix(_) => UNIX_URL_BASE,
+        }
+    }
+
+    /// Build an HTTP client which sends requests to this endpoint.
+    pub(crate) fn http_client(&self) -> Result<reqwest::Client> {
+        let builder = reqwest::Client::builder();
+        let builder = match self {
+            Self::Http(_) => builder,
+            #[cfg(unix)]
+            Self::Unix(path) => builder.unix_socket(path.as_str()),
+            #[cfg(not(unix))]
+            Self::Unix(path) => return Err(MoonrakerError::InvalidPath(path.clone())),
+        };
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = Endpoint::parse("http://printer.local:7125/").unwrap();
+        assert_eq!(endpoint, Endpoint::Http("http://printer.local:7125".to_owned()));
+        assert_eq!(endpoint.url_base(), "http://printer.local:7125");
+
+        let endpoint = Endpoint::parse("unix:///run/moonraker/printer-2.sock").unwrap();
+        assert_eq!(endpoint, Endpoint::Unix("/run/moonraker/printer-2.sock".to_owned()));
+        assert_eq!(endpoint.url_base(), "http://localhost");
+
+        assert!(Endpoint::parse("unix://").is_err());
+    }
+}
@@ -1,11 +1,11 @@
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
 use bytes::Bytes;
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
 
 use super::Client;
+use crate::{error::check_response, MoonrakerError, Result};
 
 /// File that has been uploaded to Moonraker.
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -52,59 +52,66 @@ struct DeleteResponseWrapper {
 
 impl Client {
     /// Upload a file with some gcode to the server.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn upload_file(&self, file_name: &Path) -> Result<UploadResponse> {
-        tracing::info!(file_path = file_name.to_str().unwrap(), "uploading file");
-        self.upload(
-            &PathBuf::from(file_name.file_name().unwrap().to_str().unwrap()),
-            &std::fs::read(file_name)?,
-        )
-        .await
+        tracing::info!(file_path = file_name.to_str(), "uploading file");
+        let base_name = file_name
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
+        self.upload(&PathBuf::from(base_name), &std::fs::read(file_name)?).await
     }
 
     /// Upload a byte array of gcode to the print queue.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn upload(&self, file_name: &Path, gcode: &[u8]) -> Result<UploadResponse> {
-        let file_name = file_name.to_str().unwrap();
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
         tracing::info!(file_name = file_name, "uploading gcode to the printer");
         let gcode = multipart::Part::bytes(gcode.to_owned())
             .file_name(file_name.to_owned())
             .mime_str("text/x-gcode")?;
 
-        let client = reqwest::Client::new();
+        let client = &self.http;
 
         // TODO: include checksum
 
-        Ok(client
+        let resp = client
             .post(format!("{}/server/files/upload", self.url_base))
             .multipart(multipart::Form::new().text("root", "gcodes").part("file", gcode))
             .send()
-            .await?
-            .json()
-            .await?)
+            .await?;
+        Ok(check_response(resp).await?.json().await?)
     }
 
     /// Get the contents of an uploaded file.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn get(&self, file_name: &Path) -> Result<Bytes> {
-        let file_name = file_name.to_str().unwrap();
-        let client = reqwest::Client::new();
-        Ok(client
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
+        let client = &self.http;
+        let resp = client
             .get(format!("{}/server/files/gcodes/{}", self.url_base, file_name))
             .send()
-            .await?
-            .bytes()
-            .await?)
+            .await?;
+        Ok(check_response(resp).await?.bytes().await?)
     }
 
     /// Delete an uploaded file from the print queue.
+    #[tracing::instrument(skip_all, fields(machine_id = self.machine_id.as_deref(), base = %self.url_base))]
     pub async fn delete(&self, file_name: &Path) -> Result<DeleteResponse> {
-        tracing::info!(file_path = file_name.to_str().unwrap(), "deleting file");
-        let file_name = file_name.to_str().unwrap();
-        let client = reqwest::Client::new();
-        let resp: DeleteResponseWrapper = client
+        let file_name = file_name
+            .to_str()
+            .ok_or_else(|| MoonrakerError::InvalidPath(file_name.display().to_string()))?;
+        tracing::info!(file_path = file_name, "deleting file");
+        let client = &self.http;
+        let resp = client
             .delete(format!("{}/server/files/gcodes/{}", self.url_base, file_name))
             .send()
-            .await?
-            .json()
             .await?;
+        let resp: DeleteResponseWrapper = check_response(resp).await?.json().await?;
         Ok(resp.result)
     }
 }
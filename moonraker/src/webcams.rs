@@ -0,0 +1,120 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::{error::check_response, MoonrakerError, Result};
+
+/// A webcam configured in Moonraker's `[webcam]` sections (or through the
+/// frontend).
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Webcam {
+    /// Name of the webcam.
+    pub name: String,
+
+    /// Whether the webcam is enabled.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// URL of a single still image from the webcam. This is often relative
+    /// to the printer's host, such as `/webcam/?action=snapshot`.
+    #[serde(default)]
+    pub snapshot_url: String,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct Webcams {
+    webcams: Vec<Webcam>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct WebcamsWrapper {
+    result: Webcams,
+}
+
+impl Client {
+    /// List the webcams Moonraker is configured with.
+    #[tracing::instrument(
+        skip_all,
+        level = "debug",
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base),
+    )]
+    pub async fn webcams(&self) -> Result<Vec<Webcam>> {
+        tracing::debug!(base = self.url_base, "requesting webcams");
+        let client = &self.http;
+        let resp = client
+            .get(format!("{}/server/webcams/list", self.url_base))
+            .send()
+            .await?;
+        let resp: WebcamsWrapper = check_response(resp).await?.json().await?;
+        Ok(resp.result.webcams)
+    }
+
+    /// Take a still image from `webcam`, as whatever the webcam serves
+    /// (usually a JPEG).
+    #[tracing::instrument(
+        skip_all,
+        fields(machine_id = self.machine_id.as_deref(), base = %self.url_base, webcam = webcam.name),
+    )]
+    pub async fn snapshot(&self, webcam: &Webcam) -> Result<Bytes> {
+        let url = self.resolve(&webcam.snapshot_url)?;
+        tracing::debug!(base = self.url_base, url = url, "requesting webcam snapshot");
+        let resp = self.http.get(url).send().await?;
+        Ok(check_response(resp).await?.bytes().await?)
+    }
+
+    /// Resolve a URL Moonraker handed out, which may be relative to the
+    /// printer's host.
+    fn resolve(&self, url: &str) -> Result<String> {
+        if url.is_empty() {
+            return Err(MoonrakerError::InvalidPath(url.to_owned()));
+        }
+        let base =
+            reqwest::Url::parse(&self.url_base).map_err(|_| MoonrakerError::InvalidPath(self.url_base.clone()))?;
+        base.join(url)
+            .map(String::from)
+            .map_err(|_| MoonrakerError::InvalidPath(url.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_webcams() {
+        let resp: WebcamsWrapper = serde_json::from_str(
+            r#"{"result": {"webcams": [{"name": "bed", "location": "printer", "service": "mjpegstreamer",
+                "enabled": true, "icon": "mdiWebcam", "target_fps": 15, "target_fps_idle": 5,
+                "stream_url": "/webcam/?action=stream", "snapshot_url": "/webcam/?action=snapshot",
+                "flip_horizontal": false, "flip_vertical": false, "rotation": 0, "aspect_ratio": "4:3",
+                "extra_data": {}, "source": "database", "uid": "55d3eb0c"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resp.result.webcams,
+            vec![Webcam {
+                name: "bed".to_owned(),
+                enabled: true,
+                snapshot_url: "/webcam/?action=snapshot".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let client = Client::new("http://printer.local:7125").unwrap();
+        assert_eq!(
+            client.resolve("/webcam/?action=snapshot").unwrap(),
+            "http://printer.local:7125/webcam/?action=snapshot"
+        );
+        assert_eq!(
+            client.resolve("http://camera.local/snapshot.jpg").unwrap(),
+            "http://camera.local/snapshot.jpg"
+        );
+        assert!(client.resolve("").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! Accessories attached to a machine, such as lights, fans, filters and
+//! multi-material units, described the same way whatever the machine.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// What sort of thing an [Accessory] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessoryKind {
+    /// A light, such as a chamber light or LED strip.
+    Light,
+
+    /// A fan, such as an auxiliary part cooling fan.
+    Fan,
+
+    /// An air filter or purifier.
+    Filter,
+
+    /// A multi-material unit, such as a Bambu AMS.
+    MultiMaterialUnit,
+
+    /// A switched power device, such as a relay or smart plug.
+    Power,
+
+    /// Anything else.
+    Other,
+}
+
+/// An accessory attached to a machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Accessory {
+    /// Name of the accessory, unique to the machine, which it's controlled
+    /// by.
+    pub name: String,
+
+    /// What sort of thing the accessory is.
+    pub kind: AccessoryKind,
+
+    /// Whether the accessory can be turned on and off through the API.
+    pub controllable: bool,
+
+    /// Whether the accessory is on, if that's known (and means anything for
+    /// it).
+    pub on: Option<bool>,
+
+    /// Anything else known about the accessory's state, such as a fan's
+    /// speed or a power device's status.
+    pub state: Option<String>,
+}
+
+/// Error returned when a machine has no accessory by the requested name,
+/// or it can't be controlled.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum AccessoryError {
+    /// There's no accessory by this name.
+    #[error("no accessory named {0:?}")]
+    NotFound(String),
+
+    /// The accessory can't be turned on and off.
+    #[error("accessory {0:?} can't be controlled")]
+    NotControllable(String),
+}
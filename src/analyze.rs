@@ -0,0 +1,708 @@
+//! Printability analysis of design files, before anything is sliced or sent
+//! to a machine: a handful of heuristics over the mesh which catch the most
+//! common reasons a print fails (steep overhangs, parts barely touching the
+//! bed, and details too small for the nozzle).
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::Volume;
+
+/// Faces within this distance (in millimeters) of the bottom of the part
+/// are on the bed.
+const BED_EPSILON: f64 = 0.01;
+
+/// Below this much contact with the bed (in square millimeters), a part is
+/// likely to come loose mid-print.
+const MIN_BED_CONTACT_AREA: f64 = 25.0;
+
+/// Parts smaller than this (in millimeters) at their largest were likely
+/// exported in inches.
+const MIN_PLAUSIBLE_SIZE_MM: f64 = 10.0;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Shells whose bounding boxes are within this distance (in millimeters)
+/// of each other, without overlapping, are placed against each other, and
+/// are taken to be one body.
+const TOUCH_EPSILON: f64 = 0.01;
+
+/// Units the coordinates in an STL file are in. STL files don't say, and
+/// slicers take them to be in millimeters.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StlUnits {
+    /// Millimeters; the file is printed as it is, however small.
+    Mm,
+
+    /// Inches; the file is scaled up to millimeters.
+    Inch,
+
+    /// Millimeters, unless the part is suspiciously small, in which case
+    /// it's taken to be in inches and scaled up.
+    Auto,
+}
+
+/// A part is so small it was likely exported in inches, and its units
+/// weren't given.
+#[derive(Copy, Clone, Debug, PartialEq, thiserror::Error)]
+#[error(
+    "the part is only {largest_mm:.2}mm across at its largest, so it's likely in inches; set units to `inch` to \
+     scale it up, or `mm` to print it as it is"
+)]
+pub struct SuspiciousUnits {
+    /// The part's largest dimension, in millimeters.
+    pub largest_mm: f64,
+}
+
+/// What to do with an STL file made up of separate bodies (such as an
+/// assembly, or several parts exported together), which slicers print
+/// where they are in the file, overlapping if they fit together.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MultipleBodies {
+    /// Print the file as it is, with a warning.
+    #[default]
+    Warn,
+
+    /// Split the bodies into separate objects, for the slicer to arrange
+    /// on the plate apart from each other. Only slicers which print
+    /// projects (Orca) can print them.
+    Split,
+}
+
+/// Parameters for analyzing a design file.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq)]
+pub struct AnalyzeParameters {
+    /// How far past vertical (in degrees) a downward-facing surface can
+    /// lean before it needs support.
+    #[serde(default = "default_overhang_angle")]
+    pub overhang_angle: f64,
+
+    /// Diameter of the nozzle the part would be printed with, in
+    /// millimeters. Features thinner than this can't be printed.
+    #[serde(default = "default_nozzle_diameter")]
+    pub nozzle_diameter: f64,
+}
+
+fn default_overhang_angle() -> f64 {
+    45.0
+}
+
+fn default_nozzle_diameter() -> f64 {
+    0.4
+}
+
+impl Default for AnalyzeParameters {
+    fn default() -> Self {
+        Self {
+            overhang_angle: default_overhang_angle(),
+            nozzle_diameter: default_nozzle_diameter(),
+        }
+    }
+}
+
+/// Something about a part which makes it likely to print badly.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrintabilityRisk {
+    /// Some surfaces overhang past the overhang angle, and need support.
+    Overhangs,
+
+    /// Some parts are thinner than the nozzle, and won't print.
+    TinyFeatures,
+
+    /// The part barely touches the bed (or doesn't at all), and may come
+    /// loose.
+    SmallBedContact,
+}
+
+/// How printable a design file is likely to be.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, PartialEq)]
+pub struct PrintabilityReport {
+    /// Overall score, from 0 (certain to fail) to 100 (no risks found).
+    pub score: f64,
+
+    /// Risks found, if any.
+    pub risks: Vec<PrintabilityRisk>,
+
+    /// Size of the part's bounding box.
+    pub size: Volume,
+
+    /// Number of triangles in the mesh.
+    pub triangles: usize,
+
+    /// Total surface area of the part, in square millimeters.
+    pub surface_area: f64,
+
+    /// Area of surfaces overhanging past the overhang angle, in square
+    /// millimeters.
+    pub overhang_area: f64,
+
+    /// Overhanging area, as a percentage of the surface area.
+    pub overhang_percentage: f64,
+
+    /// Area of the part in contact with the bed, in square millimeters.
+    pub bed_contact_area: f64,
+
+    /// Number of separate bodies in the mesh thinner than the nozzle.
+    pub tiny_features: usize,
+
+    /// Number of separate bodies in the mesh, such as the parts of an
+    /// assembly.
+    pub bodies: usize,
+}
+
+type Triangle = [[f64; 3]; 3];
+
+/// Parse an STL file, either binary or ASCII, into its triangles.
+pub(crate) fn parse_stl(stl: &[u8]) -> Result<Vec<Triangle>> {
+    // Binary STLs can start with "solid" too, so go by whether the length
+    // matches the triangle count in the header.
+    if stl.len() >= 84 {
+        let count = u32::from_le_bytes([stl[80], stl[81], stl[82], stl[83]]) as usize;
+        if stl.len() == 84 + count * 50 {
+            return Ok(stl[84..]
+                .chunks_exact(50)
+                .map(|facet| {
+                    let float = |offset: usize| {
+                        f32::from_le_bytes([facet[offset], facet[offset + 1], facet[offset + 2], facet[offset + 3]])
+                            as f64
+                    };
+                    // The normal (the first 12 bytes) is ignored in favour
+                    // of the winding, since plenty of exporters leave it
+                    // zeroed.
+                    let vertex = |i: usize| [float(12 + i * 12), float(16 + i * 12), float(20 + i * 12)];
+                    [vertex(0), vertex(1), vertex(2)]
+                })
+                .collect());
+        }
+    }
+
+    let text = std::str::from_utf8(stl).map_err(|_| anyhow::anyhow!("not a binary or ASCII STL file"))?;
+    if !text.trim_start().starts_with("solid") {
+        anyhow::bail!("not a binary or ASCII STL file");
+    }
+    let mut vertices = vec![];
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("vertex") {
+            continue;
+        }
+        let mut vertex = [0.0; 3];
+        for coordinate in &mut vertex {
+            *coordinate = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("invalid vertex in STL file: {:?}", line.trim()))?;
+        }
+        vertices.push(vertex);
+    }
+    if vertices.len() % 3 != 0 {
+        anyhow::bail!("STL file has a facet without three vertices");
+    }
+    Ok(vertices.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect())
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Union-find, to split a mesh into its separate shells, and those into
+/// bodies.
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // Flatten the path, so later lookups are quick.
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a] = b;
+        }
+    }
+
+    /// Number the sets in order of their first element, returning the
+    /// number of each element's set, and how many sets there are.
+    fn numbered(&mut self) -> (Vec<usize>, usize) {
+        let mut numbers = HashMap::new();
+        let labels = (0..self.parent.len())
+            .map(|i| {
+                let root = self.find(i);
+                let next = numbers.len();
+                *numbers.entry(root).or_insert(next)
+            })
+            .collect();
+        (labels, numbers.len())
+    }
+}
+
+/// Split `triangles` into shells: the sets of triangles joined to each
+/// other by their vertices.
+fn shells(triangles: &[Triangle]) -> Vec<Vec<Triangle>> {
+    // Vertices are matched up to a micron, since exporters round them
+    // differently from facet to facet.
+    let mut ids: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut vertex_ids = Vec::with_capacity(triangles.len() * 3);
+    for triangle in triangles {
+        for vertex in triangle {
+            let key = vertex.map(|coordinate| (coordinate * 1000.0).round() as i64);
+            let next = ids.len();
+            vertex_ids.push(*ids.entry(key).or_insert(next));
+        }
+    }
+
+    let mut sets = UnionFind::new(ids.len());
+    for triangle in vertex_ids.chunks_exact(3) {
+        sets.union(triangle[0], triangle[1]);
+        sets.union(triangle[1], triangle[2]);
+    }
+
+    let (labels, count) = sets.numbered();
+    let mut shells = vec![vec![]; count];
+    for (triangle, ids) in triangles.iter().zip(vertex_ids.chunks_exact(3)) {
+        shells[labels[ids[0]]].push(*triangle);
+    }
+    shells
+}
+
+/// Count the shells in `triangles` which are thinner (in X or Y) than
+/// `nozzle_diameter`.
+fn count_tiny_features(triangles: &[Triangle], nozzle_diameter: f64) -> usize {
+    shells(triangles)
+        .iter()
+        .filter(|shell| {
+            let (min, max) = bounds(shell);
+            (max[0] - min[0]).min(max[1] - min[1]) < nozzle_diameter
+        })
+        .count()
+}
+
+/// Volume enclosed by a closed shell: negative for one wound inside out,
+/// such as the inside of a hollow part.
+fn signed_volume(shell: &[Triangle]) -> f64 {
+    shell
+        .iter()
+        .map(|[a, b, c]| {
+            let normal = cross(*b, *c);
+            (a[0] * normal[0] + a[1] * normal[1] + a[2] * normal[2]) / 6.0
+        })
+        .sum()
+}
+
+/// Check whether two bounding boxes meet (at a face, an edge or a corner)
+/// without overlapping.
+fn touching((min_a, max_a): ([f64; 3], [f64; 3]), (min_b, max_b): ([f64; 3], [f64; 3])) -> bool {
+    let overlaps: [f64; 3] = std::array::from_fn(|axis| max_a[axis].min(max_b[axis]) - min_a[axis].max(min_b[axis]));
+    overlaps.iter().all(|overlap| *overlap >= -TOUCH_EPSILON)
+        && overlaps.iter().any(|overlap| *overlap <= TOUCH_EPSILON)
+}
+
+/// Check whether the bounding box `outer` contains `inner`.
+fn contains((min_outer, max_outer): ([f64; 3], [f64; 3]), (min_inner, max_inner): ([f64; 3], [f64; 3])) -> bool {
+    (0..3).all(|axis| {
+        min_outer[axis] <= min_inner[axis] + TOUCH_EPSILON && max_inner[axis] <= max_outer[axis] + TOUCH_EPSILON
+    })
+}
+
+/// Split `triangles` into the separate bodies they make up. Shells which
+/// touch (such as one stacked on another) are one body, as are a hollow
+/// part's inner and outer shells, while shells which are apart, or which
+/// overlap (such as the parts of an assembly, placed as they fit
+/// together), are separate bodies.
+fn bodies(triangles: &[Triangle]) -> Vec<Vec<Triangle>> {
+    let shells = shells(triangles);
+    let boxes = shells.iter().map(|shell| bounds(shell)).collect::<Vec<_>>();
+    let solid = shells
+        .iter()
+        .map(|shell| signed_volume(shell) >= 0.0)
+        .collect::<Vec<_>>();
+    let box_volume = |(min, max): ([f64; 3], [f64; 3])| (0..3).map(|axis| max[axis] - min[axis]).product::<f64>();
+
+    let mut sets = UnionFind::new(shells.len());
+    for i in 0..shells.len() {
+        if !solid[i] {
+            // A cavity belongs to the smallest solid shell around it. One
+            // with nothing around it is just wound inside out, and is a
+            // body of its own.
+            let around = (0..shells.len())
+                .filter(|&j| solid[j] && contains(boxes[j], boxes[i]))
+                .min_by(|&a, &b| box_volume(boxes[a]).total_cmp(&box_volume(boxes[b])));
+            if let Some(j) = around {
+                sets.union(i, j);
+            }
+            continue;
+        }
+        for j in i + 1..shells.len() {
+            if solid[j] && touching(boxes[i], boxes[j]) {
+                sets.union(i, j);
+            }
+        }
+    }
+
+    let (labels, count) = sets.numbered();
+    let mut bodies = vec![vec![]; count];
+    for (shell, label) in shells.into_iter().zip(labels) {
+        bodies[label].extend(shell);
+    }
+    bodies
+}
+
+/// Return the smallest and largest coordinates in `triangles`, on each
+/// axis.
+fn bounds(triangles: &[Triangle]) -> ([f64; 3], [f64; 3]) {
+    let mut min = [f64::MAX; 3];
+    let mut max = [f64::MIN; 3];
+    for vertex in triangles.iter().flatten() {
+        min = std::array::from_fn(|axis| min[axis].min(vertex[axis]));
+        max = std::array::from_fn(|axis| max[axis].max(vertex[axis]));
+    }
+    (min, max)
+}
+
+/// Write triangles out as a binary STL file.
+fn write_binary_stl(triangles: &[Triangle]) -> Vec<u8> {
+    let mut stl = Vec::with_capacity(84 + triangles.len() * 50);
+    stl.extend([0u8; 80]);
+    stl.extend((triangles.len() as u32).to_le_bytes());
+    for triangle in triangles {
+        // Normals are left zeroed, for the slicer to work out from the
+        // winding.
+        stl.extend([0u8; 12]);
+        for coordinate in triangle.iter().flatten() {
+            stl.extend((*coordinate as f32).to_le_bytes());
+        }
+        stl.extend([0u8; 2]);
+    }
+    stl
+}
+
+/// Convert an STL file in `units` to millimeters, returning the scaled
+/// file, or `None` if it's fine as it is. If the units aren't given, and
+/// the part is suspiciously small, this fails with [SuspiciousUnits].
+/// Files which can't be parsed are left for the slicer to reject, unless
+/// they need scaling.
+pub fn stl_to_millimeters(stl: &[u8], units: Option<StlUnits>) -> Result<Option<Vec<u8>>> {
+    if units == Some(StlUnits::Mm) {
+        return Ok(None);
+    }
+
+    let triangles = match parse_stl(stl) {
+        Ok(triangles) if !triangles.is_empty() => triangles,
+        _ if units != Some(StlUnits::Inch) => return Ok(None),
+        Ok(_) => anyhow::bail!("STL file has no facets"),
+        Err(e) => return Err(e),
+    };
+
+    let (min, max) = bounds(&triangles);
+    let largest_mm = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f64::max);
+    let scale = match units {
+        Some(StlUnits::Inch) => true,
+        Some(StlUnits::Auto) => largest_mm < MIN_PLAUSIBLE_SIZE_MM,
+        None if largest_mm < MIN_PLAUSIBLE_SIZE_MM => return Err(SuspiciousUnits { largest_mm }.into()),
+        None | Some(StlUnits::Mm) => false,
+    };
+    if !scale {
+        return Ok(None);
+    }
+
+    let scaled = triangles
+        .iter()
+        .map(|triangle| triangle.map(|vertex| vertex.map(|coordinate| coordinate * MM_PER_INCH)))
+        .collect::<Vec<_>>();
+    Ok(Some(write_binary_stl(&scaled)))
+}
+
+/// Count the separate bodies in an STL file, as [split_stl] splits them.
+/// Files which can't be parsed are counted as one, for the slicer to
+/// reject.
+pub(crate) fn count_bodies(stl: &[u8]) -> usize {
+    match parse_stl(stl) {
+        Ok(triangles) if !triangles.is_empty() => bodies(&triangles).len(),
+        _ => 1,
+    }
+}
+
+/// Split an STL file into the separate bodies in it (such as the parts of
+/// an assembly), each as its own binary STL file, left where they are in
+/// the file. Returns `None` if there's only the one body, or the file
+/// can't be parsed, which is left for the slicer to reject.
+pub fn split_stl(stl: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let triangles = parse_stl(stl).ok()?;
+    let bodies = bodies(&triangles);
+    if bodies.len() < 2 {
+        return None;
+    }
+    Some(bodies.iter().map(|body| write_binary_stl(body)).collect())
+}
+
+/// Analyze an STL file for how likely it is to print well.
+pub fn analyze_stl(stl: &[u8], params: &AnalyzeParameters) -> Result<PrintabilityReport> {
+    let triangles = parse_stl(stl)?;
+    if triangles.is_empty() {
+        anyhow::bail!("STL file has no facets");
+    }
+
+    let (min, max) = bounds(&triangles);
+
+    // A surface overhangs when its normal points further down than this.
+    let overhang_limit = -params.overhang_angle.to_radians().sin();
+
+    let mut surface_area = 0.0;
+    let mut overhang_area = 0.0;
+    let mut bed_contact_area = 0.0;
+    for [a, b, c] in &triangles {
+        let normal = cross(sub(*b, *a), sub(*c, *a));
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+        let area = length / 2.0;
+        let down = normal[2] / length;
+        surface_area += area;
+
+        let on_bed = [a, b, c].iter().all(|vertex| vertex[2] - min[2] <= BED_EPSILON);
+        if on_bed && down < -0.99 {
+            bed_contact_area += area;
+        } else if down < overhang_limit {
+            overhang_area += area;
+        }
+    }
+
+    let overhang_percentage = if surface_area > 0.0 {
+        overhang_area / surface_area * 100.0
+    } else {
+        0.0
+    };
+    let tiny_features = count_tiny_features(&triangles, params.nozzle_diameter);
+
+    // Each percent of the surface overhanging costs a point (up to half the
+    // score), a loose part costs a quarter (or half, if it's floating), and
+    // each tiny feature costs ten.
+    let mut risks = vec![];
+    let mut score = 100.0;
+    if overhang_area > 0.0 {
+        risks.push(PrintabilityRisk::Overhangs);
+        score -= overhang_percentage.min(50.0);
+    }
+    if tiny_features > 0 {
+        risks.push(PrintabilityRisk::TinyFeatures);
+        score -= (10 * tiny_features).min(30) as f64;
+    }
+    if bed_contact_area < MIN_BED_CONTACT_AREA {
+        risks.push(PrintabilityRisk::SmallBedContact);
+        score -= if bed_contact_area == 0.0 { 50.0 } else { 25.0 };
+    }
+
+    Ok(PrintabilityReport {
+        score: f64::max(score, 0.0),
+        risks,
+        size: Volume {
+            width: max[0] - min[0],
+            depth: max[1] - min[1],
+            height: max[2] - min[2],
+        },
+        triangles: triangles.len(),
+        surface_area,
+        overhang_area,
+        overhang_percentage,
+        bed_contact_area,
+        tiny_features,
+        bodies: bodies(&triangles).len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestPrint;
+
+    #[test]
+    fn test_calibration_cube() {
+        let report = analyze_stl(TestPrint::CalibrationCube.stl().as_bytes(), &Default::default()).unwrap();
+        assert_eq!(report.score, 100.0);
+        assert!(report.risks.is_empty());
+        assert_eq!(
+            report.size,
+            Volume {
+                width: 20.0,
+                depth: 20.0,
+                height: 20.0
+            }
+        );
+        assert_eq!(report.triangles, 12);
+        assert_eq!(report.surface_area, 2400.0);
+        assert_eq!(report.bed_contact_area, 400.0);
+        assert_eq!(report.overhang_area, 0.0);
+        assert_eq!(report.tiny_features, 0);
+        assert_eq!(report.bodies, 1);
+    }
+
+    #[test]
+    fn test_temperature_tower() {
+        // The tower is built from separate cuboids, so each tier above the
+        // first overhangs its neck, and each neck's underside counts too.
+        let report = analyze_stl(TestPrint::TemperatureTower.stl().as_bytes(), &Default::default()).unwrap();
+        assert_eq!(report.risks, vec![PrintabilityRisk::Overhangs]);
+        assert_eq!(report.overhang_area, 4.0 * 400.0 + 5.0 * 144.0);
+        assert!(report.score < 100.0 && report.score > 50.0, "{}", report.score);
+        // The cuboids are stacked, so make up a single body.
+        assert_eq!(report.bodies, 1);
+    }
+
+    #[test]
+    fn test_tiny_floating_feature() {
+        // A downward-facing triangle on the bed, then a sliver 0.1mm wide
+        // floating above it.
+        let stl = "solid test
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 0 2 0
+      vertex 2 0 0
+    endloop
+  endfacet
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 5
+      vertex 0 10 5
+      vertex 0.1 0 5
+    endloop
+  endfacet
+endsolid test
+";
+        let report = analyze_stl(stl.as_bytes(), &Default::default()).unwrap();
+        assert_eq!(report.bed_contact_area, 2.0);
+        assert_eq!(report.overhang_area, 0.5);
+        assert_eq!(report.tiny_features, 1);
+        assert_eq!(report.bodies, 2);
+        assert_eq!(
+            report.risks,
+            vec![
+                PrintabilityRisk::Overhangs,
+                PrintabilityRisk::TinyFeatures,
+                PrintabilityRisk::SmallBedContact
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bodies() {
+        let cube = parse_stl(TestPrint::CalibrationCube.stl().as_bytes()).unwrap();
+        let moved = |offset: f64, scale: f64| {
+            cube.iter()
+                .map(|triangle| triangle.map(|vertex| vertex.map(|coordinate| coordinate * scale + offset)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bodies(&cube).len(), 1);
+
+        // A hollow cube, with its cavity wound inside out, is one body.
+        let mut hollow = cube.clone();
+        hollow.extend(moved(5.0, 0.5).into_iter().map(|[a, b, c]| [a, c, b]));
+        assert_eq!(bodies(&hollow).len(), 1);
+
+        // Two cubes fit into each other, as an assembly, are two.
+        let mut assembly = cube.clone();
+        assembly.extend(moved(10.0, 1.0));
+        assert_eq!(bodies(&assembly).len(), 2);
+
+        let bed_level = TestPrint::BedLevel.stl();
+        assert_eq!(count_bodies(bed_level.as_bytes()), 5);
+        let split = split_stl(bed_level.as_bytes()).unwrap();
+        assert_eq!(split.len(), 5);
+        for body in split {
+            let report = analyze_stl(&body, &Default::default()).unwrap();
+            assert_eq!(report.triangles, 12);
+            assert_eq!(report.bodies, 1);
+        }
+
+        assert!(split_stl(TestPrint::CalibrationCube.stl().as_bytes()).is_none());
+        assert!(split_stl(b"not an stl").is_none());
+        assert_eq!(count_bodies(b"not an stl"), 1);
+    }
+
+    #[test]
+    fn test_binary_stl() {
+        let mut stl = vec![0u8; 80];
+        stl.extend(1u32.to_le_bytes());
+        stl.extend([0u8; 12]);
+        for vertex in [[0.0f32, 0.0, 0.0], [0.0, 10.0, 0.0], [10.0, 0.0, 0.0]] {
+            for coordinate in vertex {
+                stl.extend(coordinate.to_le_bytes());
+            }
+        }
+        stl.extend([0u8; 2]);
+
+        let report = analyze_stl(&stl, &Default::default()).unwrap();
+        assert_eq!(report.triangles, 1);
+        assert_eq!(report.bed_contact_area, 50.0);
+
+        assert!(analyze_stl(b"not an stl", &Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_stl_to_millimeters() {
+        // A 0.5" cube, exported in inches.
+        let cube = TestPrint::CalibrationCube.stl();
+        let small = cube.replace("20", "0.5");
+
+        let e = stl_to_millimeters(small.as_bytes(), None).unwrap_err();
+        let suspicious = e.downcast_ref::<SuspiciousUnits>().unwrap();
+        assert_eq!(suspicious.largest_mm, 0.5);
+
+        assert!(stl_to_millimeters(small.as_bytes(), Some(StlUnits::Mm))
+            .unwrap()
+            .is_none());
+        for units in [StlUnits::Inch, StlUnits::Auto] {
+            let scaled = stl_to_millimeters(small.as_bytes(), Some(units)).unwrap().unwrap();
+            let report = analyze_stl(&scaled, &Default::default()).unwrap();
+            assert!((report.size.width - 12.7).abs() < 1e-4, "{}", report.size.width);
+            assert_eq!(report.triangles, 12);
+        }
+
+        // A 20mm cube is fine as it is, unless it's said to be in inches.
+        assert!(stl_to_millimeters(cube.as_bytes(), None).unwrap().is_none());
+        assert!(stl_to_millimeters(cube.as_bytes(), Some(StlUnits::Auto))
+            .unwrap()
+            .is_none());
+        assert!(stl_to_millimeters(cube.as_bytes(), Some(StlUnits::Inch))
+            .unwrap()
+            .is_some());
+
+        // Files which aren't STLs are left to the slicer.
+        assert!(stl_to_millimeters(b"not an stl", None).unwrap().is_none());
+        assert!(stl_to_millimeters(b"not an stl", Some(StlUnits::Inch)).is_err());
+    }
+}
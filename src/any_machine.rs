@@ -1,7 +1,8 @@
 use anyhow::Result;
 
 use crate::{
-    Control as ControlTrait, HardwareConfiguration, MachineInfo, MachineMakeModel, MachineState, MachineType, Volume,
+    slicer::remote::SliceFormat, Control as ControlTrait, HardwareConfiguration, LayerProgress, MachineInfo,
+    MachineMakeModel, MachineState, MachineType, Volume,
 };
 
 /// AnyMachine is any supported machine.
@@ -97,6 +98,74 @@ macro_rules! for_all {
     };
 }
 
+impl AnyMachine {
+    /// Return the name of the type of machine, as used for its `type` in
+    /// the config file, such as `Bambu`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "bambu")]
+            Self::Bambu(_) => "Bambu",
+
+            #[cfg(feature = "moonraker")]
+            Self::Moonraker(_) => "Moonraker",
+
+            #[cfg(feature = "serial")]
+            Self::Usb(_) => "Usb",
+
+            Self::Noop(_) => "Noop",
+        }
+    }
+
+    /// Return the formats the machine can build from, most preferred first.
+    pub fn accepted_formats(&self) -> &'static [SliceFormat] {
+        match self {
+            #[cfg(feature = "bambu")]
+            Self::Bambu(_) => &[SliceFormat::ThreeMf],
+
+            #[cfg(feature = "moonraker")]
+            Self::Moonraker(_) => &[SliceFormat::Gcode],
+
+            #[cfg(feature = "serial")]
+            Self::Usb(_) => &[SliceFormat::Gcode],
+
+            Self::Noop(_) => &[SliceFormat::Gcode, SliceFormat::ThreeMf],
+        }
+    }
+
+    /// Return whether the machine can pause and resume jobs.
+    pub fn can_suspend(&self) -> bool {
+        match self {
+            #[cfg(feature = "bambu")]
+            Self::Bambu(_) => true,
+
+            #[cfg(feature = "moonraker")]
+            Self::Moonraker(_) => true,
+
+            #[cfg(feature = "serial")]
+            Self::Usb(_) => true,
+
+            Self::Noop(_) => true,
+        }
+    }
+
+    /// Return the hostname (or IP address) the machine is reached at, for
+    /// machines reached over the network.
+    pub fn hostname(&self) -> Option<String> {
+        match self {
+            #[cfg(feature = "bambu")]
+            Self::Bambu(machine) => Some(machine.hostname()),
+
+            #[cfg(feature = "moonraker")]
+            Self::Moonraker(machine) => machine.hostname(),
+
+            #[cfg(feature = "serial")]
+            Self::Usb(_) => None,
+
+            Self::Noop(_) => None,
+        }
+    }
+}
+
 impl MachineInfo for AnyMachineInfo {
     fn machine_type(&self) -> MachineType {
         for_all!(|self, machine| { machine.machine_type() })
@@ -135,6 +204,10 @@ impl ControlTrait for AnyMachine {
         for_all!(|self, machine| { machine.progress().await })
     }
 
+    async fn layer_progress(&self) -> Result<Option<LayerProgress>> {
+        for_all!(|self, machine| { machine.layer_progress().await })
+    }
+
     async fn state(&self) -> Result<MachineState> {
         for_all!(|self, machine| { machine.state().await })
     }
@@ -0,0 +1,106 @@
+//! Accessories on Bambu printers: the chamber light, the auxiliary and
+//! chamber fans, and any AMS units.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bambulabs::{
+    command::{Command, LedMode, LedNode},
+    fan::Fan,
+    message::{Message, System},
+};
+
+use super::Bambu;
+use crate::{Accessory, AccessoryError, AccessoryKind};
+
+/// How long to wait for the printer to say which accessories it has,
+/// before listing them as if it had them all.
+const GET_ACCESSORIES_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CHAMBER_LIGHT: &str = "chamber_light";
+const AUX_FAN: &str = "aux_fan";
+const CHAMBER_FAN: &str = "chamber_fan";
+
+impl Bambu {
+    /// List the printer's accessories, and their state.
+    pub async fn accessories(&self) -> Result<Vec<Accessory>> {
+        let status = self.client.get_status()?;
+
+        let aux_part_fan = match tokio::time::timeout(
+            GET_ACCESSORIES_TIMEOUT,
+            self.client.publish(Command::get_accessories()),
+        )
+        .await
+        {
+            Ok(Ok(Message::System(System::GetAccessories(accessories)))) => Some(accessories.aux_part_fan),
+            _ => None,
+        };
+
+        let light_on = status
+            .as_ref()
+            .and_then(|status| status.lights_report.as_ref())
+            .and_then(|lights| lights.iter().find(|light| light.node == LedNode::ChamberLight))
+            .map(|light| light.mode == LedMode::On);
+        let mut accessories = vec![Accessory {
+            name: CHAMBER_LIGHT.to_owned(),
+            kind: AccessoryKind::Light,
+            controllable: true,
+            on: light_on,
+            state: None,
+        }];
+
+        let fans = [
+            (
+                AUX_FAN,
+                aux_part_fan != Some(false),
+                status.as_ref().and_then(|status| status.big_fan1_speed.as_ref()),
+            ),
+            (
+                CHAMBER_FAN,
+                true,
+                status.as_ref().and_then(|status| status.big_fan2_speed.as_ref()),
+            ),
+        ];
+        for (name, present, speed) in fans {
+            let Some(speed) = speed.filter(|_| present) else {
+                continue;
+            };
+            accessories.push(Accessory {
+                name: name.to_owned(),
+                kind: AccessoryKind::Fan,
+                controllable: true,
+                on: Some(speed != "0"),
+                state: Some(format!("speed {}/15", speed)),
+            });
+        }
+
+        for unit in self.ams_units()? {
+            accessories.push(Accessory {
+                name: format!("ams_{}", unit.id),
+                kind: AccessoryKind::MultiMaterialUnit,
+                controllable: false,
+                on: None,
+                state: unit
+                    .humidity_percent
+                    .map(|humidity| format!("{:.0}% humidity", humidity)),
+            });
+        }
+
+        Ok(accessories)
+    }
+
+    /// Turn one of the printer's accessories on or off.
+    pub async fn set_accessory(&self, name: &str, on: bool) -> Result<()> {
+        let command = match name {
+            CHAMBER_LIGHT => Command::set_chamber_light(on.into()),
+            AUX_FAN | CHAMBER_FAN => {
+                let fan = if name == AUX_FAN { Fan::Auxiliary } else { Fan::Chamber };
+                Command::set_fan_speed(fan, if on { 100 } else { 0 })
+            }
+            _ if name.starts_with("ams_") => return Err(AccessoryError::NotControllable(name.to_owned()).into()),
+            _ => return Err(AccessoryError::NotFound(name.to_owned()).into()),
+        };
+        self.client.publish(command).await?;
+        Ok(())
+    }
+}
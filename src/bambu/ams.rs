@@ -0,0 +1,184 @@
+//! Status of Bambu AMS (Automatic Material System) units, which hold the
+//! spools of filament -- including how damp they're getting.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use bambulabs::message::PrintAmsData;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Bambu;
+use crate::FilamentMaterial;
+
+/// Status of a single AMS unit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AmsUnit {
+    /// The AMS unit id.
+    pub id: String,
+
+    /// Bambu's coarse humidity level for the unit, from 1 to 5.
+    pub humidity_level: Option<u8>,
+
+    /// Relative humidity inside the unit, in percent. Only reported by
+    /// newer firmware.
+    pub humidity_percent: Option<f64>,
+
+    /// Temperature inside the unit, in Celsius.
+    pub temperature_celsius: Option<f64>,
+
+    /// Materials of the filament loaded in the unit.
+    pub materials: Vec<FilamentMaterial>,
+}
+
+impl From<&PrintAmsData> for AmsUnit {
+    fn from(ams: &PrintAmsData) -> Self {
+        let mut materials = vec![];
+        for tray in &ams.tray {
+            let Some(tray_type) = tray.tray_type.as_deref().filter(|tray_type| !tray_type.is_empty()) else {
+                continue;
+            };
+            let material = FilamentMaterial::from_slicer_name(tray_type);
+            if !materials.contains(&material) {
+                materials.push(material);
+            }
+        }
+
+        Self {
+            id: ams.id.clone(),
+            humidity_level: ams.humidity.parse().ok(),
+            humidity_percent: ams.humidity_raw.as_deref().and_then(|humidity| humidity.parse().ok()),
+            temperature_celsius: ams.temp.parse().ok(),
+            materials,
+        }
+    }
+}
+
+impl Bambu {
+    /// Return the status of each AMS unit attached to the printer.
+    pub fn ams_units(&self) -> Result<Vec<AmsUnit>> {
+        let Some(status) = self.client.get_status()? else {
+            return Ok(vec![]);
+        };
+
+        Ok(status
+            .ams
+            .map(|ams| ams.ams.iter().map(AmsUnit::from).collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Relative humidity limits (in percent), keyed by filament type (such as
+/// `PETG`, or `PA`), above which an AMS unit holding that material is
+/// considered too damp.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HumidityLimits(pub HashMap<String, f64>);
+
+impl Default for HumidityLimits {
+    /// Limits for the usual hygroscopic suspects.
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("PA".to_owned(), 20.0),
+            ("PVA".to_owned(), 20.0),
+            ("TPU".to_owned(), 30.0),
+            ("PETG".to_owned(), 40.0),
+        ]))
+    }
+}
+
+impl HumidityLimits {
+    /// Return the humidity limit for `material`, if it has one.
+    pub fn limit_for(&self, material: FilamentMaterial) -> Option<f64> {
+        self.0
+            .iter()
+            .filter(|(name, _)| FilamentMaterial::from_slicer_name(name) == material)
+            .map(|(_, limit)| *limit)
+            .reduce(f64::min)
+    }
+}
+
+/// An AMS unit is more humid than the limit for a material it holds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HumidityWarning {
+    /// The AMS unit id.
+    pub ams_id: String,
+
+    /// The material which is at risk.
+    pub material: FilamentMaterial,
+
+    /// Relative humidity inside the unit, in percent.
+    pub humidity_percent: f64,
+
+    /// The configured limit for the material, in percent.
+    pub limit_percent: f64,
+}
+
+/// Check each AMS unit against the humidity limits for the materials loaded
+/// in it. Units which don't report a humidity percentage are skipped.
+pub fn humidity_warnings(units: &[AmsUnit], limits: &HumidityLimits) -> Vec<HumidityWarning> {
+    let mut warnings = vec![];
+    for unit in units {
+        let Some(humidity_percent) = unit.humidity_percent else {
+            continue;
+        };
+        for material in &unit.materials {
+            let Some(limit_percent) = limits.limit_for(*material) else {
+                continue;
+            };
+            if humidity_percent > limit_percent {
+                warnings.push(HumidityWarning {
+                    ams_id: unit.id.clone(),
+                    material: *material,
+                    humidity_percent,
+                    limit_percent,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(id: &str, humidity_percent: Option<f64>, materials: Vec<FilamentMaterial>) -> AmsUnit {
+        AmsUnit {
+            id: id.to_owned(),
+            humidity_level: Some(3),
+            humidity_percent,
+            temperature_celsius: Some(25.0),
+            materials,
+        }
+    }
+
+    #[test]
+    fn test_humidity_warnings() {
+        let limits = HumidityLimits::default();
+        let units = vec![
+            unit("0", Some(35.0), vec![FilamentMaterial::Pla, FilamentMaterial::Tpu]),
+            unit("1", Some(35.0), vec![FilamentMaterial::Petg]),
+            unit("2", None, vec![FilamentMaterial::Nylon]),
+        ];
+
+        assert_eq!(
+            humidity_warnings(&units, &limits),
+            vec![HumidityWarning {
+                ams_id: "0".to_owned(),
+                material: FilamentMaterial::Tpu,
+                humidity_percent: 35.0,
+                limit_percent: 30.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_limit_for() {
+        let limits: HumidityLimits = toml::from_str("PETG = 35.0\nnylon = 15.0\nPA = 25.0").unwrap();
+
+        assert_eq!(limits.limit_for(FilamentMaterial::Petg), Some(35.0));
+        assert_eq!(limits.limit_for(FilamentMaterial::Nylon), Some(15.0));
+        assert_eq!(limits.limit_for(FilamentMaterial::Pla), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! On-disk cache of discovered Bambu printers, so after a restart they can
+//! be listed (and reconnected to) straight away, rather than after they
+//! next announce themselves over SSDP.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+/// What we remember about a discovered printer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedPrinter {
+    /// The printer's name, as announced over SSDP.
+    pub name: String,
+
+    /// The printer's IP address, when it was last seen.
+    pub ip: IpAddr,
+
+    /// The printer's serial number.
+    pub serial: String,
+}
+
+/// Discovered printers, stored as JSON keyed by machine-api id.
+#[derive(Clone, Debug)]
+pub struct DiscoveryCache {
+    path: PathBuf,
+
+    /// Held while updating the cache, since several printers may be
+    /// discovered at once.
+    lock: Arc<Mutex<()>>,
+}
+
+impl DiscoveryCache {
+    /// Create a new cache, stored at `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Return every cached printer. A missing or unreadable cache is
+    /// treated as empty.
+    pub async fn load(&self) -> HashMap<String, CachedPrinter> {
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+            Err(e) => {
+                tracing::warn!(
+                    path = format!("{:?}", self.path),
+                    error = format!("{:?}", e),
+                    "failed to read discovery cache"
+                );
+                return HashMap::new();
+            }
+        };
+
+        match serde_json::from_slice(&contents) {
+            Ok(printers) => printers,
+            Err(e) => {
+                tracing::warn!(
+                    path = format!("{:?}", self.path),
+                    error = format!("{:?}", e),
+                    "ignoring corrupt discovery cache"
+                );
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Remember `printer` as `machine_api_id`, replacing anything cached
+    /// for it before.
+    pub async fn insert(&self, machine_api_id: &str, printer: CachedPrinter) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        let mut printers = self.load().await;
+        if printers.get(machine_api_id) == Some(&printer) {
+            return Ok(());
+        }
+        printers.insert(machine_api_id.to_owned(), printer);
+
+        // Write to the side and rename, so a crash mid-write doesn't lose
+        // the whole cache.
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(&printers)?).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4().simple()));
+        let cache = DiscoveryCache::new(&path);
+        assert!(cache.load().await.is_empty());
+
+        let printer = CachedPrinter {
+            name: "my-x1c".to_owned(),
+            ip: "192.168.1.103".parse().unwrap(),
+            serial: "00M00A000000000".to_owned(),
+        };
+        cache.insert("x1c", printer.clone()).await.unwrap();
+        assert_eq!(cache.load().await, HashMap::from([("x1c".to_owned(), printer)]));
+
+        tokio::fs::write(&path, "not json").await.unwrap();
+        assert!(cache.load().await.is_empty());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+//! Still images and live video from Bambu printers' cameras.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use super::{Bambu, BambuCamera};
+use crate::CameraStream;
+
+/// How long to wait for the camera to hand over an image.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(20);
+
+impl Bambu {
+    /// Take a still image from the printer's camera, as a JPEG, if we know
+    /// how to reach it. Printers which stream over RTSP are read with
+    /// `ffmpeg`, which must be installed.
+    pub async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        let Some(camera) = self.variant().map(|variant| variant.camera()) else {
+            return Ok(None);
+        };
+        let snapshot = async {
+            match camera {
+                BambuCamera::Jpeg => self.client.jpeg_snapshot().await,
+                BambuCamera::Rtsps => {
+                    let url = self.checked_camera_url(camera).await?;
+                    rtsp_snapshot(&url).await
+                }
+            }
+        };
+        let image = tokio::time::timeout(SNAPSHOT_TIMEOUT, snapshot)
+            .await
+            .context("timed out waiting for the camera")??;
+        Ok(Some(image))
+    }
+
+    /// Stream live video from the printer's camera, if we know how to reach
+    /// it. Printers which stream over RTSP are read with `ffmpeg`, which
+    /// must be installed.
+    pub async fn camera_stream(&self) -> Result<Option<CameraStream>> {
+        let Some(camera) = self.variant().map(|variant| variant.camera()) else {
+            return Ok(None);
+        };
+        let stream = match camera {
+            BambuCamera::Jpeg => {
+                let camera = tokio::time::timeout(SNAPSHOT_TIMEOUT, self.client.jpeg_camera())
+                    .await
+                    .context("timed out connecting to the camera")??;
+                CameraStream::bambu_jpeg(camera)
+            }
+            BambuCamera::Rtsps => {
+                let url = tokio::time::timeout(SNAPSHOT_TIMEOUT, self.checked_camera_url(camera))
+                    .await
+                    .context("timed out connecting to the camera")??;
+                CameraStream::rtsp(&url)?
+            }
+        };
+        Ok(Some(stream))
+    }
+
+    /// Return the URL of the printer's RTSPS camera stream, once its
+    /// certificate is checked against the printer's pinned certificate,
+    /// since `ffmpeg` can't check it itself.
+    async fn checked_camera_url(&self, camera: BambuCamera) -> Result<String> {
+        let url = self.camera_url().await.context("printer has no camera stream")?;
+        self.client.check_camera_certificate(camera.port()).await?;
+        Ok(url)
+    }
+}
+
+/// Grab a single frame from an RTSP(S) stream with `ffmpeg`, as a JPEG.
+async fn rtsp_snapshot(url: &str) -> Result<Vec<u8>> {
+    let output = tokio::process::Command::new("ffmpeg")
+        .args([
+            "-loglevel",
+            "error",
+            "-rtsp_transport",
+            "tcp",
+            "-i",
+            url,
+            "-frames:v",
+            "1",
+            "-f",
+            "image2",
+            "-c:v",
+            "mjpeg",
+            "pipe:1",
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to run ffmpeg")?;
+    if !output.status.success() || output.stdout.is_empty() {
+        anyhow::bail!(
+            "ffmpeg failed to read the camera stream: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
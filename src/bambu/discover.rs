@@ -1,16 +1,23 @@
 use std::{
     collections::HashMap,
     net::{IpAddr, Ipv4Addr},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 use anyhow::Result;
 use parse_display::{Display, FromStr};
 use serde::{Deserialize, Serialize};
-use tokio::{net::UdpSocket, sync::RwLock};
+use tokio::{net::UdpSocket, sync::RwLock, task::JoinHandle};
+use tracing::Instrument;
 
-use super::{Bambu, PrinterInfo};
-use crate::{slicer, Discover as DiscoverTrait, Machine, MachineMakeModel};
+use ipnet::{IpNet, Ipv4Net};
+
+use super::{Bambu, CachedPrinter, DiscoveryCache, KnownCertificates, PrinterInfo};
+use crate::{
+    is_on_networks, slicer, AnyMachine, BambuPrintOptions, Discover as DiscoverTrait, Machine, MachineMakeModel,
+    NetworkFilter, Supervisor, Volume,
+};
 
 /// Specific make/model of Bambu device.
 #[derive(Copy, Clone, Debug, Deserialize, Serialize, Display, FromStr, PartialEq, Eq)]
@@ -32,6 +39,28 @@ pub enum BambuVariant {
     /// Bambu Labs X1 Carbon printer.
     #[serde(rename = "X1 Carbon")]
     X1Carbon,
+    /// Bambu Labs H2D printer.
+    H2D,
+}
+
+/// How a Bambu printer's camera is streamed.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BambuCamera {
+    /// RTSP over TLS, on port 322.
+    Rtsps,
+    /// JPEG frames over a TLS socket, on port 6000.
+    Jpeg,
+}
+
+impl BambuCamera {
+    /// Port on the printer the camera is served on.
+    pub fn port(&self) -> u16 {
+        match self {
+            Self::Rtsps => 322,
+            Self::Jpeg => 6000,
+        }
+    }
 }
 
 impl BambuVariant {
@@ -52,10 +81,52 @@ impl BambuVariant {
             Some(Self::X1)
         } else if sn.starts_with("00M") {
             Some(Self::X1Carbon)
+        } else if sn.starts_with("094") {
+            Some(Self::H2D)
         } else {
             None
         }
     }
+
+    /// Get the variant from the model code the printer announces in its
+    /// `DevModel.bambu.com` SSDP header.
+    pub fn get_from_model_code(code: &str) -> Option<Self> {
+        match code {
+            "N2S" => Some(Self::A1),
+            "N1" => Some(Self::A1Mini),
+            "C11" => Some(Self::P1P),
+            "C12" => Some(Self::P1S),
+            "BL-P002" => Some(Self::X1),
+            "C13" => Some(Self::X1E),
+            "BL-P001" | "3DPrinter-X1-Carbon" => Some(Self::X1Carbon),
+            "O1D" => Some(Self::H2D),
+            _ => None,
+        }
+    }
+
+    /// Whether the printer has a chamber temperature sensor.
+    pub fn has_chamber_sensor(&self) -> bool {
+        matches!(self, Self::X1 | Self::X1E | Self::X1Carbon | Self::H2D)
+    }
+
+    /// The largest part the printer can print, in millimeters.
+    pub fn build_volume(&self) -> Volume {
+        let (width, depth, height) = match self {
+            Self::A1Mini => (180.0, 180.0, 180.0),
+            Self::A1 | Self::P1P | Self::P1S | Self::X1 | Self::X1E | Self::X1Carbon => (256.0, 256.0, 256.0),
+            // Printing with a single nozzle; using both narrows it slightly.
+            Self::H2D => (325.0, 320.0, 325.0),
+        };
+        Volume { width, depth, height }
+    }
+
+    /// How the printer's camera is streamed.
+    pub fn camera(&self) -> BambuCamera {
+        match self {
+            Self::A1 | Self::A1Mini | Self::P1P | Self::P1S => BambuCamera::Jpeg,
+            Self::X1 | Self::X1E | Self::X1Carbon | Self::H2D => BambuCamera::Rtsps,
+        }
+    }
 }
 
 /// Configuration block for a Bambu device.
@@ -69,204 +140,779 @@ pub struct Config {
 
     /// The access code for the printer.
     pub access_code: String,
+
+    /// IP address of the printer. If this and `serial` are set, the
+    /// printer is registered at startup rather than waiting for it to be
+    /// discovered over SSDP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+
+    /// Serial number of the printer. Required for static printers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    /// SHA-256 fingerprint of the printer's TLS certificate. If set, the
+    /// printer is only trusted if it presents this certificate, rather than
+    /// whatever certificate it presents. This is the printer's own
+    /// self-signed certificate, which can be fetched with
+    /// `openssl s_client -connect <ip>:8883 | openssl x509 -fingerprint -sha256`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_fingerprint: Option<String>,
+
+    /// If `certificate_fingerprint` isn't set, trust the first certificate
+    /// the printer presents, and only that certificate from then on.
+    #[serde(default)]
+    pub trust_on_first_use: bool,
+
+    /// Parse the printer's status pushes at most this often, in
+    /// milliseconds. Pushes which don't change anything are skipped
+    /// regardless, and reading the status always sees the latest push.
+    #[serde(default = "default_status_interval_ms")]
+    pub status_interval_ms: u64,
+
+    /// Record the printer's MQTT traffic (message counts by topic, recent
+    /// payloads, and payloads which couldn't be parsed), to be served at
+    /// `/v1/machines/{id}/debug/mqtt`. This is for working out what's
+    /// changed in payloads from new firmware.
+    #[serde(default)]
+    pub debug_mqtt: bool,
+
+    /// Checks and extras to run along with prints (such as bed leveling,
+    /// or a timelapse), unless a job says otherwise.
+    #[serde(default)]
+    pub print_options: BambuPrintOptions,
+}
+
+/// How many recent MQTT payloads are kept for printers with `debug_mqtt`
+/// set.
+const MQTT_DEBUG_PAYLOADS: usize = 50;
+
+fn default_status_interval_ms() -> u64 {
+    1000
+}
+
+impl Config {
+    /// Return true if this printer should be registered from config alone,
+    /// without waiting for SSDP discovery.
+    pub fn is_static(&self) -> bool {
+        self.ip.is_some()
+    }
 }
 
 const BAMBU_URN: &str = "urn:bambulab-com:device:3dprinter:1";
 
+/// Port Bambu printers send their NOTIFY announcements to. This is a
+/// non-standard port for any kind of UPnP/SSDP protocol. Incredible.
+const BAMBU_NOTIFY_PORT: u16 = 2021;
+
+/// Port some Bambu firmware uses for SSDP instead, both for announcements
+/// and for answering M-SEARCH queries. Also not the standard SSDP port
+/// (1900).
+const BAMBU_SSDP_PORT: u16 = 1990;
+
+/// SSDP multicast group.
+const SSDP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+
+/// How often to send M-SEARCH probes while there are configured printers
+/// we haven't found yet.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A printer announcement, parsed from either a NOTIFY or a response to
+/// one of our M-SEARCH probes.
+#[derive(Debug, Default, PartialEq)]
+struct Announcement {
+    urn: Option<String>,
+    name: Option<String>,
+    ip: Option<IpAddr>,
+    serial: Option<String>,
+    model: Option<String>,
+}
+
+/// Parse an SSDP payload. Returns `None` if it's not a NOTIFY or an
+/// M-SEARCH response (such as someone else's M-SEARCH query).
+fn parse_announcement(payload: &str) -> Option<Announcement> {
+    // Iterate through all non-blank lines in the payload
+    let mut lines = payload.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    // First line is a different format to the rest, and tells us the
+    // message type.
+    let header = lines.next()?;
+    if header != "NOTIFY * HTTP/1.1" && !header.starts_with("HTTP/1.1 200") {
+        tracing::trace!("Not a notify or search response, ignoring header {:?}", header);
+        return None;
+    }
+
+    let mut announcement = Announcement::default();
+    for line in lines {
+        let Some((token, rest)) = line.split_once(':') else {
+            tracing::debug!("Bad token line {}", line);
+            continue;
+        };
+
+        let token = token.trim();
+        let rest = rest.trim();
+
+        tracing::trace!("----> Token {}: {}", token, rest);
+
+        // Header names are case-insensitive, and search responses tend to
+        // shout them.
+        match token.to_ascii_lowercase().as_str() {
+            "location" => announcement.ip = parse_location(rest),
+            "devname.bambu.com" => announcement.name = Some(rest.to_owned()),
+            "usn" => announcement.serial = Some(rest.to_owned()),
+            // Not sent by older firmware; the X1E sends it on both its
+            // wired and wireless interfaces.
+            "devmodel.bambu.com" => announcement.model = Some(rest.to_owned()),
+            // NOTIFY uses NT, search responses use ST.
+            "nt" | "st" => announcement.urn = Some(rest.to_owned()),
+            // Ignore everything else
+            _ => (),
+        }
+    }
+
+    Some(announcement)
+}
+
+/// Get the printer's IP out of a `Location` header, which Bambu sets to a
+/// bare IP, but which might also be a URL.
+fn parse_location(location: &str) -> Option<IpAddr> {
+    if let Ok(ip) = location.parse() {
+        return Some(ip);
+    }
+
+    let host = location.split_once("://").map_or(location, |(_, rest)| rest);
+    let host = host.split(['/', ':']).next()?;
+    match host.parse() {
+        Ok(ip) => Some(ip),
+        Err(_) => {
+            tracing::warn!("Bad IP address in location {:?}", location);
+            None
+        }
+    }
+}
+
+/// Build an M-SEARCH probe for Bambu printers, addressed to `host`.
+fn m_search(host: &str) -> String {
+    format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 3\r\nST: {}\r\n\r\n",
+        host, BAMBU_URN
+    )
+}
+
+/// Bind the socket used for SSDP on [BAMBU_SSDP_PORT], joined to the SSDP
+/// multicast group on the given networks (or the default interface, if
+/// `None`).
+async fn bind_ssdp_socket(networks: Option<&[Ipv4Net]>) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, BAMBU_SSDP_PORT)).await?;
+    match networks {
+        Some(networks) => {
+            for network in networks {
+                socket.join_multicast_v4(SSDP_MULTICAST_ADDR, network.addr())?;
+            }
+        }
+        None => socket.join_multicast_v4(SSDP_MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED)?,
+    }
+    Ok(socket)
+}
+
+/// Receive from `socket` if there is one, or wait forever if there isn't.
+async fn recv_from_optional(
+    socket: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, std::net::SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Handle to discover connected Bambu Labs printers.
 pub struct BambuDiscover {
     config: HashMap<String, Config>,
+    network: NetworkFilter,
+    cache: Option<DiscoveryCache>,
+    known_certificates: Option<KnownCertificates>,
+    supervisor: Supervisor,
+    /// Each printer's MQTT connection, so it can be stopped if the printer
+    /// moves and is connected to again at its new address.
+    connections: Mutex<HashMap<String, JoinHandle<()>>>,
 }
 
 impl BambuDiscover {
     /// Return a new Discover handle using the provided Configuration
     /// struct [Config].
     pub fn new<ConfigsT: Into<HashMap<String, Config>>>(cfgs: ConfigsT) -> Self {
-        BambuDiscover { config: cfgs.into() }
+        BambuDiscover {
+            config: cfgs.into(),
+            network: NetworkFilter::default(),
+            cache: None,
+            known_certificates: None,
+            supervisor: Supervisor::default(),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Only discover printers on the network interfaces matching `network`.
+    pub fn with_network_filter(mut self, network: NetworkFilter) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Remember discovered printers in `cache`, so they can be registered
+    /// straight away next time with [BambuDiscover::register_cached].
+    pub fn with_cache(mut self, cache: DiscoveryCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Remember the certificates of printers configured with
+    /// `trust_on_first_use` in `known_certificates`.
+    pub fn with_known_certificates(mut self, known_certificates: KnownCertificates) -> Self {
+        self.known_certificates = Some(known_certificates);
+        self
+    }
+
+    /// Run each printer's connection under `supervisor`, so it's restarted
+    /// if it fails.
+    pub fn with_supervisor(mut self, supervisor: Supervisor) -> Self {
+        self.supervisor = supervisor;
+        self
     }
 
     fn config_for_name(&self, name: &str) -> Option<(String, Config)> {
         self.config
             .iter()
-            .find(|(_, config)| config.name == name)
+            .find(|(_, config)| !config.is_static() && config.name == name)
             .map(|(k, v)| (k.clone(), v.clone()))
     }
-}
-
-impl DiscoverTrait for BambuDiscover {
-    type Error = anyhow::Error;
 
-    async fn discover(
+    /// Register all printers configured with a static IP address, without
+    /// waiting on any discovery traffic. A printer which can't be registered
+    /// (such as one missing its serial number) is logged and skipped, so it
+    /// doesn't keep the rest from being registered.
+    pub async fn register_static(
         &self,
         channel: tokio::sync::mpsc::Sender<String>,
         printers: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
     ) -> Result<()> {
-        if self.config.is_empty() {
-            tracing::debug!("no bambu devices configured, shutting down bambu scans");
-            return Ok(());
+        for (machine_api_id, config) in self.config.iter() {
+            let Some(ip) = config.ip else {
+                continue;
+            };
+            let Some(serial) = &config.serial else {
+                tracing::error!(
+                    id = machine_api_id,
+                    ip = ip.to_string(),
+                    "skipping static bambu printer, which is missing a serial number"
+                );
+                continue;
+            };
+
+            tracing::info!(
+                id = machine_api_id,
+                ip = ip.to_string(),
+                "registering static bambu printer"
+            );
+            let machine = match self
+                .create_machine(machine_api_id, config, config.name.clone(), ip, serial, None, None)
+                .await
+            {
+                Ok(machine) => machine,
+                Err(e) => {
+                    tracing::error!(
+                        id = machine_api_id,
+                        ip = ip.to_string(),
+                        serial = serial,
+                        error = format!("{:?}", e),
+                        "failed to register static bambu printer, skipping it"
+                    );
+                    continue;
+                }
+            };
+            printers
+                .write()
+                .await
+                .insert(machine_api_id.clone(), RwLock::new(machine));
+            channel.send(machine_api_id.clone()).await?;
         }
 
-        tracing::info!("Spawning Bambu discovery task");
+        Ok(())
+    }
 
-        // Any interface, port 2021, which is a non-standard port for any kind of UPnP/SSDP protocol.
-        // Incredible.
-        let any = (Ipv4Addr::new(0, 0, 0, 0), 2021);
-        let socket = UdpSocket::bind(any).await?;
+    /// Register all printers found by a previous run, at the address they
+    /// were last seen at, without waiting on any discovery traffic. They
+    /// show up as offline until they answer, and are moved if they later
+    /// announce themselves from somewhere else.
+    pub async fn register_cached(
+        &self,
+        channel: tokio::sync::mpsc::Sender<String>,
+        printers: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+    ) -> Result<()> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
 
-        let mut socket_buf = [0u8; 1536];
+        for (machine_api_id, cached) in cache.load().await {
+            let Some(config) = self.config.get(&machine_api_id) else {
+                continue;
+            };
+            if config.is_static() || config.name != cached.name {
+                continue;
+            }
+            if printers.read().await.contains_key(&machine_api_id) {
+                continue;
+            }
 
-        while let Ok(n) = socket.recv(&mut socket_buf).await {
-            // The SSDP/UPnP frames we're looking for from Bambu printers are pure ASCII, so we don't
-            // mind if we end up with garbage in the resulting string. Note that other SSDP packets from
-            // e.g. macOS Bonjour(?) do contain binary data which means this conversion isn't suitable
-            // for them.
-            let udp_payload = String::from_utf8_lossy(&socket_buf[0..n]);
+            tracing::info!(
+                id = machine_api_id,
+                ip = cached.ip.to_string(),
+                "registering cached bambu printer"
+            );
+            let machine = self
+                .create_machine(
+                    &machine_api_id,
+                    config,
+                    cached.name,
+                    cached.ip,
+                    &cached.serial,
+                    None,
+                    None,
+                )
+                .await?;
+            printers
+                .write()
+                .await
+                .insert(machine_api_id.clone(), RwLock::new(machine));
+            channel.send(machine_api_id).await?;
+        }
 
-            // Iterate through all non-blank lines in the payload
-            let mut lines = udp_payload.lines().filter_map(|l| {
-                let l = l.trim();
+        Ok(())
+    }
 
-                if l.is_empty() {
-                    None
-                } else {
-                    Some(l)
-                }
-            });
+    /// Return true if every printer that needs discovering has been found.
+    async fn all_discovered(&self, printers: &RwLock<HashMap<String, RwLock<Machine>>>) -> bool {
+        let printers = printers.read().await;
+        self.config
+            .iter()
+            .filter(|(_, config)| !config.is_static())
+            .all(|(machine_api_id, _)| printers.contains_key(machine_api_id))
+    }
+
+    /// Ask any printers out there to announce themselves, rather than
+    /// waiting for their next NOTIFY. Responses are sent back to the port
+    /// the probe came from.
+    ///
+    /// If discovery is restricted to some networks, the probes are only
+    /// broadcast on those networks. Probes are also sent straight to each
+    /// of `unicast`, for printers broadcasts don't reach.
+    async fn probe(socket: &UdpSocket, networks: Option<&[Ipv4Net]>, unicast: &[IpAddr]) {
+        let mut targets: Vec<(IpAddr, u16)> = match networks {
+            Some(networks) => networks
+                .iter()
+                .flat_map(|network| {
+                    [
+                        (network.broadcast().into(), BAMBU_SSDP_PORT),
+                        (network.broadcast().into(), BAMBU_NOTIFY_PORT),
+                    ]
+                })
+                .collect(),
+            None => vec![
+                (SSDP_MULTICAST_ADDR.into(), BAMBU_SSDP_PORT),
+                (Ipv4Addr::BROADCAST.into(), BAMBU_NOTIFY_PORT),
+            ],
+        };
+        // The socket is IPv4, as is SSDP on Bambu printers.
+        targets.extend(
+            unicast
+                .iter()
+                .filter(|ip| ip.is_ipv4())
+                .map(|ip| (*ip, BAMBU_SSDP_PORT)),
+        );
+        for (addr, port) in targets {
+            let msg = m_search(&format!("{}:{}", addr, port));
+            if let Err(e) = socket.send_to(msg.as_bytes(), (addr, port)).await {
+                tracing::debug!(
+                    error = format!("{:?}", e),
+                    "failed to send bambu M-SEARCH to {}:{}",
+                    addr,
+                    port
+                );
+            }
+        }
+    }
 
-            // First line is a different format to the rest. We also need to check this for the message
-            // type the Bambu printer emits, which is "NOTIFY * HTTP/1.1"
-            let Some(header) = lines.next() else {
-                tracing::debug!("Bad UPnP");
+    /// Handle an SSDP payload, registering the printer it came from if it's
+    /// one we're configured for and haven't seen yet.
+    async fn handle_payload(
+        &self,
+        payload: &str,
+        channel: &tokio::sync::mpsc::Sender<String>,
+        printers: &RwLock<HashMap<String, RwLock<Machine>>>,
+    ) {
+        let Some(Announcement {
+            urn,
+            name,
+            ip,
+            serial,
+            model,
+        }) = parse_announcement(payload)
+        else {
+            return;
+        };
+
+        let Some(ip) = ip else {
+            tracing::warn!("No IP address present for printer name {:?} (URN {:?})", name, urn);
+            return;
+        };
+
+        // A little extra validation: check the URN is a Bambu printer. This is currently only
+        // tested against the Bambu Lab X1 Carbon with AMS.
+        if urn.as_deref() != Some(BAMBU_URN) {
+            tracing::warn!(
+                "Printer doesn't appear to be a Bambu labs: URN {:?} does not match {}",
+                urn,
+                BAMBU_URN
+            );
+            return;
+        }
 
-                continue;
+        let Some(name) = name else {
+            tracing::warn!("No name found for printer at {}", ip);
+            return;
+        };
+
+        let Some((machine_api_id, config)) = self.config_for_name(&name) else {
+            tracing::warn!("No config found for printer at {}", ip);
+            return;
+        };
+
+        if let Some(machine) = printers.read().await.get(&machine_api_id) {
+            // If the machine is locked, it's busy (such as with a print),
+            // and so clearly reachable where it is.
+            let known_ip = match machine.try_read() {
+                Ok(machine) => match machine.get_machine() {
+                    AnyMachine::Bambu(bambu) => Some(bambu.info.ip),
+                    _ => None,
+                },
+                Err(_) => Some(ip),
             };
+            if known_ip == Some(ip) {
+                tracing::debug!("Printer already discovered, skipping");
+                return;
+            }
+            tracing::info!(
+                id = machine_api_id,
+                ip = ip.to_string(),
+                "bambu printer has moved, reconnecting"
+            );
+        }
 
-            // We don't need to parse this properly :)))))
-            if header != "NOTIFY * HTTP/1.1" {
-                tracing::trace!("Not a notify, ignoring header {:?}", header);
+        // TODO: This is probably the secure MQTT port 8883 but we need to test that assumption
+        let port = None;
 
-                continue;
+        let serial = serial.as_deref().unwrap_or_default();
+        if let Some(cache) = &self.cache {
+            let cached = CachedPrinter {
+                name: name.clone(),
+                ip,
+                serial: serial.to_owned(),
+            };
+            if let Err(e) = cache.insert(&machine_api_id, cached).await {
+                tracing::warn!(error = format!("{:?}", e), "failed to update discovery cache");
             }
+        }
 
-            let mut urn = None;
-            let mut name = None;
-            let mut ip: Option<IpAddr> = None;
-            let mut serial = None;
-            // TODO: This is probably the secure MQTT port 8883 but we need to test that assumption
-            #[allow(unused_mut)]
-            let mut port = None;
+        let machine = match self
+            .create_machine(&machine_api_id, &config, name, ip, serial, port, model.as_deref())
+            .await
+        {
+            Ok(machine) => machine,
+            Err(e) => {
+                tracing::error!(error = format!("{:?}", e), "failed to create bambu machine");
+                return;
+            }
+        };
 
-            for line in lines {
-                let line = line.trim();
+        printers
+            .write()
+            .await
+            .insert(machine_api_id.clone(), RwLock::new(machine));
+        let _ = channel.send(machine_api_id).await;
+    }
 
-                if line.is_empty() {
-                    continue;
+    /// Connect to a printer, and build the [Machine] handle for it. The
+    /// variant is worked out from the serial number, or failing that, the
+    /// model code the printer announced.
+    #[allow(clippy::too_many_arguments)]
+    async fn create_machine(
+        &self,
+        machine_api_id: &str,
+        config: &Config,
+        name: String,
+        ip: IpAddr,
+        serial: &str,
+        port: Option<u16>,
+        model_code: Option<&str>,
+    ) -> Result<Machine> {
+        // Add a mqtt client for this printer.
+        let mut client =
+            bambulabs::client::Client::new(ip.to_string(), config.access_code.to_string(), serial.to_string())?
+                .with_machine_id(machine_api_id)
+                .with_status_interval(Duration::from_millis(config.status_interval_ms));
+        if config.debug_mqtt {
+            client = client.with_mqtt_debug(MQTT_DEBUG_PAYLOADS);
+        }
+        if let Some(fingerprint) = &config.certificate_fingerprint {
+            client = client.with_certificate_fingerprint(fingerprint)?;
+        } else if config.trust_on_first_use {
+            let Some(known) = self.known_certificates.clone() else {
+                anyhow::bail!("trust_on_first_use needs somewhere to keep known certificates");
+            };
+            client = match known.get(serial).await? {
+                Some(fingerprint) => client.with_certificate_fingerprint(&fingerprint)?,
+                None => {
+                    let serial = serial.to_owned();
+                    client.with_trust_on_first_use(move |fingerprint| {
+                        let known = known.clone();
+                        let serial = serial.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = known.insert(&serial, &fingerprint).await {
+                                tracing::error!(
+                                    serial = serial,
+                                    error = format!("{:?}", e),
+                                    "failed to remember printer certificate"
+                                );
+                            }
+                        });
+                    })?
                 }
+            };
+        }
+        let cloned_client = client.clone();
+        let span = tracing::info_span!("bambu_mqtt", machine_id = machine_api_id);
+        let connection = self.supervisor.spawn("bambu_mqtt", Some(machine_api_id), move || {
+            let mut client = cloned_client.clone();
+            async move { client.run().await }.instrument(span.clone())
+        });
+        // Stop the connection to where the printer used to be, if it's moved.
+        if let Some(old) = self
+            .connections
+            .lock()
+            .unwrap()
+            .insert(machine_api_id.to_owned(), connection)
+        {
+            old.abort();
+        }
 
-                let Some((token, rest)) = line.split_once(':') else {
-                    tracing::debug!("Bad token line {}", line);
+        let variant =
+            BambuVariant::get_from_sn(serial).or_else(|| model_code.and_then(BambuVariant::get_from_model_code));
+        let model = if let Some(variant) = variant {
+            variant.to_string()
+        } else {
+            tracing::error!("Failed to get status for printer `{}` at {}", serial, ip);
+            // Default to X1 Carbon
+            "X1C".to_string()
+        };
+
+        // At this point, we have a valid (as long as the parsing above is strict enough lmao)
+        // collection of data that represents a Bambu printer.
+        let info = PrinterInfo {
+            hostname: Some(name),
+            ip,
+            port,
+            variant,
+            make_model: MachineMakeModel {
+                manufacturer: Some("Bambu Lab".to_owned()),
+                model: Some(model),
+                serial: Some(serial.to_string()),
+            },
+        };
+
+        let slicer = config.slicer.load()?;
+
+        Ok(Machine::new(
+            Bambu {
+                info,
+                client: Arc::new(client),
+                print_options: config.print_options,
+            },
+            slicer,
+        ))
+    }
+}
 
-                    continue;
-                };
+impl DiscoverTrait for BambuDiscover {
+    type Error = anyhow::Error;
 
-                let token = token.trim();
-                let rest = rest.trim();
+    async fn discover(
+        &self,
+        channel: tokio::sync::mpsc::Sender<String>,
+        printers: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+    ) -> Result<()> {
+        if self.config.values().all(|config| config.is_static()) {
+            tracing::debug!("no bambu devices need discovery, shutting down bambu scans");
+            return Ok(());
+        }
 
-                tracing::trace!("----> Token {}: {}", token, rest);
+        tracing::info!("Spawning Bambu discovery task");
 
-                match token {
-                    "Location" => ip = Some(rest.parse().expect("Bad IP")),
-                    "DevName.bambu.com" => name = Some(rest.to_owned()),
-                    "USN" => serial = Some(rest.to_owned()),
-                    "NT" => urn = Some(rest.to_owned()),
-                    // Ignore everything else
-                    _ => (),
-                }
+        let probe_targets = self.network.probe_targets()?;
+        let networks = if self.network.allows_all() {
+            None
+        } else {
+            let networks = self.network.local_networks()?;
+            if networks.is_empty() && probe_targets.is_empty() {
+                tracing::warn!("no network interfaces match the discovery config, shutting down bambu scans");
+                return Ok(());
             }
-
-            let Some(ip) = ip else {
-                tracing::warn!("No IP address present for printer name {:?} (URN {:?})", name, urn);
-
-                continue;
-            };
-
-            // A little extra validation: check the URN is a Bambu printer. This is currently only
-            // tested against the Bambu Lab X1 Carbon with AMS.
-            if urn != Some(BAMBU_URN.to_string()) {
+            Some(networks)
+        };
+        let ipv4_networks: Option<Vec<Ipv4Net>> = networks.as_ref().map(|networks| {
+            networks
+                .iter()
+                .filter_map(|network| match network {
+                    IpNet::V4(network) => Some(*network),
+                    IpNet::V6(_) => None,
+                })
+                .collect()
+        });
+
+        let notify_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, BAMBU_NOTIFY_PORT)).await?;
+        notify_socket.set_broadcast(true)?;
+
+        // Not all firmware uses this port, and something else on this host
+        // (such as Bambu Studio) may already have it, so carry on without
+        // it if need be.
+        let ssdp_socket = match bind_ssdp_socket(ipv4_networks.as_deref()).await {
+            Ok(socket) => Some(socket),
+            Err(e) => {
                 tracing::warn!(
-                    "Printer doesn't appear to be a Bambu labs: URN {:?} does not match {}",
-                    urn,
-                    BAMBU_URN
+                    error = format!("{:?}", e),
+                    "failed to listen on port {}, only listening on {}",
+                    BAMBU_SSDP_PORT,
+                    BAMBU_NOTIFY_PORT
                 );
-
-                continue;
+                None
             }
+        };
 
-            let Some(name) = name else {
-                tracing::warn!("No name found for printer at {}", ip);
-                continue;
-            };
+        let mut probe_interval = tokio::time::interval(PROBE_INTERVAL);
+        let mut notify_buf = [0u8; 1536];
+        let mut ssdp_buf = [0u8; 1536];
 
-            let Some((machine_api_id, config)) = self.config_for_name(&name) else {
-                tracing::warn!("No config found for printer at {}", ip);
-                continue;
+        loop {
+            // The SSDP/UPnP frames we're looking for from Bambu printers are pure ASCII, so we don't
+            // mind if we end up with garbage in the resulting string. Note that other SSDP packets from
+            // e.g. macOS Bonjour(?) do contain binary data which means this conversion isn't suitable
+            // for them.
+            let (payload, source) = tokio::select! {
+                _ = probe_interval.tick() => {
+                    if !self.all_discovered(&printers).await {
+                        Self::probe(&notify_socket, ipv4_networks.as_deref(), &probe_targets).await;
+                    }
+                    continue;
+                }
+                received = notify_socket.recv_from(&mut notify_buf) => {
+                    let Ok((n, source)) = received else { break };
+                    (String::from_utf8_lossy(&notify_buf[0..n]).into_owned(), source)
+                }
+                received = recv_from_optional(ssdp_socket.as_ref(), &mut ssdp_buf) => {
+                    let Ok((n, source)) = received else { break };
+                    (String::from_utf8_lossy(&ssdp_buf[0..n]).into_owned(), source)
+                }
             };
 
-            if printers.read().await.contains_key(&machine_api_id) {
-                tracing::debug!("Printer already discovered, skipping");
-                continue;
+            if let Some(networks) = &networks {
+                if !is_on_networks(networks, &source.ip()) && !probe_targets.contains(&source.ip()) {
+                    tracing::trace!("Ignoring SSDP from {}, which is outside the discovery networks", source);
+                    continue;
+                }
             }
 
-            // Add a mqtt client for this printer.
-            let serial = serial.as_deref().unwrap_or_default();
-
-            let client =
-                bambulabs::client::Client::new(ip.to_string(), config.access_code.to_string(), serial.to_string())?;
-            let mut cloned_client = client.clone();
-            tokio::spawn(async move {
-                cloned_client.run().await.unwrap();
-            });
-
-            // Get the status so we can get the model.
-            let model = if let Some(variant) = BambuVariant::get_from_sn(serial) {
-                variant.to_string()
-            } else {
-                tracing::error!("Failed to get status for printer `{}` at {}", serial, ip);
-                // Default to X1 Carbon
-                "X1C".to_string()
-            };
+            self.handle_payload(&payload, &channel, &printers).await;
+        }
 
-            // At this point, we have a valid (as long as the parsing above is strict enough lmao)
-            // collection of data that represents a Bambu printer.
-            let info = PrinterInfo {
-                hostname: Some(name),
-                ip,
-                port,
-                make_model: MachineMakeModel {
-                    manufacturer: Some("Bambu Lab".to_owned()),
-                    model: Some(model),
-                    serial: Some(serial.to_string()),
-                },
-            };
+        Ok(())
+    }
+}
 
-            let Ok(slicer) = config.slicer.load() else {
-                tracing::error!("slicer failed to load");
-                continue;
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_notify() {
+        let payload = "NOTIFY * HTTP/1.1\r\n\
+                       HOST: 239.255.255.250:1990\r\n\
+                       Server: UPnP/1.0\r\n\
+                       Location: 192.168.1.55\r\n\
+                       NT: urn:bambulab-com:device:3dprinter:1\r\n\
+                       USN: 00M09A350100123\r\n\
+                       DevModel.bambu.com: BL-P001\r\n\
+                       DevName.bambu.com: workshop-x1c\r\n\r\n";
+
+        assert_eq!(
+            parse_announcement(payload),
+            Some(Announcement {
+                urn: Some(BAMBU_URN.to_owned()),
+                name: Some("workshop-x1c".to_owned()),
+                ip: Some("192.168.1.55".parse().unwrap()),
+                serial: Some("00M09A350100123".to_owned()),
+                model: Some("BL-P001".to_owned()),
+            })
+        );
+    }
 
-            printers.write().await.insert(
-                machine_api_id.clone(),
-                RwLock::new(Machine::new(
-                    Bambu {
-                        info,
-                        client: Arc::new(client),
-                    },
-                    slicer,
-                )),
-            );
-            let _ = channel.send(machine_api_id).await;
-        }
+    #[test]
+    fn test_parse_search_response() {
+        let payload = "HTTP/1.1 200 OK\r\n\
+                       CACHE-CONTROL: max-age=1800\r\n\
+                       LOCATION: http://192.168.1.56/\r\n\
+                       ST: urn:bambulab-com:device:3dprinter:1\r\n\
+                       USN: 01P00A400100456\r\n\
+                       DEVNAME.BAMBU.COM: office-p1s\r\n\r\n";
+
+        assert_eq!(
+            parse_announcement(payload),
+            Some(Announcement {
+                urn: Some(BAMBU_URN.to_owned()),
+                name: Some("office-p1s".to_owned()),
+                ip: Some("192.168.1.56".parse().unwrap()),
+                serial: Some("01P00A400100456".to_owned()),
+                model: None,
+            })
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn test_variant() {
+        assert_eq!(BambuVariant::get_from_sn("03W09A350100123"), Some(BambuVariant::X1E));
+        assert_eq!(BambuVariant::get_from_sn("09400A350100123"), Some(BambuVariant::H2D));
+        assert_eq!(BambuVariant::get_from_sn("ZZZ"), None);
+        assert_eq!(BambuVariant::get_from_model_code("C13"), Some(BambuVariant::X1E));
+        assert_eq!(BambuVariant::get_from_model_code("O1D"), Some(BambuVariant::H2D));
+        assert_eq!(BambuVariant::get_from_model_code("nope"), None);
+
+        assert!(BambuVariant::X1E.has_chamber_sensor());
+        assert!(!BambuVariant::P1S.has_chamber_sensor());
+        assert_eq!(BambuVariant::H2D.camera(), BambuCamera::Rtsps);
+        assert_eq!(BambuVariant::A1Mini.camera().port(), 6000);
+        assert_eq!(BambuVariant::A1Mini.build_volume().width, 180.0);
+    }
+
+    #[test]
+    fn test_parse_ignores_queries() {
+        assert_eq!(parse_announcement(&m_search("239.255.255.250:1990")), None);
+        assert_eq!(parse_announcement(""), None);
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(parse_location("10.0.0.2"), Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(
+            parse_location("http://10.0.0.2:8080/desc.xml"),
+            Some("10.0.0.2".parse().unwrap())
+        );
+        assert_eq!(parse_location("not an ip"), None);
     }
 }
@@ -1,21 +1,51 @@
 //! This module contains support for printing to Bambu Lab 3D printers.
 
+mod accessories;
+mod ams;
+mod cache;
+mod camera;
 mod control;
 mod discover;
+mod pause;
+mod plate;
+mod ready;
 mod temperature;
+mod trust;
 
 use std::{net::IpAddr, sync::Arc};
 
+pub use ams::{humidity_warnings, AmsUnit, HumidityLimits, HumidityWarning};
 use bambulabs::client::Client;
-pub use discover::{BambuDiscover, BambuVariant, Config};
+pub use cache::{CachedPrinter, DiscoveryCache};
+pub use discover::{BambuCamera, BambuDiscover, BambuVariant, Config};
+pub use plate::{PlateMismatch, SlicedPlate};
+pub use ready::SdCardFull;
+pub use trust::KnownCertificates;
 
-use crate::MachineMakeModel;
+use crate::{BambuPrintOptions, MachineMakeModel};
 
 /// Control channel handle to a Bambu Labs printer.
 #[derive(Clone)]
 pub struct Bambu {
     client: Arc<Client>,
     info: PrinterInfo,
+
+    /// Checks and extras to run along with prints, unless a job says
+    /// otherwise.
+    print_options: BambuPrintOptions,
+}
+
+impl From<BambuPrintOptions> for bambulabs::command::PrintOptions {
+    fn from(options: BambuPrintOptions) -> Self {
+        let defaults = Self::default();
+        Self {
+            bed_leveling: options.bed_leveling.unwrap_or(defaults.bed_leveling),
+            flow_calibration: options.flow_calibration.unwrap_or(defaults.flow_calibration),
+            vibration_calibration: options.vibration_calibration.unwrap_or(defaults.vibration_calibration),
+            timelapse: options.timelapse.unwrap_or(defaults.timelapse),
+            layer_inspect: options.layer_inspect.unwrap_or(defaults.layer_inspect),
+        }
+    }
 }
 
 /// Information regarding a discovered Bambu Labs printer.
@@ -33,4 +63,7 @@ pub struct PrinterInfo {
 
     /// The port of the printer.
     pub port: Option<u16>,
+
+    /// Which model of printer this is, if we could tell.
+    pub variant: Option<BambuVariant>,
 }
@@ -0,0 +1,99 @@
+//! Working out why a Bambu printer paused, from the stage it reports and
+//! its HMS error code.
+
+use bambulabs::message::{PushStatus, Stage};
+
+use crate::PauseReason;
+
+/// Format a Bambu `print_error` as the HMS code Bambu documents it as (such
+/// as `0300_400C`), if it's set.
+fn hms_code(print_error: Option<i64>) -> Option<String> {
+    match print_error {
+        Some(code) if code > 0 => Some(format!("{:04X}_{:04X}", (code >> 16) & 0xffff, code & 0xffff)),
+        _ => None,
+    }
+}
+
+/// Work out why a paused printer paused.
+pub(super) fn pause_reason(status: &PushStatus) -> PauseReason {
+    let code = hms_code(status.print_error);
+    match status.stg_cur {
+        Some(Stage::PrintingWasPausedByTheUser) => PauseReason::User,
+        Some(Stage::M400Pause | Stage::PausedByTheGcodeInsertedByTheUser) => PauseReason::Gcode,
+        Some(Stage::PausedDueToFilamentRunout) => PauseReason::FilamentRunout,
+        Some(
+            stage @ (Stage::PauseOfFrontCoverFalling
+            | Stage::PausedDueToNozzleTemperatureMalfunction
+            | Stage::PausedDueToHeatBedTemperatureMalfunction
+            | Stage::SkipStepPause
+            | Stage::PausedDueToAmsLost
+            | Stage::PausedDueToLowSpeedOfTheHeatBreakFan
+            | Stage::PausedDueToChamberTemperatureControlError
+            | Stage::NozzleFilamentCoveredDetectedPause
+            | Stage::CutterErrorPause
+            | Stage::FirstLayerErrorPause
+            | Stage::NozzleClogPause),
+        ) => PauseReason::MachineError {
+            code,
+            message: Some(stage.to_string().replace('_', " ")),
+        },
+        _ if code.is_some() => PauseReason::MachineError { code, message: None },
+        _ => PauseReason::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(extra: serde_json::Value) -> PushStatus {
+        let mut status = serde_json::json!({
+            "sequence_id": "0",
+            "nozzle_diameter": "0.4",
+            "gcode_state": "PAUSE",
+        });
+        status
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(status).unwrap()
+    }
+
+    #[test]
+    fn test_hms_code() {
+        assert_eq!(hms_code(Some(50348044)), Some("0300_400C".to_owned()));
+        assert_eq!(hms_code(Some(0)), None);
+        assert_eq!(hms_code(None), None);
+    }
+
+    #[test]
+    fn test_pause_reason() {
+        assert_eq!(pause_reason(&status(serde_json::json!({}))), PauseReason::Unknown);
+        assert_eq!(
+            pause_reason(&status(serde_json::json!({"stg_cur": 16}))),
+            PauseReason::User
+        );
+        assert_eq!(
+            pause_reason(&status(serde_json::json!({"stg_cur": 30}))),
+            PauseReason::Gcode
+        );
+        assert_eq!(
+            pause_reason(&status(serde_json::json!({"stg_cur": 6, "print_error": 117473297}))),
+            PauseReason::FilamentRunout
+        );
+        assert_eq!(
+            pause_reason(&status(serde_json::json!({"stg_cur": 35, "print_error": 50348044}))),
+            PauseReason::MachineError {
+                code: Some("0300_400C".to_owned()),
+                message: Some("nozzle clog pause".to_owned()),
+            }
+        );
+        assert_eq!(
+            pause_reason(&status(serde_json::json!({"stg_cur": 0, "print_error": 50348044}))),
+            PauseReason::MachineError {
+                code: Some("0300_400C".to_owned()),
+                message: None,
+            }
+        );
+    }
+}
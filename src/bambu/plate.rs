@@ -0,0 +1,181 @@
+//! Metadata Bambu Studio (and Orca Slicer) embed in the 3MF projects they
+//! slice, naming the printer model and nozzle the plate was sliced for, so
+//! a project sliced for one printer isn't sent to another.
+
+use std::{io::Read, path::Path};
+
+use anyhow::Result;
+
+use super::BambuVariant;
+
+/// Where the slicer records what a project was sliced for.
+const SLICE_INFO: &str = "Metadata/slice_info.config";
+
+/// How far apart two nozzle diameters can be, in millimeters, and still be
+/// the same nozzle.
+const NOZZLE_TOLERANCE: f64 = 0.001;
+
+/// What the plate in a sliced 3MF project was sliced for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SlicedPlate {
+    /// The model code of the printer the plate was sliced for, such as
+    /// `BL-P001` for an X1 Carbon.
+    pub printer_model_id: Option<String>,
+
+    /// The diameter of the nozzle the plate was sliced for, in millimeters.
+    pub nozzle_diameter: Option<f64>,
+}
+
+/// A sliced 3MF project was sliced for a different printer, or nozzle, to
+/// the one it's being sent to.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum PlateMismatch {
+    /// The project was sliced for a different model of printer.
+    #[error("the project was sliced for a {sliced_for} printer, but this is a {printer}")]
+    Model {
+        /// The model the project was sliced for.
+        sliced_for: BambuVariant,
+
+        /// The model of the printer it's being sent to.
+        printer: BambuVariant,
+    },
+
+    /// The project was sliced for a different size of nozzle.
+    #[error("the project was sliced for a {sliced_for}mm nozzle, but this printer has a {printer}mm nozzle")]
+    Nozzle {
+        /// The nozzle diameter the project was sliced for, in millimeters.
+        sliced_for: f64,
+
+        /// The printer's nozzle diameter, in millimeters.
+        printer: f64,
+    },
+}
+
+impl SlicedPlate {
+    /// Read what the 3MF project at `path` was sliced for, if it's been
+    /// sliced (that is, it has a plate's gcode in it) rather than being a
+    /// design still to slice.
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+        let sliced = archive
+            .file_names()
+            .any(|name| name.starts_with("Metadata/plate_") && name.ends_with(".gcode"));
+        if !sliced {
+            return Ok(None);
+        }
+
+        let mut slice_info = String::new();
+        match archive.by_name(SLICE_INFO) {
+            Ok(mut file) => {
+                file.read_to_string(&mut slice_info)?;
+            }
+            Err(zip::result::ZipError::FileNotFound) => return Ok(Some(Self::default())),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Some(Self::parse(&slice_info)))
+    }
+
+    /// Parse the slicer's `slice_info.config`, which records the plate's
+    /// settings as `<metadata key="..." value="..."/>` elements.
+    fn parse(slice_info: &str) -> Self {
+        Self {
+            printer_model_id: metadata_value(slice_info, "printer_model_id")
+                .filter(|id| !id.is_empty())
+                .map(str::to_owned),
+            // Printers with more than one nozzle list each of them.
+            nozzle_diameter: metadata_value(slice_info, "nozzle_diameters")
+                .and_then(|diameters| diameters.split([' ', ',']).next())
+                .and_then(|diameter| diameter.parse().ok()),
+        }
+    }
+
+    /// Check the plate was sliced for a printer of `variant`, with a nozzle
+    /// of `nozzle_diameter` millimeters. What can't be checked (such as a
+    /// model code we don't know, or a printer whose model we couldn't tell)
+    /// is let through with a warning.
+    pub fn check(&self, variant: Option<BambuVariant>, nozzle_diameter: f64) -> Result<(), PlateMismatch> {
+        match (&self.printer_model_id, variant) {
+            (Some(model_id), Some(printer)) => match BambuVariant::get_from_model_code(model_id) {
+                Some(sliced_for) if sliced_for != printer => {
+                    return Err(PlateMismatch::Model { sliced_for, printer });
+                }
+                Some(_) => {}
+                None => tracing::warn!(model_id = model_id, "project was sliced for an unknown printer model"),
+            },
+            (None, _) => tracing::warn!("project doesn't say which printer model it was sliced for"),
+            (_, None) => tracing::warn!("printer model unknown, can't check the project was sliced for it"),
+        }
+
+        if let Some(sliced_for) = self.nozzle_diameter {
+            if (sliced_for - nozzle_diameter).abs() > NOZZLE_TOLERANCE {
+                return Err(PlateMismatch::Nozzle {
+                    sliced_for,
+                    printer: nozzle_diameter,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the value of the first `<metadata>` element with the key `key`.
+fn metadata_value<'a>(xml: &'a str, key: &str) -> Option<&'a str> {
+    let needle = format!("key=\"{}\"", key);
+    let element = &xml[xml.find(&needle)? + needle.len()..];
+    let element = &element[..element.find('>')?];
+    let value = &element[element.find("value=\"")? + "value=\"".len()..];
+    Some(&value[..value.find('"')?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLICE_INFO_X1C: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <header>
+    <header_item key="X-BBL-Client-Type" value="slicer"/>
+  </header>
+  <plate>
+    <metadata key="index" value="1"/>
+    <metadata key="printer_model_id" value="BL-P001"/>
+    <metadata key="nozzle_diameters" value="0.4"/>
+    <metadata key="prediction" value="3600"/>
+  </plate>
+</config>"#;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            SlicedPlate::parse(SLICE_INFO_X1C),
+            SlicedPlate {
+                printer_model_id: Some("BL-P001".to_owned()),
+                nozzle_diameter: Some(0.4),
+            }
+        );
+        assert_eq!(SlicedPlate::parse("<config></config>"), SlicedPlate::default());
+    }
+
+    #[test]
+    fn test_check() {
+        let plate = SlicedPlate::parse(SLICE_INFO_X1C);
+
+        assert_eq!(plate.check(Some(BambuVariant::X1Carbon), 0.4), Ok(()));
+        assert_eq!(
+            plate.check(Some(BambuVariant::P1S), 0.4),
+            Err(PlateMismatch::Model {
+                sliced_for: BambuVariant::X1Carbon,
+                printer: BambuVariant::P1S,
+            })
+        );
+        assert_eq!(
+            plate.check(Some(BambuVariant::X1Carbon), 0.6),
+            Err(PlateMismatch::Nozzle {
+                sliced_for: 0.4,
+                printer: 0.6,
+            })
+        );
+        // Printers whose model we couldn't tell are let through.
+        assert_eq!(plate.check(None, 0.4), Ok(()));
+    }
+}
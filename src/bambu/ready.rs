@@ -0,0 +1,197 @@
+//! Checks that a Bambu printer is ready to start a print, and has room on
+//! its SD card for the print file, run before the file is uploaded.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use bambulabs::message::{GcodeState, PushStatus};
+
+use super::Bambu;
+
+/// How long to wait for a printer which is busy with something short (such
+/// as an AMS filament change, or finishing up a print) to settle.
+const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often to check the printer's status while waiting.
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why a printer isn't ready to start a print.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum NotReady {
+    /// We haven't had a status report from the printer yet.
+    NoStatus,
+
+    /// The printer is preparing, printing or paused.
+    Busy(GcodeState),
+
+    /// The printer has no (usable) SD card to print from.
+    NoSdCard,
+
+    /// The AMS is busy, such as changing filament or reading RFID tags.
+    AmsBusy(i64),
+}
+
+impl NotReady {
+    /// Whether the printer may become ready on its own soon, so it's worth
+    /// waiting for.
+    fn is_transient(&self) -> bool {
+        matches!(self, Self::NoStatus | Self::AmsBusy(_))
+    }
+}
+
+impl std::fmt::Display for NotReady {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoStatus => write!(f, "no status report from the printer"),
+            Self::Busy(state) => write!(f, "printer is busy ({:?})", state),
+            Self::NoSdCard => write!(f, "printer has no SD card inserted"),
+            Self::AmsBusy(status) => write!(f, "AMS is busy (status {:#x})", status),
+        }
+    }
+}
+
+/// There isn't room on the printer's SD card for the print file.
+#[derive(Copy, Clone, Debug, PartialEq, thiserror::Error)]
+#[error("printer's SD card is full: the print file needs {needed_bytes} bytes, but only {free_bytes} are free")]
+pub struct SdCardFull {
+    /// The size of the print file, in bytes.
+    pub needed_bytes: u64,
+
+    /// The space left on the SD card, in bytes.
+    pub free_bytes: u64,
+}
+
+/// What we need to know to start the print, once the printer is ready.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct Ready {
+    /// Whether to print from the AMS.
+    pub use_ams: bool,
+}
+
+/// Check whether the printer described by `status` can start a print.
+pub(super) fn check_ready(status: Option<&PushStatus>) -> Result<Ready, NotReady> {
+    let Some(status) = status else {
+        return Err(NotReady::NoStatus);
+    };
+
+    match status.gcode_state {
+        Some(state @ (GcodeState::Prepare | GcodeState::Running | GcodeState::Pause)) => {
+            return Err(NotReady::Busy(state));
+        }
+        Some(GcodeState::Idle | GcodeState::Finish | GcodeState::Failed) | None => {}
+    }
+
+    if status.sdcard == Some(false) {
+        return Err(NotReady::NoSdCard);
+    }
+
+    let use_ams = status
+        .ams
+        .as_ref()
+        .and_then(|ams| ams.ams_exist_bits.as_deref())
+        .is_some_and(|bits| bits != "0");
+
+    if use_ams {
+        // The high byte is the AMS' main state, where 0 is idle.
+        if let Some(ams_status) = status.ams_status.filter(|ams_status| (ams_status >> 8) & 0xff != 0) {
+            return Err(NotReady::AmsBusy(ams_status));
+        }
+    }
+
+    Ok(Ready { use_ams })
+}
+
+impl Bambu {
+    /// Wait for the printer to be ready to start a print, giving up straight
+    /// away if it's printing or has no SD card, or after a while if it
+    /// doesn't settle.
+    pub(super) async fn wait_until_ready(&self) -> Result<Ready> {
+        let started = std::time::Instant::now();
+        loop {
+            let status = self.client.get_status()?;
+            match check_ready(status.as_ref()) {
+                Ok(ready) => return Ok(ready),
+                Err(not_ready) if not_ready.is_transient() && started.elapsed() < READY_TIMEOUT => {
+                    tracing::debug!(reason = not_ready.to_string(), "waiting for printer to be ready");
+                }
+                Err(not_ready) => anyhow::bail!("Printer is not ready to print: {}", not_ready),
+            }
+            tokio::time::sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Check there's room on the printer's SD card for a file of
+    /// `needed_bytes`, returning an [SdCardFull] error if not. Printers
+    /// which won't say how much room there is are given the benefit of the
+    /// doubt.
+    pub(super) async fn check_free_space(&self, needed_bytes: u64) -> Result<()> {
+        match self.client.free_space().await {
+            Ok(Some(free_bytes)) if free_bytes < needed_bytes => Err(SdCardFull {
+                needed_bytes,
+                free_bytes,
+            }
+            .into()),
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => {
+                tracing::debug!("printer didn't say how much room is left on its SD card");
+                Ok(())
+            }
+            Err(e) => {
+                tracing::warn!(error = format!("{:?}", e), "failed to check room left on SD card");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(extra: serde_json::Value) -> PushStatus {
+        let mut status = serde_json::json!({
+            "sequence_id": "0",
+            "nozzle_diameter": "0.4",
+            "gcode_state": "IDLE",
+            "sdcard": true,
+        });
+        status
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(status).unwrap()
+    }
+
+    #[test]
+    fn test_check_ready() {
+        assert_eq!(check_ready(None), Err(NotReady::NoStatus));
+        assert_eq!(
+            check_ready(Some(&status(serde_json::json!({})))),
+            Ok(Ready { use_ams: false })
+        );
+        assert_eq!(
+            check_ready(Some(&status(serde_json::json!({"gcode_state": "RUNNING"})))),
+            Err(NotReady::Busy(GcodeState::Running))
+        );
+        assert_eq!(
+            check_ready(Some(&status(serde_json::json!({"sdcard": false})))),
+            Err(NotReady::NoSdCard)
+        );
+    }
+
+    #[test]
+    fn test_check_ready_ams() {
+        let ams = serde_json::json!({"ams_exist_bits": "1"});
+
+        assert_eq!(
+            check_ready(Some(&status(serde_json::json!({"ams": ams, "ams_status": 0})))),
+            Ok(Ready { use_ams: true })
+        );
+        assert_eq!(
+            check_ready(Some(&status(serde_json::json!({"ams": ams, "ams_status": 0x0102})))),
+            Err(NotReady::AmsBusy(0x0102))
+        );
+        assert!(NotReady::AmsBusy(0x0102).is_transient());
+        assert!(!NotReady::Busy(GcodeState::Running).is_transient());
+    }
+}
@@ -11,6 +11,8 @@ impl Bambu {
     pub fn get_temperature_sensors(&self) -> TemperatureSensors {
         TemperatureSensors {
             client: self.client.clone(),
+            // Assume there's a chamber sensor if we can't tell.
+            has_chamber: self.info.variant.is_none_or(|variant| variant.has_chamber_sensor()),
         }
     }
 }
@@ -19,17 +21,21 @@ impl Bambu {
 #[derive(Clone)]
 pub struct TemperatureSensors {
     client: Arc<bambulabs::client::Client>,
+    has_chamber: bool,
 }
 
 impl TemperatureSensorsTrait for TemperatureSensors {
     type Error = anyhow::Error;
 
     async fn sensors(&self) -> Result<HashMap<String, TemperatureSensor>> {
-        Ok(HashMap::from([
+        let mut sensors = HashMap::from([
             ("extruder".to_owned(), TemperatureSensor::Extruder),
             ("bed".to_owned(), TemperatureSensor::Bed),
-            ("chamber".to_owned(), TemperatureSensor::Chamber),
-        ]))
+        ]);
+        if self.has_chamber {
+            sensors.insert("chamber".to_owned(), TemperatureSensor::Chamber);
+        }
+        Ok(sensors)
     }
 
     async fn poll_sensors(&mut self) -> Result<HashMap<String, TemperatureSensorReading>> {
@@ -53,13 +59,15 @@ impl TemperatureSensorsTrait for TemperatureSensors {
             },
         );
 
-        sensor_readings.insert(
-            "chamber".to_owned(),
-            TemperatureSensorReading {
-                temperature_celsius: status.chamber_temper.unwrap_or(0.0),
-                target_temperature_celsius: None,
-            },
-        );
+        if self.has_chamber {
+            sensor_readings.insert(
+                "chamber".to_owned(),
+                TemperatureSensorReading {
+                    temperature_celsius: status.chamber_temper.unwrap_or(0.0),
+                    target_temperature_celsius: None,
+                },
+            );
+        }
 
         Ok(sensor_readings)
     }
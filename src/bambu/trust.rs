@@ -0,0 +1,82 @@
+//! On-disk store of Bambu printers' certificate fingerprints, trusted on
+//! first use, so a printer presenting a different certificate later (such
+//! as someone in the middle) is refused.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use tokio::sync::Mutex;
+
+/// Certificate fingerprints, stored as JSON keyed by printer serial number.
+#[derive(Clone, Debug)]
+pub struct KnownCertificates {
+    path: PathBuf,
+
+    /// Held while updating the store, since several printers may be
+    /// trusted at once.
+    lock: Arc<Mutex<()>>,
+}
+
+impl KnownCertificates {
+    /// Create a new store, kept at `path`.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Return the fingerprint trusted for the printer with serial number
+    /// `serial`, if there is one.
+    pub async fn get(&self, serial: &str) -> Result<Option<String>> {
+        Ok(self.load().await?.remove(serial))
+    }
+
+    /// Trust `fingerprint` for the printer with serial number `serial`.
+    pub async fn insert(&self, serial: &str, fingerprint: &str) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        let mut known = self.load().await?;
+        known.insert(serial.to_owned(), fingerprint.to_owned());
+
+        // Write to the side and rename, so a crash mid-write doesn't lose
+        // every fingerprint.
+        let tmp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(&known)?).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+
+    /// Unlike the discovery cache, a corrupt store is an error rather than
+    /// empty, since treating it as empty would trust whatever comes next.
+    async fn load(&self) -> Result<HashMap<String, String>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4().simple()));
+        let known = KnownCertificates::new(&path);
+        assert_eq!(known.get("00M00A000000000").await.unwrap(), None);
+
+        known.insert("00M00A000000000", "AB:CD").await.unwrap();
+        assert_eq!(known.get("00M00A000000000").await.unwrap().as_deref(), Some("AB:CD"));
+
+        tokio::fs::write(&path, "not json").await.unwrap();
+        assert!(known.get("00M00A000000000").await.is_err());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+//! A small client for a running machine-api server, for the commands which
+//! act on its machines rather than serving them.
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Where the server to talk to is, and the API key to send it.
+#[derive(Clone, Debug, clap::Args)]
+pub struct ServerArgs {
+    /// Base URL of the machine-api server.
+    #[arg(long, env = "MACHINE_API_URL", default_value = "http://127.0.0.1:8080")]
+    pub url: String,
+
+    /// API key to send, if the server requires one.
+    #[arg(long, env = "MACHINE_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+}
+
+/// A request the server refused or failed, as it reported it.
+#[derive(Debug, thiserror::Error)]
+#[error("{message} ({status})")]
+pub struct ServerError {
+    /// The response's HTTP status.
+    pub status: reqwest::StatusCode,
+
+    /// The server's name for what went wrong, such as `MachineBusy`.
+    pub error_code: Option<String>,
+
+    /// What went wrong, for people to read.
+    pub message: String,
+}
+
+/// The body of an error response.
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error_code: Option<String>,
+    message: String,
+}
+
+/// Handle to make requests to the server.
+pub struct Client {
+    url: reqwest::Url,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Client {
+    /// Create a new [Client] for the server `server` points to.
+    pub fn new(server: &ServerArgs) -> Result<Self> {
+        let url = reqwest::Url::parse(&server.url).with_context(|| format!("Invalid server URL {}", server.url))?;
+        if url.cannot_be_a_base() {
+            anyhow::bail!("Invalid server URL {}", server.url);
+        }
+        Ok(Self {
+            url,
+            api_key: server.api_key.clone(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Get the path made of `segments` (such as `["v1", "machines"]`),
+    /// returning its JSON body.
+    pub async fn get<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T> {
+        self.send(self.client.get(self.url(segments))).await
+    }
+
+    /// Post `form` to the path made of `segments`, returning the JSON body
+    /// of the response.
+    pub async fn post_multipart<T: DeserializeOwned>(
+        &self,
+        segments: &[&str],
+        form: reqwest::multipart::Form,
+    ) -> Result<T> {
+        self.send(self.client.post(self.url(segments)).multipart(form)).await
+    }
+
+    /// Return the URL of the path made of `segments`, each of which is
+    /// escaped, so a machine's display name can be one.
+    fn url(&self, segments: &[&str]) -> reqwest::Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in Client::new")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T> {
+        let request = match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        };
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach the server at {}", self.url))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let (error_code, message) = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(error) => (error.error_code, error.message),
+            Err(_) => (None, body),
+        };
+        Err(ServerError {
+            status,
+            error_code,
+            message,
+        }
+        .into())
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use super::{config::migrate, Cli, ExitStatus, Output, WithExitStatus};
+
+/// Upgrade the config file to the current version of the schema, printing
+/// it, or with `write`, replacing the file (keeping the original alongside
+/// it, as a `.bak`).
+pub fn migrate(cli: &Cli, write: bool) -> Result<()> {
+    let contents = std::fs::read_to_string(&cli.config)
+        .map_err(|_| anyhow::anyhow!("Config file not found at {}", &cli.config))
+        .exit_status(ExitStatus::Config)?;
+    let migrated = migrate::migrate(&contents).exit_status(ExitStatus::Config)?;
+    let upgraded = migrated.to_toml()?;
+
+    let backup = format!("{}.bak", &cli.config);
+    if write {
+        std::fs::copy(&cli.config, &backup)?;
+        std::fs::write(&cli.config, &upgraded)?;
+    }
+
+    if cli.output == Output::Json {
+        let report = MigrateReport {
+            from_version: migrated.from_version,
+            to_version: migrate::SCHEMA_VERSION,
+            notes: &migrated.notes,
+            unmapped: migrated
+                .unmapped
+                .iter()
+                .map(|(path, value)| UnmappedField {
+                    path,
+                    value: value.to_string(),
+                })
+                .collect(),
+            written_to: write.then_some(cli.config.as_str()),
+            config: (!write).then_some(upgraded.as_str()),
+        };
+        println!("{}", serde_json::to_string(&report)?);
+        return Ok(());
+    }
+
+    if !write {
+        print!("{}", upgraded);
+        return Ok(());
+    }
+
+    eprintln!(
+        "Migrated {} from schema version {} to {}, keeping the original as {}",
+        &cli.config,
+        migrated.from_version,
+        migrate::SCHEMA_VERSION,
+        backup
+    );
+    for (path, _) in &migrated.unmapped {
+        eprintln!("Left out {}, which isn't part of the current schema", path);
+    }
+    Ok(())
+}
+
+/// What `config migrate` did, as printed with `--output json`.
+#[derive(Debug, Serialize)]
+struct MigrateReport<'a> {
+    /// The version of the schema the file was written for.
+    from_version: u32,
+
+    /// The version of the schema the file was upgraded to.
+    to_version: u32,
+
+    /// Anything the migration changed which should be checked.
+    notes: &'a [String],
+
+    /// Fields which aren't part of the current schema, so were left out.
+    unmapped: Vec<UnmappedField<'a>>,
+
+    /// The file the upgraded config was written to, with `--write`.
+    written_to: Option<&'a str>,
+
+    /// The upgraded config, without `--write`.
+    config: Option<&'a str>,
+}
+
+/// A field left out of the upgraded config.
+#[derive(Debug, Serialize)]
+struct UnmappedField<'a> {
+    /// The field's dotted path.
+    path: &'a str,
+
+    /// The field's value, as TOML.
+    value: String,
+}
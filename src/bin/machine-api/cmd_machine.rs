@@ -0,0 +1,114 @@
+use std::{fmt::Write as _, path::Path};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use super::{
+    client::{Client, ServerArgs, ServerError},
+    exit, Cli, ExitStatus, WithExitStatus,
+};
+
+/// List the machines the server knows about.
+pub async fn list(cli: &Cli, server: &ServerArgs) -> Result<()> {
+    let machines: Vec<Value> = Client::new(server)?.get(&["v1", "machines"]).await?;
+    let text = machines.iter().map(describe).collect::<Vec<_>>().join("\n");
+    exit::show(cli.output, &machines, text)
+}
+
+/// Show the status of the machine `machine` (its ID, or display name).
+pub async fn status(cli: &Cli, server: &ServerArgs, machine: &str) -> Result<()> {
+    let info = get_machine(&Client::new(server)?, machine).await?;
+    exit::show(cli.output, &info, describe(&info))
+}
+
+/// Slice `file` and print it on the machine `machine` (its ID, or display
+/// name). Machines which aren't idle are refused, rather than the job
+/// waiting for them.
+pub async fn print(cli: &Cli, server: &ServerArgs, machine: &str, file: &Path, job_name: Option<&str>) -> Result<()> {
+    let client = Client::new(server)?;
+
+    let info = get_machine(&client, machine).await?;
+    if state(&info) != "idle" {
+        return Err(anyhow::anyhow!("Machine {} isn't idle: {}", id(&info), state(&info)))
+            .exit_status(ExitStatus::MachineBusy);
+    }
+
+    let file_name = file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("file.stl")
+        .to_owned();
+    let job_name = job_name.map(str::to_owned).unwrap_or_else(|| file_name.clone());
+    let content = std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let params = serde_json::json!({
+        // Sent by ID, so the job goes to the machine which was checked.
+        "machine_id": info["id"],
+        "job_name": job_name,
+    });
+    let form = reqwest::multipart::Form::new()
+        .part("file", reqwest::multipart::Part::bytes(content).file_name(file_name))
+        .part(
+            "params",
+            reqwest::multipart::Part::text(params.to_string()).mime_str("application/json")?,
+        );
+
+    let response: Value = client
+        .post_multipart(&["v1", "print"], form)
+        .await
+        .map_err(|e| with_exit_status(e, machine))?;
+    exit::show(
+        cli.output,
+        &response,
+        format!(
+            "Started job {} on {}",
+            response["job_id"].as_str().unwrap_or_default(),
+            id(&info)
+        ),
+    )
+}
+
+/// Get the machine `machine` (its ID, or display name).
+async fn get_machine(client: &Client, machine: &str) -> Result<Value> {
+    client
+        .get(&["v1", "machines", machine])
+        .await
+        .map_err(|e| with_exit_status(e, machine))
+}
+
+/// Tag a failed request about `machine` with the exit status saying why it
+/// failed, if it has its own.
+fn with_exit_status(e: anyhow::Error, machine: &str) -> anyhow::Error {
+    let Some(error) = e.downcast_ref::<ServerError>() else {
+        return e;
+    };
+    let status = match error.error_code.as_deref() {
+        _ if error.status == reqwest::StatusCode::NOT_FOUND => ExitStatus::MachineNotFound,
+        Some("MachineBusy" | "MachineReserved") => ExitStatus::MachineBusy,
+        Some("SliceFailed") => ExitStatus::SliceFailed,
+        _ => return e,
+    };
+    status.tag(e.context(format!("Failed to act on machine {:?}", machine)))
+}
+
+/// Describe a machine in a line, for people to read.
+fn describe(machine: &Value) -> String {
+    let mut line = id(machine).to_owned();
+    if let Some(display_name) = machine["display_name"].as_str() {
+        let _ = write!(line, " ({})", display_name);
+    }
+    let _ = write!(line, ": {}", state(machine));
+    if let Some(location) = machine["location"].as_str() {
+        let _ = write!(line, ", at {}", location);
+    }
+    line
+}
+
+/// Return a machine's ID.
+fn id(machine: &Value) -> &str {
+    machine["id"].as_str().unwrap_or_default()
+}
+
+/// Return a machine's state, such as `idle`.
+fn state(machine: &Value) -> &str {
+    machine["state"]["state"].as_str().unwrap_or("unknown")
+}
@@ -1,29 +1,55 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     sync::{atomic::AtomicU64, Arc},
 };
 
 use anyhow::Result;
-use machine_api::{server, AnyMachine, TemperatureSensors};
+use machine_api::{bambu, server, set_spool_dir, slicer, AnyMachine, Supervisor, TemperatureSensors};
 use prometheus_client::{
     metrics::gauge::Gauge,
     registry::{Registry, Unit},
 };
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, task::JoinHandle};
 
-use super::{Cli, Config};
+use super::{Cli, Config, ExitStatus, WithExitStatus};
+
+/// Gauges already registered for a machine, by metric name, so they're
+/// reused rather than registered again if the machine is found again (such
+/// as a Bambu printer which has moved to a new address).
+type Gauges = Arc<std::sync::Mutex<HashMap<String, Gauge<f64, AtomicU64>>>>;
+
+/// Return the gauge `name` from `gauges`, registering it in `registry` if
+/// it's new.
+fn gauge(gauges: &Gauges, registry: &mut Registry, name: &str, help: String, unit: Unit) -> Gauge<f64, AtomicU64> {
+    gauges
+        .lock()
+        .unwrap()
+        .entry(name.to_owned())
+        .or_insert_with(|| {
+            let gauge = Gauge::<f64, AtomicU64>::default();
+            registry.register_with_unit(name, help, unit, gauge.clone());
+            gauge
+        })
+        .clone()
+}
 
 /// Long-term this should get a new trait, and a MachineT: Metrics / generic
 /// param on this function.
 ///
 /// For now we can just do this for moonraker (and maybe one or two others)
 /// before we refine the API.
+///
+/// Sensors are polled by `poller`, which limits how often machines of
+/// `machine_type` are polled, until the returned task is stopped.
 async fn spawn_metrics<TemperatureSensorT>(
     registry: Arc<RwLock<Registry>>,
+    gauges: &Gauges,
+    poller: &Arc<server::Poller>,
+    machine_type: &str,
     key: &str,
     machine: TemperatureSensorT,
-) -> Result<(), TemperatureSensorT::Error>
+) -> Result<JoinHandle<()>, TemperatureSensorT::Error>
 where
     TemperatureSensorT: TemperatureSensors,
     TemperatureSensorT: Send,
@@ -31,43 +57,52 @@ where
     TemperatureSensorT::Error: Send,
     TemperatureSensorT::Error: 'static,
 {
+    let machine_sensors = machine.sensors().await?;
+
     let mut registry = registry.write().await;
 
     let registry = registry.sub_registry_with_label(("id".into(), key.to_owned().into()));
 
     let mut sensors = HashMap::new();
 
-    for (sensor_id, sensor_type) in machine.sensors().await? {
+    for (sensor_id, sensor_type) in machine_sensors {
         let sensor_id_target = format!("{}_target", sensor_id);
 
-        sensors.insert(sensor_id.to_owned(), Gauge::<f64, AtomicU64>::default());
-        sensors.insert(sensor_id_target.clone(), Gauge::<f64, AtomicU64>::default());
-
-        registry.register_with_unit(
-            &sensor_id,
-            format!("machine-api sensor {} for {}'s {:?}", sensor_id, key, sensor_type),
-            Unit::Celsius,
-            sensors.get(&sensor_id).unwrap().clone(),
+        sensors.insert(
+            sensor_id.to_owned(),
+            gauge(
+                gauges,
+                registry,
+                &sensor_id,
+                format!("machine-api sensor {} for {}'s {:?}", sensor_id, key, sensor_type),
+                Unit::Celsius,
+            ),
         );
 
-        registry.register_with_unit(
-            &sensor_id_target,
-            format!(
-                "machine-api sensor target {} for {}'s {:?}",
-                sensor_id, key, sensor_type
+        sensors.insert(
+            sensor_id_target.clone(),
+            gauge(
+                gauges,
+                registry,
+                &sensor_id_target,
+                format!(
+                    "machine-api sensor target {} for {}'s {:?}",
+                    sensor_id, key, sensor_type
+                ),
+                Unit::Celsius,
             ),
-            Unit::Celsius,
-            sensors.get(&sensor_id_target).unwrap().clone(),
         );
     }
 
-    let key = key.to_owned();
-    tokio::spawn(async move {
-        let key = key;
-        let mut machine = machine;
-        let mut sensors = sensors;
+    let state = Arc::new(tokio::sync::Mutex::new((machine, sensors)));
+    let machine_id = key.to_owned();
+    Ok(poller.spawn(machine_type, key, move || {
+        let key = machine_id.clone();
+        let state = state.clone();
+        async move {
+            let mut state = state.lock().await;
+            let (machine, sensors) = &mut *state;
 
-        loop {
             let Ok(readings) = machine.poll_sensors().await else {
                 tracing::warn!("failed to collect temperatures from {}", key);
 
@@ -89,7 +124,7 @@ where
                     gauge.set(0.0);
                 }
 
-                continue;
+                return;
             };
             tracing::trace!("metrics collected from {}", key);
 
@@ -104,32 +139,140 @@ where
                     }
                 }
             }
-
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
         }
-    });
+    }))
+}
 
-    Ok(())
+/// How often to check AMS humidity.
+const HUMIDITY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Export AMS temperature and humidity metrics for a Bambu printer, and
+/// emit an event whenever an AMS unit becomes too damp for the material
+/// loaded in it, until the returned task is stopped.
+fn spawn_humidity_monitor(
+    registry: Arc<RwLock<Registry>>,
+    gauges: Gauges,
+    events: Arc<server::Events>,
+    key: &str,
+    machine: bambu::Bambu,
+    limits: bambu::HumidityLimits,
+) -> JoinHandle<()> {
+    let key = key.to_owned();
+    tokio::spawn(async move {
+        let mut unit_gauges: HashMap<String, (Gauge<f64, AtomicU64>, Gauge<f64, AtomicU64>)> = HashMap::new();
+        // Warnings we've already emitted, so we only emit again once the
+        // humidity has dropped back under the limit.
+        let mut warned = HashSet::new();
+
+        loop {
+            tokio::time::sleep(HUMIDITY_POLL_INTERVAL).await;
+
+            let units = match machine.ams_units() {
+                Ok(units) => units,
+                Err(e) => {
+                    tracing::warn!(error = format!("{:?}", e), "failed to collect AMS status from {}", key);
+                    continue;
+                }
+            };
+
+            for unit in units.iter() {
+                if !unit_gauges.contains_key(&unit.id) {
+                    let mut registry = registry.write().await;
+                    let registry = registry.sub_registry_with_label(("id".into(), key.clone().into()));
+                    let temperature = gauge(
+                        &gauges,
+                        registry,
+                        &format!("ams_{}", unit.id),
+                        format!("machine-api sensor ams_{} for {}'s AMS", unit.id, key),
+                        Unit::Celsius,
+                    );
+                    let humidity = gauge(
+                        &gauges,
+                        registry,
+                        &format!("ams_{}_humidity", unit.id),
+                        format!("machine-api relative humidity of {}'s AMS {}", key, unit.id),
+                        Unit::Other("percent".to_owned()),
+                    );
+                    unit_gauges.insert(unit.id.clone(), (temperature, humidity));
+                }
+
+                let (temperature, humidity) = &unit_gauges[&unit.id];
+                if let Some(temperature_celsius) = unit.temperature_celsius {
+                    temperature.set(temperature_celsius);
+                }
+                if let Some(humidity_percent) = unit.humidity_percent {
+                    humidity.set(humidity_percent);
+                }
+            }
+
+            let warnings = bambu::humidity_warnings(&units, &limits);
+            let still_warned = warnings
+                .iter()
+                .map(|warning| (warning.ams_id.clone(), warning.material))
+                .collect::<HashSet<_>>();
+
+            for warning in warnings {
+                if warned.contains(&(warning.ams_id.clone(), warning.material)) {
+                    continue;
+                }
+                events.emit(server::Event::HumidityHigh {
+                    machine_id: key.clone(),
+                    unit_id: warning.ams_id,
+                    material: warning.material,
+                    humidity_percent: warning.humidity_percent,
+                    limit_percent: warning.limit_percent,
+                });
+            }
+
+            warned = still_warned;
+        }
+    })
 }
 
-pub async fn main(_cli: &Cli, cfg: &Config, bind: &str) -> Result<()> {
+pub async fn main(_cli: &Cli, cfg: &Config, bind: &str, read_only: bool, log_levels: server::LogLevels) -> Result<()> {
+    bind.parse::<SocketAddr>()
+        .map_err(|e| anyhow::anyhow!("invalid address to bind to {:?}: {}", bind, e))
+        .exit_status(ExitStatus::Config)?;
+
+    if let Some(spool_dir) = &cfg.spool_dir {
+        tokio::fs::create_dir_all(spool_dir).await?;
+        set_spool_dir(spool_dir.clone())?;
+    }
+
     let machines = Arc::new(RwLock::new(HashMap::new()));
 
     let (found_send, found_recv) = tokio::sync::mpsc::channel::<String>(1);
 
-    cfg.spawn_discover_usb(found_send.clone(), machines.clone()).await?;
-    cfg.spawn_discover_bambu(found_send.clone(), machines.clone()).await?;
+    let registry = Arc::new(RwLock::new(Registry::default()));
+    let events = Arc::new(server::Events::new(cfg.webhooks.clone()));
+    let supervisor = Supervisor::new(&mut *registry.write().await).with_events(events.clone());
+
+    cfg.spawn_discover_usb(found_send.clone(), machines.clone(), &supervisor)
+        .await?;
+    cfg.spawn_discover_bambu(found_send.clone(), machines.clone(), &supervisor)
+        .await?;
     cfg.create_noop(found_send.clone(), machines.clone()).await?;
     cfg.create_moonraker(found_send.clone(), machines.clone()).await?;
+    cfg.spawn_discover_moonraker(found_send.clone(), machines.clone(), &supervisor)
+        .await?;
 
-    let registry = Arc::new(RwLock::new(Registry::default()));
+    cfg.spawn_metrics_push(registry.clone());
+
+    let poller = Arc::new(server::Poller::new(cfg.polling.clone()));
 
     let registry1 = registry.clone();
+    let events1 = events.clone();
     let machines1 = machines.clone();
+    let cfg1 = cfg.clone();
+    let poller1 = poller.clone();
     tokio::spawn(async move {
         let machines = machines1;
         let mut found_recv = found_recv;
         let registry = registry1;
+        let events = events1;
+        let cfg = cfg1;
+        let poller = poller1;
+        let mut machine_gauges: HashMap<String, Gauges> = HashMap::new();
 
         while let Some(machine_id) = found_recv.recv().await {
             let machines_read = machines.read().await;
@@ -138,38 +281,122 @@ pub async fn main(_cli: &Cli, cfg: &Config, bind: &str) -> Result<()> {
                 continue;
             };
 
-            let machine = machine.read().await;
-            let any_machine = machine.get_machine();
+            if let Err(e) = machine.write().await.load_serial().await {
+                tracing::warn!(
+                    id = machine_id,
+                    error = format!("{:?}", e),
+                    "failed to read machine's serial number"
+                );
+            }
+
+            let aliases = cfg
+                .aliases
+                .iter()
+                .filter(|(_, id)| **id == machine_id)
+                .map(|(alias, _)| alias.clone())
+                .collect::<Vec<_>>();
+            if !aliases.is_empty() {
+                machine.write().await.set_aliases(aliases);
+            }
+
+            if let Some((entry, instance)) = cfg.machine_entry(&machine_id) {
+                let mut machine = machine.write().await;
+                // Instances on the same host share an entry, so tell them
+                // apart by name.
+                let display_name = entry.display_name.clone().map(|name| match &instance {
+                    Some(instance) => format!("{} ({})", name, instance),
+                    None => name,
+                });
+                machine.set_display_name(display_name);
+                machine.set_location(entry.location.clone());
+                machine.set_labels(entry.labels.clone());
+                machine.set_gcode_extra(entry.start_gcode_extra.clone(), entry.end_gcode_extra.clone());
+                machine.set_chamber_preheat(entry.chamber_preheat.clone());
+                machine.set_stuck_detection(entry.stuck_detection.clone());
+                machine.set_post_processors(entry.post_process.clone());
+                machine.set_arc_fitting(entry.arc_fitting.clone());
+                if entry.disabled {
+                    machine.set_disabled(true);
+                }
+            }
+
+            // Tasks are tied to the machine, so they're stopped if it's
+            // removed.
+            let mut tasks = vec![];
+            let gauges = machine_gauges.entry(machine_id.clone()).or_default().clone();
+            let machine_read = machine.read().await;
+            let any_machine = machine_read.get_machine();
 
             match &any_machine {
                 AnyMachine::Moonraker(moonraker) => {
-                    let _ = spawn_metrics(registry.clone(), &machine_id, moonraker.get_temperature_sensors()).await;
+                    tasks.extend(
+                        spawn_metrics(
+                            registry.clone(),
+                            &gauges,
+                            &poller,
+                            any_machine.type_name(),
+                            &machine_id,
+                            moonraker.get_temperature_sensors(),
+                        )
+                        .await
+                        .ok(),
+                    );
                 }
                 AnyMachine::Bambu(bambu) => {
-                    let _ = spawn_metrics(registry.clone(), &machine_id, bambu.get_temperature_sensors()).await;
+                    tasks.extend(
+                        spawn_metrics(
+                            registry.clone(),
+                            &gauges,
+                            &poller,
+                            any_machine.type_name(),
+                            &machine_id,
+                            bambu.get_temperature_sensors(),
+                        )
+                        .await
+                        .ok(),
+                    );
+                    tasks.push(spawn_humidity_monitor(
+                        registry.clone(),
+                        gauges.clone(),
+                        events.clone(),
+                        &machine_id,
+                        bambu.clone(),
+                        cfg.humidity_limits.clone(),
+                    ));
                 }
                 _ => { /* Nothing to do here! */ }
             }
-        }
-    });
-
-    let bind_addr: SocketAddr = bind.parse()?;
-    tokio::spawn(async move {
-        let bind_addr = bind_addr;
-        let responder = libmdns::Responder::new().unwrap();
-        let _svc = responder.register(
-            "_machine-api._tcp".to_owned(),
-            "Machine Api Server".to_owned(),
-            bind_addr.port(),
-            &["path=/"],
-        );
+            drop(machine_read);
 
-        tracing::info!(
-            bind_addr = bind_addr.to_string(),
-            "starting mDNS advertisement for _machine-api._tcp"
-        );
+            let mut machine = machine.write().await;
+            for task in tasks {
+                machine.add_background_task(task.abort_handle());
+            }
+        }
     });
 
-    server::serve(bind, machines, registry).await?;
+    let slicers = cfg.load_slicers().exit_status(ExitStatus::Config)?;
+    let profiles = slicer::profiles::ProfileStore::new(&cfg.profiles);
+    server::serve(
+        bind,
+        machines,
+        registry,
+        events,
+        slicers,
+        profiles,
+        Some(cfg.jobs.clone()),
+        cfg.retention.clone(),
+        cfg.job_concurrency.clone(),
+        cfg.dispatch_retry.clone(),
+        cfg.file_urls.clone(),
+        cfg.handler_task_modes.clone(),
+        read_only || cfg.read_only,
+        cfg.api_keys.clone(),
+        log_levels,
+        &cfg.discovery,
+        poller,
+    )
+    .await
+    .exit_status(ExitStatus::Unavailable)?;
     Ok(())
 }
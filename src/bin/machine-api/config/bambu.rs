@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
-use machine_api::{bambu, Discover, Machine};
+use machine_api::{bambu, Discover, Machine, Supervisor};
 use tokio::sync::RwLock;
 
 use super::{Config, MachineConfig};
@@ -11,22 +11,34 @@ impl Config {
         &self,
         channel: tokio::sync::mpsc::Sender<String>,
         machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+        supervisor: &Supervisor,
     ) -> Result<()> {
         let discovery = bambu::BambuDiscover::new(
             self.machines
                 .iter()
                 .filter_map(|(key, config)| {
-                    if let MachineConfig::Bambu(config) = config {
+                    if let MachineConfig::Bambu(config) = &config.config {
                         Some((key.clone(), config.clone()))
                     } else {
                         None
                     }
                 })
                 .collect::<HashMap<_, _>>(),
-        );
+        )
+        .with_network_filter(self.discovery.clone())
+        .with_cache(bambu::DiscoveryCache::new(&self.cache))
+        .with_known_certificates(bambu::KnownCertificates::new(&self.known_certificates))
+        .with_supervisor(supervisor.clone());
 
-        tokio::spawn(async move {
-            let _ = discovery.discover(channel, machines).await;
+        discovery.register_static(channel.clone(), machines.clone()).await?;
+        discovery.register_cached(channel.clone(), machines.clone()).await?;
+
+        let discovery = Arc::new(discovery);
+        supervisor.spawn("bambu_discover", None, move || {
+            let discovery = discovery.clone();
+            let channel = channel.clone();
+            let machines = machines.clone();
+            async move { discovery.discover(channel, machines).await }
         });
 
         Ok(())
@@ -0,0 +1,146 @@
+//! Upgrading config files written for older versions of the schema, so a
+//! change to the schema doesn't leave older files failing to load.
+
+use std::fmt::Write as _;
+
+use anyhow::{Context, Result};
+
+use super::Config;
+
+/// The current version of the config file's schema. Bump this, and add a
+/// step to [MIGRATIONS], whenever a change would stop older config files
+/// from loading (such as renaming or moving a field).
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A step upgrading a config from one version of the schema to the next,
+/// noting anything it changed which the user should check.
+type Migration = fn(&mut toml::Table, &mut Vec<String>);
+
+/// Each step, in order: the first upgrades version 0 (files from before the
+/// schema was versioned) to version 1, and so on.
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// Version 1 only introduced `version` itself.
+fn v0_to_v1(_config: &mut toml::Table, _notes: &mut Vec<String>) {}
+
+/// A config file, upgraded to the current version of the schema.
+pub struct Migrated {
+    /// The version of the schema the file was written for.
+    pub from_version: u32,
+
+    /// The upgraded config.
+    pub config: toml::Table,
+
+    /// Anything the migration changed which should be checked.
+    pub notes: Vec<String>,
+
+    /// Fields which aren't part of the current schema, so were left out,
+    /// by their dotted path.
+    pub unmapped: Vec<(String, toml::Value)>,
+}
+
+impl Migrated {
+    /// Return the upgraded config as TOML, with anything which couldn't be
+    /// mapped to the current schema left at the end, commented out.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut out = format!(
+            "# Migrated from config schema version {} to {} by `machine-api config migrate`.\n",
+            self.from_version, SCHEMA_VERSION
+        );
+        for note in &self.notes {
+            let _ = writeln!(out, "# {}", note);
+        }
+        out.push('\n');
+        out.push_str(&toml::to_string_pretty(&self.config)?);
+
+        if !self.unmapped.is_empty() {
+            out.push_str(
+                "\n# These fields aren't part of the current schema, so they were left out. Check whether\n# \
+                 they're still needed, and if so, what replaces them.\n",
+            );
+            for (path, value) in &self.unmapped {
+                let _ = writeln!(out, "# {} = {}", path, value);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Upgrade a config file's contents to the current version of the schema.
+/// Fails if the file is for a newer version, or still doesn't load once
+/// upgraded.
+pub fn migrate(contents: &str) -> Result<Migrated> {
+    let mut config: toml::Table = contents.parse().context("config file isn't valid TOML")?;
+
+    let from_version = match config.get("version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| anyhow::anyhow!("invalid config version: {}", version))?
+        }
+        Some(other) => anyhow::bail!("invalid config version: {}", other),
+    };
+    if from_version > SCHEMA_VERSION {
+        anyhow::bail!(
+            "config is for schema version {}, but this machine-api only knows up to version {}",
+            from_version,
+            SCHEMA_VERSION
+        );
+    }
+
+    let mut notes = vec![];
+    for migration in &MIGRATIONS[from_version as usize..] {
+        migration(&mut config, &mut notes);
+    }
+    config.insert("version".to_owned(), toml::Value::Integer(SCHEMA_VERSION.into()));
+
+    // Whatever doesn't survive loading and saving again isn't part of the
+    // schema.
+    let loaded: Config = toml::Value::Table(config.clone())
+        .try_into()
+        .context("the migrated config still doesn't load")?;
+    let toml::Value::Table(known) = toml::Value::try_from(&loaded)? else {
+        anyhow::bail!("config didn't serialize to a table");
+    };
+    let mut unmapped = vec![];
+    remove_unmapped(&mut config, &known, "", &mut unmapped);
+
+    Ok(Migrated {
+        from_version,
+        config,
+        notes,
+        unmapped,
+    })
+}
+
+/// Remove anything from `config` which isn't in `known`, recording it in
+/// `unmapped` by its dotted path under `prefix`. Empty tables and arrays
+/// are kept, since they're left out when saving.
+fn remove_unmapped(
+    config: &mut toml::Table,
+    known: &toml::Table,
+    prefix: &str,
+    unmapped: &mut Vec<(String, toml::Value)>,
+) {
+    let keys = config.keys().cloned().collect::<Vec<_>>();
+    for key in keys {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (config.get_mut(&key), known.get(&key)) {
+            (Some(toml::Value::Table(table)), Some(toml::Value::Table(known))) => {
+                remove_unmapped(table, known, &path, unmapped);
+            }
+            (Some(_), Some(_)) => {}
+            (Some(toml::Value::Table(table)), None) if table.is_empty() => {}
+            (Some(toml::Value::Array(array)), None) if array.is_empty() => {}
+            (Some(_), None) => {
+                if let Some(value) = config.remove(&key) {
+                    unmapped.push((path, value));
+                }
+            }
+            (None, _) => {}
+        }
+    }
+}
@@ -1,16 +1,225 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
 
-use machine_api::{bambu as crate_bambu, moonraker as crate_moonraker, noop as crate_noop, usb as crate_usb};
+use anyhow::Result;
+use machine_api::{
+    bambu as crate_bambu, gcode::ArcFitting, moonraker as crate_moonraker, noop as crate_noop, server, slicer,
+    usb as crate_usb, AnySlicer, ChamberPreheat, HttpConfig, NetworkFilter, PostProcessor, StuckDetection,
+};
 use serde::{Deserialize, Serialize};
 
 mod bambu;
+pub mod migrate;
 mod moonraker;
 mod noop;
+mod telemetry;
 mod usb;
 
+pub use telemetry::Telemetry;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
-    pub machines: HashMap<String, MachineConfig>,
+    /// Version of the schema the file was written for. Files from before
+    /// the schema was versioned are version 0; `machine-api config migrate`
+    /// upgrades older files to the current version.
+    #[serde(default)]
+    pub version: u32,
+
+    pub machines: HashMap<String, MachineEntry>,
+
+    /// Other IDs machines are known by, such as the ID a machine had before
+    /// it was found under its canonical one, mapped to its ID now.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    /// Network interfaces to run discovery and mDNS advertisement on.
+    #[serde(default)]
+    pub discovery: NetworkFilter,
+
+    /// Webhooks to POST events (such as humidity warnings, or jobs
+    /// finishing) to; either URLs, or URLs with payload templates.
+    #[serde(default)]
+    pub webhooks: Vec<server::Webhook>,
+
+    /// Relative humidity limits (in percent) for filament storage, keyed by
+    /// filament type.
+    #[serde(default)]
+    pub humidity_limits: crate_bambu::HumidityLimits,
+
+    /// Slicers to slice with on behalf of other hosts, by name.
+    #[serde(default)]
+    pub slicers: HashMap<String, slicer::Config>,
+
+    /// Where to remember discovered machines, so they're listed straight
+    /// away after a restart.
+    #[serde(default = "default_cache")]
+    pub cache: PathBuf,
+
+    /// Where to keep the certificates of printers trusted on first use.
+    #[serde(default = "default_known_certificates")]
+    pub known_certificates: PathBuf,
+
+    /// Where to keep imported Orca Slicer profiles.
+    #[serde(default = "default_profiles")]
+    pub profiles: PathBuf,
+
+    /// Where to keep jobs which haven't been sent to their machine yet, so
+    /// they're restarted after a restart.
+    #[serde(default = "default_jobs")]
+    pub jobs: PathBuf,
+
+    /// Where to write uploads, slicer outputs and other temporary files,
+    /// rather than the system's temporary directory.
+    #[serde(default)]
+    pub spool_dir: Option<PathBuf>,
+
+    /// How long to keep finished jobs, and their design files, for.
+    #[serde(default)]
+    pub retention: server::Retention,
+
+    /// How many jobs may be active at once on each type of machine, such
+    /// as `Bambu = 2`.
+    #[serde(default)]
+    pub job_concurrency: server::ConcurrencyLimits,
+
+    /// How sending jobs to their machine is retried, if it fails in a way
+    /// which might not happen again (such as timing out).
+    #[serde(default)]
+    pub dispatch_retry: server::DispatchRetry,
+
+    /// Which URLs design files may be downloaded from, when printing with
+    /// a `file_url` rather than an upload.
+    #[serde(default)]
+    pub file_urls: server::FileUrls,
+
+    /// Whether handlers are cancelled when their client disconnects, for
+    /// handlers which only read, and those which change anything.
+    #[serde(default)]
+    pub handler_task_modes: server::TaskModes,
+
+    /// How often machines are polled for their status, such as their
+    /// temperatures.
+    #[serde(default)]
+    pub polling: server::Polling,
+
+    /// Refuse every request which would change anything, while still
+    /// serving status (as `--read-only` does).
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// API keys requests must carry one of (as `Authorization: Bearer
+    /// <key>`), other than to `/ping`. If there are none, anyone who can
+    /// reach the server can use it. Keys may be scoped to some machines.
+    #[serde(default)]
+    pub api_keys: server::ApiKeys,
+
+    /// How the server reports on itself.
+    #[serde(default)]
+    pub telemetry: Telemetry,
+
+    /// How requests out of the server (such as to Moonraker, webhooks and
+    /// remote slicers) are made, such as through a proxy.
+    #[serde(default)]
+    pub http: HttpConfig,
+}
+
+fn default_cache() -> PathBuf {
+    PathBuf::from("machine-api-cache.json")
+}
+
+fn default_known_certificates() -> PathBuf {
+    PathBuf::from("machine-api-known-certificates.json")
+}
+
+fn default_profiles() -> PathBuf {
+    PathBuf::from("profiles")
+}
+
+fn default_jobs() -> PathBuf {
+    PathBuf::from("jobs")
+}
+
+impl Config {
+    /// Load the slicers other hosts may slice with.
+    pub fn load_slicers(&self) -> Result<HashMap<String, AnySlicer>> {
+        self.slicers
+            .iter()
+            .map(|(name, slicer)| Ok((name.clone(), slicer.load()?)))
+            .collect()
+    }
+
+    /// Find the config entry for a machine, along with the name of the
+    /// Moonraker instance the machine is, if it's one of several on a host.
+    pub fn machine_entry(&self, machine_id: &str) -> Option<(&MachineEntry, Option<String>)> {
+        if let Some(entry) = self.machines.get(machine_id) {
+            return Some((entry, None));
+        }
+
+        self.machines.iter().find_map(|(key, entry)| {
+            let MachineConfig::Moonraker(config) = &entry.config else {
+                return None;
+            };
+            config
+                .instances
+                .keys()
+                .find(|instance| moonraker::instance_machine_id(key, Some(instance)) == machine_id)
+                .map(|instance| (entry, Some(instance.clone())))
+        })
+    }
+}
+
+/// A single configured machine, along with how it should be presented to
+/// users.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MachineEntry {
+    /// User-facing name, such as `Rack 3 X1C`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// Where the machine physically is, such as `Rack 3, shelf B`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    /// Labels to select the machine by, such as `building = "east"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// Start the machine out of service, in maintenance mode.
+    #[serde(default)]
+    pub disabled: bool,
+
+    /// Extra gcode to run after the slicer profile's start gcode, such as
+    /// a custom purge.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_gcode_extra: Option<String>,
+
+    /// Extra gcode to run after the slicer profile's end gcode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_gcode_extra: Option<String>,
+
+    /// Wait for the chamber to warm up before starting ABS or ASA jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chamber_preheat: Option<ChamberPreheat>,
+
+    /// Flag printing jobs whose progress stops advancing, which usually
+    /// means they've failed silently.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stuck_detection: Option<StuckDetection>,
+
+    /// Scripts to run over each sliced file, in order, before it's sent to
+    /// the machine.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_process: Vec<PostProcessor>,
+
+    /// Fit `G2`/`G3` arcs to sliced gcode before it's sent to the machine.
+    /// Only enable this if the machine's firmware supports arcs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arc_fitting: Option<ArcFitting>,
+
+    #[serde(flatten)]
+    pub config: MachineConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -1,49 +1,95 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
-use machine_api::{moonraker, Machine, MachineMakeModel};
+use machine_api::{moonraker, Discover, Machine, MachineMakeModel, Supervisor};
 use tokio::sync::RwLock;
 
 use super::{Config, MachineConfig};
 
 impl Config {
-    pub async fn create_moonraker(
-        &self,
-        channel: tokio::sync::mpsc::Sender<String>,
-        machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
-    ) -> Result<()> {
-        for (key, config) in self
-            .machines
+    fn moonraker_configs(&self) -> HashMap<String, moonraker::Config> {
+        self.machines
             .iter()
             .filter_map(|(key, config)| {
-                if let MachineConfig::Moonraker(config) = config {
+                if let MachineConfig::Moonraker(config) = &config.config {
                     Some((key.clone(), config.clone()))
                 } else {
                     None
                 }
             })
-            .collect::<HashMap<_, _>>()
-        {
-            let slicer = config.slicer.load()?;
-            let (manufacturer, model) = config.variant.get_manufacturer_model();
-
-            machines.write().await.insert(
-                key.clone(),
-                RwLock::new(Machine::new(
-                    moonraker::Client::new(
-                        &config,
-                        MachineMakeModel {
-                            manufacturer,
-                            model,
-                            serial: None,
-                        },
-                    )?,
-                    slicer,
-                )),
-            );
-            channel.send(key.clone()).await?;
+            .collect()
+    }
+
+    pub async fn create_moonraker(
+        &self,
+        channel: tokio::sync::mpsc::Sender<String>,
+        machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+    ) -> Result<()> {
+        for (key, config) in self.moonraker_configs() {
+            // These are registered once they're found, by
+            // `spawn_discover_moonraker`.
+            if config.is_discovered() {
+                continue;
+            }
+
+            let instances = config.split_instances();
+            if instances.is_empty() {
+                anyhow::bail!("moonraker machine {} has no endpoint or instances", key);
+            }
+
+            for (instance, config) in instances {
+                let machine_id = instance_machine_id(&key, instance.as_deref());
+                let slicer = config.slicer.load()?;
+                let (manufacturer, model) = config.variant.get_manufacturer_model();
+
+                machines.write().await.insert(
+                    machine_id.clone(),
+                    RwLock::new(Machine::new(
+                        moonraker::Client::new(
+                            &config,
+                            MachineMakeModel {
+                                manufacturer,
+                                model,
+                                serial: None,
+                            },
+                        )?
+                        .with_machine_id(&machine_id),
+                        slicer,
+                    )),
+                );
+                channel.send(machine_id).await?;
+            }
         }
 
         Ok(())
     }
+
+    pub async fn spawn_discover_moonraker(
+        &self,
+        channel: tokio::sync::mpsc::Sender<String>,
+        machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+        supervisor: &Supervisor,
+    ) -> Result<()> {
+        let discovery =
+            moonraker::MoonrakerDiscover::new(self.moonraker_configs()).with_network_filter(self.discovery.clone());
+
+        let discovery = Arc::new(discovery);
+        supervisor.spawn("moonraker_discover", None, move || {
+            let discovery = discovery.clone();
+            let channel = channel.clone();
+            let machines = machines.clone();
+            async move { discovery.discover(channel, machines).await }
+        });
+
+        Ok(())
+    }
+}
+
+/// The ID of the machine for one of a Moonraker config's instances: the
+/// config's key, followed by the instance's name, if it has one.
+pub fn instance_machine_id(key: &str, instance: Option<&str>) -> String {
+    match instance {
+        Some(instance) => format!("{}-{}", key, instance),
+        None => key.to_owned(),
+    }
 }
@@ -16,7 +16,7 @@ impl Config {
             .machines
             .iter()
             .filter_map(|(key, config)| {
-                if let MachineConfig::Noop(config) = config {
+                if let MachineConfig::Noop(config) = &config.config {
                     Some((key.clone(), config.clone()))
                 } else {
                     None
@@ -0,0 +1,115 @@
+use std::{sync::Arc, time::Duration};
+
+use prometheus_client::registry::Registry;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::Config;
+
+/// How the server reports on itself, beyond its own `/metrics` endpoint.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Telemetry {
+    /// Push metrics to a Prometheus push gateway, for deployments (such as
+    /// Cloud Run) which can't be scraped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_gateway: Option<PushGateway>,
+}
+
+/// Where and how often to push metrics to a Prometheus push gateway.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PushGateway {
+    /// Base URL of the push gateway, such as `http://pushgateway:9091`.
+    pub url: String,
+
+    /// Job label to group the pushed metrics under.
+    #[serde(default = "default_job")]
+    pub job: String,
+
+    /// Instance label to group the pushed metrics under, to tell several
+    /// servers apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+
+    /// How often to push, in seconds.
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+fn default_job() -> String {
+    "machine-api".to_owned()
+}
+
+fn default_interval_seconds() -> u64 {
+    15
+}
+
+impl PushGateway {
+    /// URL of the metrics group to push to. The job and instance are
+    /// escaped, so they can hold any characters.
+    fn group_url(&self) -> anyhow::Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.url)?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("push gateway URL isn't an http(s) URL: {}", self.url))?;
+            segments.pop_if_empty().extend(["metrics", "job", &self.job]);
+            if let Some(instance) = &self.instance {
+                segments.extend(["instance", instance]);
+            }
+        }
+        Ok(url)
+    }
+}
+
+impl Config {
+    /// Push the registry's metrics to the configured push gateway, if any,
+    /// on an interval.
+    pub fn spawn_metrics_push(&self, registry: Arc<RwLock<Registry>>) {
+        let Some(push_gateway) = self.telemetry.push_gateway.clone() else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let client = machine_api::http_client();
+            let url = match push_gateway.group_url() {
+                Ok(url) => url,
+                Err(e) => {
+                    tracing::error!(
+                        error = format!("{:?}", e),
+                        "invalid push gateway URL, not pushing metrics"
+                    );
+                    return;
+                }
+            };
+            let mut interval = tokio::time::interval(Duration::from_secs(push_gateway.interval_seconds.max(1)));
+
+            tracing::info!(url = url.as_str(), "pushing metrics to push gateway");
+
+            loop {
+                interval.tick().await;
+
+                let mut body = String::new();
+                if let Err(e) = prometheus_client::encoding::text::encode(&mut body, &*registry.read().await) {
+                    tracing::warn!(error = format!("{:?}", e), "failed to encode metrics");
+                    continue;
+                }
+
+                // PUT replaces everything previously pushed to the group, so
+                // metrics for machines which have gone away don't linger.
+                let result = client
+                    .put(url.clone())
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                    )
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!(url = url.as_str(), error = format!("{:?}", e), "failed to push metrics");
+                }
+            }
+        });
+    }
+}
@@ -1,7 +1,7 @@
 use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
-use machine_api::{usb, Discover, Machine};
+use machine_api::{usb, Discover, Machine, Supervisor};
 use tokio::sync::RwLock;
 
 use super::{Config, MachineConfig};
@@ -11,12 +11,13 @@ impl Config {
         &self,
         channel: tokio::sync::mpsc::Sender<String>,
         machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+        supervisor: &Supervisor,
     ) -> Result<()> {
         let discovery = usb::UsbDiscovery::new(
             self.machines
                 .iter()
                 .filter_map(|(key, config)| {
-                    if let MachineConfig::Usb(config) = config {
+                    if let MachineConfig::Usb(config) = &config.config {
                         Some((key.clone(), config.clone()))
                     } else {
                         None
@@ -25,8 +26,12 @@ impl Config {
                 .collect::<HashMap<_, _>>(),
         );
 
-        tokio::spawn(async move {
-            let _ = discovery.discover(channel, machines).await;
+        let discovery = Arc::new(discovery);
+        supervisor.spawn("usb_discover", None, move || {
+            let discovery = discovery.clone();
+            let channel = channel.clone();
+            let machines = machines.clone();
+            async move { discovery.discover(channel, machines).await }
         });
 
         Ok(())
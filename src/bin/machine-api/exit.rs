@@ -0,0 +1,151 @@
+//! Exit codes, and how failures are reported, so scripts and CI pipelines
+//! can branch on what went wrong without parsing log output. The codes are
+//! stable; new ones are only ever added.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// How a command ended, as its exit code.
+///
+/// Code 2 is for an invalid command line, which `clap` exits with itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+    /// Something went wrong which doesn't have its own code.
+    Failure = 1,
+
+    /// No machine has the ID or display name the command was given.
+    MachineNotFound = 3,
+
+    /// The machine is running a job, or otherwise can't take one now.
+    MachineBusy = 4,
+
+    /// The design couldn't be sliced for the machine.
+    SliceFailed = 5,
+
+    /// The config file was missing or invalid.
+    Config = 6,
+
+    /// The server couldn't be started, or stopped unexpectedly, such as
+    /// because its address was already in use.
+    Unavailable = 7,
+}
+
+impl ExitStatus {
+    /// Return the exit status for `error`, [ExitStatus::Failure] unless it
+    /// was tagged with one by [WithExitStatus].
+    pub fn of(error: &anyhow::Error) -> Self {
+        error
+            .downcast_ref::<Failed>()
+            .map(|failed| failed.status)
+            .unwrap_or(ExitStatus::Failure)
+    }
+
+    /// Return the process exit code.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+
+    /// Tag `error` with this exit status.
+    pub fn tag(self, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(Failed {
+            status: self,
+            message: format!("{:#}", error),
+        })
+    }
+}
+
+/// An error, tagged with the exit status it should end the command with.
+#[derive(Debug)]
+struct Failed {
+    status: ExitStatus,
+    message: String,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// Tag the error of a `Result` with the exit status it should end the
+/// command with.
+pub trait WithExitStatus<T> {
+    /// Tag the error, if any, with `status`.
+    fn exit_status(self, status: ExitStatus) -> anyhow::Result<T>;
+}
+
+impl<T, E> WithExitStatus<T> for Result<T, E>
+where
+    E: Into<anyhow::Error>,
+{
+    fn exit_status(self, status: ExitStatus) -> anyhow::Result<T> {
+        self.map_err(|e| status.tag(e.into()))
+    }
+}
+
+/// How a command's output is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Output {
+    /// For people to read.
+    #[default]
+    Text,
+
+    /// As JSON, one object per line, on stdout.
+    Json,
+}
+
+/// Print a command's result as `output` says: `value` as JSON, or `text`
+/// for people to read.
+pub fn show<T: Serialize>(output: Output, value: &T, text: impl fmt::Display) -> anyhow::Result<()> {
+    match output {
+        Output::Text => println!("{}", text),
+        Output::Json => println!("{}", serde_json::to_string(value)?),
+    }
+    Ok(())
+}
+
+/// A failure, as printed with `--output json`.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    error: ReportedError<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportedError<'a> {
+    /// The process exit code.
+    code: u8,
+
+    /// What went wrong, as a name rather than a number.
+    kind: ExitStatus,
+
+    /// What went wrong, for people to read.
+    message: &'a str,
+}
+
+/// Report `error` as `output` says, returning the exit status to end the
+/// command with.
+pub fn report(output: Output, error: &anyhow::Error) -> ExitStatus {
+    let status = ExitStatus::of(error);
+    match output {
+        Output::Text => eprintln!("Error: {:?}", error),
+        Output::Json => {
+            let message = format!("{:#}", error);
+            let report = Report {
+                error: ReportedError {
+                    code: status.code(),
+                    kind: status,
+                    message: &message,
+                },
+            };
+            match serde_json::to_string(&report) {
+                Ok(report) => println!("{}", report),
+                Err(_) => eprintln!("Error: {:?}", error),
+            }
+        }
+    }
+    status
+}
@@ -1,3 +1,5 @@
+use std::process::ExitCode;
+
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use opentelemetry::trace::TracerProvider;
@@ -6,8 +8,16 @@ use tracing_subscriber::prelude::*;
 mod config;
 use config::Config;
 
+mod client;
+use client::ServerArgs;
+
+mod cmd_config;
+mod cmd_machine;
 mod cmd_serve;
 
+mod exit;
+use exit::{ExitStatus, Output, WithExitStatus};
+
 /// Serve the machine-api server.
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -28,6 +38,12 @@ struct Cli {
     /// Print logs as json
     #[clap(short, long)]
     pub json: bool,
+
+    /// How to print the command's output, including why it failed. With
+    /// `json`, failures are printed to stdout as an `error` object with its
+    /// exit `code`.
+    #[arg(long, value_enum, default_value_t = Output::Text, global = true)]
+    pub output: Output,
 }
 
 #[derive(Subcommand)]
@@ -38,6 +54,64 @@ enum Commands {
         /// `host:port` to bind to on the host system.
         #[arg(long, short, default_value = "127.0.0.1:8080")]
         bind: String,
+
+        /// Refuse every request which would change anything (such as
+        /// printing, or cancelling a job), while still serving status.
+        #[arg(long)]
+        read_only: bool,
+    },
+
+    /// Work with the config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// List the machines a running server knows about.
+    Machines {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+
+    /// Show the status of a machine on a running server.
+    Status {
+        /// ID (or display name) of the machine.
+        #[arg(long, short)]
+        machine: String,
+
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+
+    /// Slice a design and print it on a machine on a running server, if the
+    /// machine is idle.
+    Print {
+        /// ID (or display name) of the machine.
+        #[arg(long, short)]
+        machine: String,
+
+        /// Name of the job, rather than the design's file name.
+        #[arg(long)]
+        job_name: Option<String>,
+
+        /// Design file to print, such as an STL or 3MF.
+        file: std::path::PathBuf,
+
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Upgrade the config file to the current version of the schema, and
+    /// print it. Fields which aren't part of the current schema are left
+    /// at the end, commented out. Comments in the file aren't kept.
+    Migrate {
+        /// Replace the config file, rather than printing it, keeping the
+        /// original alongside it as a `.bak`.
+        #[arg(long)]
+        write: bool,
     },
 }
 
@@ -81,9 +155,41 @@ async fn handle_signals() -> Result<()> {
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
+    match run(&cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => ExitCode::from(exit::report(cli.output, &e).code()),
+    }
+}
+
+async fn run(cli: &Cli) -> Result<()> {
+    // Config commands only work on the file, so don't need it to load, or
+    // anything else set up.
+    if let Commands::Config { ref command } = cli.command {
+        return match command {
+            ConfigCommands::Migrate { write } => cmd_config::migrate(cli, *write),
+        };
+    }
+
+    // Commands acting on machines go through a running server, so don't
+    // need the config either.
+    match cli.command {
+        Commands::Machines { ref server } => return cmd_machine::list(cli, server).await,
+        Commands::Status {
+            ref machine,
+            ref server,
+        } => return cmd_machine::status(cli, server, machine).await,
+        Commands::Print {
+            ref machine,
+            ref job_name,
+            ref file,
+            ref server,
+        } => return cmd_machine::print(cli, server, machine, file, job_name.as_deref()).await,
+        Commands::Serve { .. } | Commands::Config { .. } => {}
+    }
+
     tokio::spawn(async { handle_signals().await });
 
     let level_filter = if cli.debug {
@@ -91,6 +197,9 @@ async fn main() -> Result<()> {
     } else {
         tracing_subscriber::filter::LevelFilter::INFO
     };
+    // Every layer shares the same levels, so they can be overridden for a
+    // machine at runtime.
+    let log_levels = machine_api::server::LogLevels::new(level_filter);
 
     // Format fields using the provided closure.
     // We want to make this very consise otherwise the logs are not able to be read by humans.
@@ -112,7 +221,7 @@ async fn main() -> Result<()> {
         // We could probably format these specifically for cloud run if we wanted,
         // will save that as a TODO: https://cloud.google.com/run/docs/logging#special-fields
         (
-            Some(tracing_subscriber::fmt::layer().json().with_filter(level_filter)),
+            Some(tracing_subscriber::fmt::layer().json().with_filter(log_levels.clone())),
             None,
         )
     } else {
@@ -122,7 +231,7 @@ async fn main() -> Result<()> {
                 tracing_subscriber::fmt::layer()
                     .pretty()
                     .fmt_fields(format)
-                    .with_filter(level_filter),
+                    .with_filter(log_levels.clone()),
             ),
         )
     };
@@ -139,7 +248,7 @@ async fn main() -> Result<()> {
 
     let telemetry = tracing_opentelemetry::layer()
         .with_tracer(tracer)
-        .with_filter(level_filter);
+        .with_filter(log_levels.clone());
 
     // Initialize tracing.
     tracing_subscriber::registry()
@@ -169,10 +278,31 @@ async fn main() -> Result<()> {
 
     let cfg: Config = toml::from_str(
         &std::fs::read_to_string(&cli.config)
-            .map_err(|_| anyhow::anyhow!("Config file not found at {}", &cli.config))?,
-    )?;
+            .map_err(|_| anyhow::anyhow!("Config file not found at {}", &cli.config))
+            .exit_status(ExitStatus::Config)?,
+    )
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "{}\nIf the config file was written for an older machine-api, `machine-api config migrate` may upgrade it",
+            e
+        )
+    })
+    .exit_status(ExitStatus::Config)?;
+    if cfg.version > config::migrate::SCHEMA_VERSION {
+        return Err(anyhow::anyhow!(
+            "Config file is for schema version {}, but this machine-api only knows up to version {}",
+            cfg.version,
+            config::migrate::SCHEMA_VERSION
+        ))
+        .exit_status(ExitStatus::Config);
+    }
+
+    machine_api::set_http_config(cfg.http.clone()).exit_status(ExitStatus::Config)?;
 
     match cli.command {
-        Commands::Serve { ref bind } => cmd_serve::main(&cli, &cfg, bind).await,
+        Commands::Serve { ref bind, read_only } => cmd_serve::main(cli, &cfg, bind, read_only, log_levels).await,
+        Commands::Config { .. } | Commands::Machines { .. } | Commands::Status { .. } | Commands::Print { .. } => {
+            unreachable!("only serve needs the config loaded")
+        }
     }
 }
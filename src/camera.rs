@@ -0,0 +1,134 @@
+//! Live video from machines' cameras, as a stream of JPEG frames.
+
+use anyhow::{Context, Result};
+use tokio::{
+    io::AsyncReadExt,
+    process::{Child, ChildStdout},
+};
+
+/// How much is read from `ffmpeg` at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Largest frame we'll buffer before giving up on finding its end, so a
+/// garbled stream doesn't have us allocate gigabytes.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// A machine's camera, streaming video as JPEG frames until dropped.
+pub struct CameraStream(Source);
+
+enum Source {
+    /// A Bambu printer which sends JPEG frames over its own protocol.
+    #[cfg(feature = "bambu")]
+    BambuJpeg(bambulabs::JpegCamera),
+
+    /// A stream `ffmpeg` is transcoding to JPEG frames.
+    Ffmpeg {
+        /// Kept so `ffmpeg` is killed when the stream is dropped.
+        _child: Child,
+        stdout: ChildStdout,
+        buffer: Vec<u8>,
+    },
+}
+
+impl CameraStream {
+    /// Stream the camera sending JPEG frames over a Bambu printer's own
+    /// protocol.
+    #[cfg(feature = "bambu")]
+    pub(crate) fn bambu_jpeg(camera: bambulabs::JpegCamera) -> Self {
+        Self(Source::BambuJpeg(camera))
+    }
+
+    /// Stream an RTSP(S) camera, transcoding it to JPEG frames with
+    /// `ffmpeg`, which must be installed.
+    pub(crate) fn rtsp(url: &str) -> Result<Self> {
+        let mut child = tokio::process::Command::new("ffmpeg")
+            .args([
+                "-loglevel",
+                "error",
+                "-rtsp_transport",
+                "tcp",
+                "-i",
+                url,
+                "-f",
+                "image2pipe",
+                "-c:v",
+                "mjpeg",
+                "-q:v",
+                "5",
+                "pipe:1",
+            ])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to run ffmpeg")?;
+        let stdout = child.stdout.take().context("ffmpeg has no stdout")?;
+        Ok(Self(Source::Ffmpeg {
+            _child: child,
+            stdout,
+            buffer: vec![],
+        }))
+    }
+
+    /// Wait for the camera's next frame, as a JPEG, or `None` once the
+    /// stream has ended.
+    pub async fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        match &mut self.0 {
+            #[cfg(feature = "bambu")]
+            Source::BambuJpeg(camera) => camera.next_frame().await.map(Some),
+            Source::Ffmpeg { stdout, buffer, .. } => loop {
+                if let Some(frame) = take_jpeg(buffer) {
+                    return Ok(Some(frame));
+                }
+                if buffer.len() > MAX_FRAME_SIZE {
+                    anyhow::bail!("camera stream has no frame in its first {} bytes", buffer.len());
+                }
+                let len = buffer.len();
+                buffer.resize(len + READ_SIZE, 0);
+                let read = stdout.read(&mut buffer[len..]).await?;
+                buffer.truncate(len + read);
+                if read == 0 {
+                    return Ok(None);
+                }
+            },
+        }
+    }
+}
+
+/// Take the first whole JPEG out of `buffer`, discarding anything before
+/// it. JPEGs start with an SOI marker (`FF D8`) and end with an EOI marker
+/// (`FF D9`), which can't appear in between, since `FF` bytes in the image
+/// data are followed by `00`.
+fn take_jpeg(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = buffer.windows(2).position(|marker| marker == [0xff, 0xd8])?;
+    let end = buffer[start + 2..]
+        .windows(2)
+        .position(|marker| marker == [0xff, 0xd9])?
+        + start
+        + 4;
+    let frame = buffer[start..end].to_vec();
+    buffer.drain(..end);
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_jpeg() {
+        let mut buffer = vec![0x00, 0xff, 0xd8, 0x01, 0xff, 0x00, 0xff, 0xd9, 0xff, 0xd8, 0x02];
+        assert_eq!(
+            take_jpeg(&mut buffer),
+            Some(vec![0xff, 0xd8, 0x01, 0xff, 0x00, 0xff, 0xd9])
+        );
+        // The next frame isn't finished yet.
+        assert_eq!(take_jpeg(&mut buffer), None);
+        assert_eq!(buffer, vec![0xff, 0xd8, 0x02]);
+
+        buffer.extend([0xff, 0xd9]);
+        assert_eq!(take_jpeg(&mut buffer), Some(vec![0xff, 0xd8, 0x02, 0xff, 0xd9]));
+        assert!(buffer.is_empty());
+    }
+}
@@ -1,8 +1,32 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use anyhow::Result;
 use tokio::fs::File;
 
+/// Where uploads, slicer outputs and other temporary files are written, if
+/// set with [set_spool_dir].
+static SPOOL_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set where uploads, slicer outputs and other temporary files are written,
+/// such as to keep heavy IO on a scratch disk rather than in `/tmp`. This
+/// can only be set once, before anything is written; it returns an error if
+/// it's already been set.
+pub fn set_spool_dir(path: PathBuf) -> Result<()> {
+    SPOOL_DIR
+        .set(path)
+        .map_err(|path| anyhow::anyhow!("spool directory is already set, to {}", path.display()))
+}
+
+/// Return where uploads, slicer outputs and other temporary files are
+/// written; the spool directory, if one has been set with
+/// [set_spool_dir], or the system's temporary directory if not.
+pub fn spool_dir() -> PathBuf {
+    SPOOL_DIR.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
 /// A TemporaryFile wraps a normal [tokio::fs::File]`, but will attempt to
 /// delete the file with this handle is dropped. File i/o can be done using
 /// `as_mut` or `as_ref`.
@@ -26,6 +50,20 @@ impl TemporaryFile {
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// Copy the file to `path`, returning the copy, which is also deleted
+    /// when dropped.
+    pub async fn copy_to(&self, path: &Path) -> Result<Self> {
+        tokio::fs::copy(&self.path, path).await?;
+        Self::new(path).await
+    }
+
+    /// Open the file at the path again, such as after it's been replaced by
+    /// another process.
+    pub async fn reopen(&mut self) -> Result<()> {
+        self.inner = File::open(&self.path).await?;
+        Ok(())
+    }
 }
 
 impl AsMut<File> for TemporaryFile {
@@ -0,0 +1,496 @@
+//! Arc fitting, in the style of ArcWelder: runs of short `G1` moves which
+//! trace out an arc are replaced by a single `G2`/`G3`, which makes the file
+//! smaller, and saves firmware (especially when the file is streamed over
+//! USB) from stuttering through hundreds of tiny segments.
+
+use std::f64::consts::TAU;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::TemporaryFile;
+
+/// The fewest segments worth replacing with an arc.
+const MIN_SEGMENTS: usize = 3;
+
+/// The most segments replaced by a single arc, which keeps fitting quick.
+const MAX_SEGMENTS: usize = 500;
+
+/// How much the extrusion per millimeter may vary between segments of an
+/// arc, as a fraction, so that arcs don't smear out deliberate changes in
+/// line width.
+const EXTRUSION_TOLERANCE: f64 = 0.05;
+
+/// Replace runs of linear moves which trace out an arc with `G2`/`G3` arcs.
+/// Only use this for machines whose firmware supports arcs (such as Marlin
+/// built with `ARC_SUPPORT`, or Klipper with `[gcode_arcs]`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArcFitting {
+    /// How far the arc may stray from the original path, in millimeters.
+    #[serde(default = "default_tolerance_mm")]
+    pub tolerance_mm: f64,
+
+    /// The largest radius to replace with an arc, in millimeters. Beyond
+    /// this, moves are as good as straight.
+    #[serde(default = "default_max_radius_mm")]
+    pub max_radius_mm: f64,
+}
+
+fn default_tolerance_mm() -> f64 {
+    0.05
+}
+
+fn default_max_radius_mm() -> f64 {
+    1000.0
+}
+
+impl Default for ArcFitting {
+    fn default() -> Self {
+        Self {
+            tolerance_mm: default_tolerance_mm(),
+            max_radius_mm: default_max_radius_mm(),
+        }
+    }
+}
+
+impl ArcFitting {
+    /// Fit arcs in the gcode file `file`, rewriting it in place.
+    pub async fn apply(&self, file: &mut TemporaryFile) -> Result<()> {
+        let gcode = tokio::fs::read_to_string(file.path()).await?;
+        let fitted = self.fit(&gcode);
+        tracing::info!(
+            path = format!("{:?}", file.path()),
+            before_bytes = gcode.len(),
+            after_bytes = fitted.len(),
+            "fitted arcs"
+        );
+        tokio::fs::write(file.path(), fitted).await?;
+        file.reopen().await
+    }
+
+    /// Fit arcs in `gcode`.
+    pub fn fit(&self, gcode: &str) -> String {
+        let mut fitter = Fitter::new(self);
+        for line in gcode.lines() {
+            fitter.line(line);
+        }
+        fitter.flush();
+
+        let mut out = fitter.out.join("\n");
+        if gcode.ends_with('\n') {
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+impl Point {
+    fn distance(&self, other: &Point) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// A linear, extruding move which might be part of an arc.
+#[derive(Clone, Debug)]
+struct Segment {
+    line: String,
+    end: Point,
+    extrusion: f64,
+    feedrate: Option<String>,
+}
+
+/// A circle which fits a run of segments.
+#[derive(Clone, Copy, Debug)]
+struct Arc {
+    center: Point,
+    clockwise: bool,
+}
+
+/// A `G0`/`G1` move, split into its parameters.
+#[derive(Debug, Default)]
+struct Move {
+    x: Option<f64>,
+    y: Option<f64>,
+    e: Option<f64>,
+    f: Option<String>,
+    other: bool,
+}
+
+fn parse_move(words: &[&str]) -> Option<Move> {
+    let mut parsed = Move::default();
+    for word in words {
+        let mut chars = word.chars();
+        let letter = chars.next()?;
+        let value = chars.as_str();
+        match letter.to_ascii_uppercase() {
+            'X' => parsed.x = Some(value.parse().ok()?),
+            'Y' => parsed.y = Some(value.parse().ok()?),
+            'E' => parsed.e = Some(value.parse().ok()?),
+            'F' => parsed.f = Some(value.to_owned()),
+            _ => parsed.other = true,
+        }
+    }
+    Some(parsed)
+}
+
+/// Format a coordinate without needless trailing zeros.
+fn format_number(value: f64, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" | "" => "0".to_owned(),
+        formatted => formatted.to_owned(),
+    }
+}
+
+struct Fitter<'a> {
+    config: &'a ArcFitting,
+    out: Vec<String>,
+
+    absolute: bool,
+    absolute_extrusion: bool,
+    position: Point,
+    extrusion: f64,
+
+    /// Where the current run of segments starts.
+    start: Point,
+    /// The absolute extrusion at the start of the current run.
+    start_extrusion: f64,
+    run: Vec<Segment>,
+}
+
+impl<'a> Fitter<'a> {
+    fn new(config: &'a ArcFitting) -> Self {
+        Self {
+            config,
+            out: vec![],
+            absolute: true,
+            absolute_extrusion: true,
+            position: Point { x: 0.0, y: 0.0 },
+            extrusion: 0.0,
+            start: Point { x: 0.0, y: 0.0 },
+            start_extrusion: 0.0,
+            run: vec![],
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let code = line.split(';').next().unwrap_or_default();
+        let words: Vec<&str> = code.split_whitespace().collect();
+        let Some(command) = words.first().map(|command| command.to_ascii_uppercase()) else {
+            self.pass(line);
+            return;
+        };
+
+        // Comments are kept where they are, so they're never part of an arc.
+        if line.contains(';') {
+            self.pass(line);
+            self.track(&command, &words[1..]);
+            return;
+        }
+
+        if command == "G1" || command == "G01" {
+            if let Some(segment) = self.segment(&words[1..], line) {
+                self.push(segment);
+                return;
+            }
+        }
+
+        self.pass(line);
+        self.track(&command, &words[1..]);
+    }
+
+    /// Pass a line through as it is, after whatever's queued up.
+    fn pass(&mut self, line: &str) {
+        self.flush();
+        self.out.push(line.to_owned());
+    }
+
+    /// Keep track of the machine's state across a line which isn't part of
+    /// an arc.
+    fn track(&mut self, command: &str, params: &[&str]) {
+        match command {
+            "G90" => {
+                self.absolute = true;
+                self.absolute_extrusion = true;
+            }
+            "G91" => {
+                self.absolute = false;
+                self.absolute_extrusion = false;
+            }
+            "M82" => self.absolute_extrusion = true,
+            "M83" => self.absolute_extrusion = false,
+            "G0" | "G00" | "G1" | "G01" | "G2" | "G02" | "G3" | "G03" | "G92" => {
+                let Some(parsed) = parse_move(params) else {
+                    return;
+                };
+                let relative = !self.absolute && command != "G92";
+                if let Some(x) = parsed.x {
+                    self.position.x = if relative { self.position.x + x } else { x };
+                }
+                if let Some(y) = parsed.y {
+                    self.position.y = if relative { self.position.y + y } else { y };
+                }
+                if let Some(e) = parsed.e {
+                    self.extrusion = if self.absolute_extrusion || command == "G92" {
+                        e
+                    } else {
+                        self.extrusion + e
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Parse a `G1` as a segment which might be part of an arc: an
+    /// extruding move in X/Y only, in absolute coordinates.
+    fn segment(&self, params: &[&str], line: &str) -> Option<Segment> {
+        if !self.absolute {
+            return None;
+        }
+        let parsed = parse_move(params)?;
+        if parsed.other || (parsed.x.is_none() && parsed.y.is_none()) {
+            return None;
+        }
+        let e = parsed.e?;
+        let start = self.position;
+        let end = Point {
+            x: parsed.x.unwrap_or(start.x),
+            y: parsed.y.unwrap_or(start.y),
+        };
+        let extrusion = if self.absolute_extrusion { e - self.extrusion } else { e };
+        if extrusion <= 0.0 || start.distance(&end) <= 0.0 {
+            return None;
+        }
+        // A change of speed part way through can't be kept in an arc.
+        if parsed.f.is_some() && !self.run.is_empty() {
+            return None;
+        }
+
+        Some(Segment {
+            line: line.to_owned(),
+            end,
+            extrusion,
+            feedrate: parsed.f,
+        })
+    }
+
+    fn push(&mut self, segment: Segment) {
+        if self.run.is_empty() {
+            self.start = self.position;
+            self.start_extrusion = self.extrusion;
+        }
+        self.position = segment.end;
+        self.extrusion += segment.extrusion;
+        self.run.push(segment);
+
+        if self.run.len() < MIN_SEGMENTS {
+            return;
+        }
+        if self.run.len() > MIN_SEGMENTS && self.fit(&self.run).is_none() {
+            // The run fitted until this segment; finish the arc before it,
+            // and start again from there.
+            let last = self.run.pop().expect("run is not empty");
+            let end = self.run.last().expect("run is not empty").end;
+            self.flush();
+            self.start = end;
+            self.start_extrusion = self.extrusion - last.extrusion;
+            self.run.push(last);
+            return;
+        }
+        // Drop segments off the front until what's left fits (or is too
+        // short to bother with).
+        while self.run.len() >= MIN_SEGMENTS && self.fit(&self.run).is_none() {
+            let first = self.run.remove(0);
+            self.start = first.end;
+            self.start_extrusion += first.extrusion;
+            self.out.push(first.line);
+        }
+        if self.run.len() >= MAX_SEGMENTS {
+            self.flush();
+        }
+    }
+
+    /// Write out the queued segments, as an arc if they fit one.
+    fn flush(&mut self) {
+        let run = std::mem::take(&mut self.run);
+        let arc = if run.len() >= MIN_SEGMENTS {
+            self.fit(&run)
+        } else {
+            None
+        };
+        let Some(arc) = arc else {
+            self.out.extend(run.into_iter().map(|segment| segment.line));
+            return;
+        };
+
+        let end = run.last().expect("run is not empty").end;
+        let extrusion: f64 = run.iter().map(|segment| segment.extrusion).sum();
+        let e = if self.absolute_extrusion {
+            self.start_extrusion + extrusion
+        } else {
+            extrusion
+        };
+        let mut line = format!(
+            "{} X{} Y{} I{} J{} E{}",
+            if arc.clockwise { "G2" } else { "G3" },
+            format_number(end.x, 3),
+            format_number(end.y, 3),
+            format_number(arc.center.x - self.start.x, 3),
+            format_number(arc.center.y - self.start.y, 3),
+            format_number(e, 5),
+        );
+        if let Some(feedrate) = &run[0].feedrate {
+            line.push_str(&format!(" F{}", feedrate));
+        }
+        self.out.push(line);
+    }
+
+    /// Find the arc through `run` (starting at `self.start`), if there's
+    /// one within tolerance.
+    fn fit(&self, run: &[Segment]) -> Option<Arc> {
+        let points: Vec<Point> = std::iter::once(self.start)
+            .chain(run.iter().map(|segment| segment.end))
+            .collect();
+        let (a, b, c) = (points[0], points[points.len() / 2], points[points.len() - 1]);
+
+        // Circumcenter of the first, middle and last points.
+        let d = 2.0 * (a.x * (b.y - c.y) + b.x * (c.y - a.y) + c.x * (a.y - b.y));
+        if d.abs() < 1e-9 {
+            return None;
+        }
+        let a2 = a.x * a.x + a.y * a.y;
+        let b2 = b.x * b.x + b.y * b.y;
+        let c2 = c.x * c.x + c.y * c.y;
+        let center = Point {
+            x: (a2 * (b.y - c.y) + b2 * (c.y - a.y) + c2 * (a.y - b.y)) / d,
+            y: (a2 * (c.x - b.x) + b2 * (a.x - c.x) + c2 * (b.x - a.x)) / d,
+        };
+        let radius = center.distance(&a);
+        if radius > self.config.max_radius_mm {
+            return None;
+        }
+
+        let tolerance = self.config.tolerance_mm;
+        let mut swept = 0.0;
+        let mut clockwise = None;
+        for pair in points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let midpoint = Point {
+                x: (from.x + to.x) / 2.0,
+                y: (from.y + to.y) / 2.0,
+            };
+            if (center.distance(&to) - radius).abs() > tolerance
+                || (center.distance(&midpoint) - radius).abs() > tolerance
+            {
+                return None;
+            }
+
+            // All turning the same way, and less than a full circle.
+            let cross = (from.x - center.x) * (to.y - center.y) - (from.y - center.y) * (to.x - center.x);
+            let dot = (from.x - center.x) * (to.x - center.x) + (from.y - center.y) * (to.y - center.y);
+            let is_clockwise = cross < 0.0;
+            if *clockwise.get_or_insert(is_clockwise) != is_clockwise {
+                return None;
+            }
+            swept += cross.atan2(dot).abs();
+        }
+        if swept >= TAU {
+            return None;
+        }
+
+        // Even extrusion along the whole arc.
+        let rates: Vec<f64> = points
+            .windows(2)
+            .zip(run)
+            .map(|(pair, segment)| segment.extrusion / pair[0].distance(&pair[1]))
+            .collect();
+        let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+        if rates
+            .iter()
+            .any(|rate| (rate - mean).abs() > mean * EXTRUSION_TOLERANCE)
+        {
+            return None;
+        }
+
+        Some(Arc {
+            center,
+            clockwise: clockwise?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A quarter circle of radius 10 around (50, 50), anticlockwise from
+    /// (60, 50) to (50, 60), in `segments` moves extruding 0.1 each.
+    fn quarter_circle(segments: usize) -> String {
+        let mut gcode = "G90\nM83\nG1 X60 Y50 F3000\n".to_owned();
+        for i in 1..=segments {
+            let angle = std::f64::consts::FRAC_PI_2 * i as f64 / segments as f64;
+            gcode.push_str(&format!(
+                "G1 X{:.4} Y{:.4} E0.1\n",
+                50.0 + 10.0 * angle.cos(),
+                50.0 + 10.0 * angle.sin()
+            ));
+        }
+        gcode
+    }
+
+    #[test]
+    fn test_fit_quarter_circle() {
+        let fitted = ArcFitting::default().fit(&quarter_circle(16));
+        assert_eq!(fitted, "G90\nM83\nG1 X60 Y50 F3000\nG3 X50 Y60 I-10 J0 E1.6\n");
+    }
+
+    #[test]
+    fn test_fit_absolute_extrusion() {
+        let gcode = quarter_circle(16).replace("M83", "M82\nG92 E5");
+        let mut e = 5.0;
+        let gcode: String = gcode
+            .lines()
+            .map(|line| match line.strip_suffix(" E0.1") {
+                Some(line) => {
+                    e += 0.1;
+                    format!("{} E{:.4}\n", line, e)
+                }
+                None => format!("{}\n", line),
+            })
+            .collect();
+
+        let fitted = ArcFitting::default().fit(&gcode);
+        assert!(fitted.ends_with("G3 X50 Y60 I-10 J0 E6.6\n"), "{}", fitted);
+    }
+
+    #[test]
+    fn test_fit_leaves_lines_alone() {
+        let gcode = "G90\nM83\nG1 X0 Y0\nG1 X10 Y0 E1\nG1 X20 Y0 E1\nG1 X30 Y0 E1\nG1 X40 Y0 E1\n";
+        assert_eq!(ArcFitting::default().fit(gcode), gcode);
+
+        // Comments, and too few segments, are left as they are too.
+        let gcode = "G1 X10 Y0 E1 ; perimeter\nG1 X20 Y5 E1\n";
+        assert_eq!(ArcFitting::default().fit(gcode), gcode);
+    }
+
+    #[test]
+    fn test_fit_then_line() {
+        let mut gcode = quarter_circle(16);
+        gcode.push_str("G1 X40 Y60 E0.1\nG1 X30 Y60 E0.1\n");
+
+        let fitted = ArcFitting::default().fit(&gcode);
+        assert!(
+            fitted.ends_with("G3 X50 Y60 I-10 J0 E1.6\nG1 X40 Y60 E0.1\nG1 X30 Y60 E0.1\n"),
+            "{}",
+            fitted
+        );
+    }
+}
@@ -1,14 +1,25 @@
 //! This module contains support for printing to gcode based 3D printers
 //! over some [AsyncRead]/[AsyncWrite] traited object.
 
+mod arcs;
+pub mod sender;
+mod temperature;
+#[cfg(test)]
+pub(crate) mod testing;
+
 use std::{
     pin::Pin,
     task::{Context as TaskContext, Poll},
 };
 
 use anyhow::Result;
+pub use arcs::ArcFitting;
+pub use temperature::{InvalidTemperatureSteps, TemperatureSteps};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
 
+/// Comments slicers use to mark the start of a new layer.
+pub(crate) const LAYER_MARKERS: &[&str] = &["LAYER_CHANGE", "CHANGE_LAYER", "LAYER:"];
+
 /// Create a handle to some [tokio::io::AsyncWrite]
 pub struct Client<WriteT, ReadT>
 where
@@ -0,0 +1,493 @@
+//! Sending gcode to firmware line by line over a stream, such as a serial
+//! port or a TCP connection: each line has to be acknowledged with an `ok`
+//! before the firmware has room for more, and may have to be sent again if
+//! it arrived garbled.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use super::Client;
+
+/// How lines are sent to the firmware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SenderOptions {
+    /// How many lines may be sent before the first of them is
+    /// acknowledged. 1 waits for each line to be acknowledged before
+    /// sending the next, which every firmware copes with; more keeps the
+    /// firmware's buffer full, but needs to fit in it.
+    pub window: usize,
+
+    /// Send each line with a line number and checksum, so the firmware
+    /// can ask for garbled lines to be sent again.
+    pub line_numbers: bool,
+}
+
+impl Default for SenderOptions {
+    fn default() -> Self {
+        Self {
+            window: 1,
+            line_numbers: false,
+        }
+    }
+}
+
+/// A line waiting to be sent, or waiting to be acknowledged.
+#[derive(Clone, Debug, PartialEq)]
+struct Line {
+    /// Line number, if lines are numbered.
+    number: Option<u64>,
+
+    /// The line as written, including its number and checksum.
+    text: String,
+
+    /// `false` for lines the sender adds itself, which aren't counted as
+    /// acknowledged.
+    counted: bool,
+}
+
+/// What the firmware said in reply to a line.
+#[derive(Clone, Debug, PartialEq)]
+enum Response {
+    /// A line was acknowledged, and there's room for another.
+    Ok,
+
+    /// Lines from this number on have to be sent again.
+    Resend(u64),
+
+    /// The firmware has stopped, and won't take any more lines.
+    Fatal(String),
+
+    /// Anything else, such as temperature reports or `busy` keepalives.
+    Other,
+}
+
+impl Response {
+    fn parse(line: &str) -> Self {
+        let line = line.trim();
+
+        if line == "ok" || line.starts_with("ok ") {
+            return Self::Ok;
+        }
+
+        // Marlin says `Resend: 12`, Repetier says `rs 12`.
+        let resend = line
+            .strip_prefix("Resend:")
+            .or_else(|| line.strip_prefix("rs "))
+            .and_then(|number| number.trim().parse().ok());
+        if let Some(number) = resend {
+            return Self::Resend(number);
+        }
+
+        if line.starts_with("!!") || (line.starts_with("Error:") && (line.contains("halted") || line.contains("kill")))
+        {
+            return Self::Fatal(line.to_owned());
+        }
+
+        Self::Other
+    }
+}
+
+/// Checksum of a numbered line, as firmware expects it after the `*`.
+fn checksum(line: &str) -> u8 {
+    line.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+/// Wait for the firmware to say it's started, which it does after it's
+/// reset (as many boards are when the port is opened).
+pub async fn wait_for_start<WriteT, ReadT>(client: &mut Client<WriteT, ReadT>) -> Result<()>
+where
+    ReadT: AsyncRead + Unpin,
+    WriteT: AsyncWrite + Unpin,
+{
+    loop {
+        let mut line = String::new();
+        if client.get_read().read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed while waiting for the firmware to start");
+        }
+        // Use ends with because sometimes we may still have some data left on the buffer
+        if line.trim().ends_with("start") {
+            return Ok(());
+        }
+    }
+}
+
+/// Ask the firmware what it is, with `M115`, returning the name (and
+/// version) it gives, such as `Marlin 2.1.2 (Github)`.
+pub async fn firmware_name<WriteT, ReadT>(client: &mut Client<WriteT, ReadT>) -> Result<Option<String>>
+where
+    ReadT: AsyncRead + Unpin,
+    WriteT: AsyncWrite + Unpin,
+{
+    client.write_all(b"M115\r\n").await?;
+    client.flush().await?;
+
+    let mut name = None;
+    loop {
+        let mut line = String::new();
+        if client.get_read().read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed while asking the firmware what it is");
+        }
+        if let Some(found) = parse_firmware_name(&line) {
+            name = Some(found);
+        }
+        if Response::parse(&line) == Response::Ok {
+            return Ok(name);
+        }
+    }
+}
+
+/// Pick the firmware's name out of its reply to `M115`, which runs up to
+/// the next field, such as `SOURCE_CODE_URL:`.
+fn parse_firmware_name(line: &str) -> Option<String> {
+    let (_, rest) = line.trim().split_once("FIRMWARE_NAME:")?;
+    let is_field = |word: &str| {
+        word.split_once(':')
+            .is_some_and(|(key, _)| !key.is_empty() && key.chars().all(|c| c.is_ascii_uppercase() || c == '_'))
+    };
+    let name = rest
+        .split(' ')
+        .take_while(|word| !is_field(word))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+/// Sends lines of gcode to firmware, keeping track of which have been
+/// acknowledged, and sending lines again when the firmware asks.
+///
+/// The [Client] is passed to each call, rather than held, so that it can
+/// be shared with anything else that needs it between lines (such as an
+/// emergency stop).
+#[derive(Debug)]
+pub struct Sender {
+    options: SenderOptions,
+
+    /// Lines waiting to be sent, including any to be sent again.
+    queue: VecDeque<Line>,
+
+    /// Lines sent, but not yet acknowledged, oldest first.
+    in_flight: VecDeque<Line>,
+
+    /// Number the next numbered line gets. `None` until the firmware's
+    /// line number has been reset.
+    next_number: Option<u64>,
+
+    /// Acknowledgements still to come for lines the firmware rejected,
+    /// which don't acknowledge anything we're waiting on.
+    stale_oks: usize,
+
+    /// How many lines have been acknowledged.
+    acked: usize,
+}
+
+impl Sender {
+    /// Create a new [Sender].
+    pub fn new(options: SenderOptions) -> Self {
+        Self {
+            options: SenderOptions {
+                window: options.window.max(1),
+                ..options
+            },
+            queue: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            next_number: None,
+            stale_oks: 0,
+            acked: 0,
+        }
+    }
+
+    /// How many lines the firmware has acknowledged.
+    pub fn acked(&self) -> usize {
+        self.acked
+    }
+
+    /// Send a gcode command, waiting first for room in the window.
+    /// The command must already be stripped of comments.
+    pub async fn send<WriteT, ReadT>(&mut self, client: &mut Client<WriteT, ReadT>, command: &str) -> Result<()>
+    where
+        ReadT: AsyncRead + Unpin,
+        WriteT: AsyncWrite + Unpin,
+    {
+        if self.options.line_numbers {
+            let number = match self.next_number {
+                Some(number) => number,
+                None => {
+                    // Start numbering from 1, whatever the firmware was
+                    // expecting last.
+                    self.queue.push_back(Line {
+                        number: None,
+                        text: "M110 N0".to_owned(),
+                        counted: false,
+                    });
+                    1
+                }
+            };
+            self.next_number = Some(number + 1);
+
+            let numbered = format!("N{} {}", number, command);
+            self.queue.push_back(Line {
+                number: Some(number),
+                text: format!("{}*{}", numbered, checksum(&numbered)),
+                counted: true,
+            });
+        } else {
+            self.queue.push_back(Line {
+                number: None,
+                text: command.to_owned(),
+                counted: true,
+            });
+        }
+
+        while !self.queue.is_empty() {
+            self.pump(client).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for every line sent to be acknowledged.
+    pub async fn flush<WriteT, ReadT>(&mut self, client: &mut Client<WriteT, ReadT>) -> Result<()>
+    where
+        ReadT: AsyncRead + Unpin,
+        WriteT: AsyncWrite + Unpin,
+    {
+        while !self.queue.is_empty() || !self.in_flight.is_empty() {
+            if self.queue.is_empty() {
+                self.receive(client).await?;
+            } else {
+                self.pump(client).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the next queued line if there's room for it, otherwise wait
+    /// for a reply.
+    async fn pump<WriteT, ReadT>(&mut self, client: &mut Client<WriteT, ReadT>) -> Result<()>
+    where
+        ReadT: AsyncRead + Unpin,
+        WriteT: AsyncWrite + Unpin,
+    {
+        if self.in_flight.len() >= self.options.window {
+            return self.receive(client).await;
+        }
+
+        let Some(line) = self.queue.pop_front() else {
+            return Ok(());
+        };
+        tracing::trace!(line = line.text, "writing");
+        client.write_all(format!("{}\r\n", line.text).as_bytes()).await?;
+        client.flush().await?;
+        self.in_flight.push_back(line);
+        Ok(())
+    }
+
+    /// Read and act on one line from the firmware.
+    async fn receive<WriteT, ReadT>(&mut self, client: &mut Client<WriteT, ReadT>) -> Result<()>
+    where
+        ReadT: AsyncRead + Unpin,
+        WriteT: AsyncWrite + Unpin,
+    {
+        let mut reply = String::new();
+        if client.get_read().read_line(&mut reply).await? == 0 {
+            anyhow::bail!("connection closed with {} lines unacknowledged", self.in_flight.len());
+        }
+        tracing::trace!(reply = reply.trim(), "received");
+
+        match Response::parse(&reply) {
+            Response::Ok => {
+                if self.stale_oks > 0 {
+                    self.stale_oks -= 1;
+                } else if let Some(line) = self.in_flight.pop_front() {
+                    self.acked += usize::from(line.counted);
+                }
+            }
+            Response::Resend(_) if self.stale_oks > 0 => {
+                // The firmware rejecting the lines sent after one it
+                // already asked for again, which are being sent again
+                // anyway.
+            }
+            Response::Resend(number) => {
+                // Everything before the line asked for got through; the
+                // rest is sent again, in order, ahead of anything new. The
+                // firmware still acknowledges each line it rejected.
+                while self
+                    .in_flight
+                    .front()
+                    .is_some_and(|line| line.number.is_none_or(|n| n < number))
+                {
+                    if let Some(line) = self.in_flight.pop_front() {
+                        self.acked += usize::from(line.counted);
+                    }
+                }
+                let rejected = std::mem::take(&mut self.in_flight);
+                tracing::warn!(line = number, lines = rejected.len(), "firmware asked for lines again");
+                self.stale_oks += rejected.len();
+                for line in rejected.into_iter().rev() {
+                    self.queue.push_front(line);
+                }
+            }
+            Response::Fatal(message) => anyhow::bail!("firmware stopped: {}", message),
+            Response::Other => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::testing::MockPrinter;
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(Response::parse("ok\n"), Response::Ok);
+        assert_eq!(Response::parse("ok T:210.0 /210.0 B:60.0 /60.0"), Response::Ok);
+        assert_eq!(Response::parse("Resend: 12"), Response::Resend(12));
+        assert_eq!(Response::parse("rs 12"), Response::Resend(12));
+        assert_eq!(Response::parse("echo:busy: processing"), Response::Other);
+        assert_eq!(
+            Response::parse("Error:checksum mismatch, Last Line: 3"),
+            Response::Other
+        );
+        assert!(matches!(
+            Response::parse("Error:Printer halted. kill() called!"),
+            Response::Fatal(_)
+        ));
+        assert!(matches!(
+            Response::parse("!! Lost communication with MCU"),
+            Response::Fatal(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_firmware_name() {
+        assert_eq!(
+            parse_firmware_name(
+                "FIRMWARE_NAME:Marlin 2.1.2.1 (Jun 12 2023 12:00:00) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0\n"
+            ),
+            Some("Marlin 2.1.2.1 (Jun 12 2023 12:00:00)".to_owned())
+        );
+        assert_eq!(
+            parse_firmware_name("FIRMWARE_NAME:Prusa-Firmware 3.13.3 based on Marlin FIRMWARE_URL:https://github.com/prusa3d/Prusa-Firmware PROTOCOL_VERSION:1.0"),
+            Some("Prusa-Firmware 3.13.3 based on Marlin".to_owned())
+        );
+        assert_eq!(parse_firmware_name("FIRMWARE_NAME: PROTOCOL_VERSION:1.0"), None);
+        assert_eq!(parse_firmware_name("ok"), None);
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum("N1 G28"), 18);
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (mut client, printer) = MockPrinter::new().with_temperatures().client();
+
+        let mut sender = Sender::new(SenderOptions::default());
+        for command in ["G28", "G1 X10 Y10", "M400"] {
+            sender.send(&mut client, command).await.unwrap();
+        }
+        sender.flush(&mut client).await.unwrap();
+        assert_eq!(sender.acked(), 3);
+
+        drop(client);
+        assert_eq!(printer.await.unwrap(), vec!["G28", "G1 X10 Y10", "M400"]);
+    }
+
+    #[tokio::test]
+    async fn test_send_window() {
+        // Each line is only acknowledged once the next has arrived, which
+        // would never finish if lines were sent one at a time.
+        let (mut client, printer) = MockPrinter::new().with_lag(1).client();
+
+        let mut sender = Sender::new(SenderOptions {
+            window: 2,
+            ..Default::default()
+        });
+        for command in ["G28", "G1 X10", "G1 Y10", "M400"] {
+            sender.send(&mut client, command).await.unwrap();
+        }
+        tokio::time::timeout(std::time::Duration::from_secs(5), sender.flush(&mut client))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sender.acked(), 4);
+
+        drop(client);
+        assert_eq!(printer.await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_send_resend() {
+        let (mut client, printer) = MockPrinter::new().with_garbled(2).client();
+
+        let mut sender = Sender::new(SenderOptions {
+            line_numbers: true,
+            ..Default::default()
+        });
+        for command in ["G28", "G1 X10", "G1 Y10"] {
+            sender.send(&mut client, command).await.unwrap();
+        }
+        sender.flush(&mut client).await.unwrap();
+        assert_eq!(sender.acked(), 3);
+
+        drop(client);
+        assert_eq!(
+            printer.await.unwrap(),
+            vec!["M110 N0", "N1 G28*18", "N2 G1 X10*83", "N2 G1 X10*83", "N3 G1 Y10*83"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_resend_window() {
+        // The lines sent after the garbled one are rejected too, and have
+        // to be sent again along with it, once each.
+        let (mut client, printer) = MockPrinter::new().with_garbled(2).client();
+
+        let mut sender = Sender::new(SenderOptions {
+            window: 3,
+            line_numbers: true,
+        });
+        for command in ["G28", "G1 X10", "G1 Y10", "G1 Z10"] {
+            sender.send(&mut client, command).await.unwrap();
+        }
+        sender.flush(&mut client).await.unwrap();
+        assert_eq!(sender.acked(), 4);
+
+        drop(client);
+        let received = printer.await.unwrap();
+        for number in 1..=4 {
+            let prefix = format!("N{} ", number);
+            let expected = if number == 1 { 1 } else { 2 };
+            assert_eq!(
+                received.iter().filter(|line| line.starts_with(&prefix)).count(),
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_closed() {
+        let (host, device) = tokio::io::duplex(64);
+        let (read, write) = tokio::io::split(host);
+        let mut client = Client::new(write, read);
+        drop(device);
+
+        let mut sender = Sender::new(SenderOptions::default());
+        assert!(sender.send(&mut client, "G28").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_halted() {
+        let (mut client, _printer) = MockPrinter::new().with_halt_after(1).client();
+
+        let mut sender = Sender::new(SenderOptions::default());
+        sender.send(&mut client, "G28").await.unwrap();
+        sender.send(&mut client, "G1 X10").await.unwrap();
+        assert!(sender.flush(&mut client).await.is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! Stepping the nozzle temperature as a print goes up, such as for a
+//! temperature tower: an `M104` is added to the sliced gcode at the first
+//! layer of each step.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{Cursor, Read, Write as _},
+};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::LAYER_MARKERS;
+use crate::TemporaryFile;
+
+/// How far below a step's height a layer can be and still be on it, in
+/// millimeters, so layers exactly at the top of a step aren't put on the
+/// next one by rounding.
+const HEIGHT_TOLERANCE_MM: f64 = 0.001;
+
+/// Hottest a step can take the nozzle, in Celsius, however many steps up
+/// a print goes.
+const MAX_CELSIUS: u32 = 500;
+
+/// Comments slicers use to say what height a new layer is at, such as
+/// PrusaSlicer's `;Z:0.2` and Orca Slicer's `; Z_HEIGHT: 0.2`.
+const LAYER_HEIGHT_MARKERS: &[&str] = &["Z_HEIGHT:", "Z:"];
+
+/// Change the nozzle temperature every so far up the print.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TemperatureSteps {
+    /// The nozzle temperature the first step is printed at, in Celsius.
+    pub start_celsius: u32,
+
+    /// How much the nozzle temperature changes at each step, in Celsius.
+    /// Negative to step down.
+    pub step_celsius: i32,
+
+    /// How tall each step is, in millimeters. Must be more than 0.
+    pub every_mm: f64,
+}
+
+/// Temperature steps which can't be printed, since they aren't some height
+/// apart.
+#[derive(Clone, Copy, Debug, PartialEq, thiserror::Error)]
+#[error("temperature steps must be more than 0mm apart, not {every_mm}mm")]
+pub struct InvalidTemperatureSteps {
+    /// How tall each step was asked to be, in millimeters.
+    pub every_mm: f64,
+}
+
+impl TemperatureSteps {
+    /// Check that the steps are some height apart, as they must be to be
+    /// printed.
+    pub fn check(&self) -> Result<(), InvalidTemperatureSteps> {
+        if self.every_mm.is_finite() && self.every_mm > 0.0 {
+            Ok(())
+        } else {
+            Err(InvalidTemperatureSteps {
+                every_mm: self.every_mm,
+            })
+        }
+    }
+
+    /// Return the nozzle temperature to print a layer at `z` millimeters
+    /// at, in Celsius, no hotter than [MAX_CELSIUS].
+    pub fn temperature_at(&self, z: f64) -> u32 {
+        let step = ((z - HEIGHT_TOLERANCE_MM) / self.every_mm).floor().max(0.0) as i64;
+        step.saturating_mul(i64::from(self.step_celsius))
+            .saturating_add(i64::from(self.start_celsius))
+            .clamp(0, i64::from(MAX_CELSIUS)) as u32
+    }
+
+    /// Add the temperature changes to the gcode file `file`, rewriting it
+    /// in place.
+    pub async fn apply_gcode(&self, file: &mut TemporaryFile) -> Result<()> {
+        let gcode = tokio::fs::read_to_string(file.path()).await?;
+        tokio::fs::write(file.path(), self.insert(&gcode)).await?;
+        file.reopen().await
+    }
+
+    /// Add the temperature changes to each plate's gcode in the sliced 3MF
+    /// project `file`, rewriting it in place.
+    pub async fn apply_project(&self, file: &mut TemporaryFile) -> Result<()> {
+        let project = tokio::fs::read(file.path()).await?;
+        tokio::fs::write(file.path(), self.insert_into_project(&project)?).await?;
+        file.reopen().await
+    }
+
+    /// Add an `M104` to `gcode` at the first layer of each step.
+    pub fn insert(&self, gcode: &str) -> String {
+        let mut out = String::with_capacity(gcode.len());
+        let mut new_layer = false;
+        let mut current = None;
+        let mut steps = 0;
+        for line in gcode.split_inclusive('\n') {
+            let (command, comment) = match line.split_once(';') {
+                Some((command, comment)) => (command.trim(), Some(comment.trim())),
+                None => (line.trim(), None),
+            };
+
+            if let Some(comment) = comment {
+                if LAYER_MARKERS.iter().any(|marker| comment.starts_with(marker)) {
+                    new_layer = true;
+                }
+            }
+
+            if new_layer {
+                if let Some(z) = layer_height(command, comment) {
+                    new_layer = false;
+                    let temperature = self.temperature_at(z);
+                    if current != Some(temperature) {
+                        let _ = writeln!(out, "M104 S{} ; temperature step", temperature);
+                        current = Some(temperature);
+                        steps += 1;
+                    }
+                }
+            }
+            out.push_str(line);
+        }
+
+        tracing::info!(steps = steps, "stepped nozzle temperature");
+        out
+    }
+
+    /// Add the temperature changes to each plate's gcode in a sliced 3MF
+    /// project, updating the checksums printers check the gcode against.
+    fn insert_into_project(&self, project: &[u8]) -> Result<Vec<u8>> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(project))?;
+
+        let mut plates = HashMap::new();
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            let name = file.name().to_owned();
+            if name.starts_with("Metadata/plate_") && name.ends_with(".gcode") {
+                let mut gcode = String::new();
+                file.read_to_string(&mut gcode)?;
+                plates.insert(name, self.insert(&gcode));
+            }
+        }
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index)?;
+            let name = file.name().to_owned();
+            if let Some(gcode) = plates.get(&name) {
+                zip.start_file(name, options)?;
+                zip.write_all(gcode.as_bytes())?;
+            } else if let Some(gcode) = name.strip_suffix(".md5").and_then(|gcode| plates.get(gcode)) {
+                let md5 = openssl::hash::hash(openssl::hash::MessageDigest::md5(), gcode.as_bytes())?;
+                zip.start_file(name, options)?;
+                zip.write_all(
+                    md5.iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect::<String>()
+                        .as_bytes(),
+                )?;
+            } else {
+                zip.raw_copy_file(file)?;
+            }
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+/// Return the height of a new layer, if `command` or `comment` says it:
+/// from the slicer's comment, or failing that, the move up to it.
+fn layer_height(command: &str, comment: Option<&str>) -> Option<f64> {
+    if let Some(comment) = comment {
+        for marker in LAYER_HEIGHT_MARKERS {
+            if let Some(z) = comment.strip_prefix(marker) {
+                return z.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut words = command.split_whitespace();
+    if !matches!(words.next(), Some("G0" | "G1")) {
+        return None;
+    }
+    words
+        .find_map(|word| word.strip_prefix(['Z', 'z']))
+        .and_then(|z| z.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: TemperatureSteps = TemperatureSteps {
+        start_celsius: 230,
+        step_celsius: -5,
+        every_mm: 10.0,
+    };
+
+    #[test]
+    fn test_temperature_at() {
+        assert_eq!(STEPS.temperature_at(0.2), 230);
+        assert_eq!(STEPS.temperature_at(10.0), 230);
+        assert_eq!(STEPS.temperature_at(10.2), 225);
+        assert_eq!(STEPS.temperature_at(49.8), 210);
+        assert_eq!(STEPS.temperature_at(1000.0), 0);
+
+        let steps = TemperatureSteps {
+            start_celsius: 200,
+            step_celsius: i32::MAX,
+            every_mm: 0.2,
+        };
+        assert_eq!(steps.temperature_at(0.2), 200);
+        assert_eq!(steps.temperature_at(1e12), MAX_CELSIUS);
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(STEPS.check(), Ok(()));
+        for every_mm in [0.0, -10.0, f64::NAN, f64::INFINITY] {
+            let steps = TemperatureSteps { every_mm, ..STEPS };
+            assert!(steps.check().is_err(), "{} should be refused", every_mm);
+            // Even unchecked, they don't overflow.
+            assert!(steps.temperature_at(10.0) <= MAX_CELSIUS);
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        // PrusaSlicer says each layer's height in a comment, with a z-hop
+        // of its own within the layer.
+        let gcode = "G28\nG1 Z5\n;LAYER_CHANGE\n;Z:0.2\nG1 Z0.2\nG1 X1 E1\n;LAYER_CHANGE\n;Z:10\nG1 Z10\nG1 Z10.4\n;LAYER_CHANGE\n;Z:10.2\nG1 Z10.2\nG1 X2 E2\n";
+        assert_eq!(
+            STEPS.insert(gcode),
+            "G28\nG1 Z5\n;LAYER_CHANGE\nM104 S230 ; temperature step\n;Z:0.2\nG1 Z0.2\nG1 X1 E1\n;LAYER_CHANGE\n;Z:10\nG1 Z10\nG1 Z10.4\n;LAYER_CHANGE\nM104 S225 ; temperature step\n;Z:10.2\nG1 Z10.2\nG1 X2 E2\n"
+        );
+
+        // Orca Slicer too, with its own markers.
+        let gcode = "; CHANGE_LAYER\n; Z_HEIGHT: 20.2\nG1 Z.6\n";
+        assert_eq!(
+            STEPS.insert(gcode),
+            "; CHANGE_LAYER\nM104 S220 ; temperature step\n; Z_HEIGHT: 20.2\nG1 Z.6\n"
+        );
+
+        // Others only say where a layer starts.
+        let gcode = ";LAYER:0\nG0 F3000 Z30.2\n";
+        assert_eq!(
+            STEPS.insert(gcode),
+            ";LAYER:0\nM104 S215 ; temperature step\nG0 F3000 Z30.2\n"
+        );
+    }
+
+    #[test]
+    fn test_insert_into_project() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in [
+            ("3D/3dmodel.model", "<model/>"),
+            ("Metadata/plate_1.gcode", ";LAYER_CHANGE\n;Z:0.2\nG1 Z0.2\n"),
+            ("Metadata/plate_1.gcode.md5", "stale"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let project = zip.finish().unwrap().into_inner();
+
+        let project = STEPS.insert_into_project(&project).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(project)).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        let gcode = read("Metadata/plate_1.gcode");
+        assert_eq!(gcode, ";LAYER_CHANGE\nM104 S230 ; temperature step\n;Z:0.2\nG1 Z0.2\n");
+        let md5 = openssl::hash::hash(openssl::hash::MessageDigest::md5(), gcode.as_bytes()).unwrap();
+        assert_eq!(
+            read("Metadata/plate_1.gcode.md5"),
+            md5.iter().map(|byte| format!("{:02X}", byte)).collect::<String>()
+        );
+        assert_eq!(read("3D/3dmodel.model"), "<model/>");
+    }
+}
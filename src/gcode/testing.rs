@@ -0,0 +1,206 @@
+//! A fake Marlin printer, connected over an in-memory stream, for testing
+//! code which talks to gcode firmware without any hardware.
+
+use std::collections::HashSet;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf},
+    task::JoinHandle,
+};
+
+use super::Client;
+
+/// What the printer says it is, in reply to `M115`.
+pub(crate) const FIRMWARE_INFO: &str = "FIRMWARE_NAME:Marlin 2.1.2 (Github) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 MACHINE_TYPE:Mock EXTRUDER_COUNT:1";
+
+/// What Marlin says when it's asked for an unexpected line number.
+const RESEND_ERROR: &str = "Error:Line Number is not Last Line Number+1, Last Line: ";
+
+/// A fake Marlin printer, which replies to each line it's sent according
+/// to how it's been set up. Numbered lines have their line number and
+/// checksum checked, as Marlin would.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MockPrinter {
+    /// Say `start` as soon as it's connected, as boards do once they've
+    /// been reset by the port opening.
+    start: bool,
+
+    /// Report temperatures along with each `ok`.
+    temperatures: bool,
+
+    /// Numbered lines to act as if they arrived garbled the first time
+    /// they're sent.
+    garbled: HashSet<u64>,
+
+    /// Only acknowledge each line once this many more have arrived, as if
+    /// they were waiting in the planner. `M400` is acknowledged along
+    /// with everything before it.
+    lag: usize,
+
+    /// Halt after acknowledging this many lines.
+    halt_after: Option<usize>,
+}
+
+impl MockPrinter {
+    /// Create a new [MockPrinter], which acknowledges every line as soon
+    /// as it arrives.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Say `start` as soon as it's connected.
+    pub(crate) fn with_start(mut self) -> Self {
+        self.start = true;
+        self
+    }
+
+    /// Report temperatures along with each `ok`, as Marlin does with
+    /// `ADVANCED_OK` or auto-reporting turned on.
+    pub(crate) fn with_temperatures(mut self) -> Self {
+        self.temperatures = true;
+        self
+    }
+
+    /// Ask for the numbered line `number` to be sent again the first time
+    /// it's sent, as if it arrived garbled.
+    pub(crate) fn with_garbled(mut self, number: u64) -> Self {
+        self.garbled.insert(number);
+        self
+    }
+
+    /// Only acknowledge each line once `lines` more have arrived.
+    pub(crate) fn with_lag(mut self, lines: usize) -> Self {
+        self.lag = lines;
+        self
+    }
+
+    /// Halt after acknowledging `lines` lines, and refuse everything after.
+    pub(crate) fn with_halt_after(mut self, lines: usize) -> Self {
+        self.halt_after = Some(lines);
+        self
+    }
+
+    /// Connect to the printer, returning the stream to talk to it over,
+    /// and a handle which resolves to every line it was sent once the
+    /// stream is dropped.
+    pub(crate) fn connect(self) -> (DuplexStream, JoinHandle<Vec<String>>) {
+        let (host, device) = tokio::io::duplex(4096);
+        (host, tokio::spawn(self.run(device)))
+    }
+
+    /// Connect to the printer with a [Client].
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn client(
+        self,
+    ) -> (
+        Client<WriteHalf<DuplexStream>, ReadHalf<DuplexStream>>,
+        JoinHandle<Vec<String>>,
+    ) {
+        let (stream, received) = self.connect();
+        let (read, write) = tokio::io::split(stream);
+        (Client::new(write, read), received)
+    }
+
+    /// The `ok` sent to acknowledge a line.
+    fn ok(&self) -> &'static str {
+        if self.temperatures {
+            "ok T:210.00 /210.00 B:60.00 /60.00 @:64 B@:0\n"
+        } else {
+            "ok\n"
+        }
+    }
+
+    async fn run(mut self, device: DuplexStream) -> Vec<String> {
+        let (read, mut write) = tokio::io::split(device);
+        let mut lines = BufReader::new(read).lines();
+        let mut received = vec![];
+
+        if self.start {
+            let _ = write.write_all(b"start\necho:Marlin 2.1.2\n").await;
+        }
+
+        let mut last_number = 0;
+        let mut unacked = 0;
+        let mut acked = 0;
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            received.push(line.clone());
+            let mut reply = String::new();
+
+            if self.halt_after.is_some_and(|halt_after| acked >= halt_after) {
+                reply.push_str("Error:Printer halted. kill() called!\n");
+            } else {
+                match check_line(&line, last_number) {
+                    Err(error) => {
+                        reply.push_str(&format!("{}\nResend: {}\nok\n", error, last_number + 1));
+                    }
+                    Ok(Some(number)) if self.garbled.remove(&number) => {
+                        reply.push_str(&format!(
+                            "Error:checksum mismatch, Last Line: {}\nResend: {}\nok\n",
+                            last_number,
+                            last_number + 1
+                        ));
+                    }
+                    Ok(number) => {
+                        if let Some(number) = number {
+                            last_number = number;
+                        }
+                        if line.contains("M110") {
+                            last_number = 0;
+                        }
+
+                        if line.contains("M115") {
+                            reply.push_str(FIRMWARE_INFO);
+                            reply.push('\n');
+                        }
+
+                        unacked += 1;
+                        let flush = line.contains("M400");
+                        while unacked > 0 && (unacked > self.lag || flush) {
+                            reply.push_str(self.ok());
+                            unacked -= 1;
+                            acked += 1;
+                        }
+                    }
+                }
+            }
+
+            if write.write_all(reply.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+
+        received
+    }
+}
+
+/// Check a line's number and checksum, if it has them, returning its
+/// number, or the error Marlin would reply with.
+fn check_line(line: &str, last_number: u64) -> Result<Option<u64>, String> {
+    let Some(rest) = line.strip_prefix('N') else {
+        return Ok(None);
+    };
+
+    let Some((numbered, checksum)) = line.rsplit_once('*') else {
+        return Err(format!(
+            "Error:No Checksum with line number, Last Line: {}",
+            last_number
+        ));
+    };
+    let expected = numbered.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+    if checksum.trim().parse::<u8>().ok() != Some(expected) {
+        return Err(format!("Error:checksum mismatch, Last Line: {}", last_number));
+    }
+    if numbered.contains("M110") {
+        return Ok(None);
+    }
+
+    let number = rest
+        .split_once(' ')
+        .and_then(|(number, _)| number.parse::<u64>().ok())
+        .ok_or_else(|| format!("{}{}", RESEND_ERROR, last_number))?;
+    if number != last_number + 1 {
+        return Err(format!("{}{}", RESEND_ERROR, last_number));
+    }
+    Ok(Some(number))
+}
@@ -0,0 +1,163 @@
+//! The HTTP client shared by everything which makes requests out of the
+//! server, such as to Moonraker, webhooks and remote slicers, so they can
+//! all be configured in one place, such as to go through a proxy on a
+//! corporate network which intercepts TLS.
+
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The shared client, and what it was built from, once set with
+/// [set_http_config].
+static HTTP: OnceLock<Http> = OnceLock::new();
+
+/// How requests out of the server are made. Unless `proxy` is set, the
+/// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables are
+/// honored.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct HttpConfig {
+    /// URL of the proxy to send every request through, such as
+    /// `http://proxy.corp.example.com:3128`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// Hosts to reach directly rather than through `proxy`, comma
+    /// separated as in `NO_PROXY`, such as `localhost,.lab.example.com`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+
+    /// PEM file of certificate authorities to trust as well as the system's
+    /// own, such as the one a proxy which intercepts TLS signs with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Longest to wait to connect, in seconds.
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Longest any request may take, in seconds. Unset by default, since
+    /// uploading a large file to a machine can take a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    30
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            timeout_seconds: None,
+        }
+    }
+}
+
+struct Http {
+    config: HttpConfig,
+
+    /// Certificates read from `config.ca_bundle`.
+    certificates: Vec<reqwest::Certificate>,
+
+    client: reqwest::Client,
+}
+
+impl Http {
+    fn new(config: HttpConfig) -> Result<Self> {
+        let certificates = match &config.ca_bundle {
+            Some(path) => {
+                let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+                reqwest::Certificate::from_pem_bundle(&pem)
+                    .with_context(|| format!("invalid CA bundle {}", path.display()))?
+            }
+            None => vec![],
+        };
+        let client = builder(&config, &certificates)?.build()?;
+        Ok(Self {
+            config,
+            certificates,
+            client,
+        })
+    }
+}
+
+/// Return a builder for clients which make requests as `config` says,
+/// trusting `certificates` as well as the system's own.
+fn builder(config: &HttpConfig, certificates: &[reqwest::Certificate]) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder().connect_timeout(Duration::from_secs(config.connect_timeout_seconds));
+    if let Some(timeout) = config.timeout_seconds {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+    if let Some(proxy) = &config.proxy {
+        let proxy = reqwest::Proxy::all(proxy)
+            .with_context(|| format!("invalid proxy {:?}", proxy))?
+            .no_proxy(config.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string));
+        builder = builder.proxy(proxy);
+    }
+    for certificate in certificates {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    Ok(builder)
+}
+
+/// Set how requests out of the server are made. This can only be set once,
+/// before any requests are made; it returns an error if it's already been
+/// set, or if the proxy or CA bundle are invalid.
+pub fn set_http_config(config: HttpConfig) -> Result<()> {
+    let http = Http::new(config)?;
+    HTTP.set(http)
+        .map_err(|_| anyhow::anyhow!("the HTTP client is already configured"))
+}
+
+fn http() -> &'static Http {
+    HTTP.get_or_init(|| Http::new(HttpConfig::default()).expect("the default HTTP config is valid"))
+}
+
+/// Return the shared HTTP client, configured as set with
+/// [set_http_config], or with the defaults if it hasn't been. Clones share
+/// the same connection pool.
+pub fn http_client() -> reqwest::Client {
+    http().client.clone()
+}
+
+/// Return a builder configured as the shared HTTP client is, for requests
+/// which need settings of their own, such as a different redirect policy.
+pub(crate) fn http_client_builder() -> Result<reqwest::ClientBuilder> {
+    let http = http();
+    builder(&http.config, &http.certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_config() {
+        let config: HttpConfig = toml::from_str(
+            r#"
+proxy = "http://proxy.corp.example.com:3128"
+no_proxy = "localhost,.lab.example.com"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.connect_timeout_seconds, 30);
+        assert_eq!(config.timeout_seconds, None);
+        assert!(Http::new(config).is_ok());
+
+        assert!(Http::new(HttpConfig {
+            proxy: Some("not a url".to_owned()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(Http::new(HttpConfig {
+            ca_bundle: Some("/nonexistent/ca.pem".into()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
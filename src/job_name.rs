@@ -0,0 +1,115 @@
+//! Normalization of user-provided job names, so they're safe to pass along
+//! to printers, both as a display name and as part of an uploaded filename.
+
+/// Longest job name (in characters) we'll hand to a printer. Bambu and
+/// Moonraker both get unhappy well before filesystem limits are hit.
+pub const MAX_JOB_NAME_LEN: usize = 64;
+
+/// Longest extension kept when a job name is shortened. Anything after the
+/// last `.` which is longer is taken to be part of the name.
+const MAX_EXTENSION_LEN: usize = 8;
+
+/// Job name used when nothing usable is left after sanitization.
+const DEFAULT_JOB_NAME: &str = "job";
+
+/// Number of hex characters of randomness appended to uploaded filenames.
+const SUFFIX_LEN: usize = 8;
+
+/// Normalize a user-provided job name. Anything other than ASCII
+/// alphanumerics, `-`, `_` and `.` is replaced with `_`, runs of `_` are
+/// collapsed, leading and trailing separators are trimmed, and the result
+/// is capped at [MAX_JOB_NAME_LEN] characters. Names are shortened before
+/// their extension, if they have one, so a file's name still says what
+/// format it's in.
+pub fn sanitize_job_name(job_name: &str) -> String {
+    let mut sanitized = String::with_capacity(job_name.len());
+    for ch in job_name.chars() {
+        let ch = if ch.is_ascii_alphanumeric() || ch == '-' || ch == '.' {
+            ch
+        } else {
+            '_'
+        };
+        if ch == '_' && sanitized.ends_with('_') {
+            continue;
+        }
+        sanitized.push(ch);
+    }
+
+    let sanitized = sanitized.trim_matches(is_separator);
+    if sanitized.is_empty() {
+        return DEFAULT_JOB_NAME.to_owned();
+    }
+    if sanitized.len() <= MAX_JOB_NAME_LEN {
+        return sanitized.to_owned();
+    }
+
+    // Only ASCII is left, so lengths in bytes are lengths in characters.
+    match sanitized.rsplit_once('.') {
+        Some((stem, extension)) if extension.len() <= MAX_EXTENSION_LEN => format!(
+            "{}.{}",
+            stem[..MAX_JOB_NAME_LEN - extension.len() - 1].trim_end_matches(is_separator),
+            extension
+        ),
+        _ => sanitized[..MAX_JOB_NAME_LEN].trim_end_matches(is_separator).to_owned(),
+    }
+}
+
+/// Return whether `ch` separates words in a sanitized job name.
+fn is_separator(ch: char) -> bool {
+    ch == '_' || ch == '-' || ch == '.'
+}
+
+/// Build the filename to upload a job to a printer as. The job name is
+/// sanitized, and a short random suffix is added so that two jobs with the
+/// same name don't clobber each other on the printer.
+pub fn job_file_name(job_name: &str, extension: &str) -> String {
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    format!(
+        "{}_{}.{}",
+        sanitize_job_name(job_name),
+        &suffix[..SUFFIX_LEN],
+        extension
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_job_name() {
+        assert_eq!(sanitize_job_name("benchy"), "benchy");
+        assert_eq!(sanitize_job_name("my cool part v2.1"), "my_cool_part_v2.1");
+        assert_eq!(sanitize_job_name("  ../../etc/passwd "), "etc_passwd");
+        assert_eq!(sanitize_job_name("a / b \\ c"), "a_b_c");
+        assert_eq!(sanitize_job_name("ünïcødé"), "n_c_d");
+        assert_eq!(sanitize_job_name(""), "job");
+        assert_eq!(sanitize_job_name("???"), "job");
+        assert_eq!(sanitize_job_name(&"x".repeat(200)).len(), MAX_JOB_NAME_LEN);
+        assert_eq!(sanitize_job_name(&format!("{}_y", "x".repeat(63))), "x".repeat(63));
+    }
+
+    #[test]
+    fn test_sanitize_job_name_keeps_extension() {
+        let sanitized = sanitize_job_name(&format!("{}.3mf", "x".repeat(100)));
+        assert_eq!(sanitized, format!("{}.3mf", "x".repeat(MAX_JOB_NAME_LEN - 4)));
+
+        // Separators left at the end of the shortened name are trimmed.
+        let sanitized = sanitize_job_name(&format!("{} part.stl", "x".repeat(59)));
+        assert_eq!(sanitized, format!("{}.stl", "x".repeat(59)));
+
+        // Anything too long to be an extension is shortened as a name.
+        let sanitized = sanitize_job_name(&format!("v1.{}", "x".repeat(100)));
+        assert_eq!(sanitized, format!("v1.{}", "x".repeat(MAX_JOB_NAME_LEN - 3)));
+    }
+
+    #[test]
+    fn test_job_file_name() {
+        let first = job_file_name("my part", "gcode");
+        let second = job_file_name("my part", "gcode");
+        assert!(first.starts_with("my_part_"));
+        assert!(first.ends_with(".gcode"));
+        assert_eq!(first.len(), "my_part_".len() + SUFFIX_LEN + ".gcode".len());
+        assert_ne!(first, second);
+    }
+}
@@ -11,40 +11,70 @@
 //! This crate implements support for taking designed parts, and producing
 //! real-world constructions of those parts.
 
+mod accessories;
+mod analyze;
 mod any_machine;
 #[cfg(feature = "bambu")]
 pub mod bambu;
+mod camera;
 mod discover;
 mod file;
 #[cfg(feature = "formlabs")]
 pub mod formlabs;
 pub mod gcode;
+mod http_client;
+mod job_name;
 mod machine;
+mod machine_id;
 #[cfg(feature = "moonraker")]
 pub mod moonraker;
+mod network;
 pub mod noop;
+mod post_process;
+mod project;
 pub mod server;
 pub mod slicer;
+mod supervisor;
 mod sync;
+mod test_print;
 #[cfg(test)]
 mod tests;
 mod traits;
 #[cfg(feature = "serial")]
 pub mod usb;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+pub use accessories::{Accessory, AccessoryError, AccessoryKind};
+pub use analyze::{
+    analyze_stl, split_stl, stl_to_millimeters, AnalyzeParameters, MultipleBodies, PrintabilityReport,
+    PrintabilityRisk, StlUnits, SuspiciousUnits,
+};
 pub use any_machine::{AnyMachine, AnyMachineInfo};
+pub use camera::CameraStream;
 pub use discover::Discover;
-pub use file::TemporaryFile;
-pub use machine::Machine;
+pub use file::{set_spool_dir, spool_dir, TemporaryFile};
+pub use gcode::{InvalidTemperatureSteps, TemperatureSteps};
+pub use http_client::{http_client, set_http_config, HttpConfig};
+pub use job_name::{job_file_name, sanitize_job_name, MAX_JOB_NAME_LEN};
+pub use machine::{
+    ChamberPreheat, ChamberTooCold, Machine, MaterialMismatch, NoCompatiblePipeline, Reservation, SliceJob, SlicedFile,
+    StuckDetection,
+};
+pub use machine_id::canonical_machine_id;
+pub use network::{is_on_networks, NetworkFilter};
+pub use post_process::PostProcessor;
+pub use project::{package_project, PartSettings, ProjectPart};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-pub use slicer::AnySlicer;
+pub use slicer::{AnySlicer, SlicerInvocation, SlicerNotFound, UnsupportedOption};
+pub use supervisor::Supervisor;
 pub use sync::SharedMachine;
+pub use test_print::TestPrint;
 pub use traits::{
-    BuildOptions, Control, FdmHardwareConfiguration, Filament, FilamentMaterial, GcodeControl, GcodeSlicer,
-    GcodeTemporaryFile, HardwareConfiguration, MachineInfo, MachineMakeModel, MachineState, MachineType,
+    BambuPrintOptions, BuildOptions, Control, FdmHardwareConfiguration, FdmOptions, Filament, FilamentMaterial,
+    FormSlicer, FormTemporaryFile, GcodeControl, GcodeSlicer, GcodeTemporaryFile, HardwareConfiguration, LayerProgress,
+    MachineInfo, MachineMakeModel, MachineState, MachineType, PauseReason, ProcessOptions, SlaOptions,
     SlicerConfiguration, SuspendControl, TemperatureSensor, TemperatureSensorReading, TemperatureSensors,
     ThreeMfControl, ThreeMfSlicer, ThreeMfTemporaryFile,
 };
@@ -55,6 +85,21 @@ pub enum DesignFile {
     /// Stl ("stereolithography") 3D export, as seen in `.stl` (`model/stl`)
     /// files.
     Stl(PathBuf),
+
+    /// 3MF project, as seen in `.3mf` files, such as several parts packaged
+    /// with their own settings by [package_project].
+    ThreeMf(PathBuf),
+}
+
+impl DesignFile {
+    /// Return the design file at `path`, going by its extension: `.3mf`
+    /// files are projects, and anything else is taken to be STL.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("3mf") => Self::ThreeMf(path.to_path_buf()),
+            _ => Self::Stl(path.to_path_buf()),
+        }
+    }
 }
 
 /// Set of three values to represent the extent of a 3-D Volume. This contains
@@ -1,15 +1,189 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
 
 use crate::{
-    AnyMachine, AnySlicer, BuildOptions, Control, DesignFile, GcodeControl, GcodeSlicer, MachineInfo,
-    SlicerConfiguration, ThreeMfControl, ThreeMfSlicer,
+    gcode::ArcFitting, sanitize_job_name, slicer::remote::SliceFormat, Accessory, AccessoryError, AnyMachine,
+    AnySlicer, BuildOptions, Control, DesignFile, FdmOptions, FilamentMaterial, GcodeControl, GcodeSlicer,
+    GcodeTemporaryFile, HardwareConfiguration, MachineInfo, MachineState, MachineType, PostProcessor, ProcessOptions,
+    SlaOptions, SlicerConfiguration, SlicerInvocation, SuspendControl, TemperatureSensor, TemperatureSensorReading,
+    TemperatureSensors, TemporaryFile, ThreeMfSlicer, ThreeMfTemporaryFile,
 };
 
+/// How often the chamber temperature is checked while waiting for it to
+/// warm up.
+const CHAMBER_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The filament a job would be printed with isn't the material the slicer
+/// profile expects.
+#[derive(Copy, Clone, Debug, PartialEq, thiserror::Error)]
+#[error("material mismatch: slicer profile expects {expected:?}, but {loaded:?} is loaded")]
+pub struct MaterialMismatch {
+    /// The material the slicer profile is set up for.
+    pub expected: FilamentMaterial,
+
+    /// The material loaded in the machine.
+    pub loaded: FilamentMaterial,
+}
+
+/// A machine's slicer can't produce any format the machine accepts, so
+/// nothing can be built on it.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("no compatible pipeline: {machine} machines accept {accepted:?}, but the slicer produces {produced:?}")]
+pub struct NoCompatiblePipeline {
+    /// The type of machine, such as `Bambu`.
+    pub machine: &'static str,
+
+    /// The formats the machine accepts.
+    pub accepted: Vec<SliceFormat>,
+
+    /// The formats the slicer produces.
+    pub produced: Vec<SliceFormat>,
+}
+
+/// The chamber didn't warm up enough to start a job before the configured
+/// timeout.
+#[derive(Copy, Clone, Debug, PartialEq, thiserror::Error)]
+#[error("chamber too cold: {temperature_celsius}°C, but the job needs {min_temperature_celsius}°C")]
+pub struct ChamberTooCold {
+    /// The last chamber temperature read, in Celsius.
+    pub temperature_celsius: f64,
+
+    /// The chamber temperature the job needs, in Celsius.
+    pub min_temperature_celsius: f64,
+}
+
+/// Wait for the chamber to warm up before starting jobs in materials which
+/// warp in a cold chamber (ABS and ASA).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChamberPreheat {
+    /// The chamber temperature to wait for, in Celsius.
+    pub min_temperature_celsius: f64,
+
+    /// How long to wait for the chamber to warm up, in seconds, before
+    /// failing the job.
+    #[serde(default = "default_chamber_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    /// Gcode to run before waiting, such as a macro to turn on a chamber
+    /// heater. Without it, the chamber is left to warm up passively (such
+    /// as from the bed, or a previous job).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heater_gcode: Option<String>,
+}
+
+fn default_chamber_timeout_seconds() -> u64 {
+    30 * 60
+}
+
+impl ChamberPreheat {
+    /// Wait for the chamber to warm up, reading its temperature with
+    /// `chamber_temperature` every so often, so the machine needn't be held
+    /// on to in between. Returns a [ChamberTooCold] error if it doesn't
+    /// warm up in time. Machines without a chamber temperature sensor (for
+    /// which `chamber_temperature` returns `None`) aren't waited for.
+    pub async fn wait<ReadT, FutureT>(&self, mut chamber_temperature: ReadT) -> Result<()>
+    where
+        ReadT: FnMut() -> FutureT,
+        FutureT: Future<Output = Result<Option<f64>>>,
+    {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(self.timeout_seconds);
+        loop {
+            let Some(temperature_celsius) = chamber_temperature().await? else {
+                tracing::warn!("machine has no chamber temperature sensor, not waiting for it");
+                return Ok(());
+            };
+            if temperature_celsius >= self.min_temperature_celsius {
+                return Ok(());
+            }
+
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return Err(ChamberTooCold {
+                    temperature_celsius,
+                    min_temperature_celsius: self.min_temperature_celsius,
+                }
+                .into());
+            }
+
+            tracing::info!(
+                temperature_celsius = temperature_celsius,
+                min_temperature_celsius = self.min_temperature_celsius,
+                "waiting for chamber to warm up"
+            );
+            tokio::time::sleep(CHAMBER_POLL_INTERVAL.min(deadline - now)).await;
+        }
+    }
+}
+
+/// A hold on a machine by someone working on it by hand (such as changing
+/// filament, or calibrating), during which no jobs are sent to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Reservation {
+    /// Who holds the machine, if they said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+
+    /// Why the machine is held, if they said, such as `filament change`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// When the machine was reserved.
+    pub created_at: DateTime<Utc>,
+
+    /// When the reservation runs out, unless it's released before then.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Watch printing jobs for progress which has stopped advancing, which
+/// usually means a silent failure (such as a clog, or the part coming
+/// loose) rather than a slow layer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StuckDetection {
+    /// How long progress may stay the same while the machine is printing,
+    /// in seconds, before the job is flagged as possibly stuck.
+    #[serde(default = "default_stuck_window_seconds")]
+    pub window_seconds: u64,
+
+    /// Pause the machine when a job is flagged, rather than only raising
+    /// an alert.
+    #[serde(default)]
+    pub auto_pause: bool,
+}
+
+fn default_stuck_window_seconds() -> u64 {
+    30 * 60
+}
+
 /// Create a handle to a specific Machine which is capable of producing a 3D
 /// object in the real world from a specific [crate::DesignFile].
 pub struct Machine {
     machine: AnyMachine,
-    slicer: AnySlicer,
+    slicer: Arc<AnySlicer>,
+    display_name: Option<String>,
+    location: Option<String>,
+    labels: BTreeMap<String, String>,
+    aliases: Vec<String>,
+    serial: Option<String>,
+    disabled: bool,
+    reservation: Option<Reservation>,
+    start_gcode_extra: Option<String>,
+    end_gcode_extra: Option<String>,
+    chamber_preheat: Option<ChamberPreheat>,
+    stuck_detection: Option<StuckDetection>,
+    post_processors: Vec<PostProcessor>,
+    arc_fitting: Option<ArcFitting>,
+    background_tasks: Vec<AbortHandle>,
 }
 
 impl Machine {
@@ -22,10 +196,164 @@ impl Machine {
     {
         Self {
             machine: machine.into(),
-            slicer: slicer.into(),
+            slicer: Arc::new(slicer.into()),
+            display_name: None,
+            location: None,
+            labels: BTreeMap::new(),
+            aliases: vec![],
+            serial: None,
+            disabled: false,
+            reservation: None,
+            start_gcode_extra: None,
+            end_gcode_extra: None,
+            chamber_preheat: None,
+            stuck_detection: None,
+            post_processors: vec![],
+            arc_fitting: None,
+            background_tasks: vec![],
         }
     }
 
+    /// Return the user-facing name of this machine, such as `Rack 3 X1C`,
+    /// if one was configured.
+    pub fn get_display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    /// Set the user-facing name of this machine.
+    pub fn set_display_name(&mut self, display_name: Option<String>) {
+        self.display_name = display_name;
+    }
+
+    /// Return where this machine physically is, such as `Rack 3, shelf B`,
+    /// if configured.
+    pub fn get_location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// Set where this machine physically is.
+    pub fn set_location(&mut self, location: Option<String>) {
+        self.location = location;
+    }
+
+    /// Return the labels this machine was configured with, such as
+    /// `building = "east"`, for selecting groups of machines.
+    pub fn get_labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    /// Set the labels this machine is selected by.
+    pub fn set_labels(&mut self, labels: BTreeMap<String, String>) {
+        self.labels = labels;
+    }
+
+    /// Return other IDs this machine is known by, such as the ID it had
+    /// before it was given its canonical one, so existing references to it
+    /// (such as jobs) still find it.
+    pub fn get_aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    /// Set other IDs this machine is known by.
+    pub fn set_aliases(&mut self, aliases: Vec<String>) {
+        self.aliases = aliases;
+    }
+
+    /// Return the machine's serial number, if it has one, as read by
+    /// [Machine::load_serial] when it was registered.
+    pub fn get_serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Read the machine's serial number, so it can be found by it without
+    /// asking the machine every time.
+    pub async fn load_serial(&mut self) -> Result<()> {
+        self.serial = self.machine.machine_info().await?.make_model().serial;
+        Ok(())
+    }
+
+    /// Set extra gcode to run after the slicer profile's start and end
+    /// gcode, for jobs sliced for this machine.
+    pub fn set_gcode_extra(&mut self, start_gcode_extra: Option<String>, end_gcode_extra: Option<String>) {
+        self.start_gcode_extra = start_gcode_extra;
+        self.end_gcode_extra = end_gcode_extra;
+    }
+
+    /// Set how to wait for the chamber to warm up before starting ABS or
+    /// ASA jobs, or `None` to start them straight away.
+    pub fn set_chamber_preheat(&mut self, chamber_preheat: Option<ChamberPreheat>) {
+        self.chamber_preheat = chamber_preheat;
+    }
+
+    /// Return how to watch this machine's jobs for stalled progress, if at
+    /// all.
+    pub fn get_stuck_detection(&self) -> Option<&StuckDetection> {
+        self.stuck_detection.as_ref()
+    }
+
+    /// Set how to watch this machine's jobs for stalled progress, or `None`
+    /// to not watch them.
+    pub fn set_stuck_detection(&mut self, stuck_detection: Option<StuckDetection>) {
+        self.stuck_detection = stuck_detection;
+    }
+
+    /// Set the scripts to run over each sliced file, in order, before it's
+    /// sent to the machine.
+    pub fn set_post_processors(&mut self, post_processors: Vec<PostProcessor>) {
+        self.post_processors = post_processors;
+    }
+
+    /// Set how to fit arcs to sliced gcode, or `None` to leave it as the
+    /// slicer wrote it. Only enable this if the machine's firmware supports
+    /// `G2`/`G3`.
+    pub fn set_arc_fitting(&mut self, arc_fitting: Option<ArcFitting>) {
+        self.arc_fitting = arc_fitting;
+    }
+
+    /// Tie a background task (such as polling the machine for metrics) to
+    /// this machine, so it's stopped once the machine is removed.
+    pub fn add_background_task(&mut self, task: AbortHandle) {
+        self.background_tasks.push(task);
+    }
+
+    /// Return true if this machine has been taken out of service for
+    /// maintenance.
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Take this machine out of service (or put it back). A disabled
+    /// machine reports [MachineState::Maintenance], and refuses new jobs.
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// Return who holds this machine, if it's reserved and the reservation
+    /// hasn't run out.
+    pub fn reservation(&self) -> Option<&Reservation> {
+        self.reservation
+            .as_ref()
+            .filter(|reservation| reservation.expires_at > Utc::now())
+    }
+
+    /// Reserve this machine (or release it, with `None`), returning the
+    /// reservation it had. Jobs for a reserved machine wait in its queue
+    /// until the reservation is released or runs out.
+    pub fn set_reservation(&mut self, reservation: Option<Reservation>) -> Option<Reservation> {
+        let previous = self.reservation().cloned();
+        self.reservation = reservation;
+        previous
+    }
+
+    /// Return the state of the machine, as reported by the machine itself,
+    /// unless it has been disabled for maintenance.
+    pub async fn state(&self) -> Result<MachineState> {
+        if self.disabled {
+            return Ok(MachineState::Maintenance);
+        }
+        self.machine.state().await
+    }
+
     /// Return the underlying [AnyMachine] enum.
     pub fn get_machine(&self) -> &AnyMachine {
         &self.machine
@@ -41,48 +369,580 @@ impl Machine {
         &self.slicer
     }
 
-    /// Return the underlying [AnySlicer] enum as a mutable borrow.
-    pub fn get_slicer_mut(&mut self) -> &mut AnySlicer {
-        &mut self.slicer
+    /// Return the underlying [AnySlicer] enum as a mutable borrow, unless
+    /// a job is being sliced with it.
+    pub fn get_slicer_mut(&mut self) -> Option<&mut AnySlicer> {
+        Arc::get_mut(&mut self.slicer)
     }
 
     /// Take a specific [DesignFile], and produce a real-world 3D object
-    /// from it.
+    /// from it. The `job_name` is sanitized before being handed to the
+    /// machine.
     pub async fn build(
         &mut self,
         job_name: &str,
         design_file: &DesignFile,
         slicer_configuration: &SlicerConfiguration,
     ) -> Result<()> {
-        tracing::debug!(name = job_name, "building");
+        let sliced = self.slice(design_file, slicer_configuration).await?;
+        self.dispatch(job_name, sliced, slicer_configuration).await
+    }
+
+    /// Make sure the filament a job will be printed with is the material
+    /// the slicer profile expects, returning a [MaterialMismatch] error if
+    /// not. Jobs are let through if either material isn't known.
+    pub async fn check_material(&self, slicer_configuration: &SlicerConfiguration) -> Result<()> {
+        let Some(expected) = self.slicer.expected_material().await? else {
+            return Ok(());
+        };
+        let HardwareConfiguration::Fdm { config } = self.machine.hardware_configuration().await? else {
+            return Ok(());
+        };
+
+        let filament_idx = slicer_configuration
+            .filament_idx
+            .or(config.loaded_filament_idx)
+            .unwrap_or(0);
+        let Some(loaded) = config.filaments.get(filament_idx).map(|filament| filament.material) else {
+            return Ok(());
+        };
+
+        if expected == FilamentMaterial::Unknown || loaded == FilamentMaterial::Unknown || expected == loaded {
+            return Ok(());
+        }
+
+        Err(MaterialMismatch { expected, loaded }.into())
+    }
+
+    /// If a chamber preheat is configured, and the job will be printed in
+    /// ABS or ASA, run the heater gcode (if any), and return the preheat to
+    /// wait for (see [ChamberPreheat::wait]).
+    pub async fn start_chamber_preheat(
+        &mut self,
+        slicer_configuration: &SlicerConfiguration,
+    ) -> Result<Option<ChamberPreheat>> {
+        let Some(preheat) = self.chamber_preheat.clone() else {
+            return Ok(None);
+        };
+        if self.job_material(slicer_configuration).await? != Some(FilamentMaterial::Abs) {
+            return Ok(None);
+        }
+
+        if let Some(heater_gcode) = &preheat.heater_gcode {
+            self.run_gcode(heater_gcode).await?;
+        }
+        Ok(Some(preheat))
+    }
+
+    /// Return the material a job will be printed with: what the slicer
+    /// profile expects, or failing that, the loaded filament.
+    async fn job_material(&self, slicer_configuration: &SlicerConfiguration) -> Result<Option<FilamentMaterial>> {
+        if let Some(expected) = self.slicer.expected_material().await? {
+            if expected != FilamentMaterial::Unknown {
+                return Ok(Some(expected));
+            }
+        }
+        let HardwareConfiguration::Fdm { config } = self.machine.hardware_configuration().await? else {
+            return Ok(None);
+        };
+
+        let filament_idx = slicer_configuration
+            .filament_idx
+            .or(config.loaded_filament_idx)
+            .unwrap_or(0);
+        Ok(config.filaments.get(filament_idx).map(|filament| filament.material))
+    }
+
+    /// Read the chamber temperature, in Celsius, if the machine has a
+    /// chamber temperature sensor.
+    pub async fn chamber_temperature(&self) -> Result<Option<f64>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => chamber_temperature(machine.get_temperature_sensors()).await,
+            AnyMachine::Moonraker(machine) => chamber_temperature(machine.get_temperature_sensors()).await,
+            _ => Ok(None),
+        }
+    }
+
+    /// Read each of the machine's temperature sensors, by name, if it has
+    /// any. A Bambu printer's AMS units are included too, as `ams_<id>`.
+    pub async fn temperatures(&self) -> Result<HashMap<String, TemperatureSensorReading>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => {
+                let mut temperatures = machine.get_temperature_sensors().poll_sensors().await?;
+                // AMS units come and go, so aren't sensors of their own.
+                for ams in machine.ams_units()? {
+                    if let Some(temperature_celsius) = ams.temperature_celsius {
+                        temperatures.insert(
+                            format!("ams_{}", ams.id),
+                            TemperatureSensorReading {
+                                temperature_celsius,
+                                target_temperature_celsius: None,
+                            },
+                        );
+                    }
+                }
+                Ok(temperatures)
+            }
+            AnyMachine::Moonraker(machine) => machine.get_temperature_sensors().poll_sensors().await,
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    /// Pause the job the machine is running.
+    pub async fn pause(&mut self) -> Result<()> {
+        match &mut self.machine {
+            AnyMachine::Bambu(machine) => machine.pause().await,
+            AnyMachine::Moonraker(machine) => machine.pause().await,
+            AnyMachine::Usb(machine) => machine.pause().await,
+            AnyMachine::Noop(machine) => machine.pause().await,
+        }
+    }
+
+    /// Resume the job the machine has paused.
+    pub async fn resume(&mut self) -> Result<()> {
+        match &mut self.machine {
+            AnyMachine::Bambu(machine) => machine.resume().await,
+            AnyMachine::Moonraker(machine) => machine.resume().await,
+            AnyMachine::Usb(machine) => machine.resume().await,
+            AnyMachine::Noop(machine) => machine.resume().await,
+        }
+    }
+
+    /// Turn the machine's (chamber) light on or off.
+    pub async fn set_light(&mut self, on: bool) -> Result<()> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => {
+                machine
+                    .inner()
+                    .publish(bambulabs::command::Command::set_chamber_light(on.into()))
+                    .await?;
+                Ok(())
+            }
+            AnyMachine::Noop(_) => Ok(()),
+            _ => anyhow::bail!("controlling the light is not supported by this machine"),
+        }
+    }
+
+    /// Return the version of the firmware the machine is running, if it
+    /// can tell us.
+    pub async fn firmware_version(&self) -> Result<Option<String>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.firmware_version().await,
+            AnyMachine::Moonraker(machine) => machine.firmware_version().await,
+            AnyMachine::Usb(machine) => machine.firmware_version().await,
+            _ => Ok(None),
+        }
+    }
+
+    /// List the machine's accessories, such as lights, fans and
+    /// multi-material units, and their state.
+    pub async fn accessories(&self) -> Result<Vec<Accessory>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.accessories().await,
+            AnyMachine::Moonraker(machine) => machine.accessories().await,
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Turn one of the machine's accessories on or off. If there's no such
+    /// accessory, or it can't be controlled, an [AccessoryError] is
+    /// returned.
+    pub async fn set_accessory(&mut self, name: &str, on: bool) -> Result<()> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.set_accessory(name, on).await,
+            AnyMachine::Moonraker(machine) => machine.set_accessory(name, on).await,
+            _ => Err(AccessoryError::NotFound(name.to_owned()).into()),
+        }
+    }
+
+    /// Take a still image from the machine's camera, as a JPEG, if it has
+    /// one.
+    pub async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.snapshot().await,
+            AnyMachine::Moonraker(machine) => machine.snapshot().await,
+            _ => Ok(None),
+        }
+    }
+
+    /// Stream live video from the machine's camera, as JPEG frames, if it
+    /// has a camera which streams. Cameras which only take stills (such as
+    /// most Moonraker webcams) have to be asked for one at a time with
+    /// [Machine::snapshot].
+    pub async fn camera_stream(&self) -> Result<Option<crate::CameraStream>> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.camera_stream().await,
+            _ => Ok(None),
+        }
+    }
+
+    /// Return the mesh of bed heights the machine compensates for, if it
+    /// has probed one (only Klipper machines report theirs).
+    pub async fn bed_mesh(&self) -> Result<Option<crate::moonraker::BedMesh>> {
+        match &self.machine {
+            AnyMachine::Moonraker(machine) => machine.bed_mesh().await,
+            _ => Ok(None),
+        }
+    }
+
+    /// What's been recorded of the machine's MQTT traffic, if it's a Bambu
+    /// printer configured with `debug_mqtt`.
+    pub fn mqtt_debug(&self) -> Option<bambulabs::debug::MqttDebugSnapshot> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => machine.inner().mqtt_debug(),
+            _ => None,
+        }
+    }
+
+    /// Run some gcode (such as a macro) on the machine, outside of a job.
+    async fn run_gcode(&mut self, gcode: &str) -> Result<()> {
+        match &self.machine {
+            AnyMachine::Bambu(machine) => {
+                machine
+                    .inner()
+                    .publish(bambulabs::command::Command::send_gcode_line(gcode))
+                    .await?;
+                Ok(())
+            }
+            AnyMachine::Moonraker(machine) => Ok(machine.get_client().run_gcode(gcode).await?),
+            AnyMachine::Noop(_) => Ok(()),
+            _ => anyhow::bail!("running gcode outside of a job is not supported by this machine"),
+        }
+    }
+
+    /// Pick the format to slice to for this machine: the first format the
+    /// machine accepts which the slicer produces. Returns a
+    /// [NoCompatiblePipeline] error if there isn't one.
+    pub fn slice_format(&self) -> Result<SliceFormat, NoCompatiblePipeline> {
+        let accepted = self.machine.accepted_formats();
+        let produced = self.slicer.formats();
+        accepted
+            .iter()
+            .find(|format| produced.contains(format))
+            .copied()
+            .ok_or_else(|| NoCompatiblePipeline {
+                machine: self.machine.type_name(),
+                accepted: accepted.to_vec(),
+                produced: produced.to_vec(),
+            })
+    }
+
+    /// Return the options to slice for this machine with, given the
+    /// design-specific `slicer_configuration`.
+    async fn build_options(&self, slicer_configuration: &SlicerConfiguration) -> Result<BuildOptions> {
         let hardware_configuration = self.machine.hardware_configuration().await?;
         let machine_info = self.machine.machine_info().await?;
 
-        let options = BuildOptions {
+        Ok(BuildOptions {
             make_model: machine_info.make_model(),
             machine_type: machine_info.machine_type(),
             max_part_volume: machine_info.max_part_volume(),
             hardware_configuration,
             slicer_configuration: *slicer_configuration,
-        };
+            process: match machine_info.machine_type() {
+                // Resin settings are only taken with requests to slice, such as
+                // to `/v1/slice`, since no machine prints resin jobs yet.
+                MachineType::Stereolithography => ProcessOptions::Sla(SlaOptions::default()),
+                MachineType::FusedDeposition | MachineType::Cnc => ProcessOptions::Fdm(FdmOptions {
+                    start_gcode_extra: self.start_gcode_extra.clone(),
+                    end_gcode_extra: self.end_gcode_extra.clone(),
+                }),
+            },
+        })
+    }
 
-        match &mut self.machine {
-            AnyMachine::Bambu(machine) => {
-                let three_mf = ThreeMfSlicer::generate(&self.slicer, design_file, &options).await?;
-                ThreeMfControl::build(machine, job_name, three_mf).await
+    /// Return exactly how the machine's slicer would be run to slice
+    /// `design_file`, without running it. Machines which don't slice, or
+    /// whose slicer doesn't run a command line locally, return `None`.
+    pub async fn slice_dry_run(
+        &self,
+        design_file: &DesignFile,
+        slicer_configuration: &SlicerConfiguration,
+    ) -> Result<Option<SlicerInvocation>> {
+        if let AnyMachine::Noop(_) = &self.machine {
+            return Ok(None);
+        }
+        let format = self.slice_format()?;
+        let options = self.build_options(slicer_configuration).await?;
+        self.slicer.dry_run(design_file, &options, format).await
+    }
+
+    /// Slice a specific [DesignFile] into whatever format the underlying
+    /// machine accepts, without sending it anywhere. 3MF projects already
+    /// sliced for a Bambu printer are passed through, once checked against
+    /// the printer's model and nozzle (see [crate::bambu::SlicedPlate]).
+    pub async fn slice(
+        &self,
+        design_file: &DesignFile,
+        slicer_configuration: &SlicerConfiguration,
+    ) -> Result<SlicedFile> {
+        self.slice_job(design_file, slicer_configuration)
+            .await?
+            .run(design_file)
+            .await
+    }
+
+    /// Work out how to slice a specific [DesignFile] for this machine, so
+    /// it can be sliced (see [SliceJob::run]) without holding on to the
+    /// machine for as long as the slicer takes.
+    pub async fn slice_job(
+        &self,
+        design_file: &DesignFile,
+        slicer_configuration: &SlicerConfiguration,
+    ) -> Result<SliceJob> {
+        let format = self.slice_format()?;
+        let options = self.build_options(slicer_configuration).await?;
+
+        // Projects already sliced for a Bambu printer are sent as they are,
+        // as long as they were sliced for this model and nozzle.
+        let mut already_sliced = false;
+        if let (AnyMachine::Bambu(machine), DesignFile::ThreeMf(path)) = (&self.machine, design_file) {
+            if let Some(plate) = crate::bambu::SlicedPlate::read(path)? {
+                if let HardwareConfiguration::Fdm { config } = &options.hardware_configuration {
+                    plate.check(machine.variant(), config.nozzle_diameter)?;
+                }
+                already_sliced = true;
             }
-            AnyMachine::Moonraker(machine) => {
-                let gcode = GcodeSlicer::generate(&self.slicer, design_file, &options).await?;
-                GcodeControl::build(machine, job_name, gcode).await
+        }
+
+        let slicing = match (&self.machine, format) {
+            _ if already_sliced => Slicing::AlreadySliced,
+            (AnyMachine::Noop(_), _) => Slicing::Nothing,
+            (_, SliceFormat::Gcode) => Slicing::Gcode,
+            (_, SliceFormat::ThreeMf) => Slicing::ThreeMf,
+            (_, SliceFormat::Form) => anyhow::bail!("sliced file format is not supported by this machine"),
+        };
+
+        Ok(SliceJob {
+            slicer: self.slicer.clone(),
+            options,
+            slicing,
+            arc_fitting: self.arc_fitting.clone(),
+            post_processors: self.post_processors.clone(),
+        })
+    }
+
+    /// Send an already sliced file to the machine, and start the job. The
+    /// `job_name` is sanitized before being handed to the machine.
+    pub async fn dispatch(
+        &mut self,
+        job_name: &str,
+        sliced: SlicedFile,
+        slicer_configuration: &SlicerConfiguration,
+    ) -> Result<()> {
+        if self.disabled {
+            anyhow::bail!("machine is disabled for maintenance");
+        }
+
+        let job_name = &sanitize_job_name(job_name);
+        tracing::debug!(name = job_name, "building");
+
+        match (&mut self.machine, sliced) {
+            (AnyMachine::Bambu(machine), SlicedFile::ThreeMf(three_mf)) => {
+                machine
+                    .build_with_options(job_name, three_mf, slicer_configuration.bambu.unwrap_or_default())
+                    .await
             }
-            AnyMachine::Usb(machine) => {
-                let gcode = GcodeSlicer::generate(&self.slicer, design_file, &options).await?;
+            (AnyMachine::Moonraker(machine), SlicedFile::Gcode(gcode)) => {
                 GcodeControl::build(machine, job_name, gcode).await
             }
-            AnyMachine::Noop(_) => {
+            (AnyMachine::Usb(machine), SlicedFile::Gcode(gcode)) => GcodeControl::build(machine, job_name, gcode).await,
+            (AnyMachine::Noop(_), _) => {
                 // why even bother ;)
                 Ok(())
             }
+            _ => anyhow::bail!("sliced file format is not supported by this machine"),
+        }
+    }
+}
+
+impl Drop for Machine {
+    fn drop(&mut self) {
+        for task in &self.background_tasks {
+            task.abort();
         }
     }
 }
+
+/// How a [SliceJob] turns its design file into a [SlicedFile].
+enum Slicing {
+    /// The machine doesn't need anything.
+    Nothing,
+
+    /// The design file is a project already sliced for the machine.
+    AlreadySliced,
+
+    /// Slice to gcode.
+    Gcode,
+
+    /// Slice to a .3mf project.
+    ThreeMf,
+}
+
+/// Everything needed to slice a design file for a [Machine] (see
+/// [Machine::slice_job]), taken from it so the machine isn't held on to
+/// while slicing.
+pub struct SliceJob {
+    slicer: Arc<AnySlicer>,
+    options: BuildOptions,
+    slicing: Slicing,
+    arc_fitting: Option<ArcFitting>,
+    post_processors: Vec<PostProcessor>,
+}
+
+impl SliceJob {
+    /// Return the slicer the design file will be sliced with.
+    pub fn slicer(&self) -> &AnySlicer {
+        &self.slicer
+    }
+
+    /// Slice `design_file`, which must be the file this job was made for.
+    pub async fn run(&self, design_file: &DesignFile) -> Result<SlicedFile> {
+        let mut sliced = match self.slicing {
+            Slicing::Nothing => SlicedFile::Empty,
+            Slicing::AlreadySliced => {
+                let DesignFile::ThreeMf(path) = design_file else {
+                    anyhow::bail!("design file is not a sliced project");
+                };
+                tracing::info!("sending already sliced project as it is");
+                let copy = path.with_file_name(format!(
+                    "{}_{}",
+                    uuid::Uuid::new_v4().simple(),
+                    path.file_name().and_then(|name| name.to_str()).unwrap_or("sliced.3mf")
+                ));
+                tokio::fs::copy(path, &copy).await?;
+                return Ok(SlicedFile::ThreeMf(ThreeMfTemporaryFile(
+                    TemporaryFile::new(&copy).await?,
+                )));
+            }
+            Slicing::Gcode => {
+                SlicedFile::Gcode(GcodeSlicer::generate(&*self.slicer, design_file, &self.options).await?)
+            }
+            Slicing::ThreeMf => {
+                SlicedFile::ThreeMf(ThreeMfSlicer::generate(&*self.slicer, design_file, &self.options).await?)
+            }
+        };
+
+        // Arcs are fitted before any scripts run, so scripts see the gcode
+        // as it'll be sent.
+        if let (Some(arc_fitting), SlicedFile::Gcode(GcodeTemporaryFile(file))) = (&self.arc_fitting, &mut sliced) {
+            arc_fitting.apply(file).await?;
+        }
+
+        if let Some(steps) = &self.options.slicer_configuration.temperature_steps {
+            match &mut sliced {
+                SlicedFile::Gcode(GcodeTemporaryFile(file)) => steps.apply_gcode(file).await?,
+                SlicedFile::ThreeMf(ThreeMfTemporaryFile(file)) => steps.apply_project(file).await?,
+                SlicedFile::Empty => {}
+            }
+        }
+
+        if let Some(file) = sliced.file_mut() {
+            for post_processor in &self.post_processors {
+                post_processor.run(file).await?;
+            }
+        }
+
+        Ok(sliced)
+    }
+}
+
+/// Output of a [Machine]'s slicer, in whatever format the machine accepts.
+pub enum SlicedFile {
+    /// Sliced to gcode.
+    Gcode(GcodeTemporaryFile),
+
+    /// Sliced to a .3mf project.
+    ThreeMf(ThreeMfTemporaryFile),
+
+    /// Nothing was sliced, since the machine doesn't need anything.
+    Empty,
+}
+
+impl SlicedFile {
+    /// Copy the sliced file, such as to send it again after a failure, since
+    /// [Machine::dispatch] uses it up. The copy is alongside the original.
+    pub async fn duplicate(&self) -> Result<Self> {
+        let copy = |file: &TemporaryFile| {
+            let file_name = file
+                .path()
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("sliced");
+            file.path()
+                .with_file_name(format!("{}_{}", uuid::Uuid::new_v4().simple(), file_name))
+        };
+        Ok(match self {
+            Self::Gcode(GcodeTemporaryFile(file)) => Self::Gcode(GcodeTemporaryFile(file.copy_to(&copy(file)).await?)),
+            Self::ThreeMf(ThreeMfTemporaryFile(file)) => {
+                Self::ThreeMf(ThreeMfTemporaryFile(file.copy_to(&copy(file)).await?))
+            }
+            Self::Empty => Self::Empty,
+        })
+    }
+
+    /// Return where the sliced file is, if there is one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Gcode(GcodeTemporaryFile(file)) | Self::ThreeMf(ThreeMfTemporaryFile(file)) => Some(file.path()),
+            Self::Empty => None,
+        }
+    }
+
+    /// Return the sliced file, if there is one.
+    fn file_mut(&mut self) -> Option<&mut TemporaryFile> {
+        match self {
+            Self::Gcode(GcodeTemporaryFile(file)) | Self::ThreeMf(ThreeMfTemporaryFile(file)) => Some(file),
+            Self::Empty => None,
+        }
+    }
+}
+
+/// Read the temperature of the first chamber sensor in `sensors`, if there
+/// is one.
+async fn chamber_temperature<SensorsT>(mut sensors: SensorsT) -> Result<Option<f64>>
+where
+    SensorsT: TemperatureSensors<Error = anyhow::Error>,
+{
+    let Some(name) = sensors
+        .sensors()
+        .await?
+        .into_iter()
+        .find(|(_, sensor)| *sensor == TemperatureSensor::Chamber)
+        .map(|(name, _)| name)
+    else {
+        return Ok(None);
+    };
+    Ok(sensors
+        .poll_sensors()
+        .await?
+        .get(&name)
+        .map(|reading| reading.temperature_celsius))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chamber_preheat_wait() {
+        let preheat = ChamberPreheat {
+            min_temperature_celsius: 40.0,
+            timeout_seconds: 0,
+            heater_gcode: None,
+        };
+
+        assert!(preheat.wait(|| async { Ok(Some(45.0)) }).await.is_ok());
+        // Machines without a sensor aren't waited for.
+        assert!(preheat.wait(|| async { Ok(None) }).await.is_ok());
+
+        let e = preheat.wait(|| async { Ok(Some(25.0)) }).await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<ChamberTooCold>(),
+            Some(&ChamberTooCold {
+                temperature_celsius: 25.0,
+                min_temperature_celsius: 40.0,
+            })
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Stable IDs for machines which are discovered, rather than configured
+//! under an ID of their own, so the same physical machine gets the same ID
+//! however (and in whatever order) it's found.
+
+/// Return a stable ID for a machine from its manufacturer and serial
+/// number, such as `prusa-research-czpx2418x004xk68718`. Anything other than
+/// ASCII alphanumerics is replaced with `-`, runs of `-` are collapsed, and
+/// the result is lowercase.
+pub fn canonical_machine_id(manufacturer: &str, serial: &str) -> String {
+    let mut id = String::with_capacity(manufacturer.len() + serial.len() + 1);
+    for ch in manufacturer.chars().chain(std::iter::once('-')).chain(serial.chars()) {
+        if ch.is_ascii_alphanumeric() {
+            id.push(ch.to_ascii_lowercase());
+        } else if !id.is_empty() && !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+    id.trim_end_matches('-').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_machine_id() {
+        assert_eq!(
+            canonical_machine_id("Prusa Research", "CZPX2418X004XK68718"),
+            "prusa-research-czpx2418x004xk68718"
+        );
+        assert_eq!(canonical_machine_id("  Acme, Inc. ", "ab/12 "), "acme-inc-ab-12");
+        assert_eq!(canonical_machine_id("", "1234"), "1234");
+    }
+}
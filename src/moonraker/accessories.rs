@@ -0,0 +1,39 @@
+//! Accessories on Moonraker printers, which are the power devices (relays,
+//! smart plugs, GPIOs and the like) Moonraker is configured with.
+
+use anyhow::Result;
+
+use super::Client;
+use crate::{Accessory, AccessoryError, AccessoryKind};
+
+impl Client {
+    /// List the printer's power devices, and their state.
+    pub async fn accessories(&self) -> Result<Vec<Accessory>> {
+        Ok(self
+            .client
+            .power_devices()
+            .await?
+            .into_iter()
+            .map(|device| Accessory {
+                name: device.device,
+                kind: AccessoryKind::Power,
+                controllable: true,
+                on: match device.status.as_str() {
+                    "on" => Some(true),
+                    "off" => Some(false),
+                    _ => None,
+                },
+                state: Some(device.status),
+            })
+            .collect())
+    }
+
+    /// Turn one of the printer's power devices on or off.
+    pub async fn set_accessory(&self, name: &str, on: bool) -> Result<()> {
+        if !self.accessories().await?.iter().any(|accessory| accessory.name == name) {
+            return Err(AccessoryError::NotFound(name.to_owned()).into());
+        }
+        self.client.set_power_device(name, on).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Bed meshes probed by Klipper printers, for rendering as a heightmap.
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+
+/// The mesh of bed heights a machine compensates for while printing.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct BedMesh {
+    /// Name of the mesh profile, such as `default`.
+    pub profile_name: String,
+
+    /// How many points were probed along the X axis.
+    pub x_count: usize,
+
+    /// How many points were probed along the Y axis.
+    pub y_count: usize,
+
+    /// Smallest X the mesh covers, in millimeters.
+    pub min_x: f64,
+
+    /// Smallest Y the mesh covers, in millimeters.
+    pub min_y: f64,
+
+    /// Largest X the mesh covers, in millimeters.
+    pub max_x: f64,
+
+    /// Largest Y the mesh covers, in millimeters.
+    pub max_y: f64,
+
+    /// Heights probed, in millimeters, as `y_count` rows (from the smallest
+    /// Y), each of `x_count` heights (from the smallest X).
+    pub probed_matrix: Vec<Vec<f64>>,
+
+    /// The probed heights, interpolated to the finer mesh the machine
+    /// compensates with, in the same order as `probed_matrix`.
+    pub mesh_matrix: Vec<Vec<f64>>,
+}
+
+impl From<moonraker::BedMesh> for BedMesh {
+    fn from(mesh: moonraker::BedMesh) -> Self {
+        Self {
+            profile_name: mesh.profile_name,
+            x_count: mesh.probed_matrix.first().map(Vec::len).unwrap_or(0),
+            y_count: mesh.probed_matrix.len(),
+            min_x: mesh.mesh_min[0],
+            min_y: mesh.mesh_min[1],
+            max_x: mesh.mesh_max[0],
+            max_y: mesh.mesh_max[1],
+            probed_matrix: mesh.probed_matrix,
+            mesh_matrix: mesh.mesh_matrix,
+        }
+    }
+}
+
+impl Client {
+    /// Return the printer's loaded bed mesh, if it has one.
+    pub async fn bed_mesh(&self) -> Result<Option<BedMesh>> {
+        let Some(mesh) = self.client.bed_mesh().await? else {
+            return Ok(None);
+        };
+        // Klipper reports a single empty row when no mesh is loaded.
+        if mesh.probed_matrix.iter().all(Vec::is_empty) {
+            return Ok(None);
+        }
+        Ok(Some(mesh.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_klipper() {
+        let mesh = BedMesh::from(moonraker::BedMesh {
+            profile_name: "default".to_owned(),
+            mesh_min: [10.0, 15.0],
+            mesh_max: [220.0, 225.0],
+            probed_matrix: vec![vec![0.01, -0.02, 0.0], vec![0.03, 0.02, -0.01]],
+            mesh_matrix: vec![vec![0.01, -0.02, 0.0], vec![0.03, 0.02, -0.01]],
+        });
+
+        assert_eq!((mesh.x_count, mesh.y_count), (3, 2));
+        assert_eq!(
+            (mesh.min_x, mesh.min_y, mesh.max_x, mesh.max_y),
+            (10.0, 15.0, 220.0, 225.0)
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! Still images from Moonraker printers' webcams.
+
+use anyhow::Result;
+
+use super::Client;
+
+impl Client {
+    /// Take a still image from the printer's first enabled webcam, if it
+    /// has one.
+    pub async fn snapshot(&self) -> Result<Option<Vec<u8>>> {
+        let webcams = self.client.webcams().await?;
+        let Some(webcam) = webcams
+            .iter()
+            .find(|webcam| webcam.enabled && !webcam.snapshot_url.is_empty())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(self.client.snapshot(webcam).await?.to_vec()))
+    }
+}
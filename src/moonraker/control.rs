@@ -1,13 +1,13 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use moonraker::InfoResponse;
+use moonraker::{InfoResponse, MoonrakerError};
 
 use super::Client;
 use crate::{
-    Control as ControlTrait, FdmHardwareConfiguration, GcodeControl as GcodeControlTrait, GcodeTemporaryFile,
-    HardwareConfiguration, MachineInfo as MachineInfoTrait, MachineMakeModel, MachineState, MachineType,
-    SuspendControl as SuspendControlTrait, Volume,
+    job_file_name, Control as ControlTrait, FdmHardwareConfiguration, GcodeControl as GcodeControlTrait,
+    GcodeTemporaryFile, HardwareConfiguration, LayerProgress, MachineInfo as MachineInfoTrait, MachineMakeModel,
+    MachineState, MachineType, PauseReason, SuspendControl as SuspendControlTrait, Volume,
 };
 
 /// Information about the connected Moonraker-based printer.
@@ -39,6 +39,23 @@ impl MachineInfo {
     }
 }
 
+impl Client {
+    /// Work out why Klipper paused. Klipper doesn't say, so the best we can
+    /// do is notice a filament sensor which has run out.
+    async fn pause_reason(&self) -> PauseReason {
+        match self.client.filament_sensors().await {
+            Ok(sensors) if sensors.iter().any(|sensor| sensor.enabled && !sensor.filament_detected) => {
+                PauseReason::FilamentRunout
+            }
+            Ok(_) => PauseReason::Unknown,
+            Err(e) => {
+                tracing::debug!(error = format!("{:?}", e), "failed to get filament sensors");
+                PauseReason::Unknown
+            }
+        }
+    }
+}
+
 impl ControlTrait for Client {
     type Error = anyhow::Error;
     type MachineInfo = MachineInfo;
@@ -54,16 +71,19 @@ impl ControlTrait for Client {
 
     async fn emergency_stop(&mut self) -> Result<()> {
         tracing::warn!("emergency stop requested");
-        self.client.emergency_stop().await
+        Ok(self.client.emergency_stop().await?)
     }
 
     async fn stop(&mut self) -> Result<()> {
         tracing::debug!("stop requested");
-        self.client.cancel_print().await
+        Ok(self.client.cancel_print().await?)
     }
 
     async fn healthy(&self) -> bool {
-        self.client.info().await.is_ok()
+        match self.client.info().await {
+            Ok(info) => info.klipper_error().is_none(),
+            Err(_) => false,
+        }
     }
 
     async fn progress(&self) -> Result<Option<f64>> {
@@ -74,13 +94,56 @@ impl ControlTrait for Client {
         Ok(Some(status.virtual_sdcard.progress * 100.0))
     }
 
-    async fn state(&self) -> Result<MachineState> {
+    async fn layer_progress(&self) -> Result<Option<LayerProgress>> {
         let status = self.client.status().await?;
+        if !status.virtual_sdcard.is_active {
+            return Ok(None);
+        }
+
+        // Layers are only known when the slicer (or the start gcode) tells
+        // Klipper about them, with `SET_PRINT_STATS_INFO`.
+        let (Some(current_layer), Some(total_layers)) = (
+            status.print_stats.info.current_layer,
+            status.print_stats.info.total_layer,
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(Some(LayerProgress {
+            current_layer,
+            total_layers,
+            z_mm: status.gcode_move.map(|gcode_move| gcode_move.gcode_position[2]),
+        }))
+    }
+
+    async fn state(&self) -> Result<MachineState> {
+        let status = match self.client.status().await {
+            Ok(status) => status,
+            Err(MoonrakerError::Transport(e)) if e.is_connect() || e.is_timeout() => {
+                tracing::warn!(error = format!("{:?}", e), "moonraker unreachable");
+                return Ok(MachineState::Offline);
+            }
+            Err(e) => {
+                // Moonraker is up, but refused to give us the printer
+                // objects -- this is usually Klipper being shut down or
+                // in an error state, so go ask it why.
+                if let Ok(info) = self.client.info().await {
+                    if let Some(klipper_error) = info.klipper_error() {
+                        return Ok(MachineState::Failed {
+                            message: Some(klipper_error.to_string()),
+                        });
+                    }
+                }
+                return Err(e.into());
+            }
+        };
 
         Ok(match status.print_stats.state.as_str() {
             "printing" => MachineState::Running,
             "standby" => MachineState::Idle,
-            "paused" => MachineState::Paused,
+            "paused" => MachineState::Paused {
+                reason: self.pause_reason().await,
+            },
             "complete" => MachineState::Complete,
             "cancelled" => MachineState::Complete,
             "error" => MachineState::Failed {
@@ -106,12 +169,12 @@ impl ControlTrait for Client {
 impl SuspendControlTrait for Client {
     async fn pause(&mut self) -> Result<()> {
         tracing::debug!("pause requested");
-        self.client.pause_print().await
+        Ok(self.client.pause_print().await?)
     }
 
     async fn resume(&mut self) -> Result<()> {
         tracing::debug!("resume requested");
-        self.client.resume_print().await
+        Ok(self.client.resume_print().await?)
     }
 }
 
@@ -120,8 +183,15 @@ impl GcodeControlTrait for Client {
         let gcode = gcode.0;
 
         tracing::info!(job_name = job_name, "uploading and printing gcode");
-        tracing::debug!("uploading");
-        let path: PathBuf = self.client.upload_file(gcode.path()).await?.item.path.parse()?;
+        let file_name = PathBuf::from(job_file_name(job_name, "gcode"));
+        tracing::debug!(file_name = file_name.to_str(), "uploading");
+        let path: PathBuf = self
+            .client
+            .upload(&file_name, &tokio::fs::read(gcode.path()).await?)
+            .await?
+            .item
+            .path
+            .parse()?;
         tracing::debug!("printing");
         self.client.print(&path).await?;
         Ok(())
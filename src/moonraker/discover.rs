@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::StreamExt;
+use moonraker::Client as MoonrakerClient;
+use tokio::sync::RwLock;
+
+use super::{Client, Config};
+use crate::{Discover as DiscoverTrait, Machine, MachineMakeModel, NetworkFilter};
+
+/// Port Moonraker listens on, unless configured otherwise.
+const MOONRAKER_PORT: u16 = 7125;
+
+/// How often to probe while there are configured printers we haven't
+/// found yet.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for each address to answer a probe. Most won't, so
+/// this is kept short.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most addresses to probe at once.
+const PROBE_CONCURRENCY: usize = 64;
+
+/// Handle to find Moonraker printers configured by `hostname`, by probing
+/// the addresses listed in the discovery config's `probe`, for networks
+/// mDNS doesn't reach (such as other VLANs).
+pub struct MoonrakerDiscover {
+    config: HashMap<String, Config>,
+    network: NetworkFilter,
+}
+
+impl MoonrakerDiscover {
+    /// Return a new Discover handle for the printers in `cfgs` which are
+    /// configured by `hostname`; the rest are ignored.
+    pub fn new<ConfigsT: Into<HashMap<String, Config>>>(cfgs: ConfigsT) -> Self {
+        MoonrakerDiscover {
+            config: cfgs
+                .into()
+                .into_iter()
+                .filter(|(_, config)| config.is_discovered())
+                .collect(),
+            network: NetworkFilter::default(),
+        }
+    }
+
+    /// Probe the addresses listed in `network`.
+    pub fn with_network_filter(mut self, network: NetworkFilter) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Return the host name of the Moonraker instance at `ip`, if there is
+    /// one.
+    async fn probe(ip: IpAddr) -> Option<String> {
+        let endpoint = format!("http://{}", SocketAddr::new(ip, MOONRAKER_PORT));
+        let client = MoonrakerClient::with_http_client(&endpoint, crate::http_client()).ok()?;
+        tokio::time::timeout(PROBE_TIMEOUT, async {
+            // This answers even when Klipper isn't running, but the host
+            // name comes from Klipper.
+            let server = client.server_info().await.ok()?;
+            tracing::debug!(
+                ip = ip.to_string(),
+                version = server.moonraker_version,
+                "found moonraker"
+            );
+            Some(client.info().await.ok()?.hostname)
+        })
+        .await
+        .ok()?
+    }
+
+    /// Register the printer configured with `hostname`, if any, as being at
+    /// `ip`, unless it's already registered.
+    async fn register(
+        &self,
+        hostname: &str,
+        ip: IpAddr,
+        channel: &tokio::sync::mpsc::Sender<String>,
+        printers: &RwLock<HashMap<String, RwLock<Machine>>>,
+    ) -> Result<()> {
+        let Some((machine_api_id, config)) = self
+            .config
+            .iter()
+            .find(|(_, config)| config.hostname.as_deref() == Some(hostname))
+        else {
+            tracing::debug!(ip = ip.to_string(), "No config found for moonraker {}", hostname);
+            return Ok(());
+        };
+        if printers.read().await.contains_key(machine_api_id) {
+            return Ok(());
+        }
+
+        tracing::info!(
+            id = machine_api_id,
+            ip = ip.to_string(),
+            "registering probed moonraker printer"
+        );
+        let config = Config {
+            endpoint: format!("http://{}", SocketAddr::new(ip, MOONRAKER_PORT)),
+            ..config.clone()
+        };
+        let (manufacturer, model) = config.variant.get_manufacturer_model();
+        let machine = Machine::new(
+            Client::new(
+                &config,
+                MachineMakeModel {
+                    manufacturer,
+                    model,
+                    serial: None,
+                },
+            )?
+            .with_machine_id(machine_api_id),
+            config.slicer.load()?,
+        );
+
+        printers
+            .write()
+            .await
+            .insert(machine_api_id.clone(), RwLock::new(machine));
+        channel.send(machine_api_id.clone()).await?;
+        Ok(())
+    }
+
+    /// Return true if every printer that needs discovering has been found.
+    async fn all_discovered(&self, printers: &RwLock<HashMap<String, RwLock<Machine>>>) -> bool {
+        let printers = printers.read().await;
+        self.config
+            .keys()
+            .all(|machine_api_id| printers.contains_key(machine_api_id))
+    }
+}
+
+impl DiscoverTrait for MoonrakerDiscover {
+    type Error = anyhow::Error;
+
+    async fn discover(
+        &self,
+        channel: tokio::sync::mpsc::Sender<String>,
+        printers: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+    ) -> Result<()> {
+        if self.config.is_empty() {
+            tracing::debug!("no moonraker devices need discovery, shutting down moonraker probes");
+            return Ok(());
+        }
+
+        let targets = self.network.probe_targets()?;
+        if targets.is_empty() {
+            tracing::warn!("moonraker printers are configured by hostname, but there are no addresses to probe");
+            return Ok(());
+        }
+
+        tracing::info!(targets = targets.len(), "Spawning Moonraker probe task");
+
+        let mut probe_interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            probe_interval.tick().await;
+            if self.all_discovered(&printers).await {
+                continue;
+            }
+
+            let mut found = futures::stream::iter(targets.iter().copied())
+                .map(|ip| async move { Self::probe(ip).await.map(|hostname| (hostname, ip)) })
+                .buffer_unordered(PROBE_CONCURRENCY);
+            while let Some(found) = found.next().await {
+                let Some((hostname, ip)) = found else {
+                    continue;
+                };
+                if let Err(e) = self.register(&hostname, ip, &channel, &printers).await {
+                    tracing::error!(
+                        ip = ip.to_string(),
+                        error = format!("{:?}", e),
+                        "failed to create moonraker machine"
+                    );
+                }
+            }
+        }
+    }
+}
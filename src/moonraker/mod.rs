@@ -1,11 +1,19 @@
 //! This module contains support for printing to moonraker 3D printers.
 
+mod accessories;
+mod bed_mesh;
+mod camera;
 mod control;
+mod discover;
 mod temperature;
 mod variants;
 
+use std::collections::BTreeMap;
+
 use anyhow::Result;
+pub use bed_mesh::BedMesh;
 pub use control::MachineInfo;
+pub use discover::MoonrakerDiscover;
 use moonraker::Client as MoonrakerClient;
 use serde::{Deserialize, Serialize};
 pub use temperature::TemperatureSensors;
@@ -31,8 +39,50 @@ pub struct Config {
     /// Specific make/model of Moonraker-based printer.
     pub variant: MoonrakerVariant,
 
-    /// HTTP URL to use for this printer.
+    /// HTTP URL to use for this printer, or `unix://` followed by the path
+    /// to a unix socket serving Moonraker's HTTP API. This can be left out
+    /// if `instances` or `hostname` are set.
+    #[serde(default)]
     pub endpoint: String,
+
+    /// Further Moonraker instances on the same host (for example, one per
+    /// printer, on different ports or unix sockets), by name. Each is
+    /// registered as a separate machine, with the rest of this config.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub instances: BTreeMap<String, String>,
+
+    /// Host name of the printer, as Klipper reports it. If set, and neither
+    /// `endpoint` nor `instances` are, the printer is found by probing the
+    /// discovery config's `probe` addresses for Moonraker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+impl Config {
+    /// Return true if this printer is found by probing for it, rather than
+    /// being at a configured endpoint.
+    pub fn is_discovered(&self) -> bool {
+        self.hostname.is_some() && self.endpoint.is_empty() && self.instances.is_empty()
+    }
+
+    /// Split the config up by Moonraker instance: one for the `endpoint`
+    /// (if set), without a name, and one for each of the `instances`.
+    pub fn split_instances(&self) -> Vec<(Option<String>, Config)> {
+        let single = |endpoint: &str| Config {
+            endpoint: endpoint.to_owned(),
+            instances: BTreeMap::new(),
+            ..self.clone()
+        };
+
+        let mut configs = vec![];
+        if !self.endpoint.is_empty() {
+            configs.push((None, single(&self.endpoint)));
+        }
+        for (name, endpoint) in &self.instances {
+            configs.push((Some(name.clone()), single(endpoint)));
+        }
+        configs
+    }
 }
 
 /// Client is a connection to a Moonraker instance.
@@ -52,18 +102,81 @@ impl Client {
         Ok(Self {
             make_model,
             volume: config.variant.get_max_part_volume(),
-            client: MoonrakerClient::new(&config.endpoint)?,
+            client: MoonrakerClient::with_http_client(&config.endpoint, crate::http_client())?,
             config: config.clone(),
         })
     }
 
+    /// Record `machine_id` on the spans of calls to the machine, as
+    /// [MoonrakerClient::with_machine_id] does.
+    pub fn with_machine_id(mut self, machine_id: &str) -> Self {
+        self.client = self.client.with_machine_id(machine_id);
+        self
+    }
+
     /// Return the underling [MoonrakerClient].
     pub fn get_client(&self) -> &MoonrakerClient {
         &self.client
     }
 
+    /// Return the host the printer's Moonraker endpoint is on.
+    pub fn hostname(&self) -> Option<String> {
+        let url = reqwest::Url::parse(&self.config.endpoint).ok()?;
+        url.host_str().map(str::to_owned)
+    }
+
+    /// Return the version of Klipper the printer is running.
+    pub async fn firmware_version(&self) -> Result<Option<String>> {
+        Ok(Some(self.client.info().await?.software_version))
+    }
+
     /// Return the underling [Config]
     pub(crate) fn get_config(&self) -> &Config {
         &self.config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_instances() {
+        let config: Config = toml::from_str(
+            r#"
+            nozzle_diameter = 0.4
+            filaments = []
+            variant = "Neptune4"
+            slicer.type = "Prusa"
+            slicer.config = "config/prusa/neptune4.ini"
+
+            [instances]
+            left = "http://192.168.1.102:7125"
+            right = "unix:///run/moonraker/right.sock"
+            "#,
+        )
+        .unwrap();
+
+        let configs = config.split_instances();
+        let endpoints = configs
+            .iter()
+            .map(|(name, config)| (name.as_deref(), config.endpoint.as_str(), config.instances.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            endpoints,
+            vec![
+                (Some("left"), "http://192.168.1.102:7125", 0),
+                (Some("right"), "unix:///run/moonraker/right.sock", 0),
+            ]
+        );
+
+        let config = Config {
+            endpoint: "http://192.168.1.103".to_owned(),
+            instances: BTreeMap::new(),
+            ..config
+        };
+        let configs = config.split_instances();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].0, None);
+    }
+}
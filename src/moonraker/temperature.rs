@@ -21,37 +21,113 @@ pub struct TemperatureSensors {
     client: moonraker::Client,
 }
 
-impl TemperatureSensorsTrait for TemperatureSensors {
-    type Error = anyhow::Error;
-
-    async fn sensors(&self) -> Result<HashMap<String, TemperatureSensor>> {
-        Ok(HashMap::from([
-            ("extruder".to_owned(), TemperatureSensor::Extruder),
-            ("bed".to_owned(), TemperatureSensor::Bed),
-        ]))
-    }
-
-    async fn poll_sensors(&mut self) -> Result<HashMap<String, TemperatureSensorReading>> {
+impl TemperatureSensors {
+    /// Read every sensor klipper reports, by name, along with what it's
+    /// attached to.
+    async fn read(&self) -> Result<HashMap<String, (TemperatureSensor, TemperatureSensorReading)>> {
         let readings = self.client.temperatures().await?;
 
-        let mut sensor_readings = HashMap::from([(
+        let mut sensors = HashMap::from([(
             "extruder".to_owned(),
-            TemperatureSensorReading {
-                temperature_celsius: *readings.extruder.temperatures.last().unwrap_or(&0.0),
-                target_temperature_celsius: Some(*readings.extruder.targets.last().unwrap_or(&0.0)),
-            },
+            (
+                TemperatureSensor::Extruder,
+                TemperatureSensorReading {
+                    temperature_celsius: *readings.extruder.temperatures.last().unwrap_or(&0.0),
+                    target_temperature_celsius: Some(*readings.extruder.targets.last().unwrap_or(&0.0)),
+                },
+            ),
         )]);
 
         if let Some(heater_bed) = readings.heater_bed {
-            sensor_readings.insert(
+            sensors.insert(
                 "bed".to_owned(),
-                TemperatureSensorReading {
-                    temperature_celsius: *heater_bed.temperatures.last().unwrap_or(&0.0),
-                    target_temperature_celsius: Some(*heater_bed.targets.last().unwrap_or(&0.0)),
-                },
+                (
+                    TemperatureSensor::Bed,
+                    TemperatureSensorReading {
+                        temperature_celsius: *heater_bed.temperatures.last().unwrap_or(&0.0),
+                        target_temperature_celsius: Some(*heater_bed.targets.last().unwrap_or(&0.0)),
+                    },
+                ),
             );
         }
 
-        Ok(sensor_readings)
+        for (section, sensor) in readings.sensors {
+            let Some(temperature_celsius) = sensor.temperatures.last().copied() else {
+                continue;
+            };
+            let (name, kind) = sensor_name(&section);
+            // Fall back to the whole section name if two sensors of different
+            // types share a name.
+            let name = if sensors.contains_key(&name) { section } else { name };
+            sensors.insert(
+                name,
+                (
+                    kind,
+                    TemperatureSensorReading {
+                        temperature_celsius,
+                        target_temperature_celsius: sensor.targets.and_then(|targets| targets.last().copied()),
+                    },
+                ),
+            );
+        }
+
+        Ok(sensors)
+    }
+}
+
+/// Name a sensor after its klipper config section (so `temperature_sensor
+/// mcu_temp` is `mcu_temp`), and guess what it's attached to from that.
+fn sensor_name(section: &str) -> (String, TemperatureSensor) {
+    let name = section.split_once(' ').map(|(_, name)| name).unwrap_or(section);
+    let kind = if section.starts_with("extruder") {
+        TemperatureSensor::Extruder
+    } else if name.contains("chamber") {
+        TemperatureSensor::Chamber
+    } else {
+        TemperatureSensor::Other
+    };
+    (name.to_owned(), kind)
+}
+
+impl TemperatureSensorsTrait for TemperatureSensors {
+    type Error = anyhow::Error;
+
+    async fn sensors(&self) -> Result<HashMap<String, TemperatureSensor>> {
+        Ok(self
+            .read()
+            .await?
+            .into_iter()
+            .map(|(name, (kind, _))| (name, kind))
+            .collect())
+    }
+
+    async fn poll_sensors(&mut self) -> Result<HashMap<String, TemperatureSensorReading>> {
+        Ok(self
+            .read()
+            .await?
+            .into_iter()
+            .map(|(name, (_, reading))| (name, reading))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensor_name() {
+        assert_eq!(
+            sensor_name("temperature_sensor mcu_temp"),
+            ("mcu_temp".to_owned(), TemperatureSensor::Other)
+        );
+        assert_eq!(
+            sensor_name("heater_generic chamber_heater"),
+            ("chamber_heater".to_owned(), TemperatureSensor::Chamber)
+        );
+        assert_eq!(
+            sensor_name("extruder1"),
+            ("extruder1".to_owned(), TemperatureSensor::Extruder)
+        );
     }
 }
@@ -0,0 +1,184 @@
+//! Restricting discovery (and mDNS advertisement) to some of the host's
+//! network interfaces, for hosts with more than one, and probing addresses
+//! multicast can't reach (such as printers on another VLAN).
+
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use serde::{Deserialize, Serialize};
+
+/// Which network interfaces to run discovery on.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NetworkFilter {
+    /// Interface names (such as `eth0`) or subnets in CIDR notation (such
+    /// as `192.168.1.0/24`) to use. If empty, all interfaces are used.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+
+    /// Addresses (such as `10.20.0.15`) or subnets in CIDR notation (such
+    /// as `10.20.0.0/24`) to probe directly for printers, for networks
+    /// SSDP and mDNS don't reach (such as other VLANs). Replies from these
+    /// are accepted even if they're outside `interfaces`.
+    #[serde(default)]
+    pub probe: Vec<String>,
+}
+
+/// Most addresses to probe, across all of [NetworkFilter::probe], so a
+/// mistyped prefix length doesn't flood the network.
+const MAX_PROBE_TARGETS: usize = 4096;
+
+impl NetworkFilter {
+    /// Return true if every interface may be used.
+    pub fn allows_all(&self) -> bool {
+        self.interfaces.is_empty()
+    }
+
+    /// Return the networks of the host's (non-loopback) interfaces which
+    /// match this filter, as the interface's address along with its prefix
+    /// length.
+    pub fn local_networks(&self) -> Result<Vec<IpNet>> {
+        let mut matched = vec![false; self.interfaces.len()];
+        let mut networks = vec![];
+        for interface in if_addrs::get_if_addrs()? {
+            if interface.is_loopback() {
+                continue;
+            }
+
+            let network = match &interface.addr {
+                if_addrs::IfAddr::V4(addr) => {
+                    IpNet::V4(Ipv4Net::new(addr.ip, u32::from(addr.netmask).count_ones() as u8)?)
+                }
+                if_addrs::IfAddr::V6(addr) => {
+                    IpNet::V6(Ipv6Net::new(addr.ip, u128::from(addr.netmask).count_ones() as u8)?)
+                }
+            };
+
+            let mut allowed = self.allows_all();
+            for (selector, matched) in self.interfaces.iter().zip(matched.iter_mut()) {
+                let matches = match selector.parse::<IpNet>() {
+                    Ok(subnet) => subnet.contains(&network.addr()),
+                    Err(_) => *selector == interface.name,
+                };
+                *matched |= matches;
+                allowed |= matches;
+            }
+
+            if allowed {
+                networks.push(network);
+            }
+        }
+
+        for (selector, matched) in self.interfaces.iter().zip(matched) {
+            if !matched {
+                tracing::warn!(interface = selector, "no local network interface matches");
+            }
+        }
+
+        Ok(networks)
+    }
+
+    /// Return the local addresses matching this filter, or `None` if every
+    /// interface may be used.
+    pub fn local_addrs(&self) -> Result<Option<Vec<IpAddr>>> {
+        if self.allows_all() {
+            return Ok(None);
+        }
+        Ok(Some(self.local_networks()?.iter().map(IpNet::addr).collect()))
+    }
+
+    /// Return every address to probe directly, with subnets expanded to
+    /// their hosts.
+    pub fn probe_targets(&self) -> Result<Vec<IpAddr>> {
+        let mut targets = vec![];
+        for target in &self.probe {
+            let network = match target.parse::<IpAddr>() {
+                Ok(ip) => IpNet::from(ip),
+                Err(_) => match target.parse::<IpNet>() {
+                    Ok(network) => network,
+                    Err(_) => bail!("invalid address or subnet to probe: {:?}", target),
+                },
+            };
+
+            targets.extend(network.hosts().take(MAX_PROBE_TARGETS + 1));
+            if targets.len() > MAX_PROBE_TARGETS {
+                bail!("more than {} addresses to probe", MAX_PROBE_TARGETS);
+            }
+        }
+        targets.sort();
+        targets.dedup();
+        Ok(targets)
+    }
+
+    /// Create an mDNS responder, which only answers on the interfaces
+    /// matching this filter.
+    pub fn mdns_responder(&self) -> Result<libmdns::Responder> {
+        Ok(match self.local_addrs()? {
+            Some(addrs) => libmdns::Responder::new_with_ip_list(addrs)?,
+            None => libmdns::Responder::new()?,
+        })
+    }
+}
+
+/// Return true if `ip` is on one of `networks`.
+pub fn is_on_networks(networks: &[IpNet], ip: &IpAddr) -> bool {
+    networks.iter().any(|network| network.trunc().contains(ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_on_networks() {
+        let networks = vec!["192.168.1.20/24".parse().unwrap(), "10.1.2.3/16".parse().unwrap()];
+
+        assert!(is_on_networks(&networks, &"192.168.1.55".parse().unwrap()));
+        assert!(is_on_networks(&networks, &"10.1.200.1".parse().unwrap()));
+        assert!(!is_on_networks(&networks, &"192.168.2.55".parse().unwrap()));
+        assert!(!is_on_networks(&[], &"192.168.1.55".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let filter: NetworkFilter = toml::from_str(r#"interfaces = ["eth0", "192.168.1.0/24"]"#).unwrap();
+        assert_eq!(filter.interfaces, vec!["eth0", "192.168.1.0/24"]);
+        assert!(!filter.allows_all());
+
+        let filter: NetworkFilter = toml::from_str("").unwrap();
+        assert!(filter.allows_all());
+        assert!(filter.probe.is_empty());
+    }
+
+    #[test]
+    fn test_probe_targets() {
+        let filter = NetworkFilter {
+            probe: vec![
+                "10.20.0.15".to_owned(),
+                "10.30.0.0/30".to_owned(),
+                "10.30.0.1".to_owned(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.probe_targets().unwrap(),
+            vec![
+                "10.20.0.15".parse::<IpAddr>().unwrap(),
+                "10.30.0.1".parse().unwrap(),
+                "10.30.0.2".parse().unwrap(),
+            ]
+        );
+
+        let filter = NetworkFilter {
+            probe: vec!["10.0.0.0/8".to_owned()],
+            ..Default::default()
+        };
+        assert!(filter.probe_targets().is_err());
+
+        let filter = NetworkFilter {
+            probe: vec!["printer.local".to_owned()],
+            ..Default::default()
+        };
+        assert!(filter.probe_targets().is_err());
+    }
+}
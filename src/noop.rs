@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     Control as ControlTrait, FdmHardwareConfiguration, Filament, GcodeControl as GcodeControlTrait, GcodeTemporaryFile,
-    HardwareConfiguration, MachineInfo as MachineInfoTrait, MachineMakeModel, MachineState, MachineType,
+    HardwareConfiguration, LayerProgress, MachineInfo as MachineInfoTrait, MachineMakeModel, MachineState, MachineType,
     SuspendControl as SuspendControlTrait, ThreeMfControl as ThreeMfControlTrait, ThreeMfTemporaryFile, Volume,
 };
 
@@ -35,6 +35,10 @@ pub struct Config {
 
     /// percentage through a print
     pub progress: Option<f64>,
+
+    /// layer the print is on
+    #[serde(default)]
+    pub layer_progress: Option<LayerProgress>,
 }
 
 /// Nothing to see here!
@@ -102,6 +106,10 @@ impl ControlTrait for Noop {
         Ok(self.config.progress)
     }
 
+    async fn layer_progress(&self) -> Result<Option<LayerProgress>> {
+        Ok(self.config.layer_progress)
+    }
+
     async fn state(&self) -> Result<MachineState> {
         Ok(self.config.state.clone())
     }
@@ -0,0 +1,109 @@
+//! Post-processing scripts, run over sliced files before they're sent to the
+//! machine, the same way slicers run them (such as Arc Welder, or input
+//! shaping tweaks).
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::TemporaryFile;
+
+/// A script to run over each sliced file. The script is passed the file's
+/// path as its last argument, and edits (or replaces) the file there.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PostProcessor {
+    /// The script (or program) to run.
+    pub command: PathBuf,
+
+    /// Arguments to pass to the script, before the file's path.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+
+    /// How long the script may take, in seconds, before it's killed and the
+    /// job fails.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    5 * 60
+}
+
+impl PostProcessor {
+    /// Run the script over `file`, reopening it afterwards in case the
+    /// script replaced it rather than editing it.
+    pub async fn run(&self, file: &mut TemporaryFile) -> Result<()> {
+        tracing::info!(
+            command = format!("{:?}", self.command),
+            path = format!("{:?}", file.path()),
+            "post-processing sliced file"
+        );
+
+        let child = Command::new(&self.command)
+            .args(&self.args)
+            .arg(file.path())
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(Duration::from_secs(self.timeout_seconds), child)
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Post-processing script {:?} didn't finish within {} seconds",
+                    self.command,
+                    self.timeout_seconds
+                )
+            })?
+            .with_context(|| format!("Failed to run post-processing script {:?}", self.command))?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Post-processing script {:?} failed: {}\nstdout:\n{}stderr:\n{}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        file.reopen().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_post_processor() {
+        let path = std::env::temp_dir().join(format!("{}.gcode", uuid::Uuid::new_v4().simple()));
+        tokio::fs::write(&path, "G28\n").await.unwrap();
+        let mut file = TemporaryFile::new(&path).await.unwrap();
+
+        // Replace the file, rather than editing it in place.
+        let replace = PostProcessor {
+            command: "sh".into(),
+            args: vec![
+                "-c".to_owned(),
+                "{ cat \"$0\"; echo M84; } > \"$0.new\" && mv \"$0.new\" \"$0\"".to_owned(),
+            ],
+            timeout_seconds: 10,
+        };
+        replace.run(&mut file).await.unwrap();
+
+        let mut content = String::new();
+        tokio::io::AsyncReadExt::read_to_string(file.as_mut(), &mut content)
+            .await
+            .unwrap();
+        assert_eq!(content, "G28\nM84\n");
+
+        let failing = PostProcessor {
+            command: "sh".into(),
+            args: vec!["-c".to_owned(), "echo nope >&2; exit 3".to_owned()],
+            timeout_seconds: 10,
+        };
+        let error = failing.run(&mut file).await.unwrap_err().to_string();
+        assert!(error.contains("nope"), "{}", error);
+    }
+}
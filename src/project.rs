@@ -0,0 +1,248 @@
+//! Packaging several parts into a single 3MF project, each with its own
+//! settings (such as a denser infill for a part which takes a load), for
+//! slicers which read per-object settings from the project (Orca, and
+//! Bambu Studio).
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{Cursor, Write as _},
+};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::analyze::parse_stl;
+
+/// Settings for one part of a multi-part project. Anything left unset is
+/// left to the slicer's profile.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone, Copy, Default, PartialEq)]
+pub struct PartSettings {
+    /// Sparse infill density, as a percentage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub infill_density_percent: Option<f64>,
+
+    /// Number of perimeter walls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wall_loops: Option<u32>,
+
+    /// The filament to print the part with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filament_idx: Option<usize>,
+}
+
+/// One part of a multi-part project.
+pub struct ProjectPart<'a> {
+    /// Name of the part, such as the file it was uploaded as.
+    pub name: &'a str,
+
+    /// The part, as a binary or ASCII STL file in millimeters.
+    pub stl: &'a [u8],
+
+    /// The part's own settings.
+    pub settings: PartSettings,
+}
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+ <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+ <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+ <Relationship Target="/3D/3dmodel.model" Id="rel-1" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+/// Escape `value` for use in an XML attribute.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Write the 3D model, with each part as its own object. Parts are all
+/// placed at the origin, for the slicer to arrange.
+fn write_model(parts: &[ProjectPart]) -> Result<String> {
+    let mut model = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<model unit=\"millimeter\" xml:lang=\"en-US\" \
+         xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">\n <resources>\n",
+    );
+    for (index, part) in parts.iter().enumerate() {
+        let triangles = parse_stl(part.stl).with_context(|| format!("failed to read part {:?}", part.name))?;
+        if triangles.is_empty() {
+            anyhow::bail!("part {:?} has no facets", part.name);
+        }
+
+        // Share vertices between the triangles which meet at them, as 3MF
+        // expects.
+        let mut vertices = String::new();
+        let mut indices = HashMap::new();
+        let mut faces = String::new();
+        for triangle in &triangles {
+            let mut face = [0; 3];
+            for (corner, vertex) in face.iter_mut().zip(triangle) {
+                let key = vertex.map(|coordinate| (coordinate as f32).to_bits());
+                let next = indices.len();
+                *corner = *indices.entry(key).or_insert_with(|| {
+                    let _ = writeln!(
+                        vertices,
+                        "     <vertex x=\"{}\" y=\"{}\" z=\"{}\"/>",
+                        vertex[0] as f32, vertex[1] as f32, vertex[2] as f32
+                    );
+                    next
+                });
+            }
+            let _ = writeln!(
+                faces,
+                "     <triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"/>",
+                face[0], face[1], face[2]
+            );
+        }
+
+        let _ = write!(
+            model,
+            "  <object id=\"{}\" name=\"{}\" type=\"model\">\n   <mesh>\n    <vertices>\n{}    </vertices>\n    \
+             <triangles>\n{}    </triangles>\n   </mesh>\n  </object>\n",
+            index + 1,
+            escape(part.name),
+            vertices,
+            faces
+        );
+    }
+    model.push_str(" </resources>\n <build>\n");
+    for index in 0..parts.len() {
+        let _ = writeln!(model, "  <item objectid=\"{}\"/>", index + 1);
+    }
+    model.push_str(" </build>\n</model>\n");
+    Ok(model)
+}
+
+/// Write each part's settings, in the per-object config Orca and Bambu
+/// Studio read from projects.
+fn write_model_settings(parts: &[ProjectPart]) -> String {
+    let mut config = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<config>\n");
+    for (index, part) in parts.iter().enumerate() {
+        let _ = writeln!(config, "  <object id=\"{}\">", index + 1);
+        let mut metadata = vec![("name", escape(part.name))];
+        if let Some(filament_idx) = part.settings.filament_idx {
+            // Orca numbers its filaments from one.
+            metadata.push(("extruder", (filament_idx + 1).to_string()));
+        }
+        if let Some(infill_density_percent) = part.settings.infill_density_percent {
+            metadata.push(("sparse_infill_density", format!("{}%", infill_density_percent)));
+        }
+        if let Some(wall_loops) = part.settings.wall_loops {
+            metadata.push(("wall_loops", wall_loops.to_string()));
+        }
+        for (key, value) in metadata {
+            let _ = writeln!(config, "    <metadata key=\"{}\" value=\"{}\"/>", key, value);
+        }
+        config.push_str("  </object>\n");
+    }
+    config.push_str("</config>\n");
+    config
+}
+
+/// Package `parts` into a single 3MF project, with each part's settings
+/// applied to it alone.
+pub fn package_project(parts: &[ProjectPart]) -> Result<Vec<u8>> {
+    if parts.is_empty() {
+        anyhow::bail!("a project needs at least one part");
+    }
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options = zip::write::SimpleFileOptions::default();
+    for (name, content) in [
+        ("[Content_Types].xml", CONTENT_TYPES.to_owned()),
+        ("_rels/.rels", RELS.to_owned()),
+        ("3D/3dmodel.model", write_model(parts)?),
+        ("Metadata/model_settings.config", write_model_settings(parts)),
+    ] {
+        zip.start_file(name, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::TestPrint;
+
+    fn read(project: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(project)).unwrap();
+        let mut content = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn test_package_project() {
+        let cube = TestPrint::CalibrationCube.stl();
+        let project = package_project(&[
+            ProjectPart {
+                name: "bracket.stl",
+                stl: cube.as_bytes(),
+                settings: PartSettings {
+                    infill_density_percent: Some(40.0),
+                    wall_loops: Some(4),
+                    filament_idx: None,
+                },
+            },
+            ProjectPart {
+                name: "cover <v2>.stl",
+                stl: cube.as_bytes(),
+                settings: PartSettings {
+                    filament_idx: Some(1),
+                    ..Default::default()
+                },
+            },
+        ])
+        .unwrap();
+
+        let model = read(&project, "3D/3dmodel.model");
+        assert_eq!(model.matches("<object ").count(), 2);
+        assert_eq!(model.matches("<item ").count(), 2);
+        // A cube has 8 corners, shared between its 12 triangles.
+        assert_eq!(model.matches("<vertex ").count(), 16);
+        assert_eq!(model.matches("<triangle ").count(), 24);
+        assert!(model.contains(r#"name="cover &lt;v2&gt;.stl""#));
+
+        let settings = read(&project, "Metadata/model_settings.config");
+        assert_eq!(
+            settings,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <object id="1">
+    <metadata key="name" value="bracket.stl"/>
+    <metadata key="sparse_infill_density" value="40%"/>
+    <metadata key="wall_loops" value="4"/>
+  </object>
+  <object id="2">
+    <metadata key="name" value="cover &lt;v2&gt;.stl"/>
+    <metadata key="extruder" value="2"/>
+  </object>
+</config>
+"#
+        );
+    }
+
+    #[test]
+    fn test_package_project_refuses_bad_parts() {
+        assert!(package_project(&[]).is_err());
+        assert!(package_project(&[ProjectPart {
+            name: "empty.stl",
+            stl: b"not an stl",
+            settings: PartSettings::default(),
+        }])
+        .is_err());
+    }
+}
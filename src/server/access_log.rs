@@ -0,0 +1,306 @@
+//! An access log for the API, along with how long each endpoint takes to
+//! respond. Dropshot logs every request it completes through its `slog`
+//! logger, so the access log is a drain in front of the usual one, which
+//! picks those records out. Which API key a request carried (by its
+//! position in the server's list, never the key itself) is logged as it's
+//! authorized, and picked out the same way.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::{Registry, Unit},
+};
+
+/// The message dropshot logs once it's responded to a request.
+const REQUEST_COMPLETED: &str = "request completed";
+
+/// The message dropshot logs if the client goes away before it's
+/// responded to a request.
+const REQUEST_CANCELLED: &str = "request handling cancelled (client disconnected)";
+
+/// The message logged once it's known which API key a request carried.
+pub(crate) const API_KEY_USED: &str = "api key used";
+
+/// The endpoint label for requests which don't match any endpoint, so
+/// requests for made-up paths can't create new metrics.
+const UNMATCHED: &str = "unmatched";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    method: String,
+    endpoint: String,
+    status: String,
+}
+
+/// The details dropshot logs about a request.
+#[derive(Debug, Default)]
+struct Request {
+    id: Option<String>,
+    method: Option<String>,
+    uri: Option<String>,
+    remote_addr: Option<String>,
+    status: Option<String>,
+    latency_us: Option<u64>,
+    api_key: Option<String>,
+}
+
+impl slog::Serializer for Request {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        let field = match key {
+            "req_id" => &mut self.id,
+            "method" => &mut self.method,
+            "uri" => &mut self.uri,
+            "remote_addr" => &mut self.remote_addr,
+            "response_code" => &mut self.status,
+            "api_key" => &mut self.api_key,
+            "latency_us" => {
+                self.latency_us = val.to_string().parse().ok();
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        // Values are only set once, as the record's own values come first.
+        field.get_or_insert_with(|| val.to_string());
+        Ok(())
+    }
+}
+
+/// Logs each request the server responds to, and records how long it
+/// took by endpoint.
+pub(crate) struct AccessLog {
+    /// The path of each endpoint, such as `/v1/machines/{id}`.
+    endpoints: Vec<String>,
+
+    // The metrics aren't touched across a panic, so there's nothing to be
+    // left inconsistent.
+    latency: AssertUnwindSafe<Family<RequestLabels, Histogram, fn() -> Histogram>>,
+
+    /// Which API key each request still being handled carried, by request
+    /// id.
+    api_keys: Mutex<HashMap<String, String>>,
+}
+
+impl AccessLog {
+    /// Create an access log for the endpoints in the OpenAPI `schema`,
+    /// registering the latency metric with the provided registry.
+    pub(crate) fn new(registry: &mut Registry, schema: &serde_json::Value) -> Self {
+        let latency: Family<RequestLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.001, 2.0, 16)));
+        registry.register_with_unit(
+            "machine_api_http_request_duration",
+            "Time taken to respond to API requests, by endpoint",
+            Unit::Seconds,
+            latency.clone(),
+        );
+
+        let endpoints = schema["paths"]
+            .as_object()
+            .map(|paths| paths.keys().cloned().collect())
+            .unwrap_or_default();
+
+        Self {
+            endpoints,
+            latency: AssertUnwindSafe(latency),
+            api_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Find the endpoint `path` was routed to. Unversioned (legacy) paths
+    /// are matched against their `/v1` equivalents.
+    fn endpoint(&self, path: &str) -> String {
+        if let Some(endpoint) = self.endpoints.iter().find(|endpoint| path_matches(endpoint, path)) {
+            return endpoint.clone();
+        }
+
+        let versioned = format!("/v1{}", path);
+        match self
+            .endpoints
+            .iter()
+            .find(|endpoint| path_matches(endpoint, &versioned))
+        {
+            Some(endpoint) => endpoint.trim_start_matches("/v1").to_owned(),
+            None => UNMATCHED.to_owned(),
+        }
+    }
+
+    /// Remember which API key a request carried, until it's responded to.
+    fn identify(&self, request: Request) {
+        if let (Some(id), Some(api_key)) = (request.id, request.api_key) {
+            self.api_keys.lock().unwrap().insert(id, api_key);
+        }
+    }
+
+    /// Forget which API key a request carried, returning it if it's known.
+    fn forget(&self, request: &Request) -> Option<String> {
+        let id = request.id.as_ref()?;
+        self.api_keys.lock().unwrap().remove(id)
+    }
+
+    fn record(&self, request: Request) {
+        let api_key = self.forget(&request);
+        let method = request.method.unwrap_or_default();
+        let uri = request.uri.unwrap_or_default();
+        let path = uri.split_once('?').map_or(uri.as_str(), |(path, _)| path);
+        let status = request.status.unwrap_or_default();
+        let latency = Duration::from_micros(request.latency_us.unwrap_or_default());
+
+        tracing::info!(
+            target: "machine_api::access",
+            request_id = %request.id.unwrap_or_default(),
+            method = %method,
+            path = %path,
+            status = %status,
+            latency_ms = latency.as_secs_f64() * 1000.0,
+            remote_addr = %request.remote_addr.unwrap_or_default(),
+            api_key = api_key,
+            "request completed"
+        );
+
+        self.latency
+            .get_or_create(&RequestLabels {
+                method,
+                endpoint: self.endpoint(path),
+                status,
+            })
+            .observe(latency.as_secs_f64());
+    }
+}
+
+/// Check whether `path` matches an endpoint's `template`, where each
+/// `{variable}` segment matches any one segment.
+fn path_matches(template: &str, path: &str) -> bool {
+    let mut template = template.trim_end_matches('/').split('/');
+    let mut path = path.trim_end_matches('/').split('/');
+    loop {
+        match (template.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(found)) => {
+                let variable = expected.starts_with('{') && expected.ends_with('}');
+                if !((variable && !found.is_empty()) || expected == found) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// A `slog` drain which passes records on to `inner`, other than those for
+/// completed requests, and which API key requests carried, which go to the
+/// [AccessLog].
+pub(crate) struct AccessLogDrain<D> {
+    inner: D,
+    access_log: Arc<AccessLog>,
+}
+
+impl<D> AccessLogDrain<D> {
+    /// Create a new `AccessLogDrain`, in front of `inner`.
+    pub(crate) fn new(inner: D, access_log: Arc<AccessLog>) -> Self {
+        Self { inner, access_log }
+    }
+}
+
+impl<D> slog::Drain for AccessLogDrain<D>
+where
+    D: slog::Drain<Ok = (), Err = slog::Never>,
+{
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record, values: &slog::OwnedKVList) -> Result<(), slog::Never> {
+        let message = record.msg().to_string();
+        if ![REQUEST_COMPLETED, REQUEST_CANCELLED, API_KEY_USED].contains(&message.as_str()) {
+            return self.inner.log(record, values);
+        }
+
+        let mut request = Request::default();
+        let _ = slog::KV::serialize(record.kv(), record, &mut request);
+        let _ = slog::KV::serialize(values, record, &mut request);
+        match message.as_str() {
+            REQUEST_COMPLETED => self.access_log.record(request),
+            API_KEY_USED => self.access_log.identify(request),
+            _ => {
+                self.access_log.forget(&request);
+                return self.inner.log(record, values);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_log(registry: &mut Registry) -> AccessLog {
+        AccessLog::new(
+            registry,
+            &serde_json::json!({"paths": {"/v1/machines": {}, "/v1/machines/{id}": {}, "/v1/machines/{id}/disable": {}}}),
+        )
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let access_log = access_log(&mut Registry::default());
+
+        assert_eq!(access_log.endpoint("/v1/machines"), "/v1/machines");
+        assert_eq!(access_log.endpoint("/v1/machines/"), "/v1/machines");
+        assert_eq!(access_log.endpoint("/v1/machines/mk3"), "/v1/machines/{id}");
+        assert_eq!(
+            access_log.endpoint("/v1/machines/mk3/disable"),
+            "/v1/machines/{id}/disable"
+        );
+        assert_eq!(access_log.endpoint("/machines/mk3"), "/machines/{id}");
+        assert_eq!(access_log.endpoint("/v1/machines/mk3/explode"), UNMATCHED);
+        assert_eq!(access_log.endpoint("/wp-admin"), UNMATCHED);
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut registry = Registry::default();
+        let access_log = Arc::new(access_log(&mut registry));
+        let logger = slog::Logger::root(
+            AccessLogDrain::new(slog::Discard, access_log),
+            slog::o!("method" => "GET", "uri" => "/v1/machines/mk3?id_type=id"),
+        );
+
+        slog::info!(logger, "request completed"; "response_code" => "200", "latency_us" => 1500u64);
+        slog::info!(logger, "something else"; "response_code" => "500", "latency_us" => 1500u64);
+
+        let mut metrics = String::new();
+        prometheus_client::encoding::text::encode(&mut metrics, &registry).unwrap();
+        assert!(metrics.contains(
+            r#"machine_api_http_request_duration_seconds_count{method="GET",endpoint="/v1/machines/{id}",status="200"} 1"#
+        ));
+        assert!(!metrics.contains(r#"status="500""#));
+    }
+
+    #[test]
+    fn test_drain_api_key() {
+        let access_log = Arc::new(access_log(&mut Registry::default()));
+        let logger = slog::Logger::root(
+            AccessLogDrain::new(slog::Discard, access_log.clone()),
+            slog::o!("req_id" => "1234", "method" => "GET", "uri" => "/v1/machines"),
+        );
+
+        slog::info!(logger, "{}", API_KEY_USED; "api_key" => 1);
+        assert_eq!(
+            access_log.api_keys.lock().unwrap().get("1234").map(String::as_str),
+            Some("1")
+        );
+
+        slog::info!(logger, "request completed"; "response_code" => "200", "latency_us" => 1500u64);
+        assert!(access_log.api_keys.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+//! Advertising the server over mDNS, with a summary of it in the service's
+//! TXT records, so clients on the LAN (such as desktop apps) can show
+//! whether it's healthy without connecting to it. The summary is refreshed
+//! periodically, and the service is registered again whenever the host's
+//! networks change.
+
+use std::{sync::Arc, time::Duration};
+
+use ipnet::IpNet;
+use tokio::task::JoinHandle;
+
+use super::Context;
+use crate::NetworkFilter;
+
+/// The service type the server is advertised as.
+const SERVICE_TYPE: &str = "_machine-api._tcp";
+
+/// The name the server is advertised under.
+const SERVICE_NAME: &str = "Machine Api Server";
+
+/// How often the summary (and the host's networks) are checked for
+/// changes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What's advertised about the server in its TXT records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Summary {
+    /// How many machines the server knows of.
+    machines: usize,
+
+    /// Whether the server will take jobs; it isn't read-only, and at least
+    /// one of its machines isn't disabled.
+    ready: bool,
+}
+
+impl Summary {
+    /// Summarize the server. Machines which are busy (locked) are counted
+    /// as enabled, rather than waiting on them.
+    async fn of(ctx: &Context) -> Self {
+        let machines = ctx.machines.read().await;
+        let enabled = machines
+            .values()
+            .filter(|machine| machine.try_read().map(|machine| !machine.is_disabled()).unwrap_or(true))
+            .count();
+
+        Self {
+            machines: machines.len(),
+            ready: !ctx.read_only && enabled > 0,
+        }
+    }
+
+    /// Return the TXT records to advertise.
+    fn txt_records(&self) -> Vec<String> {
+        vec![
+            "path=/".to_owned(),
+            format!("api_version={}", clap::crate_version!()),
+            format!("machines={}", self.machines),
+            format!("ready={}", self.ready),
+        ]
+    }
+}
+
+/// Advertise the server, listening on `port`, over mDNS on the network
+/// interfaces matching `network`, until the returned task is aborted.
+pub(crate) fn spawn_advertiser(ctx: Arc<Context>, port: u16, network: NetworkFilter) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut networks: Option<Vec<IpNet>> = None;
+        let mut summary: Option<Summary> = None;
+
+        // The service has to be dropped before its responder, to say
+        // goodbye on the old networks.
+        let mut service: Option<libmdns::Service> = None;
+        let mut responder: Option<libmdns::Responder> = None;
+
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let latest_networks = match network.local_networks() {
+                Ok(latest_networks) => Some(latest_networks),
+                Err(e) => {
+                    tracing::warn!(error = format!("{:?}", e), "failed to list local networks");
+                    networks.clone()
+                }
+            };
+            if responder.is_none() || latest_networks != networks {
+                drop(service.take());
+                responder = match network.mdns_responder() {
+                    Ok(responder) => Some(responder),
+                    Err(e) => {
+                        tracing::error!(error = format!("{:?}", e), "failed to start mDNS responder");
+                        continue;
+                    }
+                };
+                networks = latest_networks;
+                if summary.is_some() {
+                    tracing::info!("local networks changed, advertising again");
+                }
+            }
+
+            let latest_summary = Summary::of(&ctx).await;
+            if service.is_some() && summary.as_ref() == Some(&latest_summary) {
+                continue;
+            }
+
+            let Some(responder) = responder.as_ref() else {
+                continue;
+            };
+            let txt_records = latest_summary.txt_records();
+            let txt_records = txt_records.iter().map(String::as_str).collect::<Vec<_>>();
+
+            // Registering again replaces the old TXT records, once the old
+            // service has been dropped.
+            drop(service.take());
+            service = Some(responder.register(SERVICE_TYPE.to_owned(), SERVICE_NAME.to_owned(), port, &txt_records));
+            tracing::info!(
+                port = port,
+                machines = latest_summary.machines,
+                ready = latest_summary.ready,
+                "advertising {} over mDNS",
+                SERVICE_TYPE
+            );
+            summary = Some(latest_summary);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_txt_records() {
+        let summary = Summary {
+            machines: 3,
+            ready: true,
+        };
+        let txt_records = summary.txt_records();
+        assert_eq!(txt_records[0], "path=/");
+        assert!(txt_records[1].starts_with("api_version="));
+        assert_eq!(txt_records[2..], ["machines=3", "ready=true"]);
+    }
+}
@@ -0,0 +1,265 @@
+//! Optional API key authentication, so a server exposed on a LAN (such as
+//! one running a whole farm) only takes requests from those given a key.
+//! Keys may be scoped to some of the machines, such as a class of students
+//! who should only see the two printers in their classroom.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use dropshot::{ClientErrorStatusCode, HttpError, RequestContext};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    access_log::API_KEY_USED,
+    endpoints::{find_machine, labels_match, MachineIdType},
+    Context, Job,
+};
+
+/// API keys requests must carry one of, as `Authorization: Bearer <key>`.
+/// With no keys, every request is let through.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ApiKeys(pub Vec<ApiKey>);
+
+/// An API key, either with access to everything, or scoped to some of the
+/// machines.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ApiKey {
+    /// A key with access to every machine and endpoint.
+    Key(String),
+
+    /// A key which can only see and act on the machines listed, or those
+    /// matching one of the label selectors. It can't manage what the
+    /// server has as a whole, such as registering machines or schedules.
+    Scoped {
+        /// The key itself.
+        key: String,
+
+        /// Machine ids (as configured) the key may use.
+        #[serde(default)]
+        machines: Vec<String>,
+
+        /// Label selectors (such as `room=3b,printer`) the machines the key
+        /// may use match any one of.
+        #[serde(default)]
+        labels: Vec<String>,
+    },
+}
+
+impl ApiKey {
+    fn key(&self) -> &str {
+        match self {
+            Self::Key(key) | Self::Scoped { key, .. } => key,
+        }
+    }
+
+    fn access(&self) -> Access {
+        match self {
+            Self::Key(_) => Access::All,
+            Self::Scoped { machines, labels, .. } => Access::Machines {
+                ids: machines.clone(),
+                selectors: labels.clone(),
+            },
+        }
+    }
+}
+
+impl ApiKeys {
+    /// Find what the value of a request's `Authorization` header, if it had
+    /// one, gives access to, if it carries one of the keys.
+    fn access(&self, authorization: Option<&str>) -> Option<Access> {
+        self.authenticate(authorization).map(|(_, access)| access)
+    }
+
+    /// Find which of the keys (by its position in the list) the value of a
+    /// request's `Authorization` header carries, along with what it gives
+    /// access to. With no keys, there's no key to find.
+    fn authenticate(&self, authorization: Option<&str>) -> Option<(Option<usize>, Access)> {
+        if self.0.is_empty() {
+            return Some((None, Access::All));
+        }
+        let key = authorization.and_then(|authorization| {
+            let (scheme, key) = authorization.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| key.trim())
+        })?;
+        // Check every key, so how long this takes doesn't give away which
+        // one nearly matched.
+        self.0.iter().enumerate().fold(None, |found, (index, valid)| {
+            let matched = constant_time_eq(valid.key().as_bytes(), key.as_bytes());
+            found.or(matched.then(|| (Some(index), valid.access())))
+        })
+    }
+}
+
+/// What a request's API key gives it access to.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Access {
+    /// Every machine and endpoint.
+    All,
+
+    /// Only machines with one of `ids`, or matching one of the label
+    /// `selectors`.
+    Machines { ids: Vec<String>, selectors: Vec<String> },
+}
+
+impl Access {
+    /// Check whether the machine `id`, with `labels`, may be used.
+    pub(crate) fn allows(&self, id: &str, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Machines { ids, selectors } => {
+                // An empty selector would match every machine.
+                ids.iter().any(|allowed| allowed == id)
+                    || selectors
+                        .iter()
+                        .any(|selector| !selector.trim().is_empty() && labels_match(labels, selector))
+            }
+        }
+    }
+
+    /// Return a 403 if the machine `id`, with `labels`, may not be used.
+    pub(crate) fn check(&self, id: &str, labels: &BTreeMap<String, String>) -> Result<(), HttpError> {
+        if self.allows(id, labels) {
+            return Ok(());
+        }
+        Err(forbidden(format!("this API key can't use machine {:?}", id)))
+    }
+
+    /// Check whether the machine `id` may be used, going by its labels if
+    /// it's still around.
+    pub(crate) async fn allows_id(&self, ctx: &Context, id: &str) -> bool {
+        if *self == Self::All {
+            return true;
+        }
+        match ctx.machines.read().await.get(id) {
+            Some(machine) => self.allows(id, machine.read().await.get_labels()),
+            None => self.allows(id, &BTreeMap::new()),
+        }
+    }
+
+    /// Return a 403 if the machine `key` refers to (as [find_machine] finds
+    /// it) may not be used. Machines which aren't found are left for the
+    /// handler to report.
+    pub(crate) async fn check_machine(
+        &self,
+        ctx: &Context,
+        key: &str,
+        id_type: Option<MachineIdType>,
+    ) -> Result<(), HttpError> {
+        if *self == Self::All {
+            return Ok(());
+        }
+        let machines = ctx.machines.read().await;
+        match find_machine(&machines, key, id_type).await? {
+            Some((id, machine)) => self.check(id, machine.read().await.get_labels()),
+            None => Ok(()),
+        }
+    }
+
+    /// Return a 403 if the job `id` was sent to a machine which may not be
+    /// used. Jobs which aren't found are left for the handler to report.
+    pub(crate) async fn check_job(&self, ctx: &Context, id: &str) -> Result<(), HttpError> {
+        match ctx.jobs.get(id).await {
+            Some(job) if !self.allows_id(ctx, &job.machine_id).await => {
+                Err(forbidden(format!("this API key can't use job {:?}", id)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return a 403 unless the key has access to everything, for endpoints
+    /// which aren't about any one machine.
+    pub(crate) fn check_all(&self) -> Result<(), HttpError> {
+        match self {
+            Self::All => Ok(()),
+            Self::Machines { .. } => Err(forbidden(
+                "this API key is scoped to some machines, and can't use this endpoint".to_owned(),
+            )),
+        }
+    }
+
+    /// Keep only the jobs sent to machines which may be used.
+    pub(crate) async fn visible_jobs(&self, ctx: &Context, jobs: Vec<Job>) -> Vec<Job> {
+        let mut visible = vec![];
+        for job in jobs {
+            if self.allows_id(ctx, &job.machine_id).await {
+                visible.push(job);
+            }
+        }
+        visible
+    }
+}
+
+fn forbidden(message: String) -> HttpError {
+    HttpError::for_client_error(Some("Forbidden".to_owned()), ClientErrorStatusCode::FORBIDDEN, message)
+}
+
+/// Compare `a` and `b` in time which depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Return a 401 unless the request carries one of the server's API keys
+/// (or the server has none), for every endpoint but `/ping` to bail out
+/// with. Otherwise, return what the key gives access to.
+pub(crate) fn authorize(rqctx: &RequestContext<Arc<Context>>) -> Result<Access, HttpError> {
+    let authorization = rqctx
+        .request
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if let Some((index, access)) = rqctx.context().api_keys.authenticate(authorization) {
+        if let Some(index) = index {
+            slog::info!(rqctx.log, "{}", API_KEY_USED; "api_key" => index);
+        }
+        return Ok(access);
+    }
+
+    tracing::warn!(
+        path = rqctx.request.uri().path(),
+        "refusing request without a valid API key"
+    );
+    Err(HttpError::for_client_error(
+        Some("Unauthorized".to_owned()),
+        ClientErrorStatusCode::UNAUTHORIZED,
+        "a valid API key is required, as `Authorization: Bearer <key>`".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access() {
+        assert_eq!(ApiKeys::default().access(None), Some(Access::All));
+
+        let keys = ApiKeys(vec![ApiKey::Key("first".to_owned()), ApiKey::Key("second".to_owned())]);
+        assert_eq!(keys.access(Some("Bearer second")), Some(Access::All));
+        assert_eq!(keys.access(Some("bearer first")), Some(Access::All));
+        assert_eq!(keys.access(None), None);
+        assert_eq!(keys.access(Some("Bearer third")), None);
+        assert_eq!(keys.access(Some("Bearer firs")), None);
+        assert_eq!(keys.access(Some("Basic first")), None);
+        assert_eq!(keys.access(Some("first")), None);
+    }
+
+    #[test]
+    fn test_scoped() {
+        let keys: ApiKeys =
+            serde_json::from_str(r#"["admin", {"key": "class", "machines": ["mk4-1"], "labels": ["room=3b"]}]"#)
+                .unwrap();
+        assert_eq!(keys.access(Some("Bearer admin")), Some(Access::All));
+
+        let access = keys.access(Some("Bearer class")).unwrap();
+        let room_3b = BTreeMap::from([("room".to_owned(), "3b".to_owned())]);
+        assert!(access.allows("mk4-1", &BTreeMap::new()));
+        assert!(access.allows("x1c", &room_3b));
+        assert!(!access.allows("x1c", &BTreeMap::new()));
+        assert!(access.check_all().is_err());
+        assert!(Access::All.check_all().is_ok());
+    }
+}
@@ -1,9 +1,14 @@
 use std::{collections::HashMap, sync::Arc};
 
+use dropshot::{ClientErrorStatusCode, HttpError};
 use prometheus_client::registry::Registry;
 use tokio::sync::RwLock;
 
-use crate::Machine;
+use super::{
+    etag::SharedResponse, ApiKeys, Events, FileUrls, Files, Jobs, LogLevels, MachineUpdates, Registrations, Schedules,
+    TaskModes,
+};
+use crate::{slicer::profiles::ProfileStore, AnySlicer, Machine};
 
 /// Context for a given server -- this contains all the informatio required
 /// to serve a Machine-API request.
@@ -17,4 +22,63 @@ pub struct Context {
 
     /// Prom registry for metrics
     pub registry: Arc<RwLock<Registry>>,
+
+    /// Where to send notable [super::Event]s.
+    pub events: Arc<Events>,
+
+    /// Print jobs submitted to this server.
+    pub jobs: Arc<Jobs>,
+
+    /// Recurring print jobs registered with this server.
+    pub schedules: Arc<Schedules>,
+
+    /// Design files stored on this server, to be printed by id.
+    pub files: Arc<Files>,
+
+    /// Machines registered at runtime, rather than in the config file.
+    pub registrations: Arc<Registrations>,
+
+    /// Slicers this server will slice with on behalf of other hosts, by
+    /// name.
+    pub slicers: HashMap<String, AnySlicer>,
+
+    /// Imported Orca Slicer profiles.
+    pub profiles: ProfileStore,
+
+    /// Which URLs design files may be downloaded from.
+    pub file_urls: FileUrls,
+
+    /// Whether handlers are cancelled when their client disconnects.
+    pub task_modes: TaskModes,
+
+    /// Whether requests which would change anything are refused.
+    pub read_only: bool,
+
+    /// API keys requests must carry one of, if any.
+    pub api_keys: ApiKeys,
+
+    /// Log levels overridden for machines at runtime.
+    pub log_levels: LogLevels,
+
+    /// Live updates to machines' state, for clients watching them.
+    pub updates: MachineUpdates,
+
+    /// The latest listing of machines, shared between clients polling for
+    /// it.
+    pub(crate) machine_listing: SharedResponse,
+}
+
+impl Context {
+    /// Return a 403 if the server is read-only, for endpoints which change
+    /// anything (such as starting a print) to bail out with.
+    pub(crate) fn check_writable(&self) -> Result<(), HttpError> {
+        if self.read_only {
+            return Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::FORBIDDEN,
+                "the server is read-only".to_owned(),
+            ));
+        }
+        Ok(())
+    }
 }
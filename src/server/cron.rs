@@ -0,0 +1,225 @@
+//! Minimal parser and evaluator for standard five-field cron expressions
+//! (`minute hour day-of-month month day-of-week`), evaluated in UTC.
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+
+/// Upper bound on how many steps we'll take looking for the next run, so
+/// an expression that can never match (such as `0 0 30 2 *`) gives up
+/// instead of spinning forever.
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A parsed cron expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    /// Parse a five-field cron expression. Lists (`1,2`), ranges (`1-5`),
+    /// steps (`*/15`), month and weekday names (`jan`, `mon`), and the
+    /// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` shorthands
+    /// are supported.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expr => expr,
+        };
+
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            anyhow::bail!("cron expression must have 5 fields, found {}: {:?}", fields.len(), expr);
+        };
+
+        // Sunday can be written as either 0 or 7.
+        let mut days_of_week = parse_field(day_of_week, 0, 7, &WEEKDAY_NAMES)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59, &[])?,
+            hours: parse_field(hour, 0, 23, &[])? as u32,
+            days_of_month: parse_field(day_of_month, 1, 31, &[])? as u32,
+            months: parse_field(month, 1, 12, &MONTH_NAMES)? as u16,
+            days_of_week: (days_of_week & 0x7f) as u8,
+            any_day_of_month: day_of_month == "*",
+            any_day_of_week: day_of_week == "*",
+        })
+    }
+
+    /// Return the first time strictly after `after` that this schedule
+    /// fires, if there is one.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+
+        for _ in 0..MAX_SEARCH_STEPS {
+            if self.months & (1 << time.month()) == 0 {
+                // Skip to midnight on the first of next month.
+                let (year, month) = if time.month() == 12 {
+                    (time.year() + 1, 1)
+                } else {
+                    (time.year(), time.month() + 1)
+                };
+                time = time
+                    .with_day(1)?
+                    .with_hour(0)?
+                    .with_minute(0)?
+                    .with_year(year)?
+                    .with_month(month)?;
+                continue;
+            }
+            if !self.matches_day(time) {
+                time = (time + Duration::days(1)).with_hour(0)?.with_minute(0)?;
+                continue;
+            }
+            if self.hours & (1 << time.hour()) == 0 {
+                time = (time + Duration::hours(1)).with_minute(0)?;
+                continue;
+            }
+            if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+                continue;
+            }
+            return Some(time);
+        }
+
+        None
+    }
+
+    /// Return the next `count` times this schedule fires after `after`.
+    pub fn upcoming(&self, after: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        let mut runs = Vec::with_capacity(count);
+        let mut after = after;
+        while runs.len() < count {
+            let Some(next) = self.next_after(after) else {
+                break;
+            };
+            runs.push(next);
+            after = next;
+        }
+        runs
+    }
+
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month & (1 << time.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << time.weekday().num_days_from_sunday()) != 0;
+
+        // Like every other cron: if both day fields are restricted, a day
+        // matching either one will do.
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+}
+
+/// Parse a single cron field into a bitset of allowed values.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            anyhow::bail!("cron step can not be zero: {:?}", part);
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, names, min)?, parse_value(end, names, min)?)
+        } else {
+            let start = parse_value(range, names, min)?;
+            // `5/15` means "every 15, starting at 5".
+            (start, if part.contains('/') { max } else { start })
+        };
+
+        if start < min || end > max || start > end {
+            anyhow::bail!("cron field {:?} is out of range {}-{}", part, min, max);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+fn parse_value(value: &str, names: &[&str], offset: u32) -> Result<u32> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(idx) = names.iter().position(|name| *name == lower) {
+        return Ok(idx as u32 + offset);
+    }
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid cron value: {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(CronSchedule::parse("* * * * *").is_ok());
+        assert!(CronSchedule::parse("*/15 9-17 * jan-jun mon-fri").is_ok());
+        assert!(CronSchedule::parse("@weekly").is_ok());
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("0 0 * * funday").is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // 2024-10-07 is a Monday.
+        let monday_9am = CronSchedule::parse("0 9 * * mon").unwrap();
+        assert_eq!(
+            monday_9am.next_after(at(2024, 10, 7, 8, 30)),
+            Some(at(2024, 10, 7, 9, 0))
+        );
+        assert_eq!(
+            monday_9am.next_after(at(2024, 10, 7, 9, 0)),
+            Some(at(2024, 10, 14, 9, 0))
+        );
+
+        let every_15 = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_15.upcoming(at(2024, 12, 31, 23, 40), 3),
+            vec![at(2024, 12, 31, 23, 45), at(2025, 1, 1, 0, 0), at(2025, 1, 1, 0, 15)]
+        );
+
+        let sunday = CronSchedule::parse("0 0 * * 7").unwrap();
+        assert_eq!(sunday.next_after(at(2024, 10, 7, 0, 0)), Some(at(2024, 10, 13, 0, 0)));
+
+        let never = CronSchedule::parse("0 0 30 2 *").unwrap();
+        assert_eq!(never.next_after(at(2024, 1, 1, 0, 0)), None);
+    }
+}
@@ -1,13 +1,51 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
-use dropshot::{endpoint, HttpError, Path, RequestContext};
+use bytes::Bytes;
+use dropshot::{
+    channel, endpoint, ClientErrorStatusCode, HttpError, Path, Query, RequestContext, TypedBody,
+    WebsocketChannelResult, WebsocketConnection,
+};
+use futures::{SinkExt, StreamExt};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio_tungstenite::{
+    tungstenite::protocol::{frame::coding::CloseCode, CloseFrame, Message, Role},
+    WebSocketStream,
+};
 
-use super::{Context, CorsResponseOk, RawResponseOk};
+use super::{
+    auth::{authorize, Access},
+    job_events,
+    jobs::parse_wait,
+    legacy::LEGACY_SUNSET,
+    registrations, retry,
+    stream::{self, Frames},
+    task_mode::mutate,
+    Context, CorsResponseOk, ETaggedResponseOk, EventStreamBody, EventStreamResponseOk, FailureReason, FileResponseOk,
+    Job, JobManifest, JobPhase, JobState, Jobs, JpegResponseOk, LogLevel, MachineRegistration,
+    MachineRegistrationParameters, MachineUpdate, MjpegBody, MjpegResponseOk, QueuedJob, RawResponseOk, Schedule,
+    ScheduleParameters, StoredFile, API_VERSION,
+};
 use crate::{
-    AnyMachine, Control, DesignFile, HardwareConfiguration, MachineInfo, MachineMakeModel, MachineState, MachineType,
-    SlicerConfiguration, TemporaryFile, Volume,
+    analyze::count_bodies,
+    analyze_stl,
+    bambu::{PlateMismatch, SdCardFull},
+    moonraker::BedMesh,
+    package_project, sanitize_job_name,
+    slicer::{
+        profiles::{PresetBundle, Profile},
+        remote::{SliceFormat, SliceParameters},
+    },
+    split_stl, stl_to_millimeters, Accessory, AccessoryError, AnalyzeParameters, AnyMachine, ChamberTooCold, Control,
+    DesignFile, FormSlicer, GcodeSlicer, HardwareConfiguration, Machine, MachineInfo, MachineMakeModel, MachineState,
+    MachineType, MaterialMismatch, MultipleBodies, PartSettings, PrintabilityReport, ProjectPart, Reservation,
+    SlicerConfiguration, SlicerInvocation, StlUnits, SuspiciousUnits, TemperatureSensorReading, TemporaryFile,
+    ThreeMfSlicer, Volume,
 };
 
 /// Return the OpenAPI schema in JSON format.
@@ -19,6 +57,7 @@ use crate::{
 pub async fn api_get_schema(
     rqctx: RequestContext<Arc<Context>>,
 ) -> Result<CorsResponseOk<serde_json::Value>, HttpError> {
+    authorize(&rqctx)?;
     Ok(CorsResponseOk(rqctx.context().schema.clone()))
 }
 
@@ -41,6 +80,40 @@ pub async fn ping(_rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk
     }))
 }
 
+/// The API versions this server supports.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct ApiVersions {
+    /// The newest API version, such as `v1`.
+    pub current: String,
+
+    /// Every API version this server serves, oldest first.
+    pub supported: Vec<String>,
+
+    /// When the deprecated unversioned routes (such as `/machines`) will
+    /// be removed, as an HTTP date.
+    pub legacy_sunset: String,
+}
+
+/// Return the API versions this server supports.
+///
+/// Clients should use the newest version they understand, as a path prefix
+/// (such as `/v1/machines`). Breaking changes to responses are only made in
+/// a new version, with older versions served alongside it, so clients pinned
+/// to a version aren't silently broken.
+#[endpoint {
+    method = GET,
+    path = "/versions",
+    tags = ["meta"],
+}]
+pub async fn get_versions(rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk<ApiVersions>, HttpError> {
+    authorize(&rqctx)?;
+    Ok(CorsResponseOk(ApiVersions {
+        current: API_VERSION.to_owned(),
+        supported: vec![API_VERSION.to_owned()],
+        legacy_sunset: LEGACY_SUNSET.to_owned(),
+    }))
+}
+
 /// Extra machine-specific information regarding a connected machine.
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case", tag = "type")]
@@ -52,6 +125,8 @@ pub enum ExtraMachineInfoResponse {
         current_stage: Option<bambulabs::message::Stage>,
         /// The nozzle diameter of the machine.
         nozzle_diameter: bambulabs::message::NozzleDiameter,
+        /// Status of each attached AMS unit, including temperature and humidity.
+        ams: Vec<crate::bambu::AmsUnit>,
         // Only run in debug mode. This is just to help us know what information we have.
         #[cfg(debug_assertions)]
         #[cfg(not(test))]
@@ -66,6 +141,24 @@ pub struct MachineInfoResponse {
     /// Machine Identifier (ID) for the specific Machine.
     pub id: String,
 
+    /// User-facing name of the Machine, such as `Rack 3 X1C`. This may be
+    /// used in place of the ID to address the Machine.
+    pub display_name: Option<String>,
+
+    /// Where the Machine physically is, such as `Rack 3, shelf B`.
+    pub location: Option<String>,
+
+    /// Other IDs the Machine is known by, such as the ID it had before it
+    /// was given its canonical one. These address the Machine as its ID
+    /// does.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// Labels the Machine was configured with, such as `building = "east"`,
+    /// which `/v1/bulk/machines` selects Machines by.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
     /// Information regarding the make and model of the attached Machine.
     pub make_model: MachineMakeModel,
 
@@ -92,6 +185,19 @@ pub struct MachineInfoResponse {
     /// may dictate if a machine is capable of taking a new job.
     pub state: MachineState,
 
+    /// Set if the slicer this Machine's jobs are sliced with isn't
+    /// installed, so it can't take new jobs until it is.
+    #[serde(default)]
+    pub slicing_unavailable: bool,
+
+    /// The job the Machine is printing, or is being sent, if any. The
+    /// whole job is at `/v1/machines/{id}/job`.
+    pub current_job_id: Option<String>,
+
+    /// Who holds the Machine for work by hand, if anyone. No jobs are sent
+    /// to it until the reservation is released or runs out.
+    pub reservation: Option<Reservation>,
+
     /// Additional, per-machine information which is specific to the
     /// underlying machine type.
     pub extra: Option<ExtraMachineInfoResponse>,
@@ -100,19 +206,36 @@ pub struct MachineInfoResponse {
 impl MachineInfoResponse {
     /// Create a new API JSON Machine from a Machine struct containing the
     /// handle(s) to actually construct a part.
-    pub(crate) async fn from_machine(id: &str, machine: &AnyMachine) -> anyhow::Result<Self> {
+    #[tracing::instrument(skip_all, fields(machine_id = id))]
+    pub(crate) async fn from_machine(id: &str, machine: &Machine, jobs: &Jobs) -> anyhow::Result<Self> {
+        let display_name = machine.get_display_name().map(|name| name.to_owned());
+        let location = machine.get_location().map(|location| location.to_owned());
+        let aliases = machine.get_aliases().to_vec();
+        let labels = machine.get_labels().clone();
+        let state = machine.state().await?;
+        let slicing_unavailable = machine.get_slicer().check_installed().is_err();
+        let current_job_id = jobs.current(id).await.map(|job| job.id);
+        let reservation = machine.reservation().cloned();
+        let machine = machine.get_machine();
         let machine_info = machine.machine_info().await?;
         let hardware_configuration = machine.hardware_configuration().await?;
         let progress = machine.progress().await?;
 
         Ok(MachineInfoResponse {
             id: id.to_owned(),
+            display_name,
+            location,
+            aliases,
+            labels,
             make_model: machine_info.make_model(),
             machine_type: machine_info.machine_type(),
             max_part_volume: machine_info.max_part_volume(),
             hardware_configuration,
             progress,
-            state: machine.state().await?,
+            state,
+            slicing_unavailable,
+            current_job_id,
+            reservation,
             extra: match machine {
                 AnyMachine::Moonraker(_) => Some(ExtraMachineInfoResponse::Moonraker {}),
                 AnyMachine::Usb(_) => Some(ExtraMachineInfoResponse::Usb {}),
@@ -123,6 +246,7 @@ impl MachineInfoResponse {
                     Some(ExtraMachineInfoResponse::Bambu {
                         current_stage: status.stg_cur,
                         nozzle_diameter: status.nozzle_diameter,
+                        ams: bambu.ams_units()?,
                         #[cfg(debug_assertions)]
                         #[cfg(not(test))]
                         raw_status: status,
@@ -135,8 +259,12 @@ impl MachineInfoResponse {
 
     /// Return an API JSON Machine from a Machine struct, returning a 500
     /// if the machine fails to enumerate.
-    pub(crate) async fn from_machine_http(id: &str, machine: &AnyMachine) -> Result<MachineInfoResponse, HttpError> {
-        Self::from_machine(id, machine).await.map_err(|e| {
+    pub(crate) async fn from_machine_http(
+        id: &str,
+        machine: &Machine,
+        jobs: &Jobs,
+    ) -> Result<MachineInfoResponse, HttpError> {
+        Self::from_machine(id, machine, jobs).await.map_err(|e| {
             tracing::warn!(
                 error = format!("{:?}", e),
                 "Error while fetching information for an API Machine response"
@@ -146,23 +274,159 @@ impl MachineInfoResponse {
     }
 }
 
+/// How long a listing of machines is shared between clients polling for
+/// it, before the machines are asked for their state again.
+const MACHINE_LISTING_MAX_AGE: Duration = Duration::from_secs(1);
+
 /// List available machines and their statuses
+///
+/// The response has an `ETag`; pass it back as `If-None-Match` to get an
+/// empty `304 Not Modified` if nothing has changed since.
 #[endpoint {
     method = GET,
-    path = "/machines",
+    path = "/v1/machines",
     tags = ["machines"],
 }]
 pub async fn get_machines(
     rqctx: RequestContext<Arc<Context>>,
-) -> Result<CorsResponseOk<Vec<MachineInfoResponse>>, HttpError> {
-    tracing::info!("listing machines");
+) -> Result<ETaggedResponseOk<Vec<MachineInfoResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
     let ctx = rqctx.context();
+    let serialize = |machines: Vec<MachineInfoResponse>| {
+        serde_json::to_vec(&machines)
+            .map(Bytes::from)
+            .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))
+    };
+    // The shared listing is of every machine, so keys scoped to some of
+    // them get their own.
+    let body = match access {
+        Access::All => {
+            ctx.machine_listing
+                .get(MACHINE_LISTING_MAX_AGE, || async {
+                    serialize(list_machines(ctx).await?)
+                })
+                .await?
+        }
+        Access::Machines { .. } => serialize(list_visible_machines(ctx, &access).await?)?,
+    };
+    Ok(ETaggedResponseOk::new(rqctx.request.headers(), body))
+}
+
+/// List the machines `access` allows the use of.
+pub(crate) async fn list_visible_machines(
+    ctx: &Context,
+    access: &Access,
+) -> Result<Vec<MachineInfoResponse>, HttpError> {
+    let mut machines = list_machines(ctx).await?;
+    machines.retain(|machine| access.allows(&machine.id, &machine.labels));
+    Ok(machines)
+}
+
+pub(crate) async fn list_machines(ctx: &Context) -> Result<Vec<MachineInfoResponse>, HttpError> {
+    tracing::info!("listing machines");
     let mut machines = vec![];
     for (key, machine) in ctx.machines.read().await.iter() {
-        let api_machine = MachineInfoResponse::from_machine_http(key, machine.read().await.get_machine()).await?;
+        let api_machine = MachineInfoResponse::from_machine_http(key, &*machine.read().await, &ctx.jobs).await?;
         machines.push(api_machine);
     }
-    Ok(CorsResponseOk(machines))
+    Ok(machines)
+}
+
+/// Register a machine at runtime, without editing the config file, such as
+/// a printer brought into the lab for a day.
+///
+/// The machine is removed again once its TTL is up (if given), or the
+/// server restarts.
+#[endpoint {
+    method = POST,
+    path = "/v1/machines",
+    tags = ["machines"],
+}]
+pub async fn register_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    body: TypedBody<MachineRegistrationParameters>,
+) -> Result<CorsResponseOk<MachineRegistration>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let params = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { add_machine(&ctx, params).await }).await?,
+    ))
+}
+
+pub(crate) async fn add_machine(
+    ctx: &Arc<Context>,
+    params: MachineRegistrationParameters,
+) -> Result<MachineRegistration, HttpError> {
+    ctx.check_writable()?;
+    tracing::info!(id = params.id, "registering machine");
+    registrations::register(ctx, params)
+        .await
+        .map_err(|e| HttpError::for_bad_request(None, format!("{:?}", e)))
+}
+
+/// Query parameters for removing a machine.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct RemoveMachineQueryParams {
+    /// Remove the machine even if it has an active job, cancelling the job.
+    /// The machine itself isn't stopped.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Remove a machine which was registered at runtime.
+///
+/// Machines with an active job are refused, unless `force` is set.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/machines/{id}",
+    tags = ["machines"],
+}]
+pub async fn remove_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<RemoveMachineQueryParams>,
+) -> Result<CorsResponseOk<MachineRegistration>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    let force = query_params.into_inner().force;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { deregister_machine(&ctx, &id, force).await }).await?,
+    ))
+}
+
+pub(crate) async fn deregister_machine(
+    ctx: &Context,
+    key: &str,
+    force: bool,
+) -> Result<MachineRegistration, HttpError> {
+    ctx.check_writable()?;
+    let id = resolve_machine_id(ctx, key, None).await?;
+    if ctx.registrations.get(&id).await.is_none() {
+        return Err(HttpError::for_bad_request(
+            Some("NotRegistered".to_owned()),
+            format!(
+                "machine {:?} wasn't registered at runtime, so it can only be removed from the config",
+                id
+            ),
+        ));
+    }
+    if !force {
+        if let Some(job) = ctx.jobs.current(&id).await {
+            return Err(HttpError::for_client_error(
+                Some("MachineBusy".to_owned()),
+                ClientErrorStatusCode::CONFLICT,
+                format!(
+                    "machine {:?} has an active job {:?}; pass `force` to remove it anyway",
+                    id, job.id
+                ),
+            ));
+        }
+    }
+
+    tracing::info!(id = id, force = force, "removing machine");
+    registrations::deregister(ctx, &id, false)
+        .await
+        .ok_or_else(|| HttpError::for_not_found(None, format!("machine not found by id: {:?}", key)))
 }
 
 /// List available machines and their statuses
@@ -172,6 +436,7 @@ pub async fn get_machines(
     tags = ["hidden"],
 }]
 pub async fn get_metrics(rqctx: RequestContext<Arc<Context>>) -> Result<RawResponseOk, HttpError> {
+    authorize(&rqctx)?.check_all()?;
     let ctx = rqctx.context();
     let mut response = String::new();
     let registry = ctx.registry.read().await;
@@ -185,205 +450,2922 @@ pub async fn get_metrics(rqctx: RequestContext<Arc<Context>>) -> Result<RawRespo
 /// The path parameters for performing operations on an machine.
 #[derive(Deserialize, Debug, JsonSchema, Serialize)]
 pub struct MachinePathParams {
-    /// The machine ID.
+    /// The machine ID, its display name, its serial number, or its
+    /// hostname.
     pub id: String,
 }
 
+/// What a machine `{id}` refers to.
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MachineIdType {
+    /// The machine's ID, as configured.
+    Id,
+
+    /// The machine's display name (matched case-insensitively).
+    DisplayName,
+
+    /// The machine's serial number (matched case-insensitively).
+    Serial,
+
+    /// The hostname (or IP address) the machine is reached at.
+    Hostname,
+}
+
+/// Query parameters for performing operations on a machine.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct MachineQueryParams {
+    /// What the `id` refers to. If unset, it's tried as an ID, then a
+    /// display name, then a serial number, then a hostname.
+    #[serde(default)]
+    pub id_type: Option<MachineIdType>,
+}
+
+/// More than one machine goes by the key a request addressed a machine by,
+/// such as two machines given the same display name.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("{key:?} could be any of machines {}; address one by its id", .ids.join(", "))]
+pub(crate) struct AmbiguousMachine {
+    /// The key the machine was addressed by.
+    pub key: String,
+
+    /// The IDs of every machine going by `key`.
+    pub ids: Vec<String>,
+}
+
+impl From<AmbiguousMachine> for HttpError {
+    fn from(ambiguous: AmbiguousMachine) -> Self {
+        tracing::warn!(id = ambiguous.key, error = ambiguous.to_string(), "ambiguous machine");
+        HttpError::for_client_error(
+            Some("AmbiguousMachine".to_owned()),
+            ClientErrorStatusCode::CONFLICT,
+            ambiguous.to_string(),
+        )
+    }
+}
+
+/// Find a machine by `key`, as the given kind of identifier, or if there
+/// isn't one, as its ID, falling back to matching on its display name,
+/// serial number and hostname in turn. Returns an [AmbiguousMachine] error
+/// if more than one machine matches `key` as the first kind of identifier
+/// anything matches it as, rather than picking one of them.
+pub(crate) async fn find_machine<'a>(
+    machines: &'a HashMap<String, RwLock<Machine>>,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<Option<(&'a String, &'a RwLock<Machine>)>, AmbiguousMachine> {
+    let id_types = match id_type {
+        Some(id_type) => vec![id_type],
+        None => vec![
+            MachineIdType::Id,
+            MachineIdType::DisplayName,
+            MachineIdType::Serial,
+            MachineIdType::Hostname,
+        ],
+    };
+
+    for id_type in id_types {
+        if id_type == MachineIdType::Id {
+            if let Some(found) = machines.get_key_value(key) {
+                return Ok(Some(found));
+            }
+        }
+
+        let mut found = vec![];
+        for (id, machine) in machines.iter() {
+            let matches = {
+                let machine = machine.read().await;
+                match id_type {
+                    MachineIdType::Id => machine.get_aliases().iter().any(|alias| alias == key),
+                    _ => machine_matches(&machine, key, id_type),
+                }
+            };
+            if matches {
+                found.push((id, machine));
+            }
+        }
+        match found.len() {
+            0 => continue,
+            1 => return Ok(found.pop()),
+            _ => {
+                let mut ids = found.into_iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+                ids.sort();
+                return Err(AmbiguousMachine {
+                    key: key.to_owned(),
+                    ids,
+                });
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check whether `machine` has `key` as the given kind of identifier,
+/// other than its ID.
+fn machine_matches(machine: &Machine, key: &str, id_type: MachineIdType) -> bool {
+    let found = match id_type {
+        MachineIdType::Id => return false,
+        MachineIdType::DisplayName => machine.get_display_name().map(str::to_owned),
+        MachineIdType::Serial => machine.get_serial().map(str::to_owned),
+        MachineIdType::Hostname => machine.get_machine().hostname(),
+    };
+    found.is_some_and(|found| found.eq_ignore_ascii_case(key))
+}
+
+/// Find a machine as [find_machine] does, returning its ID.
+pub(crate) async fn resolve_machine_id(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<String, HttpError> {
+    let machines = ctx.machines.read().await;
+    match find_machine(&machines, key, id_type).await? {
+        Some((id, _)) => Ok(id.clone()),
+        None => Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        )),
+    }
+}
+
 /// Get the status of a specific machine
 #[endpoint {
     method = GET,
-    path = "/machines/{id}",
+    path = "/v1/machines/{id}",
     tags = ["machines"],
 }]
 pub async fn get_machine(
     rqctx: RequestContext<Arc<Context>>,
     path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
 ) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
-    let params = path_params.into_inner();
-    let ctx = rqctx.context();
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(machine_info(rqctx.context(), &id, id_type).await?))
+}
 
-    tracing::info!(id = params.id, "finding machine");
-    match ctx.machines.read().await.get(&params.id) {
-        Some(machine) => Ok(CorsResponseOk(
-            MachineInfoResponse::from_machine_http(&params.id, machine.read().await.get_machine()).await?,
-        )),
+pub(crate) async fn machine_info(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<MachineInfoResponse, HttpError> {
+    tracing::info!(id = key, "finding machine");
+    let machines = ctx.machines.read().await;
+    match find_machine(&machines, key, id_type).await? {
+        Some((id, machine)) => MachineInfoResponse::from_machine_http(id, &*machine.read().await, &ctx.jobs).await,
         None => Err(HttpError::for_not_found(
             None,
-            format!("machine not found by id: {:?}", &params.id),
+            format!("machine not found by id: {:?}", key),
         )),
     }
 }
 
-/// The response from the `/print` endpoint.
-#[derive(Deserialize, Debug, JsonSchema, Serialize)]
-pub struct PrintJobResponse {
-    /// The job id used for this print.
-    pub job_id: String,
+/// Get the job a specific machine is printing, or is being sent
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/job",
+    tags = ["machines"],
+}]
+pub async fn get_machine_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<Job>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(machine_job(rqctx.context(), &id, id_type).await?))
+}
 
-    /// The parameters used for this print.
-    pub parameters: PrintParameters,
+pub(crate) async fn machine_job(ctx: &Context, key: &str, id_type: Option<MachineIdType>) -> Result<Job, HttpError> {
+    let id = resolve_machine_id(ctx, key, id_type).await?;
+    ctx.jobs
+        .current(&id)
+        .await
+        .ok_or_else(|| HttpError::for_not_found(None, format!("machine {:?} has no current job", id)))
 }
 
-/** Print a given file. File must be a sliceable 3D model. */
+/// Take a machine out of service for maintenance. The machine stays listed,
+/// with the state `maintenance`, but refuses new print jobs until it is
+/// enabled again.
 #[endpoint {
     method = POST,
-    path = "/print",
+    path = "/v1/machines/{id}/disable",
     tags = ["machines"],
 }]
-pub(crate) async fn print_file(
+pub async fn disable_machine(
     rqctx: RequestContext<Arc<Context>>,
-    body_param: dropshot::MultipartBody,
-) -> Result<CorsResponseOk<PrintJobResponse>, HttpError> {
-    let mut multipart = body_param.content;
-    let (file, params) = parse_multipart_print_request(&mut multipart).await?;
-    let ctx = rqctx.context().clone();
-    let machine_id = params.machine_id.clone();
-    let job_id = uuid::Uuid::new_v4();
-    let job_name = &params.job_name;
-    let slicer_configuration = &params.slicer_configuration;
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move {
+            set_machine_disabled(&ctx, &id, id_type, true).await
+        })
+        .await?,
+    ))
+}
+
+/// Put a machine that was disabled for maintenance back into service.
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/enable",
+    tags = ["machines"],
+}]
+pub async fn enable_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move {
+            set_machine_disabled(&ctx, &id, id_type, false).await
+        })
+        .await?,
+    ))
+}
 
+pub(crate) async fn set_machine_disabled(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    disabled: bool,
+) -> Result<MachineInfoResponse, HttpError> {
+    ctx.check_writable()?;
     let machines = ctx.machines.read().await;
-    let machine = match machines.get(&machine_id) {
-        Some(machine) => machine,
-        None => {
-            tracing::warn!(id = machine_id, "machine not found");
-            return Err(HttpError::for_not_found(
-                None,
-                format!("machine not found by id: {:?}", machine_id),
-            ));
-        }
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
     };
 
-    {
-        // If the machine is not idle, we can't print to it.
-        let m = machine.read().await;
-        let state = m.get_machine().state().await.map_err(|e| {
-            tracing::error!(error = format!("{:?}", e), "failed to get machine state");
-            HttpError::for_internal_error(format!("{:?}", e))
-        })?;
-        if state != MachineState::Idle {
-            return Err(HttpError::for_bad_request(
-                None,
-                format!("machine is not idle: {:?}", state),
-            ));
-        }
-    }
+    tracing::info!(id = id, disabled = disabled, "setting machine maintenance mode");
+    machine.write().await.set_disabled(disabled);
 
-    let filepath = std::env::temp_dir().join(format!(
-        "{}_{}",
-        job_id.simple(),
-        file.file_name.unwrap_or("file".to_string())
-    ));
-    tracing::info!(path = format!("{:?}", filepath), "Writing file to disk");
+    MachineInfoResponse::from_machine_http(id, &*machine.read().await, &ctx.jobs).await
+}
 
-    // TODO: we likely want to use the kittycad api to convert the file to the right format if its
-    // not already an stl file.
+/// How long to reserve a machine for, and who for.
+#[derive(Deserialize, Debug, Clone, JsonSchema, Serialize)]
+pub struct ReservationParameters {
+    /// How many seconds to hold the machine for, unless it's released
+    /// before then.
+    pub ttl_seconds: u64,
 
-    tokio::fs::write(&filepath, file.content).await.map_err(|e| {
-        tracing::error!(error = format!("{:?}", e), "failed to write stl file");
-        HttpError::for_bad_request(None, "failed to write stl file".to_string())
-    })?;
+    /// Who is holding the machine, such as a name or an email address.
+    #[serde(default)]
+    pub holder: Option<String>,
 
-    let tmpfile = TemporaryFile::new(&filepath)
-        .await
-        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?;
+    /// Why the machine is held, such as `filament change`.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
 
-    machine
-        .write()
-        .await
-        .build(
-            job_name,
-            &DesignFile::Stl(tmpfile.path().to_path_buf()),
-            &slicer_configuration.unwrap_or_default(),
-        )
-        .await
-        .map_err(|e| {
-            tracing::warn!(error = format!("{:?}", e), "failed to build file");
-            // Get the last 100 characters of the error message
-            let mut error_message = format!("{:?}", e);
-            if error_message.len() > 100 {
-                error_message = error_message
-                    .chars()
-                    .rev()
-                    .take(100)
-                    .collect::<String>()
-                    .chars()
-                    .rev()
-                    .collect::<String>();
-            }
-            HttpError::for_bad_request(
-                None,
+/// Reserve a machine for work by hand (such as changing filament, or
+/// calibrating), so no jobs are sent to it until the reservation is
+/// released or runs out.
+///
+/// Jobs sent to the machine in the meantime wait in its queue. Reserving a
+/// machine again renews the reservation, unless someone else holds it, which
+/// fails with a `409` (`MachineReserved`).
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/reserve",
+    tags = ["machines"],
+}]
+pub async fn reserve_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+    body: TypedBody<ReservationParameters>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    let params = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { reserve(&ctx, &id, id_type, params).await }).await?,
+    ))
+}
+
+pub(crate) async fn reserve(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    params: ReservationParameters,
+) -> Result<MachineInfoResponse, HttpError> {
+    ctx.check_writable()?;
+    if params.ttl_seconds == 0 {
+        return Err(HttpError::for_bad_request(
+            None,
+            "ttl_seconds must be at least 1".to_owned(),
+        ));
+    }
+    let now = chrono::Utc::now();
+    let expires_at = i64::try_from(params.ttl_seconds)
+        .ok()
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .ok_or_else(|| HttpError::for_bad_request(None, "ttl_seconds is too long".to_owned()))?;
+
+    let machines = ctx.machines.read().await;
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+    let mut machine = machine.write().await;
+
+    if let Some(held) = machine.reservation() {
+        if held.holder != params.holder {
+            return Err(HttpError::for_client_error(
+                Some("MachineReserved".to_owned()),
+                ClientErrorStatusCode::CONFLICT,
                 format!(
-                    "Your print failed, it might be too big for the slicer or something else. {}",
-                    error_message
+                    "machine {:?} is reserved by {} until {}",
+                    id,
+                    held.holder.as_deref().unwrap_or("someone else"),
+                    held.expires_at
                 ),
-            )
-        })?;
+            ));
+        }
+    }
 
-    Ok(CorsResponseOk(PrintJobResponse {
-        job_id: job_id.to_string(),
-        parameters: params,
-    }))
+    let reservation = Reservation {
+        holder: params.holder,
+        reason: params.reason,
+        created_at: now,
+        expires_at,
+    };
+    tracing::info!(
+        id = id,
+        holder = reservation.holder.as_deref(),
+        expires_at = reservation.expires_at.to_rfc3339(),
+        "reserving machine"
+    );
+    machine.set_reservation(Some(reservation));
+
+    MachineInfoResponse::from_machine_http(id, &machine, &ctx.jobs).await
 }
 
-pub(crate) struct FileAttachment {
-    file_name: Option<String>,
-    content: bytes::Bytes,
+/// Release a machine's reservation, so jobs waiting for it can be sent to
+/// it.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/machines/{id}/reserve",
+    tags = ["machines"],
+}]
+pub async fn release_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { release(&ctx, &id, id_type).await }).await?,
+    ))
 }
 
-/// Parameters for printing.
-#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
-pub(crate) struct PrintParameters {
-    /// The machine id to print to.
-    pub machine_id: String,
+pub(crate) async fn release(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<MachineInfoResponse, HttpError> {
+    ctx.check_writable()?;
+    let machines = ctx.machines.read().await;
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+    let mut machine = machine.write().await;
 
-    /// The name for the job.
-    pub job_name: String,
+    if machine.set_reservation(None).is_some() {
+        tracing::info!(id = id, "releasing machine");
+    }
 
-    /// Requested design-specific slicer configurations.
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub slicer_configuration: Option<SlicerConfiguration>,
+    MachineInfoResponse::from_machine_http(id, &machine, &ctx.jobs).await
 }
 
-/// Possible errors returned by print endpoints.
-#[derive(Debug, thiserror::Error)]
-pub enum Error {
-    /// Some error occurred when processing the multipart upload.
-    #[error(transparent)]
-    Multer(#[from] multer::Error),
+/// Stop whatever a machine is printing.
+///
+/// The job it was printing, if it was tracked by this server, is cancelled.
+/// To cancel a specific job instead, use `/v1/jobs/{id}/cancel`.
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/stop",
+    tags = ["machines"],
+}]
+pub async fn stop_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { stop(&ctx, &id, id_type).await }).await?,
+    ))
+}
 
-    /// Some error occurred when (de)serializing the event.
-    #[error(transparent)]
-    Serialization(#[from] serde_json::Error),
+pub(crate) async fn stop(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<MachineInfoResponse, HttpError> {
+    ctx.check_writable()?;
+    let machines = ctx.machines.read().await;
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
 
-    /// Missing attachment or event data.
-    #[error("Missing file attachment or printer params.")]
-    MissingFileOrParams,
-}
+    tracing::info!(id = id, "stopping machine");
+    machine.write().await.get_machine_mut().stop().await.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to stop machine");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
 
-impl From<Error> for HttpError {
-    fn from(_err: Error) -> Self {
-        Self::for_bad_request(None, "bad request".to_string())
+    if let Some(job) = ctx.jobs.current(id).await {
+        if job.state == JobState::Printing {
+            ctx.jobs
+                .fail(&job.id, FailureReason::UserCancel, "the machine was stopped")
+                .await;
+        }
     }
-}
 
-/// Parses multipart data into an request and file that we can slice and print.
-#[tracing::instrument(skip_all)]
-pub async fn parse_multipart_print_request(
-    multipart: &mut multer::Multipart<'_>,
-) -> Result<(FileAttachment, PrintParameters), Error> {
-    let mut maybe_file = None;
-    let mut maybe_params = None;
+    MachineInfoResponse::from_machine_http(id, &*machine.read().await, &ctx.jobs).await
+}
 
-    while let Some(field) = multipart.next_field().await? {
+/// Pause the job a machine is printing.
+///
+/// Fails with a `409` if the machine can't pause jobs (`SuspendUnsupported`),
+/// or isn't printing (`MachineNotPrinting`).
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/pause",
+    tags = ["machines"],
+}]
+pub async fn pause_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { suspend(&ctx, &id, id_type, true).await }).await?,
+    ))
+}
+
+/// Resume the job a machine has paused.
+///
+/// Fails with a `409` if the machine can't pause jobs (`SuspendUnsupported`),
+/// or isn't paused (`MachineNotPaused`).
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/resume",
+    tags = ["machines"],
+}]
+pub async fn resume_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<MachineInfoResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { suspend(&ctx, &id, id_type, false).await }).await?,
+    ))
+}
+
+/// Pause (or resume, if not `pause`) the job on the machine `key`.
+pub(crate) async fn suspend(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    pause: bool,
+) -> Result<MachineInfoResponse, HttpError> {
+    ctx.check_writable()?;
+    let machines = ctx.machines.read().await;
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+    let mut machine = machine.write().await;
+
+    if !machine.get_machine().can_suspend() {
+        return Err(HttpError::for_client_error(
+            Some("SuspendUnsupported".to_owned()),
+            ClientErrorStatusCode::CONFLICT,
+            format!("machine {:?} can't pause or resume jobs", id),
+        ));
+    }
+
+    let state = machine.state().await.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to get machine state");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
+    match (pause, &state) {
+        (true, MachineState::Running) | (false, MachineState::Paused { .. }) => {}
+        (true, _) => {
+            return Err(HttpError::for_client_error(
+                Some("MachineNotPrinting".to_owned()),
+                ClientErrorStatusCode::CONFLICT,
+                format!("machine {:?} isn't printing (it's {:?})", id, state),
+            ));
+        }
+        (false, _) => {
+            return Err(HttpError::for_client_error(
+                Some("MachineNotPaused".to_owned()),
+                ClientErrorStatusCode::CONFLICT,
+                format!("machine {:?} isn't paused (it's {:?})", id, state),
+            ));
+        }
+    }
+
+    tracing::info!(id = id, pause = pause, "pausing or resuming machine");
+    let outcome = if pause {
+        machine.pause().await
+    } else {
+        machine.resume().await
+    };
+    outcome.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to pause or resume machine");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
+
+    MachineInfoResponse::from_machine_http(id, &machine, &ctx.jobs).await
+}
+
+/// How verbosely a machine logs.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema, Serialize)]
+pub struct MachineLogLevel {
+    /// The level the machine logs at, or unset to log at the server's
+    /// level like every other machine.
+    pub level: Option<LogLevel>,
+}
+
+/// Change how verbosely a machine logs, such as to trace one misbehaving
+/// machine without drowning the logs for every other machine.
+///
+/// This lasts until it's changed back, or the server restarts.
+#[endpoint {
+    method = PUT,
+    path = "/v1/machines/{id}/log_level",
+    tags = ["machines"],
+}]
+pub async fn set_machine_log_level(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+    body: TypedBody<MachineLogLevel>,
+) -> Result<CorsResponseOk<MachineLogLevel>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    let level = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(
+            &rqctx,
+            |ctx| async move { set_log_level(&ctx, &id, id_type, level).await },
+        )
+        .await?,
+    ))
+}
+
+pub(crate) async fn set_log_level(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    level: MachineLogLevel,
+) -> Result<MachineLogLevel, HttpError> {
+    ctx.check_writable()?;
+    let machines = ctx.machines.read().await;
+    let Some((id, _)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    tracing::info!(
+        id = id,
+        level = format!("{:?}", level.level),
+        "setting machine log level"
+    );
+    ctx.log_levels.set(id, level.level);
+
+    Ok(MachineLogLevel {
+        level: ctx.log_levels.get(id),
+    })
+}
+
+/// An action to take on many machines at once.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum BulkAction {
+    /// Pause the current job.
+    Pause,
+
+    /// Resume the paused job.
+    Resume,
+
+    /// Stop the current job. This doesn't fail the job; it's marked as
+    /// such once the machine reports it's stopped.
+    Stop,
+
+    /// Turn the (chamber) light on or off.
+    SetLight {
+        /// Whether the light should be on.
+        on: bool,
+    },
+}
+
+/// The body of a request to the `/bulk/machines` endpoint.
+#[derive(Deserialize, Debug, Clone, JsonSchema, Serialize)]
+pub struct BulkRequest {
+    /// The machines to act on, addressed as `/machines/{id}` would address
+    /// them.
+    #[serde(default)]
+    pub machine_ids: Vec<String>,
+
+    /// Also act on machines whose labels match this selector: a comma
+    /// separated list of `key=value` (the label is set to that value) or
+    /// `key` (the label is set) terms, all of which must match. An empty
+    /// selector matches every machine.
+    pub selector: Option<String>,
+
+    /// The action to take.
+    pub action: BulkAction,
+}
+
+/// The outcome of a bulk action on one machine.
+#[derive(Deserialize, Debug, Clone, PartialEq, JsonSchema, Serialize)]
+pub struct BulkResult {
+    /// The machine's ID, or the key it was requested by if it wasn't
+    /// found.
+    pub machine_id: String,
+
+    /// Whether the action succeeded.
+    pub success: bool,
+
+    /// Why the action failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Pause, resume or stop many machines at once, or set their lights, such
+/// as pausing everything when the fire alarm goes off. Each machine is
+/// acted on concurrently, and the outcome for each is returned; one machine
+/// failing doesn't stop the action on the rest.
+#[endpoint {
+    method = POST,
+    path = "/v1/bulk/machines",
+    tags = ["machines"],
+}]
+pub async fn bulk_machines(
+    rqctx: RequestContext<Arc<Context>>,
+    body: TypedBody<BulkRequest>,
+) -> Result<CorsResponseOk<Vec<BulkResult>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let body = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { bulk_action(&ctx, &access, body).await }).await?,
+    ))
+}
+
+/// Check whether `labels` match a selector such as `building=east,floor`.
+pub(crate) fn labels_match(labels: &BTreeMap<String, String>, selector: &str) -> bool {
+    selector
+        .split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .all(|term| match term.split_once('=') {
+            Some((key, value)) => labels.get(key.trim()).is_some_and(|found| found == value.trim()),
+            None => labels.contains_key(term),
+        })
+}
+
+pub(crate) async fn bulk_action(
+    ctx: &Context,
+    access: &Access,
+    request: BulkRequest,
+) -> Result<Vec<BulkResult>, HttpError> {
+    ctx.check_writable()?;
+    if request.machine_ids.is_empty() && request.selector.is_none() {
+        return Err(HttpError::for_bad_request(
+            None,
+            "either machine_ids or a selector is required".to_owned(),
+        ));
+    }
+
+    let machines = ctx.machines.read().await;
+
+    let mut results = vec![];
+    let mut ids: Vec<&String> = vec![];
+    for key in &request.machine_ids {
+        match find_machine(&machines, key, None).await {
+            Ok(Some((id, machine))) => {
+                access.check(id, machine.read().await.get_labels())?;
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            Ok(None) => results.push(BulkResult {
+                machine_id: key.clone(),
+                success: false,
+                error: Some("machine not found".to_owned()),
+            }),
+            Err(ambiguous) => results.push(BulkResult {
+                machine_id: key.clone(),
+                success: false,
+                error: Some(ambiguous.to_string()),
+            }),
+        }
+    }
+    if let Some(selector) = &request.selector {
+        for (id, machine) in machines.iter() {
+            let machine = machine.read().await;
+            let labels = machine.get_labels();
+            if !ids.contains(&id) && labels_match(labels, selector) && access.allows(id, labels) {
+                ids.push(id);
+            }
+        }
+    }
+
+    tracing::info!(
+        action = format!("{:?}", request.action),
+        machines = ids.len(),
+        "running bulk action"
+    );
+    let machines = &*machines;
+    let action = request.action;
+    let outcomes = futures::future::join_all(ids.into_iter().map(|id| async move {
+        let mut machine = machines[id].write().await;
+        let outcome = match action {
+            BulkAction::Pause => machine.pause().await,
+            BulkAction::Resume => machine.resume().await,
+            BulkAction::Stop => machine.get_machine_mut().stop().await,
+            BulkAction::SetLight { on } => machine.set_light(on).await,
+        };
+        if let Err(e) = &outcome {
+            tracing::warn!(id = id, error = format!("{:?}", e), "bulk action failed");
+        }
+        BulkResult {
+            machine_id: id.clone(),
+            success: outcome.is_ok(),
+            error: outcome.err().map(|e| e.to_string()),
+        }
+    }))
+    .await;
+
+    results.extend(outcomes);
+    Ok(results)
+}
+
+/// The path parameters for operating on a machine's accessory.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct AccessoryPathParams {
+    /// The machine ID, its display name, its serial number, or its
+    /// hostname.
+    pub id: String,
+
+    /// The accessory's name.
+    pub name: String,
+}
+
+/// The body of a request to control an accessory.
+#[derive(Deserialize, Debug, Clone, Copy, JsonSchema, Serialize)]
+pub struct AccessoryControl {
+    /// Whether the accessory should be on.
+    pub on: bool,
+}
+
+/// List a machine's accessories (such as lights, fans, filters and
+/// multi-material units), whether each can be controlled, and their state.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/accessories",
+    tags = ["machines"],
+}]
+pub async fn get_accessories(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<Vec<Accessory>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(list_accessories(rqctx.context(), &id, id_type).await?))
+}
+
+pub(crate) async fn list_accessories(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<Vec<Accessory>, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((_, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let accessories = machine.read().await.accessories().await;
+    accessories.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to list accessories");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })
+}
+
+/// Turn one of a machine's accessories on or off, where it can be
+/// controlled. Returns the machine's accessories.
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/accessories/{name}",
+    tags = ["machines"],
+}]
+pub async fn control_accessory(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<AccessoryPathParams>,
+    query_params: Query<MachineQueryParams>,
+    body: TypedBody<AccessoryControl>,
+) -> Result<CorsResponseOk<Vec<Accessory>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let path_params = path_params.into_inner();
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &path_params.id, id_type).await?;
+    let body = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move {
+            set_accessory(&ctx, &path_params.id, id_type, &path_params.name, body).await
+        })
+        .await?,
+    ))
+}
+
+pub(crate) async fn set_accessory(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    name: &str,
+    control: AccessoryControl,
+) -> Result<Vec<Accessory>, HttpError> {
+    ctx.check_writable()?;
+    {
+        let machines = ctx.machines.read().await;
+        let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+            return Err(HttpError::for_not_found(
+                None,
+                format!("machine not found by id: {:?}", key),
+            ));
+        };
+
+        tracing::info!(id = id, accessory = name, on = control.on, "controlling accessory");
+        let result = machine.write().await.set_accessory(name, control.on).await;
+        result.map_err(|e| match e.downcast_ref::<AccessoryError>() {
+            Some(AccessoryError::NotFound(_)) => HttpError::for_not_found(None, e.to_string()),
+            Some(AccessoryError::NotControllable(_)) => HttpError::for_bad_request(None, e.to_string()),
+            None => {
+                tracing::error!(error = format!("{:?}", e), "failed to control accessory");
+                HttpError::for_internal_error(format!("{:?}", e))
+            }
+        })?;
+    }
+
+    list_accessories(ctx, key, id_type).await
+}
+
+/// Get the mesh of bed heights a machine compensates for, such as to
+/// render it as a heightmap.
+///
+/// Only Klipper (Moonraker) machines report their bed mesh. Fails with a
+/// `404` (`NoBedMesh`) if the machine hasn't got one loaded.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/bed_mesh",
+    tags = ["machines"],
+}]
+pub async fn get_bed_mesh(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<BedMesh>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(bed_mesh(rqctx.context(), &id, id_type).await?))
+}
+
+pub(crate) async fn bed_mesh(ctx: &Context, key: &str, id_type: Option<MachineIdType>) -> Result<BedMesh, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((_, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let bed_mesh = machine.read().await.bed_mesh().await.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to get bed mesh");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
+    bed_mesh.ok_or_else(|| {
+        HttpError::for_client_error(
+            Some("NoBedMesh".to_owned()),
+            ClientErrorStatusCode::NOT_FOUND,
+            format!("machine {:?} has no bed mesh loaded", key),
+        )
+    })
+}
+
+/// Get a machine's temperature sensors, by name, such as its extruder, bed
+/// and chamber. A Bambu printer's AMS units are included too, as
+/// `ams_<id>`.
+///
+/// Only Bambu and Klipper (Moonraker) machines report their temperatures;
+/// others have no sensors, so return none.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/temperatures",
+    tags = ["machines"],
+}]
+pub async fn get_machine_temperatures(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<BTreeMap<String, TemperatureSensorReading>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(
+        machine_temperatures(rqctx.context(), &id, id_type).await?,
+    ))
+}
+
+pub(crate) async fn machine_temperatures(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<BTreeMap<String, TemperatureSensorReading>, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((_, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let temperatures = machine.read().await.temperatures().await.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to read temperatures");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
+    Ok(temperatures.into_iter().collect())
+}
+
+/// Take a still image from a machine's camera, as a JPEG.
+///
+/// Bambu printers grab a frame from their camera stream (which needs
+/// `ffmpeg` for the X1 and H2 series), and Klipper (Moonraker) machines
+/// fetch their first enabled webcam's snapshot URL. Fails with a `404`
+/// (`NoCamera`) if the machine hasn't got a camera, or a `503`
+/// (`CameraUnavailable`) if it couldn't be read.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/snapshot",
+    tags = ["machines"],
+}]
+pub async fn get_machine_snapshot(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<JpegResponseOk, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(JpegResponseOk(machine_snapshot(rqctx.context(), &id, id_type).await?))
+}
+
+pub(crate) async fn machine_snapshot(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<Vec<u8>, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((_, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let image = machine.read().await.snapshot().await.map_err(|e| {
+        tracing::warn!(error = format!("{:?}", e), "failed to take snapshot");
+        HttpError::for_unavail(Some("CameraUnavailable".to_owned()), format!("{:#}", e))
+    })?;
+    image.ok_or_else(|| {
+        HttpError::for_client_error(
+            Some("NoCamera".to_owned()),
+            ClientErrorStatusCode::NOT_FOUND,
+            format!("machine {:?} has no camera", key),
+        )
+    })
+}
+
+/// Watch a machine's camera live, as an MJPEG stream
+/// (`multipart/x-mixed-replace`), such as in an `<img>` tag.
+///
+/// Cameras which only take stills are asked for one every second. Fails
+/// with a `404` (`NoCamera`) if the machine hasn't got a camera, or a `503`
+/// (`CameraUnavailable`) if it couldn't be read.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/stream",
+    tags = ["machines"],
+}]
+pub async fn get_machine_stream(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<MjpegResponseOk, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(MjpegResponseOk(machine_stream(rqctx.context(), &id, id_type).await?))
+}
+
+pub(crate) async fn machine_stream(
+    ctx: &Arc<Context>,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<MjpegBody, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((id, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let machine = machine.read().await;
+    let unavailable = |e: anyhow::Error| {
+        tracing::warn!(id = id, error = format!("{:?}", e), "failed to stream camera");
+        HttpError::for_unavail(Some("CameraUnavailable".to_owned()), format!("{:#}", e))
+    };
+    let mut frames = Frames::open(ctx, id, &machine).await.map_err(unavailable)?;
+    let first = frames.first(&machine).await.map_err(unavailable)?.ok_or_else(|| {
+        HttpError::for_client_error(
+            Some("NoCamera".to_owned()),
+            ClientErrorStatusCode::NOT_FOUND,
+            format!("machine {:?} has no camera", key),
+        )
+    })?;
+
+    tracing::info!(id = id, "streaming camera");
+    Ok(stream::spawn(id.clone(), first, frames))
+}
+
+/// Get what's been recorded of a Bambu printer's MQTT traffic: message
+/// counts by topic, the most recent payloads, and payloads which couldn't be
+/// parsed.
+///
+/// This is for troubleshooting payloads from new firmware, and is only
+/// recorded for printers configured with `debug_mqtt`.
+#[endpoint {
+    method = GET,
+    path = "/v1/machines/{id}/debug/mqtt",
+    tags = ["machines"],
+}]
+pub async fn get_machine_mqtt_debug(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+) -> Result<CorsResponseOk<bambulabs::debug::MqttDebugSnapshot>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    Ok(CorsResponseOk(machine_mqtt_debug(rqctx.context(), &id, id_type).await?))
+}
+
+pub(crate) async fn machine_mqtt_debug(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+) -> Result<bambulabs::debug::MqttDebugSnapshot, HttpError> {
+    let machines = ctx.machines.read().await;
+    let Some((_, machine)) = find_machine(&machines, key, id_type).await? else {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", key),
+        ));
+    };
+
+    let snapshot = machine.read().await.mqtt_debug();
+    snapshot.ok_or_else(|| {
+        HttpError::for_bad_request(
+            Some("MqttDebugDisabled".to_owned()),
+            format!("MQTT traffic isn't recorded for {:?}; set `debug_mqtt` for it", key),
+        )
+    })
+}
+
+/// Watch a machine's state, temperatures and job progress over a WebSocket.
+///
+/// The machine's state is sent as soon as the connection opens, then again
+/// whenever it changes, as a JSON `MachineUpdate` per message. Machines
+/// which aren't found have the connection closed straight away.
+#[channel {
+    protocol = WEBSOCKETS,
+    path = "/v1/machines/{id}/ws",
+    tags = ["machines"],
+}]
+pub async fn watch_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+    conn: WebsocketConnection,
+) -> WebsocketChannelResult {
+    let access = authorize(&rqctx)?;
+    let ctx = rqctx.context();
+    let key = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(ctx, &key, id_type).await?;
+    let mut ws = WebSocketStream::from_raw_socket(conn.into_inner(), Role::Server, None).await;
+
+    let first = {
+        let machines = ctx.machines.read().await;
+        match find_machine(&machines, &key, id_type).await {
+            Ok(Some((id, machine))) => Some(MachineUpdate::sample(ctx, id, &*machine.read().await).await),
+            Ok(None) => None,
+            Err(ambiguous) => Some(Err(ambiguous.into())),
+        }
+    };
+    let first = match first {
+        Some(Ok(update)) => update,
+        Some(Err(e)) => {
+            return close(&mut ws, CloseCode::Error, format!("{:?}", e)).await;
+        }
+        None => {
+            return close(
+                &mut ws,
+                CloseCode::Policy,
+                format!("machine not found by id: {:?}", key),
+            )
+            .await;
+        }
+    };
+
+    let mut watch = ctx.updates.watch(&first.machine_id);
+    ws.send(Message::Text(serde_json::to_string(&first)?)).await?;
+    loop {
+        tokio::select! {
+            update = watch.recv() => {
+                let Some(update) = update else {
+                    break;
+                };
+                ws.send(Message::Text(serde_json::to_string(&update)?)).await?;
+            }
+            message = ws.next() => match message {
+                None | Some(Ok(Message::Close(_))) => break,
+                Some(Err(e)) => return Err(e.into()),
+                // Clients have nothing to say; pings are answered by
+                // tungstenite.
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Close a WebSocket, telling the client why.
+async fn close<StreamT>(ws: &mut WebSocketStream<StreamT>, code: CloseCode, reason: String) -> WebsocketChannelResult
+where
+    StreamT: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    ws.close(Some(CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+    .await?;
+    Ok(())
+}
+
+/// The response from the `/print` endpoint.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrintJobResponse {
+    /// The job id used for this print. Empty for dry runs, which don't
+    /// start a job.
+    pub job_id: String,
+
+    /// The parameters used for this print.
+    pub parameters: PrintParameters,
+
+    /// For dry runs, exactly how the machine's slicer would be run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slicer_invocation: Option<SlicerInvocation>,
+
+    /// Anything about the design files which may not print as expected,
+    /// such as a file made up of separate bodies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/** Print a given file. File must be a sliceable 3D model. */
+#[endpoint {
+    method = POST,
+    path = "/v1/print",
+    tags = ["machines"],
+}]
+pub(crate) async fn print_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<CorsResponseOk<PrintJobResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    Ok(CorsResponseOk(
+        mutate(
+            &rqctx,
+            |ctx| async move { print_upload(&ctx, &access, body_param).await },
+        )
+        .await?,
+    ))
+}
+
+pub(crate) async fn print_upload(
+    ctx: &Context,
+    access: &Access,
+    body_param: dropshot::MultipartBody,
+) -> Result<PrintJobResponse, HttpError> {
+    ctx.check_writable()?;
+    let mut multipart = body_param.content;
+    let (files, params) = parse_multipart_parts::<PrintParameters>(&mut multipart).await?;
+    access.check_machine(ctx, &params.machine_id, None).await?;
+    let mut files = match (files.len(), &params.file_url, &params.file_id) {
+        (0, Some(file_url), None) => vec![ctx.file_urls.fetch(file_url).await?],
+        (0, None, Some(file_id)) => vec![read_stored_file(ctx, file_id).await?],
+        (0, None, None) => return Err(Error::MissingFileOrParams.into()),
+        (_, None, None) => files,
+        _ => {
+            return Err(HttpError::for_bad_request(
+                None,
+                "upload a file, or set one of file_url or file_id, not more than one".to_owned(),
+            ));
+        }
+    };
+
+    let bodies = files.iter().map(|file| count_bodies(&file.content)).collect::<Vec<_>>();
+    let split = params.multiple_bodies == MultipleBodies::Split && bodies.iter().any(|bodies| *bodies > 1);
+    let warnings = if split {
+        vec![]
+    } else {
+        files
+            .iter()
+            .zip(&bodies)
+            .filter(|(_, bodies)| **bodies > 1)
+            .map(|(file, bodies)| {
+                format!(
+                    "{} has {} separate bodies, which are printed where they are in the file (overlapping, if \
+                     they're an assembly); set multiple_bodies to `split` to print each on its own",
+                    file.file_name.as_deref().unwrap_or("the file"),
+                    bodies
+                )
+            })
+            .collect()
+    };
+
+    let mut units = params.units;
+    let file = if files.len() == 1 && params.parts.is_empty() && !split {
+        files.remove(0)
+    } else {
+        // The parts are scaled as they're packaged, so the project is
+        // already in millimeters.
+        units = Some(StlUnits::Mm);
+        package_parts(&files, &params)?
+    };
+
+    if params.dry_run {
+        let slicer_invocation = print_dry_run(ctx, &params, &file).await?;
+        return Ok(PrintJobResponse {
+            job_id: String::new(),
+            parameters: params,
+            slicer_invocation: Some(slicer_invocation),
+            warnings,
+        });
+    }
+
+    let job_id = start_print_job(
+        ctx,
+        &params.machine_id,
+        &params.job_name,
+        file,
+        units,
+        &params.slicer_configuration.unwrap_or_default(),
+        params.override_material,
+        params.override_chamber_preheat,
+    )
+    .await?;
+
+    for warning in &warnings {
+        tracing::warn!(id = job_id, "{}", warning);
+    }
+    Ok(PrintJobResponse {
+        job_id,
+        parameters: params,
+        slicer_invocation: None,
+        warnings,
+    })
+}
+
+/// Return exactly how the machine in `params` would run its slicer to slice
+/// `file`, without slicing it or starting a job.
+async fn print_dry_run(
+    ctx: &Context,
+    params: &PrintParameters,
+    file: &FileAttachment,
+) -> Result<SlicerInvocation, HttpError> {
+    let slicer_configuration = params.slicer_configuration.unwrap_or_default();
+    check_slicer_configuration(&slicer_configuration)?;
+
+    let machines = ctx.machines.read().await;
+    let Some((machine_id, machine)) = find_machine(&machines, &params.machine_id, None).await? else {
+        tracing::warn!(id = params.machine_id, "machine not found");
+        return Err(HttpError::for_not_found(
+            None,
+            format!("machine not found by id: {:?}", params.machine_id),
+        ));
+    };
+
+    machine
+        .read()
+        .await
+        .slice_dry_run(&unwritten_design_file(file), &slicer_configuration)
+        .await
+        .map_err(build_error)?
+        .ok_or_else(|| dry_run_unsupported(machine_id))
+}
+
+/// Return where `file` would be written to be sliced, for a dry run, which
+/// never writes it.
+fn unwritten_design_file(file: &FileAttachment) -> DesignFile {
+    DesignFile::from_path(&crate::spool_dir().join(format!(
+        "{}_{}",
+        uuid::Uuid::new_v4().simple(),
+        sanitize_job_name(file.file_name.as_deref().unwrap_or("file"))
+    )))
+}
+
+/// The error for a dry run with a slicer (or machine) which doesn't run a
+/// slicer command line locally.
+fn dry_run_unsupported(name: &str) -> HttpError {
+    HttpError::for_client_error(
+        Some("DryRunUnsupported".to_owned()),
+        ClientErrorStatusCode::BAD_REQUEST,
+        format!("{} doesn't run a slicer locally, so has nothing to dry run", name),
+    )
+}
+
+/// Package several uploaded parts into a single 3MF project, with the
+/// settings in `params.parts` applied to each part in turn. Each part is
+/// converted to millimeters from `params.units` first, then split into its
+/// separate bodies, if `params.multiple_bodies` says to, each with the
+/// part's settings.
+fn package_parts(files: &[FileAttachment], params: &PrintParameters) -> Result<FileAttachment, HttpError> {
+    if !params.parts.is_empty() && params.parts.len() != files.len() {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!(
+                "parts has settings for {} parts, but {} files were uploaded",
+                params.parts.len(),
+                files.len()
+            ),
+        ));
+    }
+
+    let mut parts = vec![];
+    for (index, file) in files.iter().enumerate() {
+        let stl = stl_to_millimeters(&file.content, params.units)
+            .map(|scaled| scaled.unwrap_or_else(|| file.content.to_vec()))
+            .map_err(|e| units_error(&params.machine_id, e))?;
+        let name = file
+            .file_name
+            .clone()
+            .unwrap_or_else(|| format!("part{}.stl", index + 1));
+        let settings = params.parts.get(index).copied().unwrap_or_default();
+        let bodies = match params.multiple_bodies {
+            MultipleBodies::Split => split_stl(&stl),
+            MultipleBodies::Warn => None,
+        };
+        match bodies {
+            Some(bodies) => parts.extend(
+                bodies
+                    .into_iter()
+                    .enumerate()
+                    .map(|(body, stl)| (format!("{} #{}", name, body + 1), stl, settings)),
+            ),
+            None => parts.push((name, stl, settings)),
+        }
+    }
+    let parts = parts
+        .iter()
+        .map(|(name, stl, settings)| ProjectPart {
+            name,
+            stl,
+            settings: *settings,
+        })
+        .collect::<Vec<_>>();
+
+    let project = package_project(&parts).map_err(|e| HttpError::for_bad_request(None, format!("{:#}", e)))?;
+    Ok(FileAttachment {
+        file_name: Some(format!("{}.3mf", params.job_name)),
+        content: project.into(),
+    })
+}
+
+/// Return the error for a part which couldn't be converted to millimeters.
+fn units_error(machine_id: &str, e: anyhow::Error) -> HttpError {
+    match e.downcast_ref::<SuspiciousUnits>() {
+        Some(suspicious) => {
+            tracing::warn!(id = machine_id, error = suspicious.to_string(), "refusing print");
+            HttpError::for_bad_request(Some("SuspiciousUnits".to_owned()), suspicious.to_string())
+        }
+        None => HttpError::for_bad_request(None, format!("{:#}", e)),
+    }
+}
+
+/// Parameters for a test print.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct TestPrintParameters {
+    /// Which test print to send.
+    pub test_print: TestPrint,
+
+    /// Requested slicer configurations, such as which filament to use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slicer_configuration: Option<SlicerConfiguration>,
+
+    /// Print even if the loaded filament isn't the material the slicer
+    /// profile expects.
+    #[serde(default)]
+    pub override_material: bool,
+
+    /// Start the job without waiting for the machine's chamber to warm
+    /// up, for ABS or ASA jobs on machines with a chamber preheat
+    /// configured.
+    #[serde(default)]
+    pub override_chamber_preheat: bool,
+}
+
+/// Print one of the built-in test prints on a machine.
+///
+/// This is meant for commissioning a new machine: a calibration cube, a
+/// bed level test or a temperature tower can be printed without having to
+/// upload a file.
+#[endpoint {
+    method = POST,
+    path = "/v1/machines/{id}/test-print",
+    tags = ["machines"],
+}]
+pub async fn test_print(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    query_params: Query<MachineQueryParams>,
+    body_param: TypedBody<TestPrintParameters>,
+) -> Result<CorsResponseOk<PrintJobResponse>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let id_type = query_params.into_inner().id_type;
+    access.check_machine(rqctx.context(), &id, id_type).await?;
+    let params = body_param.into_inner();
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move {
+            start_test_print(&ctx, &id, id_type, params).await
+        })
+        .await?,
+    ))
+}
+
+pub(crate) async fn start_test_print(
+    ctx: &Context,
+    key: &str,
+    id_type: Option<MachineIdType>,
+    params: TestPrintParameters,
+) -> Result<PrintJobResponse, HttpError> {
+    ctx.check_writable()?;
+    let machine_id = resolve_machine_id(ctx, key, id_type).await?;
+    tracing::info!(
+        id = machine_id,
+        test_print = params.test_print.name(),
+        "starting test print"
+    );
+
+    // The temperature tower's temperatures can be overridden, such as for
+    // materials which print hotter than PLA.
+    let mut slicer_configuration = params.slicer_configuration.unwrap_or_default();
+    if slicer_configuration.temperature_steps.is_none() {
+        slicer_configuration.temperature_steps = params.test_print.temperature_steps();
+    }
+
+    let parameters = PrintParameters {
+        machine_id,
+        job_name: params.test_print.name().to_owned(),
+        file_url: None,
+        file_id: None,
+        // Test prints are modelled in millimeters, however small.
+        units: Some(StlUnits::Mm),
+        // The squares of the bed level print are meant to be apart.
+        multiple_bodies: MultipleBodies::Warn,
+        parts: vec![],
+        slicer_configuration: Some(slicer_configuration),
+        override_material: params.override_material,
+        override_chamber_preheat: params.override_chamber_preheat,
+        dry_run: false,
+    };
+    let file = FileAttachment {
+        file_name: Some(params.test_print.file_name()),
+        content: params.test_print.stl().into(),
+    };
+
+    let job_id = start_print_job(
+        ctx,
+        &parameters.machine_id,
+        &parameters.job_name,
+        file,
+        parameters.units,
+        &parameters.slicer_configuration.unwrap_or_default(),
+        parameters.override_material,
+        parameters.override_chamber_preheat,
+    )
+    .await?;
+
+    Ok(PrintJobResponse {
+        job_id,
+        parameters,
+        slicer_invocation: None,
+        warnings: vec![],
+    })
+}
+
+/// Slice a design file and send it to a machine, tracking it as a job.
+/// The machine must not be disabled for maintenance. If it's busy, the job
+/// waits in the machine's queue until it's free. Unless `override_material`
+/// is set, the loaded filament must also match the material the slicer
+/// profile expects, once the job's turn comes. Unless `override_chamber_preheat`
+/// is set, ABS and ASA jobs wait for the machine's chamber to warm up (if
+/// it's configured to) before being handed to the machine. STL files are
+/// converted to millimeters from `units`, and refused if they're so small
+/// they're likely in inches, unless `units` is given. Returns the new job's
+/// id once the job has been handed to the machine, or queued.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn start_print_job(
+    ctx: &Context,
+    machine_id: &str,
+    job_name: &str,
+    file: FileAttachment,
+    units: Option<StlUnits>,
+    slicer_configuration: &SlicerConfiguration,
+    override_material: bool,
+    override_chamber_preheat: bool,
+) -> Result<String, HttpError> {
+    check_slicer_configuration(slicer_configuration)?;
+    let job_id = uuid::Uuid::new_v4();
+
+    let (machine_id, ready) = {
+        let machines = ctx.machines.read().await;
+        let (machine_id, machine) = match find_machine(&machines, machine_id, None).await? {
+            Some((id, machine)) => (id.clone(), machine),
+            None => {
+                tracing::warn!(id = machine_id, "machine not found");
+                return Err(HttpError::for_not_found(
+                    None,
+                    format!("machine not found by id: {:?}", machine_id),
+                ));
+            }
+        };
+
+        let machine = machine.read().await;
+        let state = check_machine_accepts(&machine, &machine_id).await?;
+
+        // Busy machines get the job once the jobs ahead of it are done, and
+        // the loaded material is checked then.
+        let ready = state == MachineState::Idle
+            && machine.reservation().is_none()
+            && !ctx.jobs.is_machine_busy(&machine_id).await;
+        if ready {
+            check_material(&machine, &machine_id, slicer_configuration, override_material).await?;
+        }
+        (machine_id, ready)
+    };
+
+    // Catch parts exported in inches before taking the job.
+    let scaled = stl_to_millimeters(&file.content, units).map_err(|e| units_error(&machine_id, e))?;
+
+    let job_id = job_id.to_string();
+    let mut job = ctx.jobs.create(&job_id, &machine_id, job_name).await;
+    if let Some(file_name) = &file.file_name {
+        ctx.jobs.set_file_name(&job_id, file_name.clone()).await;
+        job.file_name = Some(file_name.clone());
+    }
+    ctx.jobs.start_phase(&job_id, JobPhase::Preprocess).await;
+
+    let file = match scaled {
+        Some(content) => {
+            if units == Some(StlUnits::Auto) {
+                tracing::warn!(id = job_id, "scaling suspiciously small part from inches");
+                ctx.jobs
+                    .log(
+                        &job_id,
+                        "the part was suspiciously small, so it was taken to be in inches, and scaled up to \
+                         millimeters"
+                            .to_owned(),
+                    )
+                    .await;
+            }
+            FileAttachment {
+                file_name: file.file_name,
+                content: content.into(),
+            }
+        }
+        None => file,
+    };
+
+    let filepath = ctx
+        .jobs
+        .artifact_path(&job_id, file.file_name.as_deref().unwrap_or("file"));
+    tracing::info!(path = format!("{:?}", filepath), "Writing file to disk");
+
+    // TODO: we likely want to use the kittycad api to convert the file to the right format if its
+    // not already an stl file.
+
+    if let Err(e) = tokio::fs::write(&filepath, file.content).await {
+        tracing::error!(error = format!("{:?}", e), "failed to write stl file");
+        ctx.jobs
+            .fail(&job_id, FailureReason::Other, "failed to write stl file")
+            .await;
+        return Err(HttpError::for_bad_request(None, "failed to write stl file".to_string()));
+    }
+
+    if !ready {
+        tracing::info!(id = job_id, machine_id = machine_id, "machine is busy, queueing job");
+        ctx.jobs
+            .join_queue(QueuedJob {
+                job,
+                artifact: filepath,
+                slicer_configuration: *slicer_configuration,
+                override_material,
+                override_chamber_preheat,
+                position: None,
+            })
+            .await;
+        return Ok(job_id);
+    }
+
+    let tmpfile = match TemporaryFile::new(&filepath).await {
+        Ok(tmpfile) => tmpfile,
+        Err(e) => {
+            ctx.jobs.fail(&job_id, FailureReason::Other, &format!("{:?}", e)).await;
+            return Err(HttpError::for_internal_error(format!("{:?}", e)));
+        }
+    };
+
+    let queued = QueuedJob {
+        job,
+        artifact: filepath,
+        slicer_configuration: *slicer_configuration,
+        override_material,
+        override_chamber_preheat,
+        position: None,
+    };
+    if let Err(e) = ctx.jobs.enqueue(&queued).await {
+        // The job can still go ahead; it just won't survive a restart.
+        tracing::warn!(id = job_id, error = format!("{:?}", e), "failed to store queued job");
+    }
+
+    run_print_job(
+        ctx,
+        &job_id,
+        &machine_id,
+        job_name,
+        tmpfile,
+        slicer_configuration,
+        override_chamber_preheat,
+    )
+    .await?;
+
+    Ok(job_id)
+}
+
+/// Check that `machine` can take a new job now: it must accept jobs at all
+/// (see [`check_machine_accepts`]), be idle, and unless `override_material`
+/// is set, the loaded filament must match the material the slicer profile
+/// expects.
+pub(crate) async fn check_machine_ready(
+    machine: &Machine,
+    machine_id: &str,
+    slicer_configuration: &SlicerConfiguration,
+    override_material: bool,
+) -> Result<(), HttpError> {
+    let state = check_machine_accepts(machine, machine_id).await?;
+    if state != MachineState::Idle {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!("machine is not idle: {:?}", state),
+        ));
+    }
+    check_material(machine, machine_id, slicer_configuration, override_material).await
+}
+
+/// Check that `machine` can take jobs at all, whether now or once it's
+/// free: its slicer must be installed, and produce a format the machine
+/// accepts, and it must not be disabled for maintenance. Returns the
+/// machine's state.
+async fn check_machine_accepts(machine: &Machine, machine_id: &str) -> Result<MachineState, HttpError> {
+    // Don't take the job only to fail it when it comes to slicing.
+    if let Err(not_found) = machine.get_slicer().check_installed() {
+        tracing::warn!(id = machine_id, error = not_found.to_string(), "refusing print");
+        return Err(HttpError::for_unavail(
+            Some("SlicerNotFound".to_owned()),
+            not_found.to_string(),
+        ));
+    }
+
+    // Nor if the slicer can't produce anything the machine takes.
+    if let Err(incompatible) = machine.slice_format() {
+        tracing::warn!(id = machine_id, error = incompatible.to_string(), "refusing print");
+        return Err(HttpError::for_bad_request(
+            Some("NoCompatiblePipeline".to_owned()),
+            incompatible.to_string(),
+        ));
+    }
+
+    let state = machine.state().await.map_err(|e| {
+        tracing::error!(error = format!("{:?}", e), "failed to get machine state");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })?;
+    if state == MachineState::Maintenance {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!("machine {:?} is disabled for maintenance", machine_id),
+        ));
+    }
+
+    Ok(state)
+}
+
+/// Check that the design-specific `slicer_configuration` asks for
+/// something which can be printed, such as temperature steps some height
+/// apart.
+fn check_slicer_configuration(slicer_configuration: &SlicerConfiguration) -> Result<(), HttpError> {
+    if let Some(steps) = &slicer_configuration.temperature_steps {
+        steps.check().map_err(|invalid| {
+            HttpError::for_bad_request(Some("InvalidTemperatureSteps".to_owned()), invalid.to_string())
+        })?;
+    }
+    Ok(())
+}
+
+/// Check that the filament loaded in `machine` matches the material the
+/// slicer profile expects, unless `override_material` is set.
+async fn check_material(
+    machine: &Machine,
+    machine_id: &str,
+    slicer_configuration: &SlicerConfiguration,
+    override_material: bool,
+) -> Result<(), HttpError> {
+    if !override_material {
+        if let Err(e) = machine.check_material(slicer_configuration).await {
+            return Err(match e.downcast_ref::<MaterialMismatch>() {
+                Some(mismatch) => {
+                    tracing::warn!(id = machine_id, error = mismatch.to_string(), "refusing print");
+                    material_mismatch_error(mismatch)
+                }
+                None => {
+                    tracing::error!(error = format!("{:?}", e), "failed to check loaded material");
+                    HttpError::for_internal_error(format!("{:?}", e))
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The error refusing a print for `mismatch`. The materials are also sent
+/// as the `x-expected-material` and `x-loaded-material` headers (such as
+/// `petg`), so clients can act on them without parsing the message.
+fn material_mismatch_error(mismatch: &MaterialMismatch) -> HttpError {
+    let mut error = HttpError::for_bad_request(Some("MaterialMismatch".to_owned()), mismatch.to_string());
+    let headers = error.headers.get_or_insert_with(Default::default);
+    for (name, material) in [
+        ("x-expected-material", mismatch.expected),
+        ("x-loaded-material", mismatch.loaded),
+    ] {
+        let value = serde_json::to_value(material).unwrap_or_default();
+        if let Some(Ok(value)) = value["type"].as_str().map(http::HeaderValue::from_str) {
+            headers.insert(name, value);
+        }
+    }
+    error
+}
+
+/// Take a job which has its design file stored on disk through slicing,
+/// and hand it to its machine.
+#[tracing::instrument(skip_all, fields(job_id = job_id, machine_id = machine_id))]
+pub(crate) async fn run_print_job(
+    ctx: &Context,
+    job_id: &str,
+    machine_id: &str,
+    job_name: &str,
+    tmpfile: TemporaryFile,
+    slicer_configuration: &SlicerConfiguration,
+    override_chamber_preheat: bool,
+) -> Result<(), HttpError> {
+    ctx.jobs.start_phase(job_id, JobPhase::QueueWait).await;
+
+    // Wait our turn, if only so many jobs may be active at once on this
+    // type of machine, without holding on to the machines in the meantime.
+    let (machine_type, slicer_profile) = {
+        let machines = ctx.machines.read().await;
+        match machines.get(machine_id) {
+            Some(machine) => {
+                let machine = machine.read().await;
+                (
+                    Some(machine.get_machine().type_name()),
+                    machine.get_slicer().profile_name(),
+                )
+            }
+            None => (None, None),
+        }
+    };
+    if let Some(slicer_profile) = slicer_profile {
+        ctx.jobs.set_slicer_profile(job_id, slicer_profile).await;
+    }
+    let slot = match machine_type {
+        Some(machine_type) => Some(ctx.jobs.acquire_slot(job_id, machine_type).await),
+        None => None,
+    };
+
+    // The machine is only held on to for as long as each step needs it,
+    // not while slicing or waiting for the chamber, which can take minutes.
+    // Holding it would hold up everything else wanting the machine (such as
+    // cancelling this very job), and everything wanting the list of
+    // machines behind anything waiting to change it.
+    ctx.jobs.start_phase(job_id, JobPhase::Slice).await;
+    let design_file = DesignFile::from_path(tmpfile.path());
+    let slice_job = job_machine(ctx, job_id, machine_id)
+        .await?
+        .read()
+        .await
+        .slice_job(&design_file, slicer_configuration)
+        .await;
+    let sliced = match slice_job {
+        Ok(slice_job) => slice_job.run(&design_file).await.map(|sliced| (slice_job, sliced)),
+        Err(e) => Err(e),
+    };
+    let (slice_job, sliced) = match sliced {
+        Ok(sliced) => sliced,
+        Err(e) => {
+            return Err(match e.downcast_ref::<PlateMismatch>() {
+                Some(mismatch) => {
+                    tracing::warn!(id = machine_id, error = mismatch.to_string(), "refusing print");
+                    ctx.jobs.fail(job_id, FailureReason::Other, &mismatch.to_string()).await;
+                    HttpError::for_bad_request(Some("PlateMismatch".to_owned()), mismatch.to_string())
+                }
+                None => {
+                    ctx.jobs
+                        .fail(job_id, FailureReason::SlicerError, &format!("{:?}", e))
+                        .await;
+                    build_error(e)
+                }
+            });
+        }
+    };
+
+    // Record what went into the part, for tracing it back to its inputs.
+    match JobManifest::new(tmpfile.path(), slice_job.slicer(), slicer_configuration, &sliced).await {
+        Ok(manifest) => ctx.jobs.set_manifest(job_id, manifest).await,
+        Err(e) => tracing::warn!(id = job_id, error = format!("{:?}", e), "failed to record job manifest"),
+    }
+
+    if !override_chamber_preheat {
+        ctx.jobs.start_phase(job_id, JobPhase::ChamberPreheat).await;
+        let preheat = job_machine(ctx, job_id, machine_id)
+            .await?
+            .write()
+            .await
+            .start_chamber_preheat(slicer_configuration)
+            .await;
+        let warmed_up = match preheat {
+            Ok(Some(preheat)) => {
+                preheat
+                    .wait(move || async move {
+                        let machines = ctx.machines.read().await;
+                        let machine = machines
+                            .get(machine_id)
+                            .ok_or_else(|| anyhow::anyhow!("machine went away"))?;
+                        let temperature = machine.read().await.chamber_temperature().await;
+                        temperature
+                    })
+                    .await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = warmed_up {
+            return Err(match e.downcast_ref::<ChamberTooCold>() {
+                Some(too_cold) => {
+                    tracing::warn!(id = machine_id, error = too_cold.to_string(), "refusing print");
+                    ctx.jobs
+                        .fail(job_id, FailureReason::Timeout, &too_cold.to_string())
+                        .await;
+                    HttpError::for_bad_request(Some("ChamberTooCold".to_owned()), too_cold.to_string())
+                }
+                None => {
+                    tracing::error!(error = format!("{:?}", e), "failed to wait for chamber");
+                    ctx.jobs
+                        .fail(job_id, FailureReason::MachineFault { code: None }, &format!("{:?}", e))
+                        .await;
+                    HttpError::for_internal_error(format!("{:?}", e))
+                }
+            });
+        }
+    }
+
+    ctx.jobs.start_phase(job_id, JobPhase::Upload).await;
+    let dispatched = retry::dispatch(
+        &ctx.jobs,
+        ctx.jobs.dispatch_retry(),
+        job_id,
+        &ctx.machines,
+        machine_id,
+        job_name,
+        sliced,
+        slicer_configuration,
+    )
+    .await;
+
+    // Note the firmware the job started (or failed to start) on, so that
+    // failures can be traced back to firmware updates.
+    let firmware_version = match ctx.machines.read().await.get(machine_id) {
+        Some(machine) => machine.read().await.firmware_version().await,
+        None => Ok(None),
+    };
+    match firmware_version {
+        Ok(Some(firmware_version)) => ctx.jobs.set_firmware_version(job_id, firmware_version).await,
+        Ok(None) => {}
+        Err(e) => {
+            tracing::debug!(
+                id = machine_id,
+                error = format!("{:?}", e),
+                "failed to get firmware version"
+            );
+        }
+    }
+
+    if let Err(e) = dispatched {
+        return Err(match e.downcast_ref::<SdCardFull>() {
+            Some(full) => {
+                tracing::warn!(id = machine_id, error = full.to_string(), "refusing print");
+                ctx.jobs.fail(job_id, FailureReason::Other, &full.to_string()).await;
+                HttpError::for_bad_request(Some("SdCardFull".to_owned()), full.to_string())
+            }
+            None => {
+                ctx.jobs
+                    .fail(job_id, FailureReason::MachineFault { code: None }, &format!("{:?}", e))
+                    .await;
+                build_error(e)
+            }
+        });
+    }
+    drop(slot);
+
+    ctx.jobs.start_phase(job_id, JobPhase::Print).await;
+    ctx.jobs
+        .spawn_print_watcher(job_id, machine_id, ctx.machines.clone(), ctx.events.clone());
+
+    Ok(())
+}
+
+/// Look up the machine a job is to run on, failing the job if it's gone
+/// away. The list of machines is held on to for as long as what's returned
+/// is, so it should be let go of as soon as possible.
+async fn job_machine<'a>(
+    ctx: &'a Context,
+    job_id: &str,
+    machine_id: &str,
+) -> Result<RwLockReadGuard<'a, RwLock<Machine>>, HttpError> {
+    let machines = ctx.machines.read().await;
+    match RwLockReadGuard::try_map(machines, |machines| machines.get(machine_id)) {
+        Ok(machine) => Ok(machine),
+        Err(machines) => {
+            drop(machines);
+            ctx.jobs.fail(job_id, FailureReason::Other, "machine went away").await;
+            Err(HttpError::for_not_found(
+                None,
+                format!("machine not found by id: {:?}", machine_id),
+            ))
+        }
+    }
+}
+
+/// Turn a failure to slice or send a file into something we can hand back
+/// to the user.
+fn build_error(e: anyhow::Error) -> HttpError {
+    tracing::warn!(error = format!("{:?}", e), "failed to build file");
+    // Get the last 100 characters of the error message
+    let mut error_message = format!("{:?}", e);
+    if error_message.len() > 100 {
+        error_message = error_message
+            .chars()
+            .rev()
+            .take(100)
+            .collect::<String>()
+            .chars()
+            .rev()
+            .collect::<String>();
+    }
+    HttpError::for_bad_request(
+        Some("SliceFailed".to_owned()),
+        format!(
+            "Your print failed, it might be too big for the slicer or something else. {}",
+            error_message
+        ),
+    )
+}
+
+/// The path parameters for performing operations on a job.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct JobPathParams {
+    /// The job ID.
+    pub id: String,
+}
+
+/// List print jobs submitted to this server, newest first.
+#[endpoint {
+    method = GET,
+    path = "/v1/jobs",
+    tags = ["machines"],
+}]
+pub async fn get_jobs(rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk<Vec<Job>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let ctx = rqctx.context();
+    Ok(CorsResponseOk(access.visible_jobs(ctx, ctx.jobs.list().await).await))
+}
+
+/// Query parameters for fetching a job.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct JobQueryParams {
+    /// If set, wait up to this long (such as `30s` or `500ms`) for the job
+    /// to change before responding, returning as soon as it does.
+    #[serde(default)]
+    pub wait_for_change: Option<String>,
+}
+
+/// Get the status and phase timings of a specific print job.
+///
+/// Pass `wait_for_change` to long-poll for the job to change, rather than
+/// polling this endpoint in a tight loop.
+#[endpoint {
+    method = GET,
+    path = "/v1/jobs/{id}",
+    tags = ["machines"],
+}]
+pub async fn get_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+    query_params: Query<JobQueryParams>,
+) -> Result<CorsResponseOk<Job>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(CorsResponseOk(
+        find_job(rqctx.context(), &id, query_params.into_inner()).await?,
+    ))
+}
+
+pub(crate) async fn find_job(ctx: &Context, id: &str, query: JobQueryParams) -> Result<Job, HttpError> {
+    let jobs = &ctx.jobs;
+
+    let job = match query.wait_for_change {
+        Some(wait) => {
+            let Some(wait) = parse_wait(&wait) else {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    format!("invalid wait_for_change duration: {:?}", wait),
+                ));
+            };
+            jobs.wait_for_change(id, wait).await
+        }
+        None => jobs.get(id).await,
+    };
+
+    job.ok_or_else(|| HttpError::for_not_found(None, format!("job not found by id: {:?}", id)))
+}
+
+/// Watch a print job's progress as server-sent events (`text/event-stream`),
+/// rather than polling it.
+///
+/// The phase the job is in is sent straight away, followed by
+/// `phase_started` as it moves through slicing, uploading and printing,
+/// `print_progress` as the machine reports more progress, and `finished`
+/// once it completes or fails, which ends the stream.
+#[endpoint {
+    method = GET,
+    path = "/v1/jobs/{id}/events",
+    tags = ["machines"],
+}]
+pub async fn get_job_events(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<EventStreamResponseOk, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(EventStreamResponseOk(job_event_stream(rqctx.context(), &id).await?))
+}
+
+pub(crate) async fn job_event_stream(ctx: &Context, id: &str) -> Result<EventStreamBody, HttpError> {
+    if ctx.jobs.get(id).await.is_none() {
+        return Err(HttpError::for_not_found(None, format!("job not found by id: {:?}", id)));
+    }
+    Ok(job_events::spawn(ctx.jobs.clone(), id.to_owned()))
+}
+
+/// Get the photo the job's machine took as the job completed or failed, as
+/// a JPEG.
+#[endpoint {
+    method = GET,
+    path = "/v1/jobs/{id}/snapshot",
+    tags = ["machines"],
+}]
+pub async fn get_job_snapshot(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<FileResponseOk, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(FileResponseOk(job_snapshot(rqctx.context(), &id).await?))
+}
+
+pub(crate) async fn job_snapshot(ctx: &Context, id: &str) -> Result<Vec<u8>, HttpError> {
+    ctx.jobs
+        .snapshot(id)
+        .await
+        .ok_or_else(|| HttpError::for_not_found(None, format!("job {:?} has no snapshot", id)))
+}
+
+/// Get what went into a print job: checksums of its design file, the slicer
+/// profiles it was sliced with, and the file sent to its machine, along
+/// with the slicer's version.
+///
+/// This is recorded once the job is sliced, so it can be shown which inputs
+/// produced a given part.
+#[endpoint {
+    method = GET,
+    path = "/v1/jobs/{id}/manifest",
+    tags = ["machines"],
+}]
+pub async fn get_job_manifest(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<CorsResponseOk<JobManifest>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(CorsResponseOk(job_manifest(rqctx.context(), &id).await?))
+}
+
+pub(crate) async fn job_manifest(ctx: &Context, id: &str) -> Result<JobManifest, HttpError> {
+    ctx.jobs
+        .manifest(id)
+        .await
+        .ok_or_else(|| HttpError::for_not_found(None, format!("job {:?} has no manifest", id)))
+}
+
+/// Cancel a print job, stopping the machine printing it.
+///
+/// Only jobs which are printing can be cancelled; jobs still being sliced
+/// or sent to the machine can't be stopped part way through.
+#[endpoint {
+    method = POST,
+    path = "/v1/jobs/{id}/cancel",
+    tags = ["machines"],
+}]
+pub async fn cancel_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<CorsResponseOk<Job>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { cancel(&ctx, &id).await }).await?,
+    ))
+}
+
+pub(crate) async fn cancel(ctx: &Context, id: &str) -> Result<Job, HttpError> {
+    ctx.check_writable()?;
+    let Some(job) = ctx.jobs.get(id).await else {
+        return Err(HttpError::for_not_found(None, format!("job not found by id: {:?}", id)));
+    };
+    if job.state != JobState::Printing {
+        return Err(HttpError::for_bad_request(
+            None,
+            format!("job is not printing: {:?}", job.state),
+        ));
+    }
+
+    {
+        let machines = ctx.machines.read().await;
+        if let Ok(Some((_, machine))) = find_machine(&machines, &job.machine_id, Some(MachineIdType::Id)).await {
+            tracing::info!(id = job.id, machine_id = job.machine_id, "cancelling job");
+            machine.write().await.get_machine_mut().stop().await.map_err(|e| {
+                tracing::error!(error = format!("{:?}", e), "failed to stop machine");
+                HttpError::for_internal_error(format!("{:?}", e))
+            })?;
+        }
+    }
+
+    ctx.jobs
+        .fail(&job.id, FailureReason::UserCancel, "cancelled by user")
+        .await;
+    ctx.jobs
+        .get(&job.id)
+        .await
+        .ok_or_else(|| HttpError::for_internal_error("job went away".to_owned()))
+}
+
+/// List print jobs waiting for their machine to be free, in the order
+/// they'll run, grouped by machine.
+#[endpoint {
+    method = GET,
+    path = "/v1/queue",
+    tags = ["machines"],
+}]
+pub async fn list_queue(rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk<Vec<Job>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let ctx = rqctx.context();
+    Ok(CorsResponseOk(
+        access.visible_jobs(ctx, ctx.jobs.list_queue().await).await,
+    ))
+}
+
+/// Where to move a queued job to.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct QueuePosition {
+    /// The job's new place in its machine's queue, counting from 1 (next).
+    /// Positions past the end of the queue move the job to the back.
+    pub position: usize,
+}
+
+/// Move a print job waiting for its machine to be free to a new place in
+/// the machine's queue.
+#[endpoint {
+    method = PUT,
+    path = "/v1/queue/{id}",
+    tags = ["machines"],
+}]
+pub async fn move_queued_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+    body: TypedBody<QueuePosition>,
+) -> Result<CorsResponseOk<Job>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let position = body.into_inner().position;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { move_queued(&ctx, &id, position).await }).await?,
+    ))
+}
+
+pub(crate) async fn move_queued(ctx: &Context, id: &str, position: usize) -> Result<Job, HttpError> {
+    ctx.check_writable()?;
+    if position == 0 {
+        return Err(HttpError::for_bad_request(
+            None,
+            "queue positions count from 1".to_owned(),
+        ));
+    }
+    if !ctx.jobs.move_in_queue(id, position).await {
+        return Err(HttpError::for_not_found(None, format!("job {:?} is not queued", id)));
+    }
+    ctx.jobs
+        .get(id)
+        .await
+        .ok_or_else(|| HttpError::for_internal_error("job went away".to_owned()))
+}
+
+/// Remove a print job from its machine's queue before it runs, cancelling
+/// it.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/queue/{id}",
+    tags = ["machines"],
+}]
+pub async fn remove_queued_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<CorsResponseOk<Job>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { remove_queued(&ctx, &id).await }).await?,
+    ))
+}
+
+pub(crate) async fn remove_queued(ctx: &Context, id: &str) -> Result<Job, HttpError> {
+    ctx.check_writable()?;
+    let Some(queued) = ctx.jobs.leave_queue(id).await else {
+        return Err(HttpError::for_not_found(None, format!("job {:?} is not queued", id)));
+    };
+
+    tracing::info!(id = id, machine_id = queued.job.machine_id, "removing queued job");
+    ctx.jobs
+        .fail(id, FailureReason::UserCancel, "removed from the queue")
+        .await;
+    if let Err(e) = tokio::fs::remove_file(&queued.artifact).await {
+        tracing::warn!(id = id, error = format!("{:?}", e), "failed to remove queued job file");
+    }
+    ctx.jobs
+        .get(id)
+        .await
+        .ok_or_else(|| HttpError::for_internal_error("job went away".to_owned()))
+}
+
+/** Create a schedule which prints a given file on a recurring basis. File must be a sliceable 3D model. */
+#[endpoint {
+    method = POST,
+    path = "/v1/schedules",
+    tags = ["machines"],
+}]
+pub(crate) async fn create_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<CorsResponseOk<Schedule>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { schedule_upload(&ctx, body_param).await }).await?,
+    ))
+}
+
+pub(crate) async fn schedule_upload(ctx: &Context, body_param: dropshot::MultipartBody) -> Result<Schedule, HttpError> {
+    ctx.check_writable()?;
+    let mut multipart = body_param.content;
+    let (file, params) = parse_multipart_request::<ScheduleParameters>(&mut multipart).await?;
+    check_slicer_configuration(&params.slicer_configuration.unwrap_or_default())?;
+
+    tracing::info!(name = params.name, cron = params.cron, "creating schedule");
+    ctx.schedules
+        .create(params, file)
+        .await
+        .map_err(|e| HttpError::for_bad_request(None, format!("{:?}", e)))
+}
+
+/// List recurring print schedules, along with their next few runs.
+#[endpoint {
+    method = GET,
+    path = "/v1/schedules",
+    tags = ["machines"],
+}]
+pub async fn get_schedules(rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk<Vec<Schedule>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(rqctx.context().schedules.list().await))
+}
+
+/// The path parameters for performing operations on a schedule.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct SchedulePathParams {
+    /// The schedule ID.
+    pub id: String,
+}
+
+fn schedule_not_found(id: &str) -> HttpError {
+    HttpError::for_not_found(None, format!("schedule not found by id: {:?}", id))
+}
+
+/// Get a specific recurring print schedule, along with its next few runs.
+#[endpoint {
+    method = GET,
+    path = "/v1/schedules/{id}",
+    tags = ["machines"],
+}]
+pub async fn get_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+) -> Result<CorsResponseOk<Schedule>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(
+        find_schedule(rqctx.context(), &path_params.into_inner().id).await?,
+    ))
+}
+
+pub(crate) async fn find_schedule(ctx: &Context, id: &str) -> Result<Schedule, HttpError> {
+    ctx.schedules.get(id).await.ok_or_else(|| schedule_not_found(id))
+}
+
+/// Update the settings of a recurring print schedule. The design file is
+/// kept as-is.
+#[endpoint {
+    method = PUT,
+    path = "/v1/schedules/{id}",
+    tags = ["machines"],
+}]
+pub async fn update_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+    body: TypedBody<ScheduleParameters>,
+) -> Result<CorsResponseOk<Schedule>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    let parameters = body.into_inner();
+    Ok(CorsResponseOk(
+        mutate(
+            &rqctx,
+            |ctx| async move { change_schedule(&ctx, &id, parameters).await },
+        )
+        .await?,
+    ))
+}
+
+pub(crate) async fn change_schedule(
+    ctx: &Context,
+    id: &str,
+    parameters: ScheduleParameters,
+) -> Result<Schedule, HttpError> {
+    ctx.check_writable()?;
+    check_slicer_configuration(&parameters.slicer_configuration.unwrap_or_default())?;
+    ctx.schedules
+        .update(id, parameters)
+        .await
+        .map_err(|e| HttpError::for_bad_request(None, format!("{:?}", e)))?
+        .ok_or_else(|| schedule_not_found(id))
+}
+
+/// Delete a recurring print schedule. Jobs it already started are left
+/// alone.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/schedules/{id}",
+    tags = ["machines"],
+}]
+pub async fn delete_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+) -> Result<CorsResponseOk<Schedule>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { remove_schedule(&ctx, &id).await }).await?,
+    ))
+}
+
+pub(crate) async fn remove_schedule(ctx: &Context, id: &str) -> Result<Schedule, HttpError> {
+    ctx.check_writable()?;
+    tracing::info!(id = id, "deleting schedule");
+    ctx.schedules.delete(id).await.ok_or_else(|| schedule_not_found(id))
+}
+
+/** Store a design file on the server, to print by id as many times as needed without uploading it again. */
+#[endpoint {
+    method = POST,
+    path = "/v1/files",
+    tags = ["machines"],
+}]
+pub(crate) async fn upload_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<CorsResponseOk<StoredFile>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { store_upload(&ctx, body_param).await }).await?,
+    ))
+}
+
+pub(crate) async fn store_upload(ctx: &Context, body_param: dropshot::MultipartBody) -> Result<StoredFile, HttpError> {
+    ctx.check_writable()?;
+    let mut multipart = body_param.content;
+    let file = parse_multipart_file(&mut multipart).await?;
+
+    tracing::info!(file_name = file.file_name.as_deref(), "storing design file");
+    ctx.files.create(file).await.map_err(|e| {
+        tracing::warn!(error = format!("{:?}", e), "failed to store design file");
+        HttpError::for_internal_error(format!("{:?}", e))
+    })
+}
+
+/// List the design files stored on the server, newest first.
+#[endpoint {
+    method = GET,
+    path = "/v1/files",
+    tags = ["machines"],
+}]
+pub async fn get_files(rqctx: RequestContext<Arc<Context>>) -> Result<CorsResponseOk<Vec<StoredFile>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(rqctx.context().files.list().await))
+}
+
+/// The path parameters for performing operations on a stored file.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct FilePathParams {
+    /// The file ID.
+    pub id: String,
+}
+
+fn file_not_found(id: &str) -> HttpError {
+    HttpError::for_not_found(None, format!("file not found by id: {:?}", id))
+}
+
+/// Read a stored design file, to print it.
+pub(crate) async fn read_stored_file(ctx: &Context, id: &str) -> Result<FileAttachment, HttpError> {
+    ctx.files
+        .read(id)
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?
+        .ok_or_else(|| file_not_found(id))
+}
+
+/// Delete a stored design file. Jobs already printing it are left alone.
+#[endpoint {
+    method = DELETE,
+    path = "/v1/files/{id}",
+    tags = ["machines"],
+}]
+pub async fn delete_file(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<FilePathParams>,
+) -> Result<CorsResponseOk<StoredFile>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { remove_stored_file(&ctx, &id).await }).await?,
+    ))
+}
+
+pub(crate) async fn remove_stored_file(ctx: &Context, id: &str) -> Result<StoredFile, HttpError> {
+    ctx.check_writable()?;
+    tracing::info!(id = id, "deleting stored file");
+    ctx.files.delete(id).await.ok_or_else(|| file_not_found(id))
+}
+
+/// Slice a design file with one of this server's configured slicers, and
+/// return the sliced file.
+///
+/// This lets hosts which are too slow to run a slicer themselves (such as a
+/// Raspberry Pi next to the printer) delegate slicing to this one.
+#[endpoint {
+    method = POST,
+    path = "/v1/slice",
+    tags = ["machines"],
+}]
+pub(crate) async fn slice_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<FileResponseOk, HttpError> {
+    authorize(&rqctx)?;
+    Ok(FileResponseOk(slice_upload(rqctx.context(), body_param).await?))
+}
+
+pub(crate) async fn slice_upload(ctx: &Context, body_param: dropshot::MultipartBody) -> Result<Vec<u8>, HttpError> {
+    let mut multipart = body_param.content;
+    let (file, params) = parse_multipart_request::<SliceParameters>(&mut multipart).await?;
+
+    let Some(slicer) = ctx.slicers.get(&params.slicer) else {
+        tracing::warn!(slicer = params.slicer, "slicer not found");
+        return Err(HttpError::for_not_found(
+            None,
+            format!("slicer not found: {:?}", params.slicer),
+        ));
+    };
+    if let Err(unsupported) = slicer.check_options(&params.options) {
+        tracing::warn!(
+            slicer = params.slicer,
+            error = unsupported.to_string(),
+            "refusing to slice"
+        );
+        return Err(HttpError::for_bad_request(
+            Some("UnsupportedOption".to_owned()),
+            unsupported.to_string(),
+        ));
+    }
+    if params.dry_run {
+        let invocation = slicer
+            .dry_run(&unwritten_design_file(&file), &params.options, params.format)
+            .await
+            .map_err(build_error)?
+            .ok_or_else(|| dry_run_unsupported(&params.slicer))?;
+        return serde_json::to_vec_pretty(&invocation).map_err(|e| HttpError::for_internal_error(format!("{:?}", e)));
+    }
+    if let Err(not_found) = slicer.check_installed() {
+        tracing::warn!(
+            slicer = params.slicer,
+            error = not_found.to_string(),
+            "refusing to slice"
+        );
+        return Err(HttpError::for_unavail(
+            Some("SlicerNotFound".to_owned()),
+            not_found.to_string(),
+        ));
+    }
+    tracing::info!(
+        slicer = params.slicer,
+        format = format!("{:?}", params.format),
+        "slicing"
+    );
+
+    let filepath = crate::spool_dir().join(format!(
+        "{}_{}",
+        uuid::Uuid::new_v4().simple(),
+        sanitize_job_name(&file.file_name.unwrap_or("file".to_string()))
+    ));
+    if let Err(e) = tokio::fs::write(&filepath, file.content).await {
+        tracing::error!(error = format!("{:?}", e), "failed to write stl file");
+        return Err(HttpError::for_internal_error("failed to write stl file".to_string()));
+    }
+    let tmpfile = TemporaryFile::new(&filepath)
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?;
+    let design_file = DesignFile::from_path(tmpfile.path());
+
+    let sliced = match params.format {
+        SliceFormat::Gcode => GcodeSlicer::generate(slicer, &design_file, &params.options)
+            .await
+            .map(|gcode| gcode.0),
+        SliceFormat::ThreeMf => ThreeMfSlicer::generate(slicer, &design_file, &params.options)
+            .await
+            .map(|three_mf| three_mf.0),
+        SliceFormat::Form => FormSlicer::generate(slicer, &design_file, &params.options)
+            .await
+            .map(|form| form.0),
+    }
+    .map_err(build_error)?;
+
+    tokio::fs::read(sliced.path())
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))
+}
+
+/// Analyze a design file for how likely it is to print well, without
+/// slicing it or sending it to a machine.
+///
+/// The report flags steep overhangs, parts too thin for the nozzle, and
+/// parts which barely touch the bed, along with an overall score.
+#[endpoint {
+    method = POST,
+    path = "/v1/analyze",
+    tags = ["machines"],
+}]
+pub(crate) async fn analyze_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<CorsResponseOk<PrintabilityReport>, HttpError> {
+    authorize(&rqctx)?;
+    Ok(CorsResponseOk(analyze_upload(body_param).await?))
+}
+
+pub(crate) async fn analyze_upload(body_param: dropshot::MultipartBody) -> Result<PrintabilityReport, HttpError> {
+    let mut multipart = body_param.content;
+    let (file, params) = parse_multipart_request::<AnalyzeParameters>(&mut multipart).await?;
+
+    tracing::info!(file_name = file.file_name.as_deref(), "analyzing design file");
+
+    // Big meshes take a while, so keep them off the executor.
+    tokio::task::spawn_blocking(move || analyze_stl(&file.content, &params))
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?
+        .map_err(|e| HttpError::for_bad_request(None, e.to_string()))
+}
+
+/// List imported Orca Slicer profiles.
+#[endpoint {
+    method = GET,
+    path = "/v1/slicer-profiles",
+    tags = ["machines"],
+}]
+pub async fn get_slicer_profiles(
+    rqctx: RequestContext<Arc<Context>>,
+) -> Result<CorsResponseOk<Vec<Profile>>, HttpError> {
+    authorize(&rqctx)?;
+    Ok(CorsResponseOk(list_profiles(rqctx.context()).await?))
+}
+
+pub(crate) async fn list_profiles(ctx: &Context) -> Result<Vec<Profile>, HttpError> {
+    ctx.profiles
+        .list()
+        .await
+        .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))
+}
+
+/// Parameters for importing a slicer profile.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub struct ImportProfileParameters {
+    /// The name to store the profile as.
+    pub name: String,
+}
+
+/// Import presets exported from Bambu Studio or Orca Slicer as an Orca
+/// Slicer profile.
+///
+/// The file is either a single preset (as JSON), or a bundle of them (such
+/// as a `.bbscfg` or `.orca_printer` file). Presets the file doesn't
+/// include are kept from the existing profile of the same name. The
+/// returned `config` can be used as the config of an Orca slicer.
+#[endpoint {
+    method = POST,
+    path = "/v1/slicer-profiles",
+    tags = ["machines"],
+}]
+pub(crate) async fn import_slicer_profile(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<CorsResponseOk<Profile>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    Ok(CorsResponseOk(
+        mutate(&rqctx, |ctx| async move { profile_upload(&ctx, body_param).await }).await?,
+    ))
+}
+
+pub(crate) async fn profile_upload(ctx: &Context, body_param: dropshot::MultipartBody) -> Result<Profile, HttpError> {
+    ctx.check_writable()?;
+    let mut multipart = body_param.content;
+    let (file, params) = parse_multipart_request::<ImportProfileParameters>(&mut multipart).await?;
+    tracing::info!(name = params.name, "importing slicer profile");
+
+    let bundle = PresetBundle::parse(&file.content).map_err(|e| {
+        tracing::warn!(error = format!("{:?}", e), "failed to parse presets");
+        HttpError::for_bad_request(None, format!("{:#}", e))
+    })?;
+    ctx.profiles.import(&params.name, &bundle).await.map_err(|e| {
+        tracing::warn!(error = format!("{:?}", e), "failed to import slicer profile");
+        HttpError::for_bad_request(None, format!("{:#}", e))
+    })
+}
+
+pub(crate) struct FileAttachment {
+    pub(crate) file_name: Option<String>,
+    pub(crate) content: bytes::Bytes,
+}
+
+/// Parameters for printing.
+#[derive(Serialize, Deserialize, Debug, JsonSchema, Clone)]
+pub(crate) struct PrintParameters {
+    /// The machine id (or display name) to print to.
+    pub machine_id: String,
+
+    /// The name for the job.
+    pub job_name: String,
+
+    /// URL to download the design file from, instead of uploading it. It
+    /// must be on one of the server's allowed hosts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_url: Option<String>,
+
+    /// Id of a file stored with `/v1/files` to print, instead of uploading
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+
+    /// Units the STL file is in. If not given, parts so small they're
+    /// likely in inches are refused with a `SuspiciousUnits` error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<StlUnits>,
+
+    /// What to do with a file made up of separate bodies, such as an
+    /// assembly. Unless they're split, it's printed as it is, with a
+    /// warning.
+    #[serde(default)]
+    pub multiple_bodies: MultipleBodies,
+
+    /// Settings for each part, in the order their files are uploaded. When
+    /// more than one file is uploaded (or this is set), the parts are
+    /// printed together as one 3MF project, with each part's settings
+    /// applied to it alone. Only slicers which support per-part settings
+    /// (Orca) can print projects.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<PartSettings>,
+
+    /// Requested design-specific slicer configurations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slicer_configuration: Option<SlicerConfiguration>,
+
+    /// Print even if the loaded filament isn't the material the slicer
+    /// profile expects.
+    #[serde(default)]
+    pub override_material: bool,
+
+    /// Start the job without waiting for the machine's chamber to warm
+    /// up, for ABS or ASA jobs on machines with a chamber preheat
+    /// configured.
+    #[serde(default)]
+    pub override_chamber_preheat: bool,
+
+    /// Rather than printing, return exactly how the machine's slicer would
+    /// be run (its command line, merged profiles and environment), without
+    /// starting a job.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Possible errors returned by print endpoints.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Some error occurred when processing the multipart upload.
+    #[error(transparent)]
+    Multer(#[from] multer::Error),
+
+    /// Some error occurred when (de)serializing the event.
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+
+    /// Missing attachment or event data.
+    #[error("Missing file attachment or printer params.")]
+    MissingFileOrParams,
+}
+
+impl From<Error> for HttpError {
+    fn from(_err: Error) -> Self {
+        Self::for_bad_request(None, "bad request".to_string())
+    }
+}
+
+/// Parses multipart data into an request and file that we can slice and print.
+#[tracing::instrument(skip_all)]
+pub async fn parse_multipart_request<ParamsT: DeserializeOwned>(
+    multipart: &mut multer::Multipart<'_>,
+) -> Result<(FileAttachment, ParamsT), Error> {
+    let (files, params) = parse_multipart_parts(multipart).await?;
+    match <[FileAttachment; 1]>::try_from(files) {
+        Ok([file]) => Ok((file, params)),
+        Err(_) => Err(Error::MissingFileOrParams),
+    }
+}
+
+/// Parses multipart data holding just a file, such as one to store.
+async fn parse_multipart_file(multipart: &mut multer::Multipart<'_>) -> Result<FileAttachment, Error> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("file") {
+            return Ok(FileAttachment {
+                file_name: field.file_name().map(str::to_string),
+                content: field.bytes().await?,
+            });
+        }
+    }
+    Err(Error::MissingFileOrParams)
+}
+
+/// Parses multipart data into an request, and the files attached, in the
+/// order they were attached.
+async fn parse_multipart_parts<ParamsT: DeserializeOwned>(
+    multipart: &mut multer::Multipart<'_>,
+) -> Result<(Vec<FileAttachment>, ParamsT), Error> {
+    let mut files = vec![];
+    let mut maybe_params = None;
+
+    while let Some(field) = multipart.next_field().await? {
         if let Some(name) = field.name() {
             if name == "file" {
-                maybe_file = Some(FileAttachment {
+                files.push(FileAttachment {
                     file_name: field.file_name().map(str::to_string),
                     content: field.bytes().await?,
                 })
             } else if name == "params" {
-                let params = field.json::<PrintParameters>().await?;
+                let params = field.json::<ParamsT>().await?;
                 maybe_params = Some(params);
             }
         } else {
@@ -392,9 +3374,8 @@ pub async fn parse_multipart_print_request(
         }
     }
 
-    if let (Some(file), Some(params)) = (maybe_file, maybe_params) {
-        Ok((file, params))
-    } else {
-        return Err(Error::MissingFileOrParams);
+    match maybe_params {
+        Some(params) => Ok((files, params)),
+        None => Err(Error::MissingFileOrParams),
     }
 }
@@ -0,0 +1,156 @@
+//! Conditional requests: responses tagged with an `ETag` of their body, so
+//! a client polling for changes can send it back as `If-None-Match`, and
+//! get an empty `304 Not Modified` if nothing has.
+
+use std::{
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use dropshot::{Body, HttpCodedResponse, HttpError};
+use http::{header, HeaderMap, HeaderValue, Response, StatusCode};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+/// Return an HTTP Response OK, with CORS and an `ETag`, or `304 Not
+/// Modified` if the client already has the same body.
+pub struct ETaggedResponseOk<T> {
+    etag: String,
+
+    /// The body, unless the client already has it.
+    body: Option<Bytes>,
+
+    body_type: PhantomData<fn() -> T>,
+}
+
+impl<T> ETaggedResponseOk<T> {
+    /// Respond with `body` (which must be `T`, serialized), unless the
+    /// request's `If-None-Match` says the client already has it.
+    pub(crate) fn new(request_headers: &HeaderMap, body: Bytes) -> Self {
+        let etag = etag(&body);
+        let not_modified = request_headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|if_none_match| matches(if_none_match, &etag));
+
+        Self {
+            etag,
+            body: (!not_modified).then_some(body),
+            body_type: PhantomData,
+        }
+    }
+}
+
+impl<T> HttpCodedResponse for ETaggedResponseOk<T>
+where
+    T: Serialize,
+    T: JsonSchema,
+    T: Send,
+    T: Sync,
+    T: 'static,
+{
+    type Body = T;
+
+    const STATUS_CODE: StatusCode = StatusCode::OK;
+    const DESCRIPTION: &'static str = "successful operation";
+}
+
+impl<T> From<ETaggedResponseOk<T>> for Result<Response<Body>, HttpError> {
+    fn from(response: ETaggedResponseOk<T>) -> Result<Response<Body>, HttpError> {
+        let builder = Response::builder()
+            .header("access-control-allow-origin", "*")
+            .header("access-control-expose-headers", "etag")
+            .header(
+                header::ETAG,
+                HeaderValue::from_str(&response.etag).map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?,
+            );
+
+        Ok(match response.body {
+            Some(body) => builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_vec()))?,
+            None => builder.status(StatusCode::NOT_MODIFIED).body(Body::empty())?,
+        })
+    }
+}
+
+/// Return the (strong) `ETag` of a body, quoted.
+fn etag(body: &[u8]) -> String {
+    let digest = openssl::sha::sha256(body);
+    let hex = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!("\"{}\"", hex)
+}
+
+/// Return whether an `If-None-Match` header matches `etag`. Weak tags match
+/// too, as they should for `GET`s.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag)
+}
+
+/// A serialized response, shared between clients asking for it within a
+/// short time of each other, so that clients polling aggressively don't
+/// each have to lock (and ask) every machine.
+#[derive(Debug, Default)]
+pub(crate) struct SharedResponse {
+    latest: Mutex<Option<(Instant, Bytes)>>,
+}
+
+impl SharedResponse {
+    /// Return the response, if it was made within `max_age`, otherwise
+    /// make it again with `make`. Clients asking while it's being made
+    /// wait for it, rather than making it too.
+    pub(crate) async fn get<F, FutureT>(&self, max_age: Duration, make: F) -> Result<Bytes, HttpError>
+    where
+        F: FnOnce() -> FutureT,
+        FutureT: std::future::Future<Output = Result<Bytes, HttpError>>,
+    {
+        let mut latest = self.latest.lock().await;
+        if let Some((made_at, body)) = latest.as_ref() {
+            if made_at.elapsed() < max_age {
+                return Ok(body.clone());
+            }
+        }
+
+        let body = make().await?;
+        *latest = Some((Instant::now(), body.clone()));
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let etag = etag(b"[]");
+        assert!(matches(&etag, &etag));
+        assert!(matches(&format!("W/{}", etag), &etag));
+        assert!(matches(&format!("\"something-else\", {}", etag), &etag));
+        assert!(matches("*", &etag));
+        assert!(!matches("\"something-else\"", &etag));
+        assert_ne!(etag, super::etag(b"[{}]"));
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let body = Bytes::from_static(b"[]");
+
+        let response = ETaggedResponseOk::<Vec<()>>::new(&HeaderMap::new(), body.clone());
+        assert!(response.body.is_some());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(&response.etag).unwrap());
+        let response = ETaggedResponseOk::<Vec<()>>::new(&headers, body);
+        assert!(response.body.is_none());
+    }
+}
@@ -0,0 +1,523 @@
+//! Notable things happening to machines, which are logged and posted to
+//! any configured webhooks.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{FailureReason, JobState};
+use crate::{FilamentMaterial, LayerProgress, PauseReason};
+
+/// Something happened that someone probably wants to know about.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Event {
+    /// A filament storage unit holding a hygroscopic material is more humid
+    /// than the configured limit for that material.
+    HumidityHigh {
+        /// The machine id.
+        machine_id: String,
+
+        /// The storage unit (such as a Bambu AMS) id.
+        unit_id: String,
+
+        /// The material which is at risk.
+        material: FilamentMaterial,
+
+        /// Relative humidity inside the unit, in percent.
+        humidity_percent: f64,
+
+        /// The configured limit for the material, in percent.
+        limit_percent: f64,
+    },
+
+    /// A printing job's progress hasn't moved for longer than the machine's
+    /// configured window, so it may have failed without the machine
+    /// noticing.
+    JobStuck {
+        /// The machine id.
+        machine_id: String,
+
+        /// The job id.
+        job_id: String,
+
+        /// The job's progress when it stopped advancing, in percent, if the
+        /// machine reports it.
+        progress: Option<f64>,
+
+        /// How long progress has been stuck, in seconds.
+        stalled_seconds: u64,
+
+        /// Whether the machine was paused as a result.
+        paused: bool,
+    },
+
+    /// A printing job passed a progress milestone. These are only sent to
+    /// webhooks which subscribe to the milestone.
+    JobProgress {
+        /// The machine id.
+        machine_id: String,
+
+        /// The job id.
+        job_id: String,
+
+        /// The job's name.
+        job_name: String,
+
+        /// The milestone the job passed.
+        milestone: ProgressMilestone,
+
+        /// The job's progress, in percent, if the machine reports it.
+        progress: Option<f64>,
+
+        /// The layer the job is on, if the machine reports layers.
+        layer_progress: Option<LayerProgress>,
+    },
+
+    /// A printing job's machine paused (or paused for a different reason
+    /// than before).
+    JobPaused {
+        /// The machine id.
+        machine_id: String,
+
+        /// The job id.
+        job_id: String,
+
+        /// Why the machine paused.
+        reason: PauseReason,
+    },
+
+    /// A job started printing, completed, or failed.
+    JobStateChanged {
+        /// The machine id.
+        machine_id: String,
+
+        /// The job id.
+        job_id: String,
+
+        /// The job's name.
+        job_name: String,
+
+        /// The job's new state.
+        state: JobState,
+
+        /// Why the job failed, if it did.
+        failure_reason: Option<FailureReason>,
+    },
+
+    /// A machine registered at runtime was removed, either by request or
+    /// because its registration expired.
+    MachineRemoved {
+        /// The machine id.
+        machine_id: String,
+
+        /// Whether the machine was removed because its registration
+        /// expired.
+        expired: bool,
+
+        /// The job which was cancelled by removing the machine anyway, if
+        /// any.
+        cancelled_job_id: Option<String>,
+    },
+
+    /// A backend task (such as discovery, or a machine's connection) failed
+    /// or panicked, and is being restarted.
+    TaskRestarted {
+        /// The task, such as `bambu_discover`.
+        task: String,
+
+        /// The machine id, if the task is only for one machine.
+        machine_id: Option<String>,
+
+        /// How many times the task has been restarted.
+        restarts: u64,
+
+        /// What went wrong.
+        error: String,
+    },
+}
+
+impl Event {
+    /// Return the event's type, as it's tagged in JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::HumidityHigh { .. } => "humidity_high",
+            Self::JobStuck { .. } => "job_stuck",
+            Self::JobProgress { .. } => "job_progress",
+            Self::JobPaused { .. } => "job_paused",
+            Self::JobStateChanged { .. } => "job_state_changed",
+            Self::MachineRemoved { .. } => "machine_removed",
+            Self::TaskRestarted { .. } => "task_restarted",
+        }
+    }
+}
+
+/// A point in a printing job's progress which webhooks can subscribe to.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ProgressMilestone {
+    /// The job's progress reached this whole percentage.
+    Percent {
+        /// The percentage reached.
+        percent: u32,
+    },
+
+    /// The job's first layer is done.
+    FirstLayer,
+
+    /// The job started its last layer.
+    LastLayer,
+}
+
+/// Which of a job's progress milestones to send a webhook, as
+/// `job_progress` events. By default, none are sent.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ProgressSubscription {
+    /// Send an event each time the job's progress reaches a multiple of
+    /// this many percent, such as every 10%.
+    #[serde(default)]
+    pub every_percent: Option<u32>,
+
+    /// Send an event once the job's first layer is done.
+    #[serde(default)]
+    pub first_layer: bool,
+
+    /// Send an event when the job starts its last layer.
+    #[serde(default)]
+    pub last_layer: bool,
+}
+
+impl ProgressSubscription {
+    /// Check whether this subscribes to `milestone`.
+    fn wants(&self, milestone: &ProgressMilestone) -> bool {
+        match milestone {
+            ProgressMilestone::Percent { percent } => self
+                .every_percent
+                .is_some_and(|every| every > 0 && percent % every == 0),
+            ProgressMilestone::FirstLayer => self.first_layer,
+            ProgressMilestone::LastLayer => self.last_layer,
+        }
+    }
+
+    /// Check whether this subscribes to any milestones at all.
+    fn is_empty(&self) -> bool {
+        self.every_percent.is_none_or(|every| every == 0) && !self.first_layer && !self.last_layer
+    }
+}
+
+/// An [Event], along with when it happened. This is the payload posted to
+/// webhooks.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct EventRecord {
+    /// When the event happened.
+    pub timestamp: DateTime<Utc>,
+
+    /// What happened.
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Somewhere to POST events to.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Webhook {
+    /// POST each event's [EventRecord] as JSON to this URL.
+    Url(String),
+
+    /// POST events to `url`, with the payload for each event type rendered
+    /// from a template (such as Slack blocks), so the receiving end doesn't
+    /// need a translator in front of it, and any job progress milestones
+    /// subscribed to.
+    Templated {
+        /// URL to POST to.
+        url: String,
+
+        /// Payload templates, keyed by event type (such as
+        /// `job_state_changed`). `{{name}}` is replaced by the event's
+        /// `name` field, and `{{machine}}` and `{{job}}` by its machine and
+        /// job ids. Events without a template are sent as JSON.
+        #[serde(default)]
+        templates: HashMap<String, String>,
+
+        /// Content type of the rendered payloads. When it's JSON, values are
+        /// escaped to go inside JSON strings.
+        #[serde(default = "default_content_type")]
+        content_type: String,
+
+        /// Job progress milestones to send `job_progress` events for.
+        #[serde(default)]
+        progress: ProgressSubscription,
+    },
+}
+
+fn default_content_type() -> String {
+    "application/json".to_owned()
+}
+
+impl Webhook {
+    /// URL to POST to.
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Url(url) | Self::Templated { url, .. } => url,
+        }
+    }
+
+    /// Check whether this webhook should be sent `event`. Every event is
+    /// sent but job progress, which is only sent for the milestones the
+    /// webhook subscribes to.
+    fn wants(&self, event: &Event) -> bool {
+        match (self, event) {
+            (Self::Url(_), Event::JobProgress { .. }) => false,
+            (Self::Templated { progress, .. }, Event::JobProgress { milestone, .. }) => progress.wants(milestone),
+            _ => true,
+        }
+    }
+
+    /// Check whether this webhook subscribes to any job progress
+    /// milestones.
+    fn wants_progress(&self) -> bool {
+        match self {
+            Self::Url(_) => false,
+            Self::Templated { progress, .. } => !progress.is_empty(),
+        }
+    }
+
+    /// Build the request delivering `record` to this webhook.
+    fn request(&self, client: &reqwest::Client, record: &EventRecord) -> reqwest::RequestBuilder {
+        let request = client.post(self.url());
+        let Self::Templated {
+            templates,
+            content_type,
+            ..
+        } = self
+        else {
+            return request.json(record);
+        };
+        let Some(template) = templates.get(record.event.name()) else {
+            return request.json(record);
+        };
+
+        let escape_json = content_type.starts_with("application/json");
+        request
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(render_template(template, record, escape_json))
+    }
+}
+
+/// Render `template` for `record`, replacing each `{{name}}` with the
+/// record's field of that name. Tagged values (such as `material`) render
+/// as their type, missing ones as nothing, and with `escape_json`, strings
+/// are escaped to sit inside a JSON string.
+fn render_template(template: &str, record: &EventRecord, escape_json: bool) -> String {
+    let fields = match serde_json::to_value(record) {
+        Ok(serde_json::Value::Object(fields)) => fields,
+        _ => Default::default(),
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        rest = &rest[start + end + 2..];
+
+        let name = match name {
+            "machine" => "machine_id",
+            "job" => "job_id",
+            name => name,
+        };
+        let value = match fields.get(name) {
+            Some(serde_json::Value::Object(tagged)) => tagged.get("type").cloned().unwrap_or_default(),
+            Some(value) => value.clone(),
+            None => serde_json::Value::Null,
+        };
+        let value = match value {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+        if escape_json {
+            // Escape as a JSON string, without the quotes.
+            let escaped = serde_json::Value::String(value).to_string();
+            rendered.push_str(&escaped[1..escaped.len() - 1]);
+        } else {
+            rendered.push_str(&value);
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Emits [Event]s to the log and to webhooks.
+#[derive(Clone, Debug)]
+pub struct Events {
+    webhooks: Vec<Webhook>,
+    client: reqwest::Client,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Events {
+    /// Create a new [Events] handle, which will POST each event to every
+    /// webhook in `webhooks`.
+    pub fn new(webhooks: Vec<Webhook>) -> Self {
+        Self {
+            webhooks,
+            client: crate::http_client(),
+        }
+    }
+
+    /// Check whether any webhook subscribes to job progress milestones, so
+    /// it's worth keeping an eye on them.
+    pub fn wants_progress(&self) -> bool {
+        self.webhooks.iter().any(Webhook::wants_progress)
+    }
+
+    /// Emit an event. Webhooks are delivered in the background; failures
+    /// are logged, and not retried.
+    pub fn emit(&self, event: Event) {
+        let webhooks: Vec<&Webhook> = self.webhooks.iter().filter(|webhook| webhook.wants(&event)).collect();
+        if let Event::JobProgress { .. } = &event {
+            // Progress is routine, and only of interest to the webhooks
+            // subscribed to it.
+            if webhooks.is_empty() {
+                return;
+            }
+            tracing::debug!(event = format!("{:?}", event), "event");
+        } else {
+            tracing::warn!(event = format!("{:?}", event), "event");
+        }
+        let record = EventRecord {
+            timestamp: Utc::now(),
+            event,
+        };
+
+        for webhook in webhooks {
+            let request = webhook.request(&self.client, &record);
+            let url = webhook.url().to_owned();
+            tokio::spawn(async move {
+                let result = request.send().await.and_then(|response| response.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!(webhook = url, error = format!("{:?}", e), "failed to deliver webhook");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_record_json() {
+        let record = EventRecord {
+            timestamp: "2024-10-07T09:00:00Z".parse().unwrap(),
+            event: Event::HumidityHigh {
+                machine_id: "x1c".to_owned(),
+                unit_id: "0".to_owned(),
+                material: FilamentMaterial::Petg,
+                humidity_percent: 45.0,
+                limit_percent: 40.0,
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            serde_json::json!({
+                "timestamp": "2024-10-07T09:00:00Z",
+                "type": "humidity_high",
+                "machine_id": "x1c",
+                "unit_id": "0",
+                "material": {"type": "petg"},
+                "humidity_percent": 45.0,
+                "limit_percent": 40.0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let record = EventRecord {
+            timestamp: "2024-10-07T09:00:00Z".parse().unwrap(),
+            event: Event::JobStateChanged {
+                machine_id: "x1c".to_owned(),
+                job_id: "1234".to_owned(),
+                job_name: "\"quoted\" part".to_owned(),
+                state: JobState::Failed,
+                failure_reason: Some(FailureReason::UserCancel),
+            },
+        };
+
+        let template =
+            r#"{"text": "{{job_name}} ({{job}}) on {{machine}} is {{ state }}: {{failure_reason}}{{nope}}"}"#;
+        assert_eq!(
+            render_template(template, &record, true),
+            r#"{"text": "\"quoted\" part (1234) on x1c is failed: user_cancel"}"#
+        );
+        assert_eq!(
+            render_template("{{job_name}} {{type}} {{unterminated", &record, false),
+            r#""quoted" part job_state_changed {{unterminated"#
+        );
+    }
+
+    #[test]
+    fn test_webhook_config() {
+        let webhooks: Vec<Webhook> = serde_json::from_value(serde_json::json!([
+            "https://example.com/events",
+            {"url": "https://hooks.slack.com/x", "templates": {"job_stuck": "{}"}},
+        ]))
+        .unwrap();
+
+        assert_eq!(webhooks[0], Webhook::Url("https://example.com/events".to_owned()));
+        assert_eq!(webhooks[1].url(), "https://hooks.slack.com/x");
+        let Webhook::Templated { content_type, .. } = &webhooks[1] else {
+            panic!("expected a templated webhook");
+        };
+        assert_eq!(content_type, "application/json");
+    }
+
+    #[test]
+    fn test_progress_subscription() {
+        let webhooks: Vec<Webhook> = serde_json::from_value(serde_json::json!([
+            "https://example.com/events",
+            {"url": "https://example.com/progress", "progress": {"every_percent": 25, "last_layer": true}},
+        ]))
+        .unwrap();
+        let progress = |milestone| Event::JobProgress {
+            machine_id: "x1c".to_owned(),
+            job_id: "1234".to_owned(),
+            job_name: "benchy".to_owned(),
+            milestone,
+            progress: None,
+            layer_progress: None,
+        };
+
+        // Plain webhooks get everything but progress.
+        assert!(!webhooks[0].wants(&progress(ProgressMilestone::LastLayer)));
+        assert!(!webhooks[0].wants_progress());
+        assert!(webhooks[0].wants(&Event::MachineRemoved {
+            machine_id: "x1c".to_owned(),
+            expired: true,
+            cancelled_job_id: None,
+        }));
+
+        assert!(webhooks[1].wants_progress());
+        assert!(webhooks[1].wants(&progress(ProgressMilestone::Percent { percent: 50 })));
+        assert!(!webhooks[1].wants(&progress(ProgressMilestone::Percent { percent: 51 })));
+        assert!(!webhooks[1].wants(&progress(ProgressMilestone::FirstLayer)));
+        assert!(webhooks[1].wants(&progress(ProgressMilestone::LastLayer)));
+
+        assert!(Events::new(webhooks).wants_progress());
+        assert!(!Events::default().wants_progress());
+    }
+}
@@ -0,0 +1,196 @@
+//! Downloading design files from a URL, rather than having them uploaded,
+//! such as from a PDM system which keeps them in object storage. Only URLs
+//! on allow-listed schemes and hosts are fetched, so the server can't be
+//! used to reach anything else on its network.
+
+use std::time::Duration;
+
+use dropshot::{ClientErrorStatusCode, HttpError};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use super::endpoints::FileAttachment;
+use crate::TemporaryFile;
+
+/// Which URLs design files may be downloaded from, and how big they may
+/// be. No URLs are allowed unless hosts are listed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FileUrls {
+    /// URL schemes which may be downloaded from.
+    #[serde(default = "default_allowed_schemes")]
+    pub allowed_schemes: Vec<String>,
+
+    /// Hosts which may be downloaded from; either exact host names, or
+    /// `*.` followed by a domain, for any host under it.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+
+    /// Largest file to download, in bytes.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+
+    /// Longest to spend downloading a file, in seconds.
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_allowed_schemes() -> Vec<String> {
+    vec!["https".to_owned()]
+}
+
+fn default_max_bytes() -> u64 {
+    512 * 1024 * 1024
+}
+
+fn default_timeout_seconds() -> u64 {
+    300
+}
+
+impl Default for FileUrls {
+    fn default() -> Self {
+        Self {
+            allowed_schemes: default_allowed_schemes(),
+            allowed_hosts: vec![],
+            max_bytes: default_max_bytes(),
+            timeout_seconds: default_timeout_seconds(),
+        }
+    }
+}
+
+impl FileUrls {
+    /// Return whether `url` may be downloaded from.
+    fn allows(&self, url: &reqwest::Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+
+        self.allowed_schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+            && self.allowed_hosts.iter().any(|allowed| {
+                let allowed = allowed.to_ascii_lowercase();
+                match allowed.strip_prefix("*.") {
+                    Some(domain) => host.strip_suffix(domain).is_some_and(|rest| rest.ends_with('.')),
+                    None => host == allowed,
+                }
+            })
+    }
+
+    /// Download the design file at `url`, returning it along with its file
+    /// name (the last segment of the URL's path).
+    pub(crate) async fn fetch(&self, url: &str) -> Result<FileAttachment, HttpError> {
+        let url = reqwest::Url::parse(url)
+            .map_err(|e| HttpError::for_bad_request(None, format!("invalid file_url: {}", e)))?;
+        if !self.allows(&url) {
+            return Err(HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::FORBIDDEN,
+                format!("file_url is not on an allowed host: {}", url),
+            ));
+        }
+
+        // Redirects are followed only to allowed URLs too.
+        let policy = self.clone();
+        let client = crate::http_client::http_client_builder()
+            .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?
+            .timeout(Duration::from_secs(self.timeout_seconds))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= 10 {
+                    attempt.error("too many redirects")
+                } else if policy.allows(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.error("redirected to a URL which isn't allowed")
+                }
+            }))
+            .build()
+            .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?;
+
+        tracing::info!(url = url.as_str(), "downloading file");
+        let download_failed = |e: reqwest::Error| {
+            tracing::warn!(
+                url = url.as_str(),
+                error = format!("{:?}", e),
+                "failed to download file"
+            );
+            HttpError::for_bad_request(None, format!("failed to download file_url: {}", e))
+        };
+        let mut response = client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(download_failed)?;
+
+        let too_large = || {
+            HttpError::for_client_error(
+                None,
+                ClientErrorStatusCode::PAYLOAD_TOO_LARGE,
+                format!("file_url is larger than {} bytes", self.max_bytes),
+            )
+        };
+        if response.content_length().is_some_and(|length| length > self.max_bytes) {
+            return Err(too_large());
+        }
+
+        // The download is spooled to disk as it arrives, rather than held in
+        // memory, and as the length isn't always known up front, it's
+        // counted as it's written.
+        let write_failed = |e: std::io::Error| {
+            tracing::error!(error = format!("{:?}", e), "failed to write downloaded file");
+            HttpError::for_internal_error(format!("failed to write downloaded file: {:?}", e))
+        };
+        let path = crate::spool_dir().join(format!("{}_download", uuid::Uuid::new_v4().simple()));
+        let mut out = tokio::fs::File::create(&path).await.map_err(write_failed)?;
+        let download = TemporaryFile::new(&path)
+            .await
+            .map_err(|e| HttpError::for_internal_error(format!("{:?}", e)))?;
+        let mut written: u64 = 0;
+        while let Some(chunk) = response.chunk().await.map_err(download_failed)? {
+            written += chunk.len() as u64;
+            if written > self.max_bytes {
+                return Err(too_large());
+            }
+            out.write_all(&chunk).await.map_err(write_failed)?;
+        }
+        out.flush().await.map_err(write_failed)?;
+        drop(out);
+        let content = tokio::fs::read(download.path()).await.map_err(write_failed)?;
+
+        let file_name = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned);
+        Ok(FileAttachment {
+            file_name,
+            content: content.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let policy = FileUrls {
+            allowed_hosts: vec!["pdm.example.com".to_owned(), "*.s3.amazonaws.com".to_owned()],
+            ..Default::default()
+        };
+        let allows = |url: &str| policy.allows(&reqwest::Url::parse(url).unwrap());
+
+        assert!(allows("https://pdm.example.com/parts/benchy.stl"));
+        assert!(allows("https://PDM.example.com/parts/benchy.stl"));
+        assert!(allows("https://bucket.s3.amazonaws.com/benchy.stl"));
+        assert!(!allows("https://s3.amazonaws.com/benchy.stl"));
+        assert!(!allows("https://evils3.amazonaws.com/benchy.stl"));
+        assert!(!allows("http://pdm.example.com/parts/benchy.stl"));
+        assert!(!allows("https://other.example.com/benchy.stl"));
+        assert!(!allows("file:///etc/passwd"));
+
+        assert!(!FileUrls::default().allows(&reqwest::Url::parse("https://pdm.example.com/").unwrap()));
+    }
+}
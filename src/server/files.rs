@@ -0,0 +1,194 @@
+//! A library of design files stored on the server, so a part printed over
+//! and over doesn't have to be uploaded for every print.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::endpoints::FileAttachment;
+
+/// A design file stored in the library.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct StoredFile {
+    /// The file id, which `/v1/print` takes as `file_id`.
+    pub id: String,
+
+    /// The name the file was uploaded with.
+    pub file_name: String,
+
+    /// Size of the file, in bytes.
+    pub size_bytes: u64,
+
+    /// SHA-256 of the file, in hex.
+    pub sha256: String,
+
+    /// When the file was uploaded.
+    pub created_at: DateTime<Utc>,
+}
+
+/// All design files stored in the library.
+pub struct Files {
+    files: RwLock<HashMap<String, StoredFile>>,
+    dir: PathBuf,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Self::new(crate::file::spool_dir().join("machine-api-files"))
+    }
+}
+
+impl Files {
+    /// Create an empty library, which will store design files (and what's
+    /// known about them) in `dir`.
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            files: RwLock::new(HashMap::new()),
+            dir,
+        }
+    }
+
+    /// Load the files already stored in the library's directory, such as
+    /// from before a restart.
+    pub async fn restore(&self) -> Result<()> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut files = self.files.write().await;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !path.extension().is_some_and(|extension| extension == "json") {
+                continue;
+            }
+            let file: StoredFile = match serde_json::from_slice(&tokio::fs::read(&path).await?) {
+                Ok(file) => file,
+                Err(e) => {
+                    tracing::warn!(
+                        path = format!("{:?}", path),
+                        error = format!("{:?}", e),
+                        "skipping stored file"
+                    );
+                    continue;
+                }
+            };
+            if !tokio::fs::try_exists(self.content_path(&file)).await? {
+                tracing::warn!(id = file.id, "stored file has gone missing");
+                continue;
+            }
+            files.insert(file.id.clone(), file);
+        }
+        tracing::info!(files = files.len(), "restored file library");
+        Ok(())
+    }
+
+    /// Store a new design file.
+    pub(crate) async fn create(&self, file: FileAttachment) -> Result<StoredFile> {
+        let mut sha256 = openssl::sha::Sha256::new();
+        sha256.update(&file.content);
+        let stored = StoredFile {
+            id: uuid::Uuid::new_v4().to_string(),
+            file_name: crate::sanitize_job_name(file.file_name.as_deref().unwrap_or("file")),
+            size_bytes: file.content.len() as u64,
+            sha256: sha256.finish().iter().map(|byte| format!("{:02x}", byte)).collect(),
+            created_at: Utc::now(),
+        };
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.content_path(&stored), &file.content).await?;
+        tokio::fs::write(self.metadata_path(&stored.id), serde_json::to_vec(&stored)?).await?;
+        self.files.write().await.insert(stored.id.clone(), stored.clone());
+        Ok(stored)
+    }
+
+    /// Get a stored file by id.
+    pub async fn get(&self, id: &str) -> Option<StoredFile> {
+        self.files.read().await.get(id).cloned()
+    }
+
+    /// List all stored files, newest first.
+    pub async fn list(&self) -> Vec<StoredFile> {
+        let mut files: Vec<StoredFile> = self.files.read().await.values().cloned().collect();
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        files
+    }
+
+    /// Read a stored file's content, to print it. Returns `Ok(None)` if
+    /// there is no such file.
+    pub(crate) async fn read(&self, id: &str) -> Result<Option<FileAttachment>> {
+        let Some(file) = self.get(id).await else {
+            return Ok(None);
+        };
+        let content = tokio::fs::read(self.content_path(&file)).await?;
+        Ok(Some(FileAttachment {
+            file_name: Some(file.file_name),
+            content: content.into(),
+        }))
+    }
+
+    /// Remove a stored file. Jobs already printing it are left alone.
+    pub async fn delete(&self, id: &str) -> Option<StoredFile> {
+        let file = self.files.write().await.remove(id)?;
+        for path in [self.content_path(&file), self.metadata_path(id)] {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(id = id, error = format!("{:?}", e), "failed to remove stored file");
+            }
+        }
+        Some(file)
+    }
+
+    fn content_path(&self, file: &StoredFile) -> PathBuf {
+        self.dir.join(format!("{}_{}", file.id, file.file_name))
+    }
+
+    fn metadata_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_files() {
+        let dir = std::env::temp_dir().join(format!("machine-api-files-{}", uuid::Uuid::new_v4()));
+        let files = Files::new(dir.clone());
+
+        let stored = files
+            .create(FileAttachment {
+                file_name: Some("cube.stl".to_owned()),
+                content: "solid cube".into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(stored.file_name, "cube.stl");
+        assert_eq!(stored.size_bytes, 10);
+        assert_eq!(stored.sha256.len(), 64);
+        assert_eq!(files.list().await, vec![stored.clone()]);
+
+        let read = files.read(&stored.id).await.unwrap().unwrap();
+        assert_eq!(read.file_name.as_deref(), Some("cube.stl"));
+        assert_eq!(read.content, "solid cube");
+
+        // Files are still there after a restart.
+        let restored = Files::new(dir.clone());
+        restored.restore().await.unwrap();
+        assert_eq!(restored.get(&stored.id).await, Some(stored.clone()));
+
+        assert_eq!(restored.delete(&stored.id).await, Some(stored.clone()));
+        assert!(restored.read(&stored.id).await.unwrap().is_none());
+        assert!(restored.delete(&stored.id).await.is_none());
+        let empty = Files::new(dir.clone());
+        empty.restore().await.unwrap();
+        assert!(empty.list().await.is_empty());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! A job's progress (its phases, how far along the print is, and how it
+//! finished), pushed to clients as server-sent events, rather than each of
+//! them polling `/v1/jobs/{id}`.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use super::{FailureReason, Job, JobPhase, JobState, Jobs};
+use crate::LayerProgress;
+
+/// How many events a slow client may fall behind by before it misses some.
+const EVENT_CAPACITY: usize = 256;
+
+/// How often a comment is sent to clients while nothing's happening, so
+/// proxies don't take the connection to be idle and close it.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Something which happened to a job.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum JobEvent {
+    /// The job moved into a new phase, such as slicing, or being uploaded
+    /// to its machine.
+    PhaseStarted {
+        /// The phase the job is now in.
+        phase: JobPhase,
+    },
+
+    /// The machine printing the job reported more progress.
+    PrintProgress {
+        /// The job's progress, in percent, if the machine reports it.
+        progress: Option<f64>,
+
+        /// The layer the job is on, if the machine reports layers.
+        layer_progress: Option<LayerProgress>,
+    },
+
+    /// The job completed or failed. Nothing more happens to it.
+    Finished {
+        /// How the job finished.
+        state: JobState,
+
+        /// Why the job failed, if it did.
+        failure_reason: Option<FailureReason>,
+
+        /// Human-readable description of why the job failed, if it did.
+        error: Option<String>,
+    },
+}
+
+impl JobEvent {
+    /// Return the event's type, as it's tagged in JSON.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PhaseStarted { .. } => "phase_started",
+            Self::PrintProgress { .. } => "print_progress",
+            Self::Finished { .. } => "finished",
+        }
+    }
+
+    /// The event for `job` having finished, if it has.
+    pub(crate) fn finished(job: &Job) -> Option<Self> {
+        job.state.is_finished().then(|| Self::Finished {
+            state: job.state.clone(),
+            failure_reason: job.failure_reason.clone(),
+            error: job.error.clone(),
+        })
+    }
+}
+
+/// A [JobEvent], along with the job and when it happened. This is the data
+/// of each server-sent event.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct JobEventRecord {
+    /// The job id.
+    pub job_id: String,
+
+    /// When the event happened.
+    pub timestamp: DateTime<Utc>,
+
+    /// What happened.
+    #[serde(flatten)]
+    pub event: JobEvent,
+}
+
+/// Events happening to jobs, broadcast to every client watching them.
+pub struct JobEvents {
+    sender: broadcast::Sender<JobEventRecord>,
+}
+
+impl Default for JobEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl JobEvents {
+    /// Start watching for events happening to the job `job_id`.
+    pub fn watch(&self, job_id: &str) -> JobWatch {
+        JobWatch {
+            job_id: job_id.to_owned(),
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// Send `event` to the job's watchers.
+    pub(crate) fn publish(&self, job_id: &str, event: JobEvent) {
+        // No one may be listening, which is fine.
+        let _ = self.sender.send(JobEventRecord {
+            job_id: job_id.to_owned(),
+            timestamp: Utc::now(),
+            event,
+        });
+    }
+}
+
+/// A client watching a job for events.
+pub struct JobWatch {
+    job_id: String,
+    receiver: broadcast::Receiver<JobEventRecord>,
+}
+
+impl JobWatch {
+    /// Wait for the next event to happen to the job. Events missed by
+    /// falling behind are skipped.
+    pub async fn recv(&mut self) -> Option<JobEventRecord> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(record) if record.job_id == self.job_id => return Some(record),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!(id = self.job_id, skipped = skipped, "job watcher fell behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Start sending the events happening to the job `id` to a client, as
+/// server-sent events, until the job finishes or the client goes away. The
+/// phase the job is already in is sent first, or how it finished, if it
+/// already has.
+pub(crate) fn spawn(jobs: Arc<Jobs>, id: String) -> EventStreamBody {
+    let (sender, receiver) = mpsc::channel(EVENT_CAPACITY);
+    // Watch before looking at the job, so nothing happening in between is
+    // missed.
+    let mut watch = jobs.watch(&id);
+    tokio::spawn(async move {
+        let Some(job) = jobs.get(&id).await else {
+            return;
+        };
+        let current = match JobEvent::finished(&job) {
+            Some(finished) => Some((job.updated_at, finished)),
+            None => job
+                .phases
+                .last()
+                .map(|timing| (timing.started_at, JobEvent::PhaseStarted { phase: timing.phase })),
+        };
+        if let Some((timestamp, event)) = current {
+            let finished = matches!(event, JobEvent::Finished { .. });
+            let record = JobEventRecord {
+                job_id: id.clone(),
+                timestamp,
+                event,
+            };
+            if sender.send(message(&record)).await.is_err() || finished {
+                return;
+            }
+        }
+
+        let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+        keep_alive.tick().await;
+        loop {
+            tokio::select! {
+                record = watch.recv() => {
+                    let Some(record) = record else {
+                        break;
+                    };
+                    let finished = matches!(record.event, JobEvent::Finished { .. });
+                    if sender.send(message(&record)).await.is_err() || finished {
+                        break;
+                    }
+                }
+                _ = keep_alive.tick() => {
+                    if sender.send(Bytes::from_static(b": keep-alive\n\n")).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        tracing::debug!(id = id, "job event stream ended");
+    });
+    EventStreamBody { receiver }
+}
+
+/// Format `record` as a server-sent event, named for its type.
+fn message(record: &JobEventRecord) -> Bytes {
+    // JSON has no raw newlines in it, so fits on the one `data` line.
+    let data = serde_json::to_string(record).unwrap_or_default();
+    format!("event: {}\ndata: {}\n\n", record.event.name(), data).into()
+}
+
+/// The body of a `text/event-stream` response, sending events as they
+/// happen.
+pub struct EventStreamBody {
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl http_body::Body for EventStreamBody {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|message| message.map(|message| Ok(http_body::Frame::data(message))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let record = JobEventRecord {
+            job_id: "1234".to_owned(),
+            timestamp: "2024-10-07T09:00:00Z".parse().unwrap(),
+            event: JobEvent::PhaseStarted { phase: JobPhase::Slice },
+        };
+
+        assert_eq!(
+            message(&record),
+            Bytes::from(
+                "event: phase_started\ndata: \
+                 {\"job_id\":\"1234\",\"timestamp\":\"2024-10-07T09:00:00Z\",\"type\":\"phase_started\",\"phase\":\"slice\"}\n\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let jobs = Jobs::new(&mut Registry::default());
+        jobs.create("job", "x1c", "benchy").await;
+        jobs.create("other", "x1c", "benchy").await;
+
+        let mut watch = jobs.watch("job");
+        jobs.start_phase("other", JobPhase::Slice).await;
+        jobs.start_phase("job", JobPhase::Slice).await;
+        jobs.start_phase("job", JobPhase::Print).await;
+        jobs.complete("job").await;
+
+        let mut events = vec![];
+        for _ in 0..3 {
+            events.push(watch.recv().await.unwrap().event);
+        }
+        assert_eq!(
+            events,
+            vec![
+                JobEvent::PhaseStarted { phase: JobPhase::Slice },
+                JobEvent::PhaseStarted { phase: JobPhase::Print },
+                JobEvent::Finished {
+                    state: JobState::Completed,
+                    failure_reason: None,
+                    error: None,
+                },
+            ]
+        );
+    }
+}
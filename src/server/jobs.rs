@@ -0,0 +1,2140 @@
+//! Tracking of print jobs submitted through the API, including how long
+//! each phase of the job took.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::{Registry, Unit},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, RwLock};
+use tracing::Instrument;
+
+use super::{
+    job_events::{JobEvent, JobEvents, JobWatch},
+    queue::MachineQueues,
+    retry::DispatchRetry,
+    slots::{ConcurrencyLimits, JobSlots},
+    Event, Events, JobManifest, ProgressMilestone,
+};
+use crate::{Control, LayerProgress, Machine, MachineState, PauseReason, SlicerConfiguration};
+
+/// How often a printing job's machine is polled to find out if it's done.
+const PRINT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for a machine to start printing a dispatched job before
+/// assuming it finished too quickly for us to see it running.
+const PRINT_START_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How far along a printing job is, as far as the machine lets on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ProgressSample {
+    percent: Option<f64>,
+    layer: Option<u32>,
+}
+
+/// A change in whether a job looks stuck.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StallChange {
+    /// Progress hasn't advanced for this long, which is over the window.
+    Stuck(Duration),
+
+    /// Progress advanced again after the job was flagged.
+    Recovered,
+}
+
+/// Tracks how long a printing job's progress has stayed the same.
+struct StallTracker {
+    last: Option<ProgressSample>,
+    since: std::time::Instant,
+    flagged: bool,
+}
+
+impl StallTracker {
+    fn new(now: std::time::Instant) -> Self {
+        Self {
+            last: None,
+            since: now,
+            flagged: false,
+        }
+    }
+
+    /// Record a sample of the job's progress, taken while the machine is
+    /// printing (`None` if it isn't, such as while paused, which doesn't
+    /// count towards the window).
+    fn observe(
+        &mut self,
+        now: std::time::Instant,
+        sample: Option<ProgressSample>,
+        window: Duration,
+    ) -> Option<StallChange> {
+        let Some(sample) = sample.filter(|sample| sample.percent.is_some() || sample.layer.is_some()) else {
+            self.since = now;
+            return None;
+        };
+
+        if self.last != Some(sample) {
+            self.last = Some(sample);
+            self.since = now;
+            if self.flagged {
+                self.flagged = false;
+                return Some(StallChange::Recovered);
+            }
+            return None;
+        }
+
+        let stalled = now.duration_since(self.since);
+        if !self.flagged && stalled >= window {
+            self.flagged = true;
+            return Some(StallChange::Stuck(stalled));
+        }
+        None
+    }
+}
+
+/// Tracks which progress milestones a printing job has passed, so each is
+/// only announced once.
+#[derive(Debug, Default)]
+struct MilestoneTracker {
+    /// The whole percentage the job had last reached, once it's reported.
+    percent: Option<u32>,
+
+    /// Whether the job's layers have been reported yet.
+    seen_layers: bool,
+    first_layer: bool,
+    last_layer: bool,
+}
+
+impl MilestoneTracker {
+    /// Record the job's progress, returning the milestones it's passed
+    /// since the last time. Those passed before the job's progress was
+    /// first seen are skipped, rather than all announced at once.
+    fn observe(&mut self, percent: Option<f64>, layers: Option<LayerProgress>) -> Vec<ProgressMilestone> {
+        let mut passed = vec![];
+
+        if let Some(percent) = percent {
+            let reached = percent.clamp(0.0, 100.0).floor() as u32;
+            if let Some(last) = self.percent {
+                passed.extend((last + 1..=reached).map(|percent| ProgressMilestone::Percent { percent }));
+            }
+            self.percent = Some(self.percent.map_or(reached, |last| last.max(reached)));
+        }
+
+        if let Some(layers) = layers {
+            let first_layer = layers.current_layer > 1;
+            let last_layer = layers.total_layers > 0 && layers.current_layer >= layers.total_layers;
+            if self.seen_layers {
+                if first_layer && !self.first_layer {
+                    passed.push(ProgressMilestone::FirstLayer);
+                }
+                if last_layer && !self.last_layer {
+                    passed.push(ProgressMilestone::LastLayer);
+                }
+            }
+            self.seen_layers = true;
+            self.first_layer |= first_layer;
+            self.last_layer |= last_layer;
+        }
+
+        passed
+    }
+}
+
+/// Longest a client may wait on a job to change in a single request.
+pub const MAX_WAIT_FOR_CHANGE: Duration = Duration::from_secs(120);
+
+/// Phase of a print job's lifecycle.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Hash, EncodeLabelValue)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPhase {
+    /// Waiting for the machine to be free.
+    QueueWait,
+
+    /// Receiving and preparing the design file.
+    Preprocess,
+
+    /// Slicing the design file for the machine.
+    Slice,
+
+    /// Waiting for the machine's chamber to warm up.
+    ChamberPreheat,
+
+    /// Sending the sliced file to the machine and starting the job.
+    Upload,
+
+    /// The machine is printing the job.
+    Print,
+}
+
+/// Current state of a print job.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// The job is being prepared and sent to the machine.
+    Pending,
+
+    /// The machine is printing the job.
+    Printing,
+
+    /// The job finished.
+    Completed,
+
+    /// The job failed.
+    Failed,
+}
+
+impl JobState {
+    /// Return true if the job is over, and won't change again.
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed)
+    }
+}
+
+/// Why a job failed (or was cancelled).
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum FailureReason {
+    /// Someone cancelled the job.
+    UserCancel,
+
+    /// The machine was emergency stopped.
+    Estop,
+
+    /// The machine reported a fault.
+    MachineFault {
+        /// The machine-specific error code (or message), if it gave one.
+        code: Option<String>,
+    },
+
+    /// The design file couldn't be sliced.
+    SlicerError,
+
+    /// Something the job was waiting on didn't happen in time, such as the
+    /// chamber warming up.
+    Timeout,
+
+    /// The machine ran out of filament.
+    FilamentRunout,
+
+    /// Anything else, such as failing to store the design file.
+    Other,
+}
+
+impl FailureReason {
+    /// Work out why a machine failed a job from the message it reported,
+    /// if any, falling back to a [FailureReason::MachineFault].
+    pub fn from_machine_message(message: Option<&str>) -> Self {
+        let Some(message) = message else {
+            return Self::MachineFault { code: None };
+        };
+        let lower = message.to_ascii_lowercase();
+        if lower.contains("runout") {
+            Self::FilamentRunout
+        } else if lower.contains("m112") || lower.contains("emergency stop") {
+            Self::Estop
+        } else {
+            Self::MachineFault {
+                code: Some(message.to_owned()),
+            }
+        }
+    }
+
+    /// Return the name of this kind of failure, as used in metrics labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UserCancel => "user_cancel",
+            Self::Estop => "estop",
+            Self::MachineFault { .. } => "machine_fault",
+            Self::SlicerError => "slicer_error",
+            Self::Timeout => "timeout",
+            Self::FilamentRunout => "filament_runout",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// How long a job spent in a single phase.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct PhaseTiming {
+    /// The phase this timing is for.
+    pub phase: JobPhase,
+
+    /// When the phase started.
+    pub started_at: DateTime<Utc>,
+
+    /// How long the phase took, in seconds. This is not set until the phase
+    /// is over.
+    pub duration_seconds: Option<f64>,
+}
+
+/// Something which happened to a job, as recorded in its log.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct JobLogEntry {
+    /// When it happened.
+    pub timestamp: DateTime<Utc>,
+
+    /// What happened.
+    pub message: String,
+}
+
+/// A photo from a job's machine, taken as the job finished.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct JobSnapshot {
+    /// When the photo was taken.
+    pub taken_at: DateTime<Utc>,
+
+    /// Size of the image, in bytes.
+    pub size_bytes: u64,
+}
+
+/// How far along a job's print is, as last reported by its machine.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct JobProgress {
+    /// Percentage of the print done, if the machine says.
+    pub percent: Option<f64>,
+
+    /// Layer the print is on, if the machine reports layers.
+    pub layer_progress: Option<LayerProgress>,
+}
+
+/// A print job submitted to the API.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct Job {
+    /// The job id.
+    pub id: String,
+
+    /// The machine id the job was sent to.
+    pub machine_id: String,
+
+    /// The name for the job.
+    pub job_name: String,
+
+    /// Name of the design file the job was submitted with, if it had one.
+    pub file_name: Option<String>,
+
+    /// Current state of the job.
+    pub state: JobState,
+
+    /// Human-readable description of why the job failed, if it did.
+    pub error: Option<String>,
+
+    /// Why the job failed, if it did.
+    pub failure_reason: Option<FailureReason>,
+
+    /// When the job was submitted.
+    pub created_at: DateTime<Utc>,
+
+    /// When the job was last updated.
+    pub updated_at: DateTime<Utc>,
+
+    /// When the job's machine started printing it.
+    pub started_at: Option<DateTime<Utc>>,
+
+    /// When the job completed or failed.
+    pub finished_at: Option<DateTime<Utc>>,
+
+    /// Timing of each phase the job has been through, in order.
+    pub phases: Vec<PhaseTiming>,
+
+    /// How far along the print is, once the machine has started it.
+    #[serde(default)]
+    pub progress: Option<JobProgress>,
+
+    /// Set while the job's progress hasn't advanced for longer than the
+    /// machine's stuck detection window.
+    #[serde(default)]
+    pub possibly_stuck: bool,
+
+    /// Why the job's machine is paused, while it is.
+    pub pause_reason: Option<PauseReason>,
+
+    /// Where the job is in the queue it's waiting in (1 is next), either for
+    /// its machine to be free, or for one of a limited number of slots on its
+    /// type of machine.
+    pub queue_position: Option<usize>,
+
+    /// Notable things which happened to the job, oldest first, such as
+    /// failed attempts to send it to its machine.
+    #[serde(default)]
+    pub log: Vec<JobLogEntry>,
+
+    /// Name of the slicer profile the job is sliced with, such as `mk3`,
+    /// once it's known, if the machine's slicer has one.
+    #[serde(default)]
+    pub slicer_profile: Option<String>,
+
+    /// Version of the firmware the machine was running when the job was
+    /// sent to it, if the machine says.
+    pub firmware_version: Option<String>,
+
+    /// Photo from the machine's camera, taken when the job completed or
+    /// failed, if the machine has one. The image itself is served at
+    /// `/v1/jobs/{id}/snapshot`.
+    pub snapshot: Option<JobSnapshot>,
+}
+
+impl Job {
+    /// Duration of a finished phase, if the job has been through it.
+    pub fn phase_duration(&self, phase: JobPhase) -> Option<f64> {
+        self.phases
+            .iter()
+            .find(|timing| timing.phase == phase)
+            .and_then(|timing| timing.duration_seconds)
+    }
+}
+
+/// A job which hasn't been handed to its machine yet, along with what's
+/// needed to start it again if the server restarts in the meantime.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct QueuedJob {
+    /// The job, as it was when it was queued.
+    pub job: Job,
+
+    /// Where the design file is stored.
+    pub artifact: PathBuf,
+
+    /// Requested slicer configurations.
+    pub slicer_configuration: SlicerConfiguration,
+
+    /// Print even if the loaded filament isn't the material the slicer
+    /// profile expects.
+    pub override_material: bool,
+
+    /// Start the job without waiting for the machine's chamber to warm up.
+    pub override_chamber_preheat: bool,
+
+    /// Where the job is in its machine's queue (1 is next), if it's waiting
+    /// for the machine to be free, so the queue keeps its order across a
+    /// restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SlotState {
+    Unlimited,
+    Waiting,
+    Held,
+}
+
+/// A job's place in the queue for its type of machine (or the slot it's
+/// taken), given up when dropped.
+pub struct JobSlot {
+    jobs: Arc<Jobs>,
+    machine_type: String,
+    id: String,
+    state: SlotState,
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        match self.state {
+            SlotState::Unlimited => return,
+            SlotState::Waiting => self.jobs.slots.leave(&self.machine_type, &self.id),
+            SlotState::Held => self.jobs.slots.release(&self.machine_type),
+        }
+        // Wake the jobs waiting for a slot, along with anyone waiting on
+        // their queue position to change.
+        self.jobs.changed.notify_waiters();
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct JobPhaseLabels {
+    phase: JobPhase,
+    slicer_profile: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct JobFailureLabels {
+    machine_id: String,
+    slicer_profile: String,
+    code: String,
+}
+
+/// How long finished jobs, and the design files in the job store, are kept
+/// for. Anything unset is kept forever.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Retention {
+    /// Keep at most this many finished jobs per machine, forgetting the
+    /// oldest first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_per_machine: Option<usize>,
+
+    /// Keep at most this many bytes of design files in the job store,
+    /// removing the oldest first. Files for jobs which haven't finished are
+    /// never removed, even if they alone are over the limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_bytes: Option<u64>,
+
+    /// Forget finished jobs, and remove design files, this long after they
+    /// were last updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_seconds: Option<u64>,
+}
+
+impl Retention {
+    /// Whether anything is ever removed.
+    pub fn is_unlimited(&self) -> bool {
+        self.keep_per_machine.is_none() && self.max_total_bytes.is_none() && self.max_age_seconds.is_none()
+    }
+}
+
+/// How often retention is applied.
+const REAP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Where finished jobs are kept, under the job store.
+const HISTORY_DIR: &str = "history";
+
+/// All jobs known to the server.
+pub struct Jobs {
+    jobs: RwLock<HashMap<String, Job>>,
+    manifests: RwLock<HashMap<String, JobManifest>>,
+    queue: MachineQueues,
+    store: Option<PathBuf>,
+    retention: Retention,
+    events: Option<Arc<Events>>,
+    slots: JobSlots,
+    dispatch_retry: DispatchRetry,
+    changed: Notify,
+    job_events: JobEvents,
+    phase_durations: Family<JobPhaseLabels, Histogram, fn() -> Histogram>,
+    failures: Family<JobFailureLabels, Counter>,
+    reaped_jobs: Counter,
+    reaped_artifacts: Counter,
+    reaped_artifact_bytes: Counter,
+}
+
+impl Jobs {
+    /// Create an empty set of jobs, registering the job metrics with the
+    /// provided registry.
+    pub fn new(registry: &mut Registry) -> Self {
+        let phase_durations: Family<JobPhaseLabels, Histogram, fn() -> Histogram> =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.1, 2.0, 18)));
+        registry.register_with_unit(
+            "machine_api_job_phase_duration",
+            "Time spent in each phase of a print job, by slicer profile",
+            Unit::Seconds,
+            phase_durations.clone(),
+        );
+
+        let failures = Family::<JobFailureLabels, Counter>::default();
+        registry.register(
+            "machine_api_job_failures",
+            "Print jobs which failed, by machine, slicer profile and reason",
+            failures.clone(),
+        );
+
+        let reaped_jobs = Counter::default();
+        registry.register(
+            "machine_api_jobs_reaped",
+            "Finished jobs forgotten under the retention policy",
+            reaped_jobs.clone(),
+        );
+
+        let reaped_artifacts = Counter::default();
+        registry.register(
+            "machine_api_job_artifacts_reaped",
+            "Design files removed from the job store under the retention policy",
+            reaped_artifacts.clone(),
+        );
+
+        let reaped_artifact_bytes = Counter::default();
+        registry.register_with_unit(
+            "machine_api_job_artifacts_reaped",
+            "Size of design files removed from the job store under the retention policy",
+            Unit::Bytes,
+            reaped_artifact_bytes.clone(),
+        );
+
+        Self {
+            jobs: RwLock::new(HashMap::new()),
+            manifests: RwLock::new(HashMap::new()),
+            queue: MachineQueues::default(),
+            store: None,
+            retention: Retention::default(),
+            events: None,
+            slots: JobSlots::default(),
+            dispatch_retry: DispatchRetry::default(),
+            changed: Notify::new(),
+            job_events: JobEvents::default(),
+            phase_durations,
+            failures,
+            reaped_jobs,
+            reaped_artifacts,
+            reaped_artifact_bytes,
+        }
+    }
+
+    /// Keep queued jobs (and their design files), and the history of
+    /// finished jobs, in `dir`, so they survive the server restarting.
+    pub fn with_store(mut self, dir: PathBuf) -> Self {
+        self.store = Some(dir);
+        self
+    }
+
+    /// Forget finished jobs, and remove design files from the job store,
+    /// according to `retention`, once [Jobs::spawn_reaper] is running.
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Emit an event to `events` whenever a job starts printing, completes
+    /// or fails.
+    pub fn with_events(mut self, events: Arc<Events>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Limit how many jobs may be active at once on each type of machine,
+    /// with jobs over the limit waiting in [Jobs::acquire_slot].
+    pub fn with_concurrency_limits(mut self, limits: ConcurrencyLimits) -> Self {
+        self.slots = JobSlots::new(limits);
+        self
+    }
+
+    /// Retry sending jobs to their machine according to `policy`, if it
+    /// fails in a way which might not happen again.
+    pub fn with_dispatch_retry(mut self, policy: DispatchRetry) -> Self {
+        self.dispatch_retry = policy;
+        self
+    }
+
+    /// Return how sending jobs to their machine is retried.
+    pub fn dispatch_retry(&self) -> &DispatchRetry {
+        &self.dispatch_retry
+    }
+
+    /// Return where to store the design file `file_name` for the job `id`;
+    /// in the job store if there is one, or the spool directory if not.
+    pub fn artifact_path(&self, id: &str, file_name: &str) -> PathBuf {
+        self.store.clone().unwrap_or_else(crate::spool_dir).join(format!(
+            "{}_{}",
+            id,
+            crate::sanitize_job_name(file_name)
+        ))
+    }
+
+    /// Record a job as queued in the job store, if there is one, until it's
+    /// handed to its machine (or fails).
+    pub async fn enqueue(&self, queued: &QueuedJob) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        tokio::fs::create_dir_all(store).await?;
+        tokio::fs::write(
+            store.join(format!("{}.json", queued.job.id)),
+            serde_json::to_vec(queued)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Remove a job from the job store, once it's no longer queued.
+    async fn dequeue(&self, id: &str) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        match tokio::fs::remove_file(store.join(format!("{}.json", id))).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!(id = id, error = format!("{:?}", e), "failed to dequeue job"),
+        }
+    }
+
+    /// Load the jobs which were still queued when the server last stopped,
+    /// tracking them again, and returning them grouped by machine, in the
+    /// order each machine should restart them. Finished jobs are loaded from
+    /// the job history too.
+    pub async fn restore(&self) -> Vec<QueuedJob> {
+        let Some(store) = &self.store else {
+            return vec![];
+        };
+        self.restore_history().await;
+        let mut entries = match tokio::fs::read_dir(store).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return vec![],
+            Err(e) => {
+                tracing::warn!(error = format!("{:?}", e), "failed to read job store");
+                return vec![];
+            }
+        };
+
+        let mut queued = vec![];
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let job: QueuedJob = match tokio::fs::read(&path)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+            {
+                Ok(job) => job,
+                Err(e) => {
+                    tracing::warn!(
+                        path = format!("{:?}", path),
+                        error = format!("{:?}", e),
+                        "failed to load queued job"
+                    );
+                    continue;
+                }
+            };
+            queued.push(job);
+        }
+        // For each machine, jobs which were under way go first, then those
+        // which were waiting in its queue, in the order they were waiting.
+        queued.sort_by(|a, b| {
+            (&a.job.machine_id, a.position.unwrap_or(0), a.job.created_at).cmp(&(
+                &b.job.machine_id,
+                b.position.unwrap_or(0),
+                b.job.created_at,
+            ))
+        });
+
+        let mut jobs = self.jobs.write().await;
+        for queued in &queued {
+            jobs.insert(queued.job.id.clone(), queued.job.clone());
+        }
+        queued
+    }
+
+    /// Apply the retention policy every so often, if there is one.
+    pub fn spawn_reaper(self: &Arc<Self>) {
+        if self.retention.is_unlimited() {
+            return;
+        }
+
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REAP_INTERVAL);
+            loop {
+                interval.tick().await;
+                jobs.reap(Utc::now()).await;
+            }
+        });
+    }
+
+    /// Forget finished jobs, and remove design files, which the retention
+    /// policy says are no longer worth keeping as of `now`.
+    async fn reap(&self, now: DateTime<Utc>) {
+        let max_age = self
+            .retention
+            .max_age_seconds
+            .map(|max_age| chrono::Duration::seconds(max_age.try_into().unwrap_or(i64::MAX)));
+        let too_old = |updated_at: DateTime<Utc>| max_age.is_some_and(|max_age| now - updated_at > max_age);
+
+        // Forget old finished jobs, and then all but the newest few on each
+        // machine.
+        let (unfinished, forgotten) = {
+            let mut jobs = self.jobs.write().await;
+            let before: Vec<String> = jobs.keys().cloned().collect();
+            jobs.retain(|_, job| !job.state.is_finished() || !too_old(job.updated_at));
+
+            if let Some(keep) = self.retention.keep_per_machine {
+                let mut finished: HashMap<&str, Vec<&Job>> = HashMap::new();
+                for job in jobs.values().filter(|job| job.state.is_finished()) {
+                    finished.entry(&job.machine_id).or_default().push(job);
+                }
+                let forget: Vec<String> = finished
+                    .into_values()
+                    .flat_map(|mut finished| {
+                        finished.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                        finished.into_iter().skip(keep).map(|job| job.id.clone())
+                    })
+                    .collect();
+                for id in forget {
+                    jobs.remove(&id);
+                }
+            }
+
+            self.manifests.write().await.retain(|id, _| jobs.contains_key(id));
+
+            let forgotten: Vec<String> = before.into_iter().filter(|id| !jobs.contains_key(id)).collect();
+            if !forgotten.is_empty() {
+                tracing::info!(reaped = forgotten.len(), "forgot old jobs");
+                self.reaped_jobs.inc_by(forgotten.len() as u64);
+            }
+
+            let unfinished = jobs
+                .values()
+                .filter(|job| !job.state.is_finished())
+                .map(|job| job.id.clone())
+                .collect::<HashSet<_>>();
+            (unfinished, forgotten)
+        };
+        self.forget_history(&forgotten).await;
+
+        if let Some(store) = &self.store {
+            if let Err(e) = self.reap_artifacts(store, &unfinished, &too_old).await {
+                tracing::warn!(error = format!("{:?}", e), "failed to reap job store");
+            }
+        }
+    }
+
+    /// Remove design files from the job store which are too old, and then
+    /// the oldest until the store is under its size limit, sparing those
+    /// for jobs which haven't finished (or are still queued from before a
+    /// restart).
+    async fn reap_artifacts(
+        &self,
+        store: &Path,
+        unfinished: &HashSet<String>,
+        too_old: &dyn Fn(DateTime<Utc>) -> bool,
+    ) -> Result<()> {
+        let mut entries = match tokio::fs::read_dir(store).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut queued = HashSet::new();
+        let mut artifacts = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    queued.insert(id.to_owned());
+                }
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let id = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.split_once('_'))
+                .map(|(id, _)| id.to_owned());
+            let modified: DateTime<Utc> = metadata.modified()?.into();
+            artifacts.push((path, id, modified, metadata.len()));
+        }
+
+        let mut total_bytes: u64 = artifacts.iter().map(|(_, _, _, len)| len).sum();
+        // Oldest first.
+        artifacts.sort_by(|a, b| a.2.cmp(&b.2));
+
+        for (path, id, modified, len) in artifacts {
+            let in_use = id.is_some_and(|id| unfinished.contains(&id) || queued.contains(&id));
+            let over_size = self
+                .retention
+                .max_total_bytes
+                .is_some_and(|max_total_bytes| total_bytes > max_total_bytes);
+            if in_use || !(too_old(modified) || over_size) {
+                continue;
+            }
+
+            tokio::fs::remove_file(&path).await?;
+            tracing::debug!(path = format!("{:?}", path), "removed old design file");
+            total_bytes -= len;
+            self.reaped_artifacts.inc();
+            self.reaped_artifact_bytes.inc_by(len);
+        }
+
+        Ok(())
+    }
+
+    /// Start tracking a new job, which is waiting on its first phase.
+    pub async fn create(&self, id: &str, machine_id: &str, job_name: &str) -> Job {
+        let now = Utc::now();
+        let job = Job {
+            id: id.to_owned(),
+            machine_id: machine_id.to_owned(),
+            job_name: job_name.to_owned(),
+            file_name: None,
+            state: JobState::Pending,
+            error: None,
+            failure_reason: None,
+            created_at: now,
+            updated_at: now,
+            started_at: None,
+            finished_at: None,
+            phases: vec![],
+            progress: None,
+            possibly_stuck: false,
+            pause_reason: None,
+            queue_position: None,
+            log: vec![],
+            slicer_profile: None,
+            firmware_version: None,
+            snapshot: None,
+        };
+        self.jobs.write().await.insert(id.to_owned(), job.clone());
+        job
+    }
+
+    /// Get a job by id.
+    pub async fn get(&self, id: &str) -> Option<Job> {
+        let mut job = self.jobs.read().await.get(id).cloned()?;
+        job.queue_position = self.queue_position(id);
+        Some(job)
+    }
+
+    /// Wait up to `timeout` for a job to change, returning early with the
+    /// updated job if it does. Finished jobs are returned right away, since
+    /// they won't change again.
+    pub async fn wait_for_change(&self, id: &str, timeout: Duration) -> Option<Job> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        // Register interest before reading the job, so we can't miss a
+        // change that lands in between.
+        let mut notified = std::pin::pin!(self.changed.notified());
+        let original = self.get(id).await?;
+        if original.state.is_finished() {
+            return Some(original);
+        }
+
+        loop {
+            if tokio::time::timeout_at(deadline, notified.as_mut()).await.is_err() {
+                return self.get(id).await;
+            }
+            notified.set(self.changed.notified());
+
+            let job = self.get(id).await?;
+            if job != original {
+                return Some(job);
+            }
+        }
+    }
+
+    /// List all jobs, newest first.
+    pub async fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.read().await.values().cloned().collect();
+        for job in &mut jobs {
+            job.queue_position = self.queue_position(&job.id);
+        }
+        jobs.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        jobs
+    }
+
+    /// The job a machine is printing, or is being sent (past waiting its
+    /// turn), if there is one. Should there somehow be more than one, the
+    /// newest is returned.
+    pub async fn current(&self, machine_id: &str) -> Option<Job> {
+        self.jobs
+            .read()
+            .await
+            .values()
+            .filter(|job| job.machine_id == machine_id && !job.state.is_finished())
+            .filter(|job| {
+                job.state == JobState::Printing
+                    || job
+                        .phases
+                        .last()
+                        .is_some_and(|timing| !matches!(timing.phase, JobPhase::Preprocess | JobPhase::QueueWait))
+            })
+            .max_by_key(|job| job.created_at)
+            .cloned()
+    }
+
+    /// Where the job `id` is in the queue it's waiting in, either for its
+    /// machine to be free or for a slot on its type of machine, counting
+    /// from 1.
+    fn queue_position(&self, id: &str) -> Option<usize> {
+        self.queue.position(id).or_else(|| self.slots.position(id))
+    }
+
+    /// Return whether a job is under way on a machine, or any are waiting
+    /// for it; that is, whether a new job for it should wait its turn.
+    pub async fn is_machine_busy(&self, machine_id: &str) -> bool {
+        self.queue.is_waiting(machine_id) || self.has_job_under_way(machine_id).await
+    }
+
+    /// Return whether a machine has an unfinished job, other than those
+    /// waiting in its queue.
+    pub(crate) async fn has_job_under_way(&self, machine_id: &str) -> bool {
+        self.jobs.read().await.values().any(|job| {
+            job.machine_id == machine_id && !job.state.is_finished() && self.queue.position(&job.id).is_none()
+        })
+    }
+
+    /// Queue a job to run once its machine is free, after the jobs already
+    /// waiting for it.
+    pub async fn join_queue(&self, queued: QueuedJob) {
+        let machine_id = queued.job.machine_id.clone();
+        self.start_phase(&queued.job.id, JobPhase::QueueWait).await;
+        self.queue.join(queued);
+        self.store_queue(&machine_id).await;
+        self.changed.notify_waiters();
+    }
+
+    /// Take a job out of its machine's queue, such as when it's about to
+    /// run, returning it if it was waiting.
+    pub async fn leave_queue(&self, id: &str) -> Option<QueuedJob> {
+        let mut queued = self.queue.leave(id)?;
+        // Jobs which were on their way to their machine go first after a
+        // restart.
+        queued.position = None;
+        if self.store.is_some() {
+            if let Err(e) = self.enqueue(&queued).await {
+                tracing::warn!(id = id, error = format!("{:?}", e), "failed to store queued job");
+            }
+        }
+        self.store_queue(&queued.job.machine_id).await;
+        self.changed.notify_waiters();
+        Some(queued)
+    }
+
+    /// Move a waiting job to `position` in its machine's queue, counting
+    /// from 1, returning whether it was waiting.
+    pub async fn move_in_queue(&self, id: &str, position: usize) -> bool {
+        let Some(machine_id) = self.queue.move_to(id, position) else {
+            return false;
+        };
+        self.store_queue(&machine_id).await;
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// List the jobs waiting for their machines to be free, in the order
+    /// they'll run, by machine.
+    pub async fn list_queue(&self) -> Vec<Job> {
+        let mut jobs = vec![];
+        for queued in self.queue.all() {
+            if let Some(job) = self.get(&queued.job.id).await {
+                jobs.push(job);
+            }
+        }
+        jobs
+    }
+
+    /// Return the job at the front of each machine's queue.
+    pub(crate) fn next_in_queue(&self) -> Vec<QueuedJob> {
+        self.queue.next()
+    }
+
+    /// Record the order of the jobs waiting for a machine in the job store,
+    /// if there is one.
+    async fn store_queue(&self, machine_id: &str) {
+        if self.store.is_none() {
+            return;
+        }
+        for (index, mut queued) in self.queue.machine(machine_id).into_iter().enumerate() {
+            queued.position = Some(index + 1);
+            if let Err(e) = self.enqueue(&queued).await {
+                tracing::warn!(
+                    id = queued.job.id,
+                    error = format!("{:?}", e),
+                    "failed to store queued job"
+                );
+            }
+        }
+    }
+
+    /// Start watching for events happening to the job `id`, such as it
+    /// moving into a new phase, its print progressing, or it finishing.
+    pub fn watch(&self, id: &str) -> JobWatch {
+        self.job_events.watch(id)
+    }
+
+    /// Tell whoever's watching the job `id` how far along its print is.
+    pub(crate) fn publish_progress(&self, id: &str, progress: Option<f64>, layer_progress: Option<LayerProgress>) {
+        self.job_events.publish(
+            id,
+            JobEvent::PrintProgress {
+                progress,
+                layer_progress,
+            },
+        );
+    }
+
+    /// Wait until any job changes, or `timeout` passes.
+    pub(crate) async fn wait_for_any_change(&self, timeout: Duration) {
+        let _ = tokio::time::timeout(timeout, self.changed.notified()).await;
+    }
+
+    /// Wait until the job `id` may go ahead on a machine of `machine_type`
+    /// (such as `Bambu`), if there's a limit on how many jobs may be active
+    /// on them at once. The job holds its slot until the returned
+    /// [JobSlot] is dropped.
+    pub async fn acquire_slot(self: &Arc<Self>, id: &str, machine_type: &str) -> JobSlot {
+        let mut slot = JobSlot {
+            jobs: self.clone(),
+            machine_type: machine_type.to_owned(),
+            id: id.to_owned(),
+            state: SlotState::Unlimited,
+        };
+        if !self.slots.is_limited(machine_type) {
+            return slot;
+        }
+
+        self.slots.join(machine_type, id);
+        slot.state = SlotState::Waiting;
+        self.changed.notify_waiters();
+
+        loop {
+            // Register interest before checking, so we can't miss a slot
+            // being freed in between.
+            let notified = self.changed.notified();
+            if self.slots.try_take(machine_type, id) {
+                slot.state = SlotState::Held;
+                self.changed.notify_waiters();
+                return slot;
+            }
+            tracing::debug!(
+                id = id,
+                position = self.slots.position(id),
+                "waiting for a slot to run job"
+            );
+            notified.await;
+        }
+    }
+
+    /// Move a job into a new phase, finishing the phase it was in (if any).
+    /// Jobs already in `phase` are left in it.
+    pub async fn start_phase(&self, id: &str, phase: JobPhase) {
+        let in_phase = self.jobs.read().await.get(id).is_some_and(|job| {
+            job.phases
+                .last()
+                .is_some_and(|timing| timing.phase == phase && timing.duration_seconds.is_none())
+        });
+        if in_phase {
+            return;
+        }
+
+        let now = Utc::now();
+        self.update(id, |job| {
+            if phase == JobPhase::Print {
+                job.state = JobState::Printing;
+                job.started_at = Some(now);
+            }
+            job.phases.push(PhaseTiming {
+                phase,
+                started_at: now,
+                duration_seconds: None,
+            });
+        })
+        .await;
+
+        self.job_events.publish(id, JobEvent::PhaseStarted { phase });
+
+        // Once the job's been handed to its machine, there's nothing to
+        // restart.
+        if phase == JobPhase::Upload {
+            self.dequeue(id).await;
+        }
+    }
+
+    /// Mark a job as completed, finishing the phase it was in. Jobs which
+    /// already failed (such as when cancelled) are left as they are.
+    pub async fn complete(&self, id: &str) {
+        self.update(id, |job| {
+            if !job.state.is_finished() {
+                job.state = JobState::Completed;
+            }
+        })
+        .await;
+    }
+
+    /// Mark a job as failed for `reason`, finishing the phase it was in, and
+    /// taking it out of its machine's queue if it was waiting there.
+    pub async fn fail(&self, id: &str, reason: FailureReason, error: &str) {
+        self.update(id, |job| {
+            if job.state.is_finished() {
+                return;
+            }
+            self.failures
+                .get_or_create(&JobFailureLabels {
+                    machine_id: job.machine_id.clone(),
+                    slicer_profile: job.slicer_profile.clone().unwrap_or_default(),
+                    code: reason.name().to_owned(),
+                })
+                .inc();
+            job.state = JobState::Failed;
+            job.error = Some(error.to_owned());
+            job.failure_reason = Some(reason);
+        })
+        .await;
+        self.dequeue(id).await;
+        if let Some(queued) = self.queue.leave(id) {
+            self.store_queue(&queued.job.machine_id).await;
+        }
+    }
+
+    /// Record something which happened to a job in its log.
+    pub async fn log(&self, id: &str, message: String) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        let now = Utc::now();
+        job.log.push(JobLogEntry {
+            timestamp: now,
+            message,
+        });
+        job.updated_at = now;
+        self.changed.notify_waiters();
+
+        let finished = job.state.is_finished().then(|| job.clone());
+        drop(jobs);
+        if let Some(job) = finished {
+            self.record(&job).await;
+        }
+    }
+
+    /// Record the name of the slicer profile the job is sliced with.
+    pub async fn set_slicer_profile(&self, id: &str, slicer_profile: String) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.slicer_profile = Some(slicer_profile);
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+    }
+
+    /// Record the version of the firmware the job's machine is running.
+    pub async fn set_firmware_version(&self, id: &str, firmware_version: String) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.firmware_version = Some(firmware_version);
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+    }
+
+    /// Record the name of the design file the job was submitted with.
+    pub async fn set_file_name(&self, id: &str, file_name: String) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.file_name = Some(file_name);
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+    }
+
+    /// Where the photo taken as the job `id` finished is kept.
+    fn snapshot_path(&self, id: &str) -> PathBuf {
+        self.artifact_path(id, "snapshot.jpg")
+    }
+
+    /// Keep `image`, a JPEG taken by the job's machine as it finished, with
+    /// the job. It's removed along with the job's design file.
+    pub async fn attach_snapshot(&self, id: &str, image: &[u8]) -> Result<()> {
+        let path = self.snapshot_path(id);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, image).await?;
+
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        job.snapshot = Some(JobSnapshot {
+            taken_at: now,
+            size_bytes: image.len() as u64,
+        });
+        job.updated_at = now;
+        self.changed.notify_waiters();
+
+        let finished = job.state.is_finished().then(|| job.clone());
+        drop(jobs);
+        if let Some(job) = finished {
+            self.record(&job).await;
+        }
+        Ok(())
+    }
+
+    /// Read the photo taken as the job `id` finished, if there is one (and
+    /// it hasn't been removed under the retention policy since).
+    pub async fn snapshot(&self, id: &str) -> Option<Vec<u8>> {
+        self.get(id).await?.snapshot?;
+        tokio::fs::read(self.snapshot_path(id)).await.ok()
+    }
+
+    /// Record what went into the job `id`, once it's been sliced.
+    pub async fn set_manifest(&self, id: &str, manifest: JobManifest) {
+        if self.jobs.read().await.contains_key(id) {
+            self.manifests.write().await.insert(id.to_owned(), manifest);
+        }
+    }
+
+    /// Return what went into the job `id`, if it's been sliced.
+    pub async fn manifest(&self, id: &str) -> Option<JobManifest> {
+        self.manifests.read().await.get(id).cloned()
+    }
+
+    /// Record how far along the job's print is.
+    pub async fn set_progress(&self, id: &str, progress: JobProgress) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.progress = Some(progress);
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+    }
+
+    /// Flag (or unflag) a job as possibly stuck.
+    pub async fn set_possibly_stuck(&self, id: &str, possibly_stuck: bool) {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return;
+        };
+        job.possibly_stuck = possibly_stuck;
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+    }
+
+    /// Record why the job's machine is paused (or that it isn't), returning
+    /// whether that changed.
+    pub async fn set_pause_reason(&self, id: &str, pause_reason: Option<PauseReason>) -> bool {
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            return false;
+        };
+        if job.pause_reason == pause_reason {
+            return false;
+        }
+        job.pause_reason = pause_reason;
+        job.updated_at = Utc::now();
+        self.changed.notify_waiters();
+        true
+    }
+
+    /// Finish the job's current phase (recording its duration), then apply
+    /// `f` to the job, emitting an event if its state changed.
+    async fn update<F: FnOnce(&mut Job)>(&self, id: &str, f: F) {
+        let now = Utc::now();
+        let mut jobs = self.jobs.write().await;
+        let Some(job) = jobs.get_mut(id) else {
+            tracing::warn!(id = id, "tried to update unknown job");
+            return;
+        };
+        let previous_state = job.state.clone();
+
+        if let Some(timing) = job.phases.last_mut() {
+            if timing.duration_seconds.is_none() {
+                let duration = (now - timing.started_at).to_std().unwrap_or_default().as_secs_f64();
+                timing.duration_seconds = Some(duration);
+                self.phase_durations
+                    .get_or_create(&JobPhaseLabels {
+                        phase: timing.phase,
+                        slicer_profile: job.slicer_profile.clone().unwrap_or_default(),
+                    })
+                    .observe(duration);
+            }
+        }
+
+        f(job);
+        if job.state.is_finished() {
+            // Whatever the machine was paused for, the job's over now.
+            job.pause_reason = None;
+            job.finished_at.get_or_insert(now);
+        }
+        job.updated_at = now;
+        self.changed.notify_waiters();
+
+        if let Some(events) = &self.events {
+            if job.state != previous_state && job.state != JobState::Pending {
+                events.emit(Event::JobStateChanged {
+                    machine_id: job.machine_id.clone(),
+                    job_id: job.id.clone(),
+                    job_name: job.job_name.clone(),
+                    state: job.state.clone(),
+                    failure_reason: job.failure_reason.clone(),
+                });
+            }
+        }
+
+        if !previous_state.is_finished() {
+            if let Some(finished) = JobEvent::finished(job) {
+                self.job_events.publish(id, finished);
+            }
+        }
+
+        let finished = job.state.is_finished().then(|| job.clone());
+        drop(jobs);
+        if let Some(job) = finished {
+            self.record(&job).await;
+        }
+    }
+
+    /// Where the finished job `id` is kept in the job history, if there's a
+    /// job store.
+    fn history_path(&self, id: &str) -> Option<PathBuf> {
+        Some(self.store.as_ref()?.join(HISTORY_DIR).join(format!("{}.json", id)))
+    }
+
+    /// Write a finished job to the job history, so it can still be looked
+    /// up after the server restarts.
+    async fn record(&self, job: &Job) {
+        let Some(path) = self.history_path(&job.id) else {
+            return;
+        };
+        let written = async {
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::write(&path, serde_json::to_vec(job)?).await?;
+            anyhow::Ok(())
+        };
+        if let Err(e) = written.await {
+            tracing::warn!(id = job.id, error = format!("{:?}", e), "failed to record job history");
+        }
+    }
+
+    /// Load the finished jobs recorded in the job history, so they can be
+    /// looked up again.
+    async fn restore_history(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let mut entries = match tokio::fs::read_dir(store.join(HISTORY_DIR)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tracing::warn!(error = format!("{:?}", e), "failed to read job history");
+                return;
+            }
+        };
+
+        let mut jobs = self.jobs.write().await;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let job: Job = match tokio::fs::read(&path)
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+            {
+                Ok(job) => job,
+                Err(e) => {
+                    tracing::warn!(
+                        path = format!("{:?}", path),
+                        error = format!("{:?}", e),
+                        "failed to load finished job"
+                    );
+                    continue;
+                }
+            };
+            jobs.entry(job.id.clone()).or_insert(job);
+        }
+    }
+
+    /// Remove forgotten jobs from the job history.
+    async fn forget_history(&self, ids: &[String]) {
+        for id in ids {
+            let Some(path) = self.history_path(id) else {
+                return;
+            };
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!(id = id, error = format!("{:?}", e), "failed to forget job history"),
+            }
+        }
+    }
+
+    /// Watch a dispatched job's machine until it's no longer printing, and
+    /// then close out the job. If the machine has stuck detection
+    /// configured, the job is flagged (and an event emitted) if its progress
+    /// stops advancing while printing.
+    pub fn spawn_print_watcher(
+        self: &Arc<Self>,
+        id: &str,
+        machine_id: &str,
+        machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
+        events: Arc<Events>,
+    ) {
+        let jobs = self.clone();
+        let id = id.to_owned();
+        // Keep watching the print as part of the job's trace.
+        let span = tracing::info_span!("print_watcher", job_id = id, machine_id = machine_id);
+
+        tokio::spawn(
+            async move {
+                let Some((machine_id, job_name)) = jobs.get(&id).await.map(|job| (job.machine_id, job.job_name)) else {
+                    return;
+                };
+                let started = std::time::Instant::now();
+                let mut seen_printing = false;
+                let mut stall = StallTracker::new(started);
+                let mut milestones = MilestoneTracker::default();
+                let mut last_sample = None;
+
+                loop {
+                    tokio::time::sleep(PRINT_POLL_INTERVAL).await;
+
+                    // Someone else closed out the job, such as by cancelling it.
+                    if jobs.get(&id).await.is_none_or(|job| job.state.is_finished()) {
+                        return;
+                    }
+
+                    let (state, sample, layers, stuck_detection) = {
+                        let machines = machines.read().await;
+                        let Some(machine) = machines.get(&machine_id) else {
+                            jobs.fail(&id, FailureReason::Other, "machine went away").await;
+                            return;
+                        };
+                        let machine = machine.read().await;
+                        let state = machine.get_machine().state().await;
+                        let stuck_detection = machine.get_stuck_detection().cloned();
+                        let (sample, layers) = match &state {
+                            Ok(MachineState::Running) => {
+                                let layers = machine.get_machine().layer_progress().await.ok().flatten();
+                                let sample = ProgressSample {
+                                    percent: machine.get_machine().progress().await.ok().flatten(),
+                                    layer: layers.map(|layers| layers.current_layer),
+                                };
+                                (Some(sample), layers)
+                            }
+                            _ => (None, None),
+                        };
+                        (state, sample, layers, stuck_detection)
+                    };
+
+                    if let Some(sample) = sample {
+                        if last_sample != Some(sample) {
+                            last_sample = Some(sample);
+                            jobs.set_progress(
+                                &id,
+                                JobProgress {
+                                    percent: sample.percent,
+                                    layer_progress: layers,
+                                },
+                            )
+                            .await;
+                            jobs.publish_progress(&id, sample.percent, layers);
+                        }
+                        for milestone in milestones.observe(sample.percent, layers) {
+                            events.emit(Event::JobProgress {
+                                machine_id: machine_id.clone(),
+                                job_id: id.clone(),
+                                job_name: job_name.clone(),
+                                milestone,
+                                progress: sample.percent,
+                                layer_progress: layers,
+                            });
+                        }
+                    }
+
+                    if let Some(stuck_detection) = stuck_detection {
+                        let window = Duration::from_secs(stuck_detection.window_seconds);
+                        match stall.observe(std::time::Instant::now(), sample, window) {
+                            Some(StallChange::Stuck(stalled)) => {
+                                let paused = stuck_detection.auto_pause && pause(&machines, &machine_id).await;
+                                jobs.set_possibly_stuck(&id, true).await;
+                                events.emit(Event::JobStuck {
+                                    machine_id: machine_id.clone(),
+                                    job_id: id.clone(),
+                                    progress: sample.and_then(|sample| sample.percent),
+                                    stalled_seconds: stalled.as_secs(),
+                                    paused,
+                                });
+                            }
+                            Some(StallChange::Recovered) => jobs.set_possibly_stuck(&id, false).await,
+                            None => {}
+                        }
+                    }
+
+                    if let Ok(state) = &state {
+                        let pause_reason = match state {
+                            MachineState::Paused { reason } => Some(reason.clone()),
+                            _ => None,
+                        };
+                        if jobs.set_pause_reason(&id, pause_reason.clone()).await {
+                            if let Some(reason) = pause_reason {
+                                events.emit(Event::JobPaused {
+                                    machine_id: machine_id.clone(),
+                                    job_id: id.clone(),
+                                    reason,
+                                });
+                            }
+                        }
+                    }
+
+                    match state {
+                        Ok(MachineState::Running) | Ok(MachineState::Paused { .. }) => seen_printing = true,
+                        Ok(MachineState::Complete) => break,
+                        Ok(MachineState::Idle) if seen_printing || started.elapsed() > PRINT_START_TIMEOUT => break,
+                        Ok(MachineState::Failed { message }) => {
+                            capture_snapshot(&jobs, &machines, &machine_id, &id).await;
+                            jobs.fail(
+                                &id,
+                                FailureReason::from_machine_message(message.as_deref()),
+                                message.as_deref().unwrap_or("machine failed"),
+                            )
+                            .await;
+                            return;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::debug!(id = id, error = format!("{:?}", e), "failed to get machine state");
+                        }
+                    }
+                }
+
+                capture_snapshot(&jobs, &machines, &machine_id, &id).await;
+                jobs.complete(&id).await;
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Take a photo with `machine_id`'s camera (if it has one), and keep it
+/// with the job `id`, so whoever's watching remotely can see how the job
+/// came out before anything else (such as the next job) happens. Failing to
+/// take one doesn't hold the job up.
+async fn capture_snapshot(
+    jobs: &Jobs,
+    machines: &RwLock<HashMap<String, RwLock<Machine>>>,
+    machine_id: &str,
+    id: &str,
+) {
+    let image = {
+        let machines = machines.read().await;
+        let Some(machine) = machines.get(machine_id) else {
+            return;
+        };
+        let machine = machine.read().await;
+        machine.snapshot().await
+    };
+    match image {
+        Ok(Some(image)) => {
+            if let Err(e) = jobs.attach_snapshot(id, &image).await {
+                tracing::warn!(id = id, error = format!("{:?}", e), "failed to keep snapshot");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            tracing::warn!(id = machine_id, error = format!("{:?}", e), "failed to take snapshot");
+            jobs.log(id, format!("failed to take a photo of the result: {}", e))
+                .await;
+        }
+    }
+}
+
+/// Pause `machine_id`, returning whether it worked.
+async fn pause(machines: &RwLock<HashMap<String, RwLock<Machine>>>, machine_id: &str) -> bool {
+    let machines = machines.read().await;
+    let Some(machine) = machines.get(machine_id) else {
+        return false;
+    };
+    match machine.write().await.pause().await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!(id = machine_id, error = format!("{:?}", e), "failed to pause stuck job");
+            false
+        }
+    }
+}
+
+/// Parse a wait duration such as `30s`, `500ms` or `2m`. A bare number is
+/// taken to be seconds. The result is capped at [MAX_WAIT_FOR_CHANGE].
+pub fn parse_wait(wait: &str) -> Option<Duration> {
+    let wait = wait.trim();
+    let split = wait.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(wait.len());
+    let (value, unit) = wait.split_at(split);
+    let value: u64 = value.parse().ok()?;
+
+    let duration = match unit.trim() {
+        "" | "s" => Duration::from_secs(value),
+        "ms" => Duration::from_millis(value),
+        "m" => Duration::from_secs(value.saturating_mul(60)),
+        _ => return None,
+    };
+
+    Some(duration.min(MAX_WAIT_FOR_CHANGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::FileDigest;
+
+    #[test]
+    fn test_parse_wait() {
+        assert_eq!(parse_wait("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_wait("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_wait("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_wait("1m"), Some(Duration::from_secs(60)));
+        assert_eq!(parse_wait("10m"), Some(MAX_WAIT_FOR_CHANGE));
+        assert_eq!(parse_wait("soon"), None);
+        assert_eq!(parse_wait("5h"), None);
+        assert_eq!(parse_wait(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_slot() {
+        let mut registry = Registry::default();
+        let jobs = Arc::new(
+            Jobs::new(&mut registry)
+                .with_concurrency_limits(ConcurrencyLimits(HashMap::from([("Bambu".to_owned(), 1)]))),
+        );
+        for id in ["first", "second"] {
+            jobs.create(id, "x1c", "benchy").await;
+        }
+
+        // Unlimited types of machine go straight ahead.
+        let _unlimited = jobs.acquire_slot("first", "Moonraker").await;
+
+        let first = jobs.acquire_slot("first", "Bambu").await;
+        let second = {
+            let jobs = jobs.clone();
+            tokio::spawn(async move { jobs.acquire_slot("second", "Bambu").await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+        assert_eq!(jobs.get("first").await.unwrap().queue_position, None);
+        assert_eq!(jobs.get("second").await.unwrap().queue_position, Some(1));
+
+        drop(first);
+        let _second = tokio::time::timeout(Duration::from_secs(5), second)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(jobs.get("second").await.unwrap().queue_position, None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_change() {
+        let mut registry = Registry::default();
+        let jobs = Arc::new(Jobs::new(&mut registry));
+        jobs.create("job", "machine", "benchy").await;
+
+        // Nothing happens, so we wait out the timeout.
+        let job = jobs.wait_for_change("job", Duration::from_millis(50)).await.unwrap();
+        assert!(job.phases.is_empty());
+
+        let waiter = {
+            let jobs = jobs.clone();
+            tokio::spawn(async move { jobs.wait_for_change("job", Duration::from_secs(30)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        jobs.start_phase("job", JobPhase::Slice).await;
+
+        let job = tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(job.phases.len(), 1);
+
+        assert!(jobs.wait_for_change("nope", Duration::from_secs(30)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_phase_timings() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry);
+
+        jobs.create("job", "machine", "benchy").await;
+        jobs.start_phase("job", JobPhase::Preprocess).await;
+        jobs.start_phase("job", JobPhase::Slice).await;
+
+        let job = jobs.get("job").await.unwrap();
+        assert_eq!(job.state, JobState::Pending);
+        assert!(job.phase_duration(JobPhase::Preprocess).is_some());
+        assert!(job.phase_duration(JobPhase::Slice).is_none());
+
+        jobs.start_phase("job", JobPhase::Print).await;
+        assert_eq!(jobs.get("job").await.unwrap().state, JobState::Printing);
+
+        jobs.complete("job").await;
+        let job = jobs.get("job").await.unwrap();
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(
+            job.phases.iter().map(|timing| timing.phase).collect::<Vec<_>>(),
+            vec![JobPhase::Preprocess, JobPhase::Slice, JobPhase::Print]
+        );
+        assert!(job.phases.iter().all(|timing| timing.duration_seconds.is_some()));
+
+        let mut metrics = String::new();
+        prometheus_client::encoding::text::encode(&mut metrics, &registry).unwrap();
+        assert!(metrics.contains(r#"machine_api_job_phase_duration_seconds_count{phase="Slice",slicer_profile=""} 1"#));
+    }
+
+    #[tokio::test]
+    async fn test_current() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry);
+
+        jobs.create("printing", "machine", "benchy").await;
+        jobs.start_phase("printing", JobPhase::Print).await;
+        jobs.create("waiting", "machine", "benchy").await;
+        jobs.start_phase("waiting", JobPhase::QueueWait).await;
+        jobs.create("elsewhere", "other", "benchy").await;
+        jobs.start_phase("elsewhere", JobPhase::Slice).await;
+
+        assert_eq!(jobs.current("machine").await.unwrap().id, "printing");
+        assert_eq!(jobs.current("other").await.unwrap().id, "elsewhere");
+        assert!(jobs.current("nope").await.is_none());
+
+        jobs.complete("printing").await;
+        assert!(jobs.current("machine").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fail() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry);
+
+        jobs.create("job", "machine", "benchy").await;
+        jobs.set_slicer_profile("job", "mk3".to_owned()).await;
+        jobs.start_phase("job", JobPhase::Print).await;
+        jobs.fail("job", FailureReason::UserCancel, "cancelled").await;
+        jobs.fail("job", FailureReason::Estop, "stopped").await;
+        jobs.complete("job").await;
+
+        let job = jobs.get("job").await.unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.failure_reason, Some(FailureReason::UserCancel));
+        assert_eq!(job.error.as_deref(), Some("cancelled"));
+
+        let mut metrics = String::new();
+        prometheus_client::encoding::text::encode(&mut metrics, &registry).unwrap();
+        assert!(metrics.contains(
+            r#"machine_api_job_failures_total{machine_id="machine",slicer_profile="mk3",code="user_cancel"} 1"#
+        ));
+        assert!(!metrics.contains(r#"code="estop""#));
+    }
+
+    #[tokio::test]
+    async fn test_pause_reason() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry);
+
+        jobs.create("job", "machine", "benchy").await;
+        jobs.start_phase("job", JobPhase::Print).await;
+        assert!(jobs.set_pause_reason("job", Some(PauseReason::FilamentRunout)).await);
+        assert!(!jobs.set_pause_reason("job", Some(PauseReason::FilamentRunout)).await);
+        assert_eq!(
+            jobs.get("job").await.unwrap().pause_reason,
+            Some(PauseReason::FilamentRunout)
+        );
+
+        assert!(jobs.set_pause_reason("job", Some(PauseReason::User)).await);
+        jobs.fail("job", FailureReason::UserCancel, "cancelled").await;
+        assert_eq!(jobs.get("job").await.unwrap().pause_reason, None);
+        assert!(!jobs.set_pause_reason("nope", None).await);
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry).with_retention(Retention {
+            keep_per_machine: Some(0),
+            ..Default::default()
+        });
+        let manifest = JobManifest {
+            sliced_at: Utc::now(),
+            input: FileDigest {
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned(),
+                size_bytes: 3,
+            },
+            slicer_version: None,
+            profiles: vec![],
+            slicer_configuration: Default::default(),
+            output: None,
+        };
+
+        jobs.create("job", "machine", "benchy").await;
+        jobs.set_manifest("job", manifest.clone()).await;
+        jobs.set_manifest("nope", manifest.clone()).await;
+        assert_eq!(jobs.manifest("job").await, Some(manifest));
+        assert_eq!(jobs.manifest("nope").await, None);
+
+        // The manifest is forgotten along with its job.
+        jobs.complete("job").await;
+        jobs.reap(Utc::now()).await;
+        assert_eq!(jobs.manifest("job").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_queue_store() {
+        let dir = std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4().simple()));
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry).with_store(dir.clone());
+
+        for id in ["queued", "dispatched", "failed"] {
+            let job = jobs.create(id, "machine", "benchy").await;
+            jobs.enqueue(&QueuedJob {
+                artifact: jobs.artifact_path(id, "benchy.stl"),
+                job,
+                slicer_configuration: Default::default(),
+                override_material: false,
+                override_chamber_preheat: true,
+                position: None,
+            })
+            .await
+            .unwrap();
+        }
+        jobs.start_phase("dispatched", JobPhase::Upload).await;
+        jobs.fail("failed", FailureReason::SlicerError, "too big").await;
+
+        // A fresh server only picks up the job which never left the queue.
+        let mut registry = Registry::default();
+        let restored = Jobs::new(&mut registry).with_store(dir.clone());
+        let queued = restored.restore().await;
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].job.id, "queued");
+        assert_eq!(queued[0].artifact, dir.join("queued_benchy.stl"));
+        assert!(queued[0].override_chamber_preheat);
+        assert_eq!(restored.get("queued").await.unwrap().state, JobState::Pending);
+        assert!(restored.get("dispatched").await.is_none());
+
+        // Finished jobs are kept in the job history.
+        let failed = restored.get("failed").await.unwrap();
+        assert_eq!(failed.state, JobState::Failed);
+        assert_eq!(failed.error.as_deref(), Some("too big"));
+        assert!(failed.finished_at.is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_machine_queue_store() {
+        let dir = std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4().simple()));
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry).with_store(dir.clone());
+
+        for id in ["first", "second", "third"] {
+            let job = jobs.create(id, "machine", "benchy").await;
+            jobs.join_queue(QueuedJob {
+                artifact: jobs.artifact_path(id, "benchy.stl"),
+                job,
+                slicer_configuration: Default::default(),
+                override_material: false,
+                override_chamber_preheat: false,
+                position: None,
+            })
+            .await;
+        }
+        assert!(jobs.is_machine_busy("machine").await);
+        assert!(!jobs.has_job_under_way("machine").await);
+        assert!(jobs.move_in_queue("third", 1).await);
+        assert!(jobs.leave_queue("first").await.is_some());
+        assert_eq!(jobs.get("second").await.unwrap().queue_position, Some(2));
+
+        // Cancelled jobs leave the queue too.
+        jobs.fail("second", FailureReason::UserCancel, "cancelled").await;
+        assert_eq!(jobs.get("second").await.unwrap().queue_position, None);
+
+        // The job which left the queue to run goes first after a restart,
+        // then the jobs still waiting, in order.
+        let mut registry = Registry::default();
+        let restored = Jobs::new(&mut registry).with_store(dir.clone());
+        let queued: Vec<String> = restored
+            .restore()
+            .await
+            .into_iter()
+            .map(|queued| queued.job.id)
+            .collect();
+        assert_eq!(queued, vec!["first", "third"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_by_machine() {
+        let dir = std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4().simple()));
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry).with_store(dir.clone());
+
+        // Each machine's jobs are interleaved with the other's.
+        for (id, machine_id) in [("b1", "b"), ("a1", "a"), ("b2", "b"), ("a2", "a"), ("b3", "b")] {
+            let job = jobs.create(id, machine_id, "benchy").await;
+            jobs.join_queue(QueuedJob {
+                artifact: jobs.artifact_path(id, "benchy.stl"),
+                job,
+                slicer_configuration: Default::default(),
+                override_material: false,
+                override_chamber_preheat: false,
+                position: None,
+            })
+            .await;
+        }
+        assert!(jobs.move_in_queue("b3", 1).await);
+        assert!(jobs.leave_queue("a2").await.is_some());
+
+        let mut registry = Registry::default();
+        let restored = Jobs::new(&mut registry).with_store(dir.clone());
+        let queued: Vec<String> = restored
+            .restore()
+            .await
+            .into_iter()
+            .map(|queued| queued.job.id)
+            .collect();
+        assert_eq!(queued, vec!["a2", "a1", "b3", "b1", "b2"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reap() {
+        let dir = std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry)
+            .with_store(dir.clone())
+            .with_retention(Retention {
+                keep_per_machine: Some(1),
+                max_total_bytes: Some(10),
+                max_age_seconds: Some(3600),
+            });
+
+        let now = Utc::now();
+        for (id, minutes_ago) in [("old", 120), ("done1", 10), ("done2", 5), ("running", 1)] {
+            jobs.create(id, "machine", "benchy").await;
+            if id != "running" {
+                jobs.complete(id).await;
+            }
+            let mut all = jobs.jobs.write().await;
+            let job = all.get_mut(id).unwrap();
+            job.created_at = now - chrono::Duration::minutes(minutes_ago);
+            job.updated_at = job.created_at;
+        }
+
+        std::fs::write(dir.join("running_benchy.stl"), [0; 20]).unwrap();
+        std::fs::write(dir.join("done1_benchy.stl"), [0; 8]).unwrap();
+        std::fs::write(dir.join("queued.json"), "{}").unwrap();
+        std::fs::write(dir.join("queued_benchy.stl"), [0; 8]).unwrap();
+
+        jobs.reap(now).await;
+
+        let mut ids: Vec<String> = jobs.list().await.into_iter().map(|job| job.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["done2", "running"]);
+
+        // Over the size limit, but only the finished job's file can go.
+        assert!(!dir.join("done1_benchy.stl").exists());
+        assert!(dir.join("running_benchy.stl").exists());
+        assert!(dir.join("queued_benchy.stl").exists());
+
+        // Forgotten jobs leave the job history too.
+        assert!(!dir.join("history/old.json").exists());
+        assert!(dir.join("history/done2.json").exists());
+
+        let mut metrics = String::new();
+        prometheus_client::encoding::text::encode(&mut metrics, &registry).unwrap();
+        assert!(metrics.contains("machine_api_jobs_reaped_total 2"));
+        assert!(metrics.contains("machine_api_job_artifacts_reaped_bytes_total 8"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_milestone_tracker() {
+        let layers = |current_layer| {
+            Some(LayerProgress {
+                current_layer,
+                total_layers: 100,
+                z_mm: None,
+            })
+        };
+        let mut milestones = MilestoneTracker::default();
+
+        // Nothing has been passed when progress is first seen.
+        assert_eq!(milestones.observe(Some(0.0), layers(1)), vec![]);
+        assert_eq!(milestones.observe(Some(0.5), layers(1)), vec![]);
+        assert_eq!(
+            milestones.observe(Some(2.1), layers(2)),
+            vec![
+                ProgressMilestone::Percent { percent: 1 },
+                ProgressMilestone::Percent { percent: 2 },
+                ProgressMilestone::FirstLayer,
+            ]
+        );
+        // Progress going backwards doesn't announce anything again.
+        assert_eq!(milestones.observe(Some(1.0), layers(2)), vec![]);
+        assert_eq!(milestones.observe(Some(2.9), layers(3)), vec![]);
+        assert_eq!(
+            milestones.observe(Some(100.0), layers(100)),
+            (3..=100)
+                .map(|percent| ProgressMilestone::Percent { percent })
+                .chain([ProgressMilestone::LastLayer])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(milestones.observe(Some(100.0), layers(100)), vec![]);
+
+        // A job first seen part way through skips what it's passed.
+        let mut milestones = MilestoneTracker::default();
+        assert_eq!(milestones.observe(Some(50.0), layers(50)), vec![]);
+        assert_eq!(
+            milestones.observe(None, layers(100)),
+            vec![ProgressMilestone::LastLayer]
+        );
+    }
+
+    #[test]
+    fn test_stall_tracker() {
+        let window = Duration::from_secs(60);
+        let start = std::time::Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let sample = |percent| {
+            Some(ProgressSample {
+                percent: Some(percent),
+                layer: None,
+            })
+        };
+        let mut stall = StallTracker::new(start);
+
+        assert_eq!(stall.observe(at(0), sample(10.0), window), None);
+        assert_eq!(stall.observe(at(50), sample(10.0), window), None);
+        // Time spent paused doesn't count.
+        assert_eq!(stall.observe(at(100), None, window), None);
+        assert_eq!(stall.observe(at(140), sample(10.0), window), None);
+        assert_eq!(
+            stall.observe(at(160), sample(10.0), window),
+            Some(StallChange::Stuck(Duration::from_secs(60)))
+        );
+        // Only flagged once.
+        assert_eq!(stall.observe(at(300), sample(10.0), window), None);
+        assert_eq!(
+            stall.observe(at(310), sample(11.0), window),
+            Some(StallChange::Recovered)
+        );
+        assert_eq!(stall.observe(at(320), sample(12.0), window), None);
+
+        // Machines which report nothing can't be stuck.
+        let nothing = Some(ProgressSample {
+            percent: None,
+            layer: None,
+        });
+        assert_eq!(stall.observe(at(1000), nothing, window), None);
+    }
+
+    #[test]
+    fn test_failure_reason_from_machine_message() {
+        assert_eq!(
+            FailureReason::from_machine_message(Some("Shutdown due to M112 command")),
+            FailureReason::Estop
+        );
+        assert_eq!(
+            FailureReason::from_machine_message(Some("Filament runout detected")),
+            FailureReason::FilamentRunout
+        );
+        assert_eq!(
+            FailureReason::from_machine_message(Some("MCU 'mcu' shutdown: Timer too close")),
+            FailureReason::MachineFault {
+                code: Some("MCU 'mcu' shutdown: Timer too close".to_owned())
+            }
+        );
+        assert_eq!(
+            FailureReason::from_machine_message(None),
+            FailureReason::MachineFault { code: None }
+        );
+    }
+}
@@ -0,0 +1,373 @@
+//! Routes from before the API was versioned, kept as unpublished aliases of
+//! their `/v1` equivalents until [LEGACY_SUNSET]. Responses carry
+//! `Deprecation` and `Sunset` headers, along with a `Link` to the `/v1`
+//! route to use instead.
+
+use std::sync::Arc;
+
+use dropshot::{endpoint, Body, HttpCodedResponse, HttpError, Path, Query, RequestContext, TypedBody};
+use http::{HeaderValue, Response, StatusCode};
+
+use super::{
+    auth::authorize,
+    endpoints::{
+        self, JobPathParams, JobQueryParams, MachineInfoResponse, MachinePathParams, PrintJobResponse,
+        SchedulePathParams, TestPrintParameters,
+    },
+    task_mode::mutate,
+    Context, CorsResponseOk, FileResponseOk, Job, Schedule, ScheduleParameters, API_VERSION,
+};
+use crate::slicer::profiles::Profile;
+
+/// When the unversioned routes will be removed, as an HTTP date.
+pub const LEGACY_SUNSET: &str = "Fri, 01 Jan 2027 00:00:00 GMT";
+
+/// Wrap a response from a legacy route, adding headers pointing the client
+/// at the `/v1` route to use instead.
+pub struct Deprecated<ResponseT> {
+    response: ResponseT,
+    successor: String,
+}
+
+impl<ResponseT> Deprecated<ResponseT> {
+    fn new(rqctx: &RequestContext<Arc<Context>>, response: ResponseT) -> Self {
+        Self {
+            response,
+            successor: format!("/{}{}", API_VERSION, rqctx.request.uri().path()),
+        }
+    }
+}
+
+impl<ResponseT> HttpCodedResponse for Deprecated<ResponseT>
+where
+    ResponseT: HttpCodedResponse,
+    ResponseT: Into<Result<Response<Body>, HttpError>>,
+{
+    type Body = ResponseT::Body;
+
+    const STATUS_CODE: StatusCode = ResponseT::STATUS_CODE;
+    const DESCRIPTION: &'static str = ResponseT::DESCRIPTION;
+}
+
+impl<ResponseT> From<Deprecated<ResponseT>> for Result<Response<Body>, HttpError>
+where
+    ResponseT: Into<Result<Response<Body>, HttpError>>,
+{
+    fn from(deprecated: Deprecated<ResponseT>) -> Result<Response<Body>, HttpError> {
+        let mut response: Response<Body> = deprecated.response.into()?;
+        let headers = response.headers_mut();
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        headers.insert("sunset", HeaderValue::from_static(LEGACY_SUNSET));
+        if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", deprecated.successor)) {
+            headers.insert(http::header::LINK, link);
+        }
+        Ok(response)
+    }
+}
+
+/// Deprecated alias of `GET /v1/machines`.
+#[endpoint {
+    method = GET,
+    path = "/machines",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_machines(
+    rqctx: RequestContext<Arc<Context>>,
+) -> Result<Deprecated<CorsResponseOk<Vec<MachineInfoResponse>>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let machines = endpoints::list_visible_machines(rqctx.context(), &access).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(machines)))
+}
+
+/// Deprecated alias of `GET /v1/machines/{id}`.
+#[endpoint {
+    method = GET,
+    path = "/machines/{id}",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+) -> Result<Deprecated<CorsResponseOk<MachineInfoResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_machine(rqctx.context(), &id, None).await?;
+    let machine = endpoints::machine_info(rqctx.context(), &id, None).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(machine)))
+}
+
+/// Deprecated alias of `POST /v1/machines/{id}/disable`.
+#[endpoint {
+    method = POST,
+    path = "/machines/{id}/disable",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn disable_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+) -> Result<Deprecated<CorsResponseOk<MachineInfoResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_machine(rqctx.context(), &id, None).await?;
+    let machine = mutate(&rqctx, |ctx| async move {
+        endpoints::set_machine_disabled(&ctx, &id, None, true).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(machine)))
+}
+
+/// Deprecated alias of `POST /v1/machines/{id}/enable`.
+#[endpoint {
+    method = POST,
+    path = "/machines/{id}/enable",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn enable_machine(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+) -> Result<Deprecated<CorsResponseOk<MachineInfoResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_machine(rqctx.context(), &id, None).await?;
+    let machine = mutate(&rqctx, |ctx| async move {
+        endpoints::set_machine_disabled(&ctx, &id, None, false).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(machine)))
+}
+
+/// Deprecated alias of `POST /v1/print`.
+#[endpoint {
+    method = POST,
+    path = "/print",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub(crate) async fn print_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<Deprecated<CorsResponseOk<PrintJobResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let job = mutate(&rqctx, |ctx| async move {
+        endpoints::print_upload(&ctx, &access, body_param).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(job)))
+}
+
+/// Deprecated alias of `POST /v1/machines/{id}/test-print`.
+#[endpoint {
+    method = POST,
+    path = "/machines/{id}/test-print",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn test_print(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<MachinePathParams>,
+    body_param: TypedBody<TestPrintParameters>,
+) -> Result<Deprecated<CorsResponseOk<PrintJobResponse>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    let params = body_param.into_inner();
+    access.check_machine(rqctx.context(), &id, None).await?;
+    let job = mutate(&rqctx, |ctx| async move {
+        endpoints::start_test_print(&ctx, &id, None, params).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(job)))
+}
+
+/// Deprecated alias of `GET /v1/jobs`.
+#[endpoint {
+    method = GET,
+    path = "/jobs",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_jobs(rqctx: RequestContext<Arc<Context>>) -> Result<Deprecated<CorsResponseOk<Vec<Job>>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let ctx = rqctx.context();
+    let jobs = access.visible_jobs(ctx, ctx.jobs.list().await).await;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(jobs)))
+}
+
+/// Deprecated alias of `GET /v1/jobs/{id}`.
+#[endpoint {
+    method = GET,
+    path = "/jobs/{id}",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+    query_params: Query<JobQueryParams>,
+) -> Result<Deprecated<CorsResponseOk<Job>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    let job = endpoints::find_job(rqctx.context(), &id, query_params.into_inner()).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(job)))
+}
+
+/// Deprecated alias of `POST /v1/jobs/{id}/cancel`.
+#[endpoint {
+    method = POST,
+    path = "/jobs/{id}/cancel",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn cancel_job(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<JobPathParams>,
+) -> Result<Deprecated<CorsResponseOk<Job>>, HttpError> {
+    let access = authorize(&rqctx)?;
+    let id = path_params.into_inner().id;
+    access.check_job(rqctx.context(), &id).await?;
+    let job = mutate(&rqctx, |ctx| async move { endpoints::cancel(&ctx, &id).await }).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(job)))
+}
+
+/// Deprecated alias of `POST /v1/schedules`.
+#[endpoint {
+    method = POST,
+    path = "/schedules",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub(crate) async fn create_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<Deprecated<CorsResponseOk<Schedule>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let schedule = mutate(&rqctx, |ctx| async move {
+        endpoints::schedule_upload(&ctx, body_param).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(schedule)))
+}
+
+/// Deprecated alias of `GET /v1/schedules`.
+#[endpoint {
+    method = GET,
+    path = "/schedules",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_schedules(
+    rqctx: RequestContext<Arc<Context>>,
+) -> Result<Deprecated<CorsResponseOk<Vec<Schedule>>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let schedules = rqctx.context().schedules.list().await;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(schedules)))
+}
+
+/// Deprecated alias of `GET /v1/schedules/{id}`.
+#[endpoint {
+    method = GET,
+    path = "/schedules/{id}",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+) -> Result<Deprecated<CorsResponseOk<Schedule>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let schedule = endpoints::find_schedule(rqctx.context(), &path_params.into_inner().id).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(schedule)))
+}
+
+/// Deprecated alias of `PUT /v1/schedules/{id}`.
+#[endpoint {
+    method = PUT,
+    path = "/schedules/{id}",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn update_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+    body: TypedBody<ScheduleParameters>,
+) -> Result<Deprecated<CorsResponseOk<Schedule>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    let parameters = body.into_inner();
+    let schedule = mutate(&rqctx, |ctx| async move {
+        endpoints::change_schedule(&ctx, &id, parameters).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(schedule)))
+}
+
+/// Deprecated alias of `DELETE /v1/schedules/{id}`.
+#[endpoint {
+    method = DELETE,
+    path = "/schedules/{id}",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn delete_schedule(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<SchedulePathParams>,
+) -> Result<Deprecated<CorsResponseOk<Schedule>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let id = path_params.into_inner().id;
+    let schedule = mutate(&rqctx, |ctx| async move { endpoints::remove_schedule(&ctx, &id).await }).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(schedule)))
+}
+
+/// Deprecated alias of `POST /v1/slice`.
+#[endpoint {
+    method = POST,
+    path = "/slice",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub(crate) async fn slice_file(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<Deprecated<FileResponseOk>, HttpError> {
+    authorize(&rqctx)?;
+    let content = endpoints::slice_upload(rqctx.context(), body_param).await?;
+    Ok(Deprecated::new(&rqctx, FileResponseOk(content)))
+}
+
+/// Deprecated alias of `GET /v1/slicer-profiles`.
+#[endpoint {
+    method = GET,
+    path = "/slicer-profiles",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub async fn get_slicer_profiles(
+    rqctx: RequestContext<Arc<Context>>,
+) -> Result<Deprecated<CorsResponseOk<Vec<Profile>>>, HttpError> {
+    authorize(&rqctx)?;
+    let profiles = endpoints::list_profiles(rqctx.context()).await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(profiles)))
+}
+
+/// Deprecated alias of `POST /v1/slicer-profiles`.
+#[endpoint {
+    method = POST,
+    path = "/slicer-profiles",
+    tags = ["hidden"],
+    unpublished = true,
+}]
+pub(crate) async fn import_slicer_profile(
+    rqctx: RequestContext<Arc<Context>>,
+    body_param: dropshot::MultipartBody,
+) -> Result<Deprecated<CorsResponseOk<Profile>>, HttpError> {
+    authorize(&rqctx)?.check_all()?;
+    let profile = mutate(&rqctx, |ctx| async move {
+        endpoints::profile_upload(&ctx, body_param).await
+    })
+    .await?;
+    Ok(Deprecated::new(&rqctx, CorsResponseOk(profile)))
+}
@@ -0,0 +1,212 @@
+//! Live updates of machines' state, temperatures and job progress, pushed
+//! to clients watching a machine over a WebSocket, rather than each of them
+//! polling `/v1/machines/{id}`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use super::Context;
+use crate::{Control, LayerProgress, Machine, MachineState, TemperatureSensorReading};
+
+/// How often watched machines are sampled for changes, besides whenever a
+/// job changes.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How many updates a slow client may fall behind by before it skips to
+/// the latest.
+const UPDATE_CAPACITY: usize = 64;
+
+/// A machine's state, as pushed to clients watching it.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct MachineUpdate {
+    /// The machine id.
+    pub machine_id: String,
+
+    /// Status of the machine.
+    pub state: MachineState,
+
+    /// The machine's temperature sensors, by name, if it has any.
+    pub temperatures: BTreeMap<String, TemperatureSensorReading>,
+
+    /// Progress of the current print, if printing.
+    pub progress: Option<f64>,
+
+    /// Layer the current print is on, if printing and the machine reports
+    /// layers.
+    pub layer_progress: Option<LayerProgress>,
+
+    /// The job the machine is printing, or is being sent, if any.
+    pub current_job_id: Option<String>,
+}
+
+impl MachineUpdate {
+    /// Sample the machine `id`'s state now.
+    pub(crate) async fn sample(ctx: &Context, id: &str, machine: &Machine) -> anyhow::Result<Self> {
+        let temperatures = match machine.temperatures().await {
+            Ok(temperatures) => temperatures.into_iter().collect(),
+            Err(e) => {
+                tracing::debug!(id = id, error = format!("{:?}", e), "failed to read temperatures");
+                BTreeMap::new()
+            }
+        };
+        Ok(Self {
+            machine_id: id.to_owned(),
+            state: machine.state().await?,
+            temperatures,
+            progress: machine.get_machine().progress().await?,
+            layer_progress: machine.get_machine().layer_progress().await?,
+            current_job_id: ctx.jobs.current(id).await.map(|job| job.id),
+        })
+    }
+}
+
+/// Updates to machines' state, broadcast to every client watching them.
+pub struct MachineUpdates {
+    sender: broadcast::Sender<MachineUpdate>,
+    /// The latest update sent for each machine, so unchanged ones aren't
+    /// sent again.
+    latest: Mutex<HashMap<String, MachineUpdate>>,
+    /// How many clients are watching each machine.
+    watchers: Mutex<HashMap<String, usize>>,
+}
+
+impl Default for MachineUpdates {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(UPDATE_CAPACITY).0,
+            latest: Default::default(),
+            watchers: Default::default(),
+        }
+    }
+}
+
+impl MachineUpdates {
+    /// Start watching for updates to the machine `machine_id`, until the
+    /// returned [Watch] is dropped.
+    pub fn watch(&self, machine_id: &str) -> Watch<'_> {
+        *self.watchers.lock().unwrap().entry(machine_id.to_owned()).or_default() += 1;
+        Watch {
+            updates: self,
+            machine_id: machine_id.to_owned(),
+            receiver: self.sender.subscribe(),
+        }
+    }
+
+    /// Return the machines being watched.
+    fn watched(&self) -> Vec<String> {
+        self.watchers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Send `update` to the machine's watchers, if it's changed since the
+    /// last one.
+    pub(crate) fn publish(&self, update: MachineUpdate) {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.get(&update.machine_id) == Some(&update) {
+            return;
+        }
+        latest.insert(update.machine_id.clone(), update.clone());
+        // No one may be listening any more, which is fine.
+        let _ = self.sender.send(update);
+    }
+}
+
+/// A client watching a machine for updates.
+pub struct Watch<'a> {
+    updates: &'a MachineUpdates,
+    machine_id: String,
+    receiver: broadcast::Receiver<MachineUpdate>,
+}
+
+impl Watch<'_> {
+    /// Wait for the next update to the machine. Updates missed by falling
+    /// behind are skipped, since a later one supersedes them.
+    pub async fn recv(&mut self) -> Option<MachineUpdate> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(update) if update.machine_id == self.machine_id => return Some(update),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!(id = self.machine_id, skipped = skipped, "watcher fell behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Watch<'_> {
+    fn drop(&mut self) {
+        let mut watchers = self.updates.watchers.lock().unwrap();
+        if let Some(count) = watchers.get_mut(&self.machine_id) {
+            *count -= 1;
+            if *count == 0 {
+                watchers.remove(&self.machine_id);
+                // Whoever watches next gets the machine's state straight
+                // away, even if it hasn't changed.
+                self.updates.latest.lock().unwrap().remove(&self.machine_id);
+            }
+        }
+    }
+}
+
+/// Start the background task which samples the machines being watched,
+/// and publishes any changes to them.
+pub fn spawn_publisher(ctx: Arc<Context>) {
+    tokio::spawn(async move {
+        loop {
+            for id in ctx.updates.watched() {
+                let machines = ctx.machines.read().await;
+                let Some(machine) = machines.get(&id) else {
+                    continue;
+                };
+                match MachineUpdate::sample(&ctx, &id, &*machine.read().await).await {
+                    Ok(update) => ctx.updates.publish(update),
+                    Err(e) => tracing::debug!(id = id, error = format!("{:?}", e), "failed to sample machine"),
+                }
+            }
+            ctx.jobs.wait_for_any_change(UPDATE_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(machine_id: &str, progress: Option<f64>) -> MachineUpdate {
+        MachineUpdate {
+            machine_id: machine_id.to_owned(),
+            state: MachineState::Running,
+            temperatures: BTreeMap::new(),
+            progress,
+            layer_progress: None,
+            current_job_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let updates = MachineUpdates::default();
+        let mut watch = updates.watch("x1c");
+        assert_eq!(updates.watched(), vec!["x1c"]);
+
+        updates.publish(update("mk4", Some(10.0)));
+        updates.publish(update("x1c", Some(10.0)));
+        // Unchanged updates aren't sent again.
+        updates.publish(update("x1c", Some(10.0)));
+        updates.publish(update("x1c", Some(20.0)));
+
+        assert_eq!(watch.recv().await, Some(update("x1c", Some(10.0))));
+        assert_eq!(watch.recv().await, Some(update("x1c", Some(20.0))));
+
+        drop(watch);
+        assert!(updates.watched().is_empty());
+    }
+}
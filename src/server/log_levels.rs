@@ -0,0 +1,267 @@
+//! Log verbosity which can be turned up (or down) for one machine at a
+//! time while the server is running, so one misbehaving machine can be
+//! traced without drowning the logs for every other machine.
+//!
+//! Logs are attributed to a machine by the `machine_id` field of any span
+//! they're in, such as the spans machines are polled in.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span,
+    subscriber::Interest,
+    Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, Filter},
+    registry::{LookupSpan, SpanRef},
+};
+
+/// How verbose logs are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    /// Nothing at all.
+    Off,
+
+    /// Only errors.
+    Error,
+
+    /// Warnings and errors.
+    Warn,
+
+    /// Informational messages, warnings and errors.
+    Info,
+
+    /// Debugging messages, and everything above.
+    Debug,
+
+    /// Everything.
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// Level for everything not overridden.
+    default: LevelFilter,
+
+    /// Levels overridden for machines, by machine id.
+    machines: HashMap<String, LogLevel>,
+}
+
+/// Log levels overridden per machine at runtime. This is a filter for
+/// `tracing_subscriber` layers; clones share the same levels, so the
+/// server can change them for every layer at once.
+#[derive(Debug, Clone)]
+pub struct LogLevels {
+    state: Arc<RwLock<State>>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self::new(LevelFilter::INFO)
+    }
+}
+
+impl LogLevels {
+    /// Create a new [LogLevels], logging at `default` unless a machine's
+    /// level is overridden.
+    pub fn new(default: LevelFilter) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(State {
+                default,
+                machines: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Return the level `machine_id` logs at, if it's overridden.
+    pub fn get(&self, machine_id: &str) -> Option<LogLevel> {
+        self.state.read().unwrap().machines.get(machine_id).copied()
+    }
+
+    /// Override the level `machine_id` logs at, or with `None`, go back to
+    /// the default.
+    pub fn set(&self, machine_id: &str, level: Option<LogLevel>) {
+        {
+            let mut state = self.state.write().unwrap();
+            match level {
+                Some(level) => state.machines.insert(machine_id.to_owned(), level),
+                None => state.machines.remove(machine_id),
+            };
+        }
+        // Callsites cache whether they're enabled, so have them ask again.
+        tracing::callsite::rebuild_interest_cache();
+    }
+}
+
+impl<S> Filter<S> for LogLevels
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        let state = self.state.read().unwrap();
+        if !state.machines.is_empty() {
+            if let Some(level) = machine_level(&state, cx.lookup_current()) {
+                return level >= *meta.level();
+            }
+        }
+        state.default >= *meta.level()
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        let state = self.state.read().unwrap();
+        if !state.machines.is_empty() {
+            // Whether it's enabled depends on which machine it's for.
+            Interest::sometimes()
+        } else if state.default >= *meta.level() {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let state = self.state.read().unwrap();
+        Some(
+            state
+                .machines
+                .values()
+                .map(|level| LevelFilter::from(*level))
+                .fold(state.default, LevelFilter::max),
+        )
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, cx: Context<'_, S>) {
+        let mut visitor = MachineIdVisitor(None);
+        attrs.record(&mut visitor);
+        remember_machine_id(visitor.0, id, &cx);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, cx: Context<'_, S>) {
+        let mut visitor = MachineIdVisitor(None);
+        values.record(&mut visitor);
+        remember_machine_id(visitor.0, id, &cx);
+    }
+}
+
+/// Return the level a machine's logs are overridden to, if `span` (or any
+/// span it's in) is for a machine which is.
+fn machine_level<S>(state: &State, span: Option<SpanRef<'_, S>>) -> Option<LevelFilter>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    span?.scope().find_map(|span| {
+        let extensions = span.extensions();
+        let machine_id = extensions.get::<MachineId>()?;
+        state.machines.get(&machine_id.0).copied().map(LevelFilter::from)
+    })
+}
+
+/// Remember which machine the span `id` is for, if it has a `machine_id`.
+fn remember_machine_id<S>(machine_id: Option<String>, id: &span::Id, cx: &Context<'_, S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if let (Some(machine_id), Some(span)) = (machine_id, cx.span(id)) {
+        span.extensions_mut().replace(MachineId(machine_id));
+    }
+}
+
+/// The machine a span is for.
+struct MachineId(String);
+
+/// Picks the `machine_id` field out of a span's fields.
+struct MachineIdVisitor(Option<String>);
+
+impl Visit for MachineIdVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "machine_id" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "machine_id" {
+            self.0 = Some(format!("{:?}", value).trim_matches('"').to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    /// Counts the events it sees.
+    #[derive(Clone, Default)]
+    struct Counter(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Counter {
+        fn on_event(&self, _event: &tracing::Event<'_>, _cx: Context<'_, S>) {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    impl Counter {
+        fn take(&self) -> usize {
+            self.0.swap(0, std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_log_levels() {
+        let levels = LogLevels::new(LevelFilter::INFO);
+        let counter = Counter::default();
+        let subscriber = tracing_subscriber::registry().with(counter.clone().with_filter(levels.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let log = |machine_id: &str| {
+                let _span = tracing::info_span!("machine", machine_id = machine_id).entered();
+                tracing::info!("info");
+                tracing::debug!("debug");
+                tracing::trace!("trace");
+            };
+
+            log("noisy");
+            log("quiet");
+            assert_eq!(counter.take(), 2);
+
+            levels.set("noisy", Some(LogLevel::Trace));
+            levels.set("quiet", Some(LogLevel::Warn));
+            assert_eq!(levels.get("noisy"), Some(LogLevel::Trace));
+            log("noisy");
+            log("quiet");
+            tracing::debug!("not a machine");
+            assert_eq!(counter.take(), 3);
+
+            levels.set("noisy", None);
+            levels.set("quiet", None);
+            assert_eq!(levels.get("noisy"), None);
+            log("noisy");
+            log("quiet");
+            assert_eq!(counter.take(), 2);
+        });
+    }
+}
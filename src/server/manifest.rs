@@ -0,0 +1,127 @@
+//! Manifests of what went into each job: checksums of its design file, the
+//! slicer profiles it was sliced with, and the file sent to its machine, so
+//! it can be shown which inputs produced a given part.
+
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+use crate::{AnySlicer, SlicedFile, SlicerConfiguration};
+
+/// Checksum of a file.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct FileDigest {
+    /// SHA-256 of the file, in hex.
+    pub sha256: String,
+
+    /// Size of the file, in bytes.
+    pub size_bytes: u64,
+}
+
+/// Checksum of a slicer profile a job was sliced with.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ProfileDigest {
+    /// Where the profile is, on the server.
+    pub path: String,
+
+    /// SHA-256 of the profile, in hex.
+    pub sha256: String,
+}
+
+/// What went into a job, and what came out of slicing it.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct JobManifest {
+    /// When the job was sliced.
+    pub sliced_at: DateTime<Utc>,
+
+    /// The design file, as it was sliced (after any scaling from inches).
+    pub input: FileDigest,
+
+    /// Version of the slicer, if it says.
+    pub slicer_version: Option<String>,
+
+    /// The slicer profiles the job was sliced with.
+    pub profiles: Vec<ProfileDigest>,
+
+    /// Settings the job overrode the profiles with.
+    pub slicer_configuration: SlicerConfiguration,
+
+    /// The sliced file sent to the machine, if it needed one.
+    pub output: Option<FileDigest>,
+}
+
+impl JobManifest {
+    /// Record what went into slicing `design_file` with `slicer`, and what
+    /// came out.
+    pub(crate) async fn new(
+        design_file: &Path,
+        slicer: &AnySlicer,
+        slicer_configuration: &SlicerConfiguration,
+        sliced: &SlicedFile,
+    ) -> Result<Self> {
+        let mut profiles = vec![];
+        for path in slicer.profiles() {
+            profiles.push(ProfileDigest {
+                path: path.display().to_string(),
+                sha256: digest(&path).await?.sha256,
+            });
+        }
+
+        Ok(Self {
+            sliced_at: Utc::now(),
+            input: digest(design_file).await?,
+            slicer_version: slicer.version().await,
+            profiles,
+            slicer_configuration: *slicer_configuration,
+            output: match sliced.path() {
+                Some(path) => Some(digest(path).await?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Return the checksum of the file at `path`.
+async fn digest(path: &Path) -> Result<FileDigest> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut sha256 = openssl::sha::Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut size_bytes = 0;
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        sha256.update(&buf[..read]);
+        size_bytes += read as u64;
+    }
+    Ok(FileDigest {
+        sha256: sha256.finish().iter().map(|byte| format!("{:02x}", byte)).collect(),
+        size_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_digest() {
+        let path = std::env::temp_dir().join(format!("{}_digest", uuid::Uuid::new_v4().simple()));
+        tokio::fs::write(&path, b"abc").await.unwrap();
+        let digest = digest(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(
+            digest,
+            FileDigest {
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned(),
+                size_bytes: 3,
+            }
+        );
+    }
+}
@@ -1,25 +1,73 @@
 //! REST-ful JSON API
 
+mod access_log;
+mod advertise;
+mod auth;
 mod context;
 mod cors;
+mod cron;
 mod endpoints;
+mod etag;
+mod events;
+mod fetch;
+mod files;
+mod job_events;
+mod jobs;
+mod legacy;
+mod live;
+mod log_levels;
+mod manifest;
+mod poller;
+mod queue;
 mod raw;
+mod registrations;
+mod restore;
+mod retry;
+mod schedules;
+mod slots;
+mod stream;
+mod task_mode;
 
-use std::{collections::HashMap, env, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf, sync::Arc};
 
+use access_log::{AccessLog, AccessLogDrain};
 use anyhow::{anyhow, Result};
+pub use auth::{ApiKey, ApiKeys};
 pub use context::Context;
 pub use cors::CorsResponseOk;
 use dropshot::{ApiDescription, ConfigDropshot, HttpServerStarter};
+pub use etag::ETaggedResponseOk;
+pub use events::{Event, EventRecord, Events, ProgressMilestone, ProgressSubscription, Webhook};
+pub use fetch::FileUrls;
+pub use files::{Files, StoredFile};
+pub use job_events::{EventStreamBody, JobEvent, JobEventRecord, JobEvents, JobWatch};
+pub use jobs::{
+    FailureReason, Job, JobLogEntry, JobPhase, JobProgress, JobSlot, JobSnapshot, JobState, Jobs, PhaseTiming,
+    QueuedJob, Retention,
+};
+pub use live::{MachineUpdate, MachineUpdates, Watch};
+pub use log_levels::{LogLevel, LogLevels};
+pub use manifest::{FileDigest, JobManifest, ProfileDigest};
+pub use poller::{Poller, Polling};
 use prometheus_client::registry::Registry;
-pub use raw::RawResponseOk;
+pub use raw::{EventStreamResponseOk, FileResponseOk, JpegResponseOk, MjpegResponseOk, RawResponseOk};
+pub use registrations::{MachineRegistration, MachineRegistrationParameters, Registrations};
+pub use retry::DispatchRetry;
+pub use schedules::{MachineSelector, Schedule, ScheduleParameters, Schedules};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
 };
+pub use slots::ConcurrencyLimits;
+pub use stream::MjpegBody;
+pub use task_mode::{TaskMode, TaskModes};
 use tokio::sync::RwLock;
 
-use crate::Machine;
+use crate::{slicer::profiles::ProfileStore, AnySlicer, Machine, NetworkFilter};
+
+/// The current API version, which prefixes every versioned route (such as
+/// `/v1/machines`).
+pub const API_VERSION: &str = "v1";
 
 /// Create an API description for the server.
 pub fn create_api_description() -> Result<ApiDescription<Arc<Context>>> {
@@ -28,8 +76,70 @@ pub fn create_api_description() -> Result<ApiDescription<Arc<Context>>> {
         api.register(endpoints::api_get_schema).unwrap();
         api.register(endpoints::print_file).unwrap();
         api.register(endpoints::get_machines).unwrap();
+        api.register(endpoints::register_machine).unwrap();
         api.register(endpoints::get_machine).unwrap();
+        api.register(endpoints::remove_machine).unwrap();
+        api.register(endpoints::get_machine_job).unwrap();
+        api.register(endpoints::disable_machine).unwrap();
+        api.register(endpoints::enable_machine).unwrap();
+        api.register(endpoints::reserve_machine).unwrap();
+        api.register(endpoints::release_machine).unwrap();
+        api.register(endpoints::stop_machine).unwrap();
+        api.register(endpoints::pause_machine).unwrap();
+        api.register(endpoints::resume_machine).unwrap();
+        api.register(endpoints::set_machine_log_level).unwrap();
+        api.register(endpoints::bulk_machines).unwrap();
+        api.register(endpoints::get_accessories).unwrap();
+        api.register(endpoints::control_accessory).unwrap();
+        api.register(endpoints::get_bed_mesh).unwrap();
+        api.register(endpoints::get_machine_temperatures).unwrap();
+        api.register(endpoints::get_machine_snapshot).unwrap();
+        api.register(endpoints::get_machine_stream).unwrap();
+        api.register(endpoints::get_machine_mqtt_debug).unwrap();
+        api.register(endpoints::watch_machine).unwrap();
+        api.register(endpoints::test_print).unwrap();
         api.register(endpoints::get_metrics).unwrap();
+        api.register(endpoints::get_jobs).unwrap();
+        api.register(endpoints::get_job).unwrap();
+        api.register(endpoints::get_job_events).unwrap();
+        api.register(endpoints::get_job_snapshot).unwrap();
+        api.register(endpoints::get_job_manifest).unwrap();
+        api.register(endpoints::cancel_job).unwrap();
+        api.register(endpoints::list_queue).unwrap();
+        api.register(endpoints::move_queued_job).unwrap();
+        api.register(endpoints::remove_queued_job).unwrap();
+        api.register(endpoints::create_schedule).unwrap();
+        api.register(endpoints::get_schedules).unwrap();
+        api.register(endpoints::get_schedule).unwrap();
+        api.register(endpoints::update_schedule).unwrap();
+        api.register(endpoints::delete_schedule).unwrap();
+        api.register(endpoints::upload_file).unwrap();
+        api.register(endpoints::get_files).unwrap();
+        api.register(endpoints::delete_file).unwrap();
+        api.register(endpoints::slice_file).unwrap();
+        api.register(endpoints::analyze_file).unwrap();
+        api.register(endpoints::get_slicer_profiles).unwrap();
+        api.register(endpoints::import_slicer_profile).unwrap();
+        api.register(endpoints::get_versions).unwrap();
+
+        // Unversioned aliases of the `/v1` routes, until they're removed.
+        api.register(legacy::get_machines).unwrap();
+        api.register(legacy::get_machine).unwrap();
+        api.register(legacy::disable_machine).unwrap();
+        api.register(legacy::enable_machine).unwrap();
+        api.register(legacy::print_file).unwrap();
+        api.register(legacy::test_print).unwrap();
+        api.register(legacy::get_jobs).unwrap();
+        api.register(legacy::get_job).unwrap();
+        api.register(legacy::cancel_job).unwrap();
+        api.register(legacy::create_schedule).unwrap();
+        api.register(legacy::get_schedules).unwrap();
+        api.register(legacy::get_schedule).unwrap();
+        api.register(legacy::update_schedule).unwrap();
+        api.register(legacy::delete_schedule).unwrap();
+        api.register(legacy::slice_file).unwrap();
+        api.register(legacy::get_slicer_profiles).unwrap();
+        api.register(legacy::import_slicer_profile).unwrap();
 
         // YOUR ENDPOINTS HERE!
 
@@ -47,11 +157,35 @@ pub fn create_api_description() -> Result<ApiDescription<Arc<Context>>> {
     Ok(api)
 }
 
-/// Create a new Machine API Server.
+/// Create a new Machine API Server. Finished jobs, and design files in
+/// `job_store`, are reaped according to `retention`, jobs active at once on
+/// each type of machine are limited by `concurrency`, and sending jobs to
+/// their machine is retried according to `dispatch_retry`, and design files
+/// may be downloaded from the URLs allowed by `file_urls`. Handlers are
+/// cancelled or run to the end when their client disconnects according to
+/// `task_modes`. If `read_only`, every request which would change anything
+/// is refused. If there are any `api_keys`, requests (other than to
+/// `/ping`) without one of them are refused. Machines' log levels are
+/// overridden through `log_levels`. Stored design files are kept in the
+/// `files` directory of `job_store`, if set, across restarts, and are never
+/// reaped.
+#[allow(clippy::too_many_arguments)]
 pub async fn create_server(
     bind: &str,
     machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
     registry: Arc<RwLock<Registry>>,
+    events: Arc<Events>,
+    slicers: HashMap<String, AnySlicer>,
+    profiles: ProfileStore,
+    job_store: Option<PathBuf>,
+    retention: Retention,
+    concurrency: ConcurrencyLimits,
+    dispatch_retry: DispatchRetry,
+    file_urls: FileUrls,
+    task_modes: TaskModes,
+    read_only: bool,
+    api_keys: ApiKeys,
+    log_levels: LogLevels,
 ) -> Result<(dropshot::HttpServer<Arc<Context>>, Arc<Context>)> {
     let mut api = create_api_description()?;
     let schema = get_openapi(&mut api)?;
@@ -59,21 +193,65 @@ pub async fn create_server(
     let config_dropshot = ConfigDropshot {
         bind_address: bind.parse()?,
         default_request_body_max_bytes: 107374182400, // 100 Gigiabytes.
-        default_handler_task_mode: dropshot::HandlerTaskMode::CancelOnDisconnect,
+        default_handler_task_mode: task_modes.dropshot(),
         log_headers: Default::default(),
     };
 
+    let access_log = Arc::new(AccessLog::new(&mut *registry.write().await, &schema));
+
+    let mut jobs = Jobs::new(&mut *registry.write().await)
+        .with_retention(retention)
+        .with_concurrency_limits(concurrency)
+        .with_dispatch_retry(dispatch_retry)
+        .with_events(events.clone());
+    let files = match &job_store {
+        Some(job_store) => {
+            let files = Files::new(job_store.join("files"));
+            if let Err(e) = files.restore().await {
+                tracing::warn!(error = format!("{:?}", e), "failed to restore file library");
+            }
+            files
+        }
+        None => Files::default(),
+    };
+    if let Some(job_store) = job_store {
+        jobs = jobs.with_store(job_store);
+    }
+    let jobs = Arc::new(jobs);
+    jobs.spawn_reaper();
+
     let api_context = Arc::new(Context {
         schema,
         machines,
         registry,
+        events,
+        jobs,
+        schedules: Arc::new(Schedules::default()),
+        files: Arc::new(files),
+        registrations: Arc::new(Registrations::default()),
+        slicers,
+        profiles,
+        file_urls,
+        task_modes,
+        read_only,
+        api_keys,
+        log_levels,
+        updates: Default::default(),
+        machine_listing: Default::default(),
     });
+    schedules::spawn_scheduler(api_context.clone());
+    restore::spawn_restored_jobs(api_context.clone()).await;
+    queue::spawn_dispatcher(api_context.clone());
+    live::spawn_publisher(api_context.clone());
 
     let server = HttpServerStarter::new(
         &config_dropshot,
         api,
         api_context.clone(),
-        &slog::Logger::root(tracing_slog::TracingSlogDrain, slog::o!()),
+        &slog::Logger::root(
+            AccessLogDrain::new(tracing_slog::TracingSlogDrain, access_log),
+            slog::o!(),
+        ),
     )
     .map_err(|error| anyhow!("failed to create server: {}", error))?
     .start();
@@ -93,22 +271,53 @@ pub fn get_openapi(api: &mut ApiDescription<Arc<Context>>) -> Result<serde_json:
         .map_err(|e| e.into())
 }
 
-/// Create a new Server, and serve.
+/// Create a new Server, and serve. The server is advertised over mDNS on
+/// the network interfaces matching `network`, along with a summary of it
+/// (such as how many machines it has). Machines with active jobs are
+/// polled more often by `poller`. Queued jobs are kept in
+/// `job_store`, if set, so they're restarted if the server is.
+#[allow(clippy::too_many_arguments)]
 pub async fn serve(
     bind: &str,
     machines: Arc<RwLock<HashMap<String, RwLock<Machine>>>>,
     registry: Arc<RwLock<Registry>>,
+    events: Arc<Events>,
+    slicers: HashMap<String, AnySlicer>,
+    profiles: ProfileStore,
+    job_store: Option<PathBuf>,
+    retention: Retention,
+    concurrency: ConcurrencyLimits,
+    dispatch_retry: DispatchRetry,
+    file_urls: FileUrls,
+    task_modes: TaskModes,
+    read_only: bool,
+    api_keys: ApiKeys,
+    log_levels: LogLevels,
+    network: &NetworkFilter,
+    poller: Arc<Poller>,
 ) -> Result<()> {
-    let (server, _api_context) = create_server(bind, machines, registry).await?;
+    let (server, api_context) = create_server(
+        bind,
+        machines,
+        registry,
+        events,
+        slicers,
+        profiles,
+        job_store,
+        retention,
+        concurrency,
+        dispatch_retry,
+        file_urls,
+        task_modes,
+        read_only,
+        api_keys,
+        log_levels,
+    )
+    .await?;
+    poller.watch_jobs(api_context.jobs.clone());
     let addr: SocketAddr = bind.parse()?;
 
-    let responder = libmdns::Responder::new().unwrap();
-    let _svc = responder.register(
-        "_machine-api._tcp".to_owned(),
-        "Machine Api Server".to_owned(),
-        addr.port(),
-        &["path=/"],
-    );
+    let _advertiser = advertise::spawn_advertiser(api_context, addr.port(), network.clone());
 
     // For Cloud run & ctrl+c, shutdown gracefully.
     // "The main process inside the container will receive SIGTERM, and after a grace period,
@@ -0,0 +1,234 @@
+//! Polling machines for their status (such as their temperatures) from one
+//! place, so that many machines on the same backend don't flood the
+//! network. Each backend's polls are spaced out to its rate limit, polls are
+//! jittered so machines don't all go at once, and machines with an active
+//! job are polled more often, without waiting behind idle ones.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::Instrument;
+
+use super::Jobs;
+
+/// How often machines are polled for their status.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Polling {
+    /// How often to poll machines without an active job, in seconds.
+    #[serde(default = "default_idle_interval_seconds")]
+    pub idle_interval_seconds: f64,
+
+    /// How often to poll machines with an active job, in seconds.
+    #[serde(default = "default_active_interval_seconds")]
+    pub active_interval_seconds: f64,
+
+    /// How much to vary each interval by, at random, as a fraction of it,
+    /// so that machines found at the same time aren't polled in lockstep.
+    #[serde(default = "default_jitter")]
+    pub jitter: f64,
+
+    /// Most polls a second of each type of machine (as in its config's
+    /// `type`, such as `Moonraker`). Types without a limit aren't limited.
+    #[serde(default)]
+    pub max_polls_per_second: HashMap<String, f64>,
+}
+
+fn default_idle_interval_seconds() -> f64 {
+    15.0
+}
+
+fn default_active_interval_seconds() -> f64 {
+    5.0
+}
+
+fn default_jitter() -> f64 {
+    0.1
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Self {
+            idle_interval_seconds: default_idle_interval_seconds(),
+            active_interval_seconds: default_active_interval_seconds(),
+            jitter: default_jitter(),
+            max_polls_per_second: HashMap::new(),
+        }
+    }
+}
+
+impl Polling {
+    /// How long to wait before polling a machine again, jittered.
+    fn interval(&self, active: bool) -> Duration {
+        let seconds = if active {
+            self.active_interval_seconds
+        } else {
+            self.idle_interval_seconds
+        };
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::random_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+        Duration::from_secs_f64((seconds * factor).max(0.0))
+    }
+
+    /// The least time between polls of a type of machine, if it's limited.
+    fn spacing(&self, machine_type: &str) -> Option<Duration> {
+        self.max_polls_per_second
+            .get(machine_type)
+            .filter(|rate| **rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(1.0 / rate))
+    }
+}
+
+/// Polls machines for their status, on behalf of whatever wants it (such
+/// as metrics).
+#[derive(Default)]
+pub struct Poller {
+    config: Polling,
+
+    /// Jobs, to tell which machines have an active one. Until these are
+    /// set, every machine is polled as if it were idle.
+    jobs: OnceLock<Arc<Jobs>>,
+
+    /// When the next poll of each type of machine may go ahead, if its
+    /// polls are limited.
+    next_turn: Mutex<HashMap<String, Instant>>,
+}
+
+impl Poller {
+    /// Create a new `Poller`, polling as often as `config` says.
+    pub fn new(config: Polling) -> Self {
+        Self {
+            config,
+            jobs: OnceLock::new(),
+            next_turn: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Poll machines with an active job in `jobs` more often. This can
+    /// only be set once; later calls are ignored.
+    pub fn watch_jobs(&self, jobs: Arc<Jobs>) {
+        let _ = self.jobs.set(jobs);
+    }
+
+    /// Return whether the machine has an active job.
+    async fn is_active(&self, machine_id: &str) -> bool {
+        match self.jobs.get() {
+            Some(jobs) => jobs.current(machine_id).await.is_some(),
+            None => false,
+        }
+    }
+
+    /// Take the next turn to poll a type of machine, returning when it is.
+    /// Active machines take the next turn, but don't wait for it, so they
+    /// aren't held up behind idle ones.
+    fn take_turn(&self, machine_type: &str, active: bool, now: Instant) -> Instant {
+        let Some(spacing) = self.config.spacing(machine_type) else {
+            return now;
+        };
+
+        let mut next_turn = self.next_turn.lock().unwrap();
+        let next_turn = next_turn.entry(machine_type.to_owned()).or_insert(now);
+        let turn = (*next_turn).max(now);
+        *next_turn = turn + spacing;
+        if active {
+            now
+        } else {
+            turn
+        }
+    }
+
+    /// Poll a machine of `machine_type` with `poll` for as long as the
+    /// returned task runs, as often as the machine's activity and its
+    /// type's rate limit allow.
+    pub fn spawn<F, FutureT>(self: &Arc<Self>, machine_type: &str, machine_id: &str, mut poll: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> FutureT + Send + 'static,
+        FutureT: Future<Output = ()> + Send + 'static,
+    {
+        let poller = self.clone();
+        let machine_type = machine_type.to_owned();
+        let machine_id = machine_id.to_owned();
+        // Logs from polling are the machine's, so its log level applies.
+        let span = tracing::info_span!("poll_machine", machine_id = machine_id);
+        tokio::spawn(
+            async move {
+                // Start at a random point in the interval, so machines found
+                // together don't stay together.
+                tokio::time::sleep(poller.config.interval(false).mul_f64(rand::random::<f64>())).await;
+
+                loop {
+                    let active = poller.is_active(&machine_id).await;
+                    tokio::time::sleep_until(poller.take_turn(&machine_type, active, Instant::now())).await;
+
+                    tracing::trace!(id = machine_id, active = active, "polling machine");
+                    poll().await;
+
+                    tokio::time::sleep(poller.config.interval(active)).await;
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval() {
+        let config = Polling::default();
+        for _ in 0..100 {
+            let idle = config.interval(false).as_secs_f64();
+            assert!((13.5..=16.5).contains(&idle), "{}", idle);
+            let active = config.interval(true).as_secs_f64();
+            assert!((4.5..=5.5).contains(&active), "{}", active);
+        }
+
+        let config = Polling {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(config.interval(false), Duration::from_secs(15));
+    }
+
+    #[test]
+    fn test_take_turn() {
+        let poller = Poller::new(Polling {
+            max_polls_per_second: HashMap::from([("Moonraker".to_owned(), 2.0)]),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        // Unlimited types go straight away.
+        assert_eq!(poller.take_turn("Bambu", false, now), now);
+        assert_eq!(poller.take_turn("Bambu", false, now), now);
+
+        // Limited types are spaced out.
+        assert_eq!(poller.take_turn("Moonraker", false, now), now);
+        assert_eq!(
+            poller.take_turn("Moonraker", false, now),
+            now + Duration::from_millis(500)
+        );
+
+        // Active machines go straight away, but push idle ones back.
+        assert_eq!(poller.take_turn("Moonraker", true, now), now);
+        assert_eq!(
+            poller.take_turn("Moonraker", false, now),
+            now + Duration::from_millis(1500)
+        );
+
+        // Turns don't pile up while nothing's polling.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(poller.take_turn("Moonraker", false, later), later);
+    }
+}
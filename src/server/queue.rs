@@ -0,0 +1,309 @@
+//! Jobs waiting for their machine to be free, so a job sent to a busy
+//! machine is queued rather than refused. Each machine's jobs run first
+//! come first served, unless they're reordered. Waiting jobs are kept in
+//! the job store (if there is one), so the queue survives a restart.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::Instrument;
+
+use super::{
+    endpoints::{check_machine_ready, find_machine, run_print_job, MachineIdType},
+    jobs::QueuedJob,
+    Context, FailureReason,
+};
+use crate::{MachineState, TemporaryFile};
+
+/// How often the queue is checked for jobs whose machine is free, besides
+/// whenever a job changes.
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a queued job waits for its machine to be (re)discovered before
+/// giving up on it, such as after a restart.
+const MACHINE_MISSING_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// The jobs waiting for each machine, by machine id, in the order they'll
+/// run.
+#[derive(Debug, Default)]
+pub(crate) struct MachineQueues {
+    queues: Mutex<HashMap<String, Vec<QueuedJob>>>,
+}
+
+impl MachineQueues {
+    /// Join the back of the queue for the job's machine.
+    pub(crate) fn join(&self, queued: QueuedJob) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.entry(queued.job.machine_id.clone()).or_default().push(queued);
+    }
+
+    /// Leave the queue, returning the job if it was waiting.
+    pub(crate) fn leave(&self, id: &str) -> Option<QueuedJob> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues
+            .values_mut()
+            .find(|queue| queue.iter().any(|queued| queued.job.id == id))?;
+        let index = queue.iter().position(|queued| queued.job.id == id)?;
+        let queued = queue.remove(index);
+        queues.retain(|_, queue| !queue.is_empty());
+        Some(queued)
+    }
+
+    /// Move a job to `position` in its machine's queue, counting from 1,
+    /// or to the back if `position` is past it. Returns the job's machine
+    /// id, if it's waiting.
+    pub(crate) fn move_to(&self, id: &str, position: usize) -> Option<String> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues
+            .values_mut()
+            .find(|queue| queue.iter().any(|queued| queued.job.id == id))?;
+        let index = queue.iter().position(|queued| queued.job.id == id)?;
+        let queued = queue.remove(index);
+        let machine_id = queued.job.machine_id.clone();
+        queue.insert(position.saturating_sub(1).min(queue.len()), queued);
+        Some(machine_id)
+    }
+
+    /// Where a job is in its machine's queue, if it's waiting, counting
+    /// from 1.
+    pub(crate) fn position(&self, id: &str) -> Option<usize> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .values()
+            .find_map(|queue| queue.iter().position(|queued| queued.job.id == id))
+            .map(|position| position + 1)
+    }
+
+    /// Return whether any jobs are waiting for a machine.
+    pub(crate) fn is_waiting(&self, machine_id: &str) -> bool {
+        self.queues.lock().unwrap().contains_key(machine_id)
+    }
+
+    /// Return the jobs waiting for a machine, in order.
+    pub(crate) fn machine(&self, machine_id: &str) -> Vec<QueuedJob> {
+        self.queues.lock().unwrap().get(machine_id).cloned().unwrap_or_default()
+    }
+
+    /// Return every waiting job, in order, by machine id.
+    pub(crate) fn all(&self) -> Vec<QueuedJob> {
+        let queues = self.queues.lock().unwrap();
+        let mut machine_ids: Vec<&String> = queues.keys().collect();
+        machine_ids.sort();
+        machine_ids
+            .into_iter()
+            .flat_map(|id| queues[id].iter().cloned())
+            .collect()
+    }
+
+    /// Return the job at the front of each machine's queue.
+    pub(crate) fn next(&self) -> Vec<QueuedJob> {
+        let queues = self.queues.lock().unwrap();
+        queues.values().filter_map(|queue| queue.first().cloned()).collect()
+    }
+}
+
+/// Whether a queued job's machine can take it now (under its current ID,
+/// which may have changed since the job was queued), should be waited on,
+/// or never will.
+enum Availability {
+    Ready(String),
+    Wait(String),
+    Missing,
+    Unavailable(String),
+}
+
+/// Check whether the machine a queued job is waiting for can take it.
+async fn check_availability(ctx: &Context, queued: &QueuedJob) -> Availability {
+    let machines = ctx.machines.read().await;
+    let Ok(Some((id, machine))) = find_machine(&machines, &queued.job.machine_id, Some(MachineIdType::Id)).await else {
+        // Machines are found by discovery, which may not have got to this
+        // one yet.
+        return Availability::Missing;
+    };
+    if ctx.jobs.has_job_under_way(id).await {
+        return Availability::Wait("machine has a job under way".to_owned());
+    }
+    let machine = machine.read().await;
+
+    // Reserved machines hold on to their queue until the reservation is
+    // released or runs out.
+    if let Some(reservation) = machine.reservation() {
+        return Availability::Wait(format!("machine is reserved until {}", reservation.expires_at));
+    }
+
+    // Machines disabled for maintenance hold on to their queue until
+    // they're enabled again.
+    match machine.state().await {
+        Ok(MachineState::Idle) => {}
+        Ok(state) => return Availability::Wait(format!("machine is not idle: {:?}", state)),
+        Err(e) => return Availability::Wait(format!("{:?}", e)),
+    }
+
+    match check_machine_ready(
+        &machine,
+        &queued.job.machine_id,
+        &queued.slicer_configuration,
+        queued.override_material,
+    )
+    .await
+    {
+        Ok(()) => Availability::Ready(id.clone()),
+        Err(e) => Availability::Unavailable(e.external_message),
+    }
+}
+
+/// Fail a queued job which will never run, removing its design file.
+async fn drop_queued(ctx: &Context, queued: &QueuedJob, reason: FailureReason, error: &str) {
+    tracing::warn!(id = queued.job.id, error = error, "dropping queued job");
+    ctx.jobs.leave_queue(&queued.job.id).await;
+    ctx.jobs.fail(&queued.job.id, reason, error).await;
+    if let Err(e) = tokio::fs::remove_file(&queued.artifact).await {
+        tracing::warn!(
+            id = queued.job.id,
+            error = format!("{:?}", e),
+            "failed to remove queued job file"
+        );
+    }
+}
+
+/// Slice a job which has reached the front of the queue, and send it to
+/// its machine.
+#[tracing::instrument(skip_all, fields(job_id = queued.job.id, machine_id = machine_id))]
+async fn run(ctx: &Context, queued: QueuedJob, machine_id: &str) {
+    let id = &queued.job.id;
+    let tmpfile = match TemporaryFile::new(&queued.artifact).await {
+        Ok(tmpfile) => tmpfile,
+        Err(e) => {
+            ctx.jobs.fail(id, FailureReason::Other, &format!("{:?}", e)).await;
+            return;
+        }
+    };
+
+    tracing::info!(id = id, machine_id = machine_id, "starting queued job");
+    if let Err(e) = run_print_job(
+        ctx,
+        id,
+        machine_id,
+        &queued.job.job_name,
+        tmpfile,
+        &queued.slicer_configuration,
+        queued.override_chamber_preheat,
+    )
+    .await
+    {
+        tracing::warn!(id = id, error = e.external_message, "queued job failed");
+    }
+}
+
+/// Send the job at the front of its machine's queue on, if the machine is
+/// free, or drop it if the machine never will be. `missing_since` is when
+/// each machine a queued job is waiting for was first found to be missing.
+async fn dispatch(ctx: &Arc<Context>, queued: QueuedJob, missing_since: &mut HashMap<String, Instant>) {
+    let machine_id = queued.job.machine_id.clone();
+    match check_availability(ctx, &queued).await {
+        Availability::Ready(id) => {
+            missing_since.remove(&machine_id);
+            if ctx.jobs.leave_queue(&queued.job.id).await.is_none() {
+                // Removed from the queue in the meantime.
+                return;
+            }
+            let ctx = ctx.clone();
+            tokio::spawn(async move { run(&ctx, queued, &id).await }.in_current_span());
+        }
+        Availability::Wait(reason) => {
+            missing_since.remove(&machine_id);
+            tracing::trace!(id = queued.job.id, reason = reason, "waiting for machine");
+        }
+        Availability::Missing => {
+            let since = *missing_since.entry(machine_id).or_insert_with(Instant::now);
+            if since.elapsed() > MACHINE_MISSING_TIMEOUT {
+                drop_queued(ctx, &queued, FailureReason::Timeout, "machine not found").await;
+            }
+        }
+        Availability::Unavailable(reason) => {
+            drop_queued(ctx, &queued, FailureReason::Other, &reason).await;
+        }
+    }
+}
+
+/// Start the background task which sends each machine the job at the front
+/// of its queue once the machine is free.
+pub fn spawn_dispatcher(ctx: Arc<Context>) {
+    tokio::spawn(async move {
+        let mut missing_since: HashMap<String, Instant> = HashMap::new();
+        loop {
+            for queued in ctx.jobs.next_in_queue() {
+                let span = tracing::info_span!("dispatch", job_id = queued.job.id, machine_id = queued.job.machine_id);
+                dispatch(&ctx, queued, &mut missing_since).instrument(span).await;
+            }
+            ctx.jobs.wait_for_any_change(DISPATCH_INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+    use crate::server::Jobs;
+
+    async fn queued(jobs: &Jobs, id: &str, machine_id: &str) -> QueuedJob {
+        QueuedJob {
+            job: jobs.create(id, machine_id, "benchy").await,
+            artifact: format!("/tmp/{}_benchy.stl", id).into(),
+            slicer_configuration: Default::default(),
+            override_material: false,
+            override_chamber_preheat: false,
+            position: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_machine_queues() {
+        let mut registry = Registry::default();
+        let jobs = Jobs::new(&mut registry);
+        let queues = MachineQueues::default();
+        for (id, machine_id) in [("a", "x1c"), ("b", "x1c"), ("c", "x1c"), ("d", "mk4")] {
+            queues.join(queued(&jobs, id, machine_id).await);
+        }
+
+        assert_eq!(queues.position("a"), Some(1));
+        assert_eq!(queues.position("c"), Some(3));
+        assert_eq!(queues.position("d"), Some(1));
+        assert!(queues.is_waiting("x1c"));
+        assert_eq!(
+            queues
+                .all()
+                .iter()
+                .map(|queued| queued.job.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["d", "a", "b", "c"]
+        );
+
+        // Reordering only moves jobs within their machine's queue.
+        assert_eq!(queues.move_to("c", 1), Some("x1c".to_owned()));
+        assert_eq!(queues.move_to("a", 10), Some("x1c".to_owned()));
+        assert_eq!(queues.move_to("nope", 1), None);
+        assert_eq!(
+            queues
+                .machine("x1c")
+                .iter()
+                .map(|queued| queued.job.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["c", "b", "a"]
+        );
+
+        let mut next: Vec<String> = queues.next().into_iter().map(|queued| queued.job.id).collect();
+        next.sort();
+        assert_eq!(next, vec!["c", "d"]);
+
+        assert!(queues.leave("d").is_some());
+        assert!(queues.leave("d").is_none());
+        assert!(!queues.is_waiting("mk4"));
+        assert_eq!(queues.position("b"), Some(2));
+    }
+}
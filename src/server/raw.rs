@@ -1,6 +1,11 @@
 use dropshot::{Body, HttpCodedResponse, HttpError};
 use http::{Response, StatusCode};
 
+use super::{
+    job_events::EventStreamBody,
+    stream::{MjpegBody, MJPEG_BOUNDARY},
+};
+
 /// Return an HTTP Response OK, but with CORS.
 pub struct RawResponseOk(pub String);
 
@@ -20,3 +25,88 @@ impl From<RawResponseOk> for Result<Response<Body>, HttpError> {
             .body(Body::from(rrok.0))?)
     }
 }
+
+/// Return a file as an HTTP Response OK, with CORS.
+pub struct FileResponseOk(pub Vec<u8>);
+
+impl HttpCodedResponse for FileResponseOk {
+    type Body = Vec<u8>;
+
+    const STATUS_CODE: StatusCode = StatusCode::OK;
+    const DESCRIPTION: &'static str = "successful operation";
+}
+
+impl From<FileResponseOk> for Result<Response<Body>, HttpError> {
+    fn from(frok: FileResponseOk) -> Result<Response<Body>, HttpError> {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/octet-stream")
+            .header("access-control-allow-origin", "*")
+            .body(Body::from(frok.0))?)
+    }
+}
+
+/// Return a JPEG image as an HTTP Response OK, with CORS.
+pub struct JpegResponseOk(pub Vec<u8>);
+
+impl HttpCodedResponse for JpegResponseOk {
+    type Body = Vec<u8>;
+
+    const STATUS_CODE: StatusCode = StatusCode::OK;
+    const DESCRIPTION: &'static str = "successful operation";
+}
+
+impl From<JpegResponseOk> for Result<Response<Body>, HttpError> {
+    fn from(jrok: JpegResponseOk) -> Result<Response<Body>, HttpError> {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "image/jpeg")
+            .header("access-control-allow-origin", "*")
+            .body(Body::from(jrok.0))?)
+    }
+}
+
+/// Return a camera's live video as an MJPEG stream, with CORS.
+pub struct MjpegResponseOk(pub MjpegBody);
+
+impl HttpCodedResponse for MjpegResponseOk {
+    type Body = Vec<u8>;
+
+    const STATUS_CODE: StatusCode = StatusCode::OK;
+    const DESCRIPTION: &'static str = "successful operation";
+}
+
+impl From<MjpegResponseOk> for Result<Response<Body>, HttpError> {
+    fn from(mrok: MjpegResponseOk) -> Result<Response<Body>, HttpError> {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                http::header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={}", MJPEG_BOUNDARY),
+            )
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .header("access-control-allow-origin", "*")
+            .body(Body::wrap(mrok.0))?)
+    }
+}
+
+/// Return server-sent events as an HTTP Response OK, with CORS.
+pub struct EventStreamResponseOk(pub EventStreamBody);
+
+impl HttpCodedResponse for EventStreamResponseOk {
+    type Body = Vec<u8>;
+
+    const STATUS_CODE: StatusCode = StatusCode::OK;
+    const DESCRIPTION: &'static str = "successful operation";
+}
+
+impl From<EventStreamResponseOk> for Result<Response<Body>, HttpError> {
+    fn from(esrok: EventStreamResponseOk) -> Result<Response<Body>, HttpError> {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "text/event-stream")
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .header("access-control-allow-origin", "*")
+            .body(Body::wrap(esrok.0))?)
+    }
+}
@@ -0,0 +1,223 @@
+//! Machines registered at runtime, rather than in the config file, such as
+//! a printer brought into the lab for a day.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::{Context, Event, FailureReason};
+use crate::{noop, slicer, Machine, MachineMakeModel, MachineType, Volume};
+
+/// How often a registration which has expired is checked again, while its
+/// machine is still busy with a job.
+const BUSY_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The kinds of machine which can be registered at runtime. These are
+/// configured as they would be in the config file.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum RegisteredMachineConfig {
+    #[cfg(feature = "moonraker")]
+    Moonraker(crate::moonraker::Config),
+    Noop(noop::Config),
+}
+
+impl RegisteredMachineConfig {
+    fn build(self, id: &str) -> Result<Machine> {
+        match self {
+            #[cfg(feature = "moonraker")]
+            Self::Moonraker(config) => {
+                if config.endpoint.is_empty() || !config.instances.is_empty() {
+                    anyhow::bail!("a registered moonraker machine needs a single `endpoint`");
+                }
+                let slicer = config.slicer.load()?;
+                let (manufacturer, model) = config.variant.get_manufacturer_model();
+                Ok(Machine::new(
+                    crate::moonraker::Client::new(
+                        &config,
+                        MachineMakeModel {
+                            manufacturer,
+                            model,
+                            serial: None,
+                        },
+                    )?
+                    .with_machine_id(id),
+                    slicer,
+                ))
+            }
+            Self::Noop(config) => Ok(Machine::new(
+                noop::Noop::new(
+                    config,
+                    MachineMakeModel {
+                        manufacturer: Some("Zoo Corporation".to_owned()),
+                        model: Some("Null Machine".to_owned()),
+                        serial: None,
+                    },
+                    MachineType::FusedDeposition,
+                    Some(Volume {
+                        width: 500.0,
+                        depth: 600.0,
+                        height: 700.0,
+                    }),
+                ),
+                slicer::noop::Slicer::new(),
+            )),
+        }
+    }
+}
+
+/// A machine to register with the server at runtime.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct MachineRegistrationParameters {
+    /// The id to register the machine as. This must not already be in use.
+    pub id: String,
+
+    /// User-facing name, such as `Loaner Voron`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// Where the machine physically is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    /// Labels to select the machine by.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// Remove the machine again after this many seconds. Machines in the
+    /// middle of a job are removed once it's finished. If not given, the
+    /// machine stays until the server is restarted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+
+    /// The machine's configuration, as it would be given for a machine in
+    /// the config file, such as `{"type": "Moonraker", "endpoint":
+    /// "http://192.168.1.20", ...}`. `Moonraker` and `Noop` machines can be
+    /// registered.
+    pub config: serde_json::Value,
+}
+
+/// A machine registered at runtime.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct MachineRegistration {
+    /// The machine id.
+    pub id: String,
+
+    /// When the machine was registered.
+    pub registered_at: DateTime<Utc>,
+
+    /// When the machine will be removed, if ever.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// All machines registered at runtime. These aren't kept across restarts.
+#[derive(Default)]
+pub struct Registrations {
+    registrations: RwLock<BTreeMap<String, MachineRegistration>>,
+}
+
+impl Registrations {
+    /// Return the registration of the machine `id`, if it was registered at
+    /// runtime.
+    pub async fn get(&self, id: &str) -> Option<MachineRegistration> {
+        self.registrations.read().await.get(id).cloned()
+    }
+
+    /// Forget the registration of the machine `id`, such as when it's
+    /// removed.
+    pub async fn remove(&self, id: &str) -> Option<MachineRegistration> {
+        self.registrations.write().await.remove(id)
+    }
+}
+
+/// Register a machine with the server, removing it again once its TTL (if
+/// any) is up.
+pub(crate) async fn register(ctx: &Arc<Context>, params: MachineRegistrationParameters) -> Result<MachineRegistration> {
+    if params.id.is_empty() {
+        anyhow::bail!("the machine id must not be empty");
+    }
+    let config: RegisteredMachineConfig = serde_json::from_value(params.config)?;
+    let mut machine = config.build(&params.id)?;
+    machine.set_display_name(params.display_name);
+    machine.set_location(params.location);
+    machine.set_labels(params.labels);
+    machine.load_serial().await?;
+
+    let registered_at = Utc::now();
+    let registration = MachineRegistration {
+        id: params.id.clone(),
+        registered_at,
+        expires_at: params
+            .ttl_seconds
+            .map(|ttl| registered_at + chrono::Duration::seconds(ttl.min(i64::MAX as u64) as i64)),
+    };
+
+    {
+        let mut machines = ctx.machines.write().await;
+        if machines.contains_key(&params.id) {
+            anyhow::bail!("a machine with the id {:?} already exists", params.id);
+        }
+        machines.insert(params.id.clone(), RwLock::new(machine));
+    }
+    ctx.registrations
+        .registrations
+        .write()
+        .await
+        .insert(params.id.clone(), registration.clone());
+    tracing::info!(id = params.id, expires_at = ?registration.expires_at, "registered machine");
+
+    if let Some(ttl) = params.ttl_seconds {
+        spawn_expiry(ctx.clone(), registration.clone(), Duration::from_secs(ttl));
+    }
+    Ok(registration)
+}
+
+/// Remove the machine registered by `registration` once `ttl` is up, unless
+/// it's been removed (or registered again) in the meantime.
+fn spawn_expiry(ctx: Arc<Context>, registration: MachineRegistration, ttl: Duration) {
+    tokio::spawn(async move {
+        tokio::time::sleep(ttl).await;
+        loop {
+            if ctx.registrations.get(&registration.id).await.as_ref() != Some(&registration) {
+                return;
+            }
+            if ctx.jobs.current(&registration.id).await.is_none() {
+                break;
+            }
+            tokio::time::sleep(BUSY_RECHECK_INTERVAL).await;
+        }
+
+        deregister(&ctx, &registration.id, true).await;
+        tracing::info!(id = registration.id, "registration expired, removed machine");
+    });
+}
+
+/// Remove the machine `id`, if it was registered at runtime, stopping its
+/// background tasks. Its current job, if any, is cancelled, though the
+/// machine itself is left alone.
+pub(crate) async fn deregister(ctx: &Context, id: &str, expired: bool) -> Option<MachineRegistration> {
+    let registration = ctx.registrations.remove(id).await?;
+    // Dropping the machine stops its background tasks.
+    drop(ctx.machines.write().await.remove(id));
+    ctx.log_levels.set(id, None);
+
+    let cancelled_job_id = match ctx.jobs.current(id).await {
+        Some(job) => {
+            ctx.jobs
+                .fail(&job.id, FailureReason::UserCancel, "the machine was removed")
+                .await;
+            Some(job.id)
+        }
+        None => None,
+    };
+    ctx.events.emit(Event::MachineRemoved {
+        machine_id: id.to_owned(),
+        expired,
+        cancelled_job_id,
+    });
+    Some(registration)
+}
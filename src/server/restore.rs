@@ -0,0 +1,22 @@
+//! Restarting jobs which were still queued when the server last stopped.
+
+use std::sync::Arc;
+
+use super::Context;
+
+/// Restore the jobs which were still queued when the server last stopped,
+/// putting them back in their machines' queues, in the order they were
+/// waiting, to be sent on one at a time once each machine is available.
+pub async fn spawn_restored_jobs(ctx: Arc<Context>) {
+    let restored = ctx.jobs.restore().await;
+    for machine_jobs in restored.chunk_by(|a, b| a.job.machine_id == b.job.machine_id) {
+        tracing::info!(
+            machine_id = machine_jobs[0].job.machine_id,
+            jobs = machine_jobs.len(),
+            "restored queued jobs"
+        );
+        for queued in machine_jobs {
+            ctx.jobs.join_queue(queued.clone()).await;
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Retrying jobs which fail to be sent to their machine for reasons which
+//! may well not happen again, such as the machine not answering in time.
+
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::Jobs;
+use crate::{Machine, MachineState, SlicedFile, SlicerConfiguration};
+
+/// How sending a job to its machine is retried, if it fails in a way which
+/// might not happen again (such as timing out).
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct DispatchRetry {
+    /// How many times to try sending a job, in all. 1 never retries.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    /// How long to wait before the first retry, in seconds. This doubles
+    /// with each retry after.
+    #[serde(default = "default_initial_backoff_seconds")]
+    pub initial_backoff_seconds: f64,
+
+    /// Longest to wait between attempts, in seconds.
+    #[serde(default = "default_max_backoff_seconds")]
+    pub max_backoff_seconds: f64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_initial_backoff_seconds() -> f64 {
+    5.0
+}
+
+fn default_max_backoff_seconds() -> f64 {
+    60.0
+}
+
+impl Default for DispatchRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            initial_backoff_seconds: default_initial_backoff_seconds(),
+            max_backoff_seconds: default_max_backoff_seconds(),
+        }
+    }
+}
+
+impl DispatchRetry {
+    /// How long to wait after the failed `attempt` (counting from 1) before
+    /// trying again.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let seconds = self.initial_backoff_seconds * 2f64.powi(attempt.saturating_sub(1).min(16) as i32);
+        Duration::from_secs_f64(seconds.min(self.max_backoff_seconds).max(0.0))
+    }
+}
+
+/// Return whether an error looks like it might not happen again, such as
+/// a timeout, or the machine refusing the connection.
+pub(crate) fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+            );
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout() || e.is_connect();
+        }
+        #[cfg(feature = "bambu")]
+        if cause.is::<bambulabs::client::TransientError>() {
+            return true;
+        }
+        cause.is::<tokio::time::error::Elapsed>()
+    })
+}
+
+/// Send a sliced job to the machine `machine_id`, retrying failures which
+/// look transient according to `policy`, and recording each failed attempt
+/// in the job's log. The machine is only held on to for each attempt, not
+/// while waiting to try again, so it can be used (or the job cancelled) in
+/// the meantime.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn dispatch(
+    jobs: &Jobs,
+    policy: &DispatchRetry,
+    job_id: &str,
+    machines: &RwLock<HashMap<String, RwLock<Machine>>>,
+    machine_id: &str,
+    job_name: &str,
+    mut sliced: SlicedFile,
+    slicer_configuration: &SlicerConfiguration,
+) -> anyhow::Result<()> {
+    let mut attempt = 1;
+    let mut previous: Option<anyhow::Error> = None;
+    loop {
+        // Sending the file uses it up, so keep a copy to try again with.
+        let spare = if attempt < policy.max_attempts {
+            match sliced.duplicate().await {
+                Ok(spare) => Some(spare),
+                Err(e) => {
+                    tracing::warn!(id = job_id, error = format!("{:?}", e), "failed to copy sliced file");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let retrying = previous.is_some();
+        let e = match try_dispatch(machines, machine_id, retrying, job_name, sliced, slicer_configuration).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => e,
+            Err(reason) => {
+                let e = previous.unwrap_or_else(|| anyhow::anyhow!("{}", reason));
+                jobs.log(
+                    job_id,
+                    format!(
+                        "attempt {} of {} to send the job to the machine wasn't made ({}): {}",
+                        attempt, policy.max_attempts, reason, e
+                    ),
+                )
+                .await;
+                return Err(e);
+            }
+        };
+
+        let retry = match spare {
+            None => Err("no attempts left".to_owned()),
+            Some(_) if !is_transient(&e) => Err("not retrying, since the error isn't transient".to_owned()),
+            Some(spare) => Ok(spare),
+        };
+
+        match retry {
+            Ok(spare) => {
+                let backoff = policy.backoff(attempt);
+                tracing::warn!(
+                    id = job_id,
+                    attempt = attempt,
+                    error = format!("{:?}", e),
+                    "failed to send job, retrying"
+                );
+                jobs.log(
+                    job_id,
+                    format!(
+                        "attempt {} of {} to send the job to the machine failed, retrying in {}s: {}",
+                        attempt,
+                        policy.max_attempts,
+                        backoff.as_secs_f64(),
+                        e
+                    ),
+                )
+                .await;
+                tokio::time::sleep(backoff).await;
+                sliced = spare;
+                attempt += 1;
+                previous = Some(e);
+            }
+            Err(reason) => {
+                jobs.log(
+                    job_id,
+                    format!(
+                        "attempt {} of {} to send the job to the machine failed ({}): {}",
+                        attempt, policy.max_attempts, reason, e
+                    ),
+                )
+                .await;
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Make one attempt at sending a job to the machine `machine_id`, holding
+/// on to it only for as long as that takes. When `retrying`, the attempt is
+/// only made if the machine is still idle, so a job it's started on
+/// (whatever it told us) isn't started twice. Returns why the attempt
+/// wasn't made, if it wasn't.
+async fn try_dispatch(
+    machines: &RwLock<HashMap<String, RwLock<Machine>>>,
+    machine_id: &str,
+    retrying: bool,
+    job_name: &str,
+    sliced: SlicedFile,
+    slicer_configuration: &SlicerConfiguration,
+) -> Result<anyhow::Result<()>, String> {
+    let machines = machines.read().await;
+    let Some(machine) = machines.get(machine_id) else {
+        return Err("the machine went away".to_owned());
+    };
+    let mut machine = machine.write().await;
+    if retrying {
+        match machine.state().await {
+            Ok(MachineState::Idle) => {}
+            Ok(state) => return Err(format!("not retrying, since the machine is {:?}", state)),
+            Err(e) => return Err(format!("not retrying, since the machine's state is unknown: {}", e)),
+        }
+    }
+    Ok(machine.dispatch(job_name, sliced, slicer_configuration).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = DispatchRetry::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(5));
+        assert_eq!(policy.backoff(2), Duration::from_secs(10));
+        assert_eq!(policy.backoff(4), Duration::from_secs(40));
+        assert_eq!(policy.backoff(5), Duration::from_secs(60));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let timeout = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert!(is_transient(&timeout));
+        assert!(is_transient(&timeout.context("failed to upload")));
+
+        let denied = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert!(!is_transient(&denied));
+        assert!(!is_transient(&anyhow::anyhow!("sliced file format is not supported")));
+    }
+}
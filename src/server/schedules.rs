@@ -0,0 +1,349 @@
+//! Recurring print jobs, started on a cron schedule.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use super::{
+    cron::CronSchedule,
+    endpoints::{find_machine, start_print_job, FileAttachment},
+    Context,
+};
+use crate::{MachineState, SlicerConfiguration, StlUnits};
+
+/// How often the scheduler checks for schedules that are due.
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of upcoming runs returned with each schedule.
+const NEXT_RUN_PREVIEW: usize = 5;
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Which machine(s) a scheduled job may be sent to.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum MachineSelector {
+    /// A specific machine, by id or display name.
+    Machine {
+        /// The machine id (or display name).
+        id: String,
+    },
+
+    /// The first idle machine out of a set of machines.
+    AnyIdle {
+        /// Machine ids (or display names) to pick from. If empty, any
+        /// machine may be used.
+        #[serde(default)]
+        machine_ids: Vec<String>,
+    },
+}
+
+/// User-provided settings for a schedule.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ScheduleParameters {
+    /// Name of this schedule.
+    pub name: String,
+
+    /// Five-field cron expression (`minute hour day-of-month month
+    /// day-of-week`), evaluated in UTC. For example, `0 9 * * mon` runs
+    /// every Monday at 09:00.
+    pub cron: String,
+
+    /// Which machine(s) the job may be sent to.
+    pub target: MachineSelector,
+
+    /// The name for each job this schedule starts.
+    pub job_name: String,
+
+    /// Units the STL file is in. If not given, parts so small they're
+    /// likely in inches are refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<StlUnits>,
+
+    /// Requested design-specific slicer configurations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slicer_configuration: Option<SlicerConfiguration>,
+
+    /// Print even if the loaded filament isn't the material the slicer
+    /// profile expects.
+    #[serde(default)]
+    pub override_material: bool,
+
+    /// Start jobs without waiting for the machine's chamber to warm up.
+    #[serde(default)]
+    pub override_chamber_preheat: bool,
+
+    /// If false, the schedule is kept, but doesn't start any jobs.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+/// A recurring print job.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct Schedule {
+    /// The schedule id.
+    pub id: String,
+
+    /// Settings for this schedule.
+    pub parameters: ScheduleParameters,
+
+    /// Name of the design file printed by this schedule.
+    pub file_name: String,
+
+    /// When the schedule was created.
+    pub created_at: DateTime<Utc>,
+
+    /// When the schedule last tried to start a job.
+    pub last_run_at: Option<DateTime<Utc>>,
+
+    /// The id of the last job this schedule started.
+    pub last_job_id: Option<String>,
+
+    /// Why the last run failed to start a job, if it did.
+    pub last_error: Option<String>,
+
+    /// The next few times this schedule will run. Empty if the schedule is
+    /// disabled.
+    pub next_runs: Vec<DateTime<Utc>>,
+}
+
+struct ScheduleEntry {
+    schedule: Schedule,
+    cron: CronSchedule,
+    artifact: PathBuf,
+    next_run: Option<DateTime<Utc>>,
+}
+
+impl ScheduleEntry {
+    fn to_schedule(&self) -> Schedule {
+        let mut schedule = self.schedule.clone();
+        if schedule.parameters.enabled {
+            schedule.next_runs = self.cron.upcoming(Utc::now(), NEXT_RUN_PREVIEW);
+        }
+        schedule
+    }
+}
+
+/// All schedules known to the server.
+pub struct Schedules {
+    schedules: RwLock<HashMap<String, ScheduleEntry>>,
+    artifact_dir: PathBuf,
+}
+
+impl Default for Schedules {
+    fn default() -> Self {
+        Self::new(crate::file::spool_dir().join("machine-api-schedules"))
+    }
+}
+
+impl Schedules {
+    /// Create an empty set of schedules, which will store design files in
+    /// `artifact_dir`.
+    pub fn new(artifact_dir: PathBuf) -> Self {
+        Self {
+            schedules: RwLock::new(HashMap::new()),
+            artifact_dir,
+        }
+    }
+
+    /// Register a new schedule, storing its design file so it can be
+    /// printed on every run.
+    pub(crate) async fn create(&self, parameters: ScheduleParameters, file: FileAttachment) -> Result<Schedule> {
+        let cron = CronSchedule::parse(&parameters.cron)?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let file_name = crate::sanitize_job_name(file.file_name.as_deref().unwrap_or("file"));
+
+        tokio::fs::create_dir_all(&self.artifact_dir).await?;
+        let artifact = self.artifact_dir.join(format!("{}_{}", id, file_name));
+        tokio::fs::write(&artifact, &file.content).await?;
+
+        let entry = ScheduleEntry {
+            next_run: cron.next_after(Utc::now()),
+            schedule: Schedule {
+                id: id.clone(),
+                parameters,
+                file_name,
+                created_at: Utc::now(),
+                last_run_at: None,
+                last_job_id: None,
+                last_error: None,
+                next_runs: vec![],
+            },
+            cron,
+            artifact,
+        };
+        let schedule = entry.to_schedule();
+        self.schedules.write().await.insert(id, entry);
+        Ok(schedule)
+    }
+
+    /// Get a schedule by id.
+    pub async fn get(&self, id: &str) -> Option<Schedule> {
+        self.schedules.read().await.get(id).map(ScheduleEntry::to_schedule)
+    }
+
+    /// List all schedules, oldest first.
+    pub async fn list(&self) -> Vec<Schedule> {
+        let mut schedules: Vec<Schedule> = self
+            .schedules
+            .read()
+            .await
+            .values()
+            .map(ScheduleEntry::to_schedule)
+            .collect();
+        schedules.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        schedules
+    }
+
+    /// Replace the settings of a schedule. The design file is kept. Returns
+    /// `Ok(None)` if there is no such schedule.
+    pub async fn update(&self, id: &str, parameters: ScheduleParameters) -> Result<Option<Schedule>> {
+        let cron = CronSchedule::parse(&parameters.cron)?;
+        let mut schedules = self.schedules.write().await;
+        let Some(entry) = schedules.get_mut(id) else {
+            return Ok(None);
+        };
+
+        entry.next_run = cron.next_after(Utc::now());
+        entry.cron = cron;
+        entry.schedule.parameters = parameters;
+        Ok(Some(entry.to_schedule()))
+    }
+
+    /// Remove a schedule, and its stored design file.
+    pub async fn delete(&self, id: &str) -> Option<Schedule> {
+        let entry = self.schedules.write().await.remove(id)?;
+        if let Err(e) = tokio::fs::remove_file(&entry.artifact).await {
+            tracing::warn!(
+                id = id,
+                error = format!("{:?}", e),
+                "failed to remove schedule artifact"
+            );
+        }
+        Some(entry.to_schedule())
+    }
+
+    /// Take the schedules that are due to run, moving each one on to its
+    /// next run time.
+    async fn take_due(&self, now: DateTime<Utc>) -> Vec<(String, ScheduleParameters, String, PathBuf)> {
+        let mut due = vec![];
+        for (id, entry) in self.schedules.write().await.iter_mut() {
+            let is_due = matches!(entry.next_run, Some(next_run) if next_run <= now);
+            if !entry.schedule.parameters.enabled || !is_due {
+                continue;
+            }
+            entry.next_run = entry.cron.next_after(now);
+            due.push((
+                id.clone(),
+                entry.schedule.parameters.clone(),
+                entry.schedule.file_name.clone(),
+                entry.artifact.clone(),
+            ));
+        }
+        due
+    }
+
+    async fn record_run(&self, id: &str, result: Result<String, String>) {
+        let mut schedules = self.schedules.write().await;
+        let Some(entry) = schedules.get_mut(id) else {
+            return;
+        };
+        entry.schedule.last_run_at = Some(Utc::now());
+        match result {
+            Ok(job_id) => {
+                entry.schedule.last_job_id = Some(job_id);
+                entry.schedule.last_error = None;
+            }
+            Err(error) => entry.schedule.last_error = Some(error),
+        }
+    }
+}
+
+/// Pick the machine a scheduled job should go to, if one is available.
+async fn select_machine(ctx: &Context, target: &MachineSelector) -> Option<String> {
+    let candidates = match target {
+        MachineSelector::Machine { id } => return Some(id.clone()),
+        MachineSelector::AnyIdle { machine_ids } if machine_ids.is_empty() => {
+            ctx.machines.read().await.keys().cloned().collect()
+        }
+        MachineSelector::AnyIdle { machine_ids } => machine_ids.clone(),
+    };
+
+    let machines = ctx.machines.read().await;
+    for candidate in candidates {
+        let (id, machine) = match find_machine(&machines, &candidate, None).await {
+            Ok(Some(found)) => found,
+            Ok(None) => continue,
+            Err(ambiguous) => {
+                tracing::warn!(error = ambiguous.to_string(), "skipping ambiguous machine");
+                continue;
+            }
+        };
+        let machine = machine.read().await;
+        if machine.reservation().is_none() && matches!(machine.state().await, Ok(MachineState::Idle)) {
+            return Some(id.clone());
+        }
+    }
+    None
+}
+
+async fn run_schedule(
+    ctx: &Context,
+    parameters: &ScheduleParameters,
+    file_name: String,
+    artifact: &Path,
+) -> Result<String, String> {
+    let Some(machine_id) = select_machine(ctx, &parameters.target).await else {
+        return Err("no idle machine matched the schedule's target".to_owned());
+    };
+
+    let content = tokio::fs::read(artifact)
+        .await
+        .map_err(|e| format!("failed to read design file: {:?}", e))?;
+
+    start_print_job(
+        ctx,
+        &machine_id,
+        &parameters.job_name,
+        FileAttachment {
+            file_name: Some(file_name),
+            content: content.into(),
+        },
+        parameters.units,
+        &parameters.slicer_configuration.unwrap_or_default(),
+        parameters.override_material,
+        parameters.override_chamber_preheat,
+    )
+    .await
+    .map_err(|e| e.external_message)
+}
+
+/// Start the background task which starts jobs for schedules as they come
+/// due.
+pub fn spawn_scheduler(ctx: Arc<Context>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(TICK_INTERVAL).await;
+
+            for (id, parameters, file_name, artifact) in ctx.schedules.take_due(Utc::now()).await {
+                tracing::info!(id = %id, name = %parameters.name, "running scheduled job");
+                let result = run_schedule(&ctx, &parameters, file_name, &artifact).await;
+                if let Err(error) = &result {
+                    tracing::warn!(id = %id, error = %error, "scheduled job did not start");
+                }
+                ctx.schedules.record_run(&id, result).await;
+            }
+        }
+    });
+}
@@ -0,0 +1,134 @@
+//! Limits on how many jobs can be active at once on each type of machine,
+//! such as to keep several Bambu uploads at once from saturating the WiFi.
+//! Jobs over the limit wait their turn, first come first served.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How many jobs may be active at once on each type of machine (as in its
+/// config's `type`, such as `Bambu`), from when they start slicing until
+/// they've been sent to the machine. Types without a limit aren't limited.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(transparent)]
+pub struct ConcurrencyLimits(pub HashMap<String, usize>);
+
+/// The jobs active on, and waiting for, one type of machine.
+#[derive(Debug, Default)]
+struct Queue {
+    active: usize,
+    waiting: VecDeque<String>,
+}
+
+/// Which jobs hold, or are waiting for, a slot on each type of machine.
+#[derive(Debug, Default)]
+pub(crate) struct JobSlots {
+    limits: ConcurrencyLimits,
+    queues: Mutex<HashMap<String, Queue>>,
+}
+
+impl JobSlots {
+    /// Create a new `JobSlots`, with `limits` on each type of machine.
+    pub(crate) fn new(limits: ConcurrencyLimits) -> Self {
+        Self {
+            limits,
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return whether jobs on this type of machine are limited at all.
+    pub(crate) fn is_limited(&self, machine_type: &str) -> bool {
+        self.limits.0.contains_key(machine_type)
+    }
+
+    /// Join the back of the queue for a type of machine.
+    pub(crate) fn join(&self, machine_type: &str, id: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        queues
+            .entry(machine_type.to_owned())
+            .or_default()
+            .waiting
+            .push_back(id.to_owned());
+    }
+
+    /// Take a slot for the job, if it's at the front of the queue and one
+    /// is free.
+    pub(crate) fn try_take(&self, machine_type: &str, id: &str) -> bool {
+        let limit = self.limits.0.get(machine_type).copied().unwrap_or(usize::MAX);
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(machine_type) else {
+            return false;
+        };
+        if queue.active >= limit || queue.waiting.front().map(String::as_str) != Some(id) {
+            return false;
+        }
+        queue.waiting.pop_front();
+        queue.active += 1;
+        true
+    }
+
+    /// Leave the queue without taking a slot, such as when the job's
+    /// request is dropped while it's waiting.
+    pub(crate) fn leave(&self, machine_type: &str, id: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(machine_type) {
+            queue.waiting.retain(|waiting| waiting != id);
+        }
+    }
+
+    /// Give back a slot taken with [JobSlots::try_take].
+    pub(crate) fn release(&self, machine_type: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(machine_type) {
+            queue.active = queue.active.saturating_sub(1);
+        }
+    }
+
+    /// Where a job is in the queue for its type of machine, if it's
+    /// waiting for a slot, counting from 1.
+    pub(crate) fn position(&self, id: &str) -> Option<usize> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .values()
+            .find_map(|queue| queue.waiting.iter().position(|waiting| waiting == id))
+            .map(|position| position + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots() {
+        let slots = JobSlots::new(ConcurrencyLimits(HashMap::from([("Bambu".to_owned(), 2)])));
+        assert!(slots.is_limited("Bambu"));
+        assert!(!slots.is_limited("Moonraker"));
+
+        for id in ["a", "b", "c", "d"] {
+            slots.join("Bambu", id);
+        }
+        assert_eq!(slots.position("a"), Some(1));
+        assert_eq!(slots.position("d"), Some(4));
+
+        // First come, first served.
+        assert!(!slots.try_take("Bambu", "b"));
+        assert!(slots.try_take("Bambu", "a"));
+        assert!(slots.try_take("Bambu", "b"));
+        assert_eq!(slots.position("a"), None);
+        assert_eq!(slots.position("c"), Some(1));
+
+        // Both slots are taken.
+        assert!(!slots.try_take("Bambu", "c"));
+
+        // Leaving the queue moves everyone behind up.
+        slots.leave("Bambu", "c");
+        assert_eq!(slots.position("d"), Some(1));
+
+        slots.release("Bambu");
+        assert!(slots.try_take("Bambu", "d"));
+    }
+}
@@ -0,0 +1,166 @@
+//! Live video from machines' cameras, re-published as MJPEG
+//! (`multipart/x-mixed-replace`), which browsers show in an `<img>` tag
+//! like any other image.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use super::Context;
+use crate::{CameraStream, Machine};
+
+/// What separates the frames of the stream.
+pub const MJPEG_BOUNDARY: &str = "machine-api-frame";
+
+/// How long to wait for a camera's first frame, before telling the client
+/// the camera is unavailable.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often cameras which only take stills are asked for one.
+const STILL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many frames may be waiting for a slow client, before the camera is
+/// read no faster than the client takes them.
+const FRAME_CAPACITY: usize = 4;
+
+/// Where a machine's camera's frames come from.
+pub(crate) enum Frames {
+    /// A camera which streams video.
+    Video(CameraStream),
+
+    /// A camera which only takes stills, asked for one every so often.
+    Stills { ctx: Arc<Context>, machine_id: String },
+}
+
+impl Frames {
+    /// Find where to read `machine`'s camera from.
+    pub(crate) async fn open(ctx: &Arc<Context>, machine_id: &str, machine: &Machine) -> Result<Self> {
+        Ok(match machine.camera_stream().await? {
+            Some(stream) => Self::Video(stream),
+            None => Self::Stills {
+                ctx: ctx.clone(),
+                machine_id: machine_id.to_owned(),
+            },
+        })
+    }
+
+    /// Read the first frame from `machine`'s camera, or `None` if it turns
+    /// out not to have one.
+    pub(crate) async fn first(&mut self, machine: &Machine) -> Result<Option<Vec<u8>>> {
+        let first = async {
+            match self {
+                Self::Video(stream) => stream
+                    .next_frame()
+                    .await?
+                    .context("the camera stream ended straight away")
+                    .map(Some),
+                Self::Stills { .. } => machine.snapshot().await,
+            }
+        };
+        tokio::time::timeout(FIRST_FRAME_TIMEOUT, first)
+            .await
+            .context("timed out waiting for the camera")?
+    }
+
+    /// Wait for the next frame, or `None` once there are no more.
+    async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Video(stream) => stream.next_frame().await,
+            Self::Stills { ctx, machine_id } => {
+                tokio::time::sleep(STILL_INTERVAL).await;
+                let machines = ctx.machines.read().await;
+                let Some(machine) = machines.get(machine_id.as_str()) else {
+                    return Ok(None);
+                };
+                let still = machine.read().await.snapshot().await;
+                still
+            }
+        }
+    }
+}
+
+/// Start sending `first`, then the rest of `frames`, to a client watching
+/// the machine `machine_id`'s camera, until it goes away or the camera
+/// stops.
+pub(crate) fn spawn(machine_id: String, first: Vec<u8>, mut frames: Frames) -> MjpegBody {
+    let (sender, receiver) = mpsc::channel(FRAME_CAPACITY);
+    tokio::spawn(async move {
+        let mut frame = first;
+        loop {
+            if sender.send(part(&frame)).await.is_err() {
+                // The client went away.
+                break;
+            }
+            frame = match frames.next().await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    tracing::warn!(id = machine_id, error = format!("{:?}", e), "camera stream failed");
+                    break;
+                }
+            };
+        }
+        tracing::debug!(id = machine_id, "camera stream ended");
+    });
+    MjpegBody { receiver }
+}
+
+/// Wrap a JPEG frame as a part of the stream.
+fn part(frame: &[u8]) -> Bytes {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        MJPEG_BOUNDARY,
+        frame.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(frame);
+    part.extend_from_slice(b"\r\n");
+    part.into()
+}
+
+/// The body of an MJPEG response, sending parts as they're read from the
+/// camera.
+pub struct MjpegBody {
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl http_body::Body for MjpegBody {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Bytes>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|part| part.map(|part| Ok(http_body::Frame::data(part))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part() {
+        assert_eq!(
+            part(&[0xff, 0xd8, 0xff, 0xd9]),
+            Bytes::from(
+                [
+                    b"--machine-api-frame\r\nContent-Type: image/jpeg\r\nContent-Length: 4\r\n\r\n".as_slice(),
+                    &[0xff, 0xd8, 0xff, 0xd9],
+                    b"\r\n",
+                ]
+                .concat()
+            )
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Whether handlers keep running when their client disconnects. Cheap
+//! reads are cancelled, so a client giving up doesn't leave work running
+//! for nobody, but handlers which change anything (especially those which
+//! take several steps on a machine, such as starting a print) run to the
+//! end, so a dropped connection can't leave a machine half-way through.
+
+use std::{future::Future, sync::Arc};
+
+use dropshot::{HttpError, RequestContext};
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use super::Context;
+
+/// What happens to a handler when its client disconnects.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskMode {
+    /// The handler is cancelled.
+    CancelOnDisconnect,
+
+    /// The handler runs to the end, and its response is thrown away.
+    Detached,
+}
+
+/// What happens to each group of handlers when their client disconnects.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TaskModes {
+    /// Handlers which don't change anything, including slicing and
+    /// analyzing uploaded files.
+    #[serde(default = "default_reads")]
+    pub reads: TaskMode,
+
+    /// Handlers which change anything, such as starting or cancelling a
+    /// print, controlling a machine, or changing a schedule. These can
+    /// only be cancelled if reads are too.
+    #[serde(default = "default_mutations")]
+    pub mutations: TaskMode,
+}
+
+fn default_reads() -> TaskMode {
+    TaskMode::CancelOnDisconnect
+}
+
+fn default_mutations() -> TaskMode {
+    TaskMode::Detached
+}
+
+impl Default for TaskModes {
+    fn default() -> Self {
+        Self {
+            reads: default_reads(),
+            mutations: default_mutations(),
+        }
+    }
+}
+
+impl TaskModes {
+    /// Return the task mode for dropshot to run every handler in. Handlers
+    /// are only cancelled by dropshot if reads are to be; mutations are
+    /// detached by [mutate] when they need to be.
+    pub(crate) fn dropshot(&self) -> dropshot::HandlerTaskMode {
+        match self.reads {
+            TaskMode::CancelOnDisconnect => dropshot::HandlerTaskMode::CancelOnDisconnect,
+            TaskMode::Detached => dropshot::HandlerTaskMode::Detached,
+        }
+    }
+}
+
+/// Run the work of a handler which changes something, detached from the
+/// request if mutations are to run to the end when their client
+/// disconnects.
+pub(crate) async fn mutate<T, F, FutureT>(rqctx: &RequestContext<Arc<Context>>, work: F) -> Result<T, HttpError>
+where
+    F: FnOnce(Arc<Context>) -> FutureT,
+    FutureT: Future<Output = Result<T, HttpError>> + Send + 'static,
+    T: Send + 'static,
+{
+    let ctx = rqctx.context().clone();
+    match ctx.task_modes.mutations {
+        TaskMode::CancelOnDisconnect => work(ctx).await,
+        // The work goes on in the handler's span, so it's traced as part of
+        // the request (and with the machine and job it's for).
+        TaskMode::Detached => tokio::spawn(work(ctx).in_current_span()).await.map_err(|e| {
+            tracing::error!(error = format!("{:?}", e), "handler panicked");
+            HttpError::for_internal_error(format!("{:?}", e))
+        })?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let modes: TaskModes = toml::from_str("").unwrap();
+        assert_eq!(modes, TaskModes::default());
+        assert!(matches!(
+            modes.dropshot(),
+            dropshot::HandlerTaskMode::CancelOnDisconnect
+        ));
+
+        let modes: TaskModes = toml::from_str(r#"reads = "detached""#).unwrap();
+        assert_eq!(modes.reads, TaskMode::Detached);
+        assert_eq!(modes.mutations, TaskMode::Detached);
+        assert!(matches!(modes.dropshot(), dropshot::HandlerTaskMode::Detached));
+    }
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use super::{orca, prusa, AnySlicer};
+use super::{orca, preform, prusa, remote, AnySlicer};
 
 /// Standard slicer config -- as used by the machine-api server and any
 /// other consumers.
@@ -21,6 +21,28 @@ pub enum Config {
         /// Use the provided `.ini` Slicer config.
         config: String,
     },
+
+    /// Use Formlabs PreForm.
+    Preform {
+        /// Formlabs machine type code, such as `FORM-4-0`.
+        machine_type: String,
+
+        /// Formlabs resin material code, such as `FLGPGR05`.
+        material_code: String,
+
+        /// Layer thickness, in millimeters.
+        layer_thickness_mm: f64,
+    },
+
+    /// Slice on a remote worker (such as another machine-api node), rather
+    /// than on this host.
+    Remote {
+        /// Base URL of the worker, such as `http://192.168.1.20:8585`.
+        endpoint: String,
+
+        /// Name of the slicer to use, as configured on the worker.
+        slicer: String,
+    },
 }
 
 impl Config {
@@ -37,6 +59,17 @@ impl Config {
                 let path = std::fs::canonicalize(&path)?;
                 orca::Slicer::new(&path).into()
             }
+            Self::Preform {
+                machine_type,
+                material_code,
+                layer_thickness_mm,
+            } => preform::Slicer::new(&preform::Config {
+                machine_type: machine_type.clone(),
+                material_code: material_code.clone(),
+                layer_thickness_mm: *layer_thickness_mm,
+            })
+            .into(),
+            Self::Remote { endpoint, slicer } => remote::Slicer::new(endpoint, slicer).into(),
         })
     }
 }
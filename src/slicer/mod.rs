@@ -5,16 +5,161 @@
 mod config;
 pub mod noop;
 pub mod orca;
+pub mod preform;
+pub mod profiles;
 pub mod prusa;
+pub mod remote;
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::Result;
 pub use config::Config;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
+use self::remote::SliceFormat;
 use crate::{
-    BuildOptions, DesignFile, GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, ThreeMfSlicer as ThreeMfSlicerTrait,
-    ThreeMfTemporaryFile,
+    BuildOptions, DesignFile, FilamentMaterial, FormSlicer as FormSlicerTrait, FormTemporaryFile,
+    GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, ThreeMfSlicer as ThreeMfSlicerTrait, ThreeMfTemporaryFile,
 };
 
+/// Append `extra` to some slicer profile gcode, on a line of its own.
+fn append_gcode(gcode: &str, extra: &str) -> String {
+    let gcode = gcode.trim_end_matches('\n');
+    if gcode.is_empty() {
+        extra.to_owned()
+    } else {
+        format!("{}\n{}", gcode, extra)
+    }
+}
+
+/// A slicer's binary isn't installed, so nothing can be sliced with it.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("{slicer} is not installed: {} not found", .path.display())]
+pub struct SlicerNotFound {
+    /// Which slicer, such as `PrusaSlicer`.
+    pub slicer: String,
+
+    /// Where the slicer's binary was expected to be.
+    pub path: PathBuf,
+}
+
+/// A slicer was asked to slice with a setting it has no way to apply.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+#[error("{slicer} doesn't support setting {option}")]
+pub struct UnsupportedOption {
+    /// Which slicer, such as `PreForm`.
+    pub slicer: String,
+
+    /// The option it doesn't support, such as `exposure_time_seconds`.
+    pub option: String,
+}
+
+/// Find the binary for `slicer`: `app_path` if it exists, or failing that
+/// (if `path_name` is set), `path_name` somewhere on the `PATH`.
+fn find_binary(slicer: &str, app_path: &str, path_name: Option<&str>) -> Result<PathBuf, SlicerNotFound> {
+    let app_path = PathBuf::from(app_path);
+    if app_path.exists() {
+        return Ok(app_path);
+    }
+
+    if let (Some(path_name), Some(path)) = (path_name, std::env::var_os("PATH")) {
+        if let Some(found) = std::env::split_paths(&path)
+            .map(|dir| dir.join(path_name))
+            .find(|candidate| candidate.is_file())
+        {
+            return Ok(found);
+        }
+    }
+
+    Err(SlicerNotFound {
+        slicer: slicer.to_owned(),
+        path: app_path,
+    })
+}
+
+/// Exactly how a slicer would be run to slice a file, for debugging slicer
+/// profiles without slicing anything.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SlicerInvocation {
+    /// The slicer binary (or where it's expected to be, if it isn't
+    /// installed).
+    pub program: PathBuf,
+
+    /// The arguments the slicer would be run with.
+    pub args: Vec<String>,
+
+    /// The profiles the slicer would load, with inherited settings and
+    /// overrides merged in, by the path they'd be loaded from.
+    pub profiles: BTreeMap<String, String>,
+
+    /// The environment the slicer would run in. Variables which look like
+    /// they hold secrets have their values redacted.
+    pub env: BTreeMap<String, String>,
+}
+
+impl SlicerInvocation {
+    /// Describe running `program` with `args`, loading `profiles`, in this
+    /// process's environment (which the slicer inherits).
+    fn new(program: PathBuf, args: Vec<String>, profiles: BTreeMap<String, String>) -> Self {
+        Self {
+            program,
+            args,
+            profiles,
+            env: redact_env(std::env::vars()),
+        }
+    }
+}
+
+/// Parts of environment variable names which mark them as holding secrets.
+const SECRET_ENV_NAMES: &[&str] = &["KEY", "TOKEN", "SECRET", "PASSWORD"];
+
+/// Collect environment variables, replacing the values of those which look
+/// like they hold secrets.
+fn redact_env(vars: impl IntoIterator<Item = (String, String)>) -> BTreeMap<String, String> {
+    vars.into_iter()
+        .map(|(name, value)| {
+            let upper = name.to_uppercase();
+            if SECRET_ENV_NAMES.iter().any(|secret| upper.contains(secret)) {
+                (name, "<redacted>".to_owned())
+            } else {
+                (name, value)
+            }
+        })
+        .collect()
+}
+
+/// How long to wait for a slicer to say what version it is.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run `binary --help`, returning the version from the banner it prints.
+async fn binary_version(binary: &Path, prefix: &str) -> Option<String> {
+    let output = tokio::time::timeout(
+        VERSION_TIMEOUT,
+        tokio::process::Command::new(binary).arg("--help").output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+    parse_version(&String::from_utf8_lossy(&output.stdout), prefix)
+}
+
+/// Return the version from a slicer's banner: what follows `prefix`, up to
+/// the first space, such as `2.8.1+linux-x64-GTK3` from
+/// `PrusaSlicer-2.8.1+linux-x64-GTK3 based on Slic3r`.
+fn parse_version(banner: &str, prefix: &str) -> Option<String> {
+    banner
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(prefix))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(|version| version.trim_end_matches(':').to_owned())
+        .filter(|version| !version.is_empty())
+}
+
 /// All Slicers that are supported by the machine-api.
 #[non_exhaustive]
 pub enum AnySlicer {
@@ -24,8 +169,14 @@ pub enum AnySlicer {
     /// Orca Slicer
     Orca(orca::Slicer),
 
+    /// Formlabs PreForm
+    Preform(preform::Slicer),
+
     /// No-op Slicer -- only empty files!
     Noop(noop::Slicer),
+
+    /// Slicing delegated to a remote worker.
+    Remote(remote::Slicer),
 }
 
 impl From<prusa::Slicer> for AnySlicer {
@@ -40,12 +191,116 @@ impl From<orca::Slicer> for AnySlicer {
     }
 }
 
+impl From<preform::Slicer> for AnySlicer {
+    fn from(slicer: preform::Slicer) -> Self {
+        Self::Preform(slicer)
+    }
+}
+
 impl From<noop::Slicer> for AnySlicer {
     fn from(slicer: noop::Slicer) -> Self {
         Self::Noop(slicer)
     }
 }
 
+impl From<remote::Slicer> for AnySlicer {
+    fn from(slicer: remote::Slicer) -> Self {
+        Self::Remote(slicer)
+    }
+}
+
+impl AnySlicer {
+    /// Return the filament material this slicer's profile is set up for, if
+    /// the profile pins one down. Slicers which slice for whatever is
+    /// loaded return `None`.
+    pub async fn expected_material(&self) -> Result<Option<FilamentMaterial>> {
+        match self {
+            Self::Prusa(slicer) => slicer.expected_material().await,
+            Self::Orca(slicer) => slicer.expected_material().await,
+            _ => Ok(None),
+        }
+    }
+
+    /// Return the formats this slicer can slice to, for machines to build
+    /// from.
+    pub fn formats(&self) -> &'static [SliceFormat] {
+        match self {
+            Self::Prusa(_) | Self::Noop(_) => &[SliceFormat::Gcode, SliceFormat::ThreeMf],
+            Self::Orca(_) => &[SliceFormat::ThreeMf],
+            Self::Preform(_) => &[SliceFormat::Form],
+            Self::Remote(_) => &[SliceFormat::Gcode, SliceFormat::ThreeMf, SliceFormat::Form],
+        }
+    }
+
+    /// Return the version of the slicer, if it says.
+    pub async fn version(&self) -> Option<String> {
+        match self {
+            Self::Prusa(slicer) => slicer.version().await,
+            Self::Orca(slicer) => slicer.version().await,
+            _ => None,
+        }
+    }
+
+    /// Return the profile files the slicer slices with, such as to record
+    /// which settings a part was sliced with. Slicers configured some
+    /// other way return none.
+    pub fn profiles(&self) -> Vec<PathBuf> {
+        match self {
+            Self::Prusa(slicer) => slicer.profiles(),
+            Self::Orca(slicer) => slicer.profiles(),
+            _ => vec![],
+        }
+    }
+
+    /// Return the name of the slicer profile, such as `mk3`, for telling
+    /// apart jobs sliced with different profiles. Slicers configured some
+    /// other way return `None`.
+    pub fn profile_name(&self) -> Option<String> {
+        match self {
+            Self::Prusa(slicer) => slicer.profile_name(),
+            Self::Orca(slicer) => slicer.profile_name(),
+            Self::Remote(slicer) => slicer.profile_name(),
+            _ => None,
+        }
+    }
+
+    /// Check that the slicer's binary is installed. Slicers which don't
+    /// run anything locally are always available.
+    pub fn check_installed(&self) -> Result<(), SlicerNotFound> {
+        match self {
+            Self::Prusa(slicer) => slicer.check_installed(),
+            Self::Orca(slicer) => slicer.check_installed(),
+            Self::Preform(slicer) => slicer.check_installed(),
+            Self::Noop(_) | Self::Remote(_) => Ok(()),
+        }
+    }
+
+    /// Check that the slicer can slice with every option in `options`, so
+    /// options it would otherwise have to ignore are refused instead.
+    pub fn check_options(&self, options: &BuildOptions) -> Result<(), UnsupportedOption> {
+        match (self, options.sla()) {
+            (Self::Preform(slicer), Some(sla)) => slicer.check_options(sla),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return exactly how the slicer would be run to slice `design_file`
+    /// to `format`, without running it. Slicers which don't run a command
+    /// line locally return `None`.
+    pub async fn dry_run(
+        &self,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+        format: SliceFormat,
+    ) -> Result<Option<SlicerInvocation>> {
+        match self {
+            Self::Prusa(slicer) => slicer.dry_run(design_file, options, format).await.map(Some),
+            Self::Orca(slicer) => slicer.dry_run(design_file, options, format).await.map(Some),
+            Self::Preform(_) | Self::Noop(_) | Self::Remote(_) => Ok(None),
+        }
+    }
+}
+
 impl GcodeSlicerTrait for AnySlicer {
     type Error = anyhow::Error;
 
@@ -54,6 +309,7 @@ impl GcodeSlicerTrait for AnySlicer {
         match self {
             Self::Prusa(slicer) => GcodeSlicerTrait::generate(slicer, design_file, options).await,
             Self::Noop(slicer) => GcodeSlicerTrait::generate(slicer, design_file, options).await,
+            Self::Remote(slicer) => GcodeSlicerTrait::generate(slicer, design_file, options).await,
             _ => Err(anyhow::anyhow!("slicer doesn't support gcode")),
         }
     }
@@ -68,6 +324,74 @@ impl ThreeMfSlicerTrait for AnySlicer {
             Self::Prusa(slicer) => ThreeMfSlicerTrait::generate(slicer, design_file, options).await,
             Self::Orca(slicer) => ThreeMfSlicerTrait::generate(slicer, design_file, options).await,
             Self::Noop(slicer) => ThreeMfSlicerTrait::generate(slicer, design_file, options).await,
+            Self::Remote(slicer) => ThreeMfSlicerTrait::generate(slicer, design_file, options).await,
+            _ => Err(anyhow::anyhow!("slicer doesn't support 3mf")),
+        }
+    }
+}
+
+impl FormSlicerTrait for AnySlicer {
+    type Error = anyhow::Error;
+
+    /// Generate a .form file from some input file.
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<FormTemporaryFile> {
+        match self {
+            Self::Preform(slicer) => FormSlicerTrait::generate(slicer, design_file, options).await,
+            Self::Remote(slicer) => FormSlicerTrait::generate(slicer, design_file, options).await,
+            _ => Err(anyhow::anyhow!("slicer doesn't support form")),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version(
+                "PrusaSlicer-2.8.1+linux-x64-GTK3 based on Slic3r (with GUI support)\nhttps://github.com/prusa3d/PrusaSlicer\n",
+                "PrusaSlicer-"
+            ),
+            Some("2.8.1+linux-x64-GTK3".to_owned())
+        );
+        assert_eq!(
+            parse_version("OrcaSlicer-02.01.01:\nUsage: orca-slicer [ OPTIONS ]", "OrcaSlicer-"),
+            Some("02.01.01".to_owned())
+        );
+        assert_eq!(parse_version("Usage: something else", "PrusaSlicer-"), None);
+    }
+
+    #[test]
+    fn test_find_binary() {
+        let not_found =
+            find_binary("Nope", "/nonexistent/nope", Some("nope-slicer-that-is-not-installed")).unwrap_err();
+        assert_eq!(
+            not_found,
+            SlicerNotFound {
+                slicer: "Nope".to_owned(),
+                path: PathBuf::from("/nonexistent/nope"),
+            }
+        );
+        assert_eq!(
+            not_found.to_string(),
+            "Nope is not installed: /nonexistent/nope not found"
+        );
+
+        let this = std::env::current_exe().unwrap();
+        assert_eq!(find_binary("Test", this.to_str().unwrap(), None), Ok(this));
+    }
+
+    #[test]
+    fn test_redact_env() {
+        let env = redact_env([
+            ("PATH".to_owned(), "/usr/bin".to_owned()),
+            ("KITTYCAD_API_TOKEN".to_owned(), "hunter2".to_owned()),
+            ("aws_secret_access_key".to_owned(), "hunter2".to_owned()),
+        ]);
+        assert_eq!(env["PATH"], "/usr/bin");
+        assert_eq!(env["KITTYCAD_API_TOKEN"], "<redacted>");
+        assert_eq!(env["aws_secret_access_key"], "<redacted>");
+    }
+}
@@ -4,7 +4,7 @@
 use anyhow::Result;
 
 use crate::{
-    BuildOptions, DesignFile, GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, TemporaryFile,
+    spool_dir, BuildOptions, DesignFile, GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, TemporaryFile,
     ThreeMfSlicer as ThreeMfSlicerTrait, ThreeMfTemporaryFile,
 };
 
@@ -29,7 +29,7 @@ impl GcodeSlicerTrait for Slicer {
     type Error = anyhow::Error;
 
     async fn generate(&self, _design_file: &DesignFile, _: &BuildOptions) -> Result<GcodeTemporaryFile> {
-        let filepath = std::env::temp_dir().join(format!("{}", uuid::Uuid::new_v4().simple()));
+        let filepath = spool_dir().join(format!("{}", uuid::Uuid::new_v4().simple()));
         {
             let _ = std::fs::File::create(&filepath);
         }
@@ -41,7 +41,7 @@ impl ThreeMfSlicerTrait for Slicer {
     type Error = anyhow::Error;
 
     async fn generate(&self, _design_file: &DesignFile, _: &BuildOptions) -> Result<ThreeMfTemporaryFile> {
-        let filepath = std::env::temp_dir().join(format!("{}", uuid::Uuid::new_v4().simple()));
+        let filepath = spool_dir().join(format!("{}", uuid::Uuid::new_v4().simple()));
         {
             let _ = std::fs::File::create(&filepath);
         }
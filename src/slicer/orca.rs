@@ -1,13 +1,17 @@
 //! Support for the orca Slicer.
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
 use tokio::process::Command;
 
+use super::{append_gcode, binary_version, find_binary, remote::SliceFormat, SlicerInvocation, SlicerNotFound};
 use crate::{
-    BuildOptions, DesignFile, HardwareConfiguration, TemporaryFile, ThreeMfSlicer as ThreeMfSlicerTrait,
-    ThreeMfTemporaryFile,
+    spool_dir, BuildOptions, DesignFile, FilamentMaterial, HardwareConfiguration, TemporaryFile,
+    ThreeMfSlicer as ThreeMfSlicerTrait, ThreeMfTemporaryFile,
 };
 
 /// Handle to invoke the Orca Slicer with some specific machine-specific config.
@@ -24,14 +28,68 @@ impl Slicer {
         }
     }
 
-    /// Generate 3MF from some input file.
-    async fn generate_via_cli(
+    /// Check that Orca Slicer is installed.
+    pub fn check_installed(&self) -> Result<(), SlicerNotFound> {
+        find_orca_slicer().map(|_| ())
+    }
+
+    /// Return the version of Orca Slicer, if it's installed.
+    pub async fn version(&self) -> Option<String> {
+        binary_version(&find_orca_slicer().ok()?, "OrcaSlicer-").await
+    }
+
+    /// Return the profiles the slicer slices with: the process, machine and
+    /// filament overrides in its config directory.
+    pub fn profiles(&self) -> Vec<PathBuf> {
+        ["process.json", "machine.json", "filament.json"]
+            .iter()
+            .map(|name| self.config.join(name))
+            .collect()
+    }
+
+    /// Return the name of the profile, which is its directory's name.
+    pub fn profile_name(&self) -> Option<String> {
+        Some(self.config.file_name()?.to_string_lossy().into_owned())
+    }
+
+    /// Return the filament material this slicer's filament overrides are
+    /// set up for, from their `filament_type` setting. Without one, the
+    /// profile inherits from whatever filament is loaded.
+    pub async fn expected_material(&self) -> Result<Option<FilamentMaterial>> {
+        let filament = tokio::fs::read_to_string(self.config.join("filament.json")).await?;
+        parse_filament_type(&filament)
+    }
+
+    /// Return exactly how Orca Slicer would be run to slice `design_file`
+    /// to `format`, without running it.
+    pub async fn dry_run(
+        &self,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+        format: SliceFormat,
+    ) -> Result<SlicerInvocation> {
+        if format != SliceFormat::ThreeMf {
+            anyhow::bail!("Orca Slicer only slices to 3mf");
+        }
+        let output_path = spool_dir().join(format!("{}.3mf", uuid::Uuid::new_v4()));
+        let (profiles, args) = self.prepare("--export-3mf", &output_path, design_file, options).await?;
+        Ok(SlicerInvocation::new(
+            find_orca_slicer().unwrap_or_else(|not_found| not_found.path),
+            args,
+            profiles,
+        ))
+    }
+
+    /// Merge the profiles Orca Slicer should load to slice `design_file` to
+    /// `output_path`, returning them (by the path each is to be written to)
+    /// along with the arguments to run Orca Slicer with.
+    async fn prepare(
         &self,
         output_flag: &str,
-        output_extension: &str,
+        output_path: &Path,
         design_file: &DesignFile,
         options: &BuildOptions,
-    ) -> Result<TemporaryFile> {
+    ) -> Result<(BTreeMap<String, String>, Vec<String>)> {
         // Make sure the config path is a directory.
         if !self.config.is_dir() {
             anyhow::bail!(
@@ -42,10 +100,10 @@ impl Slicer {
 
         let (file_path, _file_type) = match design_file {
             DesignFile::Stl(path) => (path, "stl"),
+            DesignFile::ThreeMf(path) => (path, "3mf"),
         };
 
         let uid = uuid::Uuid::new_v4();
-        let output_path = std::env::temp_dir().join(format!("{}.{}", uid, output_extension));
         let process_p = self
             .config
             .join("process.json")
@@ -85,7 +143,23 @@ impl Slicer {
             other => anyhow::bail!("Unsupported nozzle diameter for orca: {}", other),
         }
 
-        let new_machine = machine_overrides.load_inherited()?;
+        let fdm = options.fdm().cloned().unwrap_or_default();
+        let mut new_machine = machine_overrides.load_inherited()?;
+        if let bambulabs::templates::Template::Machine(machine) = &mut new_machine {
+            if let Some(extra) = &fdm.start_gcode_extra {
+                machine.machine_start_gcode = Some(append_gcode(
+                    machine.machine_start_gcode.as_deref().unwrap_or_default(),
+                    extra,
+                ));
+            }
+            if let Some(extra) = &fdm.end_gcode_extra {
+                machine.machine_end_gcode = Some(append_gcode(
+                    machine.machine_end_gcode.as_deref().unwrap_or_default(),
+                    extra,
+                ));
+            }
+        }
+
         // Get the default process for the machine.
         let bambulabs::templates::Template::Machine(machine) = &new_machine else {
             // This should never happen.
@@ -114,7 +188,8 @@ impl Slicer {
             .ok_or_else(|| anyhow::anyhow!("Invalid filament profile: {}", default_filament_profile))?
             .trim();
 
-        let temp_dir = std::env::temp_dir();
+        let temp_dir = spool_dir();
+        let mut profiles = BTreeMap::new();
         let mut filament_configs = Vec::new();
         let filament_p = self
             .config
@@ -138,19 +213,16 @@ impl Slicer {
                 uid,
                 index
             ));
-            tokio::fs::write(&filament_config, serde_json::to_string_pretty(&new_filament)?).await?;
             let filament_config = filament_config
                 .to_str()
                 .ok_or_else(|| anyhow::anyhow!("Invalid filament config path: {}", filament_config.display()))?
                 .to_string();
+            profiles.insert(filament_config.clone(), serde_json::to_string_pretty(&new_filament)?);
             filament_configs.push(filament_config);
         }
 
-        // Write each to a temporary file.
         let process_config = temp_dir.join(format!("process-{}.json", uid));
-        tokio::fs::write(&process_config, serde_json::to_string_pretty(&new_process)?).await?;
         let machine_config = temp_dir.join(format!("machine-{}.json", uid));
-        tokio::fs::write(&machine_config, serde_json::to_string_pretty(&new_machine)?).await?;
         let process_config = process_config
             .to_str()
             .ok_or_else(|| anyhow::anyhow!("Invalid process config path: {}", process_config.display()))?
@@ -160,9 +232,12 @@ impl Slicer {
             .ok_or_else(|| anyhow::anyhow!("Invalid machine config path: {}", machine_config.display()))?
             .to_string();
 
-        let settings = [process_config.clone(), machine_config.clone()].join(";");
+        profiles.insert(process_config.clone(), serde_json::to_string_pretty(&new_process)?);
+        profiles.insert(machine_config.clone(), serde_json::to_string_pretty(&new_machine)?);
 
-        let args: Vec<String> = vec![
+        let settings = [process_config, machine_config].join(";");
+
+        let mut args: Vec<String> = vec![
             "--load-settings".to_string(),
             settings,
             "--load-filament-ids".to_string(),
@@ -176,6 +251,13 @@ impl Slicer {
             "5".to_string(),
             "--orient".to_string(),
             "1".to_string(),
+        ];
+        // The parts of a project are all placed at the origin, so spread
+        // them out over the plate.
+        if matches!(design_file, DesignFile::ThreeMf(_)) {
+            args.extend(["--arrange".to_string(), "1".to_string()]);
+        }
+        args.extend([
             output_flag.to_string(),
             output_path
                 .to_str()
@@ -185,16 +267,42 @@ impl Slicer {
                 .to_str()
                 .ok_or_else(|| anyhow::anyhow!("Invalid original file path: {}", file_path.display()))?
                 .to_string(),
-        ];
+        ]);
+        Ok((profiles, args))
+    }
+
+    /// Generate 3MF from some input file.
+    #[tracing::instrument(skip_all, fields(slicer = "orca", output = output_extension))]
+    async fn generate_via_cli(
+        &self,
+        output_flag: &str,
+        output_extension: &str,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+    ) -> Result<TemporaryFile> {
+        let output_path = spool_dir().join(format!("{}.{}", uuid::Uuid::new_v4(), output_extension));
+        let (profiles, args) = self.prepare(output_flag, &output_path, design_file, options).await?;
+
+        // Write each profile to a temporary file.
+        for (path, contents) in &profiles {
+            tokio::fs::write(path, contents).await?;
+        }
 
         // Find the orcaslicer executable path.
         let orca_slicer_path = find_orca_slicer()?;
 
+        tracing::debug!(args = ?args, "running orca-slicer");
+        let started = std::time::Instant::now();
         let output = Command::new(orca_slicer_path)
             .args(&args)
             .output()
             .await
             .context("Failed to execute orca-slicer command")?;
+        tracing::info!(
+            status = %output.status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "orca-slicer exited"
+        );
 
         // Make sure the command was successful.
         if !output.status.success() {
@@ -209,10 +317,8 @@ impl Slicer {
         }
 
         // Delete all the configs.
-        tokio::fs::remove_file(&process_config).await?;
-        tokio::fs::remove_file(&machine_config).await?;
-        for filament_config in filament_configs {
-            tokio::fs::remove_file(&filament_config).await?;
+        for path in profiles.keys() {
+            tokio::fs::remove_file(path).await?;
         }
 
         let file = TemporaryFile::new(&output_path).await?;
@@ -233,41 +339,44 @@ impl ThreeMfSlicerTrait for Slicer {
     }
 }
 
+/// Find the material an Orca Slicer filament profile is for, from the
+/// first of its `filament_type`s.
+fn parse_filament_type(filament: &str) -> Result<Option<FilamentMaterial>> {
+    let bambulabs::templates::Template::Filament(filament) = serde_json::from_str(filament)? else {
+        anyhow::bail!("Invalid filament template");
+    };
+    Ok(filament
+        .filament_type
+        .first()
+        .map(|name| FilamentMaterial::from_slicer_name(name)))
+}
+
 // Find the orcaslicer executable path on macOS.
 #[cfg(target_os = "macos")]
-fn find_orca_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("/Applications/OrcaSlicer.app/Contents/MacOS/OrcaSlicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("Slicer not found")
-    }
+fn find_orca_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary(
+        "OrcaSlicer",
+        "/Applications/OrcaSlicer.app/Contents/MacOS/OrcaSlicer",
+        None,
+    )
 }
 
 // Find the orcaslicer executable path on Windows.
 #[cfg(target_os = "windows")]
-fn find_orca_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("C:\\Program Files\\OrcaSlicer\\orca-slicer.exe");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("Slicer not found")
-    }
+fn find_orca_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary("OrcaSlicer", "C:\\Program Files\\OrcaSlicer\\orca-slicer.exe", None)
 }
 
 // Find the orcaslicer executable path on Linux.
 #[cfg(target_os = "linux")]
-fn find_orca_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("/usr/bin/orca-slicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("Slicer not found")
-    }
+fn find_orca_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary("OrcaSlicer", "/usr/bin/orca-slicer", None)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test_deserialize_process_json() {
         let contents = include_str!("../../config/bambu/process.json");
@@ -285,4 +394,22 @@ mod tests {
         let contents = include_str!("../../config/bambu/filament.json");
         let _template: bambulabs::templates::Template = serde_json::from_str(contents).unwrap();
     }
+
+    #[test]
+    fn test_parse_filament_type() {
+        assert_eq!(
+            parse_filament_type(include_str!("../../config/bambu/filament.json")).unwrap(),
+            None
+        );
+        assert_eq!(
+            parse_filament_type(r#"{"type": "filament", "name": "overrides", "filament_type": ["PETG"]}"#).unwrap(),
+            Some(FilamentMaterial::Petg)
+        );
+        assert_eq!(
+            parse_filament_type(r#"{"type": "filament", "name": "overrides", "filament_type": ["PA-CF", "PLA"]}"#)
+                .unwrap(),
+            Some(FilamentMaterial::Composite)
+        );
+        assert!(parse_filament_type(r#"{"type": "process", "name": "overrides"}"#).is_err());
+    }
 }
@@ -0,0 +1,291 @@
+//! Support for Formlabs PreForm (https://formlabs.com/software/preform/),
+//! driven through the headless `PreFormServer` local API.
+
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::{Child, Command};
+
+use super::{find_binary, SlicerNotFound, UnsupportedOption};
+use crate::{
+    spool_dir, BuildOptions, DesignFile, FormSlicer as FormSlicerTrait, FormTemporaryFile, SlaOptions, TemporaryFile,
+};
+
+/// How long to wait for `PreFormServer` to start accepting requests.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Resin and print settings passed along to PreForm when creating a scene.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// Formlabs machine type code, such as `FORM-4-0`.
+    pub machine_type: String,
+
+    /// Formlabs resin material code, such as `FLGPGR05` (Grey V5).
+    pub material_code: String,
+
+    /// Layer thickness, in millimeters.
+    pub layer_thickness_mm: f64,
+}
+
+/// Handle to invoke PreForm with some specific resin/printer config.
+pub struct Slicer {
+    config: Config,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scene {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateScene<'a> {
+    machine_type: &'a str,
+    material_code: &'a str,
+    layer_thickness_mm: f64,
+    print_setting: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct FileRequest<'a> {
+    file: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelsRequest<'a> {
+    models: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct AutoSupportRequest<'a> {
+    models: &'a str,
+    density: f64,
+}
+
+/// A running `PreFormServer`; the process is killed when this is dropped.
+struct Server {
+    _child: Child,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl Server {
+    /// Start a `PreFormServer` on a free local port, and wait for it to
+    /// be ready to take requests.
+    async fn start() -> Result<Self> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+        let child = Command::new(find_preform_server()?)
+            .args(["--port", &port.to_string()])
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to execute PreFormServer command")?;
+        tracing::debug!(port = port, pid = child.id(), "started PreFormServer");
+
+        let server = Self {
+            _child: child,
+            base_url: format!("http://127.0.0.1:{}", port),
+            client: reqwest::Client::new(),
+        };
+
+        let started = std::time::Instant::now();
+        while server.client.get(&server.base_url).send().await.is_err() {
+            if started.elapsed() > STARTUP_TIMEOUT {
+                anyhow::bail!("PreFormServer did not start within {:?}", STARTUP_TIMEOUT);
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, "PreFormServer ready");
+
+        Ok(server)
+    }
+
+    async fn post<BodyT: Serialize>(&self, path: &str, body: &BodyT) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("PreFormServer {} failed: {}: {}", path, status, body);
+        }
+
+        Ok(resp)
+    }
+}
+
+impl Slicer {
+    /// Create a new [Slicer], which will invoke PreForm with the
+    /// specified resin and layer configuration.
+    pub fn new(config: &Config) -> Self {
+        tracing::debug!(
+            machine_type = %config.machine_type,
+            material_code = %config.material_code,
+            "new"
+        );
+        Self { config: config.clone() }
+    }
+
+    /// Check that PreFormServer is installed.
+    pub fn check_installed(&self) -> Result<(), SlicerNotFound> {
+        find_preform_server().map(|_| ())
+    }
+
+    /// Check that PreForm can apply every one of the `sla` options.
+    /// Formlabs resins are exposed according to the material's own
+    /// settings, which PreForm doesn't let us change.
+    pub fn check_options(&self, sla: &SlaOptions) -> Result<(), UnsupportedOption> {
+        let unsupported = if sla.exposure_time_seconds.is_some() {
+            "exposure_time_seconds"
+        } else if sla.bottom_exposure_time_seconds.is_some() {
+            "bottom_exposure_time_seconds"
+        } else {
+            return Ok(());
+        };
+        Err(UnsupportedOption {
+            slicer: "PreForm".to_owned(),
+            option: unsupported.to_owned(),
+        })
+    }
+
+    /// Generate a `.form` file from some input file, with any of `sla`
+    /// overriding the configured settings.
+    #[tracing::instrument(skip_all, fields(slicer = "preform"))]
+    async fn generate_via_server(&self, design_file: &DesignFile, sla: &SlaOptions) -> Result<TemporaryFile> {
+        let (file_path, file_type) = match design_file {
+            DesignFile::Stl(path) => (path, "stl"),
+            DesignFile::ThreeMf(_) => anyhow::bail!("PreForm doesn't support multi-part projects"),
+        };
+
+        let uid = uuid::Uuid::new_v4();
+        let output_path = spool_dir().join(format!("{}.form", uid.simple()));
+
+        self.check_options(sla)?;
+        let layer_thickness_mm = sla.layer_thickness_mm.unwrap_or(self.config.layer_thickness_mm);
+        let support_density = sla.support_density.unwrap_or(1.0);
+
+        tracing::info!(
+            machine_type = %self.config.machine_type,
+            material_code = %self.config.material_code,
+            layer_thickness_mm = layer_thickness_mm,
+            support_density = support_density,
+            file_path = file_path.to_str(),
+            file_type = file_type,
+            "building to form"
+        );
+
+        let server = Server::start().await?;
+
+        let scene: Scene = server
+            .post(
+                "/scene/",
+                &CreateScene {
+                    machine_type: &self.config.machine_type,
+                    material_code: &self.config.material_code,
+                    layer_thickness_mm,
+                    print_setting: "DEFAULT",
+                },
+            )
+            .await?
+            .json()
+            .await?;
+
+        server
+            .post(
+                &format!("/scene/{}/import-model/", scene.id),
+                &FileRequest {
+                    file: file_path
+                        .to_str()
+                        .ok_or_else(|| anyhow::anyhow!("Invalid original file path: {}", file_path.display()))?,
+                },
+            )
+            .await?;
+
+        server
+            .post(
+                &format!("/scene/{}/auto-orient/", scene.id),
+                &ModelsRequest { models: "ALL" },
+            )
+            .await?;
+        if support_density > 0.0 {
+            server
+                .post(
+                    &format!("/scene/{}/auto-support/", scene.id),
+                    &AutoSupportRequest {
+                        models: "ALL",
+                        density: support_density,
+                    },
+                )
+                .await?;
+        }
+        server
+            .post(
+                &format!("/scene/{}/auto-layout/", scene.id),
+                &ModelsRequest { models: "ALL" },
+            )
+            .await?;
+
+        server
+            .post(
+                &format!("/scene/{}/save-form/", scene.id),
+                &FileRequest {
+                    file: output_path
+                        .to_str()
+                        .ok_or_else(|| anyhow::anyhow!("Invalid output path: {}", output_path.display()))?,
+                },
+            )
+            .await?;
+
+        // Make sure the file was created.
+        if !output_path.exists() {
+            anyhow::bail!("Failed to create output file");
+        }
+
+        tracing::info!(
+            file_path = file_path.to_str(),
+            output_path = output_path.to_str(),
+            "form built",
+        );
+
+        TemporaryFile::new(&output_path).await
+    }
+}
+
+impl FormSlicerTrait for Slicer {
+    type Error = anyhow::Error;
+
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<FormTemporaryFile> {
+        let sla = options.sla().copied().unwrap_or_default();
+        Ok(FormTemporaryFile(self.generate_via_server(design_file, &sla).await?))
+    }
+}
+
+// Find the PreFormServer executable path on macOS.
+#[cfg(target_os = "macos")]
+fn find_preform_server() -> Result<PathBuf, SlicerNotFound> {
+    find_binary(
+        "PreFormServer",
+        "/Applications/PreFormServer.app/Contents/MacOS/PreFormServer",
+        None,
+    )
+}
+
+// Find the PreFormServer executable path on Windows.
+#[cfg(target_os = "windows")]
+fn find_preform_server() -> Result<PathBuf, SlicerNotFound> {
+    find_binary(
+        "PreFormServer",
+        "C:\\Program Files\\PreFormServer\\PreFormServer.exe",
+        None,
+    )
+}
+
+// Find the PreFormServer executable path on Linux.
+#[cfg(target_os = "linux")]
+fn find_preform_server() -> Result<PathBuf, SlicerNotFound> {
+    find_binary("PreFormServer", "/usr/bin/PreFormServer", Some("PreFormServer"))
+}
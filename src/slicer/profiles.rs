@@ -0,0 +1,332 @@
+//! Orca Slicer profiles, as the `machine.json`, `process.json` and
+//! `filament.json` triple [super::orca::Slicer] loads from a directory,
+//! along with importing them from presets exported by Bambu Studio or Orca
+//! Slicer.
+
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The kinds of preset which make up a profile, along with the file each
+/// is stored in.
+const PRESET_FILES: [(&str, &str); 3] = [
+    ("machine", "machine.json"),
+    ("process", "process.json"),
+    ("filament", "filament.json"),
+];
+
+/// The largest a preset in a bundle may be, unzipped, in bytes. Presets
+/// are a few kilobytes, so this only turns away zip bombs.
+const MAX_PRESET_BYTES: u64 = 4 * 1024 * 1024;
+
+/// The most a bundle may hold, unzipped, in bytes, across all its presets.
+const MAX_BUNDLE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Presets pulled out of an exported preset bundle. Any of them may be
+/// missing, such as when a single preset was exported on its own.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PresetBundle {
+    /// The machine (printer) preset.
+    pub machine: Option<Value>,
+
+    /// The process (print settings) preset.
+    pub process: Option<Value>,
+
+    /// The filament preset.
+    pub filament: Option<Value>,
+}
+
+impl PresetBundle {
+    /// Parse a preset exported by Bambu Studio or Orca Slicer. This is
+    /// either a single preset, as JSON, or a zip bundle of them (such as a
+    /// `.bbscfg`, `.bbsflmt` or `.orca_printer` file). When a bundle holds
+    /// more than one preset of a kind (such as several filaments), the
+    /// first is used. Bundles which unzip to more than a sensible size for
+    /// presets are refused.
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let mut bundle = Self::default();
+
+        if content.starts_with(b"PK\x03\x04") {
+            let mut archive = zip::ZipArchive::new(Cursor::new(content)).context("Failed to read preset bundle")?;
+            let mut total_bytes = 0;
+            for index in 0..archive.len() {
+                let mut entry = archive.by_index(index)?;
+                if !entry.is_file() || !entry.name().ends_with(".json") {
+                    continue;
+                }
+                let name = entry.name().to_owned();
+                // The sizes in the zip's directory can't be trusted, so only
+                // read one byte past the limit to tell it's been exceeded.
+                let mut contents = vec![];
+                (&mut entry).take(MAX_PRESET_BYTES + 1).read_to_end(&mut contents)?;
+                if contents.len() as u64 > MAX_PRESET_BYTES {
+                    anyhow::bail!("{} is too big: presets may be at most {} bytes", name, MAX_PRESET_BYTES);
+                }
+                total_bytes += contents.len() as u64;
+                if total_bytes > MAX_BUNDLE_BYTES {
+                    anyhow::bail!(
+                        "Preset bundle is too big: it may hold at most {} bytes of presets",
+                        MAX_BUNDLE_BYTES
+                    );
+                }
+                let preset: Value =
+                    serde_json::from_slice(&contents).with_context(|| format!("Invalid JSON in {}", name))?;
+                bundle.add(preset)?;
+            }
+        } else {
+            let preset: Value = serde_json::from_slice(content).context("Preset is neither a zip bundle nor JSON")?;
+            bundle.add(preset)?;
+        }
+
+        if bundle.machine.is_none() && bundle.process.is_none() && bundle.filament.is_none() {
+            anyhow::bail!("No machine, process or filament presets found");
+        }
+        Ok(bundle)
+    }
+
+    /// Add a preset to the bundle, by its `type`. Anything which isn't a
+    /// preset (such as the bundle's `bundle_structure.json`) is ignored.
+    fn add(&mut self, preset: Value) -> Result<()> {
+        let slot = match preset.get("type").and_then(Value::as_str) {
+            Some("machine") => &mut self.machine,
+            Some("process") => &mut self.process,
+            Some("filament") => &mut self.filament,
+            _ => return Ok(()),
+        };
+        if slot.is_some() {
+            tracing::debug!(name = format!("{:?}", preset.get("name")), "skipping extra preset");
+            return Ok(());
+        }
+
+        // Make sure the slicer will be able to load it later.
+        let _: bambulabs::templates::Template =
+            serde_json::from_value(preset.clone()).context("Preset isn't a valid slicer template")?;
+
+        *slot = Some(preset);
+        Ok(())
+    }
+
+    fn get(&self, kind: &str) -> Option<&Value> {
+        match kind {
+            "machine" => self.machine.as_ref(),
+            "process" => self.process.as_ref(),
+            "filament" => self.filament.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// A stored Orca Slicer profile.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Profile {
+    /// The profile name.
+    pub name: String,
+
+    /// The profile's directory, which can be used as the `config` of an
+    /// Orca slicer.
+    pub config: String,
+}
+
+/// Directory of Orca Slicer profiles, one subdirectory each.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileStore {
+    dir: PathBuf,
+}
+
+impl ProfileStore {
+    /// Create a new store of profiles, kept in `dir`.
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_owned() }
+    }
+
+    /// Return every stored profile, sorted by name.
+    pub async fn list(&self) -> Result<Vec<Profile>> {
+        let mut profiles = vec![];
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(profiles),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                profiles.push(self.profile(name));
+            }
+        }
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+
+    /// Store the presets in `bundle` as the profile `name`. Presets the
+    /// bundle doesn't have are kept from the existing profile of that name,
+    /// if there is one; the result must have all three.
+    pub async fn import(&self, name: &str, bundle: &PresetBundle) -> Result<Profile> {
+        if name.is_empty()
+            || name.starts_with('.')
+            || !name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.')
+        {
+            anyhow::bail!(
+                "Invalid profile name {:?}: use letters, numbers, `-`, `_` and `.`",
+                name
+            );
+        }
+
+        let profile_dir = self.dir.join(name);
+        for (kind, file_name) in PRESET_FILES {
+            if bundle.get(kind).is_none() && !tokio::fs::try_exists(profile_dir.join(file_name)).await? {
+                anyhow::bail!("Profile {:?} has no {} preset", name, kind);
+            }
+        }
+
+        tokio::fs::create_dir_all(&profile_dir).await?;
+        for (kind, file_name) in PRESET_FILES {
+            if let Some(preset) = bundle.get(kind) {
+                tokio::fs::write(profile_dir.join(file_name), serde_json::to_string_pretty(preset)?).await?;
+            }
+        }
+
+        tracing::info!(
+            name = name,
+            dir = format!("{:?}", profile_dir),
+            "imported slicer profile"
+        );
+        Ok(self.profile(name))
+    }
+
+    fn profile(&self, name: &str) -> Profile {
+        Profile {
+            name: name.to_owned(),
+            config: self.dir.join(name).to_string_lossy().into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn bundle_zip(entries: &[(&str, Value)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in entries {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.to_string().as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_parse_bundle() {
+        let content = bundle_zip(&[
+            (
+                "bundle_structure.json",
+                serde_json::json!({"printer_config": ["printer/My X1C.json"]}),
+            ),
+            (
+                "printer/My X1C.json",
+                serde_json::json!({"type": "machine", "name": "My X1C", "inherits": "Bambu Lab X1 Carbon 0.4 nozzle"}),
+            ),
+            (
+                "process/Strong.json",
+                serde_json::json!({"type": "process", "name": "Strong", "wall_loops": "4"}),
+            ),
+            (
+                "filament/Mine.json",
+                serde_json::json!({"type": "filament", "name": "Mine"}),
+            ),
+            (
+                "filament/Other.json",
+                serde_json::json!({"type": "filament", "name": "Other"}),
+            ),
+        ]);
+
+        let bundle = PresetBundle::parse(&content).unwrap();
+        assert_eq!(bundle.machine.unwrap()["name"], "My X1C");
+        assert_eq!(bundle.process.unwrap()["wall_loops"], "4");
+        assert_eq!(bundle.filament.unwrap()["name"], "Mine");
+    }
+
+    #[test]
+    fn test_parse_single() {
+        let bundle = PresetBundle::parse(br#"{"type": "process", "name": "Fast"}"#).unwrap();
+        assert_eq!(bundle.machine, None);
+        assert_eq!(bundle.process.unwrap()["name"], "Fast");
+
+        assert!(PresetBundle::parse(br#"{"hello": "world"}"#).is_err());
+        assert!(PresetBundle::parse(b"not a preset").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let dir = std::env::temp_dir().join(format!("profiles-{}", uuid::Uuid::new_v4().simple()));
+        let store = ProfileStore::new(&dir);
+        let process = PresetBundle::parse(br#"{"type": "process", "name": "Fast"}"#).unwrap();
+
+        assert!(store.import("x1c", &process).await.is_err());
+        assert!(store.import("../x1c", &process).await.is_err());
+
+        let full = PresetBundle {
+            machine: Some(serde_json::json!({"type": "machine", "name": "My X1C"})),
+            process: Some(serde_json::json!({"type": "process", "name": "Strong"})),
+            filament: Some(serde_json::json!({"type": "filament", "name": "Mine"})),
+        };
+        store.import("x1c", &full).await.unwrap();
+        let profile = store.import("x1c", &process).await.unwrap();
+        assert_eq!(store.list().await.unwrap(), vec![profile.clone()]);
+
+        let stored: Value =
+            serde_json::from_str(&tokio::fs::read_to_string(dir.join("x1c/process.json")).await.unwrap()).unwrap();
+        assert_eq!(stored["name"], "Fast");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_parse_bundle_too_big() {
+        // A preset which unzips to more than the limit, though it's tiny
+        // zipped.
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("filament/Big.json", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(br#"{"type": "filament", "name": "Big", "padding": ""#)
+            .unwrap();
+        zip.write_all(&vec![b' '; MAX_PRESET_BYTES as usize]).unwrap();
+        zip.write_all(br#""}"#).unwrap();
+        let content = zip.finish().unwrap().into_inner();
+        assert!(content.len() < 64 * 1024);
+
+        let e = PresetBundle::parse(&content).unwrap_err();
+        assert!(e.to_string().contains("too big"), "{:?}", e);
+
+        // As do enough presets under the limit.
+        let padding = " ".repeat(MAX_PRESET_BYTES as usize - 1024);
+        let presets = (0..5)
+            .map(|index| {
+                (
+                    format!("filament/{}.json", index),
+                    serde_json::json!({"type": "filament", "name": index.to_string(), "padding": padding}),
+                )
+            })
+            .collect::<Vec<_>>();
+        let content = bundle_zip(
+            &presets
+                .iter()
+                .map(|(name, preset)| (name.as_str(), preset.clone()))
+                .collect::<Vec<_>>(),
+        );
+
+        let e = PresetBundle::parse(&content).unwrap_err();
+        assert!(e.to_string().contains("Preset bundle is too big"), "{:?}", e);
+    }
+}
@@ -6,9 +6,10 @@ use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 use tokio::process::Command;
 
+use super::{binary_version, find_binary, remote::SliceFormat, SlicerInvocation, SlicerNotFound};
 use crate::{
-    BuildOptions, DesignFile, GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, TemporaryFile,
-    ThreeMfSlicer as ThreeMfSlicerTrait, ThreeMfTemporaryFile,
+    spool_dir, BuildOptions, DesignFile, FilamentMaterial, GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile,
+    TemporaryFile, ThreeMfSlicer as ThreeMfSlicerTrait, ThreeMfTemporaryFile,
 };
 
 /// Handle to invoke the Prusa Slicer with some specific machine-specific config.
@@ -26,30 +27,72 @@ impl Slicer {
         }
     }
 
-    /// Generate gcode from some input file.
-    async fn generate_from_cli(
-        &self,
-        output_flag: &str,
-        output_extension: &str,
-        design_file: &DesignFile,
-    ) -> Result<TemporaryFile> {
-        // TODO: support 3mf and other export targets through new traits.
+    /// Check that PrusaSlicer is installed.
+    pub fn check_installed(&self) -> Result<(), SlicerNotFound> {
+        find_prusa_slicer().map(|_| ())
+    }
 
-        let uid = uuid::Uuid::new_v4();
-        let output_path = std::env::temp_dir().join(format!("{}.{}", uid.simple(), output_extension));
+    /// Return the version of PrusaSlicer, if it's installed.
+    pub async fn version(&self) -> Option<String> {
+        binary_version(&find_prusa_slicer().ok()?, "PrusaSlicer-").await
+    }
 
-        let (file_path, file_type) = match design_file {
-            DesignFile::Stl(path) => (path, "stl"),
-        };
+    /// Return the profile the slicer slices with.
+    pub fn profiles(&self) -> Vec<PathBuf> {
+        vec![self.config.clone()]
+    }
 
-        tracing::info!(
-            config = self.config.to_str(),
-            file_path = file_path.to_str(),
-            file_type = file_type,
-            "building to gcode"
-        );
+    /// Return the name of the profile, such as `mk3` for `mk3.ini`.
+    pub fn profile_name(&self) -> Option<String> {
+        Some(self.config.file_stem()?.to_string_lossy().into_owned())
+    }
+
+    /// Return the filament material this slicer profile is set up for, from
+    /// its `filament_type` setting.
+    pub async fn expected_material(&self) -> Result<Option<FilamentMaterial>> {
+        let config = tokio::fs::read_to_string(&self.config).await?;
+        Ok(parse_filament_type(&config))
+    }
+
+    /// Return exactly how PrusaSlicer would be run to slice `design_file`
+    /// to `format`, without running it.
+    pub async fn dry_run(
+        &self,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+        format: SliceFormat,
+    ) -> Result<SlicerInvocation> {
+        let (output_flag, output_extension) = match format {
+            SliceFormat::Gcode => ("--export-gcode", "gcode"),
+            SliceFormat::ThreeMf => ("--export-3mf", "3mf"),
+            SliceFormat::Form => anyhow::bail!("PrusaSlicer doesn't support form"),
+        };
+        let args = self
+            .args(
+                output_flag,
+                stl_path(design_file)?,
+                &output_path(output_extension),
+                options,
+            )
+            .await?;
+        let config = tokio::fs::read_to_string(&self.config).await?;
+        Ok(SlicerInvocation::new(
+            find_prusa_slicer().unwrap_or_else(|not_found| not_found.path),
+            args,
+            [(self.config.display().to_string(), config)].into(),
+        ))
+    }
 
-        let args: Vec<String> = vec![
+    /// Return the arguments to run PrusaSlicer with, to slice `file_path`
+    /// to `output_path`.
+    async fn args(
+        &self,
+        output_flag: &str,
+        file_path: &Path,
+        output_path: &Path,
+        options: &BuildOptions,
+    ) -> Result<Vec<String>> {
+        let mut args: Vec<String> = vec![
             "--load".to_string(),
             self.config
                 .to_str()
@@ -68,11 +111,61 @@ impl Slicer {
                 .to_string(),
         ];
 
+        let fdm = options.fdm().cloned().unwrap_or_default();
+        if fdm.start_gcode_extra.is_some() || fdm.end_gcode_extra.is_some() {
+            let config = tokio::fs::read_to_string(&self.config).await?;
+            for (key, extra) in [
+                ("start_gcode", &fdm.start_gcode_extra),
+                ("end_gcode", &fdm.end_gcode_extra),
+            ] {
+                if let Some(extra) = extra {
+                    args.push(format!("--{}", key.replace('_', "-")));
+                    args.push(append_ini_gcode(
+                        parse_ini_value(&config, key).unwrap_or_default(),
+                        extra,
+                    ));
+                }
+            }
+        }
+        Ok(args)
+    }
+
+    /// Generate gcode from some input file.
+    #[tracing::instrument(skip_all, fields(slicer = "prusa", output = output_extension))]
+    async fn generate_from_cli(
+        &self,
+        output_flag: &str,
+        output_extension: &str,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+    ) -> Result<TemporaryFile> {
+        // TODO: support 3mf and other export targets through new traits.
+
+        let output_path = output_path(output_extension);
+        let file_path = stl_path(design_file)?;
+        let file_type = "stl";
+
+        tracing::info!(
+            config = self.config.to_str(),
+            file_path = file_path.to_str(),
+            file_type = file_type,
+            "building to gcode"
+        );
+
+        let args = self.args(output_flag, file_path, &output_path, options).await?;
+
+        tracing::debug!(args = ?args, "running prusa-slicer");
+        let started = std::time::Instant::now();
         let output = Command::new(find_prusa_slicer()?)
             .args(&args)
             .output()
             .await
             .context("Failed to execute prusa-slicer command")?;
+        tracing::info!(
+            status = %output.status,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "prusa-slicer exited"
+        );
 
         // Make sure the command was successful.
         if !output.status.success() {
@@ -109,9 +202,10 @@ impl Slicer {
 impl GcodeSlicerTrait for Slicer {
     type Error = anyhow::Error;
 
-    async fn generate(&self, design_file: &DesignFile, _: &BuildOptions) -> Result<GcodeTemporaryFile> {
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<GcodeTemporaryFile> {
         Ok(GcodeTemporaryFile(
-            self.generate_from_cli("--export-gcode", "gcode", design_file).await?,
+            self.generate_from_cli("--export-gcode", "gcode", design_file, options)
+                .await?,
         ))
     }
 }
@@ -119,43 +213,158 @@ impl GcodeSlicerTrait for Slicer {
 impl ThreeMfSlicerTrait for Slicer {
     type Error = anyhow::Error;
 
-    async fn generate(&self, design_file: &DesignFile, _: &BuildOptions) -> Result<ThreeMfTemporaryFile> {
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<ThreeMfTemporaryFile> {
         Ok(ThreeMfTemporaryFile(
-            self.generate_from_cli("--export-3mf", "3mf", design_file).await?,
+            self.generate_from_cli("--export-3mf", "3mf", design_file, options)
+                .await?,
         ))
     }
 }
 
-// Find the prusaslicer executable path on macOS.
-#[cfg(target_os = "macos")]
-fn find_prusa_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("/Applications/PrusaSlicer.app/Contents/MacOS/PrusaSlicer");
-    if app_path.exists() {
-        Ok(app_path)
+/// Return where to write a sliced file with the extension `extension`.
+fn output_path(extension: &str) -> PathBuf {
+    spool_dir().join(format!("{}.{}", uuid::Uuid::new_v4().simple(), extension))
+}
+
+/// Return the path of the STL file to slice; PrusaSlicer can't slice
+/// projects.
+fn stl_path(design_file: &DesignFile) -> Result<&Path> {
+    match design_file {
+        DesignFile::Stl(path) => Ok(path),
+        DesignFile::ThreeMf(_) => anyhow::bail!("PrusaSlicer doesn't support multi-part projects"),
+    }
+}
+
+/// Find the `filament_type` setting in a PrusaSlicer `.ini` config. Multi
+/// extruder configs list one type per extruder (`PLA;PETG`), in which case
+/// the first is used.
+fn parse_filament_type(config: &str) -> Option<FilamentMaterial> {
+    let value = parse_ini_value(config, "filament_type")?;
+    let first = value.split(';').next()?.trim().trim_matches('"');
+    Some(FilamentMaterial::from_slicer_name(first))
+}
+
+/// Find the (still escaped) value of a setting in a PrusaSlicer `.ini`
+/// config.
+fn parse_ini_value<'a>(config: &'a str, key: &str) -> Option<&'a str> {
+    config.lines().find_map(|line| {
+        let (line_key, value) = line.split_once('=')?;
+        (line_key.trim() == key).then(|| value.trim())
+    })
+}
+
+/// Append `extra` to some gcode from a PrusaSlicer `.ini` config, where
+/// newlines (and backslashes) are escaped, on a line of its own.
+fn append_ini_gcode(gcode: &str, extra: &str) -> String {
+    let extra = extra.replace('\\', "\\\\").replace('\n', "\\n");
+    if gcode.is_empty() {
+        extra
     } else {
-        anyhow::bail!("Slicer not found")
+        format!("{}\\n{}", gcode, extra)
     }
 }
 
+// Find the prusaslicer executable path on macOS.
+#[cfg(target_os = "macos")]
+fn find_prusa_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary(
+        "PrusaSlicer",
+        "/Applications/PrusaSlicer.app/Contents/MacOS/PrusaSlicer",
+        None,
+    )
+}
+
 // Find the prusaslicer executable path on Windows.
 #[cfg(target_os = "windows")]
-fn find_prusa_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("C:\\Program Files\\PrusaSlicer\\PrusaSlicer.exe");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("Slicer not found")
-    }
+fn find_prusa_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary("PrusaSlicer", "C:\\Program Files\\PrusaSlicer\\PrusaSlicer.exe", None)
 }
 
 // Find the prusaslicer executable path on Linux.
 #[cfg(target_os = "linux")]
-fn find_prusa_slicer() -> Result<PathBuf> {
-    let app_path = PathBuf::from("/usr/bin/prusa-slicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        // Just assume it's somewhere on the path.
-        Ok(PathBuf::from("prusa-slicer"))
+fn find_prusa_slicer() -> Result<PathBuf, SlicerNotFound> {
+    find_binary("PrusaSlicer", "/usr/bin/prusa-slicer", Some("prusa-slicer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filament_type() {
+        assert_eq!(
+            parse_filament_type(include_str!("../../config/prusa/mk3.ini")),
+            Some(FilamentMaterial::Pla)
+        );
+        assert_eq!(
+            parse_filament_type("layer_height = 0.2\nfilament_type = PETG\n"),
+            Some(FilamentMaterial::Petg)
+        );
+        assert_eq!(
+            parse_filament_type("filament_type = \"PA-CF;PLA\""),
+            Some(FilamentMaterial::Composite)
+        );
+        assert_eq!(
+            parse_filament_type("filament_type = unobtainium"),
+            Some(FilamentMaterial::Unknown)
+        );
+        assert_eq!(parse_filament_type("layer_height = 0.2"), None);
+    }
+
+    #[test]
+    fn test_append_ini_gcode() {
+        let config = "end_gcode = M104 S0\\nM84 ; disable motors\nlayer_height = 0.2\n";
+        assert_eq!(
+            append_ini_gcode(parse_ini_value(config, "end_gcode").unwrap(), "M117 Done\nM300"),
+            "M104 S0\\nM84 ; disable motors\\nM117 Done\\nM300"
+        );
+        assert_eq!(append_ini_gcode("", "M117 Hi"), "M117 Hi");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let config = Path::new(env!("CARGO_MANIFEST_DIR")).join("config/prusa/mk3.ini");
+        let slicer = Slicer::new(&config);
+        let options = BuildOptions {
+            hardware_configuration: crate::HardwareConfiguration::None,
+            slicer_configuration: Default::default(),
+            make_model: crate::MachineMakeModel {
+                manufacturer: None,
+                model: None,
+                serial: None,
+            },
+            machine_type: crate::MachineType::FusedDeposition,
+            max_part_volume: None,
+            process: crate::ProcessOptions::Fdm(crate::FdmOptions {
+                start_gcode_extra: None,
+                end_gcode_extra: Some("M117 Done".to_owned()),
+            }),
+        };
+        let design_file = DesignFile::Stl(PathBuf::from("/spool/benchy.stl"));
+
+        let invocation = slicer
+            .dry_run(&design_file, &options, SliceFormat::Gcode)
+            .await
+            .unwrap();
+        assert_eq!(
+            invocation.args[..5],
+            [
+                "--load",
+                config.to_str().unwrap(),
+                "--support-material",
+                "--export-gcode",
+                "/spool/benchy.stl"
+            ]
+        );
+        assert_eq!(invocation.args[7], "--end-gcode");
+        assert!(invocation.args[8].ends_with("\\nM117 Done"));
+        assert_eq!(
+            invocation.profiles[config.to_str().unwrap()],
+            include_str!("../../config/prusa/mk3.ini")
+        );
+        // Nothing was written.
+        assert!(!Path::new(&invocation.args[6]).exists());
+
+        assert!(slicer.dry_run(&design_file, &options, SliceFormat::Form).await.is_err());
     }
 }
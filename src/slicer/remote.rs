@@ -0,0 +1,225 @@
+//! Delegate slicing to another machine-api node (or anything else speaking
+//! the same protocol), for hosts too slow to run a slicer themselves.
+//!
+//! The design file is POSTed to the worker's `/v1/slice` endpoint, along with
+//! the name of one of the worker's configured slicers, and the sliced file
+//! comes back in the response body.
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    spool_dir, BuildOptions, DesignFile, FormSlicer as FormSlicerTrait, FormTemporaryFile,
+    GcodeSlicer as GcodeSlicerTrait, GcodeTemporaryFile, TemporaryFile, ThreeMfSlicer as ThreeMfSlicerTrait,
+    ThreeMfTemporaryFile,
+};
+
+/// The kind of file to slice a design into.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SliceFormat {
+    /// GCode.
+    Gcode,
+
+    /// A 3MF project, with embedded gcode.
+    ThreeMf,
+
+    /// A Formlabs `.form` file.
+    Form,
+}
+
+impl SliceFormat {
+    /// Return the file extension for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gcode => "gcode",
+            Self::ThreeMf => "3mf",
+            Self::Form => "form",
+        }
+    }
+}
+
+/// Parameters for a request to slice a design on a worker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SliceParameters {
+    /// Name of the slicer, as configured on the worker.
+    pub slicer: String,
+
+    /// The kind of file to produce.
+    pub format: SliceFormat,
+
+    /// Options for the machine the file is being sliced for.
+    pub options: BuildOptions,
+
+    /// Rather than slicing, return exactly how the slicer would be run
+    /// (its command line, merged profiles and environment) as JSON.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Handle to slice on a remote worker.
+#[derive(Clone, Debug)]
+pub struct Slicer {
+    endpoint: String,
+    slicer: String,
+    client: reqwest::Client,
+}
+
+impl Slicer {
+    /// Create a new [Slicer], which will ask the worker at `endpoint` (such
+    /// as `http://192.168.1.20:8585`) to slice with its slicer named
+    /// `slicer`.
+    pub fn new(endpoint: &str, slicer: &str) -> Self {
+        tracing::debug!(endpoint = endpoint, slicer = slicer, "new");
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            slicer: slicer.to_owned(),
+            client: crate::http_client(),
+        }
+    }
+
+    /// Return the name of the worker's slicer this slices with.
+    pub fn profile_name(&self) -> Option<String> {
+        Some(self.slicer.clone())
+    }
+
+    /// Upload the design file to the worker, and write the sliced file it
+    /// returns to a temporary file.
+    async fn generate_remote(
+        &self,
+        format: SliceFormat,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+    ) -> Result<TemporaryFile> {
+        // The worker goes by the file's extension, so projects are sliced
+        // as projects.
+        let (DesignFile::Stl(file_path) | DesignFile::ThreeMf(file_path)) = design_file;
+        let file_name = file_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("file.stl")
+            .to_owned();
+
+        let params = SliceParameters {
+            slicer: self.slicer.clone(),
+            format,
+            options: options.clone(),
+            dry_run: false,
+        };
+
+        tracing::info!(
+            endpoint = self.endpoint,
+            slicer = self.slicer,
+            file_path = file_path.to_str(),
+            "slicing on remote worker"
+        );
+
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "file",
+                reqwest::multipart::Part::bytes(tokio::fs::read(file_path).await?).file_name(file_name),
+            )
+            .part(
+                "params",
+                reqwest::multipart::Part::text(serde_json::to_string(&params)?).mime_str("application/json")?,
+            );
+
+        let response = self
+            .client
+            .post(format!("{}/v1/slice", self.endpoint))
+            .multipart(form)
+            .send()
+            .await
+            .context("Failed to reach slicing worker")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Slicing worker failed: {}\n{}", status, body);
+        }
+
+        let output_path = spool_dir().join(format!("{}.{}", uuid::Uuid::new_v4().simple(), format.extension()));
+        tokio::fs::write(&output_path, response.bytes().await?).await?;
+
+        tracing::info!(
+            endpoint = self.endpoint,
+            slicer = self.slicer,
+            output_path = output_path.to_str(),
+            "sliced on remote worker"
+        );
+
+        TemporaryFile::new(&output_path).await
+    }
+}
+
+impl GcodeSlicerTrait for Slicer {
+    type Error = anyhow::Error;
+
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<GcodeTemporaryFile> {
+        Ok(GcodeTemporaryFile(
+            self.generate_remote(SliceFormat::Gcode, design_file, options).await?,
+        ))
+    }
+}
+
+impl ThreeMfSlicerTrait for Slicer {
+    type Error = anyhow::Error;
+
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<ThreeMfTemporaryFile> {
+        Ok(ThreeMfTemporaryFile(
+            self.generate_remote(SliceFormat::ThreeMf, design_file, options).await?,
+        ))
+    }
+}
+
+impl FormSlicerTrait for Slicer {
+    type Error = anyhow::Error;
+
+    async fn generate(&self, design_file: &DesignFile, options: &BuildOptions) -> Result<FormTemporaryFile> {
+        Ok(FormTemporaryFile(
+            self.generate_remote(SliceFormat::Form, design_file, options).await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_trims_endpoint() {
+        let slicer = Slicer::new("http://192.168.1.20:8585/", "mk3");
+        assert_eq!(slicer.endpoint, "http://192.168.1.20:8585");
+        assert_eq!(slicer.slicer, "mk3");
+    }
+
+    #[test]
+    fn test_slice_parameters_process() {
+        let params: SliceParameters = serde_json::from_value(serde_json::json!({
+            "slicer": "form4",
+            "format": "form",
+            "options": {
+                "hardware_configuration": {"type": "none"},
+                "slicer_configuration": {},
+                "make_model": {},
+                "machine_type": "stereolithography",
+                "max_part_volume": null,
+                "process": {"type": "sla", "layer_thickness_mm": 0.05, "support_density": 0.0},
+            },
+        }))
+        .unwrap();
+        assert!(params.options.fdm().is_none());
+        let sla = params.options.sla().unwrap();
+        assert_eq!(sla.layer_thickness_mm, Some(0.05));
+        assert_eq!(sla.support_density, Some(0.0));
+        assert_eq!(sla.exposure_time_seconds, None);
+
+        // Workers which don't send the process are taken to be slicing for
+        // FDM machines.
+        let mut options = serde_json::to_value(&params.options).unwrap();
+        options.as_object_mut().unwrap().remove("process");
+        let options: BuildOptions = serde_json::from_value(options).unwrap();
+        assert_eq!(options.fdm(), Some(&Default::default()));
+    }
+}
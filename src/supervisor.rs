@@ -0,0 +1,224 @@
+//! Supervision of long-running backend tasks (such as discovery, or a
+//! machine's connection), so one which fails or panics is logged and
+//! restarted, rather than taking the process down or silently stopping
+//! updates.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family},
+    registry::Registry,
+};
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::server::{Event, Events};
+
+/// How long to wait before restarting a task the first time it fails.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest to wait before restarting a task which keeps failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a task has to run before failing for its backoff to be reset.
+const HEALTHY_RUN: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct TaskLabels {
+    task: String,
+    machine_id: String,
+}
+
+/// Runs backend tasks, restarting them with backoff if they fail or
+/// panic. Restarts are counted in the `machine_api_task_restarts` metric,
+/// and emitted as [Event::TaskRestarted] events.
+#[derive(Clone, Default)]
+pub struct Supervisor {
+    restarts: Family<TaskLabels, Counter>,
+    events: Option<Arc<Events>>,
+}
+
+impl Supervisor {
+    /// Return a new Supervisor, registering its metrics in `registry`.
+    pub fn new(registry: &mut Registry) -> Self {
+        let restarts = Family::<TaskLabels, Counter>::default();
+        registry.register(
+            "machine_api_task_restarts",
+            "Backend tasks restarted after failing or panicking, by task and machine",
+            restarts.clone(),
+        );
+
+        Self { restarts, events: None }
+    }
+
+    /// Emit an event to `events` whenever a task is restarted.
+    pub fn with_events(mut self, events: Arc<Events>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Spawn the task `name` (for the machine `machine_id`, if it's only for
+    /// one), started by calling `start`, and start it again whenever it
+    /// fails or panics. Tasks which return successfully are left stopped,
+    /// as are tasks whose returned handle is aborted.
+    pub fn spawn<StartT, FutureT>(&self, name: &str, machine_id: Option<&str>, mut start: StartT) -> JoinHandle<()>
+    where
+        StartT: FnMut() -> FutureT + Send + 'static,
+        FutureT: Future<Output = Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_owned();
+        let machine_id = machine_id.map(str::to_owned);
+        tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let attempt = tokio::spawn(start());
+                let _abort = AbortOnDrop(attempt.abort_handle());
+                let error = match attempt.await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => format!("{:?}", e),
+                    Err(e) if e.is_panic() => format!("panicked: {}", panic_message(e.into_panic())),
+                    // Only cancelled along with the runtime.
+                    Err(_) => return,
+                };
+
+                if started.elapsed() > HEALTHY_RUN {
+                    backoff = INITIAL_BACKOFF;
+                }
+                let restarts = supervisor.record_restart(&name, machine_id.as_deref(), &error);
+                tracing::error!(
+                    task = name,
+                    machine_id = machine_id,
+                    restarts = restarts,
+                    error = error,
+                    "backend task failed, restarting in {:?}",
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        })
+    }
+
+    /// Count a restart of the task `name`, emitting an event for it, and
+    /// return how many times it's been restarted.
+    fn record_restart(&self, name: &str, machine_id: Option<&str>, error: &str) -> u64 {
+        let restarts = self
+            .restarts
+            .get_or_create(&TaskLabels {
+                task: name.to_owned(),
+                machine_id: machine_id.unwrap_or_default().to_owned(),
+            })
+            .inc()
+            + 1;
+
+        if let Some(events) = &self.events {
+            events.emit(Event::TaskRestarted {
+                task: name.to_owned(),
+                machine_id: machine_id.map(str::to_owned),
+                restarts,
+                error: error.to_owned(),
+            });
+        }
+        restarts
+    }
+}
+
+/// Aborts a task when dropped, so a supervised task's current attempt is
+/// stopped along with it.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Return the message a task panicked with, if it was a string.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => (*message).to_owned(),
+            Err(_) => "(no message)".to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restarts_panicking_task() {
+        let mut registry = Registry::default();
+        let supervisor = Supervisor::new(&mut registry);
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let attempts1 = attempts.clone();
+        supervisor
+            .spawn("flaky", Some("machine"), move || {
+                let attempt = attempts1.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if attempt == 0 {
+                        panic!("oh no");
+                    }
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            supervisor
+                .restarts
+                .get_or_create(&TaskLabels {
+                    task: "flaky".to_owned(),
+                    machine_id: "machine".to_owned(),
+                })
+                .get(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort_stops_task() {
+        let supervisor = Supervisor::default();
+        let (started_send, started_recv) = tokio::sync::oneshot::channel();
+        let (stopped_send, stopped_recv) = tokio::sync::oneshot::channel::<()>();
+        let channels = Arc::new(std::sync::Mutex::new(Some((started_send, stopped_send))));
+
+        let task = supervisor.spawn("forever", Some("machine"), move || {
+            let channels = channels.lock().unwrap().take();
+            async move {
+                let Some((started, _stopped)) = channels else {
+                    panic!("restarted");
+                };
+                started.send(()).unwrap();
+                std::future::pending::<()>().await;
+                Ok(())
+            }
+        });
+
+        started_recv.await.unwrap();
+        task.abort();
+        // The attempt is dropped, and its end of the channel with it.
+        assert!(stopped_recv.await.is_err());
+    }
+
+    #[test]
+    fn test_panic_message() {
+        assert_eq!(panic_message(Box::new("oh no")), "oh no");
+        assert_eq!(panic_message(Box::new(format!("oh {}", "no"))), "oh no");
+        assert_eq!(panic_message(Box::new(1)), "(no message)");
+    }
+}
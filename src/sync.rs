@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use tokio::sync::Mutex;
 
-use crate::{Control, HardwareConfiguration, MachineState};
+use crate::{Control, HardwareConfiguration, LayerProgress, MachineState};
 
 /// Wrapper around an `Arc<Mutex<Control>>`, which helpfully will handle
 /// the locking to expose a [Control] without the caller having to care
@@ -48,6 +48,9 @@ where
     async fn progress(&self) -> Result<Option<f64>, Self::Error> {
         self.0.lock().await.progress().await
     }
+    async fn layer_progress(&self) -> Result<Option<LayerProgress>, Self::Error> {
+        self.0.lock().await.layer_progress().await
+    }
     async fn hardware_configuration(&self) -> Result<HardwareConfiguration, Self::Error> {
         self.0.lock().await.hardware_configuration().await
     }
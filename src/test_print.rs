@@ -0,0 +1,237 @@
+//! Test prints for commissioning a new machine. These are simple models,
+//! generated as STL on the fly, each exercising a particular part of the
+//! machine, so nobody has to go find and upload a file to check it works.
+
+use std::fmt::Write;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::TemperatureSteps;
+
+/// How many tiers the temperature tower has.
+const TOWER_TIERS: u8 = 5;
+
+/// How tall each tier of the temperature tower is, in millimeters,
+/// including the neck above it.
+const TOWER_TIER_MM: f64 = 10.0;
+
+/// A test print which can be sent to a machine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TestPrint {
+    /// A 20mm cube, to check dimensional accuracy.
+    CalibrationCube,
+
+    /// A single-layer square in each corner of a 150mm area, and one in the
+    /// middle, to check the bed is level.
+    BedLevel,
+
+    /// A tower of five 10mm tiers, separated by narrow necks, to compare
+    /// how a filament prints as the nozzle temperature is stepped at each
+    /// tier: by default, down from 230°C, 5°C a tier, which suits PLA.
+    TemperatureTower,
+}
+
+/// An axis-aligned box, from `min` to `max` (in millimeters).
+struct Cuboid {
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+impl TestPrint {
+    /// Return the name of the test print, used as the job and file name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CalibrationCube => "calibration-cube",
+            Self::BedLevel => "bed-level",
+            Self::TemperatureTower => "temperature-tower",
+        }
+    }
+
+    /// Return the name of the STL file for this test print.
+    pub fn file_name(&self) -> String {
+        format!("{}.stl", self.name())
+    }
+
+    /// Return the model for this test print, as an ASCII STL.
+    pub fn stl(&self) -> String {
+        let cuboids = match self {
+            Self::CalibrationCube => vec![Cuboid {
+                min: [0.0, 0.0, 0.0],
+                max: [20.0, 20.0, 20.0],
+            }],
+            Self::BedLevel => [[0.0, 0.0], [125.0, 0.0], [0.0, 125.0], [125.0, 125.0], [62.5, 62.5]]
+                .into_iter()
+                .map(|[x, y]| Cuboid {
+                    min: [x, y, 0.0],
+                    max: [x + 25.0, y + 25.0, 0.3],
+                })
+                .collect(),
+            Self::TemperatureTower => (0..TOWER_TIERS)
+                .flat_map(|tier| {
+                    let z = f64::from(tier) * TOWER_TIER_MM;
+                    [
+                        Cuboid {
+                            min: [0.0, 0.0, z],
+                            max: [20.0, 20.0, z + 9.0],
+                        },
+                        Cuboid {
+                            min: [4.0, 4.0, z + 9.0],
+                            max: [16.0, 16.0, z + TOWER_TIER_MM],
+                        },
+                    ]
+                })
+                .collect(),
+        };
+        write_stl(self.name(), &cuboids)
+    }
+
+    /// Return how the nozzle temperature is stepped up this test print, if
+    /// it is.
+    pub fn temperature_steps(&self) -> Option<TemperatureSteps> {
+        match self {
+            Self::CalibrationCube | Self::BedLevel => None,
+            Self::TemperatureTower => Some(TemperatureSteps {
+                start_celsius: 230,
+                step_celsius: -5,
+                every_mm: TOWER_TIER_MM,
+            }),
+        }
+    }
+}
+
+/// Write `cuboids` out as a single ASCII STL solid.
+fn write_stl(name: &str, cuboids: &[Cuboid]) -> String {
+    let mut stl = format!("solid {}\n", name);
+    for cuboid in cuboids {
+        let [x0, y0, z0] = cuboid.min;
+        let [x1, y1, z1] = cuboid.max;
+        let v = |x: bool, y: bool, z: bool| {
+            [
+                if x { x1 } else { x0 },
+                if y { y1 } else { y0 },
+                if z { z1 } else { z0 },
+            ]
+        };
+
+        // Two triangles per face, wound counter-clockwise seen from
+        // outside, along with the outward normal.
+        let faces = [
+            (
+                [0.0, 0.0, -1.0],
+                [
+                    v(false, false, false),
+                    v(false, true, false),
+                    v(true, true, false),
+                    v(true, false, false),
+                ],
+            ),
+            (
+                [0.0, 0.0, 1.0],
+                [
+                    v(false, false, true),
+                    v(true, false, true),
+                    v(true, true, true),
+                    v(false, true, true),
+                ],
+            ),
+            (
+                [0.0, -1.0, 0.0],
+                [
+                    v(false, false, false),
+                    v(true, false, false),
+                    v(true, false, true),
+                    v(false, false, true),
+                ],
+            ),
+            (
+                [0.0, 1.0, 0.0],
+                [
+                    v(false, true, false),
+                    v(false, true, true),
+                    v(true, true, true),
+                    v(true, true, false),
+                ],
+            ),
+            (
+                [-1.0, 0.0, 0.0],
+                [
+                    v(false, false, false),
+                    v(false, false, true),
+                    v(false, true, true),
+                    v(false, true, false),
+                ],
+            ),
+            (
+                [1.0, 0.0, 0.0],
+                [
+                    v(true, false, false),
+                    v(true, true, false),
+                    v(true, true, true),
+                    v(true, false, true),
+                ],
+            ),
+        ];
+        for (normal, [a, b, c, d]) in faces {
+            for triangle in [[a, b, c], [a, c, d]] {
+                let _ = writeln!(stl, "  facet normal {} {} {}", normal[0], normal[1], normal[2]);
+                stl.push_str("    outer loop\n");
+                for [x, y, z] in triangle {
+                    let _ = writeln!(stl, "      vertex {} {} {}", x, y, z);
+                }
+                stl.push_str("    endloop\n");
+                stl.push_str("  endfacet\n");
+            }
+        }
+    }
+    let _ = writeln!(stl, "endsolid {}", name);
+    stl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calibration_cube() {
+        let stl = TestPrint::CalibrationCube.stl();
+
+        assert!(stl.starts_with("solid calibration-cube\n"));
+        assert!(stl.ends_with("endsolid calibration-cube\n"));
+        assert_eq!(stl.matches("facet normal").count(), 12);
+        assert!(stl.contains("vertex 20 20 20\n"));
+    }
+
+    #[test]
+    fn test_facet_counts() {
+        assert_eq!(TestPrint::BedLevel.stl().matches("facet normal").count(), 5 * 12);
+        assert_eq!(
+            TestPrint::TemperatureTower.stl().matches("facet normal").count(),
+            10 * 12
+        );
+    }
+
+    #[test]
+    fn test_temperature_tower_steps() {
+        let steps = TestPrint::TemperatureTower.temperature_steps().unwrap();
+
+        // The temperature changes at the first layer of each tier.
+        // 0.2mm layers, with their heights written out as slicers do.
+        let gcode = (1..=250)
+            .map(|layer| format!(";LAYER_CHANGE\n;Z:{}.{}\nG1 X1 E1\n", layer * 2 / 10, layer * 2 % 10))
+            .collect::<String>();
+        let gcode = steps.insert(&gcode);
+        for (tier, temperature) in [230, 225, 220, 215, 210].into_iter().enumerate() {
+            let first_layer = format!(
+                ";LAYER_CHANGE\nM104 S{} ; temperature step\n;Z:{}.2\n",
+                temperature,
+                tier * 10
+            );
+            assert!(gcode.contains(&first_layer), "{}", first_layer);
+        }
+        assert_eq!(gcode.matches("M104").count(), 5);
+
+        assert_eq!(TestPrint::CalibrationCube.temperature_steps(), None);
+    }
+}
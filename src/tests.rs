@@ -19,6 +19,22 @@ struct ServerContext {
 
 impl ServerContext {
     pub async fn new() -> Result<Self> {
+        Self::with_read_only(false).await
+    }
+
+    pub async fn with_read_only(read_only: bool) -> Result<Self> {
+        Self::with_options(read_only, Default::default()).await
+    }
+
+    pub async fn with_options(read_only: bool, api_keys: crate::server::ApiKeys) -> Result<Self> {
+        Self::with_machines(read_only, api_keys, HashMap::new()).await
+    }
+
+    pub async fn with_machines(
+        read_only: bool,
+        api_keys: crate::server::ApiKeys,
+        machines: HashMap<String, RwLock<crate::Machine>>,
+    ) -> Result<Self> {
         // Find an unused port.
         let port = portpicker::pick_unused_port().ok_or_else(|| anyhow::anyhow!("no port available"))?;
         let bind = format!("127.0.0.1:{}", port);
@@ -27,8 +43,27 @@ impl ServerContext {
         // Create the server in debug mode.
         let (server, _context) = crate::server::create_server(
             &bind,
-            Arc::new(RwLock::new(HashMap::new())),
+            Arc::new(RwLock::new(machines)),
             Arc::new(RwLock::new(registry)),
+            Arc::new(crate::server::Events::default()),
+            HashMap::from([("noop".to_owned(), crate::slicer::noop::Slicer::new().into())]),
+            crate::slicer::profiles::ProfileStore::new(
+                &std::env::temp_dir().join(format!("profiles-{}", uuid::Uuid::new_v4().simple())),
+            ),
+            Some(std::env::temp_dir().join(format!("jobs-{}", uuid::Uuid::new_v4().simple()))),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            crate::server::FileUrls {
+                allowed_schemes: vec!["http".to_owned()],
+                allowed_hosts: vec!["127.0.0.1".to_owned()],
+                max_bytes: 1024 * 1024,
+                timeout_seconds: 10,
+            },
+            Default::default(),
+            read_only,
+            api_keys,
+            Default::default(),
         )
         .await?;
 
@@ -157,3 +192,1225 @@ async fn test_ping(ctx: &mut ServerContext) -> TestResult {
 
     Ok(())
 }
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_versions(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("versions")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let versions: serde_json::Value = response.json().await?;
+    assert_eq!(versions["current"], "v1");
+
+    // The unversioned routes still work, but point at their replacement.
+    let response = ctx.client.get(ctx.get_url("jobs")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()["deprecation"], "true");
+    assert!(response.headers().contains_key("sunset"));
+    assert_eq!(response.headers()["link"], r#"</v1/jobs>; rel="successor-version""#);
+    assert_eq!(response.text().await?, "[]");
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs")).send().await?;
+
+    assert!(!response.headers().contains_key("deprecation"));
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_jobs(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/jobs")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await?, "[]");
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs/nope")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs/nope/snapshot")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs/nope/manifest")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs/nope/events")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.post(ctx.get_url("v1/jobs/nope/cancel")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_queue(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/queue")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await?, "[]");
+
+    let response = ctx
+        .client
+        .put(ctx.get_url("v1/queue/nope"))
+        .json(&serde_json::json!({"position": 1}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx
+        .client
+        .put(ctx.get_url("v1/queue/nope"))
+        .json(&serde_json::json!({"position": 0}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx.client.delete(ctx.get_url("v1/queue/nope")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machines_etag(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/machines")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let etag = response.headers()["etag"].clone();
+    assert_eq!(response.text().await?, "[]");
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .header("if-none-match", etag.clone())
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()["etag"], etag);
+    assert_eq!(response.text().await?, "");
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .header("if-none-match", "\"something-else\"")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_register_machine(ctx: &mut ServerContext) -> TestResult {
+    let registration = serde_json::json!({
+        "id": "loaner",
+        "display_name": "Loaner",
+        "ttl_seconds": 3600,
+        "config": {
+            "type": "Noop",
+            "nozzle_diameter": 0.4,
+            "filaments": [{"material": {"type": "pla"}}],
+            "state": {"state": "idle"},
+        },
+    });
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&registration)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["id"], "loaner");
+    assert!(body["expires_at"].is_string());
+
+    let response = ctx.client.get(ctx.get_url("v1/machines/loaner")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // The id is taken now.
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&registration)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&serde_json::json!({"id": "bambu", "config": {"type": "Bambu"}}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx.client.post(ctx.get_url("v1/machines/loaner/stop")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx.client.delete(ctx.get_url("v1/machines/loaner")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx.client.get(ctx.get_url("v1/machines/loaner")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.delete(ctx.get_url("v1/machines/loaner")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.post(ctx.get_url("v1/machines/loaner/stop")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_pause_resume(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&serde_json::json!({
+            "id": "printing",
+            "config": {
+                "type": "Noop",
+                "nozzle_diameter": 0.4,
+                "filaments": [{"material": {"type": "pla"}}],
+                "state": {"state": "running"},
+            },
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/printing/pause"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // The no-op machine stays running, so there's nothing to resume.
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/printing/resume"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["error_code"], "MachineNotPaused");
+
+    let response = ctx.client.post(ctx.get_url("v1/machines/missing/pause")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_temperatures(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&serde_json::json!({
+            "id": "noop",
+            "config": {
+                "type": "Noop",
+                "nozzle_diameter": 0.4,
+                "filaments": [{"material": {"type": "pla"}}],
+            },
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // The no-op machine has no sensors.
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/noop/temperatures"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await?, "{}");
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/nope/temperatures"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_snapshot(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&serde_json::json!({
+            "id": "noop",
+            "config": {
+                "type": "Noop",
+                "nozzle_diameter": 0.4,
+                "filaments": [{"material": {"type": "pla"}}],
+            },
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // The no-op machine has no camera.
+    let response = ctx.client.get(ctx.get_url("v1/machines/noop/snapshot")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["error_code"], "NoCamera");
+
+    let response = ctx.client.get(ctx.get_url("v1/machines/nope/snapshot")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Nor can it be watched live.
+    let response = ctx.client.get(ctx.get_url("v1/machines/noop/stream")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["error_code"], "NoCamera");
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_reservation(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines"))
+        .json(&serde_json::json!({
+            "id": "noop",
+            "config": {
+                "type": "Noop",
+                "nozzle_diameter": 0.4,
+                "filaments": [{"material": {"type": "pla"}}],
+            },
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/noop/reserve"))
+        .json(&serde_json::json!({"ttl_seconds": 600, "holder": "sam", "reason": "filament change"}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["reservation"]["holder"], "sam");
+    assert_eq!(body["reservation"]["reason"], "filament change");
+
+    // Someone else can't take it over.
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/noop/reserve"))
+        .json(&serde_json::json!({"ttl_seconds": 600, "holder": "alex"}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["error_code"], "MachineReserved");
+
+    let response = ctx.client.get(ctx.get_url("v1/machines/noop")).send().await?;
+
+    let body: serde_json::Value = response.json().await?;
+    assert_eq!(body["reservation"]["holder"], "sam");
+
+    let response = ctx
+        .client
+        .delete(ctx.get_url("v1/machines/noop/reserve"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body: serde_json::Value = response.json().await?;
+    assert!(body["reservation"].is_null());
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/noop/reserve"))
+        .json(&serde_json::json!({"ttl_seconds": 0}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/nope/reserve"))
+        .json(&serde_json::json!({"ttl_seconds": 600}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_test_print(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/nope/test-print"))
+        .json(&serde_json::json!({"test_print": "calibration_cube"}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_print_file_url(ctx: &mut ServerContext) -> TestResult {
+    let print = |params: serde_json::Value| {
+        let form = reqwest::multipart::Form::new().part("params", reqwest::multipart::Part::text(params.to_string()));
+        ctx.client.post(ctx.get_url("v1/print")).multipart(form).send()
+    };
+
+    // Neither a file nor a URL.
+    let response = print(serde_json::json!({"machine_id": "nope", "job_name": "benchy"})).await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // Not an allowed host.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "benchy",
+        "file_url": "http://localhost/benchy.stl",
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Downloaded, but there's no such machine.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "benchy",
+        "file_url": ctx.get_url("ping"),
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_files(ctx: &mut ServerContext) -> TestResult {
+    let form = reqwest::multipart::Form::new().part(
+        "file",
+        reqwest::multipart::Part::text(crate::TestPrint::CalibrationCube.stl()).file_name("cube.stl"),
+    );
+    let response = ctx.client.post(ctx.get_url("v1/files")).multipart(form).send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let stored: crate::server::StoredFile = response.json().await?;
+    assert_eq!(stored.file_name, "cube.stl");
+
+    let response = ctx.client.get(ctx.get_url("v1/files")).send().await?;
+    let files: Vec<crate::server::StoredFile> = response.json().await?;
+    assert_eq!(files, vec![stored.clone()]);
+
+    let print = |params: serde_json::Value| {
+        let form = reqwest::multipart::Form::new().part("params", reqwest::multipart::Part::text(params.to_string()));
+        ctx.client.post(ctx.get_url("v1/print")).multipart(form).send()
+    };
+
+    // Read from the library, but there's no such machine.
+    let response = print(serde_json::json!({"machine_id": "nope", "job_name": "cube", "file_id": stored.id})).await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Both a stored file and a URL.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "cube",
+        "file_id": stored.id,
+        "file_url": ctx.get_url("ping"),
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx
+        .client
+        .delete(ctx.get_url(&format!("v1/files/{}", stored.id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let response = ctx
+        .client
+        .delete(ctx.get_url(&format!("v1/files/{}", stored.id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = print(serde_json::json!({"machine_id": "nope", "job_name": "cube", "file_id": stored.id})).await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_print_parts(ctx: &mut ServerContext) -> TestResult {
+    let print = |params: serde_json::Value| {
+        let cube = || reqwest::multipart::Part::text(crate::TestPrint::CalibrationCube.stl());
+        let form = reqwest::multipart::Form::new()
+            .part("file", cube().file_name("bracket.stl"))
+            .part("file", cube().file_name("cover.stl"))
+            .part("params", reqwest::multipart::Part::text(params.to_string()));
+        ctx.client.post(ctx.get_url("v1/print")).multipart(form).send()
+    };
+
+    // Settings for only one of the two parts.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "assembly",
+        "parts": [{"infill_density_percent": 40.0}],
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // Packaged, but there's no such machine.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "assembly",
+        "parts": [{"infill_density_percent": 40.0}, {"wall_loops": 4}],
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Each file is a single body, so there's nothing to split.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "assembly",
+        "multiple_bodies": "split",
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Not something to do with multiple bodies.
+    let response = print(serde_json::json!({
+        "machine_id": "nope",
+        "job_name": "assembly",
+        "multiple_bodies": "merge",
+    }))
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_job_events(ctx: &mut ServerContext) -> TestResult {
+    let jobs = ctx.server.app_private().jobs.clone();
+    jobs.create("job", "noop", "benchy").await;
+    jobs.start_phase("job", crate::server::JobPhase::Slice).await;
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs/job/events")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    // The stream ends once the job's finished.
+    jobs.start_phase("job", crate::server::JobPhase::Print).await;
+    jobs.complete("job").await;
+    let body = response.text().await?;
+    let events = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("event: "))
+        .collect::<Vec<_>>();
+    assert_eq!(events.last(), Some(&"finished"));
+    assert!(body.contains(r#""type":"finished","state":"completed""#), "{}", body);
+
+    // Finished jobs say how they finished, and nothing more.
+    let response = ctx.client.get(ctx.get_url("v1/jobs/job/events")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert!(response.text().await?.starts_with("event: finished\n"));
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_id_type(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/00M09A350100123?id_type=serial"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/00M09A350100123?id_type=nope"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_machine_id_type_found() -> TestResult {
+    let config: crate::noop::Config = serde_json::from_value(serde_json::json!({
+        "nozzle_diameter": 0.4,
+        "filaments": [{"material": {"type": "pla"}}],
+        "loaded_filament_idx": null,
+        "state": {"state": "idle"},
+        "progress": null,
+    }))?;
+    let mut machine = crate::Machine::new(
+        crate::noop::Noop::new(
+            config,
+            crate::MachineMakeModel {
+                manufacturer: Some("Zoo Corporation".to_owned()),
+                model: Some("Null Machine".to_owned()),
+                serial: Some("00M09A350100123".to_owned()),
+            },
+            crate::MachineType::FusedDeposition,
+            None,
+        ),
+        crate::slicer::noop::Slicer::new(),
+    );
+    machine.set_aliases(vec!["old-x1c".to_owned()]);
+    machine.load_serial().await?;
+    let ctx = ServerContext::with_machines(
+        false,
+        Default::default(),
+        HashMap::from([("x1c".to_owned(), RwLock::new(machine))]),
+    )
+    .await?;
+
+    for path in [
+        "v1/machines/00M09A350100123?id_type=serial",
+        "v1/machines/00m09a350100123?id_type=serial",
+        "v1/machines/00M09A350100123",
+        "v1/machines/old-x1c?id_type=id",
+        "v1/machines/old-x1c",
+    ] {
+        let response = ctx.client.get(ctx.get_url(path)).send().await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "{}", path);
+        assert_eq!(response.json::<serde_json::Value>().await?["id"], "x1c", "{}", path);
+    }
+
+    // Each kind of identifier only matches itself.
+    for path in [
+        "v1/machines/old-x1c?id_type=serial",
+        "v1/machines/00M09A350100123?id_type=id",
+    ] {
+        let response = ctx.client.get(ctx.get_url(path)).send().await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND, "{}", path);
+    }
+
+    ctx.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_machine_display_name_ambiguous() -> TestResult {
+    let mut machines = HashMap::new();
+    for id in ["mk3-a", "mk3-b"] {
+        let config: crate::noop::Config = serde_json::from_value(serde_json::json!({
+            "nozzle_diameter": 0.4,
+            "filaments": [{"material": {"type": "pla"}}],
+            "loaded_filament_idx": null,
+            "state": {"state": "idle"},
+            "progress": null,
+        }))?;
+        let mut machine = crate::Machine::new(
+            crate::noop::Noop::new(
+                config,
+                crate::MachineMakeModel {
+                    manufacturer: Some("Zoo Corporation".to_owned()),
+                    model: Some("Null Machine".to_owned()),
+                    serial: None,
+                },
+                crate::MachineType::FusedDeposition,
+                None,
+            ),
+            crate::slicer::noop::Slicer::new(),
+        );
+        machine.set_display_name(Some("Rack 3 MK3".to_owned()));
+        machines.insert(id.to_owned(), RwLock::new(machine));
+    }
+    let ctx = ServerContext::with_machines(false, Default::default(), machines).await?;
+
+    // Neither machine is picked when both go by the name.
+    let response = ctx.client.get(ctx.get_url("v1/machines/Rack%203%20MK3")).send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+
+    let response = ctx.client.get(ctx.get_url("v1/machines/mk3-b")).send().await?;
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.json::<serde_json::Value>().await?["id"], "mk3-b");
+
+    ctx.stop().await?;
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_job(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/machines/nope/job")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_accessories(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/nope/accessories"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/nope/accessories/chamber_light"))
+        .json(&serde_json::json!({"on": true}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_mqtt_debug(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/nope/debug/mqtt"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_machine_log_level(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .put(ctx.get_url("v1/machines/nope/log_level"))
+        .json(&serde_json::json!({"level": "trace"}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx
+        .client
+        .put(ctx.get_url("v1/machines/nope/log_level"))
+        .json(&serde_json::json!({"level": "loud"}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_bulk_machines(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/bulk/machines"))
+        .json(&serde_json::json!({"action": {"type": "pause"}}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/bulk/machines"))
+        .json(&serde_json::json!({
+            "machine_ids": ["nope"],
+            "selector": "building=east",
+            "action": {"type": "set_light", "on": false},
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let results: serde_json::Value = response.json().await?;
+    assert_eq!(
+        results,
+        serde_json::json!([{"machine_id": "nope", "success": false, "error": "machine not found"}])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_only() -> TestResult {
+    let ctx = ServerContext::with_read_only(true).await?;
+
+    let response = ctx.client.get(ctx.get_url("v1/jobs")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx.client.post(ctx.get_url("v1/jobs/nope/cancel")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/bulk/machines"))
+        .json(&serde_json::json!({"selector": "", "action": {"type": "stop"}}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Unversioned routes are refused too.
+    let response = ctx.client.delete(ctx.get_url("schedules/nope")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    ctx.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_api_keys() -> TestResult {
+    let ctx = ServerContext::with_options(
+        false,
+        crate::server::ApiKeys(vec![crate::server::ApiKey::Key("secret".to_owned())]),
+    )
+    .await?;
+
+    let response = ctx.client.get(ctx.get_url("ping")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx.client.get(ctx.get_url("v1/machines")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .bearer_auth("wrong")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .bearer_auth("secret")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    // Unversioned routes need a key too.
+    let response = ctx.client.get(ctx.get_url("machines")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    ctx.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_scoped_api_keys() -> TestResult {
+    let keys = serde_json::from_value(serde_json::json!([
+        "admin",
+        {"key": "class", "machines": ["left"], "labels": ["room=3b"]},
+    ]))?;
+    let ctx = ServerContext::with_options(false, keys).await?;
+
+    for (id, labels) in [
+        ("left", serde_json::json!({})),
+        ("right", serde_json::json!({"room": "3b"})),
+        ("other", serde_json::json!({"room": "4a"})),
+    ] {
+        let registration = serde_json::json!({
+            "id": id,
+            "labels": labels,
+            "config": {
+                "type": "Noop",
+                "nozzle_diameter": 0.4,
+                "filaments": [{"material": {"type": "pla"}}],
+            },
+        });
+
+        // Scoped keys can't register machines.
+        let response = ctx
+            .client
+            .post(ctx.get_url("v1/machines"))
+            .bearer_auth("class")
+            .json(&registration)
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+        let response = ctx
+            .client
+            .post(ctx.get_url("v1/machines"))
+            .bearer_auth("admin")
+            .json(&registration)
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let machines: Vec<serde_json::Value> = response.json().await?;
+    let mut ids: Vec<_> = machines.iter().map(|machine| machine["id"].as_str().unwrap()).collect();
+    ids.sort();
+    assert_eq!(ids, vec!["left", "right"]);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines"))
+        .bearer_auth("admin")
+        .send()
+        .await?;
+
+    assert_eq!(response.json::<Vec<serde_json::Value>>().await?.len(), 3);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/right"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/machines/other"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/machines/other/disable"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/bulk/machines"))
+        .bearer_auth("class")
+        .json(&serde_json::json!({"action": {"type": "pause"}, "machine_ids": ["other"]}))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/schedules"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // The file library is shared by every machine's jobs, so scoped keys
+    // can't see or delete it.
+    let response = ctx
+        .client
+        .get(ctx.get_url("v1/files"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = ctx
+        .client
+        .delete(ctx.get_url("v1/files/nope"))
+        .bearer_auth("class")
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Slicer profiles are shared by every machine, so only admin keys can
+    // import them.
+    for path in ["v1/slicer-profiles", "slicer-profiles"] {
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "file",
+                reqwest::multipart::Part::text(r#"{"type": "process", "name": "Fast"}"#).file_name("Fast.json"),
+            )
+            .part("params", reqwest::multipart::Part::text(r#"{"name": "x1c"}"#));
+        let response = ctx
+            .client
+            .post(ctx.get_url(path))
+            .bearer_auth("class")
+            .multipart(form)
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+
+    ctx.stop().await?;
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_schedules(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/schedules")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await?, "[]");
+
+    let response = ctx.client.get(ctx.get_url("v1/schedules/nope")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let response = ctx.client.delete(ctx.get_url("v1/schedules/nope")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_slicer_profiles(ctx: &mut ServerContext) -> TestResult {
+    let response = ctx.client.get(ctx.get_url("v1/slicer-profiles")).send().await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await?, "[]");
+
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text(r#"{"type": "process", "name": "Fast"}"#).file_name("Fast.json"),
+        )
+        .part("params", reqwest::multipart::Part::text(r#"{"name": "x1c"}"#));
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/slicer-profiles"))
+        .multipart(form)
+        .send()
+        .await?;
+
+    // A profile needs a machine and filament preset too.
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_slice(ctx: &mut ServerContext) -> TestResult {
+    use crate::{slicer::remote, GcodeSlicer};
+
+    let design = std::env::temp_dir().join(format!("{}.stl", uuid::Uuid::new_v4().simple()));
+    tokio::fs::write(&design, "solid empty\nendsolid empty\n").await?;
+    let design_file = crate::DesignFile::Stl(design.clone());
+    let options = crate::BuildOptions {
+        hardware_configuration: crate::HardwareConfiguration::None,
+        slicer_configuration: Default::default(),
+        make_model: crate::MachineMakeModel {
+            manufacturer: None,
+            model: None,
+            serial: None,
+        },
+        machine_type: crate::MachineType::FusedDeposition,
+        max_part_volume: None,
+        process: Default::default(),
+    };
+
+    let slicer = remote::Slicer::new(&ctx.get_url(""), "noop");
+    let gcode = GcodeSlicer::generate(&slicer, &design_file, &options).await?;
+    assert_eq!(tokio::fs::read(gcode.0.path()).await?, Vec::<u8>::new());
+
+    let slicer = remote::Slicer::new(&ctx.get_url(""), "nope");
+    assert!(GcodeSlicer::generate(&slicer, &design_file, &options).await.is_err());
+
+    tokio::fs::remove_file(&design).await?;
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_dry_run(ctx: &mut ServerContext) -> TestResult {
+    let post = |path: &str, params: serde_json::Value| {
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "file",
+                reqwest::multipart::Part::text(crate::TestPrint::CalibrationCube.stl()).file_name("cube.stl"),
+            )
+            .part("params", reqwest::multipart::Part::text(params.to_string()));
+        ctx.client.post(ctx.get_url(path)).multipart(form).send()
+    };
+
+    // The noop slicer doesn't run anything to dry run.
+    let response = post(
+        "v1/slice",
+        serde_json::json!({
+            "slicer": "noop",
+            "format": "gcode",
+            "options": {
+                "hardware_configuration": {"type": "none"},
+                "slicer_configuration": {},
+                "make_model": {},
+                "machine_type": "fused_deposition",
+            },
+            "dry_run": true,
+        }),
+    )
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = response.json().await?;
+    assert_eq!(error["error_code"], "DryRunUnsupported");
+
+    let response = post(
+        "v1/print",
+        serde_json::json!({"machine_id": "nope", "job_name": "cube", "dry_run": true}),
+    )
+    .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    // No job was started.
+    let response = ctx.client.get(ctx.get_url("v1/jobs")).send().await?;
+    assert_eq!(response.text().await?, "[]");
+
+    Ok(())
+}
+
+#[test_context(ServerContext)]
+#[tokio::test]
+async fn test_analyze(ctx: &mut ServerContext) -> TestResult {
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text(crate::TestPrint::CalibrationCube.stl()).file_name("cube.stl"),
+        )
+        .part("params", reqwest::multipart::Part::text("{}"));
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/analyze"))
+        .multipart(form)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let report: crate::PrintabilityReport = response.json().await?;
+    assert_eq!(report.score, 100.0);
+    assert_eq!(report.bed_contact_area, 400.0);
+    assert_eq!(report.bodies, 1);
+
+    // The bed level print's squares are separate bodies.
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text(crate::TestPrint::BedLevel.stl()).file_name("bed-level.stl"),
+        )
+        .part("params", reqwest::multipart::Part::text("{}"));
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/analyze"))
+        .multipart(form)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let report: crate::PrintabilityReport = response.json().await?;
+    assert_eq!(report.bodies, 5);
+
+    let form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::text("not an stl").file_name("nope.stl"),
+        )
+        .part("params", reqwest::multipart::Part::text("{}"));
+    let response = ctx
+        .client
+        .post(ctx.get_url("v1/analyze"))
+        .multipart(form)
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
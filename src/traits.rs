@@ -3,7 +3,7 @@ use std::{collections::HashMap, future::Future};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{DesignFile, TemporaryFile, Volume};
+use crate::{gcode::TemperatureSteps, DesignFile, TemporaryFile, Volume};
 
 /// Specific technique by which this Machine takes a design, and produces
 /// a real-world 3D object.
@@ -71,7 +71,10 @@ pub enum MachineState {
     Offline,
 
     /// Job is underway but halted, waiting for some action to take place.
-    Paused,
+    Paused {
+        /// Why the machine paused.
+        reason: PauseReason,
+    },
 
     /// Job is finished, but waiting manual action to move back to Idle.
     Complete,
@@ -83,10 +86,57 @@ pub enum MachineState {
         /// A human-readable message describing the failure.
         message: Option<String>,
     },
+
+    /// Machine has been taken out of service by an operator, and will not
+    /// accept new jobs until it is re-enabled.
+    Maintenance,
+}
+
+/// Why a machine paused, in the same terms across machines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum PauseReason {
+    /// Someone paused the machine, from its screen or through an API.
+    User,
+
+    /// The job's gcode paused the machine, such as for a filament change.
+    Gcode,
+
+    /// The machine ran out of filament.
+    FilamentRunout,
+
+    /// The machine hit an error, which it's waiting for someone to deal
+    /// with before carrying on.
+    MachineError {
+        /// The machine-specific error code (such as a Bambu HMS code), if
+        /// it gave one.
+        code: Option<String>,
+
+        /// A description of the error, if the machine gave one.
+        message: Option<String>,
+    },
+
+    /// The machine didn't say why it paused.
+    Unknown,
+}
+
+/// How far through its layers the current job is, in the same terms across
+/// machines, for display as something like "layer 57/214 (Z 11.4mm)".
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LayerProgress {
+    /// The layer currently being printed, counting from 1.
+    pub current_layer: u32,
+
+    /// The number of layers in the job.
+    pub total_layers: u32,
+
+    /// Height of the nozzle above the bed (in millimeters), if the machine
+    /// reports it.
+    pub z_mm: Option<f64>,
 }
 
 /// The material that the filament is made of.
-#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema, Copy)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema, Copy)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum FilamentMaterial {
     /// Polylactic acid based plastics
@@ -122,6 +172,25 @@ pub enum FilamentMaterial {
     Unknown,
 }
 
+impl FilamentMaterial {
+    /// Map a slicer's filament type name (such as PrusaSlicer's
+    /// `filament_type = PETG`) to a [FilamentMaterial].
+    pub fn from_slicer_name(name: &str) -> Self {
+        match name.trim().to_ascii_uppercase().as_str() {
+            "PLA" | "PLA+" => Self::Pla,
+            "PLA-S" | "PLA SUPPORT" => Self::PlaSupport,
+            "ABS" | "ASA" => Self::Abs,
+            "PETG" | "PET" => Self::Petg,
+            "PA" | "NYLON" => Self::Nylon,
+            "TPU" | "FLEX" => Self::Tpu,
+            "PVA" => Self::Pva,
+            "HIPS" => Self::Hips,
+            name if name.contains("-CF") || name.contains("-GF") => Self::Composite,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 /// Information about the filament being used in a FDM printer.
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Filament {
@@ -212,6 +281,11 @@ pub trait Control {
     /// there is no job running, or if there's no way to know the progress.
     fn progress(&self) -> impl Future<Output = Result<Option<f64>, Self::Error>>;
 
+    /// Return the layer the current job is on, out of how many. This may
+    /// return None if there is no job running, or if the machine (or the
+    /// file it's printing) doesn't report layers.
+    fn layer_progress(&self) -> impl Future<Output = Result<Option<LayerProgress>, Self::Error>>;
+
     // TODO: look at merging MachineType and HardwareConfiguration; they
     // communicate VERY similar things conceptually.
 
@@ -233,10 +307,14 @@ pub enum TemperatureSensor {
 
     /// This sensor measures the temperature of a 3d print chamber.
     Chamber,
+
+    /// This sensor measures the temperature of something else, such as the
+    /// machine's controller board, or the computer running it.
+    Other,
 }
 
 /// Temperature read from a sensor *ALWAYS IN CELSIUS*!
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct TemperatureSensorReading {
     /// The specific temperature value observed on or near the machine.
     pub temperature_celsius: f64,
@@ -309,6 +387,111 @@ pub struct SlicerConfiguration {
     /// The filament to use for the print.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub filament_idx: Option<usize>,
+
+    /// Checks and extras for Bambu machines to run along with the print.
+    /// Ignored by other machines.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bambu: Option<BambuPrintOptions>,
+
+    /// Step the nozzle temperature as the print goes up, such as for a
+    /// temperature tower. Ignored by machines which don't print gcode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_steps: Option<TemperatureSteps>,
+}
+
+/// Checks and extras Bambu machines run along with a print. Anything left
+/// unset is taken from the machine's config, or failing that, the
+/// machine's usual defaults (bed leveling, flow calibration and vibration
+/// calibration on; timelapse and first layer inspection off).
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Copy)]
+pub struct BambuPrintOptions {
+    /// Level the bed before printing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bed_leveling: Option<bool>,
+
+    /// Calibrate the extrusion flow before printing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_calibration: Option<bool>,
+
+    /// Calibrate for vibration (resonance compensation) before printing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vibration_calibration: Option<bool>,
+
+    /// Record a timelapse of the print.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timelapse: Option<bool>,
+
+    /// Inspect the first layer with the lidar, on machines which have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_inspect: Option<bool>,
+}
+
+impl BambuPrintOptions {
+    /// Fill in anything left unset from `fallback`.
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            bed_leveling: self.bed_leveling.or(fallback.bed_leveling),
+            flow_calibration: self.flow_calibration.or(fallback.flow_calibration),
+            vibration_calibration: self.vibration_calibration.or(fallback.vibration_calibration),
+            timelapse: self.timelapse.or(fallback.timelapse),
+            layer_inspect: self.layer_inspect.or(fallback.layer_inspect),
+        }
+    }
+}
+
+/// Settings for printing in resin, on SLA machines. Anything left unset
+/// is left to the slicer's profile (or the resin's defaults).
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema, Copy)]
+pub struct SlaOptions {
+    /// Layer thickness, in millimeters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_thickness_mm: Option<f64>,
+
+    /// How long each layer is exposed for, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_time_seconds: Option<f64>,
+
+    /// How long each of the first (bottom) layers is exposed for, in
+    /// seconds, to stick them to the build platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bottom_exposure_time_seconds: Option<f64>,
+
+    /// How dense the generated supports are, relative to the slicer's
+    /// default (1.0). Set to 0 to generate no supports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub support_density: Option<f64>,
+}
+
+/// Options for machines which print by extruding filament.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FdmOptions {
+    /// Extra gcode to run after the slicer profile's start gcode, for this
+    /// machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_gcode_extra: Option<String>,
+
+    /// Extra gcode to run after the slicer profile's end gcode, for this
+    /// machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_gcode_extra: Option<String>,
+}
+
+/// Options specific to the process the machine makes parts by, so slicers
+/// for one process never see another's.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ProcessOptions {
+    /// Options for FDM machines.
+    Fdm(FdmOptions),
+
+    /// Options for SLA machines.
+    Sla(SlaOptions),
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self::Fdm(FdmOptions::default())
+    }
 }
 
 /// Options passed along with the Build request that are specific to a
@@ -331,6 +514,28 @@ pub struct BuildOptions {
 
     /// Largest build volume that the machine can construct.
     pub max_part_volume: Option<Volume>,
+
+    /// Options specific to the process the machine makes parts by.
+    #[serde(default)]
+    pub process: ProcessOptions,
+}
+
+impl BuildOptions {
+    /// Return the options for FDM machines, if the machine is one.
+    pub fn fdm(&self) -> Option<&FdmOptions> {
+        match &self.process {
+            ProcessOptions::Fdm(fdm) => Some(fdm),
+            ProcessOptions::Sla(_) => None,
+        }
+    }
+
+    /// Return the options for SLA machines, if the machine is one.
+    pub fn sla(&self) -> Option<&SlaOptions> {
+        match &self.process {
+            ProcessOptions::Sla(sla) => Some(sla),
+            ProcessOptions::Fdm(_) => None,
+        }
+    }
 }
 
 /// [Control]-specific slicer which takes a particular [DesignFile], and produces
@@ -368,3 +573,21 @@ pub trait ThreeMfSlicer {
 
 /// ThreeMfTemporaryFile is a TemporaryFile full of .3mf.
 pub struct ThreeMfTemporaryFile(pub TemporaryFile);
+
+/// [Control]-specific slicer which takes a particular [DesignFile], and produces
+/// a Formlabs `.form` file.
+pub trait FormSlicer {
+    /// Error type returned by this trait.
+    type Error;
+
+    /// Take an input design file, and return a handle to a File on the
+    /// filesystem which contains the .form to be sent to the Machine.
+    fn generate(
+        &self,
+        design_file: &DesignFile,
+        options: &BuildOptions,
+    ) -> impl Future<Output = Result<FormTemporaryFile, <Self as FormSlicer>::Error>>;
+}
+
+/// FormTemporaryFile is a TemporaryFile full of .form.
+pub struct FormTemporaryFile(pub TemporaryFile);
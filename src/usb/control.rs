@@ -1,65 +1,189 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::Result;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadHalf, WriteHalf},
     sync::Mutex,
 };
 use tokio_serial::SerialStream;
 
 use super::Config;
 use crate::{
-    gcode::Client, Control as ControlTrait, FdmHardwareConfiguration, GcodeControl as GcodeControlTrait,
-    GcodeTemporaryFile, HardwareConfiguration, MachineInfo as MachineInfoTrait, MachineMakeModel, MachineState,
-    MachineType, Volume,
+    gcode::{
+        sender::{self, Sender, SenderOptions},
+        Client, LAYER_MARKERS,
+    },
+    Control as ControlTrait, FdmHardwareConfiguration, GcodeControl as GcodeControlTrait, GcodeTemporaryFile,
+    HardwareConfiguration, LayerProgress, MachineInfo as MachineInfoTrait, MachineMakeModel, MachineState, MachineType,
+    PauseReason, SuspendControl as SuspendControlTrait, Volume,
 };
 
-/// Handle to a USB based gcode 3D printer.
-#[derive(Clone)]
-pub struct Usb {
-    client: Arc<Mutex<Client<WriteHalf<SerialStream>, ReadHalf<SerialStream>>>>,
+/// Handle to a USB based gcode 3D printer, talking to it over `StreamT`
+/// (which is only something other than a serial port in tests).
+pub struct Usb<StreamT = SerialStream> {
+    client: Arc<Mutex<Client<WriteHalf<StreamT>, ReadHalf<StreamT>>>>,
     machine_info: UsbMachineInfo,
     config: Config,
+    job: Arc<Mutex<Option<PrintJob>>>,
+    firmware_version: Arc<Mutex<Option<String>>>,
 }
 
-impl Usb {
+// Not derived, since the stream itself needn't be `Clone`.
+impl<StreamT> Clone for Usb<StreamT> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            machine_info: self.machine_info.clone(),
+            config: self.config.clone(),
+            job: self.job.clone(),
+            firmware_version: self.firmware_version.clone(),
+        }
+    }
+}
+
+/// State of the gcode job currently being streamed to the printer.
+#[derive(Clone, Debug, Default)]
+struct PrintJob {
+    /// Number of gcode lines in the job.
+    lines_total: usize,
+
+    /// Number of gcode lines the printer has acknowledged.
+    lines_sent: usize,
+
+    /// A pause was requested, and will take effect at the next layer
+    /// boundary.
+    pause_requested: bool,
+
+    /// No more lines will be sent until the job is resumed.
+    paused: bool,
+
+    /// No more lines will be sent, ever.
+    cancelled: bool,
+
+    /// Set if streaming the job failed partway through.
+    error: Option<String>,
+}
+
+/// A single gcode command to send to the printer.
+#[derive(Clone, Debug, PartialEq)]
+struct GcodeLine {
+    /// The gcode command, stripped of comments and whitespace.
+    command: String,
+
+    /// `true` if this is the first command of a new layer.
+    layer_start: bool,
+}
+
+/// How long to wait for the firmware to say what it is, before starting
+/// the job without knowing.
+const FIRMWARE_NAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Split a gcode file into the commands to send, remembering where the
+/// slicer said each layer starts.
+fn parse_gcode(buf: &str) -> Vec<GcodeLine> {
+    let mut lines = vec![];
+    let mut layer_start = false;
+
+    for line in buf.lines() {
+        let (command, comment) = match line.split_once(';') {
+            Some((command, comment)) => (command.trim(), Some(comment.trim())),
+            None => (line.trim(), None),
+        };
+
+        if let Some(comment) = comment {
+            if LAYER_MARKERS.iter().any(|marker| comment.starts_with(marker)) {
+                layer_start = true;
+            }
+        }
+
+        if command.is_empty() {
+            continue;
+        }
+
+        lines.push(GcodeLine {
+            command: command.to_owned(),
+            layer_start,
+        });
+        layer_start = false;
+    }
+
+    lines
+}
+
+impl<StreamT> Usb<StreamT>
+where
+    StreamT: AsyncRead + AsyncWrite + Send + 'static,
+{
     /// Create a new USB-based gcode Machine.
-    pub fn new(stream: SerialStream, machine_info: UsbMachineInfo, config: Config) -> Self {
+    pub fn new(stream: StreamT, machine_info: UsbMachineInfo, config: Config) -> Self {
         let (reader, writer) = tokio::io::split(stream);
 
         Self {
             client: Arc::new(Mutex::new(Client::new(writer, reader))),
             machine_info,
             config,
+            job: Arc::new(Mutex::new(None)),
+            firmware_version: Arc::new(Mutex::new(None)),
         }
     }
 
-    async fn wait_for_start(&mut self) -> Result<()> {
-        loop {
-            let mut line = String::new();
-            if let Err(e) = self.client.lock().await.get_read().read_line(&mut line).await {
-                println!("wait_for_start err: {}", e);
-            } else {
-                // Use ends with because sometimes we may still have some data left on the buffer
-                if line.trim().ends_with("start") {
-                    return Ok(());
-                }
-            }
-        }
+    /// Return the [UsbMachineInfo] this Machine was created with.
+    pub fn get_machine_info(&self) -> &UsbMachineInfo {
+        &self.machine_info
+    }
+
+    /// Return the firmware the printer said it was running, when the last
+    /// job was started.
+    pub async fn firmware_version(&self) -> Result<Option<String>> {
+        Ok(self.firmware_version.lock().await.clone())
     }
 
-    async fn wait_for_ok(&mut self) -> Result<()> {
-        loop {
-            let mut line = String::new();
-            if let Err(e) = self.client.lock().await.get_read().read_line(&mut line).await {
-                println!("wait_for_ok err: {}", e);
-            } else {
-                println!("RCVD: {}", line);
-                if line.trim() == "ok" {
-                    return Ok(());
+    /// Send `lines` to the printer, as fast as it acknowledges them.
+    /// Pauses take effect at the next layer boundary, so the printer isn't
+    /// left sitting mid-layer; if the gcode has no layer markers, they take
+    /// effect on the next line.
+    async fn stream(&mut self, lines: Vec<GcodeLine>) -> Result<()> {
+        let has_layers = lines.iter().any(|line| line.layer_start);
+        let mut sender = Sender::new(SenderOptions::default());
+
+        for line in lines.iter() {
+            loop {
+                {
+                    let mut job = self.job.lock().await;
+                    let Some(job) = job.as_mut() else {
+                        return Ok(());
+                    };
+
+                    job.lines_sent = sender.acked();
+
+                    if job.cancelled {
+                        tracing::info!(lines_sent = job.lines_sent, "usb job cancelled");
+                        return Ok(());
+                    }
+
+                    if job.pause_requested && (line.layer_start || !has_layers) {
+                        tracing::info!(lines_sent = job.lines_sent, "usb job paused");
+                        job.pause_requested = false;
+                        job.paused = true;
+                    }
+
+                    if !job.paused {
+                        break;
+                    }
                 }
+
+                tokio::time::sleep(Duration::from_millis(250)).await;
             }
+
+            sender.send(&mut *self.client.lock().await, &line.command).await?;
         }
+
+        sender.flush(&mut *self.client.lock().await).await?;
+        if let Some(job) = self.job.lock().await.as_mut() {
+            job.lines_sent = sender.acked();
+        }
+
+        Ok(())
     }
 }
 
@@ -79,12 +203,22 @@ pub struct UsbMachineInfo {
     /// USB Port (/dev/ttyUSB0, etc).
     pub port: String,
 
+    /// Stable path to the USB Port (/dev/serial/by-id/..., etc), if one
+    /// exists. Unlike `port`, this won't change between reboots or when
+    /// the device is re-plugged.
+    pub stable_port: Option<String>,
+
     /// Baud rate of the Serial connection.
     pub baud: u32,
+
+    /// `true` if `baud` was found by probing the device, rather than
+    /// configured or known for the variant.
+    pub baud_detected: bool,
 }
 
 impl UsbMachineInfo {
     /// Create a new USB Machine Info directly (not via discovery).
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         machine_type: MachineType,
         make_model: MachineMakeModel,
@@ -92,6 +226,7 @@ impl UsbMachineInfo {
         vendor_id: u16,
         product_id: u16,
         port: String,
+        stable_port: Option<String>,
         baud: u32,
     ) -> Self {
         Self {
@@ -101,7 +236,9 @@ impl UsbMachineInfo {
             vendor_id,
             product_id,
             port,
+            stable_port,
             baud,
+            baud_detected: false,
         }
     }
 }
@@ -120,7 +257,10 @@ impl MachineInfoTrait for UsbMachineInfo {
     }
 }
 
-impl ControlTrait for Usb {
+impl<StreamT> ControlTrait for Usb<StreamT>
+where
+    StreamT: AsyncRead + AsyncWrite + Send + 'static,
+{
     type MachineInfo = UsbMachineInfo;
     type Error = anyhow::Error;
 
@@ -133,14 +273,54 @@ impl ControlTrait for Usb {
     }
 
     async fn stop(&mut self) -> Result<()> {
+        {
+            let mut job = self.job.lock().await;
+            if job.as_ref().is_some_and(|job| job.error.is_some()) {
+                // A failed job is only cleared by an explicit stop.
+                *job = None;
+            } else if let Some(job) = job.as_mut() {
+                // Let the streaming task notice, and clean up after itself.
+                job.cancelled = true;
+            }
+        }
         self.client.lock().await.stop().await
     }
 
     async fn state(&self) -> Result<MachineState> {
-        Ok(MachineState::Unknown)
+        let job = self.job.lock().await;
+        let Some(job) = job.as_ref() else {
+            return Ok(MachineState::Idle);
+        };
+
+        Ok(if let Some(error) = &job.error {
+            MachineState::Failed {
+                message: Some(error.clone()),
+            }
+        } else if job.paused {
+            // Jobs we're streaming only pause when they're asked to.
+            MachineState::Paused {
+                reason: PauseReason::User,
+            }
+        } else {
+            MachineState::Running
+        })
     }
 
     async fn progress(&self) -> Result<Option<f64>> {
+        let job = self.job.lock().await;
+        let Some(job) = job.as_ref() else {
+            return Ok(None);
+        };
+
+        if job.lines_total == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(job.lines_sent as f64 / job.lines_total as f64 * 100.0))
+    }
+
+    async fn layer_progress(&self) -> Result<Option<LayerProgress>> {
+        // We only see the raw gcode stream, which has no notion of layers.
         Ok(None)
     }
 
@@ -162,41 +342,230 @@ impl ControlTrait for Usb {
     }
 }
 
-impl GcodeControlTrait for Usb {
-    async fn build(&mut self, _job_name: &str, gcode: GcodeTemporaryFile) -> Result<()> {
+impl<StreamT> SuspendControlTrait for Usb<StreamT>
+where
+    StreamT: AsyncRead + AsyncWrite + Send + 'static,
+{
+    async fn pause(&mut self) -> Result<()> {
+        let mut job = self.job.lock().await;
+        let Some(job) = job.as_mut() else {
+            anyhow::bail!("no job is running");
+        };
+        job.pause_requested = true;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        let mut job = self.job.lock().await;
+        let Some(job) = job.as_mut() else {
+            anyhow::bail!("no job is running");
+        };
+        job.pause_requested = false;
+        job.paused = false;
+        Ok(())
+    }
+}
+
+impl<StreamT> GcodeControlTrait for Usb<StreamT>
+where
+    StreamT: AsyncRead + AsyncWrite + Send + 'static,
+{
+    async fn build(&mut self, job_name: &str, gcode: GcodeTemporaryFile) -> Result<()> {
         let mut gcode = gcode.0;
 
         let mut buf = String::new();
         gcode.as_mut().read_to_string(&mut buf).await?;
 
-        let lines: Vec<String> = buf
-            .lines() // split the string into an iterator of string slices
-            .map(|s| {
-                let s = String::from(s);
-                match s.split_once(';') {
-                    Some((command, _)) => command.trim().to_string(),
-                    None => s.trim().to_string(),
-                }
-            })
-            .filter(|s| !s.is_empty()) // make each slice into a string
-            .collect();
+        let lines = parse_gcode(&buf);
+
+        // A job which failed partway through is finished, even if it's
+        // kept around to report why until the machine is stopped.
+        let running = |job: &Option<PrintJob>| job.as_ref().is_some_and(|job| job.error.is_none());
+        if running(&*self.job.lock().await) {
+            anyhow::bail!("a job is already running");
+        }
 
-        self.wait_for_start().await?;
+        sender::wait_for_start(&mut *self.client.lock().await).await?;
+
+        match tokio::time::timeout(
+            FIRMWARE_NAME_TIMEOUT,
+            sender::firmware_name(&mut *self.client.lock().await),
+        )
+        .await
+        {
+            Ok(Ok(name)) => *self.firmware_version.lock().await = name,
+            Ok(Err(e)) => tracing::warn!(error = format!("{:?}", e), "failed to ask the firmware what it is"),
+            Err(_) => tracing::warn!("timed out asking the firmware what it is"),
+        }
+
+        // The job's only taken on once the firmware's ready for it, so
+        // failing to get that far (or giving up on it) leaves the machine
+        // free for the next one.
+        {
+            let mut job = self.job.lock().await;
+            if running(&job) {
+                anyhow::bail!("a job is already running");
+            }
+            *job = Some(PrintJob {
+                lines_total: lines.len(),
+                ..Default::default()
+            });
+        }
+
+        tracing::info!(job_name = job_name, lines = lines.len(), "streaming gcode");
 
         let self1 = self.clone();
         tokio::spawn(async move {
             let mut usb = self1;
-            for line in lines.iter() {
-                let msg = format!("{}\r\n", line);
-                println!("writing: {}", line);
-                usb.client.lock().await.write_all(msg.as_bytes()).await?;
-                usb.wait_for_ok().await?;
+            let result = usb.stream(lines).await;
+
+            let mut job = usb.job.lock().await;
+            match result {
+                Ok(()) => *job = None,
+                Err(e) => {
+                    tracing::warn!(error = format!("{:?}", e), "failed to stream gcode");
+                    if let Some(job) = job.as_mut() {
+                        job.error = Some(format!("{}", e));
+                    }
+                }
             }
-            Ok::<(), anyhow::Error>(())
         });
 
-        // store a handle to the joinhandle in an option in self or something?
-
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::DuplexStream;
+
+    use super::*;
+    use crate::{gcode::testing::MockPrinter, TemporaryFile};
+
+    fn usb(stream: DuplexStream) -> Usb<DuplexStream> {
+        let config: Config = toml::from_str(
+            r#"
+variant = "PrusaMk3"
+nozzle_diameter = 0.4
+filaments = []
+
+[slicer]
+type = "Prusa"
+config = "config/prusa/mk3.ini"
+"#,
+        )
+        .unwrap();
+
+        let machine_info = UsbMachineInfo::new(
+            MachineType::FusedDeposition,
+            MachineMakeModel {
+                manufacturer: Some("Prusa Research".to_owned()),
+                model: Some("i3 MK3".to_owned()),
+                serial: None,
+            },
+            None,
+            0x2c99,
+            0x0002,
+            "/dev/ttyACM0".to_owned(),
+            None,
+            115200,
+        );
+
+        Usb::new(stream, machine_info, config)
+    }
+
+    async fn gcode(contents: &str) -> GcodeTemporaryFile {
+        let path = std::env::temp_dir().join(format!("{}.gcode", uuid::Uuid::new_v4().simple()));
+        tokio::fs::write(&path, contents).await.unwrap();
+        GcodeTemporaryFile(TemporaryFile::new(&path).await.unwrap())
+    }
+
+    /// Wait for the job to stop running, returning the state it ended in.
+    async fn finished(usb: &Usb<DuplexStream>) -> MachineState {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let state = usb.state().await.unwrap();
+                if state != MachineState::Running {
+                    return state;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build() {
+        let (stream, printer) = MockPrinter::new().with_start().with_temperatures().connect();
+        let mut usb = usb(stream);
+
+        usb.build("test", gcode("G28 ; home\n;LAYER_CHANGE\nG1 Z0.2\nG1 X10 Y10\n").await)
+            .await
+            .unwrap();
+        assert_eq!(finished(&usb).await, MachineState::Idle);
+        assert_eq!(usb.progress().await.unwrap(), None);
+        assert_eq!(
+            usb.firmware_version().await.unwrap().as_deref(),
+            Some("Marlin 2.1.2 (Github)")
+        );
+
+        drop(usb);
+        assert_eq!(printer.await.unwrap(), vec!["M115", "G28", "G1 Z0.2", "G1 X10 Y10"]);
+    }
+
+    #[tokio::test]
+    async fn test_build_halted() {
+        let (stream, _printer) = MockPrinter::new().with_start().with_halt_after(1).connect();
+        let mut usb = usb(stream);
+
+        usb.build("test", gcode("G28\nG1 Z0.2\nG1 X10 Y10\n").await)
+            .await
+            .unwrap();
+        assert!(matches!(finished(&usb).await, MachineState::Failed { .. }));
+        assert!(usb.progress().await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_build_not_started() {
+        // The printer goes away before saying it's started.
+        let (stream, printer) = tokio::io::duplex(4096);
+        drop(printer);
+        let mut usb = usb(stream);
+
+        for _ in 0..2 {
+            let e = usb.build("test", gcode("G28\n").await).await.unwrap_err();
+            assert!(e.to_string().contains("connection closed"), "{:?}", e);
+        }
+        assert_eq!(usb.state().await.unwrap(), MachineState::Idle);
+    }
+
+    #[test]
+    fn test_parse_gcode() {
+        let lines = parse_gcode(
+            "; generated by PrusaSlicer\nG28 ; home\n\n;LAYER_CHANGE\n;Z:0.2\nG1 Z0.2\nG1 X10 Y10\n;LAYER_CHANGE\nG1 Z0.4\n",
+        );
+
+        assert_eq!(
+            lines,
+            vec![
+                GcodeLine {
+                    command: "G28".to_owned(),
+                    layer_start: false,
+                },
+                GcodeLine {
+                    command: "G1 Z0.2".to_owned(),
+                    layer_start: true,
+                },
+                GcodeLine {
+                    command: "G1 X10 Y10".to_owned(),
+                    layer_start: false,
+                },
+                GcodeLine {
+                    command: "G1 Z0.4".to_owned(),
+                    layer_start: true,
+                },
+            ]
+        );
+    }
+}
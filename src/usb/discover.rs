@@ -2,11 +2,14 @@ use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tokio_serial::{SerialPortBuilderExt, SerialPortType};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::RwLock,
+};
+use tokio_serial::{SerialPort as _, SerialPortBuilderExt, SerialPortType, SerialStream};
 
-use super::UsbVariant;
-use crate::{slicer, usb, Discover, Filament, Machine, MachineMakeModel};
+use super::{SerialReset, UsbVariant};
+use crate::{canonical_machine_id, slicer, usb, AnyMachine, Discover, Filament, Machine, MachineMakeModel};
 
 /// Configuration block for a USB based device.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -17,7 +20,8 @@ pub struct Config {
     /// Information regarding the specific make/model of device.
     pub variant: UsbVariant,
 
-    /// Baud rate to use when opening the serial pty.
+    /// Baud rate to use when opening the serial pty. None uses the rate
+    /// for the `variant`, or for a `Generic` device, probes for it.
     pub baud: Option<u32>,
 
     /// Serial number, as reported by the USB protocol. None will match
@@ -30,6 +34,27 @@ pub struct Config {
     /// USB Product ID (pid) to scan for. None will match any USB device.
     pub product_id: Option<u16>,
 
+    /// Serial port to bind to. This is best set to a stable path such as
+    /// `/dev/serial/by-id/usb-...`, which won't shuffle between boots the
+    /// way `/dev/ttyUSB0` does. On Windows this is a COM port (such as
+    /// `COM3`), which isn't stable, so matching on `serial` is preferred
+    /// there. None will match any port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<String>,
+
+    /// How to reset the board when opening the port. None uses the
+    /// default for the `variant`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset: Option<SerialReset>,
+
+    /// Use this config for every matching device (whatever its serial
+    /// number) which no other config matches, registering each under a
+    /// stable ID made from its manufacturer and serial number (such as
+    /// `prusa-research-czpx2418x004xk68718`), rather than this config's ID.
+    /// Devices without a serial number are skipped.
+    #[serde(default)]
+    pub template: bool,
+
     /// Extrusion hotend nozzle's diameter.
     pub nozzle_diameter: f64,
 
@@ -41,8 +66,8 @@ pub struct Config {
 }
 
 impl Config {
-    fn get_baud(&self) -> u32 {
-        self.baud.unwrap_or(self.variant.get_baud().unwrap_or(115200))
+    fn get_baud(&self) -> Option<u32> {
+        self.baud.or(self.variant.get_baud())
     }
 
     /// check to see if this qualifies as a match
@@ -59,13 +84,192 @@ impl Config {
             return false;
         }
 
-        if *serial != self.serial {
+        if !self.template && *serial != self.serial {
             tracing::trace!(serial = serial, config_serial = self.serial, "serial does not match");
             return false;
         }
 
         true
     }
+
+    fn get_serial_reset(&self) -> SerialReset {
+        self.reset.unwrap_or(self.variant.get_serial_reset())
+    }
+
+    /// check to see if the configured port (if any) is the same device
+    /// as `port_name`.
+    fn matches_port_name(&self, port_name: &str) -> bool {
+        let Some(configured) = &self.port else {
+            return true;
+        };
+
+        let matches = same_port(configured, port_name);
+
+        if !matches {
+            tracing::trace!(port_name = port_name, config_port = configured, "port does not match");
+        }
+
+        matches
+    }
+
+    /// Return a stable path for `port_name`; either the configured port,
+    /// or the first `/dev/serial/by-id` link resolving to it.
+    fn stable_port(&self, port_name: &str) -> Option<String> {
+        if let Some(configured) = &self.port {
+            return Some(configured.clone());
+        }
+        find_stable_port(port_name)
+    }
+}
+
+/// check to see if `configured` and `found` are the same device node.
+/// Stable paths are symlinks, so both sides are resolved before comparing.
+#[cfg(not(windows))]
+fn same_port(configured: &str, found: &str) -> bool {
+    match (std::fs::canonicalize(configured), std::fs::canonicalize(found)) {
+        (Ok(configured), Ok(found)) => configured == found,
+        _ => configured == found,
+    }
+}
+
+/// check to see if `configured` and `found` are the same COM port. Port
+/// names are case-insensitive, and may be written as a device path
+/// (`\\.\COM10`), which is required to open ports above `COM9` by hand.
+#[cfg(windows)]
+fn same_port(configured: &str, found: &str) -> bool {
+    normalize_com_port(configured) == normalize_com_port(found)
+}
+
+/// Return the bare, upper case name of a COM port (`COM10` for
+/// `\\.\com10`).
+#[cfg(windows)]
+fn normalize_com_port(port_name: &str) -> String {
+    port_name
+        .trim()
+        .trim_start_matches(r"\\.\")
+        .trim_start_matches(r"\\?\")
+        .to_uppercase()
+}
+
+/// Baud rate to fall back on when none is configured, and probing doesn't
+/// find one.
+const DEFAULT_BAUD: u32 = 115200;
+
+/// Baud rates to probe for, most likely first.
+const PROBE_BAUDS: &[u32] = &[250000, 115200, 57600];
+
+/// How long to give a board which was reset on open to boot, before
+/// probing it.
+const PROBE_BOOT_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How long to wait for a sane reply to a probe.
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// check to see if `line` looks like a reply from gcode firmware, rather
+/// than line noise from talking to it at the wrong baud rate.
+fn is_sane_response(line: &str) -> bool {
+    let line = line.trim();
+    if line.is_empty() || !line.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return false;
+    }
+
+    line.starts_with("ok") || line.starts_with("FIRMWARE_NAME:") || line == "start"
+}
+
+/// Try each of the common baud rates on `port_name`, sending an `M115`
+/// (firmware info) and returning the first rate which gets a sane reply.
+async fn detect_baud(port_name: &str, reset: SerialReset) -> Option<u32> {
+    for &baud in PROBE_BAUDS {
+        match probe_baud(port_name, baud, reset).await {
+            Ok(true) => return Some(baud),
+            Ok(false) => {
+                tracing::debug!(port_name = port_name, baud = baud, "no sane reply to probe");
+            }
+            Err(e) => {
+                tracing::debug!(
+                    port_name = port_name,
+                    baud = baud,
+                    error = format!("{:?}", e),
+                    "failed to probe baud rate"
+                );
+            }
+        }
+    }
+    None
+}
+
+/// Send an `M115` to `port_name` at `baud`, and check for a sane reply.
+async fn probe_baud(port_name: &str, baud: u32, reset: SerialReset) -> Result<bool> {
+    let stream = open_port(port_name, baud, reset).await?;
+    if reset != SerialReset::None {
+        tokio::time::sleep(PROBE_BOOT_DELAY).await;
+    }
+
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer.write_all(b"M115\n").await?;
+
+    let mut reader = BufReader::new(reader);
+    let read_reply = async {
+        loop {
+            let mut buf = vec![];
+            if reader.read_until(b'\n', &mut buf).await? == 0 {
+                return Ok::<_, anyhow::Error>(false);
+            }
+            // Garbage at the wrong baud rate is rarely valid UTF-8.
+            if std::str::from_utf8(&buf).is_ok_and(is_sane_response) {
+                return Ok(true);
+            }
+        }
+    };
+
+    match tokio::time::timeout(PROBE_TIMEOUT, read_reply).await {
+        Ok(sane) => sane,
+        Err(_) => Ok(false),
+    }
+}
+
+/// Open the serial port `port_name`, resetting the board as `reset` says to.
+async fn open_port(port_name: &str, baud: u32, reset: SerialReset) -> Result<SerialStream> {
+    let mut stream = tokio_serial::new(port_name, baud).open_native_async()?;
+
+    match reset {
+        SerialReset::None => {}
+        // Linux has already asserted DTR on open, which reset the board.
+        SerialReset::PulseDtr if !cfg!(windows) => {}
+        SerialReset::PulseDtr => {
+            stream.write_request_to_send(false)?;
+            stream.write_data_terminal_ready(false)?;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            stream.write_data_terminal_ready(true)?;
+        }
+    }
+
+    Ok(stream)
+}
+
+#[cfg(not(windows))]
+const SERIAL_BY_ID: &str = "/dev/serial/by-id";
+
+/// Windows has no equivalent of `/dev/serial/by-id`, so there's never a
+/// stable port there.
+#[cfg(windows)]
+fn find_stable_port(_port_name: &str) -> Option<String> {
+    None
+}
+
+/// Scan `/dev/serial/by-id` for a link that resolves to `port_name`.
+#[cfg(not(windows))]
+fn find_stable_port(port_name: &str) -> Option<String> {
+    let port_name = std::fs::canonicalize(port_name).ok()?;
+
+    for entry in std::fs::read_dir(SERIAL_BY_ID).ok()?.flatten() {
+        let path = entry.path();
+        if std::fs::canonicalize(&path).ok().as_ref() == Some(&port_name) {
+            return path.to_str().map(str::to_owned);
+        }
+    }
+
+    None
 }
 
 /// USB Discovery system -- scan for any attached USB devices through the
@@ -82,9 +286,15 @@ impl UsbDiscovery {
         Self { configs: cfgs.into() }
     }
 
-    /// Attempt to match the SerialPort to a known config block.
-    async fn find_match(&self, port: &SerialPort) -> Option<(String, Config)> {
-        for (machine_id, configuration) in self.configs.iter() {
+    /// Attempt to match the SerialPort to a known config block. Configs for
+    /// specific machines are tried before templates, each in order of their
+    /// ID, so the same device always matches the same config. A device
+    /// matching a template gets its canonical ID.
+    async fn find_match(&self, port: &SerialPort, port_name: &str) -> Option<(String, Config)> {
+        let mut configs: Vec<_> = self.configs.iter().collect();
+        configs.sort_by_key(|(machine_id, configuration)| (configuration.template, *machine_id));
+
+        for (machine_id, configuration) in configs {
             tracing::trace!(
                 vid = port.0,
                 pid = port.1,
@@ -95,7 +305,7 @@ impl UsbDiscovery {
                 config_serial = configuration.serial,
                 "checking to see if device matches config"
             );
-            if configuration.matches(port) {
+            if configuration.matches(port) && configuration.matches_port_name(port_name) {
                 tracing::trace!(
                     vid = port.0,
                     pid = port.1,
@@ -103,7 +313,24 @@ impl UsbDiscovery {
                     machine_id = machine_id,
                     "match found",
                 );
-                return Some((machine_id.clone(), configuration.clone()));
+                if !configuration.template {
+                    return Some((machine_id.clone(), configuration.clone()));
+                }
+                let Some(serial) = &port.2 else {
+                    tracing::debug!(
+                        vid = port.0,
+                        pid = port.1,
+                        template = machine_id,
+                        "device has no serial number, so no stable id; skipping template"
+                    );
+                    continue;
+                };
+                let manufacturer = configuration
+                    .variant
+                    .get_manufacturer_model()
+                    .0
+                    .unwrap_or_else(|| format!("usb-{:04x}", port.0));
+                return Some((canonical_machine_id(&manufacturer, serial), configuration.clone()));
             }
         }
         tracing::trace!(
@@ -118,6 +345,17 @@ impl UsbDiscovery {
 
 type SerialPort = (u16, u16, Option<String>);
 
+/// Return the port the already known machine `machine_id` is bound to,
+/// or `None` if it's not known (or not a USB machine at all).
+async fn known_port(found: &RwLock<HashMap<String, RwLock<Machine>>>, machine_id: &str) -> Option<String> {
+    let machines = found.read().await;
+    let machine = machines.get(machine_id)?.read().await;
+    match machine.get_machine() {
+        AnyMachine::Usb(usb) => Some(usb.get_machine_info().port.clone()),
+        _ => None,
+    }
+}
+
 impl Discover for UsbDiscovery {
     type Error = anyhow::Error;
 
@@ -157,7 +395,7 @@ impl Discover for UsbDiscovery {
                     "found a usb port, checking for matches"
                 );
 
-                let Some((machine_id, config)) = self.find_match(&port).await else {
+                let Some((machine_id, config)) = self.find_match(&port, &port_name).await else {
                     tracing::trace!(vid = port.0, pid = port.1, serial = port.2, "no matches, moving on",);
                     continue;
                 };
@@ -170,9 +408,27 @@ impl Discover for UsbDiscovery {
                     "found a matching config; checking if known"
                 );
 
-                if found.read().await.get(&machine_id).is_some() {
-                    tracing::trace!(machine_id = machine_id, "machine already exists, skipping",);
-                    continue;
+                match known_port(&found, &machine_id).await {
+                    Some(known) if known == port_name => {
+                        tracing::trace!(machine_id = machine_id, "machine already exists, skipping",);
+                        continue;
+                    }
+                    Some(known) => {
+                        // The device has re-enumerated onto a new port
+                        // (unplugged and plugged back in, hub reset, etc),
+                        // so drop the stale handle and rebind below.
+                        tracing::info!(
+                            machine_id = machine_id,
+                            old_port_name = known,
+                            port_name = port_name,
+                            "usb machine moved ports, rebinding"
+                        );
+                    }
+                    None if found.read().await.contains_key(&machine_id) => {
+                        tracing::trace!(machine_id = machine_id, "id is not a usb machine, skipping",);
+                        continue;
+                    }
+                    None => {}
                 }
 
                 tracing::info!(
@@ -183,9 +439,25 @@ impl Discover for UsbDiscovery {
                     "found a new usb connected machine"
                 );
 
-                let baud = config.get_baud();
+                let (baud, baud_detected) = match config.get_baud() {
+                    Some(baud) => (baud, false),
+                    None => match detect_baud(&port_name, config.get_serial_reset()).await {
+                        Some(baud) => {
+                            tracing::info!(machine_id = machine_id, baud = baud, "detected baud rate");
+                            (baud, true)
+                        }
+                        None => {
+                            tracing::warn!(
+                                machine_id = machine_id,
+                                baud = DEFAULT_BAUD,
+                                "failed to detect baud rate, using the default"
+                            );
+                            (DEFAULT_BAUD, false)
+                        }
+                    },
+                };
 
-                let stream = match tokio_serial::new(port_name.clone(), baud).open_native_async() {
+                let stream = match open_port(&port_name, baud, config.get_serial_reset()).await {
                     Err(e) => {
                         tracing::warn!(
                             machine_id = machine_id,
@@ -205,26 +477,26 @@ impl Discover for UsbDiscovery {
 
                 let slicer = config.slicer.load()?;
 
+                let mut machine_info = usb::UsbMachineInfo::new(
+                    config.variant.get_machine_type(),
+                    MachineMakeModel {
+                        manufacturer,
+                        model,
+                        serial: port.2,
+                    },
+                    config.variant.get_max_part_volume(),
+                    port.0,
+                    port.1,
+                    port_name.clone(),
+                    config.stable_port(&port_name),
+                    baud,
+                );
+                machine_info.baud_detected = baud_detected;
+
                 found.write().await.insert(
                     machine_id.clone(),
                     RwLock::new(Machine::new(
-                        usb::Usb::new(
-                            stream,
-                            usb::UsbMachineInfo::new(
-                                config.variant.get_machine_type(),
-                                MachineMakeModel {
-                                    manufacturer,
-                                    model,
-                                    serial: port.2,
-                                },
-                                config.variant.get_max_part_volume(),
-                                port.0,
-                                port.1,
-                                port_name.clone(),
-                                baud,
-                            ),
-                            config.clone(),
-                        ),
+                        usb::Usb::new(stream, machine_info, config.clone()),
                         slicer,
                     )),
                 );
@@ -235,3 +507,99 @@ impl Discover for UsbDiscovery {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn test_same_com_port() {
+        assert!(same_port("COM3", "COM3"));
+        assert!(same_port("com3", "COM3"));
+        assert!(same_port(r"\\.\COM10", "COM10"));
+        assert!(!same_port("COM1", "COM10"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_no_stable_port() {
+        assert_eq!(find_stable_port("COM3"), None);
+    }
+
+    #[test]
+    fn test_serial_reset() {
+        let mut config: Config = toml::from_str(
+            r#"
+variant = "PrusaMk3"
+nozzle_diameter = 0.4
+filaments = []
+
+[slicer]
+type = "Prusa"
+config = "config/prusa/mk3.ini"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.get_serial_reset(), SerialReset::PulseDtr);
+
+        config.reset = Some(SerialReset::None);
+        assert_eq!(config.get_serial_reset(), SerialReset::None);
+    }
+
+    #[tokio::test]
+    async fn test_find_match_template() {
+        let config = |serial: &str, template: bool| -> Config {
+            let mut config: Config = toml::from_str(&format!(
+                r#"
+variant = "PrusaMk3"
+vendor_id = 0x2c99
+product_id = 0x0002
+serial = "{}"
+template = {}
+nozzle_diameter = 0.4
+filaments = []
+
+[slicer]
+type = "Prusa"
+config = "config/prusa/mk3.ini"
+"#,
+                serial, template
+            ))
+            .unwrap();
+            if serial.is_empty() {
+                config.serial = None;
+            }
+            config
+        };
+        let discovery = UsbDiscovery::new(HashMap::from([
+            ("mk3".to_owned(), config("CZPX1", false)),
+            ("a-prusa".to_owned(), config("", true)),
+            ("z-prusa".to_owned(), config("", true)),
+        ]));
+
+        let found = |serial: Option<&str>| {
+            let port = (0x2c99, 0x0002, serial.map(str::to_owned));
+            let discovery = &discovery;
+            async move { discovery.find_match(&port, "/dev/ttyACM0").await.map(|(id, _)| id) }
+        };
+
+        // A configured machine keeps its id, even though templates match.
+        assert_eq!(found(Some("CZPX1")).await.as_deref(), Some("mk3"));
+        assert_eq!(found(Some("CZPX2")).await.as_deref(), Some("prusa-research-czpx2"));
+        assert_eq!(found(None).await, None);
+    }
+
+    #[test]
+    fn test_is_sane_response() {
+        assert!(is_sane_response("ok\n"));
+        assert!(is_sane_response("start\r\n"));
+        assert!(is_sane_response(
+            "FIRMWARE_NAME:Marlin 2.1.2 (Sep 21 2024) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin\n"
+        ));
+        assert!(!is_sane_response(""));
+        assert!(!is_sane_response("echo:Unknown command"));
+        assert!(!is_sane_response("o\u{7f}k\x00"));
+    }
+}
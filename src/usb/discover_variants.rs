@@ -2,6 +2,21 @@ use serde::{Deserialize, Serialize};
 
 use crate::{MachineType, Volume};
 
+/// How a board is reset when its serial port is opened, so that it's
+/// printed its `start` banner by the time we send it anything.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialReset {
+    /// Leave DTR/RTS alone; the board doesn't reset on open.
+    None,
+
+    /// The board has an Arduino-style auto-reset circuit, which resets it
+    /// when DTR is asserted. Linux asserts DTR on open (and so resets the
+    /// board for us), but Windows doesn't, so there DTR is pulsed by hand,
+    /// with RTS held low (some USB-serial chips wire RTS to reset as well).
+    PulseDtr,
+}
+
 macro_rules! usb_devices {
     ($(
       $name:ident(
@@ -11,7 +26,8 @@ macro_rules! usb_devices {
         $manufacturer:expr,
         $pid:expr,
         $model:expr,
-        $baud:expr
+        $baud:expr,
+        $reset:expr
       )
     ),+) => {
         /// All known USB Machines.
@@ -35,6 +51,16 @@ macro_rules! usb_devices {
                 }
             }
 
+            /// Return how the board needs to be reset when its serial port
+            /// is opened.
+            pub fn get_serial_reset(&self) -> SerialReset {
+                match self {
+                $(
+                    Self::$name => { $reset },
+                )*
+                }
+            }
+
             /// Return the max manufacture volume.
             pub fn get_max_part_volume(&self) -> Option<Volume> {
                 match self {
@@ -76,7 +102,16 @@ macro_rules! usb_devices {
 
 usb_devices!(
     // Generic USB based FusedDeposition 3D printer
-    Generic(MachineType::FusedDeposition, None, None, None, None, None, None),
+    Generic(
+        MachineType::FusedDeposition,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        SerialReset::PulseDtr
+    ),
     // Prusa Research Mk3
     PrusaMk3(
         MachineType::FusedDeposition,
@@ -89,7 +124,8 @@ usb_devices!(
         Some("Prusa Research".to_owned()),
         Some(0x0002),
         Some("MK3".to_owned()),
-        Some(115200)
+        Some(115200),
+        SerialReset::PulseDtr
     ) // // Prusa Research Mk4
       // PrusaMk4(
       //     MachineType::FusedDeposition,
@@ -102,6 +138,7 @@ usb_devices!(
       //     Some("Prusa Research".to_owned()),
       //     Some(0x000d),
       //     Some("MK4".to_owned()),
-      //     Some(115200)
+      //     Some(115200),
+      //     SerialReset::None
       // )
 );
@@ -6,4 +6,4 @@ mod discover_variants;
 
 pub use control::{Usb, UsbMachineInfo};
 pub use discover::{Config, UsbDiscovery};
-pub use discover_variants::UsbVariant;
+pub use discover_variants::{SerialReset, UsbVariant};